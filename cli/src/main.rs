@@ -0,0 +1,346 @@
+use std::{
+    path::{Path, PathBuf},
+    process,
+};
+
+use bitcask_rs::{
+    db::{DumpFileType, DumpRecordType, Engine},
+    options::{IndexType, Options},
+};
+use bytes::Bytes;
+
+const USAGE: &str = "usage: bitcask-cli [--read-only] [--index-type btree|skiplist|bptree] [--encryption-key <hex>] <dir> <command> [args]
+
+commands:
+  get <key>             print the value of key
+  put <key> <value>     put a key/value pair
+  delete <key>          delete a key
+  list                  list all keys
+  stat                  print engine statistics
+  merge                 merge data files
+  backup <dest_dir>     copy the data directory to dest_dir
+  dump-file <file>      print the records of a file, <file> is a data file id, hint, merge-fin, seq-no or mvcc-version
+  verify                check the crc of every record in all data files
+
+--read-only opens the engine without taking the file lock, so a live database can be inspected.
+--encryption-key is the 32-byte key of an encrypted database, written as 64 hex characters.
+dump-file and verify read the files directly and never take the file lock, the records of
+an encrypted database are decrypted with --encryption-key.";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(msg) = run(args) {
+        eprintln!("{}", msg);
+        process::exit(1);
+    }
+}
+
+fn run(args: Vec<String>) -> Result<(), String> {
+    let mut read_only = false;
+    let mut index_type = IndexType::BTree;
+    let mut encryption_key = None;
+    let mut rest = Vec::new();
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--read-only" => read_only = true,
+            "--index-type" => {
+                index_type = match iter.next().as_deref() {
+                    Some("btree") => IndexType::BTree,
+                    Some("skiplist") => IndexType::SkipList,
+                    Some("bptree") => IndexType::BPTree,
+                    _ => return Err(USAGE.to_string()),
+                }
+            }
+            "--encryption-key" => {
+                let hex = iter.next().ok_or_else(|| USAGE.to_string())?;
+                encryption_key = Some(parse_encryption_key(&hex)?);
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ => rest.push(arg),
+        }
+    }
+
+    if rest.len() < 2 {
+        return Err(USAGE.to_string());
+    }
+    let dir_path = PathBuf::from(&rest[0]);
+    let command = rest[1].as_str();
+    let params = &rest[2..];
+
+    // 直接读取文件的命令，不需要打开存储引擎
+    match (command, params) {
+        ("dump-file", [file]) => return dump_file(&dir_path, file, encryption_key),
+        ("verify", []) => return verify(&dir_path, encryption_key),
+        _ => {}
+    }
+
+    let opts = Options {
+        dir_path,
+        index_type,
+        read_only,
+        encryption_key,
+        ..Default::default()
+    };
+    let engine = Engine::open(opts).map_err(|e| format!("failed to open engine: {}", e))?;
+
+    match (command, params) {
+        ("get", [key]) => {
+            let value = engine
+                .get(Bytes::from(key.clone()))
+                .map_err(|e| format!("failed to get: {}", e))?;
+            println!("{}", String::from_utf8_lossy(&value));
+        }
+        ("put", [key, value]) => {
+            engine
+                .put(Bytes::from(key.clone()), Bytes::from(value.clone()))
+                .map_err(|e| format!("failed to put: {}", e))?;
+        }
+        ("delete", [key]) => engine
+            .delete(Bytes::from(key.clone()))
+            .map_err(|e| format!("failed to delete: {}", e))?,
+        ("list", []) => {
+            let keys = engine
+                .list_keys()
+                .map_err(|e| format!("failed to list keys: {}", e))?;
+            for key in keys {
+                println!("{}", String::from_utf8_lossy(&key));
+            }
+        }
+        ("stat", []) => {
+            let stat = engine
+                .stat()
+                .map_err(|e| format!("failed to stat: {}", e))?;
+            println!("key_num: {}", stat.key_num);
+            println!("data_file_num: {}", stat.data_file_num);
+            println!("reclaim_size: {}", stat.reclaim_size);
+            println!("disk_size: {}", stat.disk_size);
+            println!("index_key_num: {}", stat.index_key_num);
+            println!("index_memory_usage: {}", stat.index_memory_usage);
+        }
+        ("merge", []) => {
+            let report = engine
+                .merge()
+                .map_err(|e| format!("failed to merge: {}", e))?;
+            println!("files_merged: {}", report.files_merged);
+            println!("bytes_before: {}", report.bytes_before);
+            println!("bytes_after: {}", report.bytes_after);
+            println!("records_dropped: {}", report.records_dropped);
+            println!("duration: {:?}", report.duration);
+        }
+        ("backup", [dest_dir]) => engine
+            .backup(PathBuf::from(dest_dir))
+            .map_err(|e| format!("failed to backup: {}", e))?,
+        _ => return Err(USAGE.to_string()),
+    }
+
+    engine
+        .close()
+        .map_err(|e| format!("failed to close engine: {}", e))
+}
+
+// 解析十六进制格式的加密密钥
+fn parse_encryption_key(hex: &str) -> Result<[u8; 32], String> {
+    let invalid = || "encryption key must be 64 hex characters".to_string();
+    if hex.len() != 64 || !hex.is_ascii() {
+        return Err(invalid());
+    }
+    let mut key = [0u8; 32];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+    }
+    Ok(key)
+}
+
+// 根据命令行参数找到对应的文件
+fn parse_file_type(file: &str) -> Result<DumpFileType, String> {
+    let file_type = match file {
+        "hint" => DumpFileType::Hint,
+        "merge-fin" => DumpFileType::MergeFin,
+        "seq-no" => DumpFileType::SeqNo,
+        "mvcc-version" => DumpFileType::MvccVersion,
+        _ => {
+            let file_id = file
+                .parse::<u64>()
+                .map_err(|_| format!("invalid file: {}", file))?;
+            DumpFileType::Data(file_id)
+        }
+    };
+    Ok(file_type)
+}
+
+fn record_type_name(record_type: DumpRecordType) -> &'static str {
+    match record_type {
+        DumpRecordType::Put => "NORMAL",
+        DumpRecordType::Delete => "DELETE",
+        DumpRecordType::TxnFinished => "TXN_FINISHED",
+    }
+}
+
+// 打印文件中的所有记录
+fn dump_file(dir_path: &Path, file: &str, encryption_key: Option<[u8; 32]>) -> Result<(), String> {
+    let file_type = parse_file_type(file)?;
+    let iter = Engine::dump_path(dir_path.to_path_buf(), file_type, encryption_key)
+        .map_err(|e| format!("failed to open {}: {}", file, e))?;
+
+    if let Some(create_time) = iter.create_time() {
+        println!(
+            "header encrypted={} create_time={}",
+            iter.is_encrypted(),
+            create_time
+        );
+    }
+    for rec in iter {
+        let rec = rec.map_err(|e| format!("failed to dump {}: {}", file, e))?;
+        let crc = if rec.crc_valid { "ok" } else { "mismatch" };
+        let mut line = format!(
+            "offset={} size={} type={} crc={}",
+            rec.offset,
+            rec.size,
+            record_type_name(rec.record_type),
+            crc
+        );
+        if rec.timestamp > 0 {
+            line += &format!(" timestamp={}", rec.timestamp);
+        }
+
+        match file_type {
+            DumpFileType::Data(_) => {
+                line += &format!(" seq={} key={}", rec.seq_no, rec.key.escape_ascii());
+                if rec.value_pointer {
+                    // value 存放在 blob 文件中，记录中只有 value 的位置
+                    line += " value=blob";
+                } else {
+                    line += &format!(" value_size={}", rec.value.len());
+                }
+            }
+            DumpFileType::Hint => {
+                line += &format!(" key={}", rec.key.escape_ascii());
+                match rec.hint_pos {
+                    Some((file_id, offset, size)) => {
+                        line += &format!(" pos=({}, {}, {})", file_id, offset, size)
+                    }
+                    None => line += " pos=invalid",
+                }
+            }
+            _ => {
+                line += &format!(
+                    " key={} value={}",
+                    rec.key.escape_ascii(),
+                    rec.value.escape_ascii()
+                );
+            }
+        }
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+// 校验所有数据文件中记录的 crc
+fn verify(dir_path: &Path, encryption_key: Option<[u8; 32]>) -> Result<(), String> {
+    let file_ids = Engine::data_file_ids(dir_path.to_path_buf())
+        .map_err(|e| format!("failed to read {}: {}", dir_path.display(), e))?;
+
+    let mut corrupted = 0;
+    for file_id in file_ids.iter() {
+        let iter = Engine::dump_path(
+            dir_path.to_path_buf(),
+            DumpFileType::Data(*file_id),
+            encryption_key,
+        )
+        .map_err(|e| format!("failed to open data file {}: {}", file_id, e))?;
+
+        // 遇到校验失败或者无法解析的记录时，之后的数据都不可信
+        let mut valid = 0;
+        let mut corrupted_at = None;
+        for rec in iter {
+            match rec {
+                Ok(rec) if rec.crc_valid => valid += 1,
+                Ok(rec) => {
+                    corrupted_at = Some(format!("corrupted at offset {}", rec.offset));
+                    break;
+                }
+                Err(e) => {
+                    corrupted_at = Some(e.to_string());
+                    break;
+                }
+            }
+        }
+        match corrupted_at {
+            Some(msg) => {
+                corrupted += 1;
+                println!(
+                    "data file {}: {}, {} valid records before it",
+                    file_id, msg, valid
+                );
+            }
+            None => println!("data file {}: ok, {} records", file_id, valid),
+        }
+    }
+
+    if corrupted > 0 {
+        return Err(format!(
+            "{} of {} data files are corrupted",
+            corrupted,
+            file_ids.len()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::OpenOptions, os::unix::fs::FileExt};
+
+    use super::*;
+
+    #[test]
+    fn test_verify_engine_dir() {
+        let dir_path = PathBuf::from("/tmp/bitcask-cli-verify");
+        let opts = Options {
+            dir_path: dir_path.clone(),
+            ..Default::default()
+        };
+        let engine = Engine::open(opts).expect("failed to open engine");
+        for i in 0..100 {
+            let key = Bytes::from(format!("key-{}", i));
+            engine
+                .put(key.clone(), Bytes::from(format!("value-{}", i)))
+                .unwrap();
+            if i % 10 == 0 {
+                engine.delete(key).unwrap();
+            }
+        }
+        engine.close().unwrap();
+        std::mem::drop(engine);
+
+        // 引擎写入的记录带有写入时间，verify 和 dump-file 都能正确解码
+        assert!(verify(&dir_path, None).is_ok());
+        assert!(dump_file(&dir_path, "0", None).is_ok());
+        assert!(dump_file(&dir_path, "seq-no", None).is_ok());
+        assert!(dump_file(&dir_path, "hint", None).is_err());
+
+        let records: Vec<_> = Engine::dump_path(dir_path.clone(), DumpFileType::Data(0), None)
+            .unwrap()
+            .map(|rec| rec.unwrap())
+            .collect();
+        assert_eq!(records.len(), 110);
+        assert!(records.iter().all(|r| r.crc_valid && r.timestamp > 0));
+        assert_eq!(record_type_name(records[0].record_type), "NORMAL");
+        assert_eq!(record_type_name(records[1].record_type), "DELETE");
+
+        // 修改记录中的 value 之后校验失败
+        let file = OpenOptions::new()
+            .write(true)
+            .open(dir_path.join("000000000.data"))
+            .unwrap();
+        file.write_all_at(b"X", records[0].offset + records[0].size - 5)
+            .unwrap();
+        assert!(verify(&dir_path, None).is_err());
+
+        std::fs::remove_dir_all(dir_path).expect("failed to remove path");
+    }
+}
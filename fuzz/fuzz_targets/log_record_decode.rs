@@ -0,0 +1,40 @@
+//! 在数据文件的头部之后写入任意的字节，重新打开数据库并读取所有的 key
+//! 加载索引时解码损坏的记录不能 panic，只能返回错误或者丢弃损坏的数据
+//! 运行：cargo +nightly fuzz run log_record_decode
+#![no_main]
+
+use std::{fs, io::Write};
+
+use bitcask_rs::{db::Engine, options::Options};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let dir_path = std::env::temp_dir().join(format!("bitcask-rs-fuzz-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir_path);
+
+    let mut opts = Options::default();
+    opts.dir_path = dir_path.clone();
+
+    // 新建数据库，生成带有头部的空数据文件
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    engine.close().expect("failed to close engine");
+    drop(engine);
+
+    let data_file = dir_path.join(format!("{:09}.data", 0));
+    let mut file = fs::OpenOptions::new()
+        .append(true)
+        .open(&data_file)
+        .expect("failed to open data file");
+    file.write_all(data).expect("failed to write data file");
+    drop(file);
+
+    if let Ok(engine) = Engine::open(opts) {
+        if let Ok(keys) = engine.list_keys() {
+            for key in keys {
+                let _ = engine.get(key);
+            }
+        }
+    }
+
+    let _ = fs::remove_dir_all(&dir_path);
+});
@@ -1,20 +1,158 @@
-use std::{collections::HashMap, path::PathBuf, sync::Arc};
+use std::{
+    collections::HashMap,
+    io::{self, Read},
+    path::PathBuf,
+    time::Duration,
+};
 
 use actix_web::{
-    get, post,
-    web::{self, Bytes},
-    App, HttpResponse, HttpServer, Responder, Scope,
+    delete, get,
+    http::header,
+    post, put,
+    web::{self, Bytes, BytesMut},
+    App, HttpRequest, HttpResponse, HttpServer, Responder, Scope,
+};
+use bitcask_rs::{
+    db::{Engine, SizeHistogram},
+    errors::Errors,
+    options::{IndexType, IteratorOptions, Options, WriteBatchOptions},
+    value_reader::ValueReader,
 };
-use bitcask_rs::{db::Engine, options::Options};
+use clap::{Parser, ValueEnum};
+use futures_util::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+/// HTTP 服务的启动参数，每个参数都可以通过命令行或者环境变量指定
+#[derive(Parser)]
+#[command(about = "HTTP server for bitcask-rs")]
+struct Config {
+    /// 监听地址
+    #[arg(long, env = "BITCASK_BIND", default_value = "127.0.0.1:8000")]
+    bind: String,
+
+    /// 数据目录
+    #[arg(long, env = "BITCASK_DIR", default_value = "/tmp/bitcask-rs-http")]
+    dir: PathBuf,
+
+    /// 数据文件大小，单位为字节
+    #[arg(long, env = "BITCASK_DATA_FILE_SIZE", default_value_t = 64 * 1024 * 1024)]
+    data_file_size: u64,
+
+    /// 索引类型
+    #[arg(long, env = "BITCASK_INDEX_TYPE", value_enum, default_value_t = IndexKind::Btree)]
+    index_type: IndexKind,
+
+    /// 是否每次写都持久化
+    #[arg(long, env = "BITCASK_SYNC_WRITES")]
+    sync_writes: bool,
+
+    /// 累计写到多少字节后进行持久化，0 表示不按照字节数持久化
+    #[arg(long, env = "BITCASK_BYTES_PER_SYNC", default_value_t = 0)]
+    bytes_per_sync: usize,
+
+    /// 后台定期持久化活跃文件的时间间隔，单位为毫秒，0 表示不定期持久化
+    #[arg(long, env = "BITCASK_SYNC_INTERVAL_MS", default_value_t = 0)]
+    sync_interval_ms: u64,
+
+    /// 二进制接口上传和下载的 value 的大小上限，单位为字节，上传的 value 会整个缓冲在内存中
+    #[arg(long, env = "BITCASK_MAX_VALUE_SIZE", default_value_t = 64 * 1024 * 1024)]
+    max_value_size: u64,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum IndexKind {
+    Btree,
+    Skiplist,
+    Bptree,
+}
+
+impl Config {
+    fn options(&self) -> Options {
+        let mut opts = Options::default();
+        opts.dir_path = self.dir.clone();
+        opts.data_file_size = self.data_file_size;
+        opts.index_type = match self.index_type {
+            IndexKind::Btree => IndexType::BTree,
+            IndexKind::Skiplist => IndexType::SkipList,
+            IndexKind::Bptree => IndexType::BPTree,
+        };
+        opts.sync_writes = self.sync_writes;
+        opts.bytes_per_sync = self.bytes_per_sync;
+        if self.sync_interval_ms > 0 {
+            opts.sync_interval = Some(Duration::from_millis(self.sync_interval_ms));
+        }
+        opts
+    }
+}
+
+// 分页遍历没有指定 limit 时每页返回的数据条数
+const DEFAULT_PAGE_LIMIT: usize = 100;
+
+// 二进制接口下载 value 时每次从数据文件中读取并发送的字节数
+const RAW_CHUNK_SIZE: usize = 64 * 1024;
+
+/// 二进制接口的大小限制
+struct RawLimits {
+    max_value_size: u64,
+}
+
+/// 批量写入中的单个操作，按照请求中的顺序执行
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum BatchOp {
+    Put { key: String, value: String },
+    Delete { key: String },
+}
+
+/// 范围遍历的查询参数
+#[derive(Deserialize)]
+struct ScanParams {
+    prefix: Option<String>,
+    limit: Option<usize>,
+    reverse: Option<bool>,
+}
+
+/// 计数器的查询参数，delta 默认为 1
+#[derive(Deserialize)]
+struct IncrParams {
+    delta: Option<i64>,
+}
+
+/// 分页遍历的查询参数，after 为上一页返回的游标
+#[derive(Deserialize)]
+struct PageParams {
+    prefix: Option<String>,
+    after: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct KeyValue {
+    key: String,
+    value: String,
+}
+
+// 构造 JSON 格式的错误响应
+fn json_error(mut builder: actix_web::HttpResponseBuilder, msg: &str) -> HttpResponse {
+    builder.json(json!({ "error": msg }))
+}
 
 #[post("/put")]
 async fn put_handler(
-    eng: web::Data<Arc<Engine>>,
+    eng: web::Data<Engine>,
     data: web::Json<HashMap<String, String>>,
 ) -> impl Responder {
     for (key, value) in data.iter() {
-        if let Err(_) = eng.put(Bytes::from(key.to_string()), Bytes::from(value.to_string())) {
-            return HttpResponse::InternalServerError().body("failed to put value in engine");
+        match eng.put(Bytes::from(key.to_string()), Bytes::from(value.to_string())) {
+            Ok(_) => {}
+            // 正在 merge 并且可以回收的数据过多，客户端稍后重试
+            Err(Errors::WriteStalled) => {
+                return json_error(HttpResponse::ServiceUnavailable(), "write stalled")
+            }
+            Err(_) => {
+                return HttpResponse::InternalServerError().body("failed to put value in engine")
+            }
         }
     }
 
@@ -22,24 +160,127 @@ async fn put_handler(
 }
 
 #[get("/get/{key}")]
-async fn get_handler(eng: web::Data<Arc<Engine>>, key: web::Path<String>) -> impl Responder {
+async fn get_handler(eng: web::Data<Engine>, key: web::Path<String>) -> impl Responder {
     let value = match eng.get(Bytes::from(key.to_string())) {
         Ok(value) => value,
         Err(e) => {
-            if e != bitcask_rs::errors::Errors::KeyNotFound {
+            if e != Errors::KeyNotFound {
                 return HttpResponse::InternalServerError().body("failed to get value in engine");
             }
-            return HttpResponse::Ok().body("key not found");
+            return json_error(HttpResponse::NotFound(), "key not found");
         }
     };
 
     HttpResponse::Ok().body(value)
 }
 
+// 以二进制请求体上传 value，请求体整个缓冲在内存中之后再写入，大小不能超过 max_value_size
+// 引擎不支持分块写入，所以上传的接口单独命名，和按块下载的 GET /raw/{key} 区分开
+#[put("/raw-buffered/{key}")]
+async fn raw_buffered_put_handler(
+    eng: web::Data<Engine>,
+    limits: web::Data<RawLimits>,
+    key: web::Path<String>,
+    req: HttpRequest,
+    mut payload: web::Payload,
+) -> impl Responder {
+    // 请求体按块接收到预先分配的缓冲区中，声明的或者实际接收的大小超过上限时立即拒绝
+    let content_length = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if content_length.is_some_and(|len| len > limits.max_value_size) {
+        return json_error(HttpResponse::PayloadTooLarge(), "value is too large");
+    }
+    let mut value = BytesMut::with_capacity(content_length.unwrap_or(0) as usize);
+    while let Some(chunk) = payload.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(_) => return json_error(HttpResponse::BadRequest(), "failed to read request body"),
+        };
+        if (value.len() + chunk.len()) as u64 > limits.max_value_size {
+            return json_error(HttpResponse::PayloadTooLarge(), "value is too large");
+        }
+        value.extend_from_slice(&chunk);
+    }
+
+    match eng.put(Bytes::from(key.to_string()), value.freeze()) {
+        Ok(_) => HttpResponse::Ok().json(json!({ "ok": true })),
+        Err(Errors::KeyIsEmpty) => json_error(HttpResponse::BadRequest(), "key is empty"),
+        Err(Errors::WriteStalled) => {
+            json_error(HttpResponse::ServiceUnavailable(), "write stalled")
+        }
+        Err(_) => json_error(
+            HttpResponse::InternalServerError(),
+            "failed to put value in engine",
+        ),
+    }
+}
+
+#[get("/raw/{key}")]
+async fn raw_get_handler(
+    eng: web::Data<Engine>,
+    limits: web::Data<RawLimits>,
+    key: web::Path<String>,
+) -> impl Responder {
+    // 先读取 value 的大小，过大的 value 不读取数据直接拒绝
+    let key = Bytes::from(key.to_string());
+    match eng.value_len(key.clone()) {
+        Ok(len) if len > limits.max_value_size => {
+            return json_error(HttpResponse::PayloadTooLarge(), "value is too large")
+        }
+        Ok(_) => {}
+        Err(Errors::KeyNotFound) => return json_error(HttpResponse::NotFound(), "key not found"),
+        Err(Errors::KeyIsEmpty) => return json_error(HttpResponse::BadRequest(), "key is empty"),
+        Err(_) => {
+            return json_error(
+                HttpResponse::InternalServerError(),
+                "failed to get value in engine",
+            )
+        }
+    }
+    let reader = match eng.value_reader(key) {
+        Ok(reader) => reader,
+        Err(Errors::KeyNotFound) => return json_error(HttpResponse::NotFound(), "key not found"),
+        Err(_) => {
+            return json_error(
+                HttpResponse::InternalServerError(),
+                "failed to get value in engine",
+            )
+        }
+    };
+
+    // 按块读取并发送，读取文件是阻塞的，放到阻塞线程池中执行，读取出错时中断响应
+    let len = reader.len();
+    let body = stream::unfold(Some(reader), |reader: Option<ValueReader>| async move {
+        let mut reader = reader?;
+        let res = web::block(move || {
+            let mut buf = vec![0u8; RAW_CHUNK_SIZE];
+            let res = reader.read(&mut buf).map(|n| {
+                buf.truncate(n);
+                buf
+            });
+            (reader, res)
+        })
+        .await;
+        match res {
+            Ok((_, Ok(buf))) if buf.is_empty() => None,
+            Ok((reader, Ok(buf))) => Some((Ok(Bytes::from(buf)), Some(reader))),
+            Ok((_, Err(e))) => Some((Err(e), None)),
+            Err(e) => Some((Err(io::Error::new(io::ErrorKind::Other, e)), None)),
+        }
+    });
+    HttpResponse::Ok()
+        .content_type("application/octet-stream")
+        .no_chunking(len)
+        .streaming(body)
+}
+
 #[get("/delete/{key}")]
-async fn delete_handler(eng: web::Data<Arc<Engine>>, key: web::Path<String>) -> impl Responder {
+async fn delete_handler(eng: web::Data<Engine>, key: web::Path<String>) -> impl Responder {
     if let Err(e) = eng.delete(Bytes::from(key.to_string())) {
-        if e != bitcask_rs::errors::Errors::KeyIsEmpty {
+        if e != Errors::KeyIsEmpty {
             return HttpResponse::InternalServerError().body("failed to delete value in engine");
         }
     }
@@ -47,8 +288,157 @@ async fn delete_handler(eng: web::Data<Arc<Engine>>, key: web::Path<String>) ->
     HttpResponse::Ok().body("OK")
 }
 
+#[delete("/key/{key}")]
+async fn delete_key_handler(eng: web::Data<Engine>, key: web::Path<String>) -> impl Responder {
+    let key = Bytes::from(key.to_string());
+    match eng.get(key.clone()) {
+        Ok(_) => {}
+        Err(Errors::KeyNotFound) => return json_error(HttpResponse::NotFound(), "key not found"),
+        Err(Errors::KeyIsEmpty) => return json_error(HttpResponse::BadRequest(), "key is empty"),
+        Err(_) => {
+            return json_error(
+                HttpResponse::InternalServerError(),
+                "failed to delete value in engine",
+            )
+        }
+    }
+
+    if eng.delete(key).is_err() {
+        return json_error(
+            HttpResponse::InternalServerError(),
+            "failed to delete value in engine",
+        );
+    }
+
+    HttpResponse::Ok().json(json!({ "deleted": true }))
+}
+
+#[post("/incr/{key}")]
+async fn incr_handler(
+    eng: web::Data<Engine>,
+    key: web::Path<String>,
+    params: web::Query<IncrParams>,
+) -> impl Responder {
+    let delta = params.delta.unwrap_or(1);
+    match eng.increment(Bytes::from(key.to_string()), delta) {
+        Ok(value) => HttpResponse::Ok().json(json!({ "value": value })),
+        Err(Errors::KeyIsEmpty) => json_error(HttpResponse::BadRequest(), "key is empty"),
+        Err(Errors::InvaildCounterValue) => json_error(
+            HttpResponse::BadRequest(),
+            "the value is not a valid counter",
+        ),
+        Err(Errors::CounterOverflow) => {
+            json_error(HttpResponse::BadRequest(), "the counter overflows")
+        }
+        Err(Errors::WriteStalled) => {
+            json_error(HttpResponse::ServiceUnavailable(), "write stalled")
+        }
+        Err(_) => json_error(
+            HttpResponse::InternalServerError(),
+            "failed to increment counter in engine",
+        ),
+    }
+}
+
+#[post("/batch")]
+async fn batch_handler(eng: web::Data<Engine>, ops: web::Json<Vec<BatchOp>>) -> impl Responder {
+    let wb = match eng.new_write_batch(WriteBatchOptions::default()) {
+        Ok(wb) => wb,
+        Err(_) => {
+            return json_error(
+                HttpResponse::InternalServerError(),
+                "failed to create write batch",
+            )
+        }
+    };
+
+    for op in ops.iter() {
+        let res = match op {
+            BatchOp::Put { key, value } => {
+                wb.put(Bytes::from(key.to_string()), Bytes::from(value.to_string()))
+            }
+            BatchOp::Delete { key } => wb.delete(Bytes::from(key.to_string())),
+        };
+        if let Err(e) = res {
+            if e == Errors::KeyIsEmpty {
+                return json_error(HttpResponse::BadRequest(), "key is empty");
+            }
+            return json_error(
+                HttpResponse::InternalServerError(),
+                "failed to write batch in engine",
+            );
+        }
+    }
+
+    match wb.commit() {
+        Ok(_) => HttpResponse::Ok().json(json!({ "count": ops.len() })),
+        Err(Errors::ExceedMaxBatchNum) => {
+            json_error(HttpResponse::BadRequest(), "exceed the max batch num")
+        }
+        Err(_) => json_error(
+            HttpResponse::InternalServerError(),
+            "failed to commit batch in engine",
+        ),
+    }
+}
+
+#[get("/scan")]
+async fn scan_handler(eng: web::Data<Engine>, params: web::Query<ScanParams>) -> impl Responder {
+    let mut iter = eng.iter(IteratorOptions {
+        prefix: params.prefix.clone().unwrap_or_default().into_bytes(),
+        reverse: params.reverse.unwrap_or(false),
+        ..Default::default()
+    });
+
+    let limit = params.limit.unwrap_or(usize::MAX);
+    let mut result = Vec::new();
+    while result.len() < limit {
+        match iter.next() {
+            Some((key, value)) => result.push(KeyValue {
+                key: String::from_utf8_lossy(&key).to_string(),
+                value: String::from_utf8_lossy(&value).to_string(),
+            }),
+            None => break,
+        }
+    }
+
+    HttpResponse::Ok().json(result)
+}
+
+#[get("/keys")]
+async fn page_handler(eng: web::Data<Engine>, params: web::Query<PageParams>) -> impl Responder {
+    let prefix = Bytes::from(params.prefix.clone().unwrap_or_default());
+    let after = params.after.clone().map(Bytes::from);
+    let page = match eng.scan(prefix, after, params.limit.unwrap_or(DEFAULT_PAGE_LIMIT)) {
+        Ok(page) => page,
+        Err(Errors::InvaildScanLimit) => {
+            return json_error(HttpResponse::BadRequest(), "limit must be greater than 0")
+        }
+        Err(_) => {
+            return json_error(
+                HttpResponse::InternalServerError(),
+                "failed to scan keys in engine",
+            )
+        }
+    };
+
+    let items = page
+        .items
+        .into_iter()
+        .map(|(key, value)| KeyValue {
+            key: String::from_utf8_lossy(&key).to_string(),
+            value: String::from_utf8_lossy(&value).to_string(),
+        })
+        .collect::<Vec<KeyValue>>();
+    let next = page
+        .next
+        .map(|key| String::from_utf8_lossy(&key).to_string());
+
+    HttpResponse::Ok().json(json!({ "items": items, "next": next }))
+}
+
 #[get("/listkeys")]
-async fn list_keys_handler(eng: web::Data<Arc<Engine>>) -> impl Responder {
+async fn list_keys_handler(eng: web::Data<Engine>) -> impl Responder {
     let keys = match eng.list_keys() {
         Ok(keys) => keys,
         Err(_) => return HttpResponse::InternalServerError().body("failed to list keys in engine"),
@@ -66,43 +456,87 @@ async fn list_keys_handler(eng: web::Data<Arc<Engine>>) -> impl Responder {
 }
 
 #[get("/stat")]
-async fn stat_handler(eng: web::Data<Arc<Engine>>) -> impl Responder {
+async fn stat_handler(eng: web::Data<Engine>) -> impl Responder {
     let stat = match eng.stat() {
         Ok(stat) => stat,
         Err(_) => return HttpResponse::InternalServerError().body("failed to stat in engine"),
     };
+    let (key_sizes, value_sizes) = match (eng.key_size_histogram(), eng.value_size_histogram()) {
+        (Ok(key_sizes), Ok(value_sizes)) => (key_sizes, value_sizes),
+        _ => return HttpResponse::InternalServerError().body("failed to stat in engine"),
+    };
 
-    let mut result = HashMap::new();
-    result.insert("key_num", stat.key_num);
-    result.insert("reclaim_size", stat.reclaim_size);
-    result.insert("data_file_num", stat.data_file_num);
-    result.insert("disk_size", stat.disk_size as usize);
+    HttpResponse::Ok().json(json!({
+        "key_num": stat.key_num,
+        "reclaim_size": stat.reclaim_size,
+        "data_file_num": stat.data_file_num,
+        "disk_size": stat.disk_size,
+        "index_key_num": stat.index_key_num,
+        "index_memory_usage": stat.index_memory_usage,
+        "key_size_histogram": histogram_json(&key_sizes),
+        "value_size_histogram": histogram_json(&value_sizes),
+    }))
+}
 
-    HttpResponse::Ok()
-        .content_type("application/json")
-        .body(serde_json::to_string(&result).unwrap())
+// 直方图转换为 JSON，每个区间包含上界和数量
+fn histogram_json(histogram: &SizeHistogram) -> serde_json::Value {
+    let buckets: Vec<serde_json::Value> = histogram
+        .buckets
+        .iter()
+        .map(|bucket| json!({ "le": bucket.upper_bound, "count": bucket.count }))
+        .collect();
+    json!({
+        "count": histogram.count,
+        "total": histogram.total,
+        "max": histogram.max,
+        "buckets": buckets,
+    })
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    let config = Config::parse();
+
     // 启动 Engine 实例
-    let mut opts = Options::default();
-    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-http");
-    opts.data_file_size = 64 * 1024 * 1024;
-    let engine = Arc::new(Engine::open(opts.clone()).expect("failed to open engine"));
+    let engine = match Engine::open(config.options()) {
+        Ok(engine) => engine,
+        Err(e) => {
+            eprintln!("failed to open engine: {}", e);
+            std::process::exit(1);
+        }
+    };
 
-    // 启动 HTTP 服务
+    // 启动 HTTP 服务，收到 SIGINT 或 SIGTERM 时停止接收新的请求，等待处理中的请求完成之后返回
+    let app_engine = engine.clone();
+    let max_value_size = config.max_value_size;
     HttpServer::new(move || {
-        App::new().app_data(web::Data::new(engine.clone())).service(
-            Scope::new("/bitcask")
-                .service(put_handler)
-                .service(get_handler)
-                .service(delete_handler)
-                .service(list_keys_handler)
-                .service(stat_handler),
-        )
+        App::new()
+            .app_data(web::Data::new(app_engine.clone()))
+            .app_data(web::Data::new(RawLimits { max_value_size }))
+            .service(
+                Scope::new("/bitcask")
+                    .service(put_handler)
+                    .service(get_handler)
+                    .service(raw_buffered_put_handler)
+                    .service(raw_get_handler)
+                    .service(delete_handler)
+                    .service(delete_key_handler)
+                    .service(incr_handler)
+                    .service(batch_handler)
+                    .service(scan_handler)
+                    .service(page_handler)
+                    .service(list_keys_handler)
+                    .service(stat_handler),
+            )
     })
-    .bind(("127.0.0.1", 8000))?
+    .bind(config.bind.as_str())?
     .run()
-    .await
+    .await?;
+
+    // 退出之前关闭数据库，持久化活跃文件并写入事务序列号
+    if let Err(e) = engine.close() {
+        eprintln!("failed to close engine: {}", e);
+        std::process::exit(1);
+    }
+    Ok(())
 }
@@ -0,0 +1,194 @@
+//! 将旧的数据文件上传到对象存储，需要开启 object-store feature
+//! 旧的数据文件不会再修改，上传之后可以删除本地的文件，读取时从对象存储中按块读取并缓存，
+//! 重启时对象存储中的数据文件和本地的数据文件一起加载，merge 删除的旧数据文件也会从对象存储中删除
+
+use std::{fs, sync::Arc};
+
+use log::error;
+
+use crate::{
+    data::data_file::{get_data_file_name, MERGE_FIN_FILE_NAME},
+    db::{sync_dir_if_enabled, Engine},
+    errors::{Errors, Result},
+    merge::{get_merge_path, read_non_merge_file_id},
+    options::IOType,
+};
+
+impl Engine {
+    /// 将还没有上传的旧数据文件上传到对象存储，返回这次上传的文件数量
+    /// 没有开启 keep_local_files 时上传之后删除本地的文件，之后从对象存储中读取
+    pub fn upload_sealed_files(&self) -> Result<usize> {
+        self.check_closed()?;
+        if self.options.read_only {
+            return Err(Errors::DatabaseIsReadOnly);
+        }
+        let archive = match &self.archive {
+            Some(archive) => archive,
+            None => return Err(Errors::ObjectStoreNotConfigured),
+        };
+
+        // 和 merge 互斥，避免上传期间旧的数据文件被删除
+        let lock = self.merging_lock.try_lock();
+        if lock.is_none() {
+            return Err(Errors::MergeInProgress);
+        }
+
+        // 还没有安装的 merge 结果会替换掉较早的数据文件，这些文件不需要上传
+        let dir_path = self.options.dir_path.clone();
+        let merge_path = get_merge_path(dir_path.clone());
+        let merged_before = match merge_path.join(MERGE_FIN_FILE_NAME).is_file() {
+            true => read_non_merge_file_id(merge_path)?,
+            false => 0,
+        };
+
+        let mut file_ids: Vec<u64> = self
+            .older_files
+            .read()
+            .keys()
+            .copied()
+            .filter(|file_id| *file_id >= merged_before && !archive.is_archived(*file_id))
+            .collect();
+        file_ids.sort();
+
+        for file_id in file_ids.iter() {
+            let file_path = get_data_file_name(dir_path.clone(), *file_id);
+            archive.upload(*file_id, &file_path)?;
+            if archive.keep_local_files() {
+                continue;
+            }
+
+            // 先切换为从对象存储中读取，再删除本地的文件，正在读取的旧句柄仍然持有本地文件
+            if let Some(data_file) = self.older_files.write().get_mut(file_id) {
+                *data_file = Arc::new(data_file.reopen(dir_path.clone(), IOType::ObjectStore)?);
+            }
+            if let Err(e) = fs::remove_file(file_path) {
+                error!("failed to remove data file: {}", e);
+                return Err(Errors::FailedToRemoveDataFile);
+            }
+        }
+        if !file_ids.is_empty() && !archive.keep_local_files() {
+            sync_dir_if_enabled(self.options.fsync_dir, &dir_path)?;
+        }
+
+        Ok(file_ids.len())
+    }
+
+    // 已经上传到对象存储并且删除了本地文件的数据文件的总大小
+    pub(crate) fn archived_data_size(&self) -> u64 {
+        let dir_path = self.options.dir_path.clone();
+        self.older_files
+            .read()
+            .values()
+            .filter(|data_file| {
+                !get_data_file_name(dir_path.clone(), data_file.get_file_id()).is_file()
+            })
+            .map(|data_file| data_file.file_size())
+            .sum()
+    }
+
+    // merge 完成之后按照配置上传旧的数据文件，上传失败不影响 merge 的结果，下次 merge 之后重试
+    pub(crate) fn upload_after_merge(&self) {
+        let upload_after_merge = match &self.options.object_store {
+            Some(options) => options.upload_after_merge,
+            None => false,
+        };
+        if !upload_after_merge || self.options.read_only {
+            return;
+        }
+        if let Err(e) = self.upload_sealed_files() {
+            error!("failed to upload data files to object store: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::Arc};
+
+    use super::*;
+    use crate::{
+        options::{LocalObjectStore, ObjectStore, ObjectStoreOptions, Options},
+        util::rand_kv::{get_test_key, get_test_value},
+    };
+
+    // 数据目录中本地的数据文件数量
+    fn local_data_file_num(dir_path: &PathBuf) -> usize {
+        fs::read_dir(dir_path)
+            .unwrap()
+            .filter(|entry| {
+                let name = entry.as_ref().unwrap().file_name();
+                name.to_str().unwrap().ends_with(".data")
+            })
+            .count()
+    }
+
+    #[test]
+    fn test_upload_sealed_files() {
+        let store_path = PathBuf::from("/tmp/bitcask-rs-upload-sealed-store");
+        let store = Arc::new(LocalObjectStore::new(store_path.clone()).unwrap());
+        let mut object_store_options = ObjectStoreOptions::new(store.clone());
+        object_store_options.cache_chunk_size = 4 * 1024;
+        object_store_options.cache_size = 16 * 1024;
+
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-upload-sealed");
+        opts.data_file_size = 32 * 1024;
+        opts.object_store = Some(object_store_options);
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..2000 {
+            assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        let uploaded = engine.upload_sealed_files().unwrap();
+        assert!(uploaded > 0);
+        assert_eq!(store.list("").unwrap().len(), uploaded);
+        // 只保留了活跃文件
+        assert_eq!(local_data_file_num(&opts.dir_path), 1);
+        assert_eq!(engine.upload_sealed_files().unwrap(), 0);
+        for i in 0..2000 {
+            assert_eq!(
+                engine.get(get_test_key(i)).unwrap(),
+                get_test_value(i).slice(..)
+            );
+        }
+        assert!(engine.dump_file(0).is_ok());
+
+        // 重启之后从对象存储中加载数据文件
+        std::mem::drop(engine);
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine.list_keys().unwrap().len(), 2000);
+        for i in 0..1000 {
+            assert!(engine.delete(get_test_key(i)).is_ok());
+        }
+
+        // merge 之后对象存储中的旧数据文件被删除，merge 之后的数据文件重新上传
+        assert!(engine.merge().is_ok());
+        std::mem::drop(engine);
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine.list_keys().unwrap().len(), 1000);
+        for i in 1000..2000 {
+            assert_eq!(
+                engine.get(get_test_key(i)).unwrap(),
+                get_test_value(i).slice(..)
+            );
+        }
+        let older_file_num = engine.older_files.read().len();
+        assert_eq!(store.list("").unwrap().len(), older_file_num);
+        assert_eq!(local_data_file_num(&opts.dir_path), 1);
+
+        // 没有配置对象存储
+        std::mem::drop(engine);
+        opts.object_store = None;
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-upload-sealed-none");
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(
+            engine.upload_sealed_files().err().unwrap(),
+            Errors::ObjectStoreNotConfigured
+        );
+
+        std::mem::drop(engine);
+        fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+        fs::remove_dir_all("/tmp/bitcask-rs-upload-sealed").expect("failed to remove path");
+        fs::remove_dir_all(store_path).expect("failed to remove path");
+    }
+}
@@ -0,0 +1,373 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use parking_lot::{Mutex, MutexGuard};
+
+use crate::{
+    db::Engine,
+    errors::{Errors, Result},
+};
+
+// key 锁的分段数量
+const KEY_LOCK_STRIPES: usize = 64;
+
+/// 按照 key 的哈希值分段的锁，写入同一个 key 的操作需要先获取对应分段的锁
+/// 读改写操作持有锁期间读取到的数据不会被其他写入修改
+pub(crate) struct KeyLocks {
+    stripes: Vec<Mutex<()>>,
+}
+
+impl KeyLocks {
+    pub(crate) fn new() -> Self {
+        Self {
+            stripes: (0..KEY_LOCK_STRIPES).map(|_| Mutex::new(())).collect(),
+        }
+    }
+
+    /// 获取单个 key 的锁
+    pub(crate) fn lock(&self, key: &[u8]) -> MutexGuard<'_, ()> {
+        self.stripes[self.stripe(key)].lock()
+    }
+
+    /// 获取多个 key 的锁，按照分段的顺序加锁，避免死锁
+    pub(crate) fn lock_keys<'a, I>(&self, keys: I) -> Vec<MutexGuard<'_, ()>>
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        let mut stripes: Vec<usize> = keys.into_iter().map(|key| self.stripe(key)).collect();
+        stripes.sort();
+        stripes.dedup();
+        stripes
+            .into_iter()
+            .map(|i| self.stripes[i].lock())
+            .collect()
+    }
+
+    fn stripe(&self, key: &[u8]) -> usize {
+        crc32fast::hash(key) as usize % self.stripes.len()
+    }
+}
+
+impl Engine {
+    /// key 不存在时写入数据，返回是否写入
+    pub fn put_if_absent(&self, key: Bytes, value: Bytes) -> Result<bool> {
+        self.compare_and_swap(key, None, value)
+    }
+
+    /// key 当前的值等于 expected 时写入新的值，返回是否写入
+    /// expected 为 None 表示 key 必须不存在
+    pub fn compare_and_swap(
+        &self,
+        key: Bytes,
+        expected: Option<Bytes>,
+        new: Bytes,
+    ) -> Result<bool> {
+        self.check_closed()?;
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+        self.check_write_stall()?;
+
+        let _key_lock = self.key_locks.lock(&key);
+        if self.current_value(&key)? != expected {
+            return Ok(false);
+        }
+        self.do_put(key, new)?;
+        Ok(true)
+    }
+
+    /// key 当前的值等于 expected 时删除，返回是否删除
+    pub fn delete_if(&self, key: Bytes, expected: Bytes) -> Result<bool> {
+        self.check_closed()?;
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+
+        let _key_lock = self.key_locks.lock(&key);
+        if self.current_value(&key)? != Some(expected) {
+            return Ok(false);
+        }
+        self.do_delete(key)?;
+        Ok(true)
+    }
+
+    /// 计数器加上 delta，返回新的值，key 不存在时从 0 开始计数
+    /// 计数器的值按照小端序的 i64 存储，不是 8 个字节的值返回 InvaildCounterValue 错误
+    pub fn increment(&self, key: Bytes, delta: i64) -> Result<i64> {
+        self.check_closed()?;
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+        self.check_write_stall()?;
+
+        let _key_lock = self.key_locks.lock(&key);
+        let current = match self.current_value(&key)? {
+            Some(value) => decode_counter(&value)?,
+            None => 0,
+        };
+        let new = current.checked_add(delta).ok_or(Errors::CounterOverflow)?;
+        self.do_put(key, Bytes::copy_from_slice(&new.to_le_bytes()))?;
+        Ok(new)
+    }
+
+    /// 计数器减去 delta，返回新的值
+    pub fn decrement(&self, key: Bytes, delta: i64) -> Result<i64> {
+        let delta = delta.checked_neg().ok_or(Errors::CounterOverflow)?;
+        self.increment(key, delta)
+    }
+
+    /// 在 key 当前的值后面追加数据，返回追加之后 value 的长度，key 不存在时直接写入 data
+    /// 每次追加都会重新写入完整的 value，适合 value 不会无限增长的场景
+    pub fn append(&self, key: Bytes, data: Bytes) -> Result<usize> {
+        self.check_closed()?;
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+        self.check_write_stall()?;
+
+        let _key_lock = self.key_locks.lock(&key);
+        let value = match self.current_value(&key)? {
+            Some(current) => {
+                let mut buf = BytesMut::with_capacity(current.len() + data.len());
+                buf.put_slice(&current);
+                buf.put_slice(&data);
+                buf.freeze()
+            }
+            None => data,
+        };
+        let len = value.len();
+        self.do_put(key, value)?;
+        Ok(len)
+    }
+
+    // 读取 key 当前的值，不存在时返回 None
+    fn current_value(&self, key: &Bytes) -> Result<Option<Bytes>> {
+        match self.get(key.clone()) {
+            Ok(value) => Ok(Some(value)),
+            Err(Errors::KeyNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+// 解析计数器的值
+fn decode_counter(value: &[u8]) -> Result<i64> {
+    match <[u8; 8]>::try_from(value) {
+        Ok(buf) => Ok(i64::from_le_bytes(buf)),
+        Err(_) => Err(Errors::InvaildCounterValue),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::Arc, thread};
+
+    use super::*;
+    use crate::options::Options;
+
+    #[test]
+    fn test_compare_and_swap() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-compare-and-swap");
+        opts.data_file_size = 64 * 1024 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        assert!(engine
+            .put_if_absent(Bytes::from("key1"), Bytes::from("v1"))
+            .unwrap());
+        assert!(!engine
+            .put_if_absent(Bytes::from("key1"), Bytes::from("v2"))
+            .unwrap());
+        assert_eq!(engine.get(Bytes::from("key1")).unwrap(), Bytes::from("v1"));
+
+        // 当前值不匹配时不写入
+        assert!(!engine
+            .compare_and_swap(
+                Bytes::from("key1"),
+                Some(Bytes::from("v2")),
+                Bytes::from("v3")
+            )
+            .unwrap());
+        assert!(engine
+            .compare_and_swap(
+                Bytes::from("key1"),
+                Some(Bytes::from("v1")),
+                Bytes::from("v3")
+            )
+            .unwrap());
+        assert_eq!(engine.get(Bytes::from("key1")).unwrap(), Bytes::from("v3"));
+        assert!(!engine
+            .compare_and_swap(Bytes::from("key1"), None, Bytes::from("v4"))
+            .unwrap());
+
+        assert!(!engine
+            .delete_if(Bytes::from("key1"), Bytes::from("v1"))
+            .unwrap());
+        assert!(engine
+            .delete_if(Bytes::from("key1"), Bytes::from("v3"))
+            .unwrap());
+        assert_eq!(
+            engine.get(Bytes::from("key1")).err().unwrap(),
+            Errors::KeyNotFound
+        );
+        assert!(!engine
+            .delete_if(Bytes::from("key1"), Bytes::from("v3"))
+            .unwrap());
+        assert_eq!(
+            engine
+                .put_if_absent(Bytes::new(), Bytes::from("v"))
+                .err()
+                .unwrap(),
+            Errors::KeyIsEmpty
+        );
+
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_increment() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-increment");
+        opts.data_file_size = 64 * 1024 * 1024;
+        let engine = Arc::new(Engine::open(opts.clone()).expect("failed to open engine"));
+
+        assert_eq!(engine.increment(Bytes::from("counter"), 5).unwrap(), 5);
+        assert_eq!(engine.decrement(Bytes::from("counter"), 7).unwrap(), -2);
+        let value = engine.get(Bytes::from("counter")).unwrap();
+        assert_eq!(value, Bytes::copy_from_slice(&(-2i64).to_le_bytes()));
+        assert_eq!(decode_counter(&value).unwrap(), -2);
+
+        // 并发计数不会丢失更新
+        let mut handles = Vec::new();
+        for _ in 0..4 {
+            let engine = engine.clone();
+            handles.push(thread::spawn(move || {
+                for _ in 0..100 {
+                    engine.increment(Bytes::from("counter"), 1).unwrap();
+                }
+            }));
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(engine.increment(Bytes::from("counter"), 0).unwrap(), 398);
+
+        assert!(engine
+            .put(Bytes::from("not-counter"), Bytes::from("abc"))
+            .is_ok());
+        assert_eq!(
+            engine
+                .increment(Bytes::from("not-counter"), 1)
+                .err()
+                .unwrap(),
+            Errors::InvaildCounterValue
+        );
+        assert!(engine
+            .put(
+                Bytes::from("max"),
+                Bytes::copy_from_slice(&i64::MAX.to_le_bytes())
+            )
+            .is_ok());
+        assert_eq!(
+            engine.increment(Bytes::from("max"), 1).err().unwrap(),
+            Errors::CounterOverflow
+        );
+        assert_eq!(
+            engine
+                .decrement(Bytes::from("max"), i64::MIN)
+                .err()
+                .unwrap(),
+            Errors::CounterOverflow
+        );
+
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_append() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-append");
+        opts.data_file_size = 64 * 1024 * 1024;
+        let engine = Arc::new(Engine::open(opts.clone()).expect("failed to open engine"));
+
+        assert_eq!(
+            engine.append(Bytes::from("log"), Bytes::from("a")).unwrap(),
+            1
+        );
+        assert_eq!(
+            engine
+                .append(Bytes::from("log"), Bytes::from("bc"))
+                .unwrap(),
+            3
+        );
+        assert_eq!(engine.get(Bytes::from("log")).unwrap(), Bytes::from("abc"));
+
+        // 并发追加不会丢失数据
+        let mut handles = Vec::new();
+        for i in 0..4 {
+            let engine = engine.clone();
+            handles.push(thread::spawn(move || {
+                for _ in 0..50 {
+                    engine
+                        .append(Bytes::from("log"), Bytes::from(i.to_string()))
+                        .unwrap();
+                }
+            }));
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        let value = engine.get(Bytes::from("log")).unwrap();
+        assert_eq!(value.len(), 203);
+        for i in 0..4 {
+            let c = i.to_string().as_bytes()[0];
+            assert_eq!(value.iter().filter(|b| **b == c).count(), 50);
+        }
+
+        assert_eq!(
+            engine.append(Bytes::new(), Bytes::from("a")).err().unwrap(),
+            Errors::KeyIsEmpty
+        );
+
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_compare_and_swap_concurrent() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-compare-and-swap-concurrent");
+        opts.data_file_size = 64 * 1024 * 1024;
+        let engine = Arc::new(Engine::open(opts.clone()).expect("failed to open engine"));
+        assert!(engine.put(Bytes::from("counter"), Bytes::from("0")).is_ok());
+
+        // 并发地基于当前值写入新值，每次成功的写入都不会被覆盖
+        let mut handles = Vec::new();
+        for _ in 0..4 {
+            let engine = engine.clone();
+            handles.push(thread::spawn(move || {
+                let mut done = 0;
+                while done < 50 {
+                    let current = engine.get(Bytes::from("counter")).unwrap();
+                    let n: u64 = String::from_utf8(current.to_vec())
+                        .unwrap()
+                        .parse()
+                        .unwrap();
+                    let new = Bytes::from((n + 1).to_string());
+                    if engine
+                        .compare_and_swap(Bytes::from("counter"), Some(current), new)
+                        .unwrap()
+                    {
+                        done += 1;
+                    }
+                }
+            }));
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(
+            engine.get(Bytes::from("counter")).unwrap(),
+            Bytes::from("200")
+        );
+
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+}
@@ -1,45 +1,56 @@
 use std::{
     collections::HashMap,
-    sync::{atomic::Ordering, Arc},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
 };
 
 use bytes::{BufMut, Bytes, BytesMut};
 use parking_lot::Mutex;
-use prost::{decode_length_delimiter, encode_length_delimiter};
+use prost::encoding::{decode_varint, encode_varint};
 
 use crate::{
-    data::log_record::{LogRecord, LogRecordPos, LogRecordType},
+    data::log_record::{LogRecord, LogRecordType},
     db::Engine,
     errors::{Errors, Result},
+    hlc,
     options::{IndexType, WriteBatchOptions},
 };
 
 const TXN_FIN_KEY: &[u8] = "txn-fin".as_bytes();
-pub(crate) const NON_TRANSACTION_SEQ_NO: usize = 0;
+pub(crate) const NON_TRANSACTION_SEQ_NO: u64 = 0;
 
 /// 批量写操纵，保证原子性
-pub struct WriteBatch<'a> {
-    pending_writes: Arc<Mutex<HashMap<Vec<u8>, LogRecord>>>,
-    engine: &'a Engine,
+/// 暂存的操作按照写入顺序保存，提交时按照相同的顺序写入数据文件并更新索引
+/// 持有存储引擎的 handle，不依赖 engine 的生命周期，可以在线程之间传递，
+/// 或者在异步任务中跨越 await 保存
+pub struct WriteBatch {
+    pending_writes: Arc<Mutex<Vec<LogRecord>>>,
+    pending_bytes: Arc<AtomicU64>, // 暂存的 key 和 value 的字节数，只在持有 pending_writes 锁时修改
+    engine: Engine,
     options: WriteBatchOptions,
 }
 
 impl Engine {
     pub fn new_write_batch(&self, options: WriteBatchOptions) -> Result<WriteBatch> {
+        self.check_closed()?;
         if self.options.index_type == IndexType::BPTree && !self.seq_file_exists && !self.is_initial
         {
             return Err(Errors::UableToUseWriteBatch);
         }
 
         Ok(WriteBatch {
-            pending_writes: Arc::new(Mutex::new(HashMap::new())),
-            engine: self,
+            pending_writes: Arc::new(Mutex::new(Vec::new())),
+            pending_bytes: Arc::new(AtomicU64::new(0)),
+            engine: self.clone(),
             options: options,
         })
     }
 }
 
-impl WriteBatch<'_> {
+impl WriteBatch {
+    /// 暂存写入操作，暂存的字节数超过 max_batch_bytes 时返回 ExceedMaxBatchSize，这次操作不会被暂存
     pub fn put(&self, key: Bytes, value: Bytes) -> Result<()> {
         if key.is_empty() {
             return Err(Errors::KeyIsEmpty);
@@ -50,128 +61,208 @@ impl WriteBatch<'_> {
             key: key.to_vec(),
             value: value.to_vec(),
             rec_type: LogRecordType::NORMAL,
+            timestamp: 0,
+            value_pointer: false,
         };
-
-        let mut pending_writes = self.pending_writes.lock();
-        pending_writes.insert(key.to_vec(), record);
-
-        Ok(())
+        self.push(record)
     }
 
+    /// 暂存删除操作，删除对批次中之前暂存的 put 可见，之后的 put 会重新写入这个 key
+    /// key 是否存在在提交时判断：提交时数据库以及批次中更早的操作都没有这个 key 的话，
+    /// 和 Engine::delete 一样不写入删除记录
     pub fn delete(&self, key: Bytes) -> Result<()> {
         if key.is_empty() {
             return Err(Errors::KeyIsEmpty);
         }
 
-        let mut pending_writes = self.pending_writes.lock();
-        // 数据不存在就直接返回
-        let index_pos = self.engine.index.get(key.to_vec());
-        if index_pos.is_none() {
-            if pending_writes.contains_key(&key.to_vec()) {
-                pending_writes.remove(&key.to_vec());
-            }
-            return Ok(());
-        }
-
         // 暂存数据
         let record = LogRecord {
             key: key.to_vec(),
             value: Default::default(),
             rec_type: LogRecordType::DELETE,
+            timestamp: 0,
+            value_pointer: false,
         };
+        self.push(record)
+    }
 
-        pending_writes.insert(key.to_vec(), record);
+    /// 当前暂存的 key 和 value 的字节数，可以根据这个值把大量的数据拆分成多个批次提交
+    pub fn pending_bytes(&self) -> u64 {
+        self.pending_bytes.load(Ordering::SeqCst)
+    }
+
+    // 暂存一条记录，超过批次的字节数上限时返回错误
+    fn push(&self, record: LogRecord) -> Result<()> {
+        let mut pending_writes = self.pending_writes.lock();
+        let size = self.pending_bytes() + record_bytes(&record);
+        if self
+            .options
+            .max_batch_bytes
+            .is_some_and(|max_batch_bytes| size > max_batch_bytes)
+        {
+            return Err(Errors::ExceedMaxBatchSize);
+        }
+        pending_writes.push(record);
+        self.pending_bytes.store(size, Ordering::SeqCst);
         Ok(())
     }
 
-    /// 提交数据，将数据写到数据文件中，并且更新索引
-    pub fn commit(&self) -> Result<()> {
+    /// 提交数据，将数据写到数据文件中，并且更新索引，返回这个批次的写入序列号
+    /// 批次中的所有记录使用相同的序列号，没有需要写入的数据时返回 Engine::current_seq
+    pub fn commit(&self) -> Result<u64> {
+        self.engine.check_closed()?;
         let mut pending_write = self.pending_writes.lock();
         if pending_write.is_empty() {
-            return Ok(());
+            return Ok(self.engine.current_seq());
+        }
+
+        // 合并同一个 key 的冗余操作，只保留最后一次操作，并保持其原有的相对顺序
+        if self.options.merge_redundant_ops {
+            let mut last_index = HashMap::new();
+            for (i, record) in pending_write.iter().enumerate() {
+                last_index.insert(record.key.clone(), i);
+            }
+            let mut i = 0;
+            pending_write.retain(|record| {
+                let keep = last_index.get(&record.key) == Some(&i);
+                i += 1;
+                keep
+            });
+            self.pending_bytes.store(
+                pending_write.iter().map(record_bytes).sum(),
+                Ordering::SeqCst,
+            );
         }
-        if pending_write.len() > self.options.max_batch_num {
+
+        if pending_write.len() as u64 > self.options.max_batch_num {
             return Err(Errors::ExceedMaxBatchNum);
         }
 
+        // 先获取批次中所有 key 的锁，和这些 key 的读改写操作串行执行
+        let _key_locks = self
+            .engine
+            .key_locks
+            .lock_keys(pending_write.iter().map(|record| record.key.as_slice()));
+
         // 加锁保证事务串行化
         let _lock = self.engine.batch_commit_lock.lock();
+        let _relocate_lock = self.engine.relocate_lock.read();
+
+        // 按照操作顺序判断删除时 key 是否存在，批次中更早的操作优先于数据库中的数据，
+        // 删除不存在的 key 不写入删除记录
+        let mut batch_exists: HashMap<Vec<u8>, bool> = HashMap::new();
+        pending_write.retain(|record| {
+            let exists = match batch_exists.get(&record.key) {
+                Some(exists) => *exists,
+                None => self.engine.index.get(record.key.clone()).is_some(),
+            };
+            batch_exists.insert(record.key.clone(), record.rec_type == LogRecordType::NORMAL);
+            record.rec_type != LogRecordType::DELETE || exists
+        });
+        self.pending_bytes.store(
+            pending_write.iter().map(record_bytes).sum(),
+            Ordering::SeqCst,
+        );
+        if pending_write.is_empty() {
+            return Ok(self.engine.current_seq());
+        }
 
         // 获取全局事务序列号
         let seq_no = self.engine.seq_no.fetch_add(1, Ordering::SeqCst);
 
-        let mut positions: HashMap<Vec<u8>, LogRecordPos> = HashMap::new();
-        // 开始写数据到对应的数据文件
-        for (_, item) in pending_write.iter() {
-            let mut record = LogRecord {
-                key: log_record_key_with_seq(item.key.to_vec(), seq_no),
-                value: item.value.to_vec(),
-                rec_type: item.rec_type,
+        // 同一批次的数据使用相同的写入序列号，写入时间是序列号中的物理时间
+        let write_seq = self.engine.clock.tick();
+        let timestamp = hlc::physical_time(write_seq);
+        // 一次遍历编码所有记录，大 value 先写入 blob 文件，
+        // 编码时把 key 临时替换为带有 seq_no 的 key，编码之后换回原来的 key，不需要拷贝 key 和 value
+        let _rotate_lock = self.engine.value_log.rotate_lock.read();
+        let mut enc_records = Vec::with_capacity(pending_write.len() + 1);
+        for record in pending_write.iter_mut() {
+            let enc_key = log_record_key_with_seq(&record.key, seq_no);
+            let key = std::mem::replace(&mut record.key, enc_key);
+            record.timestamp = timestamp;
+            let enc_record = match record.rec_type == LogRecordType::NORMAL
+                && self.engine.is_large_value(&record.value)
+            {
+                true => self
+                    .engine
+                    .write_large_value(&key, record)
+                    .and_then(|pointer| self.engine.encode_log_record(&pointer)),
+                false => self.engine.encode_log_record(record),
             };
-
-            let pos = self.engine.append_log_record(&mut record)?;
-            positions.insert(item.key.to_vec(), pos);
+            record.key = key;
+            let enc_record = enc_record?;
+            self.engine.check_record_size(&enc_record)?;
+            enc_records.push(enc_record);
         }
 
-        // 写最后一条标识事务完成的数据
-        let mut finish_record = LogRecord {
-            key: log_record_key_with_seq(TXN_FIN_KEY.to_vec(), seq_no),
+        // 最后一条标识事务完成的数据
+        let finish_record = LogRecord {
+            key: log_record_key_with_seq(TXN_FIN_KEY, seq_no),
             value: Default::default(),
             rec_type: LogRecordType::TxnFinished,
+            timestamp: 0,
+            value_pointer: false,
         };
+        enc_records.push(self.engine.encode_log_record(&finish_record)?);
 
-        self.engine.append_log_record(&mut finish_record)?;
+        // 整个批次作为一组写入活跃文件，同一个数据文件中的记录只写一次
+        let mut positions = self.engine.append_encoded_records(enc_records)?;
+        positions.pop();
 
         // 如果配置了持久化，进行持久化操作
         if self.options.sync_writes {
             let _ = self.engine.sync();
         }
 
-        // 数据全部写完之后更新内存索引
-        for (_, item) in pending_write.iter() {
-            let record_pos = positions.get(&item.key).unwrap();
-            if item.rec_type == LogRecordType::NORMAL {
-                if let Some(old_pos) = self.engine.index.put(item.key.clone(), *record_pos) {
+        // 数据全部写完之后按照操作顺序更新内存索引和二级索引
+        for (item, record_pos) in pending_write.iter().zip(positions.iter()) {
+            match item.rec_type {
+                LogRecordType::NORMAL => {
+                    self.engine.index_put(item.key.clone(), *record_pos);
                     self.engine
-                        .reclaim_size
-                        .fetch_add(old_pos.size as usize, Ordering::SeqCst);
+                        .update_secondary_indexes(&item.key, Some((&item.value, *record_pos)));
                 }
-            }
-
-            if item.rec_type == LogRecordType::DELETE {
-                let mut size = record_pos.size;
-
-                if let Some(old_pos) = self.engine.index.delete(item.key.clone()) {
-                    size += old_pos.size;
+                _ => {
+                    // delete 这条记录本身也是可以回收的
+                    self.engine.add_reclaim_size(record_pos);
+                    self.engine.index_delete(item.key.clone());
+                    self.engine.update_secondary_indexes(&item.key, None);
                 }
-
-                self.engine
-                    .reclaim_size
-                    .fetch_add(size as usize, Ordering::SeqCst);
             }
         }
 
+        // 通知订阅者
+        self.engine.watchers.notify(&pending_write);
+
         // 将暂存的数据清空
         pending_write.clear();
+        self.pending_bytes.store(0, Ordering::SeqCst);
 
-        Ok(())
+        Ok(write_seq)
     }
 }
 
-/// 编码 seq_no 和 key
-pub(crate) fn log_record_key_with_seq(key: Vec<u8>, seq_no: usize) -> Vec<u8> {
+// 一条暂存的记录计入批次字节数的大小
+fn record_bytes(record: &LogRecord) -> u64 {
+    (record.key.len() + record.value.len()) as u64
+}
+
+/// 编码 seq_no 和 key，seq_no 固定按照 u64 的 varint 编码，数据目录在 32 位和 64 位的机器之间可以通用
+pub(crate) fn log_record_key_with_seq(key: &[u8], seq_no: u64) -> Vec<u8> {
     let mut enc_key = BytesMut::new();
-    encode_length_delimiter(seq_no, &mut enc_key).unwrap();
-    enc_key.extend_from_slice(&key.to_vec());
+    encode_varint(seq_no, &mut enc_key);
+    enc_key.extend_from_slice(&key);
     enc_key.to_vec()
 }
 
 /// 解析 LogRecord 的 key，拿到实际的 key 和 seq no
-pub(crate) fn parse_log_record_key(key: Vec<u8>) -> (Vec<u8>, usize) {
+/// 没有经过 CRC 校验的记录的 key 可能是损坏的，seq no 解码失败时返回 InvaildLogRecordKey
+pub(crate) fn parse_log_record_key(key: Vec<u8>) -> Result<(Vec<u8>, u64)> {
     let mut buf = BytesMut::new();
     buf.put_slice(&key);
-    let seq_no = decode_length_delimiter(&mut buf).unwrap();
-    (buf.to_vec(), seq_no)
+    let seq_no = decode_varint(&mut buf).map_err(|_| Errors::InvaildLogRecordKey)?;
+    Ok((buf.to_vec(), seq_no))
 }
 
 #[cfg(test)]
@@ -179,6 +270,7 @@ mod tests {
     use std::path::PathBuf;
 
     use crate::{
+        data::data_file::SEQ_NO_FILE_NAME,
         options::Options,
         util::rand_kv::{get_test_key, get_test_value},
     };
@@ -221,6 +313,41 @@ mod tests {
         std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
     }
 
+    #[test]
+    fn test_owned_write_batch() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-owned-write-batch");
+        opts.data_file_size = 64 * 1024 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        let wb = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("failed to create wirte batch");
+        assert!(wb.put(get_test_key(1), get_test_value(1)).is_ok());
+
+        // 在其他线程中继续写入并提交
+        let handle = std::thread::spawn(move || {
+            assert!(wb.put(get_test_key(2), get_test_value(2)).is_ok());
+            assert!(wb.delete(get_test_key(1)).is_ok());
+            wb.commit()
+        });
+        assert!(handle.join().unwrap().is_ok());
+
+        assert_eq!(
+            engine.get(get_test_key(1)).err().unwrap(),
+            Errors::KeyNotFound
+        );
+        assert_eq!(engine.get(get_test_key(2)).unwrap(), get_test_value(2));
+
+        // WriteBatch 已经释放，最后一个 engine 释放之后可以重新打开
+        std::mem::drop(engine);
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine.get(get_test_key(2)).unwrap(), get_test_value(2));
+
+        std::mem::drop(engine);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
     #[test]
     fn test_write_batch_2() {
         let mut opts = Options::default();
@@ -267,6 +394,301 @@ mod tests {
         std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
     }
 
+    #[test]
+    fn test_write_batch_seq_no_corrupted() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-write-batch-seq-no-corrupted");
+        opts.data_file_size = 64 * 1024 * 1024;
+        opts.index_type = IndexType::BPTree;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..3 {
+            let wb = engine
+                .new_write_batch(WriteBatchOptions::default())
+                .expect("failed to create wirte batch");
+            assert!(wb.put(get_test_key(i), get_test_value(i)).is_ok());
+            assert!(wb.commit().is_ok());
+        }
+        assert!(engine.delete(get_test_key(0)).is_ok());
+        engine.close().expect("failed to close");
+        std::mem::drop(engine);
+
+        // 事务序列号文件只写入了一半
+        let seq_no_path = opts.dir_path.join(SEQ_NO_FILE_NAME);
+        let content = std::fs::read(&seq_no_path).unwrap();
+        std::fs::write(&seq_no_path, &content[..content.len() / 2]).unwrap();
+
+        // 从数据文件中恢复事务序列号和索引
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine.seq_no.load(Ordering::SeqCst), 4);
+        assert_eq!(engine.list_keys().unwrap().len(), 2);
+        assert_eq!(engine.stat().unwrap().key_num, 2);
+        let wb = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("failed to create wirte batch");
+        assert!(wb.put(get_test_key(3), get_test_value(3)).is_ok());
+        assert!(wb.commit().is_ok());
+        assert_eq!(engine.seq_no.load(Ordering::SeqCst), 5);
+
+        std::mem::drop(wb);
+        std::mem::drop(engine);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_write_batch_contiguous_records() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-write-batch-contiguous-records");
+        opts.data_file_size = 64 * 1024 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        let wb = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("failed to create wirte batch");
+        for i in 0..1000 {
+            assert!(wb.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        assert!(wb.commit().is_ok());
+
+        // 同一批次的记录作为一组写入，在数据文件中按照操作顺序连续存放
+        let mut next_offset = None;
+        for i in 0..1000 {
+            let pos = engine.index.get(get_test_key(i).to_vec()).unwrap();
+            if let Some(offset) = next_offset {
+                assert_eq!(pos.offset, offset);
+            }
+            next_offset = Some(pos.offset + pos.size);
+        }
+        for i in 0..1000 {
+            assert_eq!(engine.get(get_test_key(i)).unwrap(), get_test_value(i));
+        }
+
+        // 重启之后同样可以读取到整个批次
+        std::mem::drop(wb);
+        std::mem::drop(engine);
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine.list_keys().unwrap().len(), 1000);
+
+        std::mem::drop(engine);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_write_batch_ordered_ops() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-write-batch-ordered-ops");
+        opts.data_file_size = 64 * 1024 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        let put_res1 = engine.put(get_test_key(1), get_test_value(1));
+        assert!(put_res1.is_ok());
+        let put_res2 = engine.put(get_test_key(2), get_test_value(2));
+        assert!(put_res2.is_ok());
+
+        let wb = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("failed to create wirte batch");
+
+        // 同一个 key 的多次操作按照顺序生效
+        assert!(wb.put(get_test_key(1), get_test_value(10)).is_ok());
+        assert!(wb.delete(get_test_key(1)).is_ok());
+        assert!(wb.put(get_test_key(1), get_test_value(11)).is_ok());
+        assert!(wb.put(get_test_key(2), get_test_value(20)).is_ok());
+        assert!(wb.delete(get_test_key(2)).is_ok());
+        // 只在批次中写入过的 key 同样可以被删除
+        assert!(wb.put(get_test_key(3), get_test_value(30)).is_ok());
+        assert!(wb.delete(get_test_key(3)).is_ok());
+        assert!(wb.commit().is_ok());
+
+        assert_eq!(engine.get(get_test_key(1)).unwrap(), get_test_value(11));
+        assert_eq!(
+            engine.get(get_test_key(2)).err().unwrap(),
+            Errors::KeyNotFound
+        );
+        assert_eq!(
+            engine.get(get_test_key(3)).err().unwrap(),
+            Errors::KeyNotFound
+        );
+
+        // 重启之后按照相同的顺序重放
+        engine.close().expect("failed to close");
+        std::mem::drop(engine);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine2.get(get_test_key(1)).unwrap(), get_test_value(11));
+        assert_eq!(
+            engine2.get(get_test_key(2)).err().unwrap(),
+            Errors::KeyNotFound
+        );
+        assert_eq!(
+            engine2.get(get_test_key(3)).err().unwrap(),
+            Errors::KeyNotFound
+        );
+
+        // 合并冗余操作
+        let wb2 = engine2
+            .new_write_batch(WriteBatchOptions {
+                max_batch_num: 2,
+                merge_redundant_ops: true,
+                ..Default::default()
+            })
+            .expect("failed to create wirte batch");
+        assert!(wb2.put(get_test_key(4), get_test_value(40)).is_ok());
+        assert!(wb2.put(get_test_key(4), get_test_value(41)).is_ok());
+        assert!(wb2.put(get_test_key(5), get_test_value(50)).is_ok());
+        assert!(wb2.put(get_test_key(4), get_test_value(42)).is_ok());
+        assert!(wb2.commit().is_ok());
+        assert_eq!(engine2.get(get_test_key(4)).unwrap(), get_test_value(42));
+        assert_eq!(engine2.get(get_test_key(5)).unwrap(), get_test_value(50));
+
+        // 不合并时每一次操作都计入批次数量
+        let wb3 = engine2
+            .new_write_batch(WriteBatchOptions {
+                max_batch_num: 2,
+                ..Default::default()
+            })
+            .expect("failed to create wirte batch");
+        assert!(wb3.put(get_test_key(6), get_test_value(60)).is_ok());
+        assert!(wb3.put(get_test_key(6), get_test_value(61)).is_ok());
+        assert!(wb3.put(get_test_key(6), get_test_value(62)).is_ok());
+        assert_eq!(wb3.commit().err().unwrap(), Errors::ExceedMaxBatchNum);
+
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_write_batch_delete_visibility() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-write-batch-delete-visibility");
+        opts.data_file_size = 64 * 1024 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        // 删除不存在的 key 不写入任何数据
+        let write_off = engine.active_file.read().get_write_off();
+        let wb = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("failed to create wirte batch");
+        assert!(wb.delete(get_test_key(1)).is_ok());
+        assert!(wb.commit().is_ok());
+        assert_eq!(engine.active_file.read().get_write_off(), write_off);
+
+        // key 是否存在在提交时判断，暂存删除之后其他写入的数据同样会被删除
+        let wb = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("failed to create wirte batch");
+        assert!(wb.delete(get_test_key(1)).is_ok());
+        assert!(engine.put(get_test_key(1), get_test_value(1)).is_ok());
+        assert!(wb.commit().is_ok());
+        assert_eq!(
+            engine.get(get_test_key(1)).err().unwrap(),
+            Errors::KeyNotFound
+        );
+
+        // 删除之后再写入，最终保留之后写入的数据
+        let wb = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("failed to create wirte batch");
+        assert!(wb.put(get_test_key(2), get_test_value(2)).is_ok());
+        assert!(wb.delete(get_test_key(2)).is_ok());
+        assert!(wb.delete(get_test_key(2)).is_ok());
+        assert!(wb.put(get_test_key(2), get_test_value(20)).is_ok());
+        assert!(wb.commit().is_ok());
+        assert_eq!(engine.get(get_test_key(2)).unwrap(), get_test_value(20));
+
+        // 合并冗余操作时的结果相同
+        let wb = engine
+            .new_write_batch(WriteBatchOptions {
+                merge_redundant_ops: true,
+                ..Default::default()
+            })
+            .expect("failed to create wirte batch");
+        assert!(wb.put(get_test_key(3), get_test_value(3)).is_ok());
+        assert!(wb.delete(get_test_key(3)).is_ok());
+        assert!(wb.delete(get_test_key(2)).is_ok());
+        assert!(wb.commit().is_ok());
+        assert_eq!(
+            engine.get(get_test_key(2)).err().unwrap(),
+            Errors::KeyNotFound
+        );
+        assert_eq!(
+            engine.get(get_test_key(3)).err().unwrap(),
+            Errors::KeyNotFound
+        );
+
+        // 重启之后结果不变
+        engine.close().expect("failed to close");
+        std::mem::drop(engine);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 1..=3 {
+            assert_eq!(
+                engine2.get(get_test_key(i)).err().unwrap(),
+                Errors::KeyNotFound
+            );
+        }
+
+        std::mem::drop(engine2);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_write_batch_max_batch_bytes() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-batch-max-bytes");
+        opts.data_file_size = 64 * 1024 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        let wb = engine
+            .new_write_batch(WriteBatchOptions {
+                max_batch_bytes: Some(100),
+                ..Default::default()
+            })
+            .expect("failed to create wirte batch");
+        assert!(wb.put(Bytes::from("a"), Bytes::from(vec![0u8; 59])).is_ok());
+        assert_eq!(wb.pending_bytes(), 60);
+        assert!(wb.delete(Bytes::from("b")).is_ok());
+        assert_eq!(wb.pending_bytes(), 61);
+
+        // 超过上限的操作不会被暂存，之前暂存的操作仍然可以提交
+        assert_eq!(
+            wb.put(Bytes::from("c"), Bytes::from(vec![0u8; 50]))
+                .err()
+                .unwrap(),
+            Errors::ExceedMaxBatchSize
+        );
+        assert_eq!(wb.pending_bytes(), 61);
+        assert!(wb.put(Bytes::from("c"), Bytes::from(vec![0u8; 38])).is_ok());
+        assert_eq!(wb.pending_bytes(), 100);
+        assert!(wb.commit().is_ok());
+        assert_eq!(wb.pending_bytes(), 0);
+        assert_eq!(engine.get(Bytes::from("c")).unwrap().len(), 38);
+
+        std::mem::drop(engine);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_log_record_key_with_seq() {
+        // 超过 32 位的 seq_no 也可以正确编码
+        for seq_no in [
+            NON_TRANSACTION_SEQ_NO,
+            1,
+            300,
+            u32::MAX as u64 + 1,
+            u64::MAX,
+        ] {
+            let enc_key = log_record_key_with_seq(b"key", seq_no);
+            assert_eq!(
+                parse_log_record_key(enc_key).unwrap(),
+                (b"key".to_vec(), seq_no)
+            );
+        }
+
+        // 和之前按照 usize 编码的数据兼容
+        let mut old_key = BytesMut::new();
+        prost::encode_length_delimiter(300, &mut old_key).unwrap();
+        old_key.extend_from_slice(b"key");
+        assert_eq!(old_key.to_vec(), log_record_key_with_seq(b"key", 300));
+    }
+
     // #[test]
     // fn test_write_batch_3() {
     //     let mut opts = Options::default();
@@ -0,0 +1,227 @@
+use std::{fs, sync::Arc};
+
+use bytes::Bytes;
+use log::{error, warn};
+
+use crate::{
+    batch::parse_log_record_key,
+    data::{
+        bloom::{bloom_hash, read_bloom_file, write_bloom_file, BloomFilter},
+        data_file::{get_bloom_file_name, DataFile},
+        log_record::LogRecordType,
+    },
+    db::{Engine, RecordLocation},
+    errors::{Errors, Result},
+};
+
+impl Engine {
+    /// 扫描数据文件，返回 key 所有记录的位置，包括已经被覆盖和删除的记录，按照写入的顺序排列
+    /// 用于校验索引以及排查问题，开启 bloom_filter 时跳过一定不包含这个 key 的旧数据文件
+    /// 扫描期间会阻塞写入
+    pub fn key_locations(&self, key: Bytes) -> Result<Vec<RecordLocation>> {
+        self.check_closed()?;
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+
+        // 和写入时的加锁顺序相同，先锁活跃文件，保证扫描期间没有数据文件被切换
+        let active_file = self.active_file.read();
+        let older_files = self.older_files.read();
+        let mut file_ids: Vec<u64> = older_files.keys().copied().collect();
+        file_ids.sort();
+
+        let mut locations = Vec::new();
+        for file_id in file_ids {
+            let data_file = older_files.get(&file_id).unwrap();
+            if !self.may_contain_key(data_file, &key)? {
+                continue;
+            }
+            scan_key_locations(data_file, &key, &mut locations)?;
+        }
+        scan_key_locations(&active_file, &key, &mut locations)?;
+        Ok(locations)
+    }
+
+    // 旧数据文件中是否可能有 key 的记录，没有开启 bloom_filter 时总是返回 true
+    fn may_contain_key(&self, data_file: &DataFile, key: &[u8]) -> Result<bool> {
+        if !self.options.bloom_filter {
+            return Ok(true);
+        }
+        Ok(self.data_file_bloom_filter(data_file)?.may_contain(key))
+    }
+
+    // 获取旧数据文件的 bloom filter，依次从缓存、bloom filter 文件中加载，
+    // 都没有或者和数据文件的大小不一致（例如文件 id 被重新使用）时扫描数据文件重新生成
+    pub(crate) fn data_file_bloom_filter(&self, data_file: &DataFile) -> Result<Arc<BloomFilter>> {
+        let file_id = data_file.get_file_id();
+        let file_size = data_file.file_size();
+        if let Some(filter) = self.bloom_filters.read().get(&file_id) {
+            if filter.data_file_size() == file_size {
+                return Ok(filter.clone());
+            }
+        }
+
+        let dir_path = self.options.dir_path.clone();
+        let filter = match read_bloom_file(dir_path.clone(), file_id) {
+            Some(filter) if filter.data_file_size() == file_size => filter,
+            _ => {
+                let filter = build_bloom_filter(data_file)?;
+                // 只读模式下不改动数据目录中的文件，写入失败时下一次重新生成
+                if !self.options.read_only {
+                    if let Err(e) = write_bloom_file(dir_path, file_id, &filter) {
+                        warn!("failed to persist bloom filter of file {}: {}", file_id, e);
+                    }
+                }
+                filter
+            }
+        };
+
+        let filter = Arc::new(filter);
+        self.bloom_filters.write().insert(file_id, filter.clone());
+        Ok(filter)
+    }
+
+    // 数据文件被删除之后删除它的 bloom filter
+    pub(crate) fn remove_bloom_filter(&self, file_id: u64) -> Result<()> {
+        self.bloom_filters.write().remove(&file_id);
+        let path = get_bloom_file_name(self.options.dir_path.clone(), file_id);
+        if path.is_file() {
+            if let Err(e) = fs::remove_file(path) {
+                error!("failed to remove bloom filter file: {}", e);
+                return Err(Errors::FailedToRemoveDataFile);
+            }
+        }
+        Ok(())
+    }
+}
+
+// 扫描数据文件中所有的 key 生成 bloom filter
+fn build_bloom_filter(data_file: &DataFile) -> Result<BloomFilter> {
+    let mut hashes = Vec::new();
+    for_each_record(data_file, |real_key, _, _, _| {
+        hashes.push(bloom_hash(&real_key));
+    })?;
+    Ok(BloomFilter::new(&hashes, data_file.file_size()))
+}
+
+// 扫描数据文件，收集 key 所有记录的位置
+fn scan_key_locations(
+    data_file: &DataFile,
+    key: &[u8],
+    locations: &mut Vec<RecordLocation>,
+) -> Result<()> {
+    let file_id = data_file.get_file_id();
+    for_each_record(data_file, |real_key, rec_type, offset, size| {
+        if rec_type != LogRecordType::TxnFinished && real_key == key {
+            locations.push(RecordLocation {
+                file_id,
+                offset,
+                size,
+            });
+        }
+    })
+}
+
+// 依次遍历数据文件中的记录，交给 handle 处理记录实际的 key、类型、偏移以及大小
+fn for_each_record<F>(data_file: &DataFile, mut handle: F) -> Result<()>
+where
+    F: FnMut(Vec<u8>, LogRecordType, u64, u64),
+{
+    let mut offset = data_file.get_header_size();
+    loop {
+        let (log_record, size) = match data_file.read_log_record(offset) {
+            Ok(result) => (result.record, result.size),
+            Err(Errors::ReadDataFileEof) => break,
+            Err(Errors::InvaildLogRecordCrc) | Err(Errors::InvalidRecordType(_)) => {
+                return Err(Errors::DataFileCorrupted {
+                    file_id: data_file.get_file_id(),
+                    offset,
+                })
+            }
+            Err(e) => return Err(e),
+        };
+
+        let (real_key, _) = parse_log_record_key(log_record.key)?;
+        handle(real_key, log_record.rec_type, offset, size as u64);
+        offset += size as u64;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::{
+        options::Options,
+        util::rand_kv::{get_test_key, get_test_value},
+    };
+
+    #[test]
+    fn test_key_locations() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-key-locations");
+        opts.data_file_size = 64 * 1024;
+        opts.data_file_merge_ratio = 0.0;
+        opts.bloom_filter = true;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..1000 {
+            assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        assert!(engine
+            .put(get_test_key(1), Bytes::from("new value"))
+            .is_ok());
+        assert!(engine.delete(get_test_key(2)).is_ok());
+
+        // 覆盖和删除的记录都会返回，最后一条是最新的数据
+        let locations = engine.key_locations(get_test_key(1)).unwrap();
+        assert_eq!(locations.len(), 2);
+        assert_eq!(
+            engine.read_at(*locations.last().unwrap()).unwrap(),
+            Bytes::from("new value")
+        );
+        assert_eq!(engine.key_locations(get_test_key(2)).unwrap().len(), 2);
+        assert!(engine
+            .key_locations(Bytes::from("not-exist"))
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            engine.key_locations(Bytes::new()).err().unwrap(),
+            Errors::KeyIsEmpty
+        );
+
+        // 旧数据文件的 bloom filter 在第一次使用时生成
+        let bloom_file = get_bloom_file_name(opts.dir_path.clone(), 0);
+        assert!(bloom_file.is_file());
+        let filter = read_bloom_file(opts.dir_path.clone(), 0).unwrap();
+        assert!(filter.may_contain(&get_test_key(1)));
+
+        // 损坏的 bloom filter 会被重新生成
+        std::mem::drop(engine);
+        fs::write(&bloom_file, b"corrupted").unwrap();
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine.key_locations(get_test_key(1)).unwrap().len(), 2);
+        assert_eq!(read_bloom_file(opts.dir_path.clone(), 0), Some(filter));
+
+        // merge 时为新的数据文件生成 bloom filter
+        assert!(engine.merge().is_ok());
+        std::mem::drop(engine);
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        let filter = read_bloom_file(opts.dir_path.clone(), 0).unwrap();
+        assert!(filter.may_contain(&get_test_key(0)));
+        assert!(!filter.may_contain(b"not-exist"));
+        assert_eq!(engine.key_locations(get_test_key(1)).unwrap().len(), 1);
+        assert!(engine.key_locations(get_test_key(2)).unwrap().is_empty());
+
+        engine.close().expect("failed to close engine");
+        assert_eq!(
+            engine.key_locations(get_test_key(1)).err().unwrap(),
+            Errors::DatabaseClosed
+        );
+
+        std::mem::drop(engine);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+}
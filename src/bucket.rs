@@ -0,0 +1,293 @@
+use bytes::{Buf, Bytes};
+use prost::{decode_length_delimiter, encode_length_delimiter};
+
+use crate::{
+    db::Engine,
+    errors::{Errors, Result},
+    iterator::Iterator,
+    options::IteratorOptions,
+};
+
+pub(crate) const BUCKET_KEY_PREFIX: &[u8] = "\0bitcask-bucket\0".as_bytes();
+
+/// 数据库中的一个命名空间，读写的 key 会自动带上 bucket 的前缀，不同 bucket 中的 key 互不影响
+pub struct Bucket<'a> {
+    engine: &'a Engine,
+    name: String,
+    prefix: Vec<u8>,
+}
+
+impl Engine {
+    /// 返回指定名称的 bucket，bucket 不需要提前创建，写入数据之后即存在
+    pub fn bucket(&self, name: &str) -> Result<Bucket<'_>> {
+        if name.is_empty() {
+            return Err(Errors::BucketNameIsEmpty);
+        }
+
+        Ok(Bucket {
+            engine: self,
+            name: name.to_string(),
+            prefix: bucket_prefix(name),
+        })
+    }
+
+    /// 返回所有包含数据的 bucket 名称，按照名称长度和名称排序
+    pub fn list_buckets(&self) -> Result<Vec<String>> {
+        let mut names: Vec<String> = Vec::new();
+        for key in self.index.list_keys()? {
+            if let Some(name) = decode_bucket_name(&key) {
+                if names.last() != Some(&name) {
+                    names.push(name);
+                }
+            }
+        }
+        // 自定义排序规则下同一个 bucket 中的 key 不一定相邻
+        if self.options.key_comparator.is_some() {
+            names.sort_by(|a, b| a.len().cmp(&b.len()).then_with(|| a.cmp(b)));
+            names.dedup();
+        }
+        Ok(names)
+    }
+
+    /// 删除 bucket 中所有的数据，为每个 key 写入删除标记
+    pub fn drop_bucket(&self, name: &str) -> Result<()> {
+        let bucket = self.bucket(name)?;
+        let mut index_iter = self.index.iterator(IteratorOptions {
+            prefix: bucket.prefix.clone(),
+            reverse: false,
+            ..Default::default()
+        });
+        while let Some((key, _)) = index_iter.next() {
+            self.delete(Bytes::from(key.clone()))?;
+        }
+        Ok(())
+    }
+}
+
+impl Bucket<'_> {
+    /// bucket 名称
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// 存储 key/value 数据，key 不能为空
+    pub fn put(&self, key: Bytes, value: Bytes) -> Result<()> {
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+        self.engine.put(self.encode_key(&key), value)?;
+        Ok(())
+    }
+
+    /// 根据 key 获取对应的数据
+    pub fn get(&self, key: Bytes) -> Result<Bytes> {
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+        self.engine.get(self.encode_key(&key))
+    }
+
+    /// 根据 key 删除对应的数据
+    pub fn delete(&self, key: Bytes) -> Result<()> {
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+        self.engine.delete(self.encode_key(&key))
+    }
+
+    /// 返回 bucket 中的所有 key
+    pub fn list_keys(&self) -> Result<Vec<Bytes>> {
+        let mut index_iter = self.engine.index.iterator(IteratorOptions {
+            prefix: self.prefix.clone(),
+            reverse: false,
+            ..Default::default()
+        });
+        let mut keys = Vec::new();
+        while let Some((key, _)) = index_iter.next() {
+            keys.push(Bytes::from(key[self.prefix.len()..].to_vec()));
+        }
+        Ok(keys)
+    }
+
+    /// 返回 bucket 的迭代器，返回的 key 不带 bucket 前缀，prefix 和上下界都是 bucket 中的 key
+    pub fn iter(&self, options: IteratorOptions) -> BucketIterator {
+        let mut prefix = self.prefix.clone();
+        prefix.extend_from_slice(&options.prefix);
+        BucketIterator {
+            iter: self.engine.iter(IteratorOptions {
+                prefix,
+                reverse: options.reverse,
+                lower_bound: options.lower_bound.map(|key| self.encode_key(&key).to_vec()),
+                upper_bound: options.upper_bound.map(|key| self.encode_key(&key).to_vec()),
+                consistent: options.consistent,
+            }),
+            prefix: self.prefix.clone(),
+        }
+    }
+
+    fn encode_key(&self, key: &[u8]) -> Bytes {
+        let mut enc_key = self.prefix.clone();
+        enc_key.extend_from_slice(key);
+        Bytes::from(enc_key)
+    }
+}
+
+/// bucket 迭代器
+pub struct BucketIterator {
+    iter: Iterator,
+    prefix: Vec<u8>,
+}
+
+impl BucketIterator {
+    /// 重新回到迭代器的起点，即第一个数据
+    pub fn rewind(&mut self) {
+        self.iter.rewind();
+    }
+
+    /// 根据传入的 key 查找到第一个大于（或小于）等于的目标 key，从这个 key 开始遍历
+    pub fn seek(&mut self, key: Vec<u8>) {
+        let mut enc_key = self.prefix.clone();
+        enc_key.extend_from_slice(&key);
+        self.iter.seek(enc_key);
+    }
+
+    /// 跳转到下一个 key，返回 None 说明遍历完成
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<(Bytes, Bytes)> {
+        self.iter
+            .next()
+            .map(|(key, value)| (key.slice(self.prefix.len()..), value))
+    }
+}
+
+/// 判断是否是 bucket 中的 key
+pub(crate) fn is_bucket_key(key: &[u8]) -> bool {
+    key.starts_with(BUCKET_KEY_PREFIX)
+}
+
+// bucket 中 key 的前缀：固定前缀 + bucket 名称长度 + bucket 名称
+fn bucket_prefix(name: &str) -> Vec<u8> {
+    let mut prefix = BUCKET_KEY_PREFIX.to_vec();
+    encode_length_delimiter(name.len(), &mut prefix).unwrap();
+    prefix.extend_from_slice(name.as_bytes());
+    prefix
+}
+
+// 从 bucket 中的 key 解析出 bucket 名称
+fn decode_bucket_name(key: &[u8]) -> Option<String> {
+    if !is_bucket_key(key) {
+        return None;
+    }
+    let mut buf = &key[BUCKET_KEY_PREFIX.len()..];
+    let name_len = decode_length_delimiter(&mut buf).ok()?;
+    if buf.remaining() < name_len {
+        return None;
+    }
+    String::from_utf8(buf[..name_len].to_vec()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::options::Options;
+
+    use super::*;
+
+    #[test]
+    fn test_bucket() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-bucket");
+        opts.data_file_size = 64 * 1024 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        assert_eq!(Errors::BucketNameIsEmpty, engine.bucket("").err().unwrap());
+
+        let users = engine.bucket("users").unwrap();
+        let orders = engine.bucket("orders").unwrap();
+        assert_eq!(users.name(), "users");
+        assert_eq!(
+            Errors::KeyIsEmpty,
+            users.put(Bytes::new(), Bytes::from("v")).err().unwrap()
+        );
+
+        // 不同 bucket 以及默认命名空间中相同的 key 互不影响
+        assert!(users.put(Bytes::from("1"), Bytes::from("tom")).is_ok());
+        assert!(users.put(Bytes::from("2"), Bytes::from("jack")).is_ok());
+        assert!(orders.put(Bytes::from("1"), Bytes::from("order-1")).is_ok());
+        assert!(engine.put(Bytes::from("1"), Bytes::from("plain")).is_ok());
+
+        assert_eq!(users.get(Bytes::from("1")).unwrap(), Bytes::from("tom"));
+        assert_eq!(
+            orders.get(Bytes::from("1")).unwrap(),
+            Bytes::from("order-1")
+        );
+        assert_eq!(engine.get(Bytes::from("1")).unwrap(), Bytes::from("plain"));
+        assert_eq!(
+            Errors::KeyNotFound,
+            orders.get(Bytes::from("2")).err().unwrap()
+        );
+
+        // 默认命名空间的遍历不包含 bucket 中的 key
+        assert_eq!(engine.list_keys().unwrap(), vec![Bytes::from("1")]);
+        let mut iter = engine.iter(IteratorOptions::default());
+        assert_eq!(iter.next().unwrap().0, Bytes::from("1"));
+        assert!(iter.next().is_none());
+
+        assert_eq!(
+            users.list_keys().unwrap(),
+            vec![Bytes::from("1"), Bytes::from("2")]
+        );
+        let mut bucket_iter = users.iter(IteratorOptions {
+            prefix: Default::default(),
+            reverse: true,
+            ..Default::default()
+        });
+        assert_eq!(
+            bucket_iter.next(),
+            Some((Bytes::from("2"), Bytes::from("jack")))
+        );
+        assert_eq!(
+            bucket_iter.next(),
+            Some((Bytes::from("1"), Bytes::from("tom")))
+        );
+        assert!(bucket_iter.next().is_none());
+        bucket_iter.seek(b"1".to_vec());
+        assert_eq!(bucket_iter.next().unwrap().0, Bytes::from("1"));
+
+        assert_eq!(
+            engine.list_buckets().unwrap(),
+            vec!["users".to_string(), "orders".to_string()]
+        );
+
+        // 删除 bucket 中的数据
+        assert!(users.delete(Bytes::from("2")).is_ok());
+        assert_eq!(users.list_keys().unwrap(), vec![Bytes::from("1")]);
+        assert!(engine.drop_bucket("users").is_ok());
+        assert!(users.list_keys().unwrap().is_empty());
+        assert_eq!(engine.list_buckets().unwrap(), vec!["orders".to_string()]);
+        assert_eq!(
+            orders.get(Bytes::from("1")).unwrap(),
+            Bytes::from("order-1")
+        );
+
+        // 重启校验
+        std::mem::drop((iter, bucket_iter));
+        std::mem::drop(engine);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine2.list_buckets().unwrap(), vec!["orders".to_string()]);
+        let orders2 = engine2.bucket("orders").unwrap();
+        assert_eq!(
+            orders2.get(Bytes::from("1")).unwrap(),
+            Bytes::from("order-1")
+        );
+        let users2 = engine2.bucket("users").unwrap();
+        assert_eq!(
+            Errors::KeyNotFound,
+            users2.get(Bytes::from("1")).err().unwrap()
+        );
+
+        std::mem::drop(engine2);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+}
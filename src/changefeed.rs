@@ -0,0 +1,227 @@
+use std::collections::VecDeque;
+
+use bytes::Bytes;
+use log::warn;
+
+use crate::{
+    batch::{parse_log_record_key, NON_TRANSACTION_SEQ_NO},
+    data::log_record::LogRecordType,
+    db::{ChangeRecord, ChangeType, Engine},
+    errors::Result,
+    replication::{read_next_record, ReplicationCursor},
+};
+
+/// 从给定位置开始按照写入顺序遍历数据文件中的变更，读到活跃文件末尾时返回 None，
+/// 之后再次调用 next 会继续返回新写入的数据，可以像 WAL 一样持续跟踪写入
+/// 事务中的记录在读到事务提交标记之后才会返回，没有提交的事务不会返回
+/// merge 删除的旧数据文件中还没有读取的记录会被跳过
+pub struct ChangeFeed {
+    engine: Engine,
+    cursor: ReplicationCursor,     // 下一条要读取的记录的位置
+    ready: VecDeque<ChangeRecord>, // 可以返回的变更
+    txn: Option<PendingTxn>,       // 正在读取的事务
+    failed: bool,                  // 读取出错之后不再继续读取
+}
+
+// 还没有读到提交标记的事务
+struct PendingTxn {
+    seq_no: u64,
+    start: ReplicationCursor, // 事务第一条记录的位置
+    records: Vec<ChangeRecord>,
+}
+
+impl Engine {
+    /// 返回从 cursor 位置开始读取变更的迭代器，从 (0, 0) 开始会读取所有的数据文件
+    /// 每条变更都带有处理完之后继续读取的位置，保存下来之后可以在重启时从这个位置继续读取
+    pub fn read_log_since(&self, cursor: ReplicationCursor) -> Result<ChangeFeed> {
+        self.check_closed()?;
+        Ok(ChangeFeed {
+            engine: self.clone(),
+            cursor,
+            ready: VecDeque::new(),
+            txn: None,
+            failed: false,
+        })
+    }
+}
+
+impl ChangeFeed {
+    /// 下一条要读取的记录的位置
+    pub fn cursor(&self) -> ReplicationCursor {
+        self.cursor
+    }
+
+    // 读取下一条记录，返回 false 说明已经读到了活跃文件的末尾
+    fn read_next(&mut self) -> Result<bool> {
+        let (file_id, offset, read_record) =
+            match read_next_record(&self.engine, &mut self.cursor)? {
+                Some(result) => result,
+                None => return Ok(false),
+            };
+        let record = read_record.record;
+        let (key, seq_no) = parse_log_record_key(record.key)?;
+
+        // 同一个事务中的记录是连续写入的，读到其他记录时说明之前的事务没有完整写入
+        if self.txn.as_ref().is_some_and(|txn| txn.seq_no != seq_no) {
+            self.discard_txn();
+        }
+
+        let change_type = match record.rec_type {
+            LogRecordType::NORMAL => ChangeType::Put,
+            LogRecordType::DELETE => ChangeType::Delete,
+            LogRecordType::TxnFinished => {
+                self.commit_txn(seq_no);
+                return Ok(true);
+            }
+        };
+        let change = ChangeRecord {
+            key: Bytes::from(key),
+            value: Bytes::from(record.value),
+            change_type,
+            timestamp: record.timestamp,
+            cursor: self.cursor,
+        };
+
+        if seq_no == NON_TRANSACTION_SEQ_NO {
+            self.ready.push_back(change);
+            return Ok(true);
+        }
+
+        // 读取位置可能在 read_next_record 中跳过了文件头部，使用记录实际的位置
+        let txn = self.txn.get_or_insert_with(|| PendingTxn {
+            seq_no,
+            start: ReplicationCursor { file_id, offset },
+            records: Vec::new(),
+        });
+        txn.records.push(change);
+        Ok(true)
+    }
+
+    // 读到提交标记之后返回事务中的所有变更
+    // 中途保存的位置指向事务的开始，重启之后会重新读取整个事务，最后一条变更的位置在提交标记之后
+    fn commit_txn(&mut self, seq_no: u64) {
+        let txn = match self.txn.take() {
+            Some(txn) if txn.seq_no == seq_no => txn,
+            _ => return,
+        };
+        let count = txn.records.len();
+        for (i, mut change) in txn.records.into_iter().enumerate() {
+            change.cursor = match i + 1 == count {
+                true => self.cursor,
+                false => txn.start,
+            };
+            self.ready.push_back(change);
+        }
+    }
+
+    fn discard_txn(&mut self) {
+        if let Some(txn) = self.txn.take() {
+            warn!(
+                "discard {} log records of unfinished transaction {} at file {} offset {}",
+                txn.records.len(),
+                txn.seq_no,
+                txn.start.file_id,
+                txn.start.offset
+            );
+        }
+    }
+}
+
+impl Iterator for ChangeFeed {
+    type Item = Result<ChangeRecord>;
+
+    fn next(&mut self) -> Option<Result<ChangeRecord>> {
+        loop {
+            if let Some(change) = self.ready.pop_front() {
+                return Some(Ok(change));
+            }
+            if self.failed {
+                return None;
+            }
+            if let Err(e) = self.engine.check_closed() {
+                return Some(Err(e));
+            }
+            match self.read_next() {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(e) => {
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::options::{Options, WriteBatchOptions};
+
+    #[test]
+    fn test_read_log_since() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-changefeed");
+        opts.data_file_size = 64 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..1000 {
+            let key = Bytes::from(format!("key-{:04}", i));
+            assert!(engine.put(key, Bytes::from("value")).is_ok());
+        }
+        assert!(engine.delete(Bytes::from("key-0001")).is_ok());
+        let wb = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .unwrap();
+        assert!(wb.put(Bytes::from("txn-1"), Bytes::from("a")).is_ok());
+        assert!(wb.put(Bytes::from("txn-2"), Bytes::from("b")).is_ok());
+        assert!(wb.commit().is_ok());
+
+        // 按照写入顺序返回所有的变更，跨越多个数据文件
+        let changes: Vec<ChangeRecord> = engine
+            .read_log_since(ReplicationCursor::default())
+            .unwrap()
+            .map(|change| change.unwrap())
+            .collect();
+        assert_eq!(changes.len(), 1003);
+        assert_eq!(changes[0].key, Bytes::from("key-0000"));
+        assert_eq!(changes[1000].key, Bytes::from("key-0001"));
+        assert_eq!(changes[1000].change_type, ChangeType::Delete);
+        assert_eq!(changes[1001].key, Bytes::from("txn-1"));
+        assert_eq!(changes[1002].value, Bytes::from("b"));
+        assert!(changes[999].cursor.file_id > changes[0].cursor.file_id);
+
+        // 从事务中间保存的位置继续读取会重新读取整个事务
+        let replay: Vec<Bytes> = engine
+            .read_log_since(changes[1001].cursor)
+            .unwrap()
+            .map(|change| change.unwrap().key)
+            .collect();
+        assert_eq!(replay, vec![Bytes::from("txn-1"), Bytes::from("txn-2")]);
+
+        // 读到末尾之后继续返回新写入的数据
+        let last_cursor = changes[1002].cursor;
+        let mut feed = engine.read_log_since(last_cursor).unwrap();
+        assert!(feed.next().is_none());
+        assert!(engine.put(Bytes::from("new"), Bytes::from("v")).is_ok());
+        let change = feed.next().unwrap().unwrap();
+        assert_eq!(change.key, Bytes::from("new"));
+        assert!(feed.next().is_none());
+
+        // 重启之后从保存的位置继续读取
+        std::mem::drop(feed);
+        std::mem::drop(engine);
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        let keys: Vec<Bytes> = engine
+            .read_log_since(last_cursor)
+            .unwrap()
+            .map(|change| change.unwrap().key)
+            .collect();
+        assert_eq!(keys, vec![Bytes::from("new")]);
+
+        std::mem::drop(engine);
+        std::fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+}
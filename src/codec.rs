@@ -0,0 +1,144 @@
+use bytes::Bytes;
+use log::error;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    db::Engine,
+    errors::{Errors, Result},
+};
+
+/// put_serde 和 get_serde 使用的序列化格式
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SerdeCodec {
+    /// bincode 二进制格式
+    #[default]
+    Bincode,
+
+    /// JSON 格式，需要开启 json feature
+    #[cfg(feature = "json")]
+    Json,
+
+    /// MessagePack 格式，需要开启 msgpack feature
+    #[cfg(feature = "msgpack")]
+    MessagePack,
+}
+
+impl SerdeCodec {
+    /// 将 value 序列化为字节数组
+    pub fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>> {
+        let res = match self {
+            SerdeCodec::Bincode => bincode::serialize(value).map_err(|e| e.to_string()),
+            #[cfg(feature = "json")]
+            SerdeCodec::Json => serde_json::to_vec(value).map_err(|e| e.to_string()),
+            #[cfg(feature = "msgpack")]
+            SerdeCodec::MessagePack => rmp_serde::to_vec_named(value).map_err(|e| e.to_string()),
+        };
+        res.map_err(|e| {
+            error!("failed to serialize value: {}", e);
+            Errors::FailedToSerializeValue
+        })
+    }
+
+    /// 从字节数组反序列化出 value
+    pub fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T> {
+        let res = match self {
+            SerdeCodec::Bincode => bincode::deserialize(data).map_err(|e| e.to_string()),
+            #[cfg(feature = "json")]
+            SerdeCodec::Json => serde_json::from_slice(data).map_err(|e| e.to_string()),
+            #[cfg(feature = "msgpack")]
+            SerdeCodec::MessagePack => rmp_serde::from_slice(data).map_err(|e| e.to_string()),
+        };
+        res.map_err(|e| {
+            error!("failed to deserialize value: {}", e);
+            Errors::FailedToDeserializeValue
+        })
+    }
+}
+
+impl Engine {
+    /// 将 value 按照 Options::serde_codec 指定的格式序列化之后存储
+    pub fn put_serde<T: Serialize + ?Sized>(&self, key: Bytes, value: &T) -> Result<()> {
+        let value = self.options.serde_codec.encode(value)?;
+        self.put(key, Bytes::from(value))?;
+        Ok(())
+    }
+
+    /// 获取 key 对应的数据，并按照 Options::serde_codec 指定的格式反序列化
+    pub fn get_serde<T: DeserializeOwned>(&self, key: Bytes) -> Result<T> {
+        let value = self.get(key)?;
+        self.options.serde_codec.decode(&value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use serde::Deserialize;
+
+    use crate::options::Options;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct User {
+        id: u64,
+        name: String,
+        tags: Vec<String>,
+        email: Option<String>,
+    }
+
+    fn test_codec(codec: SerdeCodec, dir_path: &str) {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from(dir_path);
+        opts.data_file_size = 64 * 1024 * 1024;
+        opts.serde_codec = codec;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        let user = User {
+            id: 1,
+            name: "tom".to_string(),
+            tags: vec!["admin".to_string(), "dev".to_string()],
+            email: None,
+        };
+        let res1 = engine.put_serde(Bytes::from("user-1"), &user);
+        assert!(res1.is_ok());
+        let res2 = engine.get_serde::<User>(Bytes::from("user-1"));
+        assert_eq!(res2.unwrap(), user);
+
+        let res3 = engine.get_serde::<User>(Bytes::from("user-2"));
+        assert_eq!(Errors::KeyNotFound, res3.err().unwrap());
+
+        // 数据格式不匹配
+        let res4 = engine.put(Bytes::from("user-3"), Bytes::from("not a user"));
+        assert!(res4.is_ok());
+        let res5 = engine.get_serde::<User>(Bytes::from("user-3"));
+        assert_eq!(Errors::FailedToDeserializeValue, res5.err().unwrap());
+
+        // 重启校验
+        std::mem::drop(engine);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        let res6 = engine2.get_serde::<User>(Bytes::from("user-1"));
+        assert_eq!(res6.unwrap(), user);
+
+        std::mem::drop(engine2);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_serde_bincode() {
+        test_codec(SerdeCodec::Bincode, "/tmp/bitcask-rs-serde-bincode");
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_serde_json() {
+        test_codec(SerdeCodec::Json, "/tmp/bitcask-rs-serde-json");
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn test_serde_msgpack() {
+        test_codec(SerdeCodec::MessagePack, "/tmp/bitcask-rs-serde-msgpack");
+    }
+}
@@ -0,0 +1,575 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use log::{error, warn};
+use parking_lot::Mutex;
+use prost::encoding::{decode_varint, encode_varint};
+
+use crate::{
+    batch::WriteBatch,
+    db::Engine,
+    errors::{Errors, Result},
+    options::{ColumnFamilyOptions, Options, WriteBatchOptions},
+};
+
+// 根目录中存放所有列族的子目录，每个列族使用其中以列族命名的子目录
+const FAMILIES_DIR_NAME: &str = "families";
+// 根目录中存放跨列族提交日志的子目录
+const COMMIT_LOG_DIR_NAME: &str = "commit-log";
+// 列族目录中存放数据的子目录，merge 等临时目录创建在数据目录旁边，也都位于列族目录中
+const FAMILY_DATA_DIR_NAME: &str = "data";
+
+// 提交日志中每个操作开头的标识
+const PUT_TAG: u8 = 1;
+const DELETE_TAG: u8 = 0;
+
+/// 在同一个根目录下管理多个列族，每个列族是一个独立的存储引擎实例，
+/// 拥有各自的索引、数据文件以及 merge 配置，例如元数据和大 value 可以放在不同的列族中分别调整
+/// 通过 ColumnFamilyBatch 可以原子地提交跨越多个列族的写入
+pub struct ColumnFamilies {
+    options: ColumnFamilyOptions,
+    families: Mutex<HashMap<String, Arc<Engine>>>, // 已经打开的列族
+    commit_log: Engine,                            // 跨列族提交的日志，所有列族都写入完成之后删除
+    commit_lock: Mutex<()>,                        // 跨列族的提交以及删除列族依次进行
+    next_commit_id: AtomicU64,
+    needs_recover: AtomicBool, // 有提交没有写入所有的列族，提交日志中还有剩余的提交
+}
+
+/// 跨列族的批量写入，提交时先把所有的操作写入提交日志，再依次提交到每个列族，
+/// 中途崩溃的话下次打开时根据提交日志重新写入，保证所有列族的写入要么全部生效，要么全部不生效
+/// 为了保证重新写入之后的结果一致，每个列族的写入以及提交日志总是持久化，sync_writes 不生效；
+/// 和批量写入并发写入相同 key 的数据在崩溃恢复之后可能被批量写入的数据覆盖
+pub struct ColumnFamilyBatch<'a> {
+    families: &'a ColumnFamilies,
+    pending_writes: Mutex<Vec<FamilyWrite>>,
+    pending_bytes: AtomicU64, // 暂存的 key 和 value 的字节数，只在持有 pending_writes 锁时修改
+    options: WriteBatchOptions,
+}
+
+// 暂存的一个列族中的操作，value 为空表示删除
+struct FamilyWrite {
+    family: String,
+    key: Bytes,
+    value: Option<Bytes>,
+}
+
+impl ColumnFamilies {
+    /// 打开根目录中的列族，根目录不存在时创建，列族在第一次使用时打开
+    /// 上次没有完成的跨列族提交会在打开时重新写入
+    pub fn open(options: ColumnFamilyOptions) -> Result<Self> {
+        if let Err(e) = fs::create_dir_all(options.root_dir.join(FAMILIES_DIR_NAME)) {
+            warn!("create root dir err: {}", e);
+            return Err(Errors::FailedToCreateDatabaseDir);
+        }
+
+        let mut commit_log_options = Options::default();
+        commit_log_options.dir_path = options
+            .root_dir
+            .join(COMMIT_LOG_DIR_NAME)
+            .join(FAMILY_DATA_DIR_NAME);
+        commit_log_options.sync_writes = true;
+        let commit_log = Engine::open(commit_log_options)?;
+        // 从提交日志中剩余的最大的提交 id 之后继续分配，恢复只完成一部分时新的提交也不会和剩余的提交冲突
+        let next_commit_id = match commit_log.list_keys()?.last() {
+            Some(commit_key) => decode_commit_id(commit_key)? + 1,
+            None => 0,
+        };
+
+        let families = Self {
+            options,
+            families: Mutex::new(HashMap::new()),
+            commit_log,
+            commit_lock: Mutex::new(()),
+            next_commit_id: AtomicU64::new(next_commit_id),
+            needs_recover: AtomicBool::new(false),
+        };
+        families.recover()?;
+        Ok(families)
+    }
+
+    /// 获取列族的存储引擎实例，没有打开时使用列族的配置打开，数据目录不存在时创建
+    pub fn column_family(&self, name: &str) -> Result<Arc<Engine>> {
+        check_family_name(name)?;
+        let mut families = self.families.lock();
+        if let Some(engine) = families.get(name) {
+            return Ok(engine.clone());
+        }
+
+        let engine = Arc::new(Engine::open(self.family_options(name))?);
+        families.insert(name.to_string(), engine.clone());
+        Ok(engine)
+    }
+
+    /// 根目录中所有的列族，包括还没有打开的列族，按照名称排序
+    pub fn column_families(&self) -> Result<Vec<String>> {
+        let entries = fs::read_dir(self.options.root_dir.join(FAMILIES_DIR_NAME)).map_err(|e| {
+            error!("failed to read root dir: {}", e);
+            Errors::FailedToReadDatabaseDir
+        })?;
+
+        let mut families = Vec::new();
+        for entry in entries.flatten() {
+            if !entry.path().is_dir() {
+                continue;
+            }
+            if let Some(name) = entry.file_name().to_str() {
+                families.push(name.to_string());
+            }
+        }
+        families.sort();
+        Ok(families)
+    }
+
+    /// 关闭列族并删除它的所有数据，等待正在进行的跨列族提交完成之后再删除
+    /// 其他地方持有的实例之后会返回 DatabaseClosed
+    pub fn drop_column_family(&self, name: &str) -> Result<()> {
+        check_family_name(name)?;
+        let _commit_lock = self.commit_lock.lock();
+        if let Some(engine) = self.families.lock().remove(name) {
+            engine.close()?;
+        }
+
+        let dir_path = self.family_dir(name);
+        if dir_path.is_dir() {
+            if let Err(e) = fs::remove_dir_all(dir_path) {
+                error!("failed to remove column family dir: {}", e);
+                return Err(Errors::FailedToRemoveDataFile);
+            }
+        }
+        Ok(())
+    }
+
+    /// 创建跨列族的批量写入
+    pub fn new_batch(&self, options: WriteBatchOptions) -> ColumnFamilyBatch<'_> {
+        ColumnFamilyBatch {
+            families: self,
+            pending_writes: Mutex::new(Vec::new()),
+            pending_bytes: AtomicU64::new(0),
+            options,
+        }
+    }
+
+    /// 关闭所有的列族以及提交日志，重复关闭直接返回
+    pub fn close(&self) -> Result<()> {
+        let engines: Vec<Arc<Engine>> = self
+            .families
+            .lock()
+            .drain()
+            .map(|(_, engine)| engine)
+            .collect();
+        for engine in engines {
+            engine.close()?;
+        }
+        self.commit_log.close()
+    }
+
+    // 列族使用单独的配置或者默认的配置，数据目录位于列族目录中
+    fn family_options(&self, name: &str) -> Options {
+        let mut options = match self.options.family_options.get(name) {
+            Some(options) => options.clone(),
+            None => self.options.default_options.clone(),
+        };
+        options.dir_path = self.family_dir(name).join(FAMILY_DATA_DIR_NAME);
+        options
+    }
+
+    fn family_dir(&self, name: &str) -> PathBuf {
+        self.options.root_dir.join(FAMILIES_DIR_NAME).join(name)
+    }
+
+    // 按照提交的顺序重新写入提交日志中剩余的提交，写入的列族不存在时重新创建
+    fn recover(&self) -> Result<()> {
+        for commit_key in self.commit_log.list_keys()? {
+            let writes = decode_commit(&self.commit_log.get(commit_key.clone())?)?;
+            let options = WriteBatchOptions {
+                max_batch_num: u64::MAX,
+                max_batch_bytes: None,
+                sync_writes: true,
+                merge_redundant_ops: false,
+            };
+            write_staged(self.stage(&writes, &options)?)?;
+            self.commit_log.delete(commit_key)?;
+        }
+        Ok(())
+    }
+
+    // 按照列族拆分成每个列族的批量写入，列族按照第一次出现的顺序排列
+    // 不管调用方的 sync_writes 选项，每个列族的批量写入都持久化，删除提交日志之前写入一定已经落盘
+    fn stage(
+        &self,
+        writes: &[FamilyWrite],
+        options: &WriteBatchOptions,
+    ) -> Result<Vec<(Arc<Engine>, WriteBatch)>> {
+        let mut order = Vec::new();
+        let mut batches: HashMap<&str, (Arc<Engine>, WriteBatch)> = HashMap::new();
+        for write in writes {
+            if !batches.contains_key(write.family.as_str()) {
+                let engine = self.column_family(&write.family)?;
+                let batch = engine.new_write_batch(WriteBatchOptions {
+                    max_batch_num: options.max_batch_num,
+                    max_batch_bytes: None,
+                    sync_writes: true,
+                    merge_redundant_ops: options.merge_redundant_ops,
+                })?;
+                batches.insert(write.family.as_str(), (engine, batch));
+                order.push(write.family.as_str());
+            }
+
+            let (_, batch) = &batches[write.family.as_str()];
+            match &write.value {
+                Some(value) => batch.put(write.key.clone(), value.clone())?,
+                None => batch.delete(write.key.clone())?,
+            }
+        }
+        Ok(order
+            .into_iter()
+            .filter_map(|family| batches.remove(family))
+            .collect())
+    }
+}
+
+impl Drop for ColumnFamilies {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            error!("error whiling close column families: {}", e);
+        }
+    }
+}
+
+impl ColumnFamilyBatch<'_> {
+    /// 暂存写入操作，暂存的字节数超过 max_batch_bytes 时返回 ExceedMaxBatchSize，这次操作不会被暂存
+    pub fn put(&self, family: &str, key: Bytes, value: Bytes) -> Result<()> {
+        self.push(family, key, Some(value))
+    }
+
+    /// 暂存删除操作
+    pub fn delete(&self, family: &str, key: Bytes) -> Result<()> {
+        self.push(family, key, None)
+    }
+
+    /// 当前暂存的 key 和 value 的字节数
+    pub fn pending_bytes(&self) -> u64 {
+        self.pending_bytes.load(Ordering::SeqCst)
+    }
+
+    // 暂存一个操作，超过批次的字节数上限时返回错误
+    fn push(&self, family: &str, key: Bytes, value: Option<Bytes>) -> Result<()> {
+        check_family_name(family)?;
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+
+        let mut pending_writes = self.pending_writes.lock();
+        let size = self.pending_bytes()
+            + key.len() as u64
+            + value.as_ref().map_or(0, |value| value.len() as u64);
+        if self
+            .options
+            .max_batch_bytes
+            .is_some_and(|max_batch_bytes| size > max_batch_bytes)
+        {
+            return Err(Errors::ExceedMaxBatchSize);
+        }
+        pending_writes.push(FamilyWrite {
+            family: family.to_string(),
+            key,
+            value,
+        });
+        self.pending_bytes.store(size, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// 提交数据，所有列族的写入都完成之后返回
+    /// 写入提交日志之后某个列族写入失败的话返回错误，提交日志保留，下一次提交之前或者下次打开时重新写入；
+    /// 重新写入失败时返回错误，不接受新的提交
+    pub fn commit(&self) -> Result<()> {
+        let mut pending_writes = self.pending_writes.lock();
+        if pending_writes.is_empty() {
+            return Ok(());
+        }
+        if pending_writes.len() as u64 > self.options.max_batch_num {
+            return Err(Errors::ExceedMaxBatchNum);
+        }
+
+        let families = self.families;
+        let _commit_lock = families.commit_lock.lock();
+        // 先按照顺序重新写入之前失败的提交，否则之后恢复时旧的提交会覆盖这次以及之后提交写入的数据
+        if families.needs_recover.load(Ordering::SeqCst) {
+            families.recover()?;
+            families.needs_recover.store(false, Ordering::SeqCst);
+        }
+        // 先打开涉及的列族并暂存所有的操作，出错时还没有写入提交日志，不会留下需要恢复的提交
+        let batches = families.stage(&pending_writes, &self.options)?;
+
+        let commit_id = families.next_commit_id.fetch_add(1, Ordering::SeqCst);
+        let commit_key = Bytes::copy_from_slice(&commit_id.to_be_bytes());
+        families
+            .commit_log
+            .put(commit_key.clone(), encode_commit(&pending_writes))?;
+        if let Err(e) = write_staged(batches).and_then(|_| families.commit_log.delete(commit_key)) {
+            families.needs_recover.store(true, Ordering::SeqCst);
+            return Err(e);
+        }
+
+        pending_writes.clear();
+        self.pending_bytes.store(0, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+// 依次提交每个列族的批量写入，之后再持久化每个列族的数据文件
+// 所有列族的写入都落盘之后调用方才能删除提交日志，否则崩溃之后一次提交可能只剩下部分列族的写入
+fn write_staged(batches: Vec<(Arc<Engine>, WriteBatch)>) -> Result<()> {
+    for (_, batch) in &batches {
+        batch.commit()?;
+    }
+    for (engine, _) in &batches {
+        engine.sync()?;
+    }
+    Ok(())
+}
+
+// 列族名称作为子目录的名称，不能为空，也不能包含路径分隔符
+fn check_family_name(name: &str) -> Result<()> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+        return Err(Errors::InvaildColumnFamilyName);
+    }
+    Ok(())
+}
+
+// 编码一次提交中的所有操作，每个操作的格式为
+// tag | family size | family | key size | key | value size | value，删除操作没有 value
+fn encode_commit(writes: &[FamilyWrite]) -> Bytes {
+    let mut buf = BytesMut::new();
+    for write in writes {
+        let tag = match write.value {
+            Some(_) => PUT_TAG,
+            None => DELETE_TAG,
+        };
+        buf.put_u8(tag);
+        encode_varint(write.family.len() as u64, &mut buf);
+        buf.put_slice(write.family.as_bytes());
+        encode_varint(write.key.len() as u64, &mut buf);
+        buf.put_slice(&write.key);
+        if let Some(value) = &write.value {
+            encode_varint(value.len() as u64, &mut buf);
+            buf.put_slice(value);
+        }
+    }
+    buf.freeze()
+}
+
+fn decode_commit(mut buf: &[u8]) -> Result<Vec<FamilyWrite>> {
+    let mut writes = Vec::new();
+    while buf.has_remaining() {
+        let tag = buf.get_u8();
+        let family = decode_bytes(&mut buf)?;
+        let family =
+            String::from_utf8(family.to_vec()).map_err(|_| Errors::InvaildColumnFamilyCommit)?;
+        let key = decode_bytes(&mut buf)?;
+        let value = match tag {
+            PUT_TAG => Some(decode_bytes(&mut buf)?),
+            DELETE_TAG => None,
+            _ => return Err(Errors::InvaildColumnFamilyCommit),
+        };
+        writes.push(FamilyWrite { family, key, value });
+    }
+    Ok(writes)
+}
+
+fn decode_commit_id(commit_key: &[u8]) -> Result<u64> {
+    let commit_id = commit_key
+        .try_into()
+        .map_err(|_| Errors::InvaildColumnFamilyCommit)?;
+    Ok(u64::from_be_bytes(commit_id))
+}
+
+fn decode_bytes(buf: &mut &[u8]) -> Result<Bytes> {
+    let size = decode_varint(buf).map_err(|_| Errors::InvaildColumnFamilyCommit)?;
+    if size > buf.remaining() as u64 {
+        return Err(Errors::InvaildColumnFamilyCommit);
+    }
+    let bytes = Bytes::copy_from_slice(&buf[..size as usize]);
+    buf.advance(size as usize);
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::rand_kv::{get_test_key, get_test_value};
+
+    #[test]
+    fn test_column_families() {
+        let mut options = ColumnFamilyOptions::default();
+        options.root_dir = PathBuf::from("/tmp/bitcask-rs-column-families");
+        let mut blob_options = Options::default();
+        blob_options.data_file_size = 64 * 1024;
+        options
+            .family_options
+            .insert("blobs".to_string(), blob_options);
+        let families = ColumnFamilies::open(options.clone()).expect("failed to open families");
+
+        // 列族之间相互隔离，使用各自的配置
+        let meta = families.column_family("meta").unwrap();
+        let blobs = families.column_family("blobs").unwrap();
+        assert!(Arc::ptr_eq(&meta, &families.column_family("meta").unwrap()));
+        assert!(meta.put(get_test_key(1), Bytes::from("meta")).is_ok());
+        assert_eq!(
+            blobs.get(get_test_key(1)).err().unwrap(),
+            Errors::KeyNotFound
+        );
+        for i in 0..1000 {
+            assert!(blobs.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        assert!(blobs.stat().unwrap().data_file_num > 1);
+        assert_eq!(meta.stat().unwrap().data_file_num, 1);
+
+        // 跨列族的批量写入
+        let batch = families.new_batch(WriteBatchOptions::default());
+        assert!(batch
+            .put("meta", get_test_key(2), Bytes::from("meta-2"))
+            .is_ok());
+        assert!(batch.delete("blobs", get_test_key(2)).is_ok());
+        assert!(batch
+            .put("logs", get_test_key(3), Bytes::from("log-3"))
+            .is_ok());
+        assert_eq!(
+            batch
+                .put("a/b", get_test_key(1), Bytes::new())
+                .err()
+                .unwrap(),
+            Errors::InvaildColumnFamilyName
+        );
+        assert!(batch.commit().is_ok());
+        assert_eq!(meta.get(get_test_key(2)).unwrap(), Bytes::from("meta-2"));
+        assert_eq!(
+            blobs.get(get_test_key(2)).err().unwrap(),
+            Errors::KeyNotFound
+        );
+        let logs = families.column_family("logs").unwrap();
+        assert_eq!(logs.get(get_test_key(3)).unwrap(), Bytes::from("log-3"));
+        assert!(families.commit_log.list_keys().unwrap().is_empty());
+        assert_eq!(
+            families.column_families().unwrap(),
+            vec!["blobs", "logs", "meta"]
+        );
+
+        assert!(families.drop_column_family("logs").is_ok());
+        assert_eq!(logs.stat().err().unwrap(), Errors::DatabaseClosed);
+        assert_eq!(families.column_families().unwrap(), vec!["blobs", "meta"]);
+
+        std::mem::drop(meta);
+        std::mem::drop(blobs);
+        std::mem::drop(logs);
+        std::mem::drop(families);
+        std::fs::remove_dir_all(options.root_dir).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_column_families_recover_commit() {
+        let mut options = ColumnFamilyOptions::default();
+        options.root_dir = PathBuf::from("/tmp/bitcask-rs-column-families-recover");
+        let families = ColumnFamilies::open(options.clone()).expect("failed to open families");
+
+        // 模拟写入提交日志之后、写入列族之前崩溃
+        let writes = vec![
+            FamilyWrite {
+                family: "a".to_string(),
+                key: get_test_key(1),
+                value: Some(Bytes::from("a-1")),
+            },
+            FamilyWrite {
+                family: "b".to_string(),
+                key: get_test_key(1),
+                value: Some(Bytes::from("b-1")),
+            },
+            FamilyWrite {
+                family: "b".to_string(),
+                key: get_test_key(2),
+                value: None,
+            },
+        ];
+        let b = families.column_family("b").unwrap();
+        assert!(b.put(get_test_key(2), Bytes::from("b-2")).is_ok());
+        std::mem::drop(b);
+        assert!(families
+            .commit_log
+            .put(
+                Bytes::copy_from_slice(&5u64.to_be_bytes()),
+                encode_commit(&writes)
+            )
+            .is_ok());
+        std::mem::drop(families);
+
+        // 重新打开时所有列族的写入都生效，新的提交 id 在剩余的提交之后分配
+        let families = ColumnFamilies::open(options.clone()).expect("failed to open families");
+        assert_eq!(families.next_commit_id.load(Ordering::SeqCst), 6);
+        let a = families.column_family("a").unwrap();
+        let b = families.column_family("b").unwrap();
+        assert_eq!(a.get(get_test_key(1)).unwrap(), Bytes::from("a-1"));
+        assert_eq!(b.get(get_test_key(1)).unwrap(), Bytes::from("b-1"));
+        assert_eq!(b.get(get_test_key(2)).err().unwrap(), Errors::KeyNotFound);
+        assert!(families.commit_log.list_keys().unwrap().is_empty());
+
+        assert_eq!(
+            decode_commit(&[PUT_TAG, 5]).err().unwrap(),
+            Errors::InvaildColumnFamilyCommit
+        );
+
+        std::mem::drop(a);
+        std::mem::drop(b);
+        std::mem::drop(families);
+        std::fs::remove_dir_all(options.root_dir).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_column_families_failed_commit() {
+        let mut options = ColumnFamilyOptions::default();
+        options.root_dir = PathBuf::from("/tmp/bitcask-rs-column-families-failed-commit");
+        let injection = crate::testing::inject_faults(
+            &options
+                .root_dir
+                .join(FAMILIES_DIR_NAME)
+                .join("b")
+                .join(FAMILY_DATA_DIR_NAME),
+        );
+        let families = ColumnFamilies::open(options.clone()).expect("failed to open families");
+
+        // 列族 a 写入成功之后列族 b 写入失败，提交日志保留
+        let batch = families.new_batch(WriteBatchOptions::default());
+        assert!(batch.put("a", get_test_key(1), Bytes::from("old")).is_ok());
+        assert!(batch.put("b", get_test_key(1), Bytes::from("b-1")).is_ok());
+        injection.fail_writes_after(0);
+        assert!(batch.commit().is_err());
+        assert_eq!(families.commit_log.list_keys().unwrap().len(), 1);
+        injection.clear();
+
+        // 之后的提交先重新写入失败的提交，重启之后不会被旧的提交覆盖
+        let batch = families.new_batch(WriteBatchOptions::default());
+        assert!(batch.put("a", get_test_key(1), Bytes::from("new")).is_ok());
+        assert!(batch.commit().is_ok());
+        assert!(families.commit_log.list_keys().unwrap().is_empty());
+        std::mem::drop(families);
+
+        let families = ColumnFamilies::open(options.clone()).expect("failed to open families");
+        let a = families.column_family("a").unwrap();
+        let b = families.column_family("b").unwrap();
+        assert_eq!(a.get(get_test_key(1)).unwrap(), Bytes::from("new"));
+        assert_eq!(b.get(get_test_key(1)).unwrap(), Bytes::from("b-1"));
+
+        std::mem::drop(a);
+        std::mem::drop(b);
+        std::mem::drop(families);
+        std::mem::drop(injection);
+        std::fs::remove_dir_all(options.root_dir).expect("failed to remove path");
+    }
+}
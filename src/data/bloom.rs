@@ -0,0 +1,180 @@
+use std::{fs, path::PathBuf};
+
+use bytes::{Buf, BufMut, BytesMut};
+use log::{error, warn};
+
+use crate::errors::{Errors, Result};
+
+use super::data_file::get_bloom_file_name;
+
+// 每个 key 占用的位数，误判率约为 1%
+const BLOOM_BITS_PER_KEY: usize = 10;
+// bloom filter 文件尾部的固定长度：哈希函数个数、数据文件大小以及 crc
+const BLOOM_FOOTER_SIZE: usize = 1 + 8 + 4;
+
+/// 数据文件中所有 key 的 bloom filter，用于在扫描数据文件之前判断 key 是否可能存在
+/// 返回 false 时 key 一定不在数据文件中，返回 true 时 key 可能在数据文件中
+#[derive(Debug, Clone, PartialEq)]
+pub struct BloomFilter {
+    bits: Vec<u8>,
+    num_hashes: u8,
+    data_file_size: u64, // 生成时数据文件的大小，用于判断是否和数据文件匹配
+}
+
+impl BloomFilter {
+    /// 根据数据文件中所有 key 的哈希值生成 bloom filter
+    pub fn new(hashes: &[u32], data_file_size: u64) -> Self {
+        // 数据很少时也至少使用 64 位，避免误判率过高
+        let num_bits = (hashes.len() * BLOOM_BITS_PER_KEY).max(64);
+        let num_hashes = (BLOOM_BITS_PER_KEY as f64 * 0.69) as u8;
+        let mut filter = BloomFilter {
+            bits: vec![0; num_bits.div_ceil(8)],
+            num_hashes: num_hashes.clamp(1, 30),
+            data_file_size,
+        };
+        for hash in hashes {
+            filter.add(*hash);
+        }
+        filter
+    }
+
+    /// key 是否可能在数据文件中
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        self.bit_positions(bloom_hash(key))
+            .all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    /// 生成时数据文件的大小
+    pub fn data_file_size(&self) -> u64 {
+        self.data_file_size
+    }
+
+    fn add(&mut self, hash: u32) {
+        let positions: Vec<usize> = self.bit_positions(hash).collect();
+        for bit in positions {
+            self.bits[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    // 使用两个哈希值模拟多个哈希函数，第二个哈希值由第一个旋转得到
+    fn bit_positions(&self, hash: u32) -> impl Iterator<Item = usize> {
+        let num_bits = self.bits.len() as u64 * 8;
+        let delta = hash.rotate_right(17);
+        (0..self.num_hashes as u32)
+            .map(move |i| (hash.wrapping_add(i.wrapping_mul(delta)) as u64 % num_bits) as usize)
+    }
+
+    // +------------+------------+----------------+---------+
+    // |    bits    | num hashes | data file size |   crc   |
+    // +------------+------------+----------------+---------+
+    //    变长         1字节          8字节          4字节
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = BytesMut::with_capacity(self.bits.len() + BLOOM_FOOTER_SIZE);
+        buf.put_slice(&self.bits);
+        buf.put_u8(self.num_hashes);
+        buf.put_u64(self.data_file_size);
+        let crc = crc32fast::hash(&buf);
+        buf.put_u32(crc);
+        buf.to_vec()
+    }
+
+    fn decode(content: &[u8]) -> Option<Self> {
+        if content.len() <= BLOOM_FOOTER_SIZE {
+            return None;
+        }
+        let bits_len = content.len() - BLOOM_FOOTER_SIZE;
+        let mut buf = &content[bits_len..];
+        let num_hashes = buf.get_u8();
+        let data_file_size = buf.get_u64();
+        if buf.get_u32() != crc32fast::hash(&content[..content.len() - 4]) || num_hashes == 0 {
+            return None;
+        }
+        Some(BloomFilter {
+            bits: content[..bits_len].to_vec(),
+            num_hashes,
+            data_file_size,
+        })
+    }
+}
+
+/// 计算 key 在 bloom filter 中使用的哈希值
+pub fn bloom_hash(key: &[u8]) -> u32 {
+    crc32fast::hash(key)
+}
+
+/// 将数据文件的 bloom filter 写入到数据文件旁边
+/// 读取时会校验 crc 以及数据文件的大小，不完整的文件会被忽略，所以不需要持久化
+pub fn write_bloom_file(dir_path: PathBuf, file_id: u64, filter: &BloomFilter) -> Result<()> {
+    let path = get_bloom_file_name(dir_path, file_id);
+    if let Err(e) = fs::write(&path, filter.encode()) {
+        error!("failed to write bloom filter file: {}", e);
+        return Err(Errors::write_failed(&path, e));
+    }
+    Ok(())
+}
+
+/// 读取数据文件的 bloom filter，文件不存在或者已经损坏时返回 None
+pub fn read_bloom_file(dir_path: PathBuf, file_id: u64) -> Option<BloomFilter> {
+    let path = get_bloom_file_name(dir_path, file_id);
+    if !path.is_file() {
+        return None;
+    }
+    let filter = fs::read(&path)
+        .ok()
+        .and_then(|content| BloomFilter::decode(&content));
+    if filter.is_none() {
+        warn!("bloom filter file {:?} is corrupted, ignore it", path);
+    }
+    filter
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bloom_filter() {
+        let keys: Vec<Vec<u8>> = (0..1000)
+            .map(|i| format!("bitcask-key-{}", i).into_bytes())
+            .collect();
+        let hashes: Vec<u32> = keys.iter().map(|key| bloom_hash(key)).collect();
+        let filter = BloomFilter::new(&hashes, 100);
+
+        // 不会漏掉存在的 key
+        for key in keys.iter() {
+            assert!(filter.may_contain(key));
+        }
+        // 不存在的 key 误判率很低
+        let false_positives = (0..1000)
+            .filter(|i| filter.may_contain(format!("missing-key-{}", i).as_bytes()))
+            .count();
+        assert!(false_positives < 50);
+
+        let decoded = BloomFilter::decode(&filter.encode()).unwrap();
+        assert_eq!(decoded, filter);
+        assert_eq!(decoded.data_file_size(), 100);
+
+        // 损坏的数据无法解码
+        let mut encoded = filter.encode();
+        encoded[0] ^= 0xff;
+        assert!(BloomFilter::decode(&encoded).is_none());
+        assert!(BloomFilter::decode(&[]).is_none());
+    }
+
+    #[test]
+    fn test_bloom_filter_file() {
+        let dir_path = PathBuf::from("/tmp/bitcask-rs-bloom-file");
+        fs::create_dir_all(dir_path.clone()).unwrap();
+
+        assert!(read_bloom_file(dir_path.clone(), 1).is_none());
+        let filter = BloomFilter::new(&[bloom_hash(b"key")], 10);
+        assert!(write_bloom_file(dir_path.clone(), 1, &filter).is_ok());
+        assert_eq!(read_bloom_file(dir_path.clone(), 1), Some(filter));
+
+        // 不完整的文件被忽略
+        fs::write(get_bloom_file_name(dir_path.clone(), 2), b"bloom").unwrap();
+        assert!(read_bloom_file(dir_path.clone(), 2).is_none());
+
+        fs::remove_dir_all(dir_path).expect("failed to remove path");
+    }
+}
@@ -0,0 +1,160 @@
+use std::path::PathBuf;
+
+use aes_gcm::{
+    aead::{Aead, KeyInit, Payload},
+    Aes256Gcm, Nonce,
+};
+use log::error;
+
+use crate::errors::{Errors, Result};
+
+use super::{
+    data_file::{DataFile, KEY_CHECK_FILE_NAME},
+    log_record::{LogRecord, LogRecordType},
+};
+
+/// 加密使用的随机数长度
+pub const NONCE_SIZE: usize = 12;
+
+// 用于校验密钥的数据
+const KEY_CHECK_KEY: &str = "key.check";
+const KEY_CHECK_VALUE: &str = "bitcask-rs";
+
+/// 记录加密器，使用 AES-256-GCM 加密记录中的 key 和 value
+pub struct Cipher {
+    aead: Aes256Gcm,
+}
+
+impl Cipher {
+    pub fn new(key: &[u8; 32]) -> Result<Self> {
+        match Aes256Gcm::new_from_slice(key) {
+            Ok(aead) => Ok(Cipher { aead }),
+            Err(_) => Err(Errors::InvaildEncryptionKey),
+        }
+    }
+
+    /// 加密数据，返回随机数和密文，aad 为参与校验但不加密的附加数据
+    pub fn encrypt(&self, plaintext: &[u8], aad: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+        let nonce: [u8; NONCE_SIZE] = rand::random();
+        let payload = Payload {
+            msg: plaintext,
+            aad,
+        };
+        match self.aead.encrypt(Nonce::from_slice(&nonce), payload) {
+            Ok(ciphertext) => Ok((nonce.to_vec(), ciphertext)),
+            Err(e) => {
+                error!("failed to encrypt data: {:?}", e);
+                Err(Errors::FailedToEncryptData)
+            }
+        }
+    }
+
+    /// 解密数据，密钥错误或者数据被篡改时返回错误
+    pub fn decrypt(&self, nonce: &[u8], ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        if nonce.len() != NONCE_SIZE {
+            return Err(Errors::FailedToDecryptLogRecord);
+        }
+        let payload = Payload {
+            msg: ciphertext,
+            aad,
+        };
+        match self.aead.decrypt(Nonce::from_slice(nonce), payload) {
+            Ok(plaintext) => Ok(plaintext),
+            Err(_) => Err(Errors::FailedToDecryptLogRecord),
+        }
+    }
+}
+
+/// 根据密钥创建加密器，并使用数据目录中的密钥校验文件校验密钥是否正确
+/// 校验文件不存在时，如果允许写入则使用当前密钥创建
+pub(crate) fn load_cipher(
+    dir_path: PathBuf,
+    encryption_key: Option<&[u8; 32]>,
+    read_only: bool,
+) -> Result<Option<Cipher>> {
+    let key_check_path = dir_path.join(KEY_CHECK_FILE_NAME);
+    let key = match encryption_key {
+        Some(key) => key,
+        None => {
+            // 数据目录已经开启了加密，必须提供密钥
+            if key_check_path.is_file() {
+                return Err(Errors::EncryptionKeyRequired);
+            }
+            return Ok(None);
+        }
+    };
+
+    let cipher = Cipher::new(key)?;
+    if key_check_path.is_file() {
+        let key_check_file = DataFile::new_key_check_file(dir_path)?;
+        let record = key_check_file.read_log_record(0)?.record;
+        match record.decrypt(&cipher) {
+            Ok(record) if record.value == KEY_CHECK_VALUE.as_bytes() => {}
+            _ => return Err(Errors::InvaildEncryptionKey),
+        }
+    } else if !read_only {
+        let key_check_file = DataFile::new_key_check_file(dir_path)?;
+        let record = LogRecord {
+            key: KEY_CHECK_KEY.as_bytes().to_vec(),
+            value: KEY_CHECK_VALUE.as_bytes().to_vec(),
+            rec_type: LogRecordType::NORMAL,
+            timestamp: 0,
+            value_pointer: false,
+        };
+        key_check_file.write(&record.encrypt(&cipher)?.encode())?;
+        key_check_file.sync()?;
+    }
+
+    Ok(Some(cipher))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cipher_encrypt_decrypt() {
+        let cipher = Cipher::new(&[7; 32]).unwrap();
+
+        let (nonce, ciphertext) = cipher.encrypt(b"bitcask-rs-kv", b"aad").unwrap();
+        assert_eq!(nonce.len(), NONCE_SIZE);
+        assert_ne!(ciphertext, b"bitcask-rs-kv".to_vec());
+        let plaintext = cipher.decrypt(&nonce, &ciphertext, b"aad").unwrap();
+        assert_eq!(plaintext, b"bitcask-rs-kv".to_vec());
+
+        // 附加数据不一致
+        let res1 = cipher.decrypt(&nonce, &ciphertext, b"other");
+        assert_eq!(res1.err().unwrap(), Errors::FailedToDecryptLogRecord);
+
+        // 密钥不一致
+        let other_cipher = Cipher::new(&[8; 32]).unwrap();
+        let res2 = other_cipher.decrypt(&nonce, &ciphertext, b"aad");
+        assert_eq!(res2.err().unwrap(), Errors::FailedToDecryptLogRecord);
+    }
+
+    #[test]
+    fn test_load_cipher() {
+        let dir_path = std::env::temp_dir().join("bitcask-rs-load-cipher");
+        std::fs::create_dir_all(dir_path.clone()).expect("failed to create dir");
+
+        // 未开启加密
+        let res1 = load_cipher(dir_path.clone(), None, false);
+        assert!(res1.unwrap().is_none());
+
+        // 第一次使用密钥时创建校验文件
+        let res2 = load_cipher(dir_path.clone(), Some(&[1; 32]), false);
+        assert!(res2.unwrap().is_some());
+        assert!(dir_path.join(KEY_CHECK_FILE_NAME).is_file());
+
+        let res3 = load_cipher(dir_path.clone(), Some(&[1; 32]), true);
+        assert!(res3.unwrap().is_some());
+
+        let res4 = load_cipher(dir_path.clone(), Some(&[2; 32]), false);
+        assert_eq!(res4.err().unwrap(), Errors::InvaildEncryptionKey);
+
+        let res5 = load_cipher(dir_path.clone(), None, false);
+        assert_eq!(res5.err().unwrap(), Errors::EncryptionKeyRequired);
+
+        std::fs::remove_dir_all(dir_path).expect("failed to remove path");
+    }
+}
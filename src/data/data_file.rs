@@ -1,90 +1,355 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    fs,
+    io::IoSlice,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use bytes::Buf;
+use bytes::BufMut;
 use bytes::BytesMut;
+use log::error;
 
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use prost::decode_length_delimiter;
+use prost::encoding::{decode_varint, encoded_len_varint};
 use prost::length_delimiter_len;
 
 use crate::errors::Errors;
 use crate::options::IOType;
 use crate::{
     errors::Result,
-    fio::{self, new_io_manager},
+    fio::{
+        self, data_file_exists,
+        file_cache::{CachedIO, FileCache},
+        new_io_manager,
+        sequential::{SequentialReader, DEFAULT_READ_AHEAD_SIZE},
+    },
+    util,
 };
 
+use super::cipher::Cipher;
 use super::log_record::LogRecord;
 use super::log_record::LogRecordPos;
 use super::log_record::LogRecordType;
-use super::log_record::{max_log_record_header_size, ReadLogRecord};
+use super::log_record::{
+    max_log_record_header_size, ReadLogRecord, LOG_RECORD_TIMESTAMP_FLAG,
+    LOG_RECORD_VALUE_POINTER_FLAG,
+};
 
 pub const DATA_FILE_NAME_SUFFIX: &str = ".data";
+pub const BLOB_FILE_NAME_SUFFIX: &str = ".blob";
+pub const BLOOM_FILE_NAME_SUFFIX: &str = ".bloom";
+pub const BLOB_GC_FILE_NAME: &str = "blob-gc";
 pub const HINT_FILE_NAME: &str = "hint-index";
 pub const MERGE_FIN_FILE_NAME: &str = "merge-fin";
 pub const SEQ_NO_FILE_NAME: &str = "seq-no";
+pub const MVCC_VERSION_FILE_NAME: &str = "mvcc-version";
+pub const STATS_FILE_NAME: &str = "stats";
+pub const KEY_CHECK_FILE_NAME: &str = "key-check";
+pub const MANIFEST_FILE_NAME: &str = "MANIFEST";
+pub const MANIFEST_TMP_FILE_NAME: &str = "MANIFEST.tmp";
+
+/// 数据文件头部的魔数，第一个字节不是合法的记录类型，可以和没有头部的旧数据文件区分开
+pub const DATA_FILE_MAGIC: &[u8; 4] = b"BKRS";
+/// 当前的数据文件格式版本
+pub const DATA_FILE_FORMAT_VERSION: u16 = 1;
+/// 数据文件头部的大小
+pub const DATA_FILE_HEADER_SIZE: u64 = 20;
+
+/// 数据文件头部标志位：记录经过压缩，预留给后续的压缩功能
+#[allow(dead_code)]
+pub const DATA_FILE_FLAG_COMPRESSED: u16 = 1;
+/// 数据文件头部标志位：记录经过加密
+pub const DATA_FILE_FLAG_ENCRYPTED: u16 = 1 << 1;
+// 当前版本能够识别的标志位
+const SUPPORTED_DATA_FILE_FLAGS: u16 = DATA_FILE_FLAG_ENCRYPTED;
+
+/// 读取一条记录时默认第一次读取的字节数，不超过这个大小的记录只需要一次 IO
+pub const DEFAULT_READ_CHUNK_SIZE: usize = 4 * 1024;
+
+/// 数据文件头部，创建数据文件时写入，打开数据文件时校验
+///
+/// +-------------+-------------+-------------+------------------+-------------+
+/// |    magic    |   version   |    flags    |   create time    |  crc 校验值  |
+/// +-------------+-------------+-------------+------------------+-------------+
+///      4字节         2字节          2字节            8字节            4字节
+///
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct DataFileHeader {
+    pub version: u16,
+    pub flags: u16,
+    pub create_time: u64, // 创建时间，单位毫秒
+}
+
+impl DataFileHeader {
+    pub fn new(flags: u16) -> Self {
+        let create_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        DataFileHeader {
+            version: DATA_FILE_FORMAT_VERSION,
+            flags,
+            create_time,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = BytesMut::with_capacity(DATA_FILE_HEADER_SIZE as usize);
+        buf.put_slice(DATA_FILE_MAGIC);
+        buf.put_u16(self.version);
+        buf.put_u16(self.flags);
+        buf.put_u64(self.create_time);
+        let crc = crc32fast::hash(&buf);
+        buf.put_u32(crc);
+        buf.to_vec()
+    }
+
+    // 解码头部，crc 校验失败返回 None
+    fn decode(mut buf: &[u8]) -> Option<Self> {
+        let crc = crc32fast::hash(&buf[..DATA_FILE_HEADER_SIZE as usize - 4]);
+        buf.advance(DATA_FILE_MAGIC.len());
+        let header = DataFileHeader {
+            version: buf.get_u16(),
+            flags: buf.get_u16(),
+            create_time: buf.get_u64(),
+        };
+        if buf.get_u32() != crc {
+            return None;
+        }
+        Some(header)
+    }
+}
+
+// 数据文件中一条记录的 header 信息
+struct LogRecordHeader {
+    type_byte: u8,
+    timestamp: u64,
+    key_size: usize,
+    value_size: usize,
+    header_size: usize,
+}
+
+impl LogRecordHeader {
+    // 记录在文件中的大小，最后 4 个字节是 CRC 校验值
+    fn record_size(&self) -> u64 {
+        (self.header_size as u64)
+            .saturating_add(self.key_size as u64)
+            .saturating_add(self.value_size as u64)
+            .saturating_add(4)
+    }
+}
+
+// 一条记录在数据文件中的布局，依次是 header、key、value 以及 4 个字节的 CRC 校验值
+pub(crate) struct RecordLayout {
+    pub(crate) rec_type: LogRecordType,
+    pub(crate) value_pointer: bool, // value 是否为指向 blob 文件的指针
+    pub(crate) header_size: u64,
+    pub(crate) key_size: u64,
+    pub(crate) value_size: u64,
+}
 
 /// 数据文件
 pub struct DataFile {
-    file_id: Arc<RwLock<u64>>,           // 数据文件 ID
-    wirte_off: Arc<RwLock<u64>>,         // 当前写偏移，记录该数据文件写到哪个位置了
-    io_manager: Box<dyn fio::IOManager>, // IO 管理接口
+    file_id: Arc<RwLock<u64>>,            // 数据文件 ID
+    wirte_off: Arc<RwLock<u64>>,          // 当前写偏移，记录该数据文件写到哪个位置了
+    io_manager: Box<dyn fio::IOManager>,  // IO 管理接口
+    header: Option<DataFileHeader>,       // 文件头部，没有头部的旧数据文件为 None
+    cipher: Option<Arc<Cipher>>,          // 记录加密器，只有带加密标志位的文件才会使用
+    io_type: IOType,                      // 打开文件时使用的 IO 类型
+    sealed: AtomicBool,                   // 是否已经从活跃文件转换为旧的数据文件，之后不能再写入
+    deferred_delete: Arc<DeferredDelete>, // 共享同一个文件的所有句柄都释放之后删除文件
+}
+
+// 数据文件被 merge 删除之后，共享同一个文件的所有句柄都释放时才真正删除文件，
+// 已经拿到句柄的读取不会因为文件被删除而失败
+#[derive(Default)]
+struct DeferredDelete {
+    path: Mutex<Option<PathBuf>>,
+}
+
+impl Drop for DeferredDelete {
+    fn drop(&mut self) {
+        if let Some(path) = self.path.lock().take() {
+            if path.is_file() {
+                if let Err(e) = fs::remove_file(&path) {
+                    error!("failed to remove data file {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
 }
 
 impl DataFile {
-    // 创建或打开一个新的数据文件
-    pub fn new(dir_path: PathBuf, file_id: u64, io_type: IOType) -> Result<DataFile> {
+    // 创建或打开一个新的数据文件，cipher 不为空时新建的数据文件中的记录会被加密
+    pub fn new(
+        dir_path: PathBuf,
+        file_id: u64,
+        io_type: IOType,
+        cipher: Option<Arc<Cipher>>,
+    ) -> Result<DataFile> {
         // 根据 path 和 file_id 构造出来完整的文件名称
         let filename = get_data_file_name(dir_path, file_id);
+        open_with_header(filename, file_id, io_type, cipher)
+    }
+
+    // 打开外部生成的已经存在的数据文件，只用于校验其中的数据
+    pub fn open_external_file(filename: PathBuf, cipher: Option<Arc<Cipher>>) -> Result<DataFile> {
+        open_with_header(filename, 0, IOType::StandardFIO, cipher)
+    }
+
+    // 新建或打开 hint 索引文件，和数据文件一样带有头部
+    pub fn new_hint_file(dir_path: PathBuf, cipher: Option<Arc<Cipher>>) -> Result<DataFile> {
+        let filename = dir_path.join(HINT_FILE_NAME);
+        open_with_header(filename, 0, IOType::StandardFIO, cipher)
+    }
+
+    // 新建或打开标识 merge 完成的文件
+    pub fn new_merge_fin_file(dir_path: PathBuf) -> Result<DataFile> {
+        DataFile::open_merge_fin_file(dir_path.join(MERGE_FIN_FILE_NAME))
+    }
+
+    // 打开指定路径的标识 merge 完成的文件，安装 merge 结果时文件名会带上安装阶段的后缀
+    pub fn open_merge_fin_file(filename: PathBuf) -> Result<DataFile> {
         // 初始化 IO manager
-        let io_manager = new_io_manager(filename, io_type);
+        let io_manager = new_io_manager(filename, IOType::StandardFIO)?;
 
         Ok(DataFile {
-            file_id: Arc::new(RwLock::new(file_id)),
+            file_id: Arc::new(RwLock::new(0)),
             wirte_off: Arc::new(RwLock::new(0)),
             io_manager: io_manager,
+            header: None,
+            cipher: None,
+            io_type: IOType::StandardFIO,
+            sealed: AtomicBool::new(false),
+            deferred_delete: Arc::default(),
         })
     }
 
-    // 新建或打开 hint 索引文件
-    pub fn new_hint_file(dir_path: PathBuf) -> Result<DataFile> {
-        let filename = dir_path.join(HINT_FILE_NAME);
+    // 创建或打开存放大 value 的 blob 文件，和数据文件一样带有头部
+    pub fn new_blob_file(
+        dir_path: PathBuf,
+        file_id: u64,
+        cipher: Option<Arc<Cipher>>,
+    ) -> Result<DataFile> {
+        let filename = get_blob_file_name(dir_path, file_id);
+        open_with_header(filename, file_id, IOType::StandardFIO, cipher)
+    }
+
+    // 新建或打开记录 merge 之后可以删除的 blob 文件的文件
+    pub fn new_blob_gc_file(dir_path: PathBuf) -> Result<DataFile> {
+        let filename = dir_path.join(BLOB_GC_FILE_NAME);
 
         // 初始化 IO manager
-        let io_manager = new_io_manager(filename, IOType::StandardFIO);
+        let io_manager = new_io_manager(filename, IOType::StandardFIO)?;
+
+        Ok(DataFile {
+            file_id: Arc::new(RwLock::new(0)),
+            wirte_off: Arc::new(RwLock::new(0)),
+            io_manager,
+            header: None,
+            cipher: None,
+            io_type: IOType::StandardFIO,
+            sealed: AtomicBool::new(false),
+            deferred_delete: Arc::default(),
+        })
+    }
+
+    // 新建或打开存储事务序列号的文件
+    pub fn new_seq_no_file(dir_path: PathBuf) -> Result<DataFile> {
+        let filename = dir_path.join(SEQ_NO_FILE_NAME);
+
+        // 初始化 IO manager
+        let io_manager = new_io_manager(filename, IOType::StandardFIO)?;
 
         Ok(DataFile {
             file_id: Arc::new(RwLock::new(0)),
             wirte_off: Arc::new(RwLock::new(0)),
             io_manager: io_manager,
+            header: None,
+            cipher: None,
+            io_type: IOType::StandardFIO,
+            sealed: AtomicBool::new(false),
+            deferred_delete: Arc::default(),
         })
     }
 
-    // 新建或打开标识 merge 完成的文件
-    pub fn new_merge_fin_file(dir_path: PathBuf) -> Result<DataFile> {
-        let filename = dir_path.join(MERGE_FIN_FILE_NAME);
+    // 新建或打开存储 MVCC 事务版本号的文件
+    pub fn new_mvcc_version_file(dir_path: PathBuf) -> Result<DataFile> {
+        let filename = dir_path.join(MVCC_VERSION_FILE_NAME);
 
         // 初始化 IO manager
-        let io_manager = new_io_manager(filename, IOType::StandardFIO);
+        let io_manager = new_io_manager(filename, IOType::StandardFIO)?;
 
         Ok(DataFile {
             file_id: Arc::new(RwLock::new(0)),
             wirte_off: Arc::new(RwLock::new(0)),
             io_manager: io_manager,
+            header: None,
+            cipher: None,
+            io_type: IOType::StandardFIO,
+            sealed: AtomicBool::new(false),
+            deferred_delete: Arc::default(),
         })
     }
 
-    // 新建或打开存储事务序列号的文件
-    pub fn new_seq_no_file(dir_path: PathBuf) -> Result<DataFile> {
-        let filename = dir_path.join(SEQ_NO_FILE_NAME);
+    // 新建或打开存储统计信息的文件
+    pub fn new_stats_file(dir_path: PathBuf) -> Result<DataFile> {
+        let filename = dir_path.join(STATS_FILE_NAME);
+
+        // 初始化 IO manager
+        let io_manager = new_io_manager(filename, IOType::StandardFIO)?;
+
+        Ok(DataFile {
+            file_id: Arc::new(RwLock::new(0)),
+            wirte_off: Arc::new(RwLock::new(0)),
+            io_manager: io_manager,
+            header: None,
+            cipher: None,
+            io_type: IOType::StandardFIO,
+            sealed: AtomicBool::new(false),
+            deferred_delete: Arc::default(),
+        })
+    }
+
+    // 新建或打开校验加密密钥的文件
+    pub fn new_key_check_file(dir_path: PathBuf) -> Result<DataFile> {
+        let filename = dir_path.join(KEY_CHECK_FILE_NAME);
 
         // 初始化 IO manager
-        let io_manager = new_io_manager(filename, IOType::StandardFIO);
+        let io_manager = new_io_manager(filename, IOType::StandardFIO)?;
 
         Ok(DataFile {
             file_id: Arc::new(RwLock::new(0)),
             wirte_off: Arc::new(RwLock::new(0)),
             io_manager: io_manager,
+            header: None,
+            cipher: None,
+            io_type: IOType::StandardFIO,
+            sealed: AtomicBool::new(false),
+            deferred_delete: Arc::default(),
+        })
+    }
+
+    // 新建或打开记录数据文件列表的 manifest 文件，生成新的 manifest 时先写入临时文件
+    pub fn open_manifest_file(filename: PathBuf) -> Result<DataFile> {
+        // 初始化 IO manager
+        let io_manager = new_io_manager(filename, IOType::StandardFIO)?;
+
+        Ok(DataFile {
+            file_id: Arc::new(RwLock::new(0)),
+            wirte_off: Arc::new(RwLock::new(0)),
+            io_manager,
+            header: None,
+            cipher: None,
+            io_type: IOType::StandardFIO,
+            sealed: AtomicBool::new(false),
+            deferred_delete: Arc::default(),
         })
     }
 
@@ -103,63 +368,182 @@ impl DataFile {
         *read_guard
     }
 
+    /// 文件中的记录是否经过加密
+    pub fn is_encrypted(&self) -> bool {
+        match self.header {
+            Some(header) => header.flags & DATA_FILE_FLAG_ENCRYPTED != 0,
+            None => false,
+        }
+    }
+
+    /// 文件的创建时间，单位毫秒，没有头部的旧数据文件返回 None
+    pub fn create_time(&self) -> Option<u64> {
+        self.header.map(|header| header.create_time)
+    }
+
+    /// 头部的大小，即第一条记录的偏移
+    pub fn get_header_size(&self) -> u64 {
+        match self.header {
+            Some(_) => DATA_FILE_HEADER_SIZE,
+            None => 0,
+        }
+    }
+
     // 根据 offset 从数据文件中读取一个 LogRecord
     pub fn read_log_record(&self, offset: u64) -> Result<ReadLogRecord> {
-        // 先读取 header 部分的数据
-        // 初始化 header 字节数组
-        let mut header_buf = BytesMut::zeroed(max_log_record_header_size());
-
-        self.io_manager.read(&mut header_buf, offset)?;
+        self.read_log_record_with_chunk(offset, DEFAULT_READ_CHUNK_SIZE)
+    }
 
-        // 取出 type，在第一个字节
-        let rec_type = header_buf.get_u8();
+    // 和 read_log_record 相同，第一次读取 chunk_size 字节，记录不超过 chunk_size 时只需要一次 IO
+    pub fn read_log_record_with_chunk(
+        &self,
+        offset: u64,
+        chunk_size: usize,
+    ) -> Result<ReadLogRecord> {
+        let mut reader = self.io_manager.as_ref();
+        let (header, buf) =
+            self.read_log_record_buf(&mut reader, self.file_size(), offset, chunk_size)?;
+        match self.decode_log_record(header, buf)? {
+            (read_record, true) => Ok(read_record),
+            (_, false) => Err(Errors::InvaildLogRecordCrc),
+        }
+    }
 
-        // 取出 key 和 value 的长度
-        let key_size = decode_length_delimiter(&mut header_buf).unwrap();
-        let value_size = decode_length_delimiter(&mut header_buf).unwrap();
+    // 根据 offset 从数据文件中读取一个 LogRecord，CRC 校验失败时仍然返回读取到的记录，同时返回校验结果
+    // 校验失败的记录不会被解密，记录的长度无法解析时返回 InvaildLogRecordCrc，
+    // 记录类型无法识别时返回 InvalidRecordType
+    pub fn read_log_record_unchecked(&self, offset: u64) -> Result<(ReadLogRecord, bool)> {
+        let mut reader = self.io_manager.as_ref();
+        let (header, buf) = self.read_log_record_buf(
+            &mut reader,
+            self.file_size(),
+            offset,
+            DEFAULT_READ_CHUNK_SIZE,
+        )?;
+        self.decode_log_record(header, buf)
+    }
 
-        // 如果 key 和 value 均为空，则说明读取到了文件末尾，直接返回
-        if key_size == 0 && value_size == 0 {
+    // 读取 offset 处完整的一条记录，先读取 chunk_size（至少是最大的 header 大小）字节，
+    // 大部分记录一次读取就可以拿到 header 和 key/value，超过 chunk_size 的记录再读取剩余的部分
+    fn read_log_record_buf<R: ReadAt>(
+        &self,
+        reader: &mut R,
+        file_size: u64,
+        offset: u64,
+        chunk_size: usize,
+    ) -> Result<(LogRecordHeader, BytesMut)> {
+        let chunk_size = chunk_size.max(max_log_record_header_size()) as u64;
+        // 文件末尾的记录可能比最大的 header 还要短
+        let read_size = chunk_size.min(file_size.saturating_sub(offset));
+        let mut buf = BytesMut::zeroed(read_size as usize);
+        if buf.is_empty() {
             return Err(Errors::ReadDataFileEof);
         }
+        reader.read_at(&mut buf, offset)?;
 
-        // 获取实际的 header 大小
-        let actual_header_size =
-            length_delimiter_len(key_size) + length_delimiter_len(value_size) + 1;
+        let header = parse_log_record_header(file_size, offset, &buf)?;
+        let record_size = header.record_size() as usize;
+        if record_size <= buf.len() {
+            buf.truncate(record_size);
+        } else {
+            let read_len = buf.len();
+            buf.resize(record_size, 0);
+            reader.read_at(&mut buf[read_len..], offset + read_len as u64)?;
+        }
+        Ok((header, buf))
+    }
 
-        // 读取实际的 key 和 value，最后 4 个字节是 CRC 校验值
-        let mut kv_buf = BytesMut::zeroed(key_size + value_size + 4);
-        self.io_manager
-            .read(&mut kv_buf, offset + actual_header_size as u64)?;
+    // 解码 read_log_record_buf 读取到的完整记录，返回记录以及 CRC 校验结果
+    fn decode_log_record(
+        &self,
+        header: LogRecordHeader,
+        mut buf: BytesMut,
+    ) -> Result<(ReadLogRecord, bool)> {
+        let rec_type = LogRecordType::from_u8(
+            header.type_byte & !(LOG_RECORD_TIMESTAMP_FLAG | LOG_RECORD_VALUE_POINTER_FLAG),
+        )?;
+        let (key_size, value_size) = (header.key_size, header.value_size);
 
-        // 构造 LogRecord
-        let log_record = LogRecord {
-            key: kv_buf.get(..key_size).unwrap().to_vec(),
-            value: kv_buf.get(key_size..kv_buf.len() - 4).unwrap().to_vec(),
-            rec_type: LogRecordType::from_u8(rec_type),
+        // 跳过 header，之后是实际的 key 和 value，最后 4 个字节是 CRC 校验值
+        buf.advance(header.header_size);
+        let mut log_record = LogRecord {
+            key: buf.get(..key_size).unwrap().to_vec(),
+            value: buf.get(key_size..key_size + value_size).unwrap().to_vec(),
+            rec_type,
+            timestamp: header.timestamp,
+            value_pointer: header.type_byte & LOG_RECORD_VALUE_POINTER_FLAG != 0,
         };
 
-        // 将 kv_buf 的读取指针向前移动到 crc 字段的位置
-        kv_buf.advance(key_size + value_size);
+        // 将读取指针向前移动到 crc 字段的位置
+        buf.advance(key_size + value_size);
+
+        let crc_valid = buf.get_u32() == log_record.get_crc();
 
-        if kv_buf.get_u32() != log_record.get_crc() {
-            return Err(Errors::InvaildLogRecordCrc);
+        // 解密记录，size 仍然是记录在文件中的实际大小
+        if crc_valid && self.is_encrypted() {
+            if let Some(cipher) = self.cipher.as_ref() {
+                log_record = log_record.decrypt(cipher)?;
+            }
         }
 
         // 构造结果并返回
-        Ok(ReadLogRecord {
+        let read_record = ReadLogRecord {
             record: log_record,
-            size: actual_header_size + key_size + value_size + 4,
+            size: header.record_size() as usize,
+        };
+        Ok((read_record, crc_valid))
+    }
+
+    // 只读取 offset 处记录的 header 和 key，不读取 value，也不校验 CRC
+    // 加密的数据文件需要读取完整的记录才能解密 key，只能使用 read_log_record
+    pub(crate) fn read_log_record_key(&self, offset: u64) -> Result<ReadLogRecord> {
+        let mut reader = self.io_manager.as_ref();
+        read_log_record_key(&mut reader, self.file_size(), offset)
+    }
+
+    // 只解析 offset 处记录的 header，返回记录在文件中的大小，不校验记录类型和 CRC
+    // 用于跳过类型无法识别的记录
+    pub(crate) fn log_record_size(&self, offset: u64) -> Result<u64> {
+        let mut reader = self.io_manager.as_ref();
+        Ok(read_log_record_header(&mut reader, self.file_size(), offset)?.record_size())
+    }
+
+    // 只解析 offset 处记录的 header，返回记录中 key 和 value 的位置，不读取 key 和 value
+    // 加密的记录中 value 的大小包括了 key 以及校验值，不是实际 value 的大小
+    pub(crate) fn read_record_layout(&self, offset: u64) -> Result<RecordLayout> {
+        let mut reader = self.io_manager.as_ref();
+        let header = read_log_record_header(&mut reader, self.file_size(), offset)?;
+        let rec_type = LogRecordType::from_u8(
+            header.type_byte & !(LOG_RECORD_TIMESTAMP_FLAG | LOG_RECORD_VALUE_POINTER_FLAG),
+        )?;
+        Ok(RecordLayout {
+            rec_type,
+            value_pointer: header.type_byte & LOG_RECORD_VALUE_POINTER_FLAG != 0,
+            header_size: header.header_size as u64,
+            key_size: header.key_size as u64,
+            value_size: header.value_size as u64,
         })
     }
 
+    // 从 offset 处读取原始的字节，不解析记录
+    pub(crate) fn read_raw(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        self.io_manager.read(buf, offset)
+    }
+
     /// 写 hint 索引到文件当中
     pub fn write_hint_record(&self, key: Vec<u8>, pos: LogRecordPos) -> Result<()> {
-        let hint_record = LogRecord {
+        let mut hint_record = LogRecord {
             key: key,
             value: pos.encode(),
             rec_type: LogRecordType::NORMAL,
+            timestamp: 0,
+            value_pointer: false,
         };
+        if self.is_encrypted() {
+            if let Some(cipher) = self.cipher.as_ref() {
+                hint_record = hint_record.encrypt(cipher)?;
+            }
+        }
 
         let enc_record = hint_record.encode();
         self.write(&enc_record)?;
@@ -167,6 +551,9 @@ impl DataFile {
     }
 
     pub fn write(&self, buf: &[u8]) -> Result<usize> {
+        if self.is_sealed() {
+            return Err(Errors::DataFileIsSealed);
+        }
         let n_bytes = self.io_manager.write(buf)?;
         // 更新 write_off 字段
         let mut write_off = self.wirte_off.write();
@@ -174,17 +561,329 @@ impl DataFile {
         Ok(n_bytes)
     }
 
+    /// 一次写入多段数据，写入的数据和依次调用 write 相同
+    pub fn write_vectored(&self, bufs: &[IoSlice]) -> Result<usize> {
+        if self.is_sealed() {
+            return Err(Errors::DataFileIsSealed);
+        }
+        let n_bytes = self.io_manager.write_vectored(bufs)?;
+        let mut write_off = self.wirte_off.write();
+        *write_off += n_bytes as u64;
+        Ok(n_bytes)
+    }
+
     pub fn sync(&self) -> Result<()> {
         self.io_manager.sync()
     }
 
-    pub fn set_io_manager(&mut self, dir_path: PathBuf, io_type: IOType) {
-        self.io_manager = new_io_manager(get_data_file_name(dir_path, self.get_file_id()), io_type);
+    /// 按照 size 为数据文件预先分配磁盘空间，不改变文件大小
+    pub fn preallocate(&self, size: u64) -> Result<()> {
+        self.io_manager.preallocate(size)
+    }
+
+    /// 使用新的 IO 类型重新打开数据文件，返回新的句柄，写偏移和原来的句柄共享
+    /// 原来的句柄仍然可以继续读取，正在读取的调用方不受影响
+    pub fn reopen(&self, dir_path: PathBuf, io_type: IOType) -> Result<DataFile> {
+        let io_manager = new_io_manager(get_data_file_name(dir_path, self.get_file_id()), io_type)?;
+        Ok(DataFile {
+            file_id: self.file_id.clone(),
+            wirte_off: self.wirte_off.clone(),
+            io_manager,
+            header: self.header,
+            cipher: self.cipher.clone(),
+            io_type,
+            sealed: AtomicBool::new(self.is_sealed()),
+            deferred_delete: self.deferred_delete.clone(),
+        })
+    }
+
+    /// 返回通过 FileCache 按需打开的新句柄，写偏移和原来的句柄共享，只能用于已经转换为旧数据文件的文件
+    /// 文件句柄只在读取时打开，同时打开的文件数量超过缓存的上限时会被关闭
+    pub fn cached(&self, dir_path: PathBuf, io_type: IOType, cache: &Arc<FileCache>) -> DataFile {
+        let file_name = get_data_file_name(dir_path, self.get_file_id());
+        DataFile {
+            file_id: self.file_id.clone(),
+            wirte_off: self.wirte_off.clone(),
+            io_manager: Box::new(CachedIO::new(file_name, io_type, self.file_size(), cache)),
+            header: self.header,
+            cipher: self.cipher.clone(),
+            io_type,
+            sealed: AtomicBool::new(true),
+            deferred_delete: self.deferred_delete.clone(),
+        }
+    }
+
+    /// 打开文件时使用的 IO 类型
+    pub fn io_type(&self) -> IOType {
+        self.io_type
+    }
+
+    /// 活跃文件写满之后转换为旧的数据文件，同一个句柄继续在旧的数据文件集合中使用，之后的写入返回错误
+    /// 调用之前需要先持久化文件中的数据
+    pub fn seal(&self) {
+        self.sealed.store(true, Ordering::SeqCst);
+    }
+
+    /// 数据文件已经从数据库中移除，共享同一个文件的所有句柄都释放之后删除 path 对应的文件
+    pub fn delete_on_release(&self, path: PathBuf) {
+        *self.deferred_delete.path.lock() = Some(path);
+    }
+
+    /// 是否已经转换为旧的数据文件
+    pub fn is_sealed(&self) -> bool {
+        self.sealed.load(Ordering::SeqCst)
     }
 
     pub fn file_size(&self) -> u64 {
         self.io_manager.size()
     }
+
+    /// 创建一个带有预读缓冲区的顺序读取器，用于从头到尾扫描整个文件
+    pub fn sequential_reader(&self) -> DataFileReader<'_> {
+        DataFileReader {
+            data_file: self,
+            reader: SequentialReader::new(self.io_manager.as_ref(), DEFAULT_READ_AHEAD_SIZE),
+            file_size: self.file_size(),
+        }
+    }
+}
+
+/// 顺序扫描数据文件的读取器，读取的数据先经过预读缓冲区，
+/// 逐条读取记录时不会为每条记录单独发起 IO
+/// 文件大小在创建时确定，之后追加写入的数据不会被读取到
+pub struct DataFileReader<'a> {
+    data_file: &'a DataFile,
+    reader: SequentialReader<'a>,
+    file_size: u64,
+}
+
+impl DataFileReader<'_> {
+    /// 和 DataFile::read_log_record 相同，根据 offset 读取一个 LogRecord
+    pub fn read_log_record(&mut self, offset: u64) -> Result<ReadLogRecord> {
+        let (header, buf) = self.data_file.read_log_record_buf(
+            &mut self.reader,
+            self.file_size,
+            offset,
+            max_log_record_header_size(),
+        )?;
+        match self.data_file.decode_log_record(header, buf)? {
+            (read_record, true) => Ok(read_record),
+            (_, false) => Err(Errors::InvaildLogRecordCrc),
+        }
+    }
+
+    // 和 DataFile::read_log_record_key 相同，只读取 header 和 key
+    pub(crate) fn read_log_record_key(&mut self, offset: u64) -> Result<ReadLogRecord> {
+        read_log_record_key(&mut self.reader, self.file_size, offset)
+    }
+
+    // 和 DataFile::log_record_size 相同，只解析 header 获取记录的大小
+    pub(crate) fn log_record_size(&mut self, offset: u64) -> Result<u64> {
+        Ok(read_log_record_header(&mut self.reader, self.file_size, offset)?.record_size())
+    }
+}
+
+// 读取记录时的数据来源，可以直接读取文件，也可以经过顺序读取器的预读缓冲区
+trait ReadAt {
+    fn read_at(&mut self, buf: &mut [u8], offset: u64) -> Result<usize>;
+}
+
+impl ReadAt for &(dyn fio::IOManager + '_) {
+    fn read_at(&mut self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        self.read(buf, offset)
+    }
+}
+
+impl ReadAt for SequentialReader<'_> {
+    fn read_at(&mut self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        self.read(buf, offset)
+    }
+}
+
+// 只读取 offset 处记录的 header 和 key，不读取 value，也不校验 CRC
+fn read_log_record_key<R: ReadAt>(
+    reader: &mut R,
+    file_size: u64,
+    offset: u64,
+) -> Result<ReadLogRecord> {
+    let header = read_log_record_header(reader, file_size, offset)?;
+    let rec_type = LogRecordType::from_u8(
+        header.type_byte & !(LOG_RECORD_TIMESTAMP_FLAG | LOG_RECORD_VALUE_POINTER_FLAG),
+    )?;
+
+    let mut key = vec![0; header.key_size];
+    reader.read_at(&mut key, offset + header.header_size as u64)?;
+
+    Ok(ReadLogRecord {
+        record: LogRecord {
+            key,
+            value: Default::default(),
+            rec_type,
+            timestamp: header.timestamp,
+            value_pointer: header.type_byte & LOG_RECORD_VALUE_POINTER_FLAG != 0,
+        },
+        size: header.record_size() as usize,
+    })
+}
+
+// 读取并解析 offset 处记录的 header，记录超出了文件末尾时返回 InvaildLogRecordCrc
+fn read_log_record_header<R: ReadAt>(
+    reader: &mut R,
+    file_size: u64,
+    offset: u64,
+) -> Result<LogRecordHeader> {
+    // 初始化 header 字节数组，文件末尾的记录可能比最大的 header 还要短
+    let header_size = (max_log_record_header_size() as u64).min(file_size.saturating_sub(offset));
+    let mut header_buf = BytesMut::zeroed(header_size as usize);
+    if header_buf.is_empty() {
+        return Err(Errors::ReadDataFileEof);
+    }
+
+    reader.read_at(&mut header_buf, offset)?;
+    parse_log_record_header(file_size, offset, &header_buf)
+}
+
+// 从 offset 处读取到的数据中解析记录的 header
+fn parse_log_record_header(
+    file_size: u64,
+    offset: u64,
+    mut header_buf: &[u8],
+) -> Result<LogRecordHeader> {
+    // 取出 type，在第一个字节，最高的两位分别标识是否带有写入时间以及 value 是否为指针
+    let type_byte = header_buf.get_u8();
+    let timestamp = match type_byte & LOG_RECORD_TIMESTAMP_FLAG != 0 {
+        true => match decode_varint(&mut header_buf) {
+            Ok(timestamp) => timestamp,
+            Err(_) => return Err(Errors::InvaildLogRecordCrc),
+        },
+        false => 0,
+    };
+
+    // 取出 key 和 value 的长度，长度字段无法解析说明数据已经损坏
+    let key_size = match decode_length_delimiter(&mut header_buf) {
+        Ok(size) => size,
+        Err(_) => return Err(Errors::InvaildLogRecordCrc),
+    };
+    let value_size = match decode_length_delimiter(&mut header_buf) {
+        Ok(size) => size,
+        Err(_) => return Err(Errors::InvaildLogRecordCrc),
+    };
+
+    // 如果 key 和 value 均为空，则说明读取到了文件末尾，直接返回
+    if key_size == 0 && value_size == 0 {
+        return Err(Errors::ReadDataFileEof);
+    }
+
+    // 获取实际的 header 大小
+    let timestamp_size = match timestamp {
+        0 => 0,
+        timestamp => encoded_len_varint(timestamp),
+    };
+    let header = LogRecordHeader {
+        type_byte,
+        timestamp,
+        key_size,
+        value_size,
+        header_size: length_delimiter_len(key_size)
+            + length_delimiter_len(value_size)
+            + timestamp_size
+            + 1,
+    };
+
+    // 记录超出了文件末尾，说明数据损坏或者没有完整写入
+    if offset.saturating_add(header.record_size()) > file_size {
+        return Err(Errors::InvaildLogRecordCrc);
+    }
+    Ok(header)
+}
+
+// 创建或打开带有头部的文件
+fn open_with_header(
+    filename: PathBuf,
+    file_id: u64,
+    io_type: IOType,
+    cipher: Option<Arc<Cipher>>,
+) -> Result<DataFile> {
+    let is_new_file = !data_file_exists(&filename);
+    // 初始化 IO manager
+    let io_manager = new_io_manager(filename, io_type)?;
+
+    // 新建的文件写入头部，已存在的文件读取并校验头部
+    let header = match is_new_file {
+        true => {
+            let flags = match cipher {
+                Some(_) => DATA_FILE_FLAG_ENCRYPTED,
+                None => 0,
+            };
+            let header = DataFileHeader::new(flags);
+            io_manager.write(&header.encode())?;
+            Some(header)
+        }
+        false => read_data_file_header(io_manager.as_ref(), file_id)?,
+    };
+
+    // 加密的文件必须提供密钥
+    if let Some(header) = header {
+        if header.flags & DATA_FILE_FLAG_ENCRYPTED != 0 && cipher.is_none() {
+            return Err(Errors::EncryptionKeyRequired);
+        }
+    }
+
+    let write_off = match header {
+        Some(_) => DATA_FILE_HEADER_SIZE,
+        None => 0,
+    };
+
+    Ok(DataFile {
+        file_id: Arc::new(RwLock::new(file_id)),
+        wirte_off: Arc::new(RwLock::new(write_off)),
+        io_manager,
+        header,
+        cipher,
+        io_type,
+        sealed: AtomicBool::new(false),
+        deferred_delete: Arc::default(),
+    })
+}
+
+// 读取并校验数据文件的头部，没有头部的旧数据文件返回 None
+fn read_data_file_header(
+    io_manager: &dyn fio::IOManager,
+    file_id: u64,
+) -> Result<Option<DataFileHeader>> {
+    let size = io_manager.size().min(DATA_FILE_HEADER_SIZE);
+    let mut buf = BytesMut::zeroed(size as usize);
+    if !buf.is_empty() {
+        io_manager.read(&mut buf, 0)?;
+    }
+    if !buf.starts_with(DATA_FILE_MAGIC) {
+        return Ok(None);
+    }
+
+    // 头部不完整或者校验失败
+    let header = match size == DATA_FILE_HEADER_SIZE {
+        true => DataFileHeader::decode(&buf),
+        false => None,
+    };
+    let header = match header {
+        Some(header) => header,
+        None => return Err(Errors::DataFileCorrupted { file_id, offset: 0 }),
+    };
+
+    if header.version > DATA_FILE_FORMAT_VERSION {
+        return Err(Errors::UnsupportedDataFileVersion {
+            file_id,
+            version: header.version,
+        });
+    }
+    if header.flags & !SUPPORTED_DATA_FILE_FLAGS != 0 {
+        return Err(Errors::UnsupportedDataFileFlags {
+            file_id,
+            flags: header.flags,
+        });
+    }
+
+    Ok(Some(header))
 }
 
 pub fn get_data_file_name(path: PathBuf, file_id: u64) -> PathBuf {
@@ -192,25 +891,48 @@ pub fn get_data_file_name(path: PathBuf, file_id: u64) -> PathBuf {
     path.join(name)
 }
 
+pub fn get_blob_file_name(path: PathBuf, file_id: u64) -> PathBuf {
+    let name = std::format!("{:09}", file_id) + BLOB_FILE_NAME_SUFFIX;
+    path.join(name)
+}
+
+pub fn get_bloom_file_name(path: PathBuf, file_id: u64) -> PathBuf {
+    let name = std::format!("{:09}", file_id) + BLOOM_FILE_NAME_SUFFIX;
+    path.join(name)
+}
+
+// 写入只包含一条记录的文件（例如事务序列号文件、标识 merge 完成的文件），
+// 通过临时文件加重命名替换旧的文件，崩溃时不会留下只写了一半的文件
+pub fn write_record_file(filename: PathBuf, record: &LogRecord, fsync_dir: bool) -> Result<()> {
+    util::file::write_file_atomically(&filename, &record.encode(), fsync_dir).map_err(|e| {
+        error!("failed to write file {:?}: {}", filename, e);
+        Errors::write_failed(&filename, e)
+    })
+}
+
 #[cfg(test)]
 mod tests {
+    use std::path::Path;
+
+    use proptest::prelude::*;
+
     use super::*;
 
     #[test]
     fn test_new_data_file() {
         let dir_path = std::env::temp_dir();
 
-        let data_file_res1 = DataFile::new(dir_path.clone(), 0, IOType::StandardFIO);
+        let data_file_res1 = DataFile::new(dir_path.clone(), 0, IOType::StandardFIO, None);
         assert!(data_file_res1.is_ok());
         let data_file1 = data_file_res1.unwrap();
         assert_eq!(data_file1.get_file_id(), 0);
 
-        let data_file_res2 = DataFile::new(dir_path.clone(), 0, IOType::StandardFIO);
+        let data_file_res2 = DataFile::new(dir_path.clone(), 0, IOType::StandardFIO, None);
         assert!(data_file_res2.is_ok());
         let data_file2 = data_file_res2.unwrap();
         assert_eq!(data_file2.get_file_id(), 0);
 
-        let data_file_res3 = DataFile::new(dir_path.clone(), 660, IOType::StandardFIO);
+        let data_file_res3 = DataFile::new(dir_path.clone(), 660, IOType::StandardFIO, None);
         assert!(data_file_res3.is_ok());
         let data_file3 = data_file_res3.unwrap();
         assert_eq!(data_file3.get_file_id(), 660);
@@ -219,7 +941,7 @@ mod tests {
     #[test]
     fn test_data_file_write() {
         let dir_path = std::env::temp_dir();
-        let data_file_res1 = DataFile::new(dir_path.clone(), 100, IOType::StandardFIO);
+        let data_file_res1 = DataFile::new(dir_path.clone(), 100, IOType::StandardFIO, None);
         assert!(data_file_res1.is_ok());
         let data_file1 = data_file_res1.unwrap();
         assert_eq!(data_file1.get_file_id(), 100);
@@ -237,10 +959,37 @@ mod tests {
         assert_eq!(write_res3.unwrap(), 5 as usize);
     }
 
+    #[test]
+    fn test_data_file_seal() {
+        let dir_path = std::env::temp_dir();
+        let filename = get_data_file_name(dir_path.clone(), 300);
+        let _ = std::fs::remove_file(&filename);
+        let data_file = DataFile::new(dir_path.clone(), 300, IOType::StandardFIO, None).unwrap();
+        assert!(data_file.write("aaa".as_bytes()).is_ok());
+        assert!(!data_file.is_sealed());
+
+        // 转换为旧的数据文件之后不能再写入，写偏移保持不变，仍然可以读取
+        data_file.seal();
+        assert!(data_file.is_sealed());
+        assert_eq!(
+            data_file.write("bbb".as_bytes()).err().unwrap(),
+            Errors::DataFileIsSealed
+        );
+        assert_eq!(data_file.get_write_off(), DATA_FILE_HEADER_SIZE + 3);
+        assert_eq!(data_file.file_size(), DATA_FILE_HEADER_SIZE + 3);
+
+        // 重新打开的句柄共享写偏移，并且同样是只读的
+        let reopened = data_file.reopen(dir_path, IOType::StandardFIO).unwrap();
+        assert!(reopened.is_sealed());
+        assert_eq!(reopened.get_write_off(), data_file.get_write_off());
+
+        std::fs::remove_file(filename).expect("failed to remove file");
+    }
+
     #[test]
     fn test_data_file_sync() {
         let dir_path = std::env::temp_dir();
-        let data_file_res1 = DataFile::new(dir_path.clone(), 200, IOType::StandardFIO);
+        let data_file_res1 = DataFile::new(dir_path.clone(), 200, IOType::StandardFIO, None);
         assert!(data_file_res1.is_ok());
         let data_file1 = data_file_res1.unwrap();
         assert_eq!(data_file1.get_file_id(), 200);
@@ -252,7 +1001,7 @@ mod tests {
     #[test]
     fn test_data_file_read_log_record() {
         let dir_path = std::env::temp_dir();
-        let data_file_res1 = DataFile::new(dir_path.clone(), 200, IOType::StandardFIO);
+        let data_file_res1 = DataFile::new(dir_path.clone(), 200, IOType::StandardFIO, None);
         assert!(data_file_res1.is_ok());
         let data_file1 = data_file_res1.unwrap();
         assert_eq!(data_file1.get_file_id(), 200);
@@ -262,12 +1011,15 @@ mod tests {
             key: "name".as_bytes().to_vec(),
             value: "bitcask-rs-kv".as_bytes().to_vec(),
             rec_type: LogRecordType::NORMAL,
+            timestamp: 0,
+            value_pointer: false,
         };
         let write_res1 = data_file1.write(&rec1.encode());
         assert!(write_res1.is_ok());
 
-        // 从起始位置开始读取
-        let read_res1 = data_file1.read_log_record(0);
+        // 从头部之后的起始位置开始读取
+        let start = data_file1.get_header_size();
+        let read_res1 = data_file1.read_log_record(start);
         assert!(read_res1.is_ok());
         let read_enc1 = read_res1.ok().unwrap().record;
         assert_eq!(rec1.key, read_enc1.key);
@@ -278,12 +1030,14 @@ mod tests {
             key: "name".as_bytes().to_vec(),
             value: "new-value".as_bytes().to_vec(),
             rec_type: LogRecordType::NORMAL,
+            timestamp: 0,
+            value_pointer: false,
         };
         let write_res2 = data_file1.write(&rec2.encode());
         assert!(write_res2.is_ok());
 
         // 从新的位置开始读取
-        let read_res2 = data_file1.read_log_record(24);
+        let read_res2 = data_file1.read_log_record(start + 24);
         assert!(read_res2.is_ok());
         let read_enc2 = read_res2.ok().unwrap().record;
         assert_eq!(rec2.key, read_enc2.key);
@@ -295,15 +1049,409 @@ mod tests {
             key: "name".as_bytes().to_vec(),
             value: Default::default(),
             rec_type: LogRecordType::DELETE,
+            timestamp: 0,
+            value_pointer: false,
         };
         let write_res3 = data_file1.write(&rec3.encode());
         assert!(write_res3.is_ok());
 
-        let read_res3 = data_file1.read_log_record(44);
+        let read_res3 = data_file1.read_log_record(start + 44);
         assert!(read_res3.is_ok());
         let read_enc3 = read_res3.ok().unwrap().record;
         assert_eq!(rec3.key, read_enc3.key);
         assert_eq!(rec3.value, read_enc3.value);
         assert_eq!(rec3.rec_type, read_enc3.rec_type);
     }
+
+    #[test]
+    fn test_data_file_read_log_record_chunk() {
+        let dir_path = PathBuf::from("/tmp/bitcask-rs-data-file-read-chunk");
+        std::fs::create_dir_all(dir_path.clone()).expect("failed to create dir");
+        let data_file = DataFile::new(dir_path.clone(), 0, IOType::StandardFIO, None).unwrap();
+
+        // 小记录、超过默认读取大小的记录以及文件末尾的小记录
+        let records: Vec<LogRecord> = [10, 10 * 1024, 20]
+            .iter()
+            .map(|value_size| LogRecord {
+                key: "name".as_bytes().to_vec(),
+                value: vec![b'v'; *value_size],
+                rec_type: LogRecordType::NORMAL,
+                timestamp: 0,
+                value_pointer: false,
+            })
+            .collect();
+        let mut offsets = Vec::new();
+        for record in records.iter() {
+            offsets.push(data_file.get_write_off());
+            assert!(data_file.write(&record.encode()).is_ok());
+        }
+
+        // 不同的读取大小读取到的记录相同，读取大小小于 header 时按照 header 的大小读取
+        for chunk_size in [0, 16, DEFAULT_READ_CHUNK_SIZE, 1024 * 1024] {
+            for (record, offset) in records.iter().zip(offsets.iter()) {
+                let read_record = data_file
+                    .read_log_record_with_chunk(*offset, chunk_size)
+                    .unwrap();
+                assert_eq!(read_record.record.key, record.key);
+                assert_eq!(read_record.record.value, record.value);
+                assert_eq!(read_record.size, record.encode().len());
+            }
+        }
+        assert_eq!(
+            data_file
+                .read_log_record_with_chunk(data_file.get_write_off(), 16)
+                .err()
+                .unwrap(),
+            Errors::ReadDataFileEof
+        );
+
+        std::fs::remove_dir_all(dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_data_file_sequential_reader() {
+        let dir_path = PathBuf::from("/tmp/bitcask-rs-data-file-sequential-reader");
+        std::fs::create_dir_all(dir_path.clone()).expect("failed to create dir");
+        let data_file = DataFile::new(dir_path.clone(), 0, IOType::StandardFIO, None).unwrap();
+
+        // 大量小记录跨越预读的边界，以及超过预读大小的记录
+        let mut records: Vec<LogRecord> = (0..10000)
+            .map(|i| LogRecord {
+                key: format!("bitcask-rs-key-{:09}", i).into_bytes(),
+                value: vec![b'v'; i % 100],
+                rec_type: LogRecordType::NORMAL,
+                timestamp: 0,
+                value_pointer: false,
+            })
+            .collect();
+        records.insert(
+            5000,
+            LogRecord {
+                key: "large".as_bytes().to_vec(),
+                value: vec![b'v'; DEFAULT_READ_AHEAD_SIZE + 10],
+                rec_type: LogRecordType::NORMAL,
+                timestamp: 0,
+                value_pointer: false,
+            },
+        );
+        for record in records.iter() {
+            assert!(data_file.write(&record.encode()).is_ok());
+        }
+
+        let mut reader = data_file.sequential_reader();
+        let mut offset = data_file.get_header_size();
+        for record in records.iter() {
+            let read_record = reader.read_log_record(offset).unwrap();
+            assert_eq!(read_record.record.key, record.key);
+            assert_eq!(read_record.record.value, record.value);
+
+            let key_record = reader.read_log_record_key(offset).unwrap();
+            assert_eq!(key_record.record.key, record.key);
+            assert_eq!(key_record.size, read_record.size);
+            assert_eq!(
+                reader.log_record_size(offset).unwrap(),
+                read_record.size as u64
+            );
+            offset += read_record.size as u64;
+        }
+        assert_eq!(
+            reader.read_log_record(offset).err().unwrap(),
+            Errors::ReadDataFileEof
+        );
+
+        // 创建读取器之后写入的数据不会被读取到
+        assert!(data_file.write(&records[0].encode()).is_ok());
+        assert_eq!(
+            reader.read_log_record(offset).err().unwrap(),
+            Errors::ReadDataFileEof
+        );
+
+        std::fs::remove_dir_all(dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_data_file_read_timestamp() {
+        let dir_path = std::env::temp_dir().join("bitcask-rs-data-file-timestamp");
+        std::fs::create_dir_all(dir_path.clone()).expect("failed to create dir");
+        let data_file = DataFile::new(dir_path.clone(), 0, IOType::StandardFIO, None).unwrap();
+
+        // 带有写入时间的记录和没有写入时间的记录可以混合存放
+        let rec1 = LogRecord {
+            key: "name".as_bytes().to_vec(),
+            value: "bitcask-rs-kv".as_bytes().to_vec(),
+            rec_type: LogRecordType::NORMAL,
+            timestamp: 1700000000000,
+            value_pointer: false,
+        };
+        let enc1 = rec1.encode();
+        data_file.write(&enc1).unwrap();
+        let rec2 = LogRecord {
+            key: "name".as_bytes().to_vec(),
+            value: Default::default(),
+            rec_type: LogRecordType::DELETE,
+            timestamp: 0,
+            value_pointer: false,
+        };
+        data_file.write(&rec2.encode()).unwrap();
+
+        let start = data_file.get_header_size();
+        let read_res1 = data_file.read_log_record(start).unwrap();
+        assert_eq!(read_res1.size, enc1.len());
+        assert_eq!(read_res1.record.value, rec1.value);
+        assert_eq!(read_res1.record.rec_type, LogRecordType::NORMAL);
+        assert_eq!(read_res1.record.timestamp, rec1.timestamp);
+
+        let read_res2 = data_file
+            .read_log_record(start + enc1.len() as u64)
+            .unwrap();
+        assert_eq!(read_res2.record.rec_type, LogRecordType::DELETE);
+        assert_eq!(read_res2.record.timestamp, 0);
+
+        std::fs::remove_dir_all(dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_data_file_header() {
+        let dir_path = std::env::temp_dir().join("bitcask-rs-data-file-header");
+        std::fs::create_dir_all(dir_path.clone()).expect("failed to create dir");
+
+        // 新建的数据文件写入头部
+        let data_file1 = DataFile::new(dir_path.clone(), 0, IOType::StandardFIO, None).unwrap();
+        let header = data_file1.header;
+        assert!(header.is_some());
+        assert_eq!(header.unwrap().version, DATA_FILE_FORMAT_VERSION);
+        assert_eq!(header.unwrap().flags, 0);
+        assert_eq!(data_file1.get_header_size(), DATA_FILE_HEADER_SIZE);
+        assert_eq!(data_file1.get_write_off(), DATA_FILE_HEADER_SIZE);
+
+        let rec = LogRecord {
+            key: "name".as_bytes().to_vec(),
+            value: "bitcask-rs-kv".as_bytes().to_vec(),
+            rec_type: LogRecordType::NORMAL,
+            timestamp: 0,
+            value_pointer: false,
+        };
+        data_file1.write(&rec.encode()).unwrap();
+        data_file1.sync().unwrap();
+
+        // 重新打开之后校验头部
+        let data_file2 = DataFile::new(dir_path.clone(), 0, IOType::StandardFIO, None).unwrap();
+        assert_eq!(data_file2.header, header);
+        let read_res = data_file2.read_log_record(data_file2.get_header_size());
+        assert_eq!(read_res.unwrap().record.value, rec.value);
+
+        let data_file3 = DataFile::new(dir_path.clone(), 0, IOType::MemoryMap, None).unwrap();
+        assert_eq!(data_file3.header, header);
+
+        std::fs::remove_dir_all(dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_data_file_without_header() {
+        let dir_path = std::env::temp_dir().join("bitcask-rs-data-file-without-header");
+        std::fs::create_dir_all(dir_path.clone()).expect("failed to create dir");
+
+        // 没有头部的旧数据文件，记录从 0 开始
+        let rec = LogRecord {
+            key: "name".as_bytes().to_vec(),
+            value: "bitcask-rs-kv".as_bytes().to_vec(),
+            rec_type: LogRecordType::NORMAL,
+            timestamp: 0,
+            value_pointer: false,
+        };
+        std::fs::write(get_data_file_name(dir_path.clone(), 0), rec.encode()).unwrap();
+
+        let data_file = DataFile::new(dir_path.clone(), 0, IOType::StandardFIO, None).unwrap();
+        assert!(data_file.header.is_none());
+        assert_eq!(data_file.get_header_size(), 0);
+        let read_res = data_file.read_log_record(0);
+        assert_eq!(read_res.unwrap().record.value, rec.value);
+
+        std::fs::remove_dir_all(dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_data_file_invalid_header() {
+        let dir_path = std::env::temp_dir().join("bitcask-rs-data-file-invalid-header");
+        std::fs::create_dir_all(dir_path.clone()).expect("failed to create dir");
+
+        // 未知的格式版本
+        let mut header = DataFileHeader::new(0);
+        header.version = DATA_FILE_FORMAT_VERSION + 1;
+        std::fs::write(get_data_file_name(dir_path.clone(), 1), header.encode()).unwrap();
+        let res1 = DataFile::new(dir_path.clone(), 1, IOType::StandardFIO, None);
+        assert_eq!(
+            res1.err().unwrap(),
+            Errors::UnsupportedDataFileVersion {
+                file_id: 1,
+                version: DATA_FILE_FORMAT_VERSION + 1
+            }
+        );
+
+        // 未知的标志位
+        let header = DataFileHeader::new(DATA_FILE_FLAG_COMPRESSED);
+        std::fs::write(get_data_file_name(dir_path.clone(), 2), header.encode()).unwrap();
+        let res2 = DataFile::new(dir_path.clone(), 2, IOType::StandardFIO, None);
+        assert_eq!(
+            res2.err().unwrap(),
+            Errors::UnsupportedDataFileFlags {
+                file_id: 2,
+                flags: DATA_FILE_FLAG_COMPRESSED
+            }
+        );
+
+        // 头部校验失败
+        let mut enc = DataFileHeader::new(0).encode();
+        enc[8] ^= 0xff;
+        std::fs::write(get_data_file_name(dir_path.clone(), 3), enc).unwrap();
+        let res3 = DataFile::new(dir_path.clone(), 3, IOType::StandardFIO, None);
+        assert_eq!(
+            res3.err().unwrap(),
+            Errors::DataFileCorrupted {
+                file_id: 3,
+                offset: 0
+            }
+        );
+
+        std::fs::remove_dir_all(dir_path).expect("failed to remove path");
+    }
+
+    // 随机生成的 LogRecord：key 不为空，写入时间为 0 时不编码写入时间
+    fn log_record_strategy() -> impl Strategy<Value = LogRecord> {
+        (
+            prop::collection::vec(any::<u8>(), 1..64),
+            prop::collection::vec(any::<u8>(), 0..256),
+            prop_oneof![
+                Just(LogRecordType::NORMAL),
+                Just(LogRecordType::DELETE),
+                Just(LogRecordType::TxnFinished),
+            ],
+            prop_oneof![Just(0u64), any::<u64>()],
+            any::<bool>(),
+        )
+            .prop_map(
+                |(key, value, rec_type, timestamp, value_pointer)| LogRecord {
+                    key,
+                    value,
+                    rec_type,
+                    timestamp,
+                    value_pointer,
+                },
+            )
+    }
+
+    // 将记录依次写入新的数据文件，返回每条记录的起始位置以及数据文件的路径
+    fn write_log_records(dir_path: &Path, records: &[LogRecord]) -> (Vec<u64>, PathBuf) {
+        if dir_path.is_dir() {
+            std::fs::remove_dir_all(dir_path).unwrap();
+        }
+        std::fs::create_dir_all(dir_path).unwrap();
+        let data_file =
+            DataFile::new(dir_path.to_path_buf(), 0, IOType::StandardFIO, None).unwrap();
+        let mut offsets = Vec::with_capacity(records.len());
+        for record in records {
+            offsets.push(data_file.get_write_off());
+            let enc = record.encode();
+            data_file.write(&enc).unwrap();
+        }
+        (offsets, get_data_file_name(dir_path.to_path_buf(), 0))
+    }
+
+    fn assert_record_eq(read: &LogRecord, expected: &LogRecord) {
+        assert_eq!(read.key, expected.key);
+        assert_eq!(read.value, expected.value);
+        assert_eq!(read.rec_type, expected.rec_type);
+        assert_eq!(read.timestamp, expected.timestamp);
+        assert_eq!(read.value_pointer, expected.value_pointer);
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        // 任意的记录编码之后都可以按顺序完整读出，最后读到文件末尾
+        #[test]
+        fn prop_log_record_round_trip(
+            records in prop::collection::vec(log_record_strategy(), 1..16)
+        ) {
+            let dir_path = std::env::temp_dir().join("bitcask-rs-prop-round-trip");
+            let (offsets, _) = write_log_records(&dir_path, &records);
+
+            let data_file = DataFile::new(dir_path.clone(), 0, IOType::StandardFIO, None).unwrap();
+            let mut offset = data_file.get_header_size();
+            for (record, expected_offset) in records.iter().zip(offsets) {
+                prop_assert_eq!(offset, expected_offset);
+                let read = data_file.read_log_record(offset).unwrap();
+                prop_assert_eq!(read.size, record.encode().len());
+                assert_record_eq(&read.record, record);
+                offset += read.size as u64;
+            }
+            prop_assert_eq!(
+                data_file.read_log_record(offset).err().unwrap(),
+                Errors::ReadDataFileEof
+            );
+
+            std::fs::remove_dir_all(dir_path).unwrap();
+        }
+
+        // 截断或者翻转任意一位之后读取不会 panic，只会返回类型化的错误，
+        // 损坏位置之前的记录仍然可以正确读出
+        #[test]
+        fn prop_corrupted_log_record(
+            records in prop::collection::vec(log_record_strategy(), 1..8),
+            corrupt_at in any::<u64>(),
+            bit in 0u8..8,
+            truncate in any::<bool>(),
+        ) {
+            let dir_path = std::env::temp_dir().join("bitcask-rs-prop-corrupted");
+            let (offsets, file_path) = write_log_records(&dir_path, &records);
+
+            let mut content = std::fs::read(&file_path).unwrap();
+            let header_size = offsets[0];
+            let corrupt_at = header_size + corrupt_at % (content.len() as u64 - header_size);
+            match truncate {
+                true => content.truncate(corrupt_at as usize),
+                false => content[corrupt_at as usize] ^= 1 << bit,
+            }
+            std::fs::write(&file_path, &content).unwrap();
+
+            let data_file = DataFile::new(dir_path.clone(), 0, IOType::StandardFIO, None).unwrap();
+            let mut offset = header_size;
+            let mut index = 0;
+            loop {
+                match data_file.read_log_record(offset) {
+                    Ok(read) => {
+                        // 损坏位置之前的记录和写入的完全相同
+                        let end = offset + read.size as u64;
+                        if end <= corrupt_at && index < records.len() {
+                            assert_record_eq(&read.record, &records[index]);
+                        }
+                        offset = end;
+                        index += 1;
+                    }
+                    Err(e) => {
+                        prop_assert!(
+                            matches!(
+                                e,
+                                Errors::InvaildLogRecordCrc
+                                    | Errors::InvalidRecordType(_)
+                                    | Errors::ReadDataFileEof
+                            ),
+                            "unexpected error: {}",
+                            e
+                        );
+                        break;
+                    }
+                }
+            }
+            // 完整的记录都在损坏位置之前读出
+            let intact = offsets
+                .iter()
+                .zip(records.iter())
+                .filter(|(start, record)| **start + record.encode().len() as u64 <= corrupt_at)
+                .count();
+            prop_assert!(index >= intact);
+
+            std::fs::remove_dir_all(dir_path).unwrap();
+        }
+    }
 }
@@ -1,10 +1,20 @@
-use core::panic;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use bytes::{BufMut, BytesMut};
 use prost::{
-    encode_length_delimiter, encoding::decode_varint, encoding::encode_varint, length_delimiter_len,
+    decode_length_delimiter, encode_length_delimiter, encoding::decode_varint,
+    encoding::encode_varint, encoding::encoded_len_varint, length_delimiter_len,
 };
 
+use crate::errors::{Errors, Result};
+
+use super::cipher::Cipher;
+
+/// 记录类型字节的最高位，标识 header 中带有写入时间戳，没有时间戳的记录和旧版本的格式完全一致
+pub const LOG_RECORD_TIMESTAMP_FLAG: u8 = 0x80;
+/// 记录类型字节的次高位，标识 value 是指向 blob 文件中实际数据的指针
+pub const LOG_RECORD_VALUE_POINTER_FLAG: u8 = 0x40;
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum LogRecordType {
     // 正常 put 的数据
@@ -18,12 +28,13 @@ pub enum LogRecordType {
 }
 
 impl LogRecordType {
-    pub fn from_u8(v: u8) -> Self {
+    // 无法识别的类型说明数据已经损坏，返回 InvalidRecordType
+    pub fn from_u8(v: u8) -> Result<Self> {
         match v {
-            1 => LogRecordType::NORMAL,
-            2 => LogRecordType::DELETE,
-            3 => LogRecordType::TxnFinished,
-            _ => panic!("unknown log record type"),
+            1 => Ok(LogRecordType::NORMAL),
+            2 => Ok(LogRecordType::DELETE),
+            3 => Ok(LogRecordType::TxnFinished),
+            _ => Err(Errors::InvalidRecordType(v)),
         }
     }
 }
@@ -41,15 +52,20 @@ pub struct LogRecord {
     pub(crate) key: Vec<u8>,
     pub(crate) value: Vec<u8>,
     pub(crate) rec_type: LogRecordType,
+    pub(crate) timestamp: u64, // 写入时间，单位毫秒，为 0 表示没有记录写入时间
+    pub(crate) value_pointer: bool, // value 是否为指向 blob 文件中实际数据的位置信息
 }
 
 impl LogRecord {
     /// 对 LogRecord 进行编码，返回字节数组及长度
     ///
-    /// +--------------+--------------+------------+--------------+--------------+--------------+
-    /// |   type 类型   |   key size   | value size |    key       |  value       | crc 校验值    |
-    /// +--------------+--------------+------------+--------------+--------------+--------------+
-    ///      1字节           变长(最大5)   变长(最大5)   变长             变长             4字节
+    /// +--------------+--------------+--------------+------------+--------------+--------------+--------------+
+    /// |   type 类型   |  timestamp   |   key size   | value size |    key       |  value       | crc 校验值    |
+    /// +--------------+--------------+--------------+------------+--------------+--------------+--------------+
+    ///      1字节        变长(最大10)     变长(最大5)   变长(最大5)   变长             变长             4字节
+    ///
+    /// timestamp 不为 0 时 type 的最高位为 1，并写入 timestamp；否则不写入 timestamp
+    /// value 为指向 blob 文件的指针时 type 的次高位为 1
     ///
     pub fn encode(&self) -> Vec<u8> {
         let (enc_buf, _) = self.encode_and_get_crc();
@@ -66,8 +82,8 @@ impl LogRecord {
         let mut buf = BytesMut::new();
         buf.reserve(self.encoded_length());
 
-        // 第一个字节存放 Type 类型
-        buf.put_u8(self.rec_type as u8);
+        // 第一个字节存放 Type 类型，有写入时间时紧跟着存放时间戳
+        self.encode_type_and_timestamp(&mut buf);
 
         // 再存储 key 和 value 的长度
         encode_length_delimiter(self.key.len(), &mut buf).unwrap();
@@ -87,8 +103,77 @@ impl LogRecord {
         (buf.to_vec(), crc)
     }
 
+    /// 加密记录的 key 和 value，加密之后记录的 key 为随机数，value 为密文，记录类型和写入时间不加密
+    /// 记录类型（包含标志位）和写入时间作为附加数据参与校验，被篡改时无法解密
+    ///
+    /// +--------------+--------------+---------------------------------------------+
+    /// |   type 类型   |  key(nonce)  |  value(密文)                                 |
+    /// +--------------+--------------+---------------------------------------------+
+    ///                     12字节        加密(key size + key + value) + 16字节校验值
+    ///
+    pub fn encrypt(&self, cipher: &Cipher) -> Result<LogRecord> {
+        let mut buf = BytesMut::new();
+        encode_length_delimiter(self.key.len(), &mut buf).unwrap();
+        buf.extend_from_slice(&self.key);
+        buf.extend_from_slice(&self.value);
+
+        let (nonce, ciphertext) = cipher.encrypt(&buf, &self.encrypt_aad())?;
+        Ok(LogRecord {
+            key: nonce,
+            value: ciphertext,
+            rec_type: self.rec_type,
+            timestamp: self.timestamp,
+            value_pointer: self.value_pointer,
+        })
+    }
+
+    /// 解密经过 encrypt 加密的记录
+    pub fn decrypt(self, cipher: &Cipher) -> Result<LogRecord> {
+        let plaintext = cipher.decrypt(&self.key, &self.value, &self.encrypt_aad())?;
+
+        let mut buf = plaintext.as_slice();
+        let key_size = match decode_length_delimiter(&mut buf) {
+            Ok(size) if size <= buf.len() => size,
+            _ => return Err(Errors::FailedToDecryptLogRecord),
+        };
+        Ok(LogRecord {
+            key: buf[..key_size].to_vec(),
+            value: buf[key_size..].to_vec(),
+            rec_type: self.rec_type,
+            timestamp: self.timestamp,
+            value_pointer: self.value_pointer,
+        })
+    }
+
+    // 编码记录的第一个字节 type，以及紧跟着的写入时间
+    fn encode_type_and_timestamp(&self, buf: &mut BytesMut) {
+        let mut type_byte = self.rec_type as u8;
+        if self.value_pointer {
+            type_byte |= LOG_RECORD_VALUE_POINTER_FLAG;
+        }
+        if self.timestamp > 0 {
+            buf.put_u8(type_byte | LOG_RECORD_TIMESTAMP_FLAG);
+            encode_varint(self.timestamp, buf);
+        } else {
+            buf.put_u8(type_byte);
+        }
+    }
+
+    // 加密时的附加数据，和记录头部中的 type 以及写入时间相同
+    // 没有写入时间和 blob 指针的记录只有记录类型，和之前版本加密的数据兼容
+    fn encrypt_aad(&self) -> Vec<u8> {
+        let mut buf = BytesMut::new();
+        self.encode_type_and_timestamp(&mut buf);
+        buf.to_vec()
+    }
+
     fn encoded_length(&self) -> usize {
+        let timestamp_len = match self.timestamp {
+            0 => 0,
+            timestamp => encoded_len_varint(timestamp),
+        };
         std::mem::size_of::<u8>()
+            + timestamp_len
             + length_delimiter_len(self.key.len())
             + length_delimiter_len(self.value.len())
             + self.key.len()
@@ -98,7 +183,7 @@ impl LogRecord {
 }
 
 /// 数据位置索引信息，描述数据存储到了哪个位置
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct LogRecordPos {
     pub(crate) file_id: u64,
     pub(crate) offset: u64,
@@ -115,31 +200,20 @@ impl LogRecordPos {
     }
 }
 
-/// 解码 LogRecordPos
-pub fn decode_log_record_pos(pos: Vec<u8>) -> LogRecordPos {
+/// 解码 LogRecordPos，编码损坏时返回 InvaildLogRecordPos
+pub fn decode_log_record_pos(pos: Vec<u8>) -> Result<LogRecordPos> {
     let mut buf = BytesMut::new();
     buf.put_slice(&pos);
 
-    let fid = match decode_varint(&mut buf) {
-        Ok(fid) => fid,
-        Err(e) => panic!("decode log record pos fid err: {}", e),
-    };
-
-    let offset = match decode_varint(&mut buf) {
-        Ok(offset) => offset,
-        Err(e) => panic!("decode log record pos offset err: {}", e),
-    };
-
-    let size = match decode_varint(&mut buf) {
-        Ok(size) => size,
-        Err(e) => panic!("decode log record pos size err: {}", e),
-    };
-
-    LogRecordPos {
-        file_id: fid ,
-        offset: offset,
-        size: size,
-    }
+    let mut decode = || decode_varint(&mut buf).map_err(|_| Errors::InvaildLogRecordPos);
+    let file_id = decode()?;
+    let offset = decode()?;
+    let size = decode()?;
+    Ok(LogRecordPos {
+        file_id,
+        offset,
+        size,
+    })
 }
 
 /// 从数据文件中读取的 log_record 信息，包含其 size
@@ -150,8 +224,19 @@ pub struct ReadLogRecord {
 }
 
 /// 获取 LogRecord header 部分的最大长度
+/// key 和 value 的长度均不超过 u32::MAX，编码之后最多 5 个字节，时间戳编码之后最多 10 个字节
 pub fn max_log_record_header_size() -> usize {
-    std::mem::size_of::<u8>() + length_delimiter_len(std::mem::size_of::<u32>()) * 2
+    std::mem::size_of::<u8>()
+        + encoded_len_varint(u64::MAX)
+        + length_delimiter_len(u32::MAX as usize) * 2
+}
+
+/// 当前时间，单位毫秒，作为记录的写入时间
+pub(crate) fn current_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
 }
 
 #[cfg(test)]
@@ -165,6 +250,8 @@ mod tests {
             key: "name".as_bytes().to_vec(),
             value: "bitcask-rs".as_bytes().to_vec(),
             rec_type: LogRecordType::NORMAL,
+            timestamp: 0,
+            value_pointer: false,
         };
         let enc1 = rec1.encode();
         assert!(enc1.len() > 5);
@@ -175,6 +262,8 @@ mod tests {
             key: "name".as_bytes().to_vec(),
             value: Default::default(),
             rec_type: LogRecordType::NORMAL,
+            timestamp: 0,
+            value_pointer: false,
         };
         let enc2 = rec2.encode();
         assert!(enc2.len() > 5);
@@ -185,9 +274,137 @@ mod tests {
             key: "name".as_bytes().to_vec(),
             value: "bitcask-rs".as_bytes().to_vec(),
             rec_type: LogRecordType::DELETE,
+            timestamp: 0,
+            value_pointer: false,
         };
         let enc3 = rec3.encode();
         assert!(enc3.len() > 5);
         assert_eq!(1867197446, rec3.get_crc());
     }
+
+    #[test]
+    fn test_log_record_type_from_u8() {
+        assert_eq!(LogRecordType::from_u8(1).unwrap(), LogRecordType::NORMAL);
+        assert_eq!(LogRecordType::from_u8(2).unwrap(), LogRecordType::DELETE);
+        assert_eq!(
+            LogRecordType::from_u8(3).unwrap(),
+            LogRecordType::TxnFinished
+        );
+        for v in [0, 4, 0x3f, 0xff] {
+            assert_eq!(
+                LogRecordType::from_u8(v).err().unwrap(),
+                Errors::InvalidRecordType(v)
+            );
+        }
+    }
+
+    #[test]
+    fn test_max_log_record_header_size() {
+        // key 和 value 的长度都需要多个字节编码的情况
+        let rec = LogRecord {
+            key: vec![1; 300],
+            value: vec![2; 70000],
+            rec_type: LogRecordType::NORMAL,
+            timestamp: 0,
+            value_pointer: false,
+        };
+        let enc = rec.encode();
+        let header_size = enc.len() - rec.key.len() - rec.value.len() - 4;
+        assert_eq!(header_size, 1 + 2 + 3);
+        assert!(header_size <= max_log_record_header_size());
+
+        assert_eq!(
+            max_log_record_header_size(),
+            1 + 10 + length_delimiter_len(u32::MAX as usize) * 2
+        );
+
+        // 带有写入时间的记录
+        let rec = LogRecord {
+            key: vec![1; 300],
+            value: vec![2; 70000],
+            rec_type: LogRecordType::NORMAL,
+            timestamp: u64::MAX,
+            value_pointer: false,
+        };
+        let enc = rec.encode();
+        let header_size = enc.len() - rec.key.len() - rec.value.len() - 4;
+        assert_eq!(header_size, 1 + 10 + 2 + 3);
+        assert!(header_size <= max_log_record_header_size());
+    }
+
+    #[test]
+    fn test_log_record_encode_timestamp() {
+        let mut rec = LogRecord {
+            key: "name".as_bytes().to_vec(),
+            value: "bitcask-rs".as_bytes().to_vec(),
+            rec_type: LogRecordType::DELETE,
+            timestamp: 0,
+            value_pointer: false,
+        };
+        // 没有写入时间时和旧版本的格式一致
+        let enc1 = rec.encode();
+        assert_eq!(enc1[0], LogRecordType::DELETE as u8);
+        assert_eq!(1867197446, rec.get_crc());
+
+        rec.timestamp = 1700000000000;
+        let enc2 = rec.encode();
+        assert_eq!(
+            enc2[0],
+            LogRecordType::DELETE as u8 | LOG_RECORD_TIMESTAMP_FLAG
+        );
+        assert_eq!(enc2.len(), enc1.len() + encoded_len_varint(rec.timestamp));
+        assert_eq!(enc2.len(), rec.encoded_length());
+        assert_ne!(1867197446, rec.get_crc());
+    }
+
+    #[test]
+    fn test_log_record_encrypt() {
+        let cipher = Cipher::new(&[1; 32]).unwrap();
+        let rec = LogRecord {
+            key: "name".as_bytes().to_vec(),
+            value: "bitcask-rs".as_bytes().to_vec(),
+            rec_type: LogRecordType::NORMAL,
+            timestamp: 0,
+            value_pointer: false,
+        };
+
+        let enc_rec = rec.encrypt(&cipher).unwrap();
+        assert_eq!(enc_rec.rec_type, LogRecordType::NORMAL);
+        assert_ne!(enc_rec.key, rec.key);
+        let dec_rec = enc_rec.decrypt(&cipher).unwrap();
+        assert_eq!(dec_rec.key, rec.key);
+        assert_eq!(dec_rec.value, rec.value);
+
+        // 记录类型被篡改
+        let mut enc_rec = rec.encrypt(&cipher).unwrap();
+        enc_rec.rec_type = LogRecordType::DELETE;
+        assert!(enc_rec.decrypt(&cipher).is_err());
+
+        // 写入时间和 blob 指针标志位被篡改
+        let rec = LogRecord {
+            timestamp: 1700000000000,
+            ..rec
+        };
+        let mut enc_rec = rec.encrypt(&cipher).unwrap();
+        enc_rec.timestamp += 1;
+        assert!(enc_rec.decrypt(&cipher).is_err());
+        let mut enc_rec = rec.encrypt(&cipher).unwrap();
+        enc_rec.value_pointer = true;
+        assert!(enc_rec.decrypt(&cipher).is_err());
+        let dec_rec = rec.encrypt(&cipher).unwrap().decrypt(&cipher).unwrap();
+        assert_eq!(dec_rec.timestamp, rec.timestamp);
+        assert_eq!(dec_rec.value, rec.value);
+
+        // value 为空的删除记录
+        let rec = LogRecord {
+            key: "name".as_bytes().to_vec(),
+            value: Default::default(),
+            rec_type: LogRecordType::DELETE,
+            timestamp: 0,
+            value_pointer: false,
+        };
+        let dec_rec = rec.encrypt(&cipher).unwrap().decrypt(&cipher).unwrap();
+        assert_eq!(dec_rec.key, rec.key);
+        assert!(dec_rec.value.is_empty());
+    }
 }
@@ -1,2 +1,4 @@
+pub mod bloom;
+pub mod cipher;
 pub mod data_file;
 pub mod log_record;
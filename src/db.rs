@@ -1,51 +1,309 @@
-use core::panic;
 use std::{
-    collections::HashMap,
+    collections::{BTreeSet, HashMap, VecDeque},
     fs::{self, File},
-    path::PathBuf,
+    io::IoSlice,
+    ops::Deref,
+    path::{Path, PathBuf},
     sync::{
-        atomic::{AtomicUsize, Ordering},
-        Arc,
+        atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        mpsc, Arc,
     },
+    thread,
+    time::{Duration, Instant},
 };
 
 use bytes::Bytes;
 use fs2::FileExt;
 use log::{error, warn};
-use parking_lot::{Mutex, RwLock};
+use parking_lot::{Condvar, Mutex, RwLock};
 
 use crate::{
+    atomic::KeyLocks,
     batch::{log_record_key_with_seq, parse_log_record_key, NON_TRANSACTION_SEQ_NO},
     data::{
-        data_file::{DataFile, DATA_FILE_NAME_SUFFIX, MERGE_FIN_FILE_NAME, SEQ_NO_FILE_NAME},
-        log_record::{LogRecord, LogRecordPos, LogRecordType, TransactionRecord},
+        bloom::BloomFilter,
+        cipher::{load_cipher, Cipher},
+        data_file::{
+            get_data_file_name, write_record_file, DataFile, DATA_FILE_HEADER_SIZE,
+            DATA_FILE_NAME_SUFFIX, MERGE_FIN_FILE_NAME, SEQ_NO_FILE_NAME,
+        },
+        log_record::{current_timestamp, LogRecord, LogRecordPos, LogRecordType, ReadLogRecord},
     },
     errors::{Errors, Result},
+    fio::file_cache::FileCache,
+    hlc::{self, HybridClock},
     index,
-    merge::load_merge_files,
-    options::{IOType, IndexType, Options},
+    manifest::{load_manifest_data_files, ActiveFileMeta, Manifest, ManifestFiles},
+    merge::{load_merge_files, read_non_merge_file_id},
+    mvcc::{ActiveTxn, TxnReaper},
+    options::{
+        IOType, IndexType, InvalidRecordTypeMode, Options, RuntimeOption, StartupVerify,
+        WriteStallMode,
+    },
+    replication::ReplicationCursor,
+    secondary_index::SecondaryIndexes,
+    stats::EngineStats,
     util,
+    value_log::{decode_value_pointer, ValueLog},
+    watch::Watchers,
 };
 
-const INITIAL_FILE_ID: u64 = 0;
+#[cfg(feature = "object-store")]
+use crate::fio::object_store::{self, ArchiveRegistration};
+
+pub use crate::merge::{MergeHandle, MergeProgress};
+
+pub(crate) const INITIAL_FILE_ID: u64 = 0;
 pub(crate) const FILE_LOCK_NAME: &str = "flock";
-const SEQ_NO_KEY: &str = "seq.no";
+pub(crate) const SEQ_NO_KEY: &str = "seq.no";
+// 保留最近多少次 merge 的结果
+pub(crate) const MERGE_HISTORY_SIZE: usize = 16;
+// try_open 等待数据目录被释放时的重试间隔
+const TRY_OPEN_INTERVAL: Duration = Duration::from_millis(10);
+
+// 当前进程中已经打开的数据目录，部分平台上同一个进程可以重复获取文件锁，
+// 需要在进程内部额外记录，避免同一个数据目录被打开两次
+static OPEN_DIRS: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+// 在当前进程中占用数据目录，释放时从 OPEN_DIRS 中移除
+pub(crate) struct DirRegistration(PathBuf);
+
+impl DirRegistration {
+    // 数据目录按照规范化之后的路径记录，不同写法的同一个目录也会被识别出来
+    pub(crate) fn register(dir_path: &Path) -> Result<Self> {
+        let path = dir_path.canonicalize().map_err(|e| {
+            error!("failed to canonicalize database dir: {}", e);
+            Errors::FailedToReadDatabaseDir
+        })?;
+        if !OPEN_DIRS.lock().insert(path.clone()) {
+            return Err(Errors::DatabaseIsUsing);
+        }
+        Ok(Self(path))
+    }
+}
 
-/// bitcask 存储引擎实例结构体
+impl Drop for DirRegistration {
+    fn drop(&mut self) {
+        OPEN_DIRS.lock().remove(&self.0);
+    }
+}
+
+/// bitcask 存储引擎实例，内部状态通过 Arc 共享，clone 的开销很小，
+/// 所有的 clone 都访问同一个存储引擎，最后一个被释放时关闭存储引擎
 pub struct Engine {
+    inner: Arc<EngineInner>,
+}
+
+/// 存储引擎的内部状态，通过 Engine 访问
+pub struct EngineInner {
     pub(crate) options: Arc<Options>,
-    pub(crate) active_file: Arc<RwLock<DataFile>>, // 当前活跃数据文件
-    pub(crate) older_files: Arc<RwLock<HashMap<u64, DataFile>>>, // 旧的数据文件集合
+    // 运行时可以调整的配置项，读取这些配置时以这里为准，options 中保存的是打开时的值
+    pub(crate) runtime_options: RuntimeOptions,
+    // 当前活跃数据文件，数据文件通过 Arc 共享，读取时只在查找期间持有锁，读取数据时不持有锁
+    pub(crate) active_file: Arc<RwLock<Arc<DataFile>>>,
+    pub(crate) older_files: Arc<RwLock<HashMap<u64, Arc<DataFile>>>>, // 旧的数据文件集合
     pub(crate) index: Box<dyn index::Indexer>,     // 数据内存索引
     file_ids: Vec<u64>, // 数据库启动时的文件 id，只用于加载索引时使用，不能在其他地方更新或使用
     pub(crate) batch_commit_lock: Mutex<()>, // 事务提交保证串行化
-    pub(crate) seq_no: Arc<AtomicUsize>, // 全局事务序列号，全局递增
+    pub(crate) key_locks: KeyLocks, // 按照 key 分段的锁，保证同一个 key 的写入和读改写操作串行执行
+    pub(crate) seq_no: Arc<AtomicU64>, // 全局事务序列号，全局递增
+    pub(crate) clock: HybridClock, // 为每次写入生成单调递增的写入序列号
     pub(crate) merging_lock: Mutex<()>, // 防止多个线程同时 merge
-    lock_file: File,    // 文件锁，保证只能在数据目录上打开一个实例
-    bytes_write: Arc<AtomicUsize>, // 累计写入了多少字节
+    pub(crate) merge_history: Mutex<VecDeque<MergeReport>>, // 最近几次 merge 的结果
+    // 写入数据并更新索引期间持有读锁，merge_files 搬移有效数据以及 ingest 安装数据文件时持有写锁，
+    // 避免覆盖并发写入的新数据
+    pub(crate) relocate_lock: RwLock<()>,
+    lock_file: Option<File>, // 文件锁，保证只能在数据目录上打开一个实例，只读模式下不持有
+    dir_registration: Mutex<Option<DirRegistration>>, // 在当前进程中占用数据目录，只读模式下不占用
+    pub(crate) bytes_write: Arc<AtomicUsize>, // 累计写入了多少字节
     pub(crate) seq_file_exists: bool, // 事务序列号文件是否存在
     pub(crate) is_initial: bool, // 是否是第一次初始化该目录
     pub(crate) reclaim_size: Arc<AtomicUsize>, // 累计有多少空间可以 merge 释放
+    pub(crate) mvcc_version: Arc<AtomicU64>, // 下一个 MVCC 事务版本号，全局递增
+    pub(crate) active_txn: Arc<RwLock<HashMap<u64, ActiveTxn>>>, // 当前活跃的 MVCC 事务，只在内存中，不持久化
+    pub(crate) cipher: Option<Arc<Cipher>>, // 记录加密器，未开启加密时为空
+    pub(crate) file_cache: Option<Arc<FileCache>>, // 旧数据文件的句柄缓存，没有设置 max_open_files 时为空
+    pub(crate) watchers: Watchers, // key 变更的订阅者
+    pub(crate) secondary_indexes: SecondaryIndexes, // 注册的二级索引
+    pub(crate) value_log: ValueLog, // 存放大 value 的 blob 文件
+    pub(crate) stats: EngineStats, // key 数量和每个数据文件可以回收的数据量
+    pub(crate) bloom_filters: RwLock<HashMap<u64, Arc<BloomFilter>>>, // 已经加载的旧数据文件的 bloom filter
+    sync_worker: Mutex<Option<SyncWorker>>, // 后台定期持久化活跃文件的线程
+    txn_reaper: Mutex<Option<TxnReaper>>, // 后台回滚超时事务的线程
+    write_queue: Mutex<WriteQueue>, // 组提交的写入队列
+    write_queue_cond: Condvar, // 通知等待中的写入者
+    pub(crate) active_file_meta: Mutex<ActiveFileMeta>, // 活跃文件的元数据，写满之后记录到 manifest 中
+    pub(crate) manifest: Manifest, // 数据文件列表以及写满的数据文件的元数据
+    closed: AtomicBool,      // 数据库是否已经关闭
+    #[cfg(feature = "object-store")]
+    pub(crate) archive: Option<ArchiveRegistration>, // 旧数据文件上传到的对象存储
+}
+
+// 等待组提交的写入队列
+#[derive(Default)]
+struct WriteQueue {
+    next_id: u64,
+    pending: Vec<(u64, Vec<u8>)>,                // 等待写入的记录
+    results: HashMap<u64, Result<LogRecordPos>>, // 已经写入的记录的结果
+    writing: bool,                               // 是否有 leader 正在写入
+}
+
+// 运行时可以调整的配置项的当前值
+pub(crate) struct RuntimeOptions {
+    sync_writes: AtomicBool,
+    bytes_per_sync: AtomicUsize,
+    data_file_merge_ratio: AtomicU32, // f32 的二进制表示
+}
+
+impl RuntimeOptions {
+    fn new(options: &Options) -> Self {
+        Self {
+            sync_writes: AtomicBool::new(options.sync_writes),
+            bytes_per_sync: AtomicUsize::new(options.bytes_per_sync),
+            data_file_merge_ratio: AtomicU32::new(options.data_file_merge_ratio.to_bits()),
+        }
+    }
+
+    pub(crate) fn sync_writes(&self) -> bool {
+        self.sync_writes.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn bytes_per_sync(&self) -> usize {
+        self.bytes_per_sync.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn data_file_merge_ratio(&self) -> f32 {
+        f32::from_bits(self.data_file_merge_ratio.load(Ordering::SeqCst))
+    }
+}
+
+// 一次写入完成后，设置这次写入的每条记录的结果
+fn push_write_results(
+    results: &mut Vec<Result<LogRecordPos>>,
+    positions: &mut Vec<LogRecordPos>,
+    res: Result<()>,
+) {
+    for pos in positions.drain(..) {
+        results.push(res.clone().map(|_| pos));
+    }
+}
+
+// 一条记录写入失败之后不再写入这一组中剩余的记录，剩余的记录都返回同一个错误
+// 否则批量写入中间的记录失败之后，之后的记录以及事务完成标记仍然会写入，重启之后部分写入的批次被当作已经提交
+fn fail_remaining_records(results: &mut Vec<Result<LogRecordPos>>, len: usize, e: Errors) {
+    results.resize_with(len, || Err(e.clone()));
+}
+
+// 按照固定的时间间隔持久化活跃文件，停止时丢弃 sender 通知线程退出
+struct SyncWorker {
+    sender: mpsc::Sender<()>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl SyncWorker {
+    fn start(
+        active_file: Arc<RwLock<Arc<DataFile>>>,
+        bytes_write: Arc<AtomicUsize>,
+        interval: Duration,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel::<()>();
+        let handle = thread::spawn(move || {
+            while let Err(mpsc::RecvTimeoutError::Timeout) = receiver.recv_timeout(interval) {
+                // 持久化期间不持有锁，不阻塞活跃文件的切换
+                let active_file = active_file.read().clone();
+                match active_file.sync() {
+                    // 清空累计值
+                    Ok(()) => bytes_write.store(0, Ordering::SeqCst),
+                    Err(e) => error!("failed to sync active file: {}", e),
+                }
+            }
+        });
+        Self { sender, handle }
+    }
+
+    fn stop(self) {
+        drop(self.sender);
+        if self.handle.join().is_err() {
+            error!("sync worker thread panicked");
+        }
+    }
+}
+
+// 加载索引时从数据文件中读取到的一条记录
+struct IndexRecord {
+    key: Vec<u8>,
+    seq_no: u64,
+    rec_type: LogRecordType,
+    timestamp: u64,
+    pos: LogRecordPos,
+}
+
+// 按照文件 id 从小到大的顺序，把数据文件中的记录更新到内存索引中
+struct IndexLoader<'a> {
+    engine: &'a Engine,
+    active_file: &'a DataFile,
+    active_meta: Option<ActiveFileMeta>, // 活跃文件从头开始扫描，统计活跃文件的元数据
+    transaction_records: HashMap<u64, Vec<IndexRecord>>, // 暂存还没有读到提交标记的事务数据
+}
+
+impl IndexLoader<'_> {
+    fn begin_file(&mut self, data_file: &DataFile) {
+        let file_id = data_file.get_file_id();
+        if file_id == self.active_file.get_file_id() {
+            self.active_meta = Some(ActiveFileMeta::new(file_id, true));
+        }
+    }
+
+    fn add_record(&mut self, data_file: &DataFile, record: IndexRecord) {
+        if let Some(active_meta) = self.active_meta.as_mut() {
+            let key = match data_file.is_encrypted() {
+                true => None,
+                false => Some(record.key.as_slice()),
+            };
+            active_meta
+                .meta
+                .add_record(record.rec_type, record.timestamp, key);
+        }
+
+        // 非事务提交的情况
+        if record.seq_no == NON_TRANSACTION_SEQ_NO {
+            self.engine
+                .upadte_index(record.key, record.rec_type, record.pos);
+            return;
+        }
+
+        // 有事务提交标记，更新内存索引
+        if record.rec_type == LogRecordType::TxnFinished {
+            // 事务的数据可能已经通过 hint 文件加载过了
+            if let Some(txn_records) = self.transaction_records.remove(&record.seq_no) {
+                for txn_record in txn_records {
+                    self.engine
+                        .upadte_index(txn_record.key, txn_record.rec_type, txn_record.pos);
+                }
+            }
+        } else {
+            self.transaction_records
+                .entry(record.seq_no)
+                .or_default()
+                .push(record);
+        }
+    }
+
+    fn finish_file(&mut self, data_file: &DataFile, offset: u64) {
+        // 设置活跃文件的 offset
+        if let Some(active_meta) = self.active_meta.take() {
+            *self.engine.active_file_meta.lock() = active_meta;
+            data_file.set_write_off(offset);
+        }
+    }
+}
+
+// 事务序列号文件的加载结果
+#[derive(Debug, PartialEq)]
+enum SeqNoState {
+    Loaded(u64),
+    Missing,   // 文件不存在，上次没有正常关闭
+    Corrupted, // 文件无法解析
 }
 
 /// 存储引擎相关统计数据
@@ -59,26 +317,264 @@ pub struct Stat {
     pub reclaim_size: usize,
     /// 数据目录占据的磁盘空间大小
     pub disk_size: u64,
+    /// 内存索引中 key 的数量，包含 MVCC 事务内部使用的 key 和 bucket 中的 key
+    pub index_key_num: usize,
+    /// 内存索引占用的内存大小（字节），估算值，BPTree 索引不占用内存，始终为 0
+    pub index_memory_usage: usize,
+}
+
+/// 活跃的 MVCC 事务信息
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransactionInfo {
+    /// 事务版本号
+    pub version: u64,
+    /// 事务开启之后经过的时间
+    pub age: Duration,
+    /// 事务中写入的 key 的数量
+    pub write_key_num: usize,
+}
+
+/// 单个数据文件的统计数据
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FileStat {
+    /// 数据文件 id
+    pub file_id: u64,
+    /// 仍然有效的数据量
+    pub live_size: u64,
+    /// 可以回收的数据量
+    pub dead_size: u64,
+}
+
+/// 前缀相同的一组 key 的统计数据
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PrefixStat {
+    /// 前缀为 prefix 的 key 的数量
+    pub key_count: usize,
+    /// 这些 key 最新的记录在数据文件中占据的大小，是一个估算值
+    pub approx_bytes: u64,
+}
+
+/// 大小分布的直方图，按照 2 的幂次划分区间，只包含非空的区间
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SizeHistogram {
+    /// 按照上界从小到大排列的区间
+    pub buckets: Vec<HistogramBucket>,
+    /// 统计的数量
+    pub count: u64,
+    /// 所有大小的总和
+    pub total: u64,
+    /// 最大的大小
+    pub max: u64,
+}
+
+/// 直方图中的一个区间，包含大小在 (upper_bound / 2, upper_bound] 之间的数量，
+/// 上界为 1 的区间同时包含大小为 0 的数量
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HistogramBucket {
+    /// 区间的上界（包含）
+    pub upper_bound: u64,
+    /// 大小在这个区间中的数量
+    pub count: u64,
+}
+
+impl SizeHistogram {
+    pub(crate) fn add(&mut self, size: u64) {
+        let upper_bound = size.checked_next_power_of_two().unwrap_or(u64::MAX);
+        match self
+            .buckets
+            .binary_search_by_key(&upper_bound, |bucket| bucket.upper_bound)
+        {
+            Ok(i) => self.buckets[i].count += 1,
+            Err(i) => self.buckets.insert(
+                i,
+                HistogramBucket {
+                    upper_bound,
+                    count: 1,
+                },
+            ),
+        }
+        self.count += 1;
+        self.total += size;
+        self.max = self.max.max(size);
+    }
+}
+
+/// 一次 merge 的结果，用于观察 merge 是否真正回收了空间
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MergeReport {
+    /// 参与 merge 的数据文件数量
+    pub files_merged: usize,
+    /// 参与 merge 的数据文件总大小
+    pub bytes_before: u64,
+    /// merge 生成的数据文件总大小
+    pub bytes_after: u64,
+    /// 丢弃的无效记录数量
+    pub records_dropped: u64,
+    /// merge 的耗时
+    pub duration: Duration,
+}
+
+/// 数据在数据文件中的位置，可以保存下来之后通过 Engine::read_at 直接读取 value
+/// merge 会重写数据文件，merge 之后之前获取的位置不再有效
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RecordLocation {
+    /// 数据文件 id
+    pub file_id: u64,
+    /// 记录在数据文件中的偏移
+    pub offset: u64,
+    /// 记录在数据文件中占用的大小
+    pub size: u64,
+}
+
+impl From<LogRecordPos> for RecordLocation {
+    fn from(pos: LogRecordPos) -> Self {
+        Self {
+            file_id: pos.file_id,
+            offset: pos.offset,
+            size: pos.size,
+        }
+    }
+}
+
+/// 数据的元信息，由 Engine::get_with_meta 返回
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Meta {
+    /// 写入时间，单位毫秒，没有记录写入时间的旧数据为 0
+    pub timestamp: u64,
+    /// 记录在数据文件中占用的大小
+    pub size: u64,
+    /// 记录所在的数据文件 id
+    pub file_id: u64,
+}
+
+/// Engine::dump_path 可以直接打开遍历的文件
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFileType {
+    /// 指定 id 的数据文件
+    Data(u64),
+    /// merge 生成的 hint 索引文件
+    Hint,
+    /// 标识 merge 完成的文件
+    MergeFin,
+    /// 事务序列号文件
+    SeqNo,
+    /// MVCC 事务版本号文件
+    MvccVersion,
+}
+
+/// 数据文件中的一条记录，由 Engine::dump_file 和 Engine::dump_path 返回
+#[derive(Debug, Clone, PartialEq)]
+pub struct DumpRecord {
+    /// 记录在数据文件中的偏移
+    pub offset: u64,
+    /// 记录在数据文件中占用的大小
+    pub size: u64,
+    /// 记录的类型
+    pub record_type: DumpRecordType,
+    /// 实际的 key，不包含事务序列号
+    pub key: Bytes,
+    /// 事务序列号，不在事务中写入的记录为 0
+    pub seq_no: u64,
+    /// 记录中的 value，value_pointer 为 true 时是 value 在 blob 文件中的位置
+    pub value: Bytes,
+    /// value 是否单独存放在 blob 文件中
+    pub value_pointer: bool,
+    /// 写入时间，单位毫秒，没有记录写入时间的旧数据为 0
+    pub timestamp: u64,
+    /// CRC 校验是否通过，校验失败时记录中的数据不可信，加密的记录也不会被解密
+    pub crc_valid: bool,
+    /// hint 文件中的记录指向的位置：(file id, offset, size)，其他文件中的记录为 None
+    pub hint_pos: Option<(u64, u64, u64)>,
+}
+
+/// 数据文件中记录的类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpRecordType {
+    /// 写入的数据
+    Put,
+    /// 删除标记
+    Delete,
+    /// 事务提交标记
+    TxnFinished,
+}
+
+/// 数据文件中的一次变更，由 Engine::read_log_since 返回
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangeRecord {
+    /// 实际的 key
+    pub key: Bytes,
+    /// 写入的 value，删除时为空
+    pub value: Bytes,
+    /// 变更的类型
+    pub change_type: ChangeType,
+    /// 写入时间，单位毫秒，没有记录写入时间的旧数据为 0
+    pub timestamp: u64,
+    /// 处理完这条记录之后继续读取的位置，持久化之后可以在重启时从这个位置继续读取
+    pub cursor: ReplicationCursor,
+}
+
+/// 变更的类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeType {
+    /// 写入数据
+    Put,
+    /// 删除数据
+    Delete,
+}
+
+/// 修复数据目录的结果
+#[derive(Debug, Default)]
+pub struct RepairStat {
+    /// 扫描的数据文件数量
+    pub data_file_num: usize,
+    /// 修复后有效的 key 数量
+    pub key_num: usize,
+    /// 存在损坏数据的文件数量
+    pub corrupted_file_num: usize,
+    /// 丢弃的损坏数据大小
+    pub dropped_bytes: u64,
+    /// 修复后的事务序列号
+    pub seq_no: u64,
 }
 
 impl Engine {
     /// 打开 bitcask 存储引擎实例
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "open", skip_all, fields(dir = %opts.dir_path.display()))
+    )]
     pub fn open(opts: Options) -> Result<Self> {
+        #[cfg(feature = "tracing")]
+        let start = Instant::now();
+
         // 校验用户传递过来的配置项
         if let Some(e) = check_options(&opts) {
             return Err(e);
         }
 
+        // 只读模式下 B+ 树索引文件可能正在被其他实例使用，改为从数据文件中加载内存索引
+        let mut opts = opts;
+        if opts.read_only && opts.index_type == IndexType::BPTree {
+            opts.index_type = IndexType::BTree;
+        }
+
         let mut is_initial = false;
         let options: Options = opts.clone();
         // 判断数据目录是否存在，如果不存在的话则创建这个目录
         let dir_path = options.dir_path;
         if !dir_path.is_dir() {
+            // 只读模式下不创建数据目录
+            if options.read_only {
+                return Err(Errors::FailedToReadDatabaseDir);
+            }
             is_initial = true;
             if let Err(e) = fs::create_dir_all(dir_path.as_path()) {
                 warn!("create database dir err: {}", e);
                 return Err(Errors::FailedToCreateDatabaseDir);
             }
+            if let Some(parent) = dir_path.parent() {
+                sync_dir_if_enabled(options.fsync_dir, parent)?;
+            }
         }
 
         let entries = fs::read_dir(dir_path.clone()).unwrap();
@@ -86,27 +582,74 @@ impl Engine {
             is_initial = true;
         }
 
-        // 判断数据目录是否已经被使用了
-        let lock_file = fs::OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(dir_path.join(FILE_LOCK_NAME))
-            .unwrap();
-        if let Err(e) = lock_file.try_lock_exclusive() {
-            error!("get lock file err: {}", e);
-            println!("get lock file err: {}", e);
-            return Err(Errors::DatabaseIsUsing);
+        // 判断数据目录是否已经被使用了，只读模式下不获取文件锁
+        let mut lock_file = None;
+        let mut dir_registration = None;
+        if !options.read_only {
+            dir_registration = Some(DirRegistration::register(&dir_path)?);
+            let file = fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .open(dir_path.join(FILE_LOCK_NAME))
+                .unwrap();
+            if let Err(e) = file.try_lock_exclusive() {
+                error!("get lock file err: {}", e);
+                println!("get lock file err: {}", e);
+                return Err(Errors::DatabaseIsUsing);
+            }
+            lock_file = Some(file);
         }
 
-        // 加载 merge 数据目录
-        let is_merged = match load_merge_files(dir_path.clone()) {
-            Ok(is_merged) => is_merged,
-            Err(e) => return Err(e),
+        // 注册对象存储，之后加载 merge 数据目录以及数据文件时需要访问已经上传的数据文件
+        #[cfg(feature = "object-store")]
+        let archive = match options.object_store.clone() {
+            Some(object_store_options) => {
+                Some(object_store::register(&dir_path, object_store_options)?)
+            }
+            None => None,
         };
 
-        // 加载数据文件
-        let mut data_files = load_data_files(dir_path.clone(), opts.mmap_at_startup)?;
+        // 加载 merge 数据目录，只读模式下不改动数据目录中的文件
+        let is_merged = if options.read_only {
+            false
+        } else {
+            match load_merge_files(dir_path.clone()) {
+                Ok(is_merged) => is_merged,
+                Err(e) => return Err(e),
+            }
+        };
+
+        // 加载加密器，并校验密钥是否正确
+        let cipher = load_cipher(
+            dir_path.clone(),
+            options.encryption_key.as_ref(),
+            options.read_only,
+        )?
+        .map(Arc::new);
+
+        // 按照 manifest 中的数据文件列表加载数据文件，没有 manifest 时（旧版本的数据目录，
+        // 或者安装 merge 结果时已经删除了 manifest）扫描数据目录
+        // 设置了 max_open_files 时旧数据文件在加载时就转换为按需打开的句柄
+        let file_cache = opts
+            .max_open_files
+            .map(|capacity| Arc::new(FileCache::new(capacity)));
+        let manifest_files = Manifest::read(&dir_path)?;
+        let mut data_files = match manifest_files.as_ref() {
+            Some(files) => load_manifest_data_files(
+                dir_path.clone(),
+                files,
+                opts.mmap_at_startup,
+                cipher.clone(),
+                file_cache.as_ref(),
+            )?,
+            None => load_data_files(
+                dir_path.clone(),
+                opts.mmap_at_startup,
+                cipher.clone(),
+                file_cache.as_ref(),
+            )?,
+        };
 
         // 设置 file id 信息
         let mut file_ids: Vec<u64> = Vec::new();
@@ -122,40 +665,107 @@ impl Engine {
         if data_files.len() > 1 {
             for _ in 0..=data_files.len() - 2 {
                 let file = data_files.pop().unwrap();
-                older_files.insert(file.get_file_id(), file);
+                file.seal();
+                older_files.insert(file.get_file_id(), Arc::new(file));
             }
         }
 
+        // 打开存放大 value 的 blob 文件
+        let value_log = ValueLog::open(
+            dir_path.clone(),
+            options.data_file_size,
+            options.fsync_dir,
+            cipher.clone(),
+        )?;
+
         // 拿到当前活跃文件，即列表中最后一个文件
         let active_file = match data_files.pop() {
             Some(v) => v,
-            None => DataFile::new(dir_path.clone(), INITIAL_FILE_ID, IOType::StandardFIO)?,
+            None => {
+                let data_file = DataFile::new(
+                    dir_path.clone(),
+                    INITIAL_FILE_ID,
+                    IOType::StandardFIO,
+                    cipher.clone(),
+                )?;
+                if !options.read_only {
+                    preallocate_if_enabled(&options, &data_file)?;
+                    sync_dir_if_enabled(options.fsync_dir, &dir_path)?;
+                }
+                data_file
+            }
         };
 
+        // 重新生成只包含当前数据文件的 manifest，活跃文件还没有写满，没有元数据
+        let active_file_id = active_file.get_file_id();
+        let files: ManifestFiles = older_files
+            .keys()
+            .map(|file_id| {
+                let meta = manifest_files
+                    .as_ref()
+                    .and_then(|files| files.get(file_id).cloned().flatten());
+                (*file_id, meta)
+            })
+            .chain(std::iter::once((active_file_id, None)))
+            .collect();
+        let manifest = Manifest::create(dir_path.clone(), files, options.read_only, options.fsync_dir)?;
+        // 新建的活跃文件中没有记录，元数据是完整的，已有的活跃文件在加载索引时统计
+        let active_file_meta = ActiveFileMeta::new(
+            active_file_id,
+            active_file.file_size() <= active_file.get_header_size(),
+        );
+
         // 构造存储引擎实例
-        let mut engine = Self {
+        let inner = EngineInner {
+            runtime_options: RuntimeOptions::new(&opts),
             options: Arc::new(opts),
-            active_file: Arc::new(RwLock::new(active_file)),
+            active_file: Arc::new(RwLock::new(Arc::new(active_file))),
             older_files: Arc::new(RwLock::new(older_files)),
-            index: index::new_indexer(options.index_type, dir_path.clone()),
+            index: index::new_indexer(options.index_type, dir_path.clone(), options.key_comparator),
             file_ids: file_ids,
             batch_commit_lock: Mutex::new(()),
-            seq_no: Arc::new(AtomicUsize::new(1)),
+            key_locks: KeyLocks::new(),
+            seq_no: Arc::new(AtomicU64::new(1)),
+            clock: HybridClock::new(),
             merging_lock: Mutex::new(()),
+            merge_history: Mutex::new(VecDeque::new()),
+            relocate_lock: RwLock::new(()),
             lock_file: lock_file,
+            dir_registration: Mutex::new(dir_registration),
             bytes_write: Arc::new(AtomicUsize::new(0)),
             seq_file_exists: false,
             is_initial: is_initial,
             reclaim_size: Arc::new(AtomicUsize::new(0)),
+            mvcc_version: Arc::new(AtomicU64::new(1)),
+            active_txn: Arc::new(RwLock::new(HashMap::new())),
+            cipher,
+            file_cache,
+            watchers: Watchers::new(),
+            secondary_indexes: SecondaryIndexes::new(),
+            value_log,
+            stats: EngineStats::new(),
+            bloom_filters: RwLock::new(HashMap::new()),
+            sync_worker: Mutex::new(None),
+            txn_reaper: Mutex::new(None),
+            write_queue: Mutex::new(WriteQueue::default()),
+            write_queue_cond: Condvar::new(),
+            active_file_meta: Mutex::new(active_file_meta),
+            manifest,
+            closed: AtomicBool::new(false),
+            #[cfg(feature = "object-store")]
+            archive,
+        };
+        let mut engine = Self {
+            inner: Arc::new(inner),
         };
 
         // B+ 树不需要从数据文件加载索引
         if engine.options.index_type != IndexType::BPTree {
             // 从 hint 文件中加载索引
-            engine.load_index_from_hint_file()?;
+            let rescan_from = engine.load_index_from_hint_file()?;
 
             // 从数据文件中加载内存索引
-            let current_seq_no = engine.load_index_from_data_files()?;
+            let current_seq_no = engine.load_index_from_data_files(rescan_from)?;
 
             // 更新当前事务序列号
             if current_seq_no > 0 {
@@ -164,95 +774,228 @@ impl Engine {
 
             // 重置 IO 类型
             if engine.options.mmap_at_startup {
-                engine.reset_io_type();
+                engine.reset_io_type()?;
+            }
+        }
+
+        // B+ 树索引模式下加载事务序列号，merge 之后会重新加载索引，不需要读取
+        let mut rebuild_bptree = is_merged;
+        if engine.options.index_type == IndexType::BPTree && !is_merged {
+            match engine.load_seq_no()? {
+                SeqNoState::Loaded(seq_no) => {
+                    engine.seq_no.store(seq_no, Ordering::SeqCst);
+                    engine.inner_mut().seq_file_exists = true;
+                }
+                SeqNoState::Missing => {}
+                // 事务序列号文件损坏，从数据文件中重建索引的同时恢复事务序列号
+                SeqNoState::Corrupted => {
+                    rebuild_bptree = true;
+                    engine.inner_mut().seq_file_exists = true;
+                }
             }
         }
 
         if engine.options.index_type == IndexType::BPTree {
             // merge 之后重新加载 bptree 索引
-            if is_merged {
+            if rebuild_bptree {
                 // 清空之前的索引数据
                 engine.index.clear();
+                engine.stats.reset();
 
                 // 从 hint 文件中加载索引
-                engine.load_index_from_hint_file()?;
+                let rescan_from = engine.load_index_from_hint_file()?;
 
                 // 从数据文件中加载内存索引
-                let current_seq_no = engine.load_index_from_data_files()?;
+                let current_seq_no = engine.load_index_from_data_files(rescan_from)?;
 
                 // 更新当前事务序列号
                 if current_seq_no > 0 {
                     engine.seq_no.store(current_seq_no + 1, Ordering::SeqCst);
                 }
-            } else {
-                // 加载事务序列号
-                let (exists, seq_no) = engine.load_seq_no();
-                engine.seq_no.store(seq_no, Ordering::SeqCst);
-                engine.seq_file_exists = exists;
 
+                // 重置 IO 类型
+                if engine.options.mmap_at_startup {
+                    engine.reset_io_type()?;
+                }
+            } else {
                 // 设置当前活跃文件的偏移
                 let active_file = engine.active_file.write();
                 active_file.set_write_off(active_file.file_size());
             }
         }
 
+        // 加载统计信息，只有 B+ 树索引没有重新扫描数据文件时才使用持久化的统计信息，
+        // 其余情况在加载索引时已经重新计算，文件不存在时（异常退出）只能重新统计 key 的数量
+        let reuse_stats = engine.options.index_type == IndexType::BPTree && !rebuild_bptree;
+        if !engine.load_stats(reuse_stats)? && reuse_stats {
+            engine.count_keys()?;
+        }
+
+        // 加载 MVCC 事务版本号，需要在索引加载完成之后
+        engine.load_mvcc_version()?;
+
+        // 写入序列号从已有记录中最晚的写入时间之后开始，系统时间回退时重新打开之后仍然递增
+        engine.restore_clock();
+
+        // 启动后台定期持久化活跃文件的线程
+        if let Some(interval) = engine.options.sync_interval {
+            if !engine.options.read_only {
+                *engine.sync_worker.lock() = Some(SyncWorker::start(
+                    engine.active_file.clone(),
+                    engine.bytes_write.clone(),
+                    interval,
+                ));
+            }
+        }
+
+        // 启动后台回滚超时事务的线程
+        if let Some(max_age) = engine.options.max_transaction_age {
+            *engine.txn_reaper.lock() = Some(TxnReaper::start(engine.active_txn.clone(), max_age));
+        }
+
+        // 安装了 merge 的结果之后，旧的数据文件按照配置上传到对象存储
+        #[cfg(feature = "object-store")]
+        if is_merged {
+            engine.upload_after_merge();
+        }
+
+        #[cfg(feature = "tracing")]
+        tracing::info!(
+            data_files = engine.file_ids.len(),
+            keys = engine.stats.key_num(),
+            merged = is_merged,
+            elapsed_ms = start.elapsed().as_millis() as u64,
+            "engine opened"
+        );
+
         Ok(engine)
     }
 
+    /// 和 open 相同，数据目录正在被其他实例使用时等待它被释放，超过 timeout 之后返回 DatabaseIsUsing
+    pub fn try_open(opts: Options, timeout: Duration) -> Result<Self> {
+        let start = Instant::now();
+        loop {
+            match Self::open(opts.clone()) {
+                Err(Errors::DatabaseIsUsing) if start.elapsed() < timeout => {
+                    let remaining = timeout.saturating_sub(start.elapsed());
+                    thread::sleep(remaining.min(TRY_OPEN_INTERVAL));
+                }
+                res => return res,
+            }
+        }
+    }
+
     /// B+ 树索引模式下加载事务序列号
-    fn load_seq_no(&self) -> (bool, usize) {
+    fn load_seq_no(&self) -> Result<SeqNoState> {
         let seq_no_file_path = self.options.dir_path.join(SEQ_NO_FILE_NAME);
         if !seq_no_file_path.is_file() {
-            return (false, 0);
+            return Ok(SeqNoState::Missing);
         }
 
-        let seq_no_file = DataFile::new_seq_no_file(self.options.dir_path.clone()).unwrap();
-        let record = match seq_no_file.read_log_record(0) {
-            Ok(res) => res.record,
-            Err(e) => panic!("failed to read seq no: {}", e),
+        // 文件损坏时（例如旧版本写入到一半时崩溃）不直接返回错误，由调用方重建索引恢复
+        let seq_no_file = DataFile::new_seq_no_file(self.options.dir_path.clone())?;
+        let state = match seq_no_file.read_log_record(0) {
+            Ok(res) => match String::from_utf8(res.record.value)
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+            {
+                Some(seq_no) => SeqNoState::Loaded(seq_no),
+                None => SeqNoState::Corrupted,
+            },
+            Err(_) => SeqNoState::Corrupted,
         };
+        drop(seq_no_file);
+        if state == SeqNoState::Corrupted {
+            warn!("seq no file is corrupted, rebuild index from data files");
+        }
 
-        let v = String::from_utf8(record.value).unwrap();
-        let seq_no = v.parse::<usize>().unwrap();
-
-        // 加载后删掉，避免追加写入
-        fs::remove_file(seq_no_file_path).unwrap();
+        // 加载后删掉，避免异常退出之后读到过期的序列号
+        if let Err(e) = fs::remove_file(seq_no_file_path) {
+            error!("failed to remove seq no file: {}", e);
+            return Err(Errors::FailedToRemoveDataFile);
+        }
 
-        (true, seq_no)
+        Ok(state)
     }
 
-    /// 存储 key/value 数据，key 不能为空
-    pub fn put(&self, key: Bytes, value: Bytes) -> Result<()> {
+    /// 存储 key/value 数据，key 不能为空，返回这次写入的序列号
+    /// 序列号单调递增，可以用来确定写入的先后顺序，参考 Engine::current_seq
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "put",
+            skip_all,
+            fields(key_len = key.len(), value_len = value.len())
+        )
+    )]
+    pub fn put(&self, key: Bytes, value: Bytes) -> Result<u64> {
+        self.check_closed()?;
         // 判断 key 的有效性
         if key.is_empty() {
             return Err(Errors::KeyIsEmpty);
         }
+        self.check_write_stall()?;
 
+        // 和同一个 key 的读改写操作串行执行
+        let _key_lock = self.key_locks.lock(&key);
+        self.do_put(key, value)
+    }
+
+    // 写入数据并更新索引，调用方需要持有 key 的锁，返回这次写入的序列号
+    pub(crate) fn do_put(&self, key: Bytes, value: Bytes) -> Result<u64> {
+        // 写入时间是序列号中的物理时间
+        let seq = self.clock.tick();
+        self.write_value(key, value, hlc::physical_time(seq))?;
+        Ok(seq)
+    }
+
+    // 写入一条指定写入时间的数据并更新索引，调用方需要持有 key 的锁
+    pub(crate) fn write_value(&self, key: Bytes, value: Bytes, timestamp: u64) -> Result<()> {
         // 构造 LogRecord
         let mut record = LogRecord {
-            key: log_record_key_with_seq(key.to_vec(), NON_TRANSACTION_SEQ_NO),
+            key: log_record_key_with_seq(&key, NON_TRANSACTION_SEQ_NO),
             value: value.to_vec(),
             rec_type: LogRecordType::NORMAL,
+            timestamp,
+            value_pointer: false,
         };
 
-        // 追加写到当前活跃数据文件中
-        let log_record_pos = self.append_log_record(&mut record)?;
+        // 追加写到当前活跃数据文件中，大 value 先写入 blob 文件，数据文件中只写入指针
+        let _relocate_lock = self.relocate_lock.read();
+        let log_record_pos = if self.is_large_value(&value) {
+            let _rotate_lock = self.value_log.rotate_lock.read();
+            let pointer_record = self.write_large_value(&key, &record)?;
+            self.append_user_record(&pointer_record)?
+        } else {
+            self.append_user_record(&record)?
+        };
 
         // 更新内存索引
-        if let Some(old_pos) = self.index.put(key.to_vec(), log_record_pos) {
-            self.reclaim_size
-                .fetch_add(old_pos.size as usize, Ordering::SeqCst);
-        }
+        self.index_put(key.to_vec(), log_record_pos);
+
+        // 更新二级索引
+        self.update_secondary_indexes(&key, Some((&value, log_record_pos)));
+
+        // 通知订阅者
+        record.key = key.to_vec();
+        self.watchers.notify(&[record]);
 
         Ok(())
     }
 
     pub fn delete(&self, key: Bytes) -> Result<()> {
+        self.check_closed()?;
         // 判断 key 的有效性
         if key.is_empty() {
             return Err(Errors::KeyIsEmpty);
         }
 
+        let _key_lock = self.key_locks.lock(&key);
+        self.do_delete(key)
+    }
+
+    // 写入删除记录并更新索引，调用方需要持有 key 的锁
+    pub(crate) fn do_delete(&self, key: Bytes) -> Result<()> {
         // 从内存索引中取出对应的数据，不存在的话直接返回
         let pos = self.index.get(key.to_vec());
         if pos.is_none() {
@@ -261,63 +1004,138 @@ impl Engine {
 
         // 构造 LogRecord，标识其是被删除的
         let mut record = LogRecord {
-            key: log_record_key_with_seq(key.to_vec(), NON_TRANSACTION_SEQ_NO),
+            key: log_record_key_with_seq(&key, NON_TRANSACTION_SEQ_NO),
             value: Default::default(),
             rec_type: LogRecordType::DELETE,
+            timestamp: hlc::physical_time(self.clock.tick()),
+            value_pointer: false,
         };
 
         // 写入到数据文件中
-        let pos = self.append_log_record(&mut record)?;
+        let _relocate_lock = self.relocate_lock.read();
+        let pos = self.append_user_record(&record)?;
         // delete 这条记录本身也是可以回收的
-        self.reclaim_size
-            .fetch_add(pos.size as usize, Ordering::SeqCst);
+        self.add_reclaim_size(&pos);
 
         // 删除内存索引中对应的 key
-        if let Some(old_pos) = self.index.delete(key.to_vec()) {
-            self.reclaim_size
-                .fetch_add(old_pos.size as usize, Ordering::SeqCst);
-        }
+        self.index_delete(key.to_vec());
+
+        // 更新二级索引
+        self.update_secondary_indexes(&key, None);
+
+        // 通知订阅者
+        record.key = key.to_vec();
+        self.watchers.notify(&[record]);
 
         Ok(())
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "get", skip_all, fields(key_len = key.len()))
+    )]
     pub fn get(&self, key: Bytes) -> Result<Bytes> {
+        self.check_closed()?;
         // 判断 key 的有效性
         if key.is_empty() {
             return Err(Errors::KeyIsEmpty);
         }
 
-        // 从内存索引中获取 key 对应的数据信息
-        let pos = self.index.get(key.to_vec());
-        // 如果 key 不存在直接返回
-        if pos.is_none() {
+        // 从内存索引中获取 key 对应的数据信息，根据索引获取数据文件中的 value
+        let (_, read_record) = self.read_log_record_by_key(&key)?;
+        if read_record.record.rec_type == LogRecordType::DELETE {
+            return Err(Errors::KeyNotFound);
+        }
+        Ok(read_record.record.value.into())
+    }
+
+    /// 获取 key 对应的 value 的大小，不读取 value，可以在读取之前设置 Content-Length 或者拒绝过大的 value
+    /// 只读取记录的 header，value 存放在 blob 文件中时再读取 blob 文件中记录的 header；
+    /// 加密的数据库需要读取完整的记录才能解密，开销和 get 相同
+    pub fn value_len(&self, key: Bytes) -> Result<u64> {
+        self.check_closed()?;
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+        let (_, value_len) = self.read_by_key(&key, |pos| self.read_value_len(pos))?;
+        Ok(value_len)
+    }
+
+    /// 根据 key 获取对应的数据以及数据的写入时间等元信息
+    pub fn get_with_meta(&self, key: Bytes) -> Result<(Bytes, Meta)> {
+        self.check_closed()?;
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+
+        let (log_record_pos, read_record) = self.read_log_record_by_key(&key)?;
+        let log_record = read_record.record;
+        if log_record.rec_type == LogRecordType::DELETE {
             return Err(Errors::KeyNotFound);
         }
 
-        let log_record_pos = pos.unwrap();
-        // 根据索引获取数据文件中的 value
-        self.get_value_by_position(&log_record_pos)
+        let meta = Meta {
+            timestamp: log_record.timestamp,
+            size: log_record_pos.size,
+            file_id: log_record_pos.file_id,
+        };
+        Ok((log_record.value.into(), meta))
+    }
+
+    /// 获取 key 对应的数据在数据文件中的位置，key 不存在时返回 None
+    pub fn get_position(&self, key: Bytes) -> Option<RecordLocation> {
+        if key.is_empty() || self.is_closed() {
+            return None;
+        }
+        self.index.get(key.to_vec()).map(RecordLocation::from)
+    }
+
+    /// 根据 get_position 返回的位置直接读取 value，不经过内存索引
+    pub fn read_at(&self, location: RecordLocation) -> Result<Bytes> {
+        self.check_closed()?;
+        let log_record_pos = LogRecordPos {
+            file_id: location.file_id,
+            offset: location.offset,
+            size: location.size,
+        };
+        let read_record = match self.read_log_record_by_position(&log_record_pos) {
+            Ok(read_record) => read_record,
+            Err(Errors::ReadDataFileEof)
+            | Err(Errors::InvaildLogRecordCrc)
+            | Err(Errors::InvalidRecordType(_)) => return Err(Errors::InvaildRecordLocation),
+            Err(e) => return Err(e),
+        };
+
+        // 位置信息需要和读取到的记录完全一致
+        if read_record.size as u64 != location.size
+            || read_record.record.rec_type == LogRecordType::TxnFinished
+        {
+            return Err(Errors::InvaildRecordLocation);
+        }
+        if read_record.record.rec_type == LogRecordType::DELETE {
+            return Err(Errors::KeyNotFound);
+        }
+
+        Ok(read_record.record.value.into())
     }
 
     // 根据索引信息获取 value
     pub(crate) fn get_value_by_position(&self, log_record_pos: &LogRecordPos) -> Result<Bytes> {
-        // 从对应的数据文件中获取对应的 LogRecord
-        let active_file = self.active_file.read();
-        let older_files = self.older_files.read();
-        let log_record = match active_file.get_file_id() == log_record_pos.file_id {
-            true => active_file.read_log_record(log_record_pos.offset)?.record,
-            false => {
-                let data_file = older_files.get(&log_record_pos.file_id);
-                if data_file.is_none() {
-                    // 找不到对应的数据文件，返回错误
-                    return Err(Errors::DataFileNotFound);
-                }
-                data_file
-                    .unwrap()
-                    .read_log_record(log_record_pos.offset)?
-                    .record
-            }
-        };
+        let data_file = self.get_data_file(log_record_pos.file_id)?;
+        self.get_value_from_file(&data_file, log_record_pos)
+    }
+
+    // 从给定的数据文件中读取索引信息指向的 value，数据文件需要是 log_record_pos 所在的文件
+    pub(crate) fn get_value_from_file(
+        &self,
+        data_file: &DataFile,
+        log_record_pos: &LogRecordPos,
+    ) -> Result<Bytes> {
+        let (offset, chunk_size) = (log_record_pos.offset, self.options.read_chunk_size);
+        let mut log_record = data_file
+            .read_log_record_with_chunk(offset, chunk_size)?
+            .record;
+        self.resolve_value_pointer(&mut log_record, log_record_pos.file_id, offset)?;
 
         // 判断 LogRecord 的类型
         if log_record.rec_type == LogRecordType::DELETE {
@@ -328,67 +1146,385 @@ impl Engine {
         Ok(log_record.value.into())
     }
 
+    // 从对应的数据文件中读取索引信息指向的 LogRecord，value 存放在 blob 文件中时读取出实际的 value
+    pub(crate) fn read_log_record_by_position(
+        &self,
+        log_record_pos: &LogRecordPos,
+    ) -> Result<ReadLogRecord> {
+        let mut read_record = self.read_raw_log_record(log_record_pos)?;
+        self.resolve_value_pointer(
+            &mut read_record.record,
+            log_record_pos.file_id,
+            log_record_pos.offset,
+        )?;
+        Ok(read_record)
+    }
+
+    // 从对应的数据文件中读取索引信息指向的 LogRecord，不读取 blob 文件中的 value
+    pub(crate) fn read_raw_log_record(
+        &self,
+        log_record_pos: &LogRecordPos,
+    ) -> Result<ReadLogRecord> {
+        let (offset, chunk_size) = (log_record_pos.offset, self.options.read_chunk_size);
+        let data_file = self.get_data_file(log_record_pos.file_id)?;
+        data_file.read_log_record_with_chunk(offset, chunk_size)
+    }
+
+    // 读取索引中 key 对应的记录，返回记录的位置以及记录
+    pub(crate) fn read_log_record_by_key(
+        &self,
+        key: &[u8],
+    ) -> Result<(LogRecordPos, ReadLogRecord)> {
+        self.read_by_key(key, |pos| self.read_log_record_by_position(pos))
+    }
+
+    // 根据索引中 key 对应的位置读取数据，返回记录的位置以及读取的结果
+    // 查找索引之后、获取数据文件之前数据文件可能被 merge 删除，此时索引已经指向搬移之后的位置，重新查找索引
+    pub(crate) fn read_by_key<T, F>(&self, key: &[u8], read: F) -> Result<(LogRecordPos, T)>
+    where
+        F: Fn(&LogRecordPos) -> Result<T>,
+    {
+        let mut log_record_pos = match self.index.get(key.to_vec()) {
+            Some(pos) => pos,
+            None => return Err(Errors::KeyNotFound),
+        };
+        loop {
+            match read(&log_record_pos) {
+                Err(Errors::DataFileNotFound) => match self.index.get(key.to_vec()) {
+                    Some(pos) if pos != log_record_pos => log_record_pos = pos,
+                    Some(_) => return Err(Errors::DataFileNotFound),
+                    None => return Err(Errors::KeyNotFound),
+                },
+                res => return res.map(|value| (log_record_pos, value)),
+            }
+        }
+    }
+
+    // 读取位置指向的记录中 value 的大小，只读取记录的 header
+    fn read_value_len(&self, log_record_pos: &LogRecordPos) -> Result<u64> {
+        let data_file = self.get_data_file(log_record_pos.file_id)?;
+        // 加密的记录需要读取完整的记录才能解密出 value
+        if data_file.is_encrypted() {
+            let mut record = data_file
+                .read_log_record_with_chunk(log_record_pos.offset, self.options.read_chunk_size)?
+                .record;
+            if record.rec_type == LogRecordType::DELETE {
+                return Err(Errors::KeyNotFound);
+            }
+            self.resolve_value_pointer(&mut record, log_record_pos.file_id, log_record_pos.offset)?;
+            return Ok(record.value.len() as u64);
+        }
+
+        let layout = data_file.read_record_layout(log_record_pos.offset)?;
+        if layout.rec_type == LogRecordType::DELETE {
+            return Err(Errors::KeyNotFound);
+        }
+        if !layout.value_pointer {
+            return Ok(layout.value_size);
+        }
+        // 数据文件中只有指向 blob 文件的指针，再读取 blob 文件中记录的 header
+        let record = data_file.read_log_record(log_record_pos.offset)?.record;
+        let blob_pos =
+            decode_value_pointer(record.value, log_record_pos.file_id, log_record_pos.offset)?;
+        self.value_log.value_size(&blob_pos)
+    }
+
+    // 从旧的数据文件集合中移除数据文件并删除文件，正在读取这个文件的调用方释放句柄之后才真正删除，
+    // 没有调用方持有时立即删除；manifest 中已经记录了删除，删除之前崩溃的话重启之后不会加载这个文件
+    pub(crate) fn remove_older_file(
+        &self,
+        older_files: &mut HashMap<u64, Arc<DataFile>>,
+        file_id: u64,
+    ) -> Result<()> {
+        let data_file_path = get_data_file_name(self.options.dir_path.clone(), file_id);
+        if let Some(data_file) = older_files.remove(&file_id) {
+            data_file.delete_on_release(data_file_path);
+            return Ok(());
+        }
+        // 已经上传到对象存储的数据文件本地可能已经删除
+        if data_file_path.is_file() {
+            if let Err(e) = fs::remove_file(data_file_path) {
+                error!("failed to remove data file: {}", e);
+                return Err(Errors::FailedToRemoveDataFile);
+            }
+        }
+        Ok(())
+    }
+
+    // 根据文件 id 获取数据文件，只在查找期间持有锁，之后的读取不会阻塞活跃文件的写入和切换
+    pub(crate) fn get_data_file(&self, file_id: u64) -> Result<Arc<DataFile>> {
+        let active_file = self.active_file.read();
+        if active_file.get_file_id() == file_id {
+            return Ok(active_file.clone());
+        }
+        drop(active_file);
+        match self.older_files.read().get(&file_id) {
+            Some(data_file) => Ok(data_file.clone()),
+            // 找不到对应的数据文件，返回错误
+            None => Err(Errors::DataFileNotFound),
+        }
+    }
+
     // 追加写数据到当前活跃数据文件中
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(size = tracing::field::Empty))
+    )]
     pub(crate) fn append_log_record(&self, record: &mut LogRecord) -> Result<LogRecordPos> {
-        let dir_path = self.options.dir_path.clone();
+        let enc_record = self.encode_log_record(record)?;
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("size", enc_record.len());
+
+        let mut positions = self.append_encoded_records(vec![enc_record])?;
+        Ok(positions.pop().unwrap())
+    }
+
+    // 追加写入用户写入的一条记录，和 append_log_record 不同的是会先检查记录能否放入数据文件
+    fn append_user_record(&self, record: &LogRecord) -> Result<LogRecordPos> {
+        let enc_record = self.encode_log_record(record)?;
+        self.check_record_size(&enc_record)?;
+        let mut positions = self.append_encoded_records(vec![enc_record])?;
+        Ok(positions.pop().unwrap())
+    }
+
+    // 编码之后的记录加上数据文件头部超过 data_file_size 时，任何数据文件都放不下这条记录，直接返回错误
+    // merge 搬移已有的记录时不做检查，调小 data_file_size 之后原来的大记录会单独放在一个数据文件中
+    pub(crate) fn check_record_size(&self, enc_record: &[u8]) -> Result<()> {
+        let size = enc_record.len() as u64;
+        if DATA_FILE_HEADER_SIZE + size > self.options.data_file_size {
+            return Err(Errors::RecordTooLargeForDataFile {
+                size,
+                data_file_size: self.options.data_file_size,
+            });
+        }
+        Ok(())
+    }
+
+    // 输入数据进行编码，开启加密时先加密记录
+    pub(crate) fn encode_log_record(&self, record: &LogRecord) -> Result<Vec<u8>> {
+        match self.cipher.as_ref() {
+            Some(cipher) => Ok(record.encrypt(cipher)?.encode()),
+            None => Ok(record.encode()),
+        }
+    }
+
+    // 追加写入一组已经编码的记录，返回每条记录的位置，任意一条写入失败时返回错误
+    // 同一组记录在写入队列中是连续的，由同一个 leader 一次性写入
+    pub(crate) fn append_encoded_records(
+        &self,
+        enc_records: Vec<Vec<u8>>,
+    ) -> Result<Vec<LogRecordPos>> {
+        if self.options.read_only {
+            return Err(Errors::DatabaseIsReadOnly);
+        }
+        if enc_records.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // 组提交：写入者把记录放入队列，由当前没有其他写入者在写时的第一个写入者作为 leader，
+        // 一次性写入队列中的所有记录并只持久化一次，其余写入者等待 leader 返回结果
+        let mut queue = self.write_queue.lock();
+        let first_id = queue.next_id;
+        let ids = first_id..first_id + enc_records.len() as u64;
+        queue.next_id = ids.end;
+        queue.pending.extend(ids.clone().zip(enc_records));
+        loop {
+            if queue.results.contains_key(&first_id) {
+                break;
+            }
+            if !queue.writing {
+                queue.writing = true;
+                let group = std::mem::take(&mut queue.pending);
+                drop(queue);
+
+                let results = self.write_log_records(&group);
+
+                queue = self.write_queue.lock();
+                for ((id, _), res) in group.iter().zip(results) {
+                    queue.results.insert(*id, res);
+                }
+                queue.writing = false;
+                self.write_queue_cond.notify_all();
+                break;
+            }
+            self.write_queue_cond.wait(&mut queue);
+        }
+
+        // 同一组记录的结果是一起写入的，先全部取出再返回第一个错误
+        let results: Vec<Result<LogRecordPos>> = ids
+            .map(|id| queue.results.remove(&id).unwrap())
+            .collect();
+        results.into_iter().collect()
+    }
 
-        // 输入数据进行编码
-        let enc_record = record.encode();
-        let record_len = enc_record.len() as u64;
+    // 将一组编码后的记录追加写入到活跃文件中，同一个数据文件中连续的记录只写一次
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(
+                records = group.len(),
+                bytes = group.iter().map(|(_, buf)| buf.len()).sum::<usize>()
+            )
+        )
+    )]
+    fn write_log_records(&self, group: &[(u64, Vec<u8>)]) -> Vec<Result<LogRecordPos>> {
+        let mut results = Vec::with_capacity(group.len());
+        let dir_path = self.options.dir_path.clone();
 
         // 获取到当前活跃文件
         let mut active_file = self.active_file.write();
 
-        // 判断当前活跃文件大小是否到达了阈值
-        if active_file.get_write_off() + record_len > self.options.data_file_size {
-            // 将当前活跃文件进行持久化
-            active_file.sync()?;
+        // 切换活跃文件失败时元数据中的文件 id 可能和活跃文件不一致，此时元数据不完整
+        let mut active_meta = self.active_file_meta.lock();
+        if active_meta.file_id != active_file.get_file_id() {
+            *active_meta = ActiveFileMeta::new(active_file.get_file_id(), false);
+        }
+
+        // 同一个数据文件中连续的记录通过一次 write_vectored 写入，不需要拷贝到同一个缓冲区
+        let mut slices: Vec<IoSlice> = Vec::new();
+        let mut pending_bytes = 0;
+        let mut positions = Vec::new();
+        let mut i = 0;
+        while i < group.len() {
+            let record_len = group[i].1.len() as u64;
+
+            // 判断当前活跃文件大小是否到达了阈值，或者满足了轮转策略中的其他条件，
+            // 开启加密之后未加密的活跃文件也不再写入
+            // 空的活跃文件放不下的记录直接写入，单独占用一个超过 data_file_size 的数据文件，避免不停地切换空文件
+            let write_off = active_file.get_write_off() + pending_bytes;
+            if (write_off + record_len > self.options.data_file_size
+                && write_off > active_file.get_header_size())
+                || self.reach_rotation_limit(&active_file, active_meta.meta.record_count)
+                || active_file.is_encrypted() != self.cipher.is_some()
+            {
+                // 先写入已经攒下的记录
+                if !slices.is_empty() {
+                    let res = active_file.write_vectored(&slices).map(|_| ());
+                    let failed = res.clone().err();
+                    push_write_results(&mut results, &mut positions, res);
+                    slices.clear();
+                    pending_bytes = 0;
+                    if let Some(e) = failed {
+                        fail_remaining_records(&mut results, group.len(), e);
+                        break;
+                    }
+                    continue;
+                }
+
+                // 将当前活跃文件进行持久化
+                if let Err(e) = active_file.sync() {
+                    fail_remaining_records(&mut results, group.len(), e);
+                    break;
+                }
 
-            let current_fid = active_file.get_file_id();
-            // 旧的数据文件存储到 map 中
-            let mut older_files = self.older_files.write();
-            let old_file = DataFile::new(dir_path.clone(), current_fid, IOType::StandardFIO)?;
-            older_files.insert(current_fid, old_file);
+                let current_fid = active_file.get_file_id();
+                // 创建新的数据文件之前先记录到 manifest 中
+                let mut older_files = self.older_files.write();
+                let rotated = self
+                    .record_rotation(&mut active_meta, &[], current_fid + 1)
+                    .and_then(|_| {
+                        // 打开新的数据文件
+                        let new_file = DataFile::new(
+                            dir_path.clone(),
+                            current_fid + 1,
+                            IOType::StandardFIO,
+                            self.cipher.clone(),
+                        )?;
+                        preallocate_if_enabled(&self.options, &new_file)?;
+                        sync_dir_if_enabled(self.options.fsync_dir, &dir_path)?;
+                        Ok(new_file)
+                    });
+                match rotated {
+                    Ok(new_file) => {
+                        // 原来的活跃文件句柄直接作为旧的数据文件，不需要重新打开
+                        let old_file = std::mem::replace(&mut *active_file, Arc::new(new_file));
+                        older_files.insert(current_fid, self.seal_data_file(old_file));
+                    }
+                    Err(e) => {
+                        fail_remaining_records(&mut results, group.len(), e);
+                        break;
+                    }
+                }
+            }
 
-            // 打开新的数据文件
-            let new_file = DataFile::new(dir_path.clone(), current_fid + 1, IOType::StandardFIO)?;
-            *active_file = new_file;
+            // 构造数据索引信息
+            positions.push(LogRecordPos {
+                file_id: active_file.get_file_id(),
+                offset: active_file.get_write_off() + pending_bytes,
+                size: record_len,
+            });
+            slices.push(IoSlice::new(&group[i].1));
+            pending_bytes += record_len;
+            active_meta
+                .meta
+                .add_encoded_record(&group[i].1, !active_file.is_encrypted());
+            i += 1;
         }
+        drop(active_meta);
 
         // 追加数据到当前活跃文件中
-        let write_off = active_file.get_write_off();
-        active_file.write(&enc_record)?;
-
-        let previous = self
-            .bytes_write
-            .fetch_add(enc_record.len(), Ordering::SeqCst);
-
-        // 根据配置项决定是否持久化
-        let mut need_sync = self.options.sync_writes;
-        if !need_sync
-            && self.options.bytes_per_sync > 0
-            && previous + enc_record.len() >= self.options.bytes_per_sync
-        {
+        if !slices.is_empty() {
+            let res = active_file.write_vectored(&slices).map(|_| ());
+            push_write_results(&mut results, &mut positions, res);
+        }
+
+        let written: usize = group.iter().map(|(_, enc_record)| enc_record.len()).sum();
+        let previous = self.bytes_write.fetch_add(written, Ordering::SeqCst);
+
+        // 根据配置项决定是否持久化，一组记录只持久化一次
+        let mut need_sync = self.runtime_options.sync_writes();
+        let bytes_per_sync = self.runtime_options.bytes_per_sync();
+        if !need_sync && bytes_per_sync > 0 && previous + written >= bytes_per_sync {
             need_sync = true;
         }
 
         if need_sync {
-            active_file.sync()?;
-            // 清空累计值
-            self.bytes_write.store(0, Ordering::SeqCst);
+            #[cfg(feature = "tracing")]
+            let _span = tracing::debug_span!("fsync").entered();
+            match active_file.sync() {
+                // 清空累计值
+                Ok(()) => self.bytes_write.store(0, Ordering::SeqCst),
+                Err(e) => {
+                    for res in results.iter_mut() {
+                        if res.is_ok() {
+                            *res = Err(e.clone());
+                        }
+                    }
+                }
+            }
         }
 
-        // 构造数据索引信息
-        Ok(LogRecordPos {
-            file_id: active_file.get_file_id(),
-            offset: write_off,
-            size: enc_record.len() as u64,
-        })
+        results
+    }
+
+    // 活跃文件中的记录数量或者写入时间是否达到了轮转策略的上限，空的活跃文件不需要轮转
+    fn reach_rotation_limit(&self, active_file: &DataFile, file_records: u64) -> bool {
+        if file_records == 0 {
+            return false;
+        }
+        let rotation = &self.options.file_rotation;
+        if let Some(max_records) = rotation.max_records {
+            if file_records >= max_records {
+                return true;
+            }
+        }
+        match (rotation.max_age, active_file.create_time()) {
+            (Some(max_age), Some(create_time)) => {
+                current_timestamp().saturating_sub(create_time) >= max_age.as_millis() as u64
+            }
+            _ => false,
+        }
     }
 
     /// 从数据文件中加载内存索引
     /// 遍历数据文件中的内容，并依次处理其中的记录
-    fn load_index_from_data_files(&self) -> Result<usize> {
+    /// hint 文件不完整时，rescan_from 为需要重新扫描的起始位置（文件 id 和偏移）
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(data_files = self.file_ids.len()))
+    )]
+    fn load_index_from_data_files(&self, rescan_from: Option<(u64, u64)>) -> Result<u64> {
         let mut current_seq_no = NON_TRANSACTION_SEQ_NO;
 
         // 数据文件为空，直接返回
@@ -401,154 +1537,288 @@ impl Engine {
         let mut non_merge_fid = 0;
         let meger_fin_filename = self.options.dir_path.join(MERGE_FIN_FILE_NAME);
         if meger_fin_filename.is_file() {
-            let megre_fin_file = DataFile::new_merge_fin_file(self.options.dir_path.clone())?;
-            let megre_fin_record = megre_fin_file.read_log_record(0)?;
-            let v = String::from_utf8(megre_fin_record.record.value).unwrap();
-
-            non_merge_fid = v.parse::<u64>().unwrap();
+            non_merge_fid = read_non_merge_file_id(self.options.dir_path.clone())?;
             has_merge = true;
         }
 
-        // 暂存事务相关数据
-        let mut transaction_records: HashMap<usize, Vec<TransactionRecord>> = HashMap::new();
-
-        let active_files = self.active_file.read();
+        let active_file = self.active_file.read();
         let older_files = self.older_files.read();
 
-        // 遍历每个文件 id，取出对应的数据文件，并加载其中的数据
-        for (i, file_id) in self.file_ids.iter().enumerate() {
-            // 如果比最近未参与 merge 的文件 ID 更小，则已经从 hint 文件中加载过索引了
-            if has_merge && *file_id < non_merge_fid {
-                continue;
-            }
-
-            let mut offset = 0;
-            loop {
-                let log_record_res = match *file_id == active_files.get_file_id() {
-                    true => active_files.read_log_record(offset),
-                    false => {
-                        let data_file = older_files.get(file_id).unwrap();
-                        data_file.read_log_record(offset)
+        // 如果比最近未参与 merge 的文件 ID 更小，则已经从 hint 文件中加载过索引了，
+        // 除非 hint 文件不完整，需要从 hint 文件中最后一条有效记录之后的位置开始重新扫描
+        let data_files: Vec<(&DataFile, u64)> = self
+            .file_ids
+            .iter()
+            .filter(|file_id| {
+                !has_merge
+                    || **file_id >= non_merge_fid
+                    || matches!(rescan_from, Some((fid, _)) if **file_id >= fid)
+            })
+            .map(|file_id| {
+                let data_file: &DataFile = match *file_id == active_file.get_file_id() {
+                    true => &active_file,
+                    false => older_files.get(file_id).unwrap(),
+                };
+                let start_offset = match rescan_from {
+                    Some((fid, offset)) if has_merge && fid == *file_id => {
+                        offset.max(data_file.get_header_size())
                     }
+                    _ => data_file.get_header_size(),
                 };
+                (data_file, start_offset)
+            })
+            .collect();
+
+        let mut loader = IndexLoader {
+            engine: self,
+            active_file: &active_file,
+            active_meta: None,
+            transaction_records: HashMap::new(),
+        };
 
-                let (mut log_record, size) = match log_record_res {
-                    Ok(result) => (result.record, result.size),
-                    Err(e) => {
-                        if e == Errors::ReadDataFileEof {
-                            break;
-                        }
-                        return Err(e);
+        if self.options.load_index_threads > 1 && data_files.len() > 1 {
+            // 多个线程并行扫描数据文件，当前线程按照文件 id 从小到大的顺序统一更新内存索引
+            util::parallel::for_each_ordered(
+                &data_files,
+                self.options.load_index_threads,
+                |(data_file, start_offset)| {
+                    let mut seq_no = NON_TRANSACTION_SEQ_NO;
+                    let mut records = Vec::new();
+                    let offset =
+                        self.scan_data_file(data_file, *start_offset, &mut seq_no, |record| {
+                            records.push(record)
+                        })?;
+                    Ok((records, offset, seq_no))
+                },
+                |(data_file, _), (records, offset, seq_no)| {
+                    current_seq_no = current_seq_no.max(seq_no);
+                    loader.begin_file(data_file);
+                    for record in records {
+                        loader.add_record(data_file, record);
                     }
-                };
+                    loader.finish_file(data_file, offset);
+                    Ok(())
+                },
+            )?;
+        } else {
+            // 单线程加载时边扫描边更新内存索引，不需要暂存整个文件的记录
+            for (data_file, start_offset) in data_files.iter() {
+                loader.begin_file(data_file);
+                let offset =
+                    self.scan_data_file(data_file, *start_offset, &mut current_seq_no, |record| {
+                        loader.add_record(data_file, record)
+                    })?;
+                loader.finish_file(data_file, offset);
+            }
+        }
 
-                // 构建内存索引
-                let log_record_pos = LogRecordPos {
-                    file_id: *file_id,
-                    offset: offset,
-                    size: size as u64,
-                };
+        Ok(current_seq_no)
+    }
 
-                // 解析 key，拿到实际的 key 和 seq no
-                let (rel_key, seq_no) = parse_log_record_key(log_record.key.clone());
-                // 非事务提交的情况
-                if seq_no == NON_TRANSACTION_SEQ_NO {
-                    self.upadte_index(rel_key, log_record.rec_type, log_record_pos);
-                } else {
-                    // 有事务提交标记，更新内存索引
-                    if log_record.rec_type == LogRecordType::TxnFinished {
-                        let records = transaction_records.get(&seq_no).unwrap();
-                        for txn_record in records.iter() {
-                            self.upadte_index(
-                                txn_record.record.key.clone(),
-                                txn_record.record.rec_type,
-                                txn_record.pos,
-                            );
+    // 从 start_offset 开始遍历数据文件中的记录，依次把记录的索引信息交给 handle，返回最后一条记录之后的 offset
+    fn scan_data_file<F>(
+        &self,
+        data_file: &DataFile,
+        start_offset: u64,
+        current_seq_no: &mut u64,
+        mut handle: F,
+    ) -> Result<u64>
+    where
+        F: FnMut(IndexRecord),
+    {
+        let file_id = data_file.get_file_id();
+
+        // 按照配置决定是否校验记录的 CRC，不校验时只读取 header 和 key
+        let is_active = file_id == *self.file_ids.last().unwrap();
+        let verify = data_file.is_encrypted()
+            || match self.options.startup_verify {
+                StartupVerify::Off => false,
+                StartupVerify::ChecksumActiveFile => is_active,
+                StartupVerify::Full => true,
+            };
+
+        // 从头到尾扫描文件，通过预读缓冲区读取，避免每条记录都产生一次 IO
+        let mut reader = data_file.sequential_reader();
+        let mut offset = start_offset;
+        loop {
+            let res = match verify {
+                true => reader.read_log_record(offset),
+                false => reader.read_log_record_key(offset),
+            };
+            let (log_record, size) = match res {
+                Ok(result) => (result.record, result.size),
+                Err(e) => {
+                    if e == Errors::ReadDataFileEof {
+                        break;
+                    }
+                    // 数据损坏时返回具体的位置，可以通过 Engine::repair 修复
+                    if e == Errors::InvaildLogRecordCrc {
+                        // 只读模式下活跃文件可能正在被写入，末尾不完整的数据直接忽略
+                        if self.options.read_only && is_active {
+                            break;
                         }
-                        transaction_records.remove(&seq_no);
-                    } else {
-                        log_record.key = rel_key;
-                        transaction_records
-                            .entry(seq_no)
-                            .or_insert(Vec::new())
-                            .push(TransactionRecord {
-                                record: log_record,
-                                pos: log_record_pos,
-                            });
+                        return Err(Errors::DataFileCorrupted { file_id, offset });
                     }
+                    // 记录类型无法识别时按照配置跳过这条记录、忽略之后的记录或者返回错误
+                    if let Errors::InvalidRecordType(rec_type) = e {
+                        match self.options.invalid_record_type {
+                            InvalidRecordTypeMode::Skip => {
+                                warn!(
+                                    "skip log record with unknown type {} in data file {} at offset {}",
+                                    rec_type, file_id, offset
+                                );
+                                offset += reader.log_record_size(offset)?;
+                                continue;
+                            }
+                            InvalidRecordTypeMode::Stop => {
+                                warn!(
+                                    "stop loading data file {} at offset {}, unknown log record type {}",
+                                    file_id, offset, rec_type
+                                );
+                                break;
+                            }
+                            InvalidRecordTypeMode::Strict => {
+                                return Err(Errors::DataFileCorrupted { file_id, offset })
+                            }
+                        }
+                    }
+                    return Err(e);
                 }
+            };
+
+            // 解析 key，拿到实际的 key 和 seq no，没有校验 CRC 的记录的 key 可能是损坏的
+            let (rel_key, seq_no) = parse_log_record_key(log_record.key)
+                .map_err(|_| Errors::DataFileCorrupted { file_id, offset })?;
+            handle(IndexRecord {
+                key: rel_key,
+                seq_no,
+                rec_type: log_record.rec_type,
+                timestamp: log_record.timestamp,
+                pos: LogRecordPos {
+                    file_id,
+                    offset,
+                    size: size as u64,
+                },
+            });
 
-                // 更新当前事务序列号
-                if seq_no > current_seq_no {
-                    current_seq_no = seq_no;
-                }
-
-                // 递增 offset，下一次读取的时候从新的位置开始
-                offset += size as u64;
+            // 更新当前事务序列号
+            if seq_no > *current_seq_no {
+                *current_seq_no = seq_no;
             }
 
-            // 设置活跃文件的 offset
-            if i == self.file_ids.len() - 1 {
-                active_files.set_write_off(offset);
-            }
+            // 递增 offset，下一次读取的时候从新的位置开始
+            offset += size as u64;
         }
 
-        Ok(current_seq_no)
+        Ok(offset)
     }
 
-    /// 关闭数据库，释放相关资源
-    pub fn close(&self) -> Result<()> {
-        // 如果数据目录不存在则返回
-        if !self.options.dir_path.is_dir() {
-            return Ok(());
+    // 根据数据文件元数据中记录的最晚写入时间恢复写入序列号
+    // B+ 树索引启动时不扫描活跃文件，活跃文件中的写入时间只能依赖系统时间
+    fn restore_clock(&self) {
+        for file_id in self.older_files.read().keys() {
+            if let Some(meta) = self.manifest.file_meta(*file_id) {
+                self.clock.observe(meta.max_timestamp);
+            }
         }
+        self.clock.observe(self.active_file_meta.lock().meta.max_timestamp);
+    }
 
-        // 记录事务序列号
-        let seq_no_file = DataFile::new_seq_no_file(self.options.dir_path.clone())?;
-        let seq_no = self.seq_no.load(Ordering::SeqCst);
-        let record = LogRecord {
-            key: SEQ_NO_KEY.as_bytes().to_vec(),
-            value: seq_no.to_string().into_bytes(),
-            rec_type: LogRecordType::NORMAL,
-        };
-        seq_no_file.write(&record.encode())?;
-        seq_no_file.sync()?;
+    // 打开存储引擎期间还没有其他的 clone，可以直接修改内部状态
+    fn inner_mut(&mut self) -> &mut EngineInner {
+        Arc::get_mut(&mut self.inner).expect("engine is shared while opening")
+    }
 
-        let read_guard = self.active_file.read();
-        read_guard.sync()?;
+    /// 数据库是否已经关闭
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
 
-        // 释放文件锁
-        self.lock_file.unlock().unwrap();
+    // 可以回收的数据量超过限制并且正在 merge 时，按照配置等待一段时间或者拒绝写入
+    pub(crate) fn check_write_stall(&self) -> Result<()> {
+        let limit = match self.options.write_stall_reclaim_size {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        if self.reclaim_size.load(Ordering::SeqCst) <= limit || !self.merging_lock.is_locked() {
+            return Ok(());
+        }
+        match self.options.write_stall_mode {
+            WriteStallMode::Delay(duration) => {
+                thread::sleep(duration);
+                Ok(())
+            }
+            WriteStallMode::Reject => Err(Errors::WriteStalled),
+        }
+    }
 
+    pub(crate) fn check_closed(&self) -> Result<()> {
+        if self.is_closed() {
+            return Err(Errors::DatabaseClosed);
+        }
         Ok(())
     }
 
     /// 持久化当前活跃文件
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "sync", skip_all))]
     pub fn sync(&self) -> Result<()> {
-        let read_guard = self.active_file.read();
-        read_guard.sync()
+        self.check_closed()?;
+        self.value_log.sync()?;
+        // 切换活跃文件时会先持久化旧的活跃文件，这里不需要持有锁
+        let active_file = self.active_file.read().clone();
+        active_file.sync()
+    }
+
+    /// 立即将当前活跃文件转换为旧的数据文件并切换到新的活跃文件，返回转换的数据文件 id
+    /// 不需要等到活跃文件写满，例如备份或者上传到对象存储之前让最新写入的数据进入旧的数据文件
+    /// 活跃文件中没有数据时同样会切换
+    pub fn rotate_active_file(&self) -> Result<u64> {
+        self.check_closed()?;
+        if self.options.read_only {
+            return Err(Errors::DatabaseIsReadOnly);
+        }
+
+        let dir_path = self.options.dir_path.clone();
+        let mut active_file = self.active_file.write();
+        let mut active_meta = self.active_file_meta.lock();
+        if active_meta.file_id != active_file.get_file_id() {
+            *active_meta = ActiveFileMeta::new(active_file.get_file_id(), false);
+        }
+        active_file.sync()?;
+
+        let current_fid = active_file.get_file_id();
+        let mut older_files = self.older_files.write();
+        self.record_rotation(&mut active_meta, &[], current_fid + 1)?;
+        let new_file = DataFile::new(
+            dir_path.clone(),
+            current_fid + 1,
+            IOType::StandardFIO,
+            self.cipher.clone(),
+        )?;
+        preallocate_if_enabled(&self.options, &new_file)?;
+        sync_dir_if_enabled(self.options.fsync_dir, &dir_path)?;
+
+        let old_file = std::mem::replace(&mut *active_file, Arc::new(new_file));
+        older_files.insert(current_fid, self.seal_data_file(old_file));
+        Ok(current_fid)
     }
 
     // 加载磁盘数据时更新内存索引
     fn upadte_index(&self, key: Vec<u8>, rec_type: LogRecordType, pos: LogRecordPos) {
-        if rec_type == LogRecordType::NORMAL {
-            if let Some(old_pos) = self.index.put(key.clone(), pos) {
-                self.reclaim_size
-                    .fetch_add(old_pos.size as usize, Ordering::SeqCst);
-            }
-        }
-        if rec_type == LogRecordType::DELETE {
-            // delete 这条记录本身也是可以回收的
-            let mut size = pos.size;
-            if let Some(old_pos) = self.index.delete(key) {
-                size += old_pos.size;
+        match rec_type {
+            LogRecordType::NORMAL => self.index_put(key, pos),
+            LogRecordType::DELETE => {
+                // delete 这条记录本身也是可以回收的
+                self.add_reclaim_size(&pos);
+                self.index_delete(key);
             }
-            self.reclaim_size.fetch_add(size as usize, Ordering::SeqCst);
+            _ => {}
         }
     }
 
     /// 备份数据目录
     pub fn backup(&self, dest_dir: PathBuf) -> Result<()> {
+        self.check_closed()?;
         let exculde = [FILE_LOCK_NAME];
         if let Err(e) = util::file::copy_dir(self.options.dir_path.clone(), dest_dir, &exculde) {
             error!("failed to copy dir: {}", e);
@@ -558,30 +1828,177 @@ impl Engine {
         Ok(())
     }
 
-    fn reset_io_type(&self) {
+    fn reset_io_type(&self) -> Result<()> {
+        let dir_path = self.options.dir_path.clone();
         let mut active_file = self.active_file.write();
-        active_file.set_io_manager(self.options.dir_path.clone(), IOType::StandardFIO);
+        *active_file = Arc::new(active_file.reopen(dir_path.clone(), IOType::StandardFIO)?);
 
         let mut older_files = self.older_files.write();
         for (_, file) in older_files.iter_mut() {
-            file.set_io_manager(self.options.dir_path.clone(), IOType::StandardFIO);
+            *file = Arc::new(match &self.file_cache {
+                Some(cache) => file.cached(dir_path.clone(), IOType::StandardFIO, cache),
+                None => file.reopen(dir_path.clone(), IOType::StandardFIO)?,
+            });
+        }
+        Ok(())
+    }
+
+    // 活跃文件写满之后转换为旧的数据文件，调用之前需要先持久化文件中的数据
+    // 设置了 max_open_files 时换成按需打开的句柄，原来的句柄在正在读取的调用方释放之后关闭
+    pub(crate) fn seal_data_file(&self, data_file: Arc<DataFile>) -> Arc<DataFile> {
+        data_file.seal();
+        match &self.file_cache {
+            Some(cache) => Arc::new(data_file.cached(
+                self.options.dir_path.clone(),
+                data_file.io_type(),
+                cache,
+            )),
+            None => data_file,
+        }
+    }
+
+    /// 最近一次写入的序列号，put 以及 WriteBatch::commit 返回的序列号都不大于这个值
+    /// 序列号的物理时间部分就是记录的写入时间，重新打开之后生成的序列号仍然比之前的大
+    pub fn current_seq(&self) -> u64 {
+        self.clock.current()
+    }
+
+    /// 当前生效的配置，包含运行时调整之后的值
+    pub fn options(&self) -> Options {
+        let mut options = (*self.options).clone();
+        options.sync_writes = self.runtime_options.sync_writes();
+        options.bytes_per_sync = self.runtime_options.bytes_per_sync();
+        options.data_file_merge_ratio = self.runtime_options.data_file_merge_ratio();
+        #[cfg(feature = "object-store")]
+        if let (Some(object_store), Some(archive)) =
+            (options.object_store.as_mut(), self.archive.as_ref())
+        {
+            object_store.cache_size = archive.cache_size();
         }
+        options
+    }
+
+    /// 运行时调整配置项，修改只在内存中生效，重新打开数据库之后恢复为 Options 中的值
+    pub fn set_runtime_option(&self, option: RuntimeOption) -> Result<()> {
+        self.check_closed()?;
+        let runtime_options = &self.runtime_options;
+        match option {
+            RuntimeOption::SyncWrites(sync_writes) => {
+                runtime_options
+                    .sync_writes
+                    .store(sync_writes, Ordering::SeqCst);
+            }
+            RuntimeOption::BytesPerSync(bytes_per_sync) => {
+                runtime_options
+                    .bytes_per_sync
+                    .store(bytes_per_sync, Ordering::SeqCst);
+            }
+            RuntimeOption::DataFileMergeRatio(ratio) => {
+                if !(0.0..=1.0).contains(&ratio) {
+                    return Err(Errors::InvaildDataFileMergeRatio);
+                }
+                runtime_options
+                    .data_file_merge_ratio
+                    .store(ratio.to_bits(), Ordering::SeqCst);
+            }
+            #[cfg(feature = "object-store")]
+            RuntimeOption::ObjectStoreCacheSize(cache_size) => match self.archive.as_ref() {
+                Some(archive) => archive.set_cache_size(cache_size),
+                None => return Err(Errors::ObjectStoreNotConfigured),
+            },
+        }
+        Ok(())
     }
 
     /// 获取数据库统计信息
     pub fn stat(&self) -> Result<Stat> {
-        let keys = self.list_keys()?;
+        self.check_closed()?;
         let older_files = self.older_files.read();
         Ok(Stat {
-            key_num: keys.len(),
+            key_num: self.stats.key_num(),
             data_file_num: older_files.len() + 1,
             reclaim_size: self.reclaim_size.load(Ordering::SeqCst),
             disk_size: util::file::dir_disk_size(self.options.dir_path.clone()),
+            index_key_num: self.index.len(),
+            index_memory_usage: self.index.memory_usage(),
         })
     }
 }
 
-impl Drop for Engine {
+impl EngineInner {
+    /// 关闭数据库，释放相关资源，重复关闭直接返回
+    /// 关闭之后除了 close 以外的接口都会返回 DatabaseClosed 错误
+    pub fn close(&self) -> Result<()> {
+        if self.closed.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        // 停止后台回滚超时事务的线程
+        if let Some(txn_reaper) = self.txn_reaper.lock().take() {
+            txn_reaper.stop();
+        }
+
+        // 如果数据目录不存在则返回
+        if !self.options.dir_path.is_dir() {
+            self.dir_registration.lock().take();
+            return Ok(());
+        }
+
+        // 只读模式下没有需要记录的数据，也没有持有文件锁
+        if self.options.read_only {
+            return Ok(());
+        }
+
+        // 停止后台持久化线程，最后统一持久化活跃文件
+        if let Some(sync_worker) = self.sync_worker.lock().take() {
+            sync_worker.stop();
+        }
+
+        // 记录事务序列号，之后统一持久化数据目录
+        let seq_no = self.seq_no.load(Ordering::SeqCst);
+        write_seq_no_file(self.options.dir_path.clone(), seq_no, false)?;
+
+        // 记录 MVCC 事务版本号
+        self.save_mvcc_version()?;
+
+        // 记录统计信息
+        self.save_stats()?;
+        sync_dir_if_enabled(self.options.fsync_dir, &self.options.dir_path)?;
+
+        let read_guard = self.active_file.read();
+        read_guard.sync()?;
+        self.value_log.sync()?;
+
+        // 释放文件锁
+        if let Some(lock_file) = &self.lock_file {
+            if let Err(e) = lock_file.unlock() {
+                error!("failed to unlock database dir: {}", e);
+            }
+        }
+        self.dir_registration.lock().take();
+
+        Ok(())
+    }
+}
+
+impl Clone for Engine {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl Deref for Engine {
+    type Target = EngineInner;
+
+    fn deref(&self) -> &EngineInner {
+        &self.inner
+    }
+}
+
+impl Drop for EngineInner {
+    // 最后一个 Engine 被释放时关闭存储引擎
     fn drop(&mut self) {
         if let Err(e) = self.close() {
             error!("error whiling close engine: {}", e);
@@ -589,13 +2006,44 @@ impl Drop for Engine {
     }
 }
 
+// 写入事务序列号文件
+pub(crate) fn write_seq_no_file(dir_path: PathBuf, seq_no: u64, fsync_dir: bool) -> Result<()> {
+    let record = LogRecord {
+        key: SEQ_NO_KEY.as_bytes().to_vec(),
+        value: seq_no.to_string().into_bytes(),
+        rec_type: LogRecordType::NORMAL,
+        timestamp: 0,
+        value_pointer: false,
+    };
+    write_record_file(dir_path.join(SEQ_NO_FILE_NAME), &record, fsync_dir)
+}
+
+// 开启 fsync_dir 时持久化目录，保证其中新建、重命名以及删除的文件在宕机之后仍然有效
+pub(crate) fn sync_dir_if_enabled(fsync_dir: bool, dir_path: &Path) -> Result<()> {
+    if !fsync_dir {
+        return Ok(());
+    }
+    util::file::sync_dir(dir_path).map_err(|e| {
+        error!("failed to sync dir: {}", e);
+        Errors::sync_failed(dir_path, e)
+    })
+}
+
+// 开启 preallocate 时为新建的活跃文件预先分配 data_file_size 大小的磁盘空间
+pub(crate) fn preallocate_if_enabled(options: &Options, data_file: &DataFile) -> Result<()> {
+    if !options.preallocate {
+        return Ok(());
+    }
+    data_file.preallocate(options.data_file_size)
+}
+
 fn check_options(opts: &Options) -> Option<Errors> {
     let dir_path = opts.dir_path.to_str();
     if dir_path.is_none() || dir_path.unwrap().len() == 0 {
         return Some(Errors::DirPathIsEmpty);
     }
 
-    if opts.data_file_size <= 0 {
+    if opts.data_file_size <= DATA_FILE_HEADER_SIZE {
         return Some(Errors::DataFileSizeTooSmall);
     }
 
@@ -603,11 +2051,61 @@ fn check_options(opts: &Options) -> Option<Errors> {
         return Some(Errors::InvaildDataFileMergeRatio);
     }
 
+    if opts.merge_threads == 0 {
+        return Some(Errors::InvaildMergeThreads);
+    }
+
+    if opts.merge_io_type == IOType::MemoryMap {
+        return Some(Errors::InvaildMergeIOType);
+    }
+
+    if opts.load_index_threads == 0 {
+        return Some(Errors::InvaildLoadIndexThreads);
+    }
+
+    if opts.sync_interval == Some(Duration::ZERO) {
+        return Some(Errors::InvaildSyncInterval);
+    }
+
+    if opts.file_rotation.max_records == Some(0)
+        || opts.file_rotation.max_age == Some(Duration::ZERO)
+    {
+        return Some(Errors::InvaildFileRotation);
+    }
+
+    if opts.max_transaction_age == Some(Duration::ZERO) {
+        return Some(Errors::InvaildMaxTransactionAge);
+    }
+
+    if opts.value_log_threshold == Some(0) {
+        return Some(Errors::InvaildValueLogThreshold);
+    }
+
+    if opts.max_open_files == Some(0) {
+        return Some(Errors::InvaildMaxOpenFiles);
+    }
+
+    // B+ 树索引文件中的 key 是明文存储的
+    if opts.encryption_key.is_some() && opts.index_type == IndexType::BPTree {
+        return Some(Errors::EncryptionUnsupportedIndexType);
+    }
+
+    // 跳表和 B+ 树索引只能按照字节序排序
+    if opts.key_comparator.is_some() && opts.index_type != IndexType::BTree {
+        return Some(Errors::KeyComparatorUnsupportedIndexType);
+    }
+
     None
 }
 
 // 从数据目录中加载数据文件
-fn load_data_files(dir_path: PathBuf, use_mmap_io: bool) -> Result<Vec<DataFile>> {
+// file_cache 不为空时除了最后一个数据文件之外都转换为按需打开的旧数据文件
+pub(crate) fn load_data_files(
+    dir_path: PathBuf,
+    use_mmap_io: bool,
+    cipher: Option<Arc<Cipher>>,
+    file_cache: Option<&Arc<FileCache>>,
+) -> Result<Vec<DataFile>> {
     // 读取数据目录
     let dir = fs::read_dir(dir_path.clone());
     if dir.is_err() {
@@ -637,6 +2135,16 @@ fn load_data_files(dir_path: PathBuf, use_mmap_io: bool) -> Result<Vec<DataFile>
         }
     }
 
+    // 已经上传到对象存储并且删除了本地文件的数据文件
+    #[cfg(feature = "object-store")]
+    if let Some(archive) = object_store::find_archive(&dir_path) {
+        for file_id in archive.archived_file_ids() {
+            if !file_ids.contains(&file_id) {
+                file_ids.push(file_id);
+            }
+        }
+    }
+
     // 如果没有数据文件直接返回
     if file_ids.is_empty() {
         return Ok(data_files);
@@ -651,9 +2159,24 @@ fn load_data_files(dir_path: PathBuf, use_mmap_io: bool) -> Result<Vec<DataFile>
         if use_mmap_io {
             io_type = IOType::MemoryMap;
         }
-        let data_file = DataFile::new(dir_path.clone(), *file_id, io_type)?;
-        data_files.push(data_file);
+        let data_file = DataFile::new(dir_path.clone(), *file_id, io_type, cipher.clone())?;
+        push_loaded_data_file(&mut data_files, data_file, &dir_path, file_cache);
     }
 
     Ok(data_files)
 }
+
+// 加载数据文件时，打开下一个数据文件之前先把上一个数据文件转换为按需打开的旧数据文件，
+// 启动时同时打开的文件数量不会超过缓存的上限，最后一个数据文件作为活跃文件保持打开
+pub(crate) fn push_loaded_data_file(
+    data_files: &mut Vec<DataFile>,
+    data_file: DataFile,
+    dir_path: &Path,
+    file_cache: Option<&Arc<FileCache>>,
+) {
+    if let (Some(cache), Some(last_file)) = (file_cache, data_files.pop()) {
+        last_file.seal();
+        data_files.push(last_file.cached(dir_path.to_path_buf(), last_file.io_type(), cache));
+    }
+    data_files.push(data_file);
+}
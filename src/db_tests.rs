@@ -1,10 +1,23 @@
 use bytes::Bytes;
-use std::path::PathBuf;
+use std::{
+    path::PathBuf,
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
 
 use crate::{
-    db::Engine,
+    batch::{log_record_key_with_seq, NON_TRANSACTION_SEQ_NO},
+    data::{
+        data_file::{get_data_file_name, DATA_FILE_HEADER_SIZE},
+        log_record::{current_timestamp, LogRecord, LogRecordType},
+    },
+    db::{Engine, HistogramBucket, RecordLocation},
     errors::Errors,
-    options::Options,
+    hlc,
+    options::{
+        FileRotation, IndexType, InvalidRecordTypeMode, IteratorOptions, Options, RuntimeOption,
+        StartupVerify, WriteBatchOptions, WriteStallMode,
+    },
     util::rand_kv::{get_test_key, get_test_value},
 };
 
@@ -122,6 +135,32 @@ fn test_engine_get() {
     std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
 }
 
+#[test]
+fn test_engine_large_key_value() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-large-key-value");
+    opts.data_file_size = 64 * 1024 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // key 和 value 的长度需要多个字节编码
+    let key = Bytes::from(vec![b'k'; 1024]);
+    let value = Bytes::from(vec![b'v'; 128 * 1024]);
+    let res1 = engine.put(key.clone(), value.clone());
+    assert!(res1.is_ok());
+    let res2 = engine.put(get_test_key(1), get_test_value(1));
+    assert!(res2.is_ok());
+    assert_eq!(engine.get(key.clone()).unwrap(), value);
+
+    // 重启之后校验
+    std::mem::drop(engine);
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(engine2.get(key.clone()).unwrap(), value);
+    assert_eq!(engine2.get(get_test_key(1)).unwrap(), get_test_value(1));
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
 #[test]
 fn test_engine_delete() {
     let mut opts = Options::default();
@@ -190,6 +229,47 @@ fn test_engine_close() {
     std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
 }
 
+#[test]
+fn test_engine_close_twice() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-close-twice");
+    opts.data_file_size = 64 * 1024 * 1024;
+    opts.sync_interval = Some(Duration::from_millis(10));
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    let res1 = engine.put(get_test_key(11), get_test_value(11));
+    assert!(res1.is_ok());
+    assert!(!engine.is_closed());
+
+    // 重复关闭直接返回
+    assert!(engine.close().is_ok());
+    assert!(engine.is_closed());
+    assert!(engine.close().is_ok());
+
+    // 关闭之后的操作都返回错误
+    let res2 = engine.put(get_test_key(12), get_test_value(12));
+    assert_eq!(Errors::DatabaseClosed, res2.err().unwrap());
+    let res3 = engine.get(get_test_key(11));
+    assert_eq!(Errors::DatabaseClosed, res3.err().unwrap());
+    let res4 = engine.delete(get_test_key(11));
+    assert_eq!(Errors::DatabaseClosed, res4.err().unwrap());
+    assert_eq!(Errors::DatabaseClosed, engine.list_keys().err().unwrap());
+    assert_eq!(Errors::DatabaseClosed, engine.sync().err().unwrap());
+    assert_eq!(Errors::DatabaseClosed, engine.merge().err().unwrap());
+    let res5 = engine.new_write_batch(WriteBatchOptions::default());
+    assert_eq!(Errors::DatabaseClosed, res5.err().unwrap());
+    assert!(engine.get_position(get_test_key(11)).is_none());
+
+    // 关闭之后文件锁已经释放，drop 时不会再次关闭
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    std::mem::drop(engine);
+    let res6 = engine2.get(get_test_key(11));
+    assert_eq!(res6.unwrap(), get_test_value(11));
+
+    std::mem::drop(engine2);
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
 #[test]
 fn test_engine_sync() {
     let mut opts = Options::default();
@@ -231,6 +311,138 @@ fn test_engine_filelock() {
     std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
 }
 
+#[test]
+fn test_engine_read_only() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-read-only");
+    opts.data_file_size = 64 * 1024 * 1024;
+
+    // 数据目录不存在时不会创建
+    let mut ro_opts = opts.clone();
+    ro_opts.read_only = true;
+    let res1 = Engine::open(ro_opts.clone());
+    assert_eq!(res1.err().unwrap(), Errors::FailedToReadDatabaseDir);
+
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..100 {
+        let res = engine.put(get_test_key(i), get_test_value(i));
+        assert!(res.is_ok());
+    }
+
+    // 不获取文件锁，可以和正在使用的实例同时打开
+    let ro_engine = Engine::open(ro_opts.clone()).expect("failed to open engine");
+    assert_eq!(ro_engine.list_keys().unwrap().len(), 100);
+    assert_eq!(ro_engine.get(get_test_key(10)).unwrap(), get_test_value(10));
+
+    // 不允许写入
+    let res2 = ro_engine.put(get_test_key(100), get_test_value(100));
+    assert_eq!(res2.err().unwrap(), Errors::DatabaseIsReadOnly);
+    let res3 = ro_engine.delete(get_test_key(10));
+    assert_eq!(res3.err().unwrap(), Errors::DatabaseIsReadOnly);
+    let res4 = ro_engine.merge();
+    assert_eq!(res4.err().unwrap(), Errors::DatabaseIsReadOnly);
+    std::mem::drop(ro_engine);
+
+    // 原实例不受影响
+    let res5 = engine.put(get_test_key(100), get_test_value(100));
+    assert!(res5.is_ok());
+    let res6 = Engine::open(opts.clone());
+    assert_eq!(res6.err().unwrap(), Errors::DatabaseIsUsing);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_encryption() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-encryption");
+    opts.data_file_size = 64 * 1024;
+    opts.data_file_merge_ratio = 0 as f32;
+    opts.encryption_key = Some([6; 32]);
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..1000 {
+        let res = engine.put(get_test_key(i), get_test_value(i));
+        assert!(res.is_ok());
+    }
+    for i in 0..100 {
+        let res = engine.delete(get_test_key(i));
+        assert!(res.is_ok());
+    }
+    assert_eq!(engine.get(get_test_key(100)).unwrap(), get_test_value(100));
+
+    // 数据文件中不包含明文
+    let contains_plaintext = |file_name: &str| {
+        let data = std::fs::read(opts.dir_path.join(file_name)).unwrap();
+        data.windows(10).any(|w| w == "bitcask-rs".as_bytes())
+    };
+    assert!(!contains_plaintext("000000000.data"));
+
+    // merge 之后 hint 文件中也不包含明文
+    let res1 = engine.merge();
+    assert!(res1.is_ok());
+    std::mem::drop(engine);
+
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert!(!contains_plaintext("hint-index"));
+    assert_eq!(engine2.list_keys().unwrap().len(), 900);
+    assert_eq!(engine2.get(get_test_key(100)).unwrap(), get_test_value(100));
+    assert_eq!(
+        engine2.get(get_test_key(10)).err().unwrap(),
+        Errors::KeyNotFound
+    );
+    std::mem::drop(engine2);
+
+    // 没有密钥或者密钥错误时无法打开
+    let mut opts2 = opts.clone();
+    opts2.encryption_key = None;
+    let res2 = Engine::open(opts2.clone());
+    assert_eq!(res2.err().unwrap(), Errors::EncryptionKeyRequired);
+    opts2.encryption_key = Some([7; 32]);
+    let res3 = Engine::open(opts2.clone());
+    assert_eq!(res3.err().unwrap(), Errors::InvaildEncryptionKey);
+
+    // B+ 树索引不支持加密
+    let mut opts3 = opts.clone();
+    opts3.index_type = IndexType::BPTree;
+    let res4 = Engine::open(opts3);
+    assert_eq!(res4.err().unwrap(), Errors::EncryptionUnsupportedIndexType);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_enable_encryption() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-enable-encryption");
+    opts.data_file_size = 64 * 1024 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..100 {
+        let res = engine.put(get_test_key(i), get_test_value(i));
+        assert!(res.is_ok());
+    }
+    std::mem::drop(engine);
+
+    // 已有的数据目录开启加密，新的数据写入加密的数据文件
+    opts.encryption_key = Some([6; 32]);
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 100..200 {
+        let res = engine2.put(get_test_key(i), get_test_value(i));
+        assert!(res.is_ok());
+    }
+    std::mem::drop(engine2);
+
+    let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(engine3.list_keys().unwrap().len(), 200);
+    assert_eq!(engine3.get(get_test_key(10)).unwrap(), get_test_value(10));
+    assert_eq!(engine3.get(get_test_key(150)).unwrap(), get_test_value(150));
+    assert_eq!(engine3.stat().unwrap().data_file_num, 2);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
 #[test]
 fn test_engine_stat() {
     let mut opts = Options::default();
@@ -255,11 +467,87 @@ fn test_engine_stat() {
 
     let stat = engine.stat().unwrap();
     assert!(stat.reclaim_size > 0);
+    assert_eq!(stat.index_key_num, 7000);
+    assert!(stat.index_memory_usage > 0);
 
     // 删除测试的文件夹
     std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
 }
 
+#[test]
+fn test_engine_stat_index_all_index() {
+    for (i, index_type) in [IndexType::BTree, IndexType::SkipList, IndexType::BPTree]
+        .into_iter()
+        .enumerate()
+    {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from(format!("/tmp/bitcask-rs-stat-index-{}", i));
+        opts.data_file_size = 64 * 1024 * 1024;
+        opts.index_type = index_type;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..100 {
+            assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        for i in 0..50 {
+            assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        for i in 0..20 {
+            assert!(engine.delete(get_test_key(i)).is_ok());
+        }
+
+        let stat = engine.stat().unwrap();
+        assert_eq!(stat.index_key_num, 80);
+        match opts.index_type {
+            IndexType::BPTree => assert_eq!(stat.index_memory_usage, 0),
+            _ => assert!(stat.index_memory_usage > 0),
+        }
+
+        // 重启之后数量不变
+        engine.close().expect("failed to close engine");
+        std::mem::drop(engine);
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine.stat().unwrap().index_key_num, 80);
+
+        std::mem::drop(engine);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+}
+
+#[test]
+fn test_engine_reclaim_size_all_index() {
+    // 覆盖和删除时返回的旧位置信息用于统计可回收的空间，所有的索引类型结果一致
+    for (i, index_type) in [IndexType::BTree, IndexType::SkipList, IndexType::BPTree]
+        .into_iter()
+        .enumerate()
+    {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from(format!("/tmp/bitcask-rs-reclaim-size-{}", i));
+        opts.data_file_size = 64 * 1024 * 1024;
+        opts.index_type = index_type;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        assert!(engine.put(get_test_key(1), get_test_value(1)).is_ok());
+        let size1 = engine.get_position(get_test_key(1)).unwrap().size;
+        assert!(engine.put(get_test_key(1), get_test_value(2)).is_ok());
+        let size2 = engine.get_position(get_test_key(1)).unwrap().size;
+        assert_eq!(engine.stat().unwrap().reclaim_size, size1 as usize);
+
+        assert!(engine.put(get_test_key(2), get_test_value(3)).is_ok());
+        assert!(engine.delete(get_test_key(1)).is_ok());
+        let reclaim_size = engine.stat().unwrap().reclaim_size;
+        // 还包括删除记录本身的大小
+        assert!(reclaim_size > (size1 + size2) as usize);
+
+        // 删除不存在的 key 不会写入数据
+        assert!(engine.delete(get_test_key(1)).is_ok());
+        assert_eq!(engine.stat().unwrap().reclaim_size, reclaim_size);
+
+        std::mem::drop(engine);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+}
+
 #[test]
 fn test_engine_backup() {
     let mut opts = Options::default();
@@ -291,3 +579,1339 @@ fn test_engine_backup() {
     std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
     std::fs::remove_dir_all(opts2.clone().dir_path).expect("failed to remove path");
 }
+
+#[test]
+fn test_engine_load_index_parallel() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-load-index-parallel");
+    opts.data_file_size = 64 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    for i in 0..5000 {
+        let res = engine.put(get_test_key(i), get_test_value(i));
+        assert!(res.is_ok());
+    }
+    for i in 0..1000 {
+        let res = engine.put(get_test_key(i), Bytes::from("new value"));
+        assert!(res.is_ok());
+    }
+    for i in 4000..5000 {
+        let res = engine.delete(get_test_key(i));
+        assert!(res.is_ok());
+    }
+    // 事务数据可能跨越多个数据文件
+    let wb = engine
+        .new_write_batch(WriteBatchOptions::default())
+        .expect("failed to create write batch");
+    for i in 5000..6000 {
+        let res = wb.put(get_test_key(i), get_test_value(i));
+        assert!(res.is_ok());
+    }
+    assert!(wb.commit().is_ok());
+    let seq_no = engine.seq_no.load(Ordering::SeqCst);
+    std::mem::drop(wb);
+    std::mem::drop(engine);
+
+    // 多个线程并行加载索引
+    opts.load_index_threads = 4;
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert!(engine2.older_files.read().len() > 4);
+    assert_eq!(engine2.seq_no.load(Ordering::SeqCst), seq_no);
+    assert_eq!(engine2.list_keys().unwrap().len(), 5000);
+    for i in 0..1000 {
+        let res = engine2.get(get_test_key(i));
+        assert_eq!(res.ok().unwrap(), Bytes::from("new value"));
+    }
+    for i in 1000..4000 {
+        let res = engine2.get(get_test_key(i));
+        assert_eq!(res.ok().unwrap(), get_test_value(i));
+    }
+    for i in 4000..5000 {
+        let res = engine2.get(get_test_key(i));
+        assert_eq!(Errors::KeyNotFound, res.err().unwrap());
+    }
+    for i in 5000..6000 {
+        let res = engine2.get(get_test_key(i));
+        assert_eq!(res.ok().unwrap(), get_test_value(i));
+    }
+
+    // 重启之后继续写入活跃文件
+    let res = engine2.put(get_test_key(6000), get_test_value(6000));
+    assert!(res.is_ok());
+    std::mem::drop(engine2);
+
+    opts.load_index_threads = 0;
+    let res = Engine::open(opts.clone());
+    assert_eq!(Errors::InvaildLoadIndexThreads, res.err().unwrap());
+
+    opts.load_index_threads = 4;
+    let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(engine3.list_keys().unwrap().len(), 5001);
+
+    // 删除测试的文件夹
+    std::mem::drop(engine3);
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_sync_interval() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-sync-interval");
+    opts.data_file_size = 64 * 1024 * 1024;
+
+    opts.sync_interval = Some(Duration::ZERO);
+    let res = Engine::open(opts.clone());
+    assert_eq!(Errors::InvaildSyncInterval, res.err().unwrap());
+
+    opts.sync_interval = Some(Duration::from_millis(10));
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..100 {
+        let res = engine.put(get_test_key(i), get_test_value(i));
+        assert!(res.is_ok());
+    }
+    assert!(engine.bytes_write.load(Ordering::SeqCst) > 0);
+
+    // 后台线程持久化之后清空累计值
+    let mut synced = false;
+    for _ in 0..100 {
+        std::thread::sleep(Duration::from_millis(10));
+        if engine.bytes_write.load(Ordering::SeqCst) == 0 {
+            synced = true;
+            break;
+        }
+    }
+    assert!(synced);
+
+    // 关闭之后后台线程退出
+    assert!(engine.close().is_ok());
+    std::mem::drop(engine);
+
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(engine2.list_keys().unwrap().len(), 100);
+
+    // 删除测试的文件夹
+    std::mem::drop(engine2);
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_group_commit() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-group-commit");
+    opts.data_file_size = 64 * 1024;
+    opts.sync_writes = true;
+    let engine = Arc::new(Engine::open(opts.clone()).expect("failed to open engine"));
+
+    // 多个线程并发写入，写入会被合并成一组并且只持久化一次
+    let mut handles = Vec::new();
+    for t in 0..8 {
+        let engine = engine.clone();
+        handles.push(std::thread::spawn(move || {
+            for i in t * 500..(t + 1) * 500 {
+                let res = engine.put(get_test_key(i), get_test_value(i));
+                assert!(res.is_ok());
+            }
+        }));
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    assert!(engine.older_files.read().len() > 1);
+    for i in 0..4000 {
+        let res = engine.get(get_test_key(i));
+        assert_eq!(res.ok().unwrap(), get_test_value(i));
+    }
+
+    // 重启校验
+    std::mem::drop(engine);
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(engine2.list_keys().unwrap().len(), 4000);
+    for i in 0..4000 {
+        let res = engine2.get(get_test_key(i));
+        assert_eq!(res.ok().unwrap(), get_test_value(i));
+    }
+
+    // 删除测试的文件夹
+    std::mem::drop(engine2);
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_read_at() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-read-at");
+    opts.data_file_size = 64 * 1024 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // key 不存在
+    assert!(engine.get_position(get_test_key(1)).is_none());
+    assert!(engine.get_position(Bytes::new()).is_none());
+
+    let res1 = engine.put(get_test_key(1), get_test_value(1));
+    assert!(res1.is_ok());
+    let location1 = engine.get_position(get_test_key(1)).unwrap();
+    assert_eq!(engine.read_at(location1).unwrap(), get_test_value(1));
+
+    // 更新之后旧的位置仍然可以读取到旧的 value
+    let res2 = engine.put(get_test_key(1), Bytes::from("new value"));
+    assert!(res2.is_ok());
+    let location2 = engine.get_position(get_test_key(1)).unwrap();
+    assert_ne!(location1, location2);
+    assert_eq!(engine.read_at(location2).unwrap(), Bytes::from("new value"));
+    assert_eq!(engine.read_at(location1).unwrap(), get_test_value(1));
+
+    // 删除之后不再有位置信息
+    let res3 = engine.delete(get_test_key(1));
+    assert!(res3.is_ok());
+    assert!(engine.get_position(get_test_key(1)).is_none());
+
+    // 无效的位置
+    let mut location3 = location2;
+    location3.size += 1;
+    assert_eq!(
+        Errors::InvaildRecordLocation,
+        engine.read_at(location3).err().unwrap()
+    );
+    location3 = location2;
+    location3.offset += 1;
+    assert!(engine.read_at(location3).is_err());
+    location3 = location2;
+    location3.offset = 64 * 1024;
+    assert_eq!(
+        Errors::InvaildRecordLocation,
+        engine.read_at(location3).err().unwrap()
+    );
+    location3 = location2;
+    location3.file_id = 100;
+    assert_eq!(
+        Errors::DataFileNotFound,
+        engine.read_at(location3).err().unwrap()
+    );
+
+    // 重启之后位置仍然有效
+    std::mem::drop(engine);
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(
+        engine2.read_at(location2).unwrap(),
+        Bytes::from("new value")
+    );
+
+    // 删除测试的文件夹
+    std::mem::drop(engine2);
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_get_with_meta() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-get-with-meta");
+    opts.data_file_size = 64 * 1024 * 1024;
+    opts.data_file_merge_ratio = 0 as f32;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    let res1 = engine.get_with_meta(get_test_key(1));
+    assert_eq!(Errors::KeyNotFound, res1.err().unwrap());
+    let res2 = engine.get_with_meta(Bytes::new());
+    assert_eq!(Errors::KeyIsEmpty, res2.err().unwrap());
+
+    let before = current_timestamp();
+    assert!(engine.put(get_test_key(1), get_test_value(1)).is_ok());
+    let after = current_timestamp();
+    let (value, meta) = engine.get_with_meta(get_test_key(1)).unwrap();
+    assert_eq!(value, get_test_value(1));
+    assert!(meta.timestamp >= before && meta.timestamp <= after);
+    let location = engine.get_position(get_test_key(1)).unwrap();
+    assert_eq!(meta.size, location.size);
+    assert_eq!(meta.file_id, location.file_id);
+
+    // 覆盖写入之后更新写入时间
+    std::thread::sleep(Duration::from_millis(5));
+    assert!(engine.put(get_test_key(1), get_test_value(2)).is_ok());
+    let (value2, meta2) = engine.get_with_meta(get_test_key(1)).unwrap();
+    assert_eq!(value2, get_test_value(2));
+    assert!(meta2.timestamp > meta.timestamp);
+
+    // 批量写入的数据使用提交时的时间
+    let wb = engine
+        .new_write_batch(WriteBatchOptions::default())
+        .expect("failed to create write batch");
+    assert!(wb.put(get_test_key(2), get_test_value(2)).is_ok());
+    assert!(wb.put(get_test_key(3), get_test_value(3)).is_ok());
+    assert!(wb.commit().is_ok());
+    let (_, meta3) = engine.get_with_meta(get_test_key(2)).unwrap();
+    let (_, meta4) = engine.get_with_meta(get_test_key(3)).unwrap();
+    assert!(meta3.timestamp >= meta2.timestamp);
+    assert_eq!(meta3.timestamp, meta4.timestamp);
+
+    assert!(engine.delete(get_test_key(3)).is_ok());
+    let res3 = engine.get_with_meta(get_test_key(3));
+    assert_eq!(Errors::KeyNotFound, res3.err().unwrap());
+
+    // 重启和 merge 之后写入时间保持不变
+    std::mem::drop(wb);
+    std::mem::drop(engine);
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(engine2.get_with_meta(get_test_key(1)).unwrap().1, meta2);
+    assert!(engine2.merge().is_ok());
+    std::mem::drop(engine2);
+    let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+    let (value5, meta5) = engine3.get_with_meta(get_test_key(1)).unwrap();
+    assert_eq!(value5, get_test_value(2));
+    assert_eq!(meta5.timestamp, meta2.timestamp);
+    assert_eq!(
+        engine3.get_with_meta(get_test_key(2)).unwrap().1.timestamp,
+        meta3.timestamp
+    );
+
+    std::mem::drop(engine3);
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+// 按照 key 末尾的数字排序，没有数字后缀的 key 按照字节序排在前面
+fn numeric_suffix_comparator(a: &[u8], b: &[u8]) -> std::cmp::Ordering {
+    fn split(key: &[u8]) -> (&[u8], Option<u64>) {
+        let pos = key
+            .iter()
+            .rposition(|c| !c.is_ascii_digit())
+            .map_or(0, |p| p + 1);
+        let num = std::str::from_utf8(&key[pos..])
+            .ok()
+            .and_then(|n| n.parse().ok());
+        (&key[..pos], num)
+    }
+    let (a_prefix, a_num) = split(a);
+    let (b_prefix, b_num) = split(b);
+    a_prefix
+        .cmp(b_prefix)
+        .then_with(|| a_num.cmp(&b_num))
+        .then_with(|| a.cmp(b))
+}
+
+#[test]
+fn test_engine_key_comparator() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-key-comparator");
+    opts.data_file_size = 64 * 1024 * 1024;
+    opts.key_comparator = Some(numeric_suffix_comparator);
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    for i in [10, 2, 1, 30, 3] {
+        let res = engine.put(Bytes::from(format!("item-{}", i)), get_test_value(i));
+        assert!(res.is_ok());
+    }
+    assert!(engine.put(Bytes::from("other"), get_test_value(0)).is_ok());
+    let bucket = engine.bucket("b").unwrap();
+    assert!(bucket.put(Bytes::from("k"), get_test_value(0)).is_ok());
+
+    let expected: Vec<Bytes> = [1, 2, 3, 10, 30]
+        .iter()
+        .map(|i| Bytes::from(format!("item-{}", i)))
+        .collect();
+    let mut all = expected.clone();
+    all.push(Bytes::from("other"));
+    assert_eq!(engine.list_keys().unwrap(), all);
+
+    // 按照前缀遍历，前缀相同的 key 按照自定义规则排序
+    let mut iter = engine.iter(IteratorOptions {
+        prefix: b"item-".to_vec(),
+        reverse: false,
+        ..Default::default()
+    });
+    let mut keys = Vec::new();
+    while let Some((key, _)) = iter.next() {
+        keys.push(key);
+    }
+    assert_eq!(keys, expected);
+
+    // seek 同样按照自定义规则查找
+    iter.seek(b"item-4".to_vec());
+    assert_eq!(iter.next().unwrap().0, Bytes::from("item-10"));
+
+    let mut rev_iter = engine.iter(IteratorOptions {
+        prefix: b"item-".to_vec(),
+        reverse: true,
+        ..Default::default()
+    });
+    rev_iter.seek(b"item-9".to_vec());
+    assert_eq!(rev_iter.next().unwrap().0, Bytes::from("item-3"));
+    assert_eq!(rev_iter.next().unwrap().0, Bytes::from("item-2"));
+
+    // 事务迭代器
+    let txn = engine.begin();
+    assert!(txn.put(Bytes::from("txn-20"), get_test_value(20)).is_ok());
+    assert!(txn.put(Bytes::from("txn-9"), get_test_value(9)).is_ok());
+    assert!(txn.commit().is_ok());
+    let txn2 = engine.begin();
+    let mut txn_iter = txn2.iter(IteratorOptions::default());
+    txn_iter.seek(b"txn-10".to_vec());
+    assert_eq!(txn_iter.next().unwrap().0, Bytes::from("txn-20"));
+    assert!(txn2.rollback().is_ok());
+    assert_eq!(engine.list_buckets().unwrap(), vec!["b".to_string()]);
+
+    // 重启之后顺序不变
+    std::mem::drop((iter, rev_iter, txn, txn2));
+    std::mem::drop(engine);
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(engine2.list_keys().unwrap(), all);
+    std::mem::drop(engine2);
+
+    // 只支持 BTree 索引
+    let mut opts2 = opts.clone();
+    opts2.index_type = IndexType::SkipList;
+    let res = Engine::open(opts2);
+    assert_eq!(
+        Errors::KeyComparatorUnsupportedIndexType,
+        res.err().unwrap()
+    );
+
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_stats_persisted() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-stats-persisted");
+    opts.data_file_size = 64 * 1024;
+    opts.index_type = IndexType::BPTree;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    for i in 0..1000 {
+        assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+    }
+    for i in 0..300 {
+        assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+    }
+    for i in 900..1000 {
+        assert!(engine.delete(get_test_key(i)).is_ok());
+    }
+    // bucket 中的 key 不计入 key 的数量
+    let bucket = engine.bucket("b").unwrap();
+    assert!(bucket.put(get_test_key(1), get_test_value(1)).is_ok());
+
+    let stat = engine.stat().unwrap();
+    assert_eq!(stat.key_num, 900);
+    assert!(stat.reclaim_size > 0);
+    let file_stats = engine.file_stats().unwrap();
+    assert!(file_stats.len() > 1);
+    let dead_size: u64 = file_stats.iter().map(|f| f.dead_size).sum();
+    assert_eq!(dead_size as usize, stat.reclaim_size);
+    assert!(file_stats[0].live_size > 0 && file_stats[0].dead_size > 0);
+
+    // B+ 树索引重启之后不会扫描数据文件，直接使用持久化的统计信息
+    std::mem::drop(engine);
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    let stat2 = engine2.stat().unwrap();
+    assert_eq!(stat2.key_num, 900);
+    assert_eq!(stat2.reclaim_size, stat.reclaim_size);
+    assert_eq!(engine2.file_stats().unwrap(), file_stats);
+    assert!(!opts.dir_path.join("stats").exists());
+
+    // 统计信息文件不存在时（异常退出），重新统计 key 的数量
+    std::mem::drop(engine2);
+    std::fs::remove_file(opts.dir_path.join("stats")).expect("failed to remove stats file");
+    let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+    let stat3 = engine3.stat().unwrap();
+    assert_eq!(stat3.key_num, 900);
+    assert_eq!(stat3.reclaim_size, 0);
+    std::mem::drop(engine3);
+
+    // 内存索引在加载索引时重新计算统计信息
+    let mut opts2 = Options::default();
+    opts2.dir_path = PathBuf::from("/tmp/bitcask-rs-stats-persisted-btree");
+    opts2.data_file_size = 64 * 1024;
+    let engine4 = Engine::open(opts2.clone()).expect("failed to open engine");
+    for i in 0..500 {
+        assert!(engine4.put(get_test_key(i), get_test_value(i)).is_ok());
+    }
+    for i in 0..100 {
+        assert!(engine4.delete(get_test_key(i)).is_ok());
+    }
+    let stat4 = engine4.stat().unwrap();
+    let file_stats4 = engine4.file_stats().unwrap();
+    std::mem::drop(engine4);
+    let engine5 = Engine::open(opts2.clone()).expect("failed to open engine");
+    let stat5 = engine5.stat().unwrap();
+    assert_eq!(stat5.key_num, 400);
+    assert_eq!(stat5.reclaim_size, stat4.reclaim_size);
+    assert_eq!(engine5.file_stats().unwrap(), file_stats4);
+
+    std::mem::drop(engine5);
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    std::fs::remove_dir_all(opts2.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_fsync_dir() {
+    for fsync_dir in [true, false] {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from(format!("/tmp/bitcask-rs-fsync-dir-{}", fsync_dir));
+        opts.data_file_size = 64 * 1024;
+        opts.fsync_dir = fsync_dir;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        // 写入足够多的数据，触发活跃文件的切换
+        for i in 0..2000 {
+            assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        assert!(engine.file_stats().unwrap().len() > 1);
+        assert!(engine.close().is_ok());
+        std::mem::drop(engine);
+
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..2000 {
+            assert_eq!(engine2.get(get_test_key(i)).unwrap(), get_test_value(i));
+        }
+        std::mem::drop(engine2);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+}
+
+#[test]
+fn test_engine_write_stall() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-write-stall");
+    opts.data_file_size = 64 * 1024;
+    opts.write_stall_reclaim_size = Some(1024);
+    opts.write_stall_mode = WriteStallMode::Reject;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    for _ in 0..2 {
+        for i in 0..100 {
+            assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+    }
+    assert!(engine.stat().unwrap().reclaim_size > 1024);
+
+    // 没有进行 merge 时不限流
+    assert!(engine.put(get_test_key(1), get_test_value(1)).is_ok());
+    let merging = engine.merging_lock.lock();
+    assert_eq!(
+        engine
+            .put(get_test_key(1), get_test_value(1))
+            .err()
+            .unwrap(),
+        Errors::WriteStalled
+    );
+    // 只限制 put
+    assert!(engine.delete(get_test_key(1)).is_ok());
+    drop(merging);
+    assert!(engine.put(get_test_key(1), get_test_value(1)).is_ok());
+    std::mem::drop(engine);
+
+    // 等待一段时间之后继续写入
+    opts.write_stall_mode = WriteStallMode::Delay(Duration::from_millis(50));
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    let merging = engine2.merging_lock.lock();
+    let start = std::time::Instant::now();
+    assert!(engine2.put(get_test_key(1), get_test_value(1)).is_ok());
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    drop(merging);
+    assert_eq!(engine2.get(get_test_key(1)).unwrap(), get_test_value(1));
+
+    std::mem::drop(engine2);
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_open_registry() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-open-registry");
+    opts.data_file_size = 64 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    assert!(engine.put(get_test_key(1), get_test_value(1)).is_ok());
+
+    // 同一个目录的不同写法
+    let mut opts2 = opts.clone();
+    opts2.dir_path = PathBuf::from("/tmp/./bitcask-rs-open-registry/../bitcask-rs-open-registry");
+    assert_eq!(
+        Engine::open(opts2.clone()).err().unwrap(),
+        Errors::DatabaseIsUsing
+    );
+    let start = std::time::Instant::now();
+    assert_eq!(
+        Engine::try_open(opts2.clone(), Duration::from_millis(50))
+            .err()
+            .unwrap(),
+        Errors::DatabaseIsUsing
+    );
+    assert!(start.elapsed() >= Duration::from_millis(50));
+
+    // 只读模式不占用数据目录
+    let mut ro_opts = opts.clone();
+    ro_opts.read_only = true;
+    let ro_engine = Engine::open(ro_opts).expect("failed to open engine");
+    std::mem::drop(ro_engine);
+
+    // 关闭之后可以重新打开
+    let handle = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(engine.close().is_ok());
+        engine
+    });
+    let engine2 =
+        Engine::try_open(opts2.clone(), Duration::from_secs(10)).expect("failed to open engine");
+    assert_eq!(engine2.get(get_test_key(1)).unwrap(), get_test_value(1));
+    std::mem::drop(handle.join().unwrap());
+
+    std::mem::drop(engine2);
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_prefix_stat() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-prefix-stat");
+    opts.data_file_size = 64 * 1024 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    for i in 0..10 {
+        let key = Bytes::from(format!("tenant-a/{}", i));
+        assert!(engine.put(key, get_test_value(i)).is_ok());
+    }
+    for i in 0..5 {
+        let key = Bytes::from(format!("tenant-b/{}", i));
+        assert!(engine.put(key, Bytes::from("v")).is_ok());
+    }
+    assert!(engine.delete(Bytes::from("tenant-a/0")).is_ok());
+    // 覆盖写入只统计最新的数据
+    assert!(engine
+        .put(Bytes::from("tenant-b/0"), Bytes::from("v"))
+        .is_ok());
+    // bucket 中的 key 不计入统计
+    let bucket = engine.bucket("tenant-a").unwrap();
+    assert!(bucket.put(get_test_key(1), get_test_value(1)).is_ok());
+
+    let stat_a = engine.prefix_stat(Bytes::from("tenant-a/")).unwrap();
+    assert_eq!(stat_a.key_count, 9);
+    assert!(stat_a.approx_bytes > 9 * get_test_value(1).len() as u64);
+    let stat_b = engine.prefix_stat(Bytes::from("tenant-b/")).unwrap();
+    assert_eq!(stat_b.key_count, 5);
+    assert!(stat_b.approx_bytes > 0 && stat_b.approx_bytes < stat_a.approx_bytes);
+
+    let all = engine.prefix_stat(Bytes::new()).unwrap();
+    assert_eq!(all.key_count, 14);
+    assert_eq!(all.approx_bytes, stat_a.approx_bytes + stat_b.approx_bytes);
+    assert_eq!(
+        engine.prefix_stat(Bytes::from("tenant-c/")).unwrap(),
+        Default::default()
+    );
+
+    std::mem::drop(bucket);
+    std::mem::drop(engine);
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_size_histogram() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-size-histogram");
+    opts.data_file_size = 64 * 1024 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    for i in 0..10 {
+        let key = Bytes::from(format!("k{}", i));
+        assert!(engine.put(key, Bytes::from(vec![b'v'; 100])).is_ok());
+    }
+    for i in 0..5 {
+        let key = Bytes::from(format!("long-key-{:08}", i));
+        assert!(engine.put(key, Bytes::from(vec![b'v'; 1000])).is_ok());
+    }
+    // bucket 中的 key 不计入统计
+    let bucket = engine.bucket("users").unwrap();
+    assert!(bucket.put(get_test_key(1), get_test_value(1)).is_ok());
+
+    let key_sizes = engine.key_size_histogram().unwrap();
+    assert_eq!(key_sizes.count, 15);
+    assert_eq!(key_sizes.total, 10 * 2 + 5 * 17);
+    assert_eq!(key_sizes.max, 17);
+    assert_eq!(
+        key_sizes.buckets,
+        vec![
+            HistogramBucket {
+                upper_bound: 2,
+                count: 10
+            },
+            HistogramBucket {
+                upper_bound: 32,
+                count: 5
+            },
+        ]
+    );
+
+    // value 的大小包含记录头部的开销
+    let value_sizes = engine.value_size_histogram().unwrap();
+    assert_eq!(value_sizes.count, 15);
+    assert_eq!(value_sizes.buckets.len(), 2);
+    assert_eq!(value_sizes.buckets[0].upper_bound, 128);
+    assert_eq!(value_sizes.buckets[0].count, 10);
+    assert_eq!(value_sizes.buckets[1].upper_bound, 1024);
+    assert_eq!(value_sizes.buckets[1].count, 5);
+
+    std::mem::drop(bucket);
+    std::mem::drop(engine);
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_file_rotation() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-file-rotation");
+    opts.data_file_size = 64 * 1024 * 1024;
+
+    opts.file_rotation.max_records = Some(0);
+    let res = Engine::open(opts.clone());
+    assert_eq!(Errors::InvaildFileRotation, res.err().unwrap());
+    opts.file_rotation = FileRotation {
+        max_records: None,
+        max_age: Some(Duration::ZERO),
+    };
+    let res = Engine::open(opts.clone());
+    assert_eq!(Errors::InvaildFileRotation, res.err().unwrap());
+
+    // 按照记录数量轮转
+    opts.file_rotation = FileRotation {
+        max_records: Some(100),
+        max_age: None,
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..250 {
+        assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+    }
+    assert_eq!(engine.stat().unwrap().data_file_num, 3);
+
+    // 重启之后继续累计活跃文件中的记录数量
+    std::mem::drop(engine);
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 250..300 {
+        assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+    }
+    assert_eq!(engine.stat().unwrap().data_file_num, 3);
+    assert!(engine.put(get_test_key(300), get_test_value(300)).is_ok());
+    assert_eq!(engine.stat().unwrap().data_file_num, 4);
+    for i in 0..=300 {
+        assert_eq!(
+            engine.get(get_test_key(i)).unwrap(),
+            get_test_value(i).slice(..)
+        );
+    }
+
+    // 按照写入时间轮转，空的活跃文件不轮转
+    std::mem::drop(engine);
+    opts.file_rotation = FileRotation {
+        max_records: None,
+        max_age: Some(Duration::from_millis(50)),
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    let data_file_num = engine.stat().unwrap().data_file_num;
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(engine.put(get_test_key(1), get_test_value(1)).is_ok());
+    assert_eq!(engine.stat().unwrap().data_file_num, data_file_num + 1);
+    assert!(engine.put(get_test_key(2), get_test_value(2)).is_ok());
+    assert_eq!(engine.stat().unwrap().data_file_num, data_file_num + 1);
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(engine.put(get_test_key(3), get_test_value(3)).is_ok());
+    assert_eq!(engine.stat().unwrap().data_file_num, data_file_num + 2);
+
+    std::mem::drop(engine);
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_invalid_record_type() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-invalid-record-type");
+    opts.data_file_size = 64 * 1024 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..10 {
+        assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+    }
+    let location = engine.get_position(get_test_key(5)).unwrap();
+    std::mem::drop(engine);
+
+    // 修改第 5 条记录的类型字节，保留最高两位的标志位
+    let file_path = get_data_file_name(opts.dir_path.clone(), location.file_id);
+    let mut content = std::fs::read(&file_path).unwrap();
+    let type_byte = &mut content[location.offset as usize];
+    *type_byte = (*type_byte & 0xc0) | 0x3f;
+    std::fs::write(&file_path, &content).unwrap();
+
+    // 默认打开失败，返回损坏的位置
+    assert_eq!(
+        Errors::DataFileCorrupted {
+            file_id: location.file_id,
+            offset: location.offset,
+        },
+        Engine::open(opts.clone()).err().unwrap()
+    );
+
+    // 跳过损坏的记录，之后的记录仍然可以读取
+    opts.invalid_record_type = InvalidRecordTypeMode::Skip;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(engine.list_keys().unwrap().len(), 9);
+    assert_eq!(
+        Errors::KeyNotFound,
+        engine.get(get_test_key(5)).err().unwrap()
+    );
+    assert_eq!(engine.get(get_test_key(9)).unwrap(), get_test_value(9));
+    std::mem::drop(engine);
+
+    // 忽略损坏的记录以及之后的记录
+    opts.invalid_record_type = InvalidRecordTypeMode::Stop;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(engine.list_keys().unwrap().len(), 5);
+    assert_eq!(engine.get(get_test_key(4)).unwrap(), get_test_value(4));
+    assert_eq!(
+        Errors::KeyNotFound,
+        engine.get(get_test_key(6)).err().unwrap()
+    );
+
+    std::mem::drop(engine);
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_startup_verify() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-startup-verify");
+    opts.data_file_size = 32 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..1000 {
+        assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+    }
+    let older = engine.get_position(get_test_key(0)).unwrap();
+    let older_key = engine.get_position(get_test_key(2)).unwrap();
+    let active = engine.get_position(get_test_key(999)).unwrap();
+    assert_ne!(older.file_id, active.file_id);
+    assert_eq!(older.file_id, older_key.file_id);
+    std::mem::drop(engine);
+
+    // 修改记录 value 的最后一个字节，CRC 校验失败
+    let corrupt = |location: RecordLocation| {
+        let file_path = get_data_file_name(opts.dir_path.clone(), location.file_id);
+        let mut content = std::fs::read(&file_path).unwrap();
+        content[(location.offset + location.size - 5) as usize] ^= 0xff;
+        std::fs::write(&file_path, &content).unwrap();
+    };
+
+    // 旧的数据文件损坏，只有完整校验时打开失败
+    corrupt(older);
+    assert_eq!(
+        Errors::DataFileCorrupted {
+            file_id: older.file_id,
+            offset: older.offset,
+        },
+        Engine::open(opts.clone()).err().unwrap()
+    );
+    for verify in [StartupVerify::Off, StartupVerify::ChecksumActiveFile] {
+        opts.startup_verify = verify;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine.list_keys().unwrap().len(), 1000);
+        assert_eq!(
+            Errors::InvaildLogRecordCrc,
+            engine.get(get_test_key(0)).err().unwrap()
+        );
+        assert_eq!(engine.get(get_test_key(1)).unwrap(), get_test_value(1));
+        std::mem::drop(engine);
+    }
+
+    // 活跃文件损坏，只有关闭校验时才能打开
+    corrupt(active);
+    opts.startup_verify = StartupVerify::ChecksumActiveFile;
+    assert_eq!(
+        Errors::DataFileCorrupted {
+            file_id: active.file_id,
+            offset: active.offset,
+        },
+        Engine::open(opts.clone()).err().unwrap()
+    );
+    opts.startup_verify = StartupVerify::Off;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(
+        Errors::InvaildLogRecordCrc,
+        engine.get(get_test_key(999)).err().unwrap()
+    );
+    std::mem::drop(engine);
+
+    // 不校验 CRC 时记录的 key 损坏，解析 key 失败返回错误而不是 panic
+    let file_path = get_data_file_name(opts.dir_path.clone(), older_key.file_id);
+    let mut content = std::fs::read(&file_path).unwrap();
+    let enc_key = log_record_key_with_seq(&get_test_key(2), NON_TRANSACTION_SEQ_NO);
+    let start = older_key.offset as usize;
+    let end = (older_key.offset + older_key.size) as usize;
+    let key_offset = start
+        + content[start..end]
+            .windows(enc_key.len())
+            .position(|window| window == enc_key.as_slice())
+            .unwrap();
+    content[key_offset..key_offset + 10].fill(0xff);
+    std::fs::write(&file_path, &content).unwrap();
+    assert_eq!(
+        Errors::DataFileCorrupted {
+            file_id: older_key.file_id,
+            offset: older_key.offset,
+        },
+        Engine::open(opts.clone()).err().unwrap()
+    );
+
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_preallocate() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-preallocate");
+    opts.data_file_size = 1024 * 1024;
+    opts.preallocate = true;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // 写满第一个数据文件，轮转之后的活跃文件同样预先分配空间
+    let mut i = 0;
+    while engine.stat().unwrap().data_file_num < 2 {
+        assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+        i += 1;
+    }
+
+    // 预分配不改变文件大小，文件大小和写入的位置一致
+    let active_file_path = get_data_file_name(opts.dir_path.clone(), 1);
+    let metadata = std::fs::metadata(&active_file_path).unwrap();
+    assert_eq!(metadata.len(), engine.active_file.read().get_write_off());
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::fs::MetadataExt;
+        assert!(metadata.blocks() * 512 >= opts.data_file_size);
+    }
+
+    // 重启之后可以读取到所有的数据并继续写入
+    std::mem::drop(engine);
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+    for j in 0..=i {
+        assert_eq!(engine.get(get_test_key(j)).unwrap(), get_test_value(j));
+    }
+
+    std::mem::drop(engine);
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_runtime_option() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-runtime-option");
+    opts.data_file_size = 64 * 1024 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    let effective = engine.options();
+    assert_eq!(effective.sync_writes, false);
+    assert_eq!(effective.bytes_per_sync, 0);
+    assert_eq!(effective.data_file_merge_ratio, opts.data_file_merge_ratio);
+
+    assert!(engine
+        .set_runtime_option(RuntimeOption::SyncWrites(true))
+        .is_ok());
+    assert!(engine
+        .set_runtime_option(RuntimeOption::BytesPerSync(4096))
+        .is_ok());
+    assert!(engine
+        .set_runtime_option(RuntimeOption::DataFileMergeRatio(0.0))
+        .is_ok());
+    assert_eq!(
+        engine
+            .set_runtime_option(RuntimeOption::DataFileMergeRatio(1.5))
+            .err()
+            .unwrap(),
+        Errors::InvaildDataFileMergeRatio
+    );
+
+    let effective = engine.options();
+    assert_eq!(effective.sync_writes, true);
+    assert_eq!(effective.bytes_per_sync, 4096);
+    assert_eq!(effective.data_file_merge_ratio, 0.0);
+
+    // 每次写都持久化，写入之后没有累计未持久化的数据
+    assert!(engine.put(get_test_key(1), get_test_value(1)).is_ok());
+    assert_eq!(engine.bytes_write.load(Ordering::SeqCst), 0);
+
+    // 调整之后的 merge 阈值立即生效
+    for i in 0..1000 {
+        assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+    }
+    assert!(engine.merge().is_ok());
+
+    // 重新打开之后恢复为 Options 中的值
+    std::mem::drop(engine);
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(engine.options().sync_writes, false);
+    assert_eq!(engine.options().bytes_per_sync, 0);
+
+    std::mem::drop(engine);
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_write_seq() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-write-seq");
+    opts.data_file_size = 64 * 1024 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // put 和批量提交返回的序列号单调递增，记录的写入时间是序列号中的物理时间
+    let mut last_seq = engine.current_seq();
+    for i in 0..100 {
+        let seq = engine.put(get_test_key(i), get_test_value(i)).unwrap();
+        assert!(seq > last_seq);
+        assert_eq!(engine.current_seq(), seq);
+        let (_, meta) = engine.get_with_meta(get_test_key(i)).unwrap();
+        assert_eq!(meta.timestamp, hlc::physical_time(seq));
+        last_seq = seq;
+    }
+
+    let wb = engine
+        .new_write_batch(WriteBatchOptions::default())
+        .unwrap();
+    assert!(wb.put(get_test_key(100), get_test_value(100)).is_ok());
+    assert!(wb.delete(get_test_key(1)).is_ok());
+    let batch_seq = wb.commit().unwrap();
+    assert!(batch_seq > last_seq);
+    // 没有需要写入的数据时返回当前的序列号
+    assert_eq!(wb.commit().unwrap(), batch_seq);
+
+    // 删除同样会推进序列号
+    assert!(engine.delete(get_test_key(2)).is_ok());
+    assert!(engine.current_seq() > batch_seq);
+    let last_seq = engine.current_seq();
+
+    // 重新打开之后序列号仍然递增
+    std::mem::drop(engine);
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    assert!(engine.current_seq() >= hlc::physical_time(last_seq) << 16);
+    let seq = engine.put(get_test_key(1), get_test_value(1)).unwrap();
+    assert!(seq > last_seq);
+
+    std::mem::drop(engine);
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_record_near_data_file_size() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-record-size");
+    opts.data_file_size = 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // 找到正好可以放满一个空数据文件的 value 大小
+    let key = Bytes::from("key");
+    let record_size = |value_len: usize| {
+        LogRecord {
+            key: log_record_key_with_seq(&key, NON_TRANSACTION_SEQ_NO),
+            value: vec![0; value_len],
+            rec_type: LogRecordType::NORMAL,
+            timestamp: current_timestamp(),
+            value_pointer: false,
+        }
+        .encode()
+        .len() as u64
+    };
+    let mut value_len = 0;
+    while DATA_FILE_HEADER_SIZE + record_size(value_len + 1) <= opts.data_file_size {
+        value_len += 1;
+    }
+
+    // 正好放满数据文件的记录可以写入，每条记录单独占用一个数据文件，不会产生空的数据文件
+    for _ in 0..3 {
+        let value = Bytes::from(vec![b'a'; value_len]);
+        assert!(engine.put(key.clone(), value).is_ok());
+    }
+    assert_eq!(engine.get(key.clone()).unwrap().len(), value_len);
+    assert_eq!(engine.older_files.read().len(), 2);
+    for file_id in 0..3 {
+        let file_size = std::fs::metadata(get_data_file_name(opts.dir_path.clone(), file_id))
+            .unwrap()
+            .len();
+        assert_eq!(file_size, opts.data_file_size);
+    }
+
+    // 超过一个字节的记录无法写入任何数据文件
+    let res = engine.put(key.clone(), Bytes::from(vec![b'b'; value_len + 1]));
+    assert_eq!(
+        res.err().unwrap(),
+        Errors::RecordTooLargeForDataFile {
+            size: record_size(value_len + 1),
+            data_file_size: opts.data_file_size,
+        }
+    );
+    let wb = engine
+        .new_write_batch(WriteBatchOptions::default())
+        .unwrap();
+    assert!(wb
+        .put(key.clone(), Bytes::from(vec![b'b'; value_len + 1]))
+        .is_ok());
+    assert!(matches!(
+        wb.commit(),
+        Err(Errors::RecordTooLargeForDataFile { .. })
+    ));
+    assert_eq!(engine.get(key.clone()).unwrap().len(), value_len);
+
+    // 小的记录继续写入新的数据文件
+    assert!(engine.put(get_test_key(1), get_test_value(1)).is_ok());
+    assert_eq!(engine.older_files.read().len(), 3);
+
+    // 数据文件的大小必须大于数据文件头部的大小
+    let mut invalid_opts = opts.clone();
+    invalid_opts.data_file_size = DATA_FILE_HEADER_SIZE;
+    assert_eq!(
+        Engine::open(invalid_opts).err().unwrap(),
+        Errors::DataFileSizeTooSmall
+    );
+
+    std::mem::drop(engine);
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_read_during_rotation() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-read-during-rotation");
+    opts.data_file_size = 64 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..1000 {
+        assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+    }
+
+    // 读取时拿到的数据文件句柄不持有锁，活跃文件切换之后仍然可以继续读取
+    let active_file_id = engine.active_file.read().get_file_id();
+    let data_file = engine.get_data_file(active_file_id).unwrap();
+    for i in 1000..2000 {
+        assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+    }
+    assert!(engine.active_file.read().get_file_id() > active_file_id);
+    let pos = engine.index.get(get_test_key(999).to_vec()).unwrap();
+    assert_eq!(pos.file_id, active_file_id);
+    let record = data_file.read_log_record(pos.offset).unwrap().record;
+    assert_eq!(record.value, get_test_value(999).to_vec());
+
+    // 并发读取和写入，写入不断切换活跃文件
+    let mut handles = Vec::new();
+    for t in 0..4 {
+        let engine = engine.clone();
+        handles.push(std::thread::spawn(move || {
+            for i in 0..2000 {
+                let key = get_test_key((i + t * 500) % 2000);
+                assert!(engine.get(key).is_ok());
+            }
+        }));
+    }
+    for i in 2000..4000 {
+        assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    std::mem::drop(data_file);
+    std::mem::drop(engine);
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_max_open_files() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-max-open-files");
+    opts.data_file_size = 16 * 1024;
+    opts.max_open_files = Some(2);
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..2000 {
+        assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+    }
+    assert!(engine.older_files.read().len() > 10);
+
+    // 旧数据文件按需打开，同时打开的文件数量不超过上限
+    let file_cache = engine.file_cache.clone().unwrap();
+    for i in 0..2000 {
+        assert_eq!(engine.get(get_test_key(i)).unwrap(), get_test_value(i));
+        assert!(file_cache.open_files() <= 2);
+    }
+
+    // 重启之后加载索引以及读取同样不超过上限
+    std::mem::drop(engine);
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    let file_cache = engine.file_cache.clone().unwrap();
+    assert!(file_cache.open_files() <= 2);
+    for i in (0..2000).rev() {
+        assert_eq!(engine.get(get_test_key(i)).unwrap(), get_test_value(i));
+        assert!(file_cache.open_files() <= 2);
+    }
+
+    opts.max_open_files = Some(0);
+    assert_eq!(
+        Engine::open(opts.clone()).err().unwrap(),
+        Errors::InvaildMaxOpenFiles
+    );
+
+    std::mem::drop(engine);
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_pin_data_file_during_merge() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-pin-data-file");
+    opts.data_file_size = 32 * 1024;
+    opts.max_open_files = Some(1);
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..500 {
+        assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+    }
+    for i in 0..500 {
+        assert!(engine.put(get_test_key(i), Bytes::from("new")).is_ok());
+    }
+
+    // 持有旧数据文件的句柄，读取其他文件之后这个文件的句柄被缓存关闭
+    let data_file = engine.get_data_file(0).unwrap();
+    let other_file = engine.get_data_file(1).unwrap();
+    assert!(other_file.read_log_record(DATA_FILE_HEADER_SIZE).is_ok());
+    std::mem::drop(other_file);
+
+    // merge 删除数据文件之后，文件在句柄释放之前仍然存在，可以重新打开读取
+    let merged = engine.merge_files(0.0).unwrap();
+    assert!(merged.contains(&0));
+    assert_eq!(
+        engine.get_data_file(0).err().unwrap(),
+        Errors::DataFileNotFound
+    );
+    assert!(get_data_file_name(opts.dir_path.clone(), 0).is_file());
+    let record = data_file
+        .read_log_record(DATA_FILE_HEADER_SIZE)
+        .unwrap()
+        .record;
+    assert_eq!(
+        record.key,
+        log_record_key_with_seq(&get_test_key(0), NON_TRANSACTION_SEQ_NO)
+    );
+    assert_eq!(record.value, get_test_value(0).to_vec());
+
+    // 句柄释放之后文件被删除
+    std::mem::drop(data_file);
+    assert!(!get_data_file_name(opts.dir_path.clone(), 0).is_file());
+    assert_eq!(engine.get(get_test_key(0)).unwrap(), Bytes::from("new"));
+
+    std::mem::drop(engine);
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_rotate_active_file() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-rotate-active-file");
+    opts.data_file_size = 64 * 1024 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..100 {
+        assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+    }
+
+    // 没有写满的活跃文件也会被转换为旧的数据文件
+    assert_eq!(engine.rotate_active_file().unwrap(), 0);
+    assert!(engine.older_files.read().contains_key(&0));
+    assert!(engine.get_data_file(0).unwrap().is_sealed());
+    assert_eq!(engine.active_file.read().get_file_id(), 1);
+    assert_eq!(engine.get(get_test_key(1)).unwrap(), get_test_value(1));
+
+    // 之后的写入进入新的活跃文件
+    assert!(engine.put(get_test_key(100), get_test_value(100)).is_ok());
+    assert_eq!(engine.get_position(get_test_key(100)).unwrap().file_id, 1);
+    assert_eq!(engine.rotate_active_file().unwrap(), 1);
+
+    std::mem::drop(engine);
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(engine.older_files.read().len(), 2);
+    for i in 0..=100 {
+        assert_eq!(engine.get(get_test_key(i)).unwrap(), get_test_value(i));
+    }
+
+    std::mem::drop(engine);
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_value_len() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-value-len");
+    opts.value_log_threshold = Some(1024);
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    assert!(engine.put(get_test_key(1), Bytes::from("value")).is_ok());
+    assert!(engine
+        .put(get_test_key(2), Bytes::from(vec![1u8; 4096]))
+        .is_ok());
+    assert!(engine.put(get_test_key(3), Bytes::new()).is_ok());
+    assert!(engine.put(get_test_key(4), Bytes::from("deleted")).is_ok());
+    assert!(engine.delete(get_test_key(4)).is_ok());
+
+    assert_eq!(engine.value_len(get_test_key(1)).unwrap(), 5);
+    // 存放在 blob 文件中的 value
+    assert_eq!(engine.value_len(get_test_key(2)).unwrap(), 4096);
+    assert_eq!(engine.value_len(get_test_key(3)).unwrap(), 0);
+    assert_eq!(
+        engine.value_len(get_test_key(4)).err().unwrap(),
+        Errors::KeyNotFound
+    );
+    assert_eq!(
+        engine.value_len(get_test_key(5)).err().unwrap(),
+        Errors::KeyNotFound
+    );
+    assert_eq!(
+        engine.value_len(Bytes::new()).err().unwrap(),
+        Errors::KeyIsEmpty
+    );
+    std::mem::drop(engine);
+
+    // 加密的数据库读取完整的记录
+    let mut encrypted_opts = opts.clone();
+    encrypted_opts.dir_path = PathBuf::from("/tmp/bitcask-rs-value-len-encrypted");
+    encrypted_opts.encryption_key = Some([6; 32]);
+    let engine = Engine::open(encrypted_opts.clone()).expect("failed to open engine");
+    assert!(engine.put(get_test_key(1), Bytes::from("value")).is_ok());
+    assert!(engine
+        .put(get_test_key(2), Bytes::from(vec![1u8; 4096]))
+        .is_ok());
+    assert_eq!(engine.value_len(get_test_key(1)).unwrap(), 5);
+    assert_eq!(engine.value_len(get_test_key(2)).unwrap(), 4096);
+
+    std::mem::drop(engine);
+    std::fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    std::fs::remove_dir_all(encrypted_opts.dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_batch_rotation_failure() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-batch-rotation-failure");
+    opts.data_file_size = 4 * 1024;
+    let injection = crate::testing::inject_faults(&opts.dir_path);
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // 批量写入跨越多个数据文件，中间切换活跃文件之前的持久化失败一次
+    let batch = engine
+        .new_write_batch(WriteBatchOptions::default())
+        .unwrap();
+    for i in 0..100 {
+        assert!(batch.put(get_test_key(i), get_test_value(i)).is_ok());
+    }
+    injection.fail_next_sync();
+    assert!(batch.commit().is_err());
+    assert!(!injection.is_triggered());
+
+    // 失败之后剩余的记录以及事务完成标记都没有写入，重启之后批量写入中的数据都不可见
+    std::mem::drop(engine);
+    std::mem::drop(injection);
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..100 {
+        assert_eq!(
+            engine.get(get_test_key(i)).err().unwrap(),
+            Errors::KeyNotFound
+        );
+    }
+
+    std::mem::drop(engine);
+    std::fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+}
@@ -0,0 +1,348 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::Read,
+    path::{Path, PathBuf},
+    sync::{atomic::Ordering, Arc},
+};
+
+use fs2::FileExt;
+use log::{error, warn};
+
+use crate::{
+    data::data_file::{
+        DataFile, BLOB_FILE_NAME_SUFFIX, BLOB_GC_FILE_NAME, BLOOM_FILE_NAME_SUFFIX,
+        DATA_FILE_MAGIC, DATA_FILE_NAME_SUFFIX, HINT_FILE_NAME, KEY_CHECK_FILE_NAME,
+        MANIFEST_FILE_NAME, MERGE_FIN_FILE_NAME, MVCC_VERSION_FILE_NAME, SEQ_NO_FILE_NAME,
+        STATS_FILE_NAME,
+    },
+    db::{preallocate_if_enabled, sync_dir_if_enabled, DirRegistration, Engine, FILE_LOCK_NAME},
+    errors::{Errors, Result},
+    index::bptree::BPTREE_INDEXER_FILE_NAME,
+    merge::{
+        get_merge_path, MERGE_FIN_CLEANED_NAME, MERGE_FIN_COMMITTED_NAME, MERGE_TEMP_FILE_SUFFIX,
+    },
+    options::IOType,
+    raft::RAFT_APPLIED_FILE_NAME,
+};
+
+impl Engine {
+    /// 删除整个数据目录，包括数据文件、hint 文件、merge 数据目录、事务序列号文件以及索引文件
+    /// 删除期间持有文件锁，数据目录正在被使用时返回 DatabaseIsUsing，数据目录不存在时直接返回
+    /// 删除之前校验数据目录中存在 manifest 或者带有魔数的数据文件，不是 bitcask-rs 的数据目录时不删除任何文件；
+    /// 数据目录中存在 bitcask-rs 之外的文件或者目录时返回 UnknownFileInDatabaseDir，同样不删除任何文件
+    /// 已经上传到对象存储的数据文件不会被删除
+    pub fn destroy(dir_path: PathBuf) -> Result<()> {
+        if !dir_path.is_dir() {
+            return Ok(());
+        }
+        // 获取文件锁之前先校验，不是 bitcask-rs 的数据目录时不会留下文件锁
+        if !is_database_dir(&dir_path)? {
+            warn!(
+                "refuse to destroy {}, not a bitcask-rs database dir",
+                dir_path.display()
+            );
+            return Err(Errors::NotDatabaseDir);
+        }
+
+        let _dir_registration = DirRegistration::register(&dir_path)?;
+        let lock_path = dir_path.join(FILE_LOCK_NAME);
+        let lock_file = match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&lock_path)
+        {
+            Ok(file) => file,
+            Err(e) => {
+                error!("open lock file err: {}", e);
+                return Err(Errors::open_failed(&lock_path, e));
+            }
+        };
+        if let Err(e) = lock_file.try_lock_exclusive() {
+            error!("get lock file err: {}", e);
+            return Err(Errors::DatabaseIsUsing);
+        }
+
+        let res = destroy_dir(&dir_path);
+
+        if let Err(e) = lock_file.unlock() {
+            error!("release lock file err: {}", e);
+        }
+        drop(lock_file);
+        res?;
+
+        // 最后删除文件锁以及数据目录本身
+        remove_path(&lock_path)?;
+        if let Err(e) = fs::remove_dir(&dir_path) {
+            error!("failed to remove database dir: {}", e);
+            return Err(Errors::FailedToRemoveDataFile);
+        }
+        if let Some(parent) = dir_path.parent() {
+            sync_dir_if_enabled(true, parent)?;
+        }
+        Ok(())
+    }
+
+    /// 清空数据库中的所有数据，删除所有的数据文件并切换到新的空的活跃文件，之后可以继续写入
+    /// 期间阻塞其他的写入，正在 merge 时返回 MergeInProgress；注册的二级索引保留，其中的数据被清空
+    /// 不会通知 watch 的订阅者，blob 文件在之后的 merge 中回收；
+    /// 清空过程中崩溃的话，重启之后可能只有部分数据文件被删除
+    pub fn clear(&self) -> Result<()> {
+        self.check_closed()?;
+        if self.options.read_only {
+            return Err(Errors::DatabaseIsReadOnly);
+        }
+
+        let lock = self.merging_lock.try_lock();
+        if lock.is_none() {
+            return Err(Errors::MergeInProgress);
+        }
+        let _commit_lock = self.batch_commit_lock.lock();
+        let _relocate_lock = self.relocate_lock.write();
+
+        // 还没有安装的 merge 结果以及 hint 文件中的数据都会在重启时重新加载，先于数据文件删除
+        let dir_path = self.options.dir_path.clone();
+        let merge_path = get_merge_path(dir_path.clone());
+        if merge_path.is_dir() {
+            if let Err(e) = fs::remove_dir_all(&merge_path) {
+                error!("failed to remove merge dir: {}", e);
+                return Err(Errors::FailedToRemoveDataFile);
+            }
+        }
+        remove_path(&dir_path.join(MERGE_FIN_FILE_NAME))?;
+        remove_path(&dir_path.join(HINT_FILE_NAME))?;
+        sync_dir_if_enabled(self.options.fsync_dir, &dir_path)?;
+
+        // 先切换到新的活跃文件，再删除原来的活跃文件以及所有旧的数据文件
+        let mut active_file = self.active_file.write();
+        let mut older_files = self.older_files.write();
+        let active_file_id = active_file.get_file_id();
+        self.record_rotation(&mut self.active_file_meta.lock(), &[], active_file_id + 1)?;
+        let new_file = DataFile::new(
+            dir_path.clone(),
+            active_file_id + 1,
+            IOType::StandardFIO,
+            self.cipher.clone(),
+        )?;
+        preallocate_if_enabled(&self.options, &new_file)?;
+        let old_file = std::mem::replace(&mut *active_file, Arc::new(new_file));
+        older_files.insert(active_file_id, old_file);
+
+        // 先清空索引再删除数据文件，并发读取的调用方重新查找索引时返回 KeyNotFound，而不是 DataFileNotFound
+        self.index.clear();
+        self.secondary_indexes.clear();
+
+        // 正在读取的调用方释放句柄之后才删除文件
+        let file_ids: Vec<u64> = older_files.keys().copied().collect();
+        for file_id in file_ids {
+            self.manifest.delete_file(file_id)?;
+            self.remove_older_file(&mut older_files, file_id)?;
+            #[cfg(feature = "object-store")]
+            if let Some(archive) = &self.archive {
+                archive.remove(file_id)?;
+            }
+            self.remove_bloom_filter(file_id)?;
+        }
+        drop(older_files);
+        drop(active_file);
+        sync_dir_if_enabled(self.options.fsync_dir, &dir_path)?;
+
+        self.stats.reset();
+        self.reclaim_size.store(0, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+// 数据目录中 bitcask-rs 使用的固定文件名
+const DATABASE_FILE_NAMES: &[&str] = &[
+    HINT_FILE_NAME,
+    MERGE_FIN_FILE_NAME,
+    MERGE_FIN_COMMITTED_NAME,
+    MERGE_FIN_CLEANED_NAME,
+    SEQ_NO_FILE_NAME,
+    MVCC_VERSION_FILE_NAME,
+    STATS_FILE_NAME,
+    KEY_CHECK_FILE_NAME,
+    MANIFEST_FILE_NAME,
+    BLOB_GC_FILE_NAME,
+    BPTREE_INDEXER_FILE_NAME,
+    RAFT_APPLIED_FILE_NAME,
+];
+
+// 数据目录中 bitcask-rs 使用的文件名后缀
+const DATABASE_FILE_SUFFIXES: &[&str] = &[
+    DATA_FILE_NAME_SUFFIX,
+    BLOB_FILE_NAME_SUFFIX,
+    BLOOM_FILE_NAME_SUFFIX,
+];
+
+// 删除数据目录中 bitcask-rs 的文件，以及数据目录旁边的 merge 数据目录，文件锁由调用方最后删除
+// 存在其他的文件或者目录时返回 UnknownFileInDatabaseDir，不删除任何文件
+fn destroy_dir(dir_path: &Path) -> Result<()> {
+    let mut paths = Vec::new();
+    for entry in read_dir(dir_path)? {
+        let path = entry.path();
+        let file_name = entry.file_name();
+        if file_name == FILE_LOCK_NAME {
+            continue;
+        }
+        let file_name = file_name.to_string_lossy();
+        if path.is_dir() || !is_database_file(&file_name) {
+            warn!(
+                "refuse to destroy {}, unknown file {}",
+                dir_path.display(),
+                file_name
+            );
+            return Err(Errors::UnknownFileInDatabaseDir(file_name.into_owned()));
+        }
+        paths.push(path);
+    }
+
+    let merge_path = get_merge_path(dir_path.to_path_buf());
+    if merge_path.is_dir() {
+        if let Err(e) = fs::remove_dir_all(&merge_path) {
+            error!("failed to remove merge dir: {}", e);
+            return Err(Errors::FailedToRemoveDataFile);
+        }
+    }
+    for path in paths {
+        remove_path(&path)?;
+    }
+    Ok(())
+}
+
+// 是否是 bitcask-rs 在数据目录中使用的文件，包括原子写入以及安装 merge 结果时的临时文件
+fn is_database_file(file_name: &str) -> bool {
+    let file_name = file_name
+        .strip_suffix(".tmp")
+        .or_else(|| file_name.strip_suffix(MERGE_TEMP_FILE_SUFFIX))
+        .unwrap_or(file_name);
+    DATABASE_FILE_NAMES.contains(&file_name)
+        || DATABASE_FILE_SUFFIXES
+            .iter()
+            .any(|suffix| file_name.ends_with(suffix))
+}
+
+// 数据目录中存在 manifest 或者带有魔数的数据文件时，认为是 bitcask-rs 的数据目录
+fn is_database_dir(dir_path: &Path) -> Result<bool> {
+    if dir_path.join(MANIFEST_FILE_NAME).is_file() {
+        return Ok(true);
+    }
+    for entry in read_dir(dir_path)? {
+        let file_name = entry.file_name();
+        if !file_name.to_string_lossy().ends_with(DATA_FILE_NAME_SUFFIX) {
+            continue;
+        }
+        let mut magic = [0u8; 4];
+        let read = fs::File::open(entry.path()).and_then(|mut file| file.read_exact(&mut magic));
+        if read.is_ok() && &magic == DATA_FILE_MAGIC {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn read_dir(dir_path: &Path) -> Result<Vec<fs::DirEntry>> {
+    let entries = fs::read_dir(dir_path).and_then(|entries| entries.collect());
+    entries.map_err(|e| {
+        error!("failed to read database dir: {}", e);
+        Errors::FailedToReadDatabaseDir
+    })
+}
+
+fn remove_path(path: &Path) -> Result<()> {
+    if path.is_file() {
+        if let Err(e) = fs::remove_file(path) {
+            error!("failed to remove {}: {}", path.display(), e);
+            return Err(Errors::FailedToRemoveDataFile);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::{
+        data::data_file::get_data_file_name,
+        options::Options,
+        util::rand_kv::{get_test_key, get_test_value},
+    };
+
+    #[test]
+    fn test_engine_clear() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-clear");
+        opts.data_file_size = 64 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..1000 {
+            assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        assert!(!engine.older_files.read().is_empty());
+
+        assert!(engine.clear().is_ok());
+        assert!(engine.list_keys().unwrap().is_empty());
+        assert!(engine.older_files.read().is_empty());
+        assert_eq!(engine.stat().unwrap().key_num, 0);
+
+        // 清空之后可以继续写入，重启之后之前的数据不会重新出现
+        assert!(engine.put(Bytes::from("new"), Bytes::from("value")).is_ok());
+        std::mem::drop(engine);
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine.list_keys().unwrap(), vec![Bytes::from("new")]);
+        assert_eq!(
+            engine.get(get_test_key(1)).err().unwrap(),
+            Errors::KeyNotFound
+        );
+
+        std::mem::drop(engine);
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_engine_destroy() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-destroy");
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..100 {
+            assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+
+        // 正在使用的数据目录不能删除
+        assert_eq!(
+            Engine::destroy(opts.dir_path.clone()).err().unwrap(),
+            Errors::DatabaseIsUsing
+        );
+        std::mem::drop(engine);
+
+        // 数据目录中存在其他文件时不删除任何文件
+        let user_file = opts.dir_path.join("user.txt");
+        fs::write(&user_file, b"user data").unwrap();
+        assert_eq!(
+            Engine::destroy(opts.dir_path.clone()).err().unwrap(),
+            Errors::UnknownFileInDatabaseDir("user.txt".to_string())
+        );
+        assert!(user_file.is_file());
+        assert!(get_data_file_name(opts.dir_path.clone(), 0).is_file());
+        fs::remove_file(&user_file).unwrap();
+
+        assert!(Engine::destroy(opts.dir_path.clone()).is_ok());
+        assert!(!opts.dir_path.exists());
+        // 不存在的数据目录
+        assert!(Engine::destroy(opts.dir_path.clone()).is_ok());
+
+        // 不是 bitcask-rs 的数据目录时不删除任何文件
+        let other_path = PathBuf::from("/tmp/bitcask-rs-destroy-other");
+        fs::create_dir_all(&other_path).unwrap();
+        fs::write(other_path.join("000000001.data"), b"not a data file").unwrap();
+        assert_eq!(
+            Engine::destroy(other_path.clone()).err().unwrap(),
+            Errors::NotDatabaseDir
+        );
+        assert!(other_path.join("000000001.data").is_file());
+        fs::remove_dir_all(other_path).expect("failed to remove path");
+    }
+}
@@ -0,0 +1,332 @@
+use std::{fs, path::PathBuf, sync::Arc};
+
+use prost::encoding::decode_varint;
+
+use crate::{
+    data::{
+        cipher::load_cipher,
+        data_file::{
+            get_data_file_name, DataFile, DATA_FILE_NAME_SUFFIX, HINT_FILE_NAME,
+            MERGE_FIN_FILE_NAME, MVCC_VERSION_FILE_NAME, SEQ_NO_FILE_NAME,
+        },
+        log_record::LogRecordType,
+    },
+    db::{DumpFileType, DumpRecord, DumpRecordType, Engine},
+    errors::{Errors, Result},
+    fio::data_file_exists,
+    options::IOType,
+};
+
+/// 遍历单个文件中所有记录的迭代器，CRC 校验失败的记录也会返回
+/// 记录的长度无法解析时返回 DataFileCorrupted 错误，之后的数据无法继续遍历
+pub struct DumpIterator {
+    data_file: DataFile,
+    file_type: DumpFileType,
+    offset: u64,
+    finished: bool,
+}
+
+impl Engine {
+    /// 返回遍历数据文件中所有记录的迭代器，用于查看和排查数据文件中的内容
+    /// 使用单独打开的文件读取，不影响正常的读写，遍历期间文件被 merge 删除也可以继续读取
+    pub fn dump_file(&self, file_id: u64) -> Result<DumpIterator> {
+        self.check_closed()?;
+        let dir_path = self.options.dir_path.clone();
+        if !data_file_exists(&get_data_file_name(dir_path.clone(), file_id)) {
+            return Err(Errors::DataFileNotFound);
+        }
+
+        let data_file = DataFile::new(dir_path, file_id, IOType::StandardFIO, self.cipher.clone())?;
+        Ok(DumpIterator::new(data_file, DumpFileType::Data(file_id)))
+    }
+
+    /// 不打开存储引擎，直接遍历数据目录中指定文件的所有记录，也不获取文件锁，可以查看正在使用的数据目录
+    /// 加密的数据目录需要提供密钥，记录会被解密
+    pub fn dump_path(
+        dir_path: PathBuf,
+        file_type: DumpFileType,
+        encryption_key: Option<[u8; 32]>,
+    ) -> Result<DumpIterator> {
+        let filename = match file_type {
+            DumpFileType::Data(file_id) => get_data_file_name(dir_path.clone(), file_id),
+            DumpFileType::Hint => dir_path.join(HINT_FILE_NAME),
+            DumpFileType::MergeFin => dir_path.join(MERGE_FIN_FILE_NAME),
+            DumpFileType::SeqNo => dir_path.join(SEQ_NO_FILE_NAME),
+            DumpFileType::MvccVersion => dir_path.join(MVCC_VERSION_FILE_NAME),
+        };
+        // 打开文件时不存在会被创建，需要先检查
+        if !data_file_exists(&filename) {
+            return Err(Errors::DataFileNotFound);
+        }
+
+        let cipher = load_cipher(dir_path.clone(), encryption_key.as_ref(), true)?.map(Arc::new);
+        let data_file = match file_type {
+            DumpFileType::Data(file_id) => {
+                DataFile::new(dir_path, file_id, IOType::StandardFIO, cipher)?
+            }
+            DumpFileType::Hint => DataFile::new_hint_file(dir_path, cipher)?,
+            DumpFileType::MergeFin => DataFile::new_merge_fin_file(dir_path)?,
+            DumpFileType::SeqNo => DataFile::new_seq_no_file(dir_path)?,
+            DumpFileType::MvccVersion => DataFile::new_mvcc_version_file(dir_path)?,
+        };
+        Ok(DumpIterator::new(data_file, file_type))
+    }
+
+    /// 数据目录中所有数据文件的 id，从小到大排列，和 Engine::dump_path 一起用于检查整个数据目录
+    pub fn data_file_ids(dir_path: PathBuf) -> Result<Vec<u64>> {
+        let dir = match fs::read_dir(&dir_path) {
+            Ok(dir) => dir,
+            Err(_) => return Err(Errors::FailedToReadDatabaseDir),
+        };
+        let mut file_ids: Vec<u64> = dir
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let file_name = entry.file_name().into_string().ok()?;
+                file_name
+                    .strip_suffix(DATA_FILE_NAME_SUFFIX)?
+                    .parse::<u64>()
+                    .ok()
+            })
+            .collect();
+        file_ids.sort();
+        Ok(file_ids)
+    }
+}
+
+impl DumpIterator {
+    fn new(data_file: DataFile, file_type: DumpFileType) -> Self {
+        let offset = data_file.get_header_size();
+        DumpIterator {
+            data_file,
+            file_type,
+            offset,
+            finished: false,
+        }
+    }
+
+    /// 文件中的记录是否经过加密
+    pub fn is_encrypted(&self) -> bool {
+        self.data_file.is_encrypted()
+    }
+
+    /// 文件的创建时间，单位毫秒，没有头部的文件返回 None
+    pub fn create_time(&self) -> Option<u64> {
+        self.data_file.create_time()
+    }
+}
+
+impl Iterator for DumpIterator {
+    type Item = Result<DumpRecord>;
+
+    fn next(&mut self) -> Option<Result<DumpRecord>> {
+        if self.finished {
+            return None;
+        }
+
+        let (read_record, crc_valid) = match self.data_file.read_log_record_unchecked(self.offset) {
+            Ok(result) => result,
+            Err(e) => {
+                self.finished = true;
+                return match e {
+                    Errors::ReadDataFileEof => None,
+                    Errors::InvaildLogRecordCrc | Errors::InvalidRecordType(_) => {
+                        Some(Err(Errors::DataFileCorrupted {
+                            file_id: self.data_file.get_file_id(),
+                            offset: self.offset,
+                        }))
+                    }
+                    e => Some(Err(e)),
+                };
+            }
+        };
+
+        let offset = self.offset;
+        let size = read_record.size as u64;
+        self.offset += size;
+
+        let log_record = read_record.record;
+        // 只有数据文件中的 key 带有事务序列号，hint 文件中的 value 是记录的位置
+        let (key, seq_no) = match self.file_type {
+            DumpFileType::Data(_) => split_seq_no(log_record.key),
+            _ => (log_record.key, 0),
+        };
+        let hint_pos = match self.file_type {
+            DumpFileType::Hint => decode_hint_pos(&log_record.value),
+            _ => None,
+        };
+        Some(Ok(DumpRecord {
+            offset,
+            size,
+            record_type: match log_record.rec_type {
+                LogRecordType::NORMAL => DumpRecordType::Put,
+                LogRecordType::DELETE => DumpRecordType::Delete,
+                LogRecordType::TxnFinished => DumpRecordType::TxnFinished,
+            },
+            key: key.into(),
+            seq_no,
+            value: log_record.value.into(),
+            value_pointer: log_record.value_pointer,
+            timestamp: log_record.timestamp,
+            crc_valid,
+            hint_pos,
+        }))
+    }
+}
+
+// 拆分出 key 前面的事务序列号，损坏的记录无法解析时原样返回
+fn split_seq_no(key: Vec<u8>) -> (Vec<u8>, u64) {
+    let mut buf = &key[..];
+    match decode_varint(&mut buf) {
+        Ok(seq_no) => (buf.to_vec(), seq_no),
+        Err(_) => (key, 0),
+    }
+}
+
+// 解码 hint 文件中记录的位置，损坏的记录无法解析时返回 None
+fn decode_hint_pos(value: &[u8]) -> Option<(u64, u64, u64)> {
+    let mut buf = value;
+    let file_id = decode_varint(&mut buf).ok()?;
+    let offset = decode_varint(&mut buf).ok()?;
+    let size = decode_varint(&mut buf).ok()?;
+    Some((file_id, offset, size))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::OpenOptions, os::unix::fs::FileExt, path::PathBuf};
+
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::options::{Options, WriteBatchOptions};
+
+    #[test]
+    fn test_dump_file() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-dump-file");
+        opts.data_file_size = 64 * 1024 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        assert!(engine
+            .put(Bytes::from("key1"), Bytes::from("value1"))
+            .is_ok());
+        assert!(engine.delete(Bytes::from("key1")).is_ok());
+        let batch = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .unwrap();
+        assert!(batch
+            .put(Bytes::from("key2"), Bytes::from("value2"))
+            .is_ok());
+        assert!(batch.commit().is_ok());
+
+        let records: Vec<DumpRecord> = engine
+            .dump_file(0)
+            .unwrap()
+            .map(|record| record.unwrap())
+            .collect();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[0].record_type, DumpRecordType::Put);
+        assert_eq!(records[0].key, Bytes::from("key1"));
+        assert_eq!(records[0].value, Bytes::from("value1"));
+        assert_eq!(records[0].seq_no, 0);
+        assert!(records[0].crc_valid);
+        assert_eq!(records[1].record_type, DumpRecordType::Delete);
+        assert_eq!(records[1].offset, records[0].offset + records[0].size);
+        assert_eq!(records[2].key, Bytes::from("key2"));
+        assert!(records[2].seq_no > 0);
+        assert_eq!(records[3].record_type, DumpRecordType::TxnFinished);
+        assert_eq!(records[3].seq_no, records[2].seq_no);
+
+        // 修改 value 之后 CRC 校验失败，仍然可以继续遍历之后的记录
+        let file = OpenOptions::new()
+            .write(true)
+            .open(get_data_file_name(opts.dir_path.clone(), 0))
+            .unwrap();
+        let value_offset = records[0].offset + records[0].size - 5;
+        file.write_all_at(b"X", value_offset).unwrap();
+        let dumped: Vec<DumpRecord> = engine
+            .dump_file(0)
+            .unwrap()
+            .map(|record| record.unwrap())
+            .collect();
+        assert_eq!(dumped.len(), 4);
+        assert!(!dumped[0].crc_valid);
+        assert_eq!(dumped[0].value, Bytes::from("valueX"));
+        assert!(dumped[1..].iter().all(|record| record.crc_valid));
+
+        // 无法解析的记录返回错误，之后不再继续遍历
+        file.write_all_at(&[0xff; 16], records[2].offset).unwrap();
+        let mut iter = engine.dump_file(0).unwrap();
+        assert!(iter.next().unwrap().is_ok());
+        assert!(iter.next().unwrap().is_ok());
+        assert_eq!(
+            iter.next().unwrap().err().unwrap(),
+            Errors::DataFileCorrupted {
+                file_id: 0,
+                offset: records[2].offset
+            }
+        );
+        assert!(iter.next().is_none());
+
+        assert_eq!(
+            engine.dump_file(10).err().unwrap(),
+            Errors::DataFileNotFound
+        );
+
+        std::mem::drop(engine);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_dump_path() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-dump-path");
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        assert!(engine
+            .put(Bytes::from("key1"), Bytes::from("value1"))
+            .is_ok());
+        assert!(engine.delete(Bytes::from("key1")).is_ok());
+        assert!(engine.close().is_ok());
+        std::mem::drop(engine);
+
+        // 不打开存储引擎直接遍历文件
+        assert_eq!(
+            Engine::data_file_ids(opts.dir_path.clone()).unwrap(),
+            vec![0]
+        );
+        let iter = Engine::dump_path(opts.dir_path.clone(), DumpFileType::Data(0), None).unwrap();
+        assert!(!iter.is_encrypted());
+        assert!(iter.create_time().is_some());
+        let records: Vec<DumpRecord> = iter.map(|record| record.unwrap()).collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].record_type, DumpRecordType::Put);
+        assert_eq!(records[0].key, Bytes::from("key1"));
+        assert!(records[0].timestamp > 0);
+        assert_eq!(records[1].record_type, DumpRecordType::Delete);
+        assert!(records.iter().all(|record| record.crc_valid));
+
+        // 其他文件中的 key 没有事务序列号
+        let records: Vec<DumpRecord> =
+            Engine::dump_path(opts.dir_path.clone(), DumpFileType::SeqNo, None)
+                .unwrap()
+                .map(|record| record.unwrap())
+                .collect();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].key, Bytes::from("seq.no"));
+
+        assert_eq!(
+            Engine::dump_path(opts.dir_path.clone(), DumpFileType::Data(10), None)
+                .err()
+                .unwrap(),
+            Errors::DataFileNotFound
+        );
+        assert_eq!(
+            Engine::dump_path(opts.dir_path.clone(), DumpFileType::Hint, None)
+                .err()
+                .unwrap(),
+            Errors::DataFileNotFound
+        );
+
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+}
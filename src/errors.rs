@@ -1,20 +1,27 @@
-use std::result;
+use std::{fmt, io, path::Path, result, sync::Arc};
 
 use thiserror::Error;
 
-#[derive(Debug, Error, PartialEq)]
+/// 文件读写相关的错误中带有出错的文件路径以及底层的 IO 错误，方便从日志中定位出错的文件
+/// 之后可能会增加新的错误类型，外部匹配时需要保留通配分支
+#[derive(Debug, Clone, Error, PartialEq)]
+#[non_exhaustive]
 pub enum Errors {
-    #[error("failed to read from data file")]
-    FailedToReadDataFromDataFile,
+    #[error("failed to read from data file {path} at offset {offset}: {source}")]
+    FailedToReadDataFromDataFile {
+        path: String,
+        offset: u64,
+        source: IoError,
+    },
 
-    #[error("failed to write to data file")]
-    FailedToWriteDataToDataFile,
+    #[error("failed to write to data file {path}: {source}")]
+    FailedToWriteDataToDataFile { path: String, source: IoError },
 
-    #[error("failed to sync data file")]
-    FailedSyncDataFile,
+    #[error("failed to sync data file {path}: {source}")]
+    FailedSyncDataFile { path: String, source: IoError },
 
-    #[error("failed to open data file")]
-    FailedOpenDataFile,
+    #[error("failed to open data file {path}: {source}")]
+    FailedOpenDataFile { path: String, source: IoError },
 
     #[error("the key is empty")]
     KeyIsEmpty,
@@ -34,7 +41,7 @@ pub enum Errors {
     #[error("database dir path can not be empty")]
     DirPathIsEmpty,
 
-    #[error("database data file size must be greater than 0")]
+    #[error("database data file size must be greater than the data file header size")]
     DataFileSizeTooSmall,
 
     #[error("failed to create the database dir")]
@@ -46,15 +53,36 @@ pub enum Errors {
     #[error("the database dir maybe corrupted")]
     DataDirCorrupted,
 
+    #[error("the dir is not a bitcask-rs database dir")]
+    NotDatabaseDir,
+
+    #[error("unknown file {0} in the database dir")]
+    UnknownFileInDatabaseDir(String),
+
+    #[error("log record size {size} is too large for data file size {data_file_size}")]
+    RecordTooLargeForDataFile { size: u64, data_file_size: u64 },
+
     #[error("read data file eof")]
     ReadDataFileEof,
 
     #[error("invalid crc value, log record maybe corrupted")]
     InvaildLogRecordCrc,
 
+    #[error("invalid log record key, log record maybe corrupted")]
+    InvaildLogRecordKey,
+
+    #[error("invalid log record position, log record maybe corrupted")]
+    InvaildLogRecordPos,
+
+    #[error("unknown log record type {0}, log record maybe corrupted")]
+    InvalidRecordType(u8),
+
     #[error("key conflicts with other transactions")]
     MvccTxnWriteKeyConflictsWithOtherTransactions,
 
+    #[error("data read by the transaction has been modified by other transactions")]
+    MvccTxnReadConflictsWithOtherTransactions,
+
     #[error("active txn is not exist")]
     MvccCommitActiveTxnIsNotExist,
 
@@ -64,9 +92,18 @@ pub enum Errors {
     #[error("exceed the max batch num")]
     ExceedMaxBatchNum,
 
+    #[error("exceed the max batch size")]
+    ExceedMaxBatchSize,
+
     #[error("merge is in progress")]
     MergeInProgress,
 
+    #[error("merge is cancelled")]
+    MergeCancelled,
+
+    #[error("failed to install merge files")]
+    FailedToInstallMergeFiles,
+
     #[error("the database directory is used by another process")]
     DatabaseIsUsing,
 
@@ -84,6 +121,238 @@ pub enum Errors {
 
     #[error("failed to copy database dir")]
     FailedToCopyDir,
+
+    #[error("merge threads num must be greater than 0")]
+    InvaildMergeThreads,
+
+    #[error("load index threads num must be greater than 0")]
+    InvaildLoadIndexThreads,
+
+    #[error("sync interval must be greater than 0")]
+    InvaildSyncInterval,
+
+    #[error("file rotation max records and max age must be greater than 0")]
+    InvaildFileRotation,
+
+    #[error("max transaction age must be greater than 0")]
+    InvaildMaxTransactionAge,
+
+    #[error("the value is not a valid counter")]
+    InvaildCounterValue,
+
+    #[error("the counter overflows")]
+    CounterOverflow,
+
+    #[error("value log threshold must be greater than 0")]
+    InvaildValueLogThreshold,
+
+    #[error("max open files must be greater than 0")]
+    InvaildMaxOpenFiles,
+
+    #[error("blob file not found")]
+    BlobFileNotFound,
+
+    #[error("data file {file_id} is corrupted at offset {offset}")]
+    DataFileCorrupted { file_id: u64, offset: u64 },
+
+    #[error("data file {file_id} in the manifest is missing")]
+    DataFileMissing { file_id: u64 },
+
+    #[error("the manifest file is corrupted")]
+    ManifestCorrupted,
+
+    #[error("the database is opened in read-only mode")]
+    DatabaseIsReadOnly,
+
+    #[error("data file {file_id} has unsupported format version {version}")]
+    UnsupportedDataFileVersion { file_id: u64, version: u16 },
+
+    #[error("data file {file_id} has unsupported flags {flags:#x}")]
+    UnsupportedDataFileFlags { file_id: u64, flags: u16 },
+
+    #[error("invalid encryption key")]
+    InvaildEncryptionKey,
+
+    #[error("the database is encrypted, encryption key is required")]
+    EncryptionKeyRequired,
+
+    #[error("encryption is not supported by the bptree index")]
+    EncryptionUnsupportedIndexType,
+
+    #[error("key comparator is only supported by the btree index")]
+    KeyComparatorUnsupportedIndexType,
+
+    #[error("failed to encrypt data")]
+    FailedToEncryptData,
+
+    #[error("failed to decrypt log record, the encryption key maybe wrong")]
+    FailedToDecryptLogRecord,
+
+    #[error("invalid record location")]
+    InvaildRecordLocation,
+
+    #[error("secondary index already exists")]
+    SecondaryIndexExists,
+
+    #[error("secondary index is not found")]
+    SecondaryIndexNotFound,
+
+    #[error("bucket name can not be empty")]
+    BucketNameIsEmpty,
+
+    #[error("failed to serialize value")]
+    FailedToSerializeValue,
+
+    #[error("failed to deserialize value")]
+    FailedToDeserializeValue,
+
+    #[error("replication connection error")]
+    ReplicationConnectionFailed,
+
+    #[error("invalid replication message")]
+    InvaildReplicationMessage,
+
+    #[error("replication is not supported by the encrypted database")]
+    ReplicationUnsupportedEncryption,
+
+    #[error("the database is closed")]
+    DatabaseClosed,
+
+    #[error("failed to remove data file")]
+    FailedToRemoveDataFile,
+
+    #[error("scan limit must be greater than 0")]
+    InvaildScanLimit,
+
+    #[error("invalid raft command")]
+    InvaildRaftCommand,
+
+    #[error("failed to install raft snapshot")]
+    FailedToInstallSnapshot,
+
+    #[error("merge io type must be StandardFIO or DirectIO")]
+    InvaildMergeIOType,
+
+    #[error("ingest keys must be sorted in ascending order without duplicates")]
+    IngestKeysNotSorted,
+
+    #[error("failed to install ingest files")]
+    FailedToInstallIngestFiles,
+
+    #[error("external data file {path} is invalid at offset {offset}")]
+    InvaildExternalFile { path: String, offset: u64 },
+
+    #[error("write stalled, too much reclaimable data while merging")]
+    WriteStalled,
+
+    #[error("tenant name must be a non-empty dir name")]
+    InvaildTenantName,
+
+    #[error("column family name must be a non-empty dir name")]
+    InvaildColumnFamilyName,
+
+    #[error("column family commit log is corrupted")]
+    InvaildColumnFamilyCommit,
+
+    #[error("failed to access object store")]
+    FailedToAccessObjectStore,
+
+    #[error("object store is not configured")]
+    ObjectStoreNotConfigured,
+
+    #[error("invalid object store options")]
+    InvaildObjectStoreOptions,
+
+    #[error("archived data file is read only")]
+    ArchivedDataFileIsReadOnly,
+
+    #[error("data file is sealed and can not be written")]
+    DataFileIsSealed,
+
+    #[error("encoded key is invalid")]
+    InvaildEncodedKey,
+
+    #[error("failed to read or write export stream: {source}")]
+    FailedToAccessExport { source: IoError },
+
+    #[error("export stream is invalid at offset {offset}")]
+    InvaildExport { offset: u64 },
+
+    #[error("unsupported export format version {0}")]
+    UnsupportedExportVersion(u16),
+
+    #[error("failed to read rocksdb data: {0}")]
+    FailedToReadRocksDb(String),
 }
 
 pub type Result<T> = result::Result<T, Errors>;
+
+impl Errors {
+    /// 读取文件失败，offset 为读取的位置
+    pub(crate) fn read_failed(path: &Path, offset: u64, source: io::Error) -> Self {
+        Errors::FailedToReadDataFromDataFile {
+            path: path.display().to_string(),
+            offset,
+            source: source.into(),
+        }
+    }
+
+    /// 写入文件失败
+    pub(crate) fn write_failed(path: &Path, source: io::Error) -> Self {
+        Errors::FailedToWriteDataToDataFile {
+            path: path.display().to_string(),
+            source: source.into(),
+        }
+    }
+
+    /// 持久化文件或者目录失败
+    pub(crate) fn sync_failed(path: &Path, source: io::Error) -> Self {
+        Errors::FailedSyncDataFile {
+            path: path.display().to_string(),
+            source: source.into(),
+        }
+    }
+
+    /// 打开文件失败
+    pub(crate) fn open_failed(path: &Path, source: io::Error) -> Self {
+        Errors::FailedOpenDataFile {
+            path: path.display().to_string(),
+            source: source.into(),
+        }
+    }
+}
+
+/// 包装 std::io::Error，使 Errors 仍然可以 Clone 以及比较，比较时只比较错误的类型
+#[derive(Debug, Clone)]
+pub struct IoError(Arc<io::Error>);
+
+impl IoError {
+    /// 底层 IO 错误的类型
+    pub fn kind(&self) -> io::ErrorKind {
+        self.0.kind()
+    }
+}
+
+impl From<io::Error> for IoError {
+    fn from(e: io::Error) -> Self {
+        IoError(Arc::new(e))
+    }
+}
+
+impl PartialEq for IoError {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind() == other.kind()
+    }
+}
+
+impl fmt::Display for IoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for IoError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
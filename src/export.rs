@@ -0,0 +1,285 @@
+use std::io::{self, Read, Write};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use log::error;
+use prost::encoding::{decode_varint, encode_varint};
+
+use crate::{
+    data::log_record::LogRecordType,
+    db::Engine,
+    errors::{Errors, Result},
+    options::IteratorOptions,
+};
+
+// 导出格式的标识和版本，格式发生不兼容的变化时增加版本号
+const EXPORT_MAGIC: &[u8; 4] = b"BKEX";
+const EXPORT_FORMAT_VERSION: u16 = 1;
+
+// 每一段数据开头的标识
+const ENTRY_TAG: u8 = 1;
+const END_TAG: u8 = 0;
+
+// 单条数据的最大长度，超过时认为数据已经损坏，避免分配过大的内存
+const MAX_ENTRY_SIZE: u32 = u32::MAX >> 1;
+
+/// 导出格式，和数据文件的格式以及机器的字节序无关
+///
+/// +----------+-----------+
+/// |  magic   |  version  |
+/// +----------+-----------+
+///    4字节       2字节
+///
+/// 之后是任意多条数据，每条数据的格式为
+///
+/// +-------+-----------+-------------+------------+-------+---------+---------+
+/// |  tag  |  payload  |  timestamp  |  key size  |  key  |  value  |   crc   |
+/// |  (1)  |   size    |             |            |       |         |         |
+/// +-------+-----------+-------------+------------+-------+---------+---------+
+///   1字节     4字节      变长（最大10）  变长（最大5）   变长     变长      4字节
+///
+/// 最后以结束标记结尾，记录导出的数据条数，读取到结束标记才说明数据是完整的
+///
+/// +-------+---------+---------+
+/// |  tag  |  count  |   crc   |
+/// |  (0)  |         |         |
+/// +-------+---------+---------+
+///   1字节    8字节     4字节
+///
+/// 整数按照大端序存放，crc 覆盖同一段数据中 crc 之前的所有字节
+impl Engine {
+    /// 将数据库中所有的数据以及写入时间导出到 writer 中，返回导出的数据条数
+    /// 包括 bucket 以及 MVCC 事务内部使用的 key，导出期间的写入不一定包含在导出的数据中
+    pub fn export<W: Write>(&self, writer: &mut W) -> Result<u64> {
+        self.check_closed()?;
+        let mut header = BytesMut::with_capacity(EXPORT_MAGIC.len() + 2);
+        header.put_slice(EXPORT_MAGIC);
+        header.put_u16(EXPORT_FORMAT_VERSION);
+        writer.write_all(&header).map_err(export_err)?;
+
+        let mut count = 0;
+        let mut index_iter = self.index.iterator(IteratorOptions::default());
+        while let Some((key, pos)) = index_iter.next() {
+            let read_record = match self.read_log_record_by_position(pos) {
+                Ok(read_record) => read_record,
+                // 数据文件已经被 merge 删除，从索引中重新获取最新的位置，key 已经不存在的话直接跳过
+                Err(Errors::DataFileNotFound) => match self.index.get(key.clone()) {
+                    Some(pos) => self.read_log_record_by_position(&pos)?,
+                    None => continue,
+                },
+                Err(e) => return Err(e),
+            };
+            let record = read_record.record;
+            if record.rec_type == LogRecordType::DELETE {
+                continue;
+            }
+            writer
+                .write_all(&encode_entry(key, &record.value, record.timestamp))
+                .map_err(export_err)?;
+            count += 1;
+        }
+
+        let mut end = BytesMut::with_capacity(13);
+        end.put_u8(END_TAG);
+        end.put_u64(count);
+        end.put_u32(crc32fast::hash(&end));
+        writer.write_all(&end).map_err(export_err)?;
+        writer.flush().map_err(export_err)?;
+        Ok(count)
+    }
+
+    /// 将 export 导出的数据写入到数据库中，保留原来的写入时间，返回导入的数据条数
+    /// 已经存在的 key 会被覆盖，导入不是原子的，出错时已经导入的数据仍然有效
+    pub fn import<R: Read>(&self, reader: &mut R) -> Result<u64> {
+        self.check_closed()?;
+        if self.options.read_only {
+            return Err(Errors::DatabaseIsReadOnly);
+        }
+
+        let mut header = [0u8; 6];
+        read_exact(reader, &mut header, 0)?;
+        if &header[..4] != EXPORT_MAGIC {
+            return Err(Errors::InvaildExport { offset: 0 });
+        }
+        let version = (&header[4..]).get_u16();
+        if version != EXPORT_FORMAT_VERSION {
+            return Err(Errors::UnsupportedExportVersion(version));
+        }
+
+        let mut offset = header.len() as u64;
+        let mut count = 0;
+        loop {
+            let mut tag = [0u8; 1];
+            read_exact(reader, &mut tag, offset)?;
+            match tag[0] {
+                ENTRY_TAG => {
+                    let (key, value, timestamp, size) = read_entry(reader, offset)?;
+                    self.check_write_stall()?;
+                    let _key_lock = self.key_locks.lock(&key);
+                    // 之后写入的数据的写入时间不早于导入的数据
+                    self.clock.observe(timestamp);
+                    self.write_value(key, value, timestamp)?;
+                    offset += size;
+                    count += 1;
+                }
+                END_TAG => {
+                    let mut end = [0u8; 13];
+                    end[0] = END_TAG;
+                    read_exact(reader, &mut end[1..], offset)?;
+                    let mut buf = &end[1..];
+                    let expected = buf.get_u64();
+                    if buf.get_u32() != crc32fast::hash(&end[..9]) || expected != count {
+                        return Err(Errors::InvaildExport { offset });
+                    }
+                    return Ok(count);
+                }
+                _ => return Err(Errors::InvaildExport { offset }),
+            }
+        }
+    }
+}
+
+// 编码一条导出的数据
+fn encode_entry(key: &[u8], value: &[u8], timestamp: u64) -> Vec<u8> {
+    let mut payload = BytesMut::new();
+    encode_varint(timestamp, &mut payload);
+    encode_varint(key.len() as u64, &mut payload);
+    payload.put_slice(key);
+    payload.put_slice(value);
+
+    let mut buf = BytesMut::with_capacity(payload.len() + 9);
+    buf.put_u8(ENTRY_TAG);
+    buf.put_u32(payload.len() as u32);
+    buf.put_slice(&payload);
+    buf.put_u32(crc32fast::hash(&buf));
+    buf.to_vec()
+}
+
+// 读取一条数据中 tag 之后的部分，返回 key、value、写入时间以及这条数据的总长度
+fn read_entry<R: Read>(reader: &mut R, offset: u64) -> Result<(Bytes, Bytes, u64, u64)> {
+    let mut size_buf = [0u8; 4];
+    read_exact(reader, &mut size_buf, offset)?;
+    let payload_size = u32::from_be_bytes(size_buf);
+    if payload_size > MAX_ENTRY_SIZE {
+        return Err(Errors::InvaildExport { offset });
+    }
+
+    let mut buf = BytesMut::zeroed(payload_size as usize + 4);
+    read_exact(reader, &mut buf, offset)?;
+    let crc = (&buf[payload_size as usize..]).get_u32();
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&[ENTRY_TAG]);
+    hasher.update(&size_buf);
+    hasher.update(&buf[..payload_size as usize]);
+    if hasher.finalize() != crc {
+        return Err(Errors::InvaildExport { offset });
+    }
+    buf.truncate(payload_size as usize);
+
+    let mut payload = &buf[..];
+    let timestamp = decode_varint(&mut payload).map_err(|_| Errors::InvaildExport { offset })?;
+    let key_size = decode_varint(&mut payload).map_err(|_| Errors::InvaildExport { offset })?;
+    if key_size == 0 || key_size > payload.len() as u64 {
+        return Err(Errors::InvaildExport { offset });
+    }
+    let key = Bytes::copy_from_slice(&payload[..key_size as usize]);
+    let value = Bytes::copy_from_slice(&payload[key_size as usize..]);
+    Ok((key, value, timestamp, payload_size as u64 + 9))
+}
+
+// 读取固定长度的数据，数据提前结束说明导出的数据不完整
+fn read_exact<R: Read>(reader: &mut R, buf: &mut [u8], offset: u64) -> Result<()> {
+    match reader.read_exact(buf) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+            Err(Errors::InvaildExport { offset })
+        }
+        Err(e) => Err(export_err(e)),
+    }
+}
+
+fn export_err(e: io::Error) -> Errors {
+    error!("export stream io err: {}", e);
+    Errors::FailedToAccessExport { source: e.into() }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::{
+        options::Options,
+        util::rand_kv::{get_test_key, get_test_value},
+    };
+
+    #[test]
+    fn test_export_import() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-export");
+        opts.data_file_size = 64 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..1000 {
+            assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        for i in 0..100 {
+            assert!(engine.delete(get_test_key(i)).is_ok());
+        }
+        let bucket = engine.bucket("users").unwrap();
+        assert!(bucket.put(get_test_key(1), get_test_value(1)).is_ok());
+
+        let mut exported = Vec::new();
+        assert_eq!(engine.export(&mut exported).unwrap(), 901);
+
+        // 导入到新的数据库中，数据和写入时间都和原来的数据库相同
+        let mut import_opts = opts.clone();
+        import_opts.dir_path = PathBuf::from("/tmp/bitcask-rs-import");
+        let imported = Engine::open(import_opts.clone()).expect("failed to open engine");
+        assert_eq!(imported.import(&mut exported.as_slice()).unwrap(), 901);
+        assert_eq!(imported.list_keys().unwrap().len(), 900);
+        for i in 0..1000 {
+            match i < 100 {
+                true => assert_eq!(
+                    imported.get(get_test_key(i)).err().unwrap(),
+                    Errors::KeyNotFound
+                ),
+                false => {
+                    let (value, meta) = imported.get_with_meta(get_test_key(i)).unwrap();
+                    assert_eq!(value, get_test_value(i));
+                    let (_, origin_meta) = engine.get_with_meta(get_test_key(i)).unwrap();
+                    assert_eq!(meta.timestamp, origin_meta.timestamp);
+                }
+            }
+        }
+        let imported_bucket = imported.bucket("users").unwrap();
+        assert_eq!(
+            imported_bucket.get(get_test_key(1)).unwrap(),
+            get_test_value(1)
+        );
+
+        // 截断或者损坏的数据无法导入
+        let truncated = &exported[..exported.len() - 1];
+        assert_eq!(
+            imported.import(&mut &truncated[..]).err().unwrap(),
+            Errors::InvaildExport {
+                offset: exported.len() as u64 - 13
+            }
+        );
+        let mut corrupted = exported.clone();
+        corrupted[20] ^= 0xff;
+        assert_eq!(
+            imported.import(&mut corrupted.as_slice()).err().unwrap(),
+            Errors::InvaildExport { offset: 6 }
+        );
+        let mut unsupported = exported.clone();
+        unsupported[5] = 2;
+        assert_eq!(
+            imported.import(&mut unsupported.as_slice()).err().unwrap(),
+            Errors::UnsupportedExportVersion(2)
+        );
+
+        std::mem::drop(engine);
+        std::mem::drop(imported);
+        std::fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+        std::fs::remove_dir_all(import_opts.dir_path).expect("failed to remove path");
+    }
+}
@@ -0,0 +1,278 @@
+use std::{
+    alloc::{self, Layout},
+    fs::{File, OpenOptions},
+    ops::{Deref, DerefMut},
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+    ptr::NonNull,
+};
+
+#[cfg(target_os = "linux")]
+use std::os::unix::fs::OpenOptionsExt;
+
+use log::{error, warn};
+use parking_lot::RwLock;
+
+use super::IOManager;
+
+use crate::errors::{Errors, Result};
+
+// O_DIRECT 要求读写的偏移、长度以及内存地址按照块大小对齐
+const DIRECT_IO_ALIGNMENT: usize = 4096;
+
+/// 使用 O_DIRECT 打开的文件 IO，读写都绕过操作系统的页缓存
+/// 写入时将不足一个块的尾部数据补齐之后写入，再将文件截断到实际的长度，下一次写入时重写这个块，
+/// 因此文件中的数据和标准文件 IO 写入的完全相同
+/// 文件系统不支持 O_DIRECT 时（例如 tmpfs）退化为普通的文件读写
+pub struct DirectIO {
+    inner: RwLock<DirectFile>,
+    path: PathBuf, // 文件路径，出错时记录在错误中
+}
+
+struct DirectFile {
+    fd: File,      // 系统文件描述符
+    size: u64,     // 文件的实际长度
+    tail: Vec<u8>, // 最后一个不完整的块中的数据
+}
+
+impl DirectIO {
+    pub fn new(file_name: PathBuf) -> Result<Self> {
+        let fd = open_direct(&file_name).map_err(|e| {
+            error!("open data file err: {}", e);
+            Errors::open_failed(&file_name, e)
+        })?;
+        let size = fd
+            .metadata()
+            .map_err(|e| {
+                error!("open data file err: {}", e);
+                Errors::open_failed(&file_name, e)
+            })?
+            .len();
+
+        // 读出最后一个不完整的块，之后的写入需要和它拼接成完整的块
+        let tail_start = align_down(size);
+        let mut block = AlignedBuf::new(DIRECT_IO_ALIGNMENT);
+        let tail_len = (size - tail_start) as usize;
+        if tail_len > 0 {
+            read_full_at(&fd, &file_name, &mut block, tail_start)?;
+        }
+
+        Ok(DirectIO {
+            inner: RwLock::new(DirectFile {
+                fd,
+                size,
+                tail: block[..tail_len].to_vec(),
+            }),
+            path: file_name,
+        })
+    }
+}
+
+impl IOManager for DirectIO {
+    fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        let inner = self.inner.read();
+        if offset >= inner.size {
+            return Ok(0);
+        }
+
+        // 按照块对齐读取包含目标数据的范围
+        let len = buf.len().min((inner.size - offset) as usize);
+        let start = align_down(offset);
+        let end = align_up(offset + len as u64);
+        let mut aligned = AlignedBuf::new((end - start) as usize);
+        read_full_at(&inner.fd, &self.path, &mut aligned, start)?;
+
+        let begin = (offset - start) as usize;
+        buf[..len].copy_from_slice(&aligned[begin..begin + len]);
+        Ok(len)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        let mut inner = self.inner.write();
+
+        // 从最后一个不完整的块开始，补齐之后整块写入
+        let tail_start = align_down(inner.size);
+        let data_len = inner.tail.len() + buf.len();
+        let mut aligned = AlignedBuf::new(align_up(data_len as u64) as usize);
+        aligned[..inner.tail.len()].copy_from_slice(&inner.tail);
+        aligned[inner.tail.len()..data_len].copy_from_slice(buf);
+
+        let new_size = inner.size + buf.len() as u64;
+        let res = inner
+            .fd
+            .write_all_at(&aligned, tail_start)
+            .and_then(|_| inner.fd.set_len(new_size));
+        if let Err(e) = res {
+            error!("write data to data file err: {}", e);
+            return Err(Errors::write_failed(&self.path, e));
+        }
+
+        let new_tail_start = (align_down(new_size) - tail_start) as usize;
+        inner.tail = aligned[new_tail_start..data_len].to_vec();
+        inner.size = new_size;
+        Ok(buf.len())
+    }
+
+    fn sync(&self) -> Result<()> {
+        let inner = self.inner.read();
+        if let Err(e) = inner.fd.sync_all() {
+            error!("sync data file err: {}", e);
+            return Err(Errors::sync_failed(&self.path, e));
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        self.inner.read().size
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn open_direct(file_name: &Path) -> std::io::Result<File> {
+    let res = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .write(true)
+        .truncate(false)
+        .custom_flags(libc::O_DIRECT)
+        .open(file_name);
+    match res {
+        Err(e) if e.raw_os_error() == Some(libc::EINVAL) => {
+            warn!(
+                "O_DIRECT is not supported for {:?}, fallback to buffered io",
+                file_name
+            );
+            open_buffered(file_name)
+        }
+        res => res,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_direct(file_name: &Path) -> std::io::Result<File> {
+    warn!("O_DIRECT is not supported on this platform, fallback to buffered io");
+    open_buffered(file_name)
+}
+
+fn open_buffered(file_name: &Path) -> std::io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .read(true)
+        .write(true)
+        .truncate(false)
+        .open(file_name)
+}
+
+// 读满 buf，读到文件末尾时剩余的部分保持为 0
+fn read_full_at(fd: &File, path: &Path, buf: &mut [u8], offset: u64) -> Result<()> {
+    let mut n = 0;
+    while n < buf.len() {
+        match fd.read_at(&mut buf[n..], offset + n as u64) {
+            Ok(0) => break,
+            Ok(size) => n += size,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => {
+                error!("read data from data file err: {}", e);
+                return Err(Errors::read_failed(path, offset + n as u64, e));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn align_down(offset: u64) -> u64 {
+    offset / DIRECT_IO_ALIGNMENT as u64 * DIRECT_IO_ALIGNMENT as u64
+}
+
+fn align_up(offset: u64) -> u64 {
+    align_down(offset + DIRECT_IO_ALIGNMENT as u64 - 1)
+}
+
+// 按照块大小对齐的内存，初始化为 0
+struct AlignedBuf {
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+impl AlignedBuf {
+    fn new(len: usize) -> Self {
+        let layout = Self::layout(len);
+        // SAFETY: layout 的大小至少为一个块，不会为 0
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        match NonNull::new(ptr) {
+            Some(ptr) => AlignedBuf { ptr, len },
+            None => alloc::handle_alloc_error(layout),
+        }
+    }
+
+    fn layout(len: usize) -> Layout {
+        Layout::from_size_align(len.max(DIRECT_IO_ALIGNMENT), DIRECT_IO_ALIGNMENT).unwrap()
+    }
+}
+
+impl Deref for AlignedBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: ptr 指向 new 中分配并初始化的至少 len 字节的内存
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for AlignedBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: 同 deref，并且 &mut self 保证了独占访问
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for AlignedBuf {
+    fn drop(&mut self) {
+        // SAFETY: 使用和分配时相同的 layout 释放
+        unsafe { alloc::dealloc(self.ptr.as_ptr(), Self::layout(self.len)) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_direct_io_write_read() {
+        let path = PathBuf::from("/tmp/bitcask-rs-direct-io-a.data");
+        let _ = fs::remove_file(&path);
+
+        let dio = DirectIO::new(path.clone()).unwrap();
+        let mut expected = Vec::new();
+        for i in 0..200 {
+            let data = vec![i as u8; 37 * (i % 7 + 1)];
+            assert_eq!(dio.write(&data).unwrap(), data.len());
+            expected.extend_from_slice(&data);
+        }
+        assert_eq!(dio.size(), expected.len() as u64);
+        assert!(dio.sync().is_ok());
+        // 文件中的内容和写入的数据完全相同，没有补齐的数据
+        assert_eq!(fs::read(&path).unwrap(), expected);
+
+        // 跨块读取以及读到文件末尾
+        let mut buf = vec![0u8; 5000];
+        assert_eq!(dio.read(&mut buf, 4000).unwrap(), 5000);
+        assert_eq!(buf, expected[4000..9000]);
+        let offset = expected.len() as u64 - 10;
+        assert_eq!(dio.read(&mut buf, offset).unwrap(), 10);
+        assert_eq!(buf[..10], expected[expected.len() - 10..]);
+        assert_eq!(dio.read(&mut buf, expected.len() as u64).unwrap(), 0);
+
+        // 重新打开之后继续追加
+        std::mem::drop(dio);
+        let dio = DirectIO::new(path.clone()).unwrap();
+        assert_eq!(dio.size(), expected.len() as u64);
+        assert!(dio.write(b"appended").is_ok());
+        expected.extend_from_slice(b"appended");
+        assert_eq!(fs::read(&path).unwrap(), expected);
+
+        fs::remove_file(path).unwrap();
+    }
+}
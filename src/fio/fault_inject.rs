@@ -0,0 +1,212 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use parking_lot::Mutex;
+
+use super::IOManager;
+
+use crate::errors::{Errors, Result};
+
+// 注册了故障注入的数据目录，目录中新打开的文件都会使用 FaultInjectIO
+static INJECTORS: Mutex<Vec<(PathBuf, Arc<FaultInjector>)>> = Mutex::new(Vec::new());
+
+// 表示没有设置故障点
+const NO_FAULT: u64 = u64::MAX;
+
+/// 故障注入的配置，统计目录中所有文件的写入和持久化次数，在指定的位置返回错误
+/// 用于测试写入失败或者宕机之后数据能否正确恢复
+pub struct FaultInjector {
+    writes: AtomicU64,             // 已经成功写入的次数
+    syncs: AtomicU64,              // 已经成功持久化的次数
+    fail_write_at: AtomicU64,      // 成功写入这么多次之后所有的写入都失败
+    fail_sync_at: AtomicU64,       // 成功持久化这么多次之后所有的持久化都失败
+    fail_next_sync: AtomicBool,    // 下一次持久化失败，之后的持久化恢复正常
+    torn_write_bytes: AtomicUsize, // 写入失败时仍然写入的字节数，模拟宕机时没有完整写入的数据
+}
+
+impl FaultInjector {
+    fn new() -> Self {
+        Self {
+            writes: AtomicU64::new(0),
+            syncs: AtomicU64::new(0),
+            fail_write_at: AtomicU64::new(NO_FAULT),
+            fail_sync_at: AtomicU64::new(NO_FAULT),
+            fail_next_sync: AtomicBool::new(false),
+            torn_write_bytes: AtomicUsize::new(0),
+        }
+    }
+
+    /// 再成功写入 n 次之后，之后所有的写入都返回错误
+    pub fn fail_writes_after(&self, n: u64) {
+        let writes = self.writes.load(Ordering::SeqCst);
+        self.fail_write_at.store(writes + n, Ordering::SeqCst);
+    }
+
+    /// 再成功持久化 n 次之后，之后所有的持久化都返回错误
+    pub fn fail_syncs_after(&self, n: u64) {
+        let syncs = self.syncs.load(Ordering::SeqCst);
+        self.fail_sync_at.store(syncs + n, Ordering::SeqCst);
+    }
+
+    /// 只有下一次持久化返回错误，之后的持久化恢复正常，模拟短暂的 IO 故障
+    pub fn fail_next_sync(&self) {
+        self.fail_next_sync.store(true, Ordering::SeqCst);
+    }
+
+    /// 第一次写入失败时仍然写入前 bytes 个字节，模拟宕机时只写入了一部分的数据
+    pub fn torn_writes(&self, bytes: usize) {
+        self.torn_write_bytes.store(bytes, Ordering::SeqCst);
+    }
+
+    /// 清除所有的故障点，读写恢复正常
+    pub fn clear(&self) {
+        self.fail_write_at.store(NO_FAULT, Ordering::SeqCst);
+        self.fail_sync_at.store(NO_FAULT, Ordering::SeqCst);
+        self.fail_next_sync.store(false, Ordering::SeqCst);
+        self.torn_write_bytes.store(0, Ordering::SeqCst);
+    }
+
+    /// 已经成功写入的次数
+    pub fn write_count(&self) -> u64 {
+        self.writes.load(Ordering::SeqCst)
+    }
+
+    /// 已经成功持久化的次数
+    pub fn sync_count(&self) -> u64 {
+        self.syncs.load(Ordering::SeqCst)
+    }
+
+    /// 是否已经有写入或者持久化因为故障失败
+    pub fn is_triggered(&self) -> bool {
+        self.writes.load(Ordering::SeqCst) >= self.fail_write_at.load(Ordering::SeqCst)
+            || self.syncs.load(Ordering::SeqCst) >= self.fail_sync_at.load(Ordering::SeqCst)
+    }
+}
+
+/// 在数据目录上注册故障注入，之后在目录（以及 merge 使用的临时目录）中打开的文件都会按照配置注入故障
+/// 返回的 FaultInjection 被释放时取消注册
+pub fn register(dir_path: &Path) -> FaultInjection {
+    let injector = Arc::new(FaultInjector::new());
+    INJECTORS
+        .lock()
+        .push((dir_path.to_path_buf(), injector.clone()));
+    FaultInjection {
+        dir_path: dir_path.to_path_buf(),
+        injector,
+    }
+}
+
+/// 数据目录上注册的故障注入，释放时取消注册
+pub struct FaultInjection {
+    dir_path: PathBuf,
+    injector: Arc<FaultInjector>,
+}
+
+impl std::ops::Deref for FaultInjection {
+    type Target = FaultInjector;
+
+    fn deref(&self) -> &FaultInjector {
+        &self.injector
+    }
+}
+
+impl Drop for FaultInjection {
+    fn drop(&mut self) {
+        INJECTORS.lock().retain(|(dir_path, injector)| {
+            !(dir_path == &self.dir_path && Arc::ptr_eq(injector, &self.injector))
+        });
+    }
+}
+
+// 查找文件所在目录注册的故障注入，merge 等临时目录位于数据目录旁边，名称以数据目录的名称加 - 开头
+pub(crate) fn find_injector(file_name: &Path) -> Option<Arc<FaultInjector>> {
+    let parent = file_name.parent()?;
+    let injectors = INJECTORS.lock();
+    injectors
+        .iter()
+        .find(|(dir_path, _)| {
+            if parent == dir_path.as_path() {
+                return true;
+            }
+            let dir_name = dir_path.file_name().and_then(|name| name.to_str());
+            let parent_name = parent.file_name().and_then(|name| name.to_str());
+            match (dir_name, parent_name) {
+                (Some(dir_name), Some(parent_name)) => {
+                    parent.parent() == dir_path.parent()
+                        && parent_name.starts_with(&format!("{}-", dir_name))
+                }
+                _ => false,
+            }
+        })
+        .map(|(_, injector)| injector.clone())
+}
+
+/// 按照 FaultInjector 的配置注入故障的 IO，其余的操作交给实际的 IO 处理
+pub struct FaultInjectIO {
+    inner: Box<dyn IOManager>,
+    injector: Arc<FaultInjector>,
+    path: PathBuf,
+}
+
+impl FaultInjectIO {
+    pub fn new(path: PathBuf, inner: Box<dyn IOManager>, injector: Arc<FaultInjector>) -> Self {
+        Self {
+            inner,
+            injector,
+            path,
+        }
+    }
+}
+
+impl IOManager for FaultInjectIO {
+    fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        self.inner.read(buf, offset)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        let injector = &self.injector;
+        if injector.writes.load(Ordering::SeqCst) >= injector.fail_write_at.load(Ordering::SeqCst) {
+            // 只有第一次失败的写入会写入部分数据
+            let torn_bytes = injector.torn_write_bytes.swap(0, Ordering::SeqCst);
+            if torn_bytes > 0 {
+                self.inner.write(&buf[..torn_bytes.min(buf.len())])?;
+            }
+            return Err(Errors::write_failed(&self.path, injected_error()));
+        }
+
+        let n = self.inner.write(buf)?;
+        injector.writes.fetch_add(1, Ordering::SeqCst);
+        Ok(n)
+    }
+
+    fn preallocate(&self, len: u64) -> Result<()> {
+        self.inner.preallocate(len)
+    }
+
+    fn sync(&self) -> Result<()> {
+        let injector = &self.injector;
+        if injector.syncs.load(Ordering::SeqCst) >= injector.fail_sync_at.load(Ordering::SeqCst)
+            || injector.fail_next_sync.swap(false, Ordering::SeqCst)
+        {
+            return Err(Errors::sync_failed(&self.path, injected_error()));
+        }
+
+        self.inner.sync()?;
+        injector.syncs.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        self.inner.size()
+    }
+}
+
+// 注入的故障对应的 IO 错误
+fn injected_error() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Other, "injected fault")
+}
@@ -0,0 +1,212 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Weak,
+    },
+};
+
+use parking_lot::Mutex;
+
+use super::{data_file_exists, new_io_manager, IOManager};
+
+use crate::{
+    errors::{Errors, Result},
+    options::IOType,
+};
+
+/// 旧数据文件的打开句柄缓存，限制同时打开的旧数据文件数量
+/// 超过上限时关闭最近最少使用的文件，之后读取时再重新打开
+pub struct FileCache {
+    capacity: usize,
+    clock: AtomicU64,   // 逻辑时钟，记录每个文件最后一次读取的先后顺序
+    next_id: AtomicU64, // 分配给每个 CachedIO 的唯一 ID
+    opened: Mutex<HashMap<u64, Weak<Slot>>>, // 当前打开了句柄的文件
+}
+
+// 一个旧数据文件的句柄，被淘汰之后为空
+struct Slot {
+    handle: Mutex<Option<Arc<dyn IOManager>>>,
+    last_used: AtomicU64,
+}
+
+impl FileCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            clock: AtomicU64::new(0),
+            next_id: AtomicU64::new(0),
+            opened: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 当前打开了句柄的旧数据文件数量
+    pub fn open_files(&self) -> usize {
+        self.opened.lock().len()
+    }
+
+    fn touch(&self, slot: &Slot) {
+        let now = self.clock.fetch_add(1, Ordering::SeqCst);
+        slot.last_used.store(now, Ordering::SeqCst);
+    }
+
+    // 记录新打开的文件，超过上限时关闭最近最少使用的文件
+    // 正在读取被淘汰文件的调用方仍然持有句柄，读取完成之后才真正关闭
+    fn insert(&self, id: u64, slot: &Arc<Slot>) {
+        let mut opened = self.opened.lock();
+        opened.insert(id, Arc::downgrade(slot));
+        while opened.len() > self.capacity {
+            let victim = opened
+                .iter()
+                .filter(|(victim_id, _)| **victim_id != id)
+                .min_by_key(|(_, slot)| match slot.upgrade() {
+                    Some(slot) => slot.last_used.load(Ordering::SeqCst),
+                    None => 0,
+                })
+                .map(|(victim_id, _)| *victim_id);
+            let victim = match victim {
+                Some(victim) => victim,
+                None => break,
+            };
+            if let Some(slot) = opened.remove(&victim).and_then(|slot| slot.upgrade()) {
+                slot.handle.lock().take();
+            }
+        }
+    }
+
+    fn remove(&self, id: u64) {
+        self.opened.lock().remove(&id);
+    }
+}
+
+/// 通过 FileCache 按需打开的旧数据文件，只能读取
+/// 读取时文件没有打开的话重新打开，文件的大小在创建时确定，之后不会再变化
+pub struct CachedIO {
+    id: u64,
+    path: PathBuf,
+    io_type: IOType,
+    size: u64,
+    slot: Arc<Slot>,
+    cache: Arc<FileCache>,
+}
+
+impl CachedIO {
+    pub fn new(path: PathBuf, io_type: IOType, size: u64, cache: &Arc<FileCache>) -> Self {
+        Self {
+            id: cache.next_id.fetch_add(1, Ordering::SeqCst),
+            path,
+            io_type,
+            size,
+            slot: Arc::new(Slot {
+                handle: Mutex::new(None),
+                last_used: AtomicU64::new(0),
+            }),
+            cache: cache.clone(),
+        }
+    }
+
+    // 获取文件的句柄，没有打开时重新打开
+    fn handle(&self) -> Result<Arc<dyn IOManager>> {
+        let mut handle = self.slot.handle.lock();
+        if let Some(io_manager) = handle.as_ref() {
+            let io_manager = io_manager.clone();
+            drop(handle);
+            self.cache.touch(&self.slot);
+            return Ok(io_manager);
+        }
+
+        // 文件已经被 merge 删除的话不能重新创建
+        if !data_file_exists(&self.path) {
+            return Err(Errors::DataFileNotFound);
+        }
+        let io_manager: Arc<dyn IOManager> =
+            Arc::from(new_io_manager(self.path.clone(), self.io_type)?);
+        *handle = Some(io_manager.clone());
+        drop(handle);
+
+        self.cache.touch(&self.slot);
+        self.cache.insert(self.id, &self.slot);
+        Ok(io_manager)
+    }
+}
+
+impl IOManager for CachedIO {
+    fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        self.handle()?.read(buf, offset)
+    }
+
+    fn write(&self, _buf: &[u8]) -> Result<usize> {
+        Err(Errors::DataFileIsSealed)
+    }
+
+    fn sync(&self) -> Result<()> {
+        // 没有打开的文件在关闭之前已经持久化过了
+        let handle = self.slot.handle.lock().clone();
+        match handle {
+            Some(io_manager) => io_manager.sync(),
+            None => Ok(()),
+        }
+    }
+
+    fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl Drop for CachedIO {
+    fn drop(&mut self) {
+        self.cache.remove(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_cached_io_read() {
+        let dir_path = PathBuf::from("/tmp/bitcask-rs-file-cache");
+        fs::create_dir_all(&dir_path).unwrap();
+        let cache = Arc::new(FileCache::new(2));
+
+        let mut files = Vec::new();
+        for i in 0..5 {
+            let path = dir_path.join(format!("{}.data", i));
+            fs::write(&path, format!("file-{}", i)).unwrap();
+            files.push(CachedIO::new(path, IOType::StandardFIO, 6, &cache));
+        }
+        assert_eq!(cache.open_files(), 0);
+
+        // 同时打开的文件数量不超过上限
+        for _ in 0..3 {
+            for (i, file) in files.iter().enumerate() {
+                let mut buf = [0u8; 6];
+                assert_eq!(file.read(&mut buf, 0).unwrap(), 6);
+                assert_eq!(buf, format!("file-{}", i).as_bytes());
+                assert!(cache.open_files() <= 2);
+            }
+        }
+        assert_eq!(
+            files[0].write(b"a").err().unwrap(),
+            Errors::DataFileIsSealed
+        );
+        assert_eq!(files[0].size(), 6);
+
+        // 删除之后的文件不会被重新创建
+        let path = dir_path.join("0.data");
+        fs::remove_file(&path).unwrap();
+        let mut buf = [0u8; 6];
+        assert_eq!(
+            files[0].read(&mut buf, 0).err().unwrap(),
+            Errors::DataFileNotFound
+        );
+        assert!(!path.exists());
+
+        std::mem::drop(files);
+        assert_eq!(cache.open_files(), 0);
+        fs::remove_dir_all(dir_path).expect("failed to remove path");
+    }
+}
@@ -1,7 +1,7 @@
 use std::{
     fs::{File, OpenOptions},
-    io::Write,
-    os::unix::fs::FileExt,
+    io::{self, IoSlice, Write},
+    os::unix::{fs::FileExt, io::AsRawFd},
     path::PathBuf,
     sync::Arc,
 };
@@ -16,6 +16,7 @@ use crate::errors::{Errors, Result};
 /// 标准系统文件 IO
 pub struct FileIO {
     fd: Arc<RwLock<File>>, // 系统文件描述符
+    path: PathBuf,         // 文件路径，出错时记录在错误中
 }
 
 impl FileIO {
@@ -25,16 +26,17 @@ impl FileIO {
             .read(true)
             .write(true)
             .append(true)
-            .open(file_name)
+            .open(&file_name)
         {
             Ok(file) => {
                 return Ok(FileIO {
                     fd: Arc::new(RwLock::new(file)),
+                    path: file_name,
                 });
             }
             Err(e) => {
                 error!("open data file err: {}", e);
-                return Err(Errors::FailedOpenDataFile);
+                return Err(Errors::open_failed(&file_name, e));
             }
         }
     }
@@ -47,7 +49,7 @@ impl IOManager for FileIO {
             Ok(n) => return Ok(n),
             Err(e) => {
                 error!("read data from data file err: {}", e);
-                return Err(Errors::FailedToReadDataFromDataFile);
+                return Err(Errors::read_failed(&self.path, offset, e));
             }
         }
     }
@@ -58,16 +60,68 @@ impl IOManager for FileIO {
             Ok(n) => return Ok(n),
             Err(e) => {
                 error!("write data to data file err: {}", e);
-                return Err(Errors::FailedToWriteDataToDataFile);
+                return Err(Errors::write_failed(&self.path, e));
             }
         }
     }
 
+    // 通过 writev 一次系统调用写入多段数据，只写入了一部分时继续写入剩余的数据
+    fn write_vectored(&self, bufs: &[IoSlice]) -> Result<usize> {
+        let mut write_guard = self.fd.write();
+        let mut slices = bufs.to_vec();
+        let mut remaining = &mut slices[..];
+        let mut written = 0;
+        while !remaining.is_empty() {
+            match write_guard.write_vectored(remaining) {
+                Ok(0) => {
+                    let e = io::Error::from(io::ErrorKind::WriteZero);
+                    error!("write data to data file err: {}", e);
+                    return Err(Errors::write_failed(&self.path, e));
+                }
+                Ok(n) => {
+                    written += n;
+                    IoSlice::advance_slices(&mut remaining, n);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    error!("write data to data file err: {}", e);
+                    return Err(Errors::write_failed(&self.path, e));
+                }
+            }
+        }
+        Ok(written)
+    }
+
     fn sync(&self) -> Result<()> {
         let read_guard = self.fd.read();
         if let Err(e) = read_guard.sync_all() {
             error!("sync data file err: {}", e);
-            return Err(Errors::FailedSyncDataFile);
+            return Err(Errors::sync_failed(&self.path, e));
+        }
+        Ok(())
+    }
+
+    // 使用 FALLOC_FL_KEEP_SIZE 只分配磁盘空间，不改变文件大小，追加写入的位置和读取到的文件大小不受影响，
+    // set_len 会改变文件大小，不能用于追加写入的数据文件
+    #[cfg(target_os = "linux")]
+    fn preallocate(&self, len: u64) -> Result<()> {
+        let read_guard = self.fd.read();
+        let ret = unsafe {
+            libc::fallocate(
+                read_guard.as_raw_fd(),
+                libc::FALLOC_FL_KEEP_SIZE,
+                0,
+                len as libc::off_t,
+            )
+        };
+        if ret != 0 {
+            let e = io::Error::last_os_error();
+            // 文件系统不支持预分配时忽略
+            if e.raw_os_error() == Some(libc::EOPNOTSUPP) {
+                return Ok(());
+            }
+            error!("preallocate data file err: {}", e);
+            return Err(Errors::write_failed(&self.path, e));
         }
         Ok(())
     }
@@ -133,6 +187,30 @@ mod tests {
         assert!(res3.is_ok());
     }
 
+    #[test]
+    fn test_file_io_write_vectored() {
+        let path = PathBuf::from("/tmp/bitcask-rs-file-io-write-vectored.data");
+        let fio = FileIO::new(path.clone()).unwrap();
+
+        let res1 = fio.write("key-a".as_bytes());
+        assert_eq!(5, res1.ok().unwrap());
+        let bufs = [
+            IoSlice::new("key-b".as_bytes()),
+            IoSlice::new("".as_bytes()),
+            IoSlice::new("key-cc".as_bytes()),
+        ];
+        let res2 = fio.write_vectored(&bufs);
+        assert_eq!(11, res2.ok().unwrap());
+        assert_eq!(16, fio.size());
+
+        let mut buf = [0u8; 16];
+        assert_eq!(16, fio.read(&mut buf, 0).unwrap());
+        assert_eq!(&buf, b"key-akey-bkey-cc");
+
+        let res3 = fs::remove_file(path.clone());
+        assert!(res3.is_ok());
+    }
+
     #[test]
     fn test_file_io_sync() {
         let path = PathBuf::from("/tmp/c.data");
@@ -154,4 +232,21 @@ mod tests {
         let res3 = fs::remove_file(path.clone());
         assert!(res3.is_ok());
     }
+
+    #[test]
+    fn test_file_io_open_error() {
+        let path = PathBuf::from("/tmp/bitcask-rs-file-io-not-exist/a.data");
+        let err = FileIO::new(path.clone()).err().unwrap();
+        match &err {
+            Errors::FailedOpenDataFile { path: p, source } => {
+                assert_eq!(*p, path.display().to_string());
+                assert_eq!(source.kind(), std::io::ErrorKind::NotFound);
+            }
+            _ => panic!("unexpected error: {}", err),
+        }
+        // 错误信息中带有出错的文件路径
+        assert!(err
+            .to_string()
+            .contains("/tmp/bitcask-rs-file-io-not-exist/a.data"));
+    }
 }
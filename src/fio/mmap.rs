@@ -14,7 +14,7 @@ pub struct MMapIO {
 
 impl MMapIO {
     pub fn new(file_name: PathBuf) -> Result<Self> {
-        match OpenOptions::new().read(true).open(file_name) {
+        match OpenOptions::new().read(true).open(&file_name) {
             Ok(file) => {
                 let map = unsafe { Mmap::map(&file).expect("failed to map the file") };
 
@@ -24,7 +24,7 @@ impl MMapIO {
             }
             Err(e) => {
                 error!("open data file err: {}", e);
-                return Err(Errors::FailedOpenDataFile);
+                return Err(Errors::open_failed(&file_name, e));
             }
         }
     }
@@ -49,7 +49,8 @@ impl IOManager for MMapIO {
     }
 
     fn sync(&self) -> Result<()> {
-        unimplemented!();
+        // 只读映射，没有需要持久化的数据
+        Ok(())
     }
 
     fn size(&self) -> u64 {
@@ -1,28 +1,88 @@
+pub mod direct_io;
+#[cfg(any(test, feature = "fault-inject"))]
+pub mod fault_inject;
+pub mod file_cache;
 pub mod file_io;
 pub mod mmap;
+#[cfg(feature = "object-store")]
+pub mod object_store;
+pub mod sequential;
 
-use std::path::PathBuf;
+use std::{
+    io::IoSlice,
+    path::{Path, PathBuf},
+};
 
+use direct_io::DirectIO;
 use file_io::FileIO;
 use mmap::MMapIO;
+#[cfg(feature = "object-store")]
+use object_store::ObjectStoreIO;
 
 use crate::{errors::Result, options::IOType};
 
-/// 抽象 IO 管理接口，可以接入不同的 IO 类型，目前支持标准文件 IO
+/// 抽象 IO 管理接口，可以接入不同的 IO 类型，目前支持标准文件 IO、内存文件映射和 O_DIRECT 文件 IO
 pub trait IOManager: Sync + Send {
     /// 从文件给定位置读取对应的数据
     fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize>;
     /// 写入字节数组到文件
     fn write(&self, buf: &[u8]) -> Result<usize>;
+    /// 按照顺序写入多个字节数组到文件，默认拷贝到同一个缓冲区之后调用一次 write
+    fn write_vectored(&self, bufs: &[IoSlice]) -> Result<usize> {
+        if let [buf] = bufs {
+            return self.write(buf);
+        }
+        let mut data = Vec::with_capacity(bufs.iter().map(|buf| buf.len()).sum());
+        for buf in bufs {
+            data.extend_from_slice(buf);
+        }
+        self.write(&data)
+    }
     /// 持久化数据
     fn sync(&self) -> Result<()>;
+    /// 为文件预先分配 len 字节的磁盘空间，不改变文件大小，默认不做任何操作
+    fn preallocate(&self, _len: u64) -> Result<()> {
+        Ok(())
+    }
     /// 获取文件的大小
     fn size(&self) -> u64;
 }
 
-pub fn new_io_manager(file_name: PathBuf, io_type: IOType) -> Box<dyn IOManager> {
-    match io_type {
-        IOType::StandardFIO => Box::new(FileIO::new(file_name).unwrap()),
-        IOType::MemoryMap => Box::new(MMapIO::new(file_name).unwrap()),
+/// 根据 IO 类型打开文件，打开失败时返回错误，例如文件系统不支持 O_DIRECT
+pub fn new_io_manager(file_name: PathBuf, io_type: IOType) -> Result<Box<dyn IOManager>> {
+    #[cfg(any(test, feature = "fault-inject"))]
+    let injector = fault_inject::find_injector(&file_name);
+
+    // 已经上传到对象存储并且删除了本地文件的数据文件只能从对象存储中读取
+    #[cfg(feature = "object-store")]
+    let io_type = match !file_name.is_file() && object_store::is_archived_file(&file_name) {
+        true => IOType::ObjectStore,
+        false => io_type,
+    };
+
+    let io_manager: Box<dyn IOManager> = match io_type {
+        IOType::StandardFIO => Box::new(FileIO::new(file_name.clone())?),
+        IOType::MemoryMap => Box::new(MMapIO::new(file_name.clone())?),
+        IOType::DirectIO => Box::new(DirectIO::new(file_name.clone())?),
+        #[cfg(feature = "object-store")]
+        IOType::ObjectStore => Box::new(ObjectStoreIO::new(file_name.clone())?),
+    };
+
+    // 注册了故障注入的目录中的文件按照配置注入故障
+    #[cfg(any(test, feature = "fault-inject"))]
+    if let Some(injector) = injector {
+        return Ok(Box::new(fault_inject::FaultInjectIO::new(
+            file_name, io_manager, injector,
+        )));
+    }
+    Ok(io_manager)
+}
+
+/// 数据文件是否存在，已经上传到对象存储中的数据文件即使删除了本地文件也认为存在
+pub(crate) fn data_file_exists(file_name: &Path) -> bool {
+    #[cfg(feature = "object-store")]
+    if object_store::is_archived_file(file_name) {
+        return true;
     }
+    file_name.is_file()
 }
@@ -0,0 +1,476 @@
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use log::error;
+use parking_lot::{Mutex, RwLock};
+
+use super::IOManager;
+
+use crate::{
+    data::data_file::DATA_FILE_NAME_SUFFIX,
+    errors::{Errors, Result},
+    options::ObjectStoreOptions,
+};
+
+// 配置了对象存储的数据目录，目录中已经上传的数据文件从对象存储中读取
+static ARCHIVES: Mutex<Vec<(PathBuf, Arc<Archive>)>> = Mutex::new(Vec::new());
+
+/// 对象存储接口，接入 S3 等兼容的对象存储时实现这个接口即可
+/// 对象通过 key 访问，上传之后不会再修改
+pub trait ObjectStore: Sync + Send {
+    /// 读取对象从 offset 开始最多 len 个字节的数据，超出对象大小的部分不返回
+    fn get_range(&self, key: &str, offset: u64, len: usize) -> Result<Vec<u8>>;
+    /// 将本地文件上传为对象，对象已经存在时覆盖
+    fn put(&self, key: &str, file_path: &Path) -> Result<()>;
+    /// 对象的大小，对象不存在时返回 None
+    fn size(&self, key: &str) -> Result<Option<u64>>;
+    /// 列出以 prefix 开头的所有对象的 key
+    fn list(&self, prefix: &str) -> Result<Vec<String>>;
+    /// 删除对象，对象不存在时不返回错误
+    fn delete(&self, key: &str) -> Result<()>;
+}
+
+/// 使用本地目录模拟的对象存储，对象存放在以 key 命名的文件中，
+/// 可以用于测试，或者对接挂载到本地的对象存储
+pub struct LocalObjectStore {
+    root: PathBuf,
+}
+
+impl LocalObjectStore {
+    pub fn new(root: PathBuf) -> Result<Self> {
+        if let Err(e) = fs::create_dir_all(&root) {
+            error!("failed to create object store dir: {}", e);
+            return Err(Errors::FailedToAccessObjectStore);
+        }
+        Ok(Self { root })
+    }
+}
+
+fn object_store_error(e: std::io::Error) -> Errors {
+    error!("failed to access object store: {}", e);
+    Errors::FailedToAccessObjectStore
+}
+
+impl ObjectStore for LocalObjectStore {
+    fn get_range(&self, key: &str, offset: u64, len: usize) -> Result<Vec<u8>> {
+        let mut file = File::open(self.root.join(key)).map_err(object_store_error)?;
+        file.seek(SeekFrom::Start(offset))
+            .map_err(object_store_error)?;
+        let mut buf = Vec::with_capacity(len);
+        file.take(len as u64)
+            .read_to_end(&mut buf)
+            .map_err(object_store_error)?;
+        Ok(buf)
+    }
+
+    fn put(&self, key: &str, file_path: &Path) -> Result<()> {
+        let path = self.root.join(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(object_store_error)?;
+        }
+        // 先写入临时文件再重命名，读取时不会看到只上传了一部分的对象
+        let tmp_path = path.with_extension("uploading");
+        fs::copy(file_path, &tmp_path).map_err(object_store_error)?;
+        fs::rename(&tmp_path, &path).map_err(object_store_error)
+    }
+
+    fn size(&self, key: &str) -> Result<Option<u64>> {
+        match fs::metadata(self.root.join(key)) {
+            Ok(metadata) => Ok(Some(metadata.len())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(object_store_error(e)),
+        }
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        // prefix 中最后一个 / 之前的部分是目录
+        let (dir, name_prefix) = match prefix.rfind('/') {
+            Some(pos) => (&prefix[..=pos], &prefix[pos + 1..]),
+            None => ("", prefix),
+        };
+        let entries = match fs::read_dir(self.root.join(dir)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(object_store_error(e)),
+        };
+
+        let mut keys = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(object_store_error)?;
+            if let Some(name) = entry.file_name().to_str() {
+                if name.starts_with(name_prefix) && entry.path().is_file() {
+                    keys.push(format!("{}{}", dir, name));
+                }
+            }
+        }
+        keys.sort();
+        Ok(keys)
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        match fs::remove_file(self.root.join(key)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(object_store_error(e)),
+            _ => Ok(()),
+        }
+    }
+}
+
+// 按照固定大小的块缓存从对象存储中读取的数据，超过容量时淘汰最早缓存的块
+struct RangeCache {
+    chunks: HashMap<(u64, u64), Arc<Vec<u8>>>, // (文件 id, 块序号) -> 块的数据
+    order: VecDeque<(u64, u64)>,               // 缓存的顺序
+    size: usize,                               // 已经缓存的数据量
+    capacity: usize,                           // 最多缓存的数据量
+}
+
+impl RangeCache {
+    fn get(&self, key: &(u64, u64)) -> Option<Arc<Vec<u8>>> {
+        self.chunks.get(key).cloned()
+    }
+
+    fn insert(&mut self, key: (u64, u64), chunk: Arc<Vec<u8>>) {
+        if chunk.len() > self.capacity || self.chunks.contains_key(&key) {
+            return;
+        }
+        while self.size + chunk.len() > self.capacity {
+            match self.order.pop_front() {
+                Some(old) => {
+                    if let Some(old_chunk) = self.chunks.remove(&old) {
+                        self.size -= old_chunk.len();
+                    }
+                }
+                None => break,
+            }
+        }
+        self.size += chunk.len();
+        self.order.push_back(key);
+        self.chunks.insert(key, chunk);
+    }
+
+    // 调整缓存的容量，超出新容量的部分按照缓存的顺序淘汰
+    fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.size > self.capacity {
+            match self.order.pop_front() {
+                Some(old) => {
+                    if let Some(old_chunk) = self.chunks.remove(&old) {
+                        self.size -= old_chunk.len();
+                    }
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn remove_file(&mut self, file_id: u64) {
+        self.order.retain(|(fid, _)| *fid != file_id);
+        let chunks = &mut self.chunks;
+        let mut removed = 0;
+        chunks.retain(|(fid, _), chunk| {
+            if *fid == file_id {
+                removed += chunk.len();
+                return false;
+            }
+            true
+        });
+        self.size -= removed;
+    }
+}
+
+/// 数据目录使用的对象存储，以及已经上传到对象存储中的数据文件
+pub(crate) struct Archive {
+    options: ObjectStoreOptions,
+    cache: Mutex<RangeCache>,
+    archived: RwLock<BTreeSet<u64>>, // 已经上传的数据文件 id
+}
+
+impl Archive {
+    fn object_key(&self, file_id: u64) -> String {
+        format!(
+            "{}{:09}{}",
+            self.options.prefix, file_id, DATA_FILE_NAME_SUFFIX
+        )
+    }
+
+    /// 已经上传到对象存储中的数据文件 id，从小到大排列
+    pub(crate) fn archived_file_ids(&self) -> Vec<u64> {
+        self.archived.read().iter().copied().collect()
+    }
+
+    /// 上传之后是否保留本地的数据文件
+    pub(crate) fn keep_local_files(&self) -> bool {
+        self.options.keep_local_files
+    }
+
+    /// 读取缓存的容量
+    pub(crate) fn cache_size(&self) -> usize {
+        self.cache.lock().capacity
+    }
+
+    /// 运行时调整读取缓存的容量
+    pub(crate) fn set_cache_size(&self, cache_size: usize) {
+        self.cache.lock().set_capacity(cache_size);
+    }
+
+    pub(crate) fn is_archived(&self, file_id: u64) -> bool {
+        self.archived.read().contains(&file_id)
+    }
+
+    /// 上传数据文件
+    pub(crate) fn upload(&self, file_id: u64, file_path: &Path) -> Result<()> {
+        self.options
+            .store
+            .put(&self.object_key(file_id), file_path)?;
+        self.archived.write().insert(file_id);
+        Ok(())
+    }
+
+    /// 从对象存储中删除数据文件
+    pub(crate) fn remove(&self, file_id: u64) -> Result<()> {
+        if !self.is_archived(file_id) {
+            return Ok(());
+        }
+        self.options.store.delete(&self.object_key(file_id))?;
+        self.archived.write().remove(&file_id);
+        self.cache.lock().remove_file(file_id);
+        Ok(())
+    }
+
+    // 读取数据文件中的一个块，优先从缓存中读取
+    fn read_chunk(&self, file_id: u64, chunk_index: u64) -> Result<Arc<Vec<u8>>> {
+        if let Some(chunk) = self.cache.lock().get(&(file_id, chunk_index)) {
+            return Ok(chunk);
+        }
+        let chunk_size = self.options.cache_chunk_size;
+        let chunk = Arc::new(self.options.store.get_range(
+            &self.object_key(file_id),
+            chunk_index * chunk_size as u64,
+            chunk_size,
+        )?);
+        self.cache
+            .lock()
+            .insert((file_id, chunk_index), chunk.clone());
+        Ok(chunk)
+    }
+}
+
+/// 在数据目录上注册对象存储，列出已经上传的数据文件，返回值被释放时取消注册
+pub(crate) fn register(
+    dir_path: &Path,
+    options: ObjectStoreOptions,
+) -> Result<ArchiveRegistration> {
+    if options.cache_chunk_size == 0 {
+        return Err(Errors::InvaildObjectStoreOptions);
+    }
+
+    let mut archived = BTreeSet::new();
+    for key in options.store.list(&options.prefix)? {
+        let file_id = key[options.prefix.len()..]
+            .strip_suffix(DATA_FILE_NAME_SUFFIX)
+            .and_then(|id| id.parse::<u64>().ok());
+        if let Some(file_id) = file_id {
+            archived.insert(file_id);
+        }
+    }
+
+    let archive = Arc::new(Archive {
+        cache: Mutex::new(RangeCache {
+            chunks: HashMap::new(),
+            order: VecDeque::new(),
+            size: 0,
+            capacity: options.cache_size,
+        }),
+        options,
+        archived: RwLock::new(archived),
+    });
+    ARCHIVES
+        .lock()
+        .push((dir_path.to_path_buf(), archive.clone()));
+    Ok(ArchiveRegistration {
+        dir_path: dir_path.to_path_buf(),
+        archive,
+    })
+}
+
+/// 数据目录上注册的对象存储，释放时取消注册
+pub(crate) struct ArchiveRegistration {
+    dir_path: PathBuf,
+    archive: Arc<Archive>,
+}
+
+impl std::ops::Deref for ArchiveRegistration {
+    type Target = Archive;
+
+    fn deref(&self) -> &Archive {
+        &self.archive
+    }
+}
+
+impl Drop for ArchiveRegistration {
+    fn drop(&mut self) {
+        ARCHIVES.lock().retain(|(dir_path, archive)| {
+            !(dir_path == &self.dir_path && Arc::ptr_eq(archive, &self.archive))
+        });
+    }
+}
+
+/// 数据目录注册的对象存储
+pub(crate) fn find_archive(dir_path: &Path) -> Option<Arc<Archive>> {
+    ARCHIVES
+        .lock()
+        .iter()
+        .find(|(path, _)| path == dir_path)
+        .map(|(_, archive)| archive.clone())
+}
+
+// 解析出数据文件所在的目录和文件 id
+fn parse_data_file_name(file_name: &Path) -> Option<(&Path, u64)> {
+    let file_id = file_name
+        .file_name()?
+        .to_str()?
+        .strip_suffix(DATA_FILE_NAME_SUFFIX)?
+        .parse::<u64>()
+        .ok()?;
+    Some((file_name.parent()?, file_id))
+}
+
+/// 数据文件是否已经上传到对象存储中
+pub(crate) fn is_archived_file(file_name: &Path) -> bool {
+    match parse_data_file_name(file_name) {
+        Some((dir_path, file_id)) => {
+            find_archive(dir_path).is_some_and(|archive| archive.is_archived(file_id))
+        }
+        None => false,
+    }
+}
+
+/// 从对象存储中读取已经上传的数据文件，只读，按照块缓存读取到的数据
+pub struct ObjectStoreIO {
+    archive: Arc<Archive>,
+    file_id: u64,
+    size: u64,
+}
+
+impl ObjectStoreIO {
+    pub fn new(file_name: PathBuf) -> Result<Self> {
+        let (archive, file_id) = match parse_data_file_name(&file_name) {
+            Some((dir_path, file_id)) => match find_archive(dir_path) {
+                Some(archive) if archive.is_archived(file_id) => (archive, file_id),
+                _ => return Err(Errors::DataFileNotFound),
+            },
+            None => return Err(Errors::DataFileNotFound),
+        };
+        let size = match archive.options.store.size(&archive.object_key(file_id))? {
+            Some(size) => size,
+            None => return Err(Errors::DataFileNotFound),
+        };
+        Ok(Self {
+            archive,
+            file_id,
+            size,
+        })
+    }
+}
+
+impl IOManager for ObjectStoreIO {
+    fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        let chunk_size = self.archive.options.cache_chunk_size as u64;
+        let end = self.size.min(offset + buf.len() as u64);
+        let mut pos = offset;
+        while pos < end {
+            let chunk = self.archive.read_chunk(self.file_id, pos / chunk_size)?;
+            let start = (pos % chunk_size) as usize;
+            if start >= chunk.len() {
+                break;
+            }
+            let n = (chunk.len() - start).min((end - pos) as usize);
+            let buf_start = (pos - offset) as usize;
+            buf[buf_start..buf_start + n].copy_from_slice(&chunk[start..start + n]);
+            pos += n as u64;
+        }
+        Ok((pos - offset) as usize)
+    }
+
+    fn write(&self, _buf: &[u8]) -> Result<usize> {
+        Err(Errors::ArchivedDataFileIsReadOnly)
+    }
+
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fio::new_io_manager, options::IOType};
+
+    #[test]
+    fn test_object_store_io() {
+        let dir_path = PathBuf::from("/tmp/bitcask-rs-object-store-io");
+        let store_path = PathBuf::from("/tmp/bitcask-rs-object-store-io-store");
+        fs::create_dir_all(&dir_path).unwrap();
+        let file_path = dir_path.join("000000003.data");
+        let content: Vec<u8> = (0..100).collect();
+        fs::write(&file_path, &content).unwrap();
+
+        let store = Arc::new(LocalObjectStore::new(store_path.clone()).unwrap());
+        let mut options = ObjectStoreOptions::new(store.clone());
+        options.prefix = "db/".to_string();
+        options.cache_chunk_size = 16;
+        options.cache_size = 32;
+        let archive = register(&dir_path, options).unwrap();
+        assert!(!is_archived_file(&file_path));
+        assert!(archive.upload(3, &file_path).is_ok());
+        assert!(is_archived_file(&file_path));
+        assert_eq!(store.list("db/").unwrap(), vec!["db/000000003.data"]);
+
+        // 删除本地文件之后从对象存储中读取
+        fs::remove_file(&file_path).unwrap();
+        let io = ObjectStoreIO::new(file_path.clone()).unwrap();
+        assert_eq!(io.size(), 100);
+        let mut buf = [0u8; 40];
+        assert_eq!(io.read(&mut buf, 10).unwrap(), 40);
+        assert_eq!(&buf[..], &content[10..50]);
+        // 缓存不会超过容量
+        assert!(archive.cache.lock().size <= 32);
+        assert_eq!(io.read(&mut buf, 90).unwrap(), 10);
+        assert_eq!(&buf[..10], &content[90..]);
+        assert_eq!(io.read(&mut buf, 100).unwrap(), 0);
+        assert_eq!(
+            io.write(b"a").err().unwrap(),
+            Errors::ArchivedDataFileIsReadOnly
+        );
+
+        // 重新注册时加载已经上传的数据文件
+        std::mem::drop(archive);
+        assert!(!is_archived_file(&file_path));
+        let mut options = ObjectStoreOptions::new(store.clone());
+        options.prefix = "db/".to_string();
+        let archive = register(&dir_path, options).unwrap();
+        assert_eq!(archive.archived_file_ids(), vec![3]);
+        assert!(archive.remove(3).is_ok());
+        assert!(store.list("db/").unwrap().is_empty());
+        assert!(ObjectStoreIO::new(file_path.clone()).is_err());
+        // 对象存储中读取不到数据文件时返回错误，不会 panic
+        assert_eq!(
+            new_io_manager(file_path, IOType::ObjectStore)
+                .err()
+                .unwrap(),
+            Errors::DataFileNotFound
+        );
+
+        std::mem::drop(archive);
+        fs::remove_dir_all(dir_path).expect("failed to remove path");
+        fs::remove_dir_all(store_path).expect("failed to remove path");
+    }
+}
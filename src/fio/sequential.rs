@@ -0,0 +1,110 @@
+use crate::errors::Result;
+
+use super::IOManager;
+
+/// 顺序读取时预读的默认大小
+pub const DEFAULT_READ_AHEAD_SIZE: usize = 256 * 1024;
+
+/// 带有预读缓冲区的顺序读取器，用于启动时加载索引以及 merge 等从头到尾扫描文件的场景
+/// 每次缓冲区中没有需要的数据时从读取位置开始一次读取 capacity 字节，之后的读取直接从缓冲区中拷贝，
+/// 避免每条记录都产生一次（或两次）系统调用
+pub struct SequentialReader<'a> {
+    io_manager: &'a dyn IOManager,
+    buf: Vec<u8>,    // 预读的数据
+    buf_offset: u64, // 缓冲区中第一个字节在文件中的位置
+    capacity: usize, // 每次预读的大小
+}
+
+impl<'a> SequentialReader<'a> {
+    pub fn new(io_manager: &'a dyn IOManager, capacity: usize) -> Self {
+        Self {
+            io_manager,
+            buf: Vec::new(),
+            buf_offset: 0,
+            capacity,
+        }
+    }
+
+    /// 从文件给定位置读取数据，和 IOManager::read 的语义相同
+    pub fn read(&mut self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        // 比预读大小还要大的读取直接读取文件，不经过缓冲区
+        if buf.len() > self.capacity {
+            return self.io_manager.read(buf, offset);
+        }
+
+        if !self.contains(offset, buf.len()) {
+            self.fill(offset)?;
+            // 文件末尾的数据不够，按照 IOManager 的语义直接读取
+            if !self.contains(offset, buf.len()) {
+                return self.io_manager.read(buf, offset);
+            }
+        }
+
+        let start = (offset - self.buf_offset) as usize;
+        buf.copy_from_slice(&self.buf[start..start + buf.len()]);
+        Ok(buf.len())
+    }
+
+    // 缓冲区中是否包含 [offset, offset + len) 范围内的数据
+    fn contains(&self, offset: u64, len: usize) -> bool {
+        offset >= self.buf_offset
+            && offset + len as u64 <= self.buf_offset + self.buf.len() as u64
+    }
+
+    // 从 offset 开始预读，最多读取到文件末尾
+    fn fill(&mut self, offset: u64) -> Result<()> {
+        let len = (self.capacity as u64).min(self.io_manager.size().saturating_sub(offset));
+        self.buf.resize(len as usize, 0);
+        self.buf_offset = offset;
+        if len > 0 {
+            let n = self.io_manager.read(&mut self.buf, offset)?;
+            self.buf.truncate(n);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::fio::file_io::FileIO;
+
+    use super::*;
+
+    #[test]
+    fn test_sequential_reader() {
+        let path = PathBuf::from("/tmp/bitcask-rs-sequential-reader.data");
+        let fio = FileIO::new(path.clone()).unwrap();
+        let data: Vec<u8> = (0..100u8).collect();
+        assert!(fio.write(&data).is_ok());
+
+        let mut reader = SequentialReader::new(&fio, 16);
+
+        // 顺序读取，跨越预读的边界
+        let mut offset = 0;
+        while offset + 7 <= 100 {
+            let mut buf = [0u8; 7];
+            assert_eq!(reader.read(&mut buf, offset).unwrap(), 7);
+            assert_eq!(&buf, &data[offset as usize..offset as usize + 7]);
+            offset += 7;
+        }
+
+        // 向前读取以及比预读大小更大的读取
+        let mut buf = [0u8; 5];
+        assert_eq!(reader.read(&mut buf, 3).unwrap(), 5);
+        assert_eq!(&buf, &data[3..8]);
+        let mut buf = [0u8; 40];
+        assert_eq!(reader.read(&mut buf, 50).unwrap(), 40);
+        assert_eq!(&buf[..], &data[50..90]);
+
+        // 文件末尾不完整的读取和 IOManager 的语义相同
+        let mut buf = [0u8; 8];
+        assert_eq!(
+            reader.read(&mut buf, 96).unwrap(),
+            fio.read(&mut [0u8; 8], 96).unwrap()
+        );
+
+        std::fs::remove_file(path).expect("failed to remove file");
+    }
+}
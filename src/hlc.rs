@@ -0,0 +1,86 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::data::log_record::current_timestamp;
+
+// 低 16 位是同一毫秒内的逻辑计数
+const LOGICAL_BITS: u32 = 16;
+const LOGICAL_MASK: u64 = (1 << LOGICAL_BITS) - 1;
+
+/// 写入序列号中的物理时间部分，即这次写入的记录的写入时间，单位毫秒
+/// 需要获取某个序列号之后的所有写入时，可以从写入时间不早于这个时间的记录开始查找
+pub fn physical_time(seq: u64) -> u64 {
+    seq >> LOGICAL_BITS
+}
+
+// 混合逻辑时钟，为每次写入生成单调递增的序列号
+// 高 48 位是毫秒级的物理时间，低 16 位是逻辑计数，物理时间不变或者回退时只增加逻辑计数，
+// 因此序列号始终递增，并且和写入时间的大小关系一致
+pub(crate) struct HybridClock {
+    last: AtomicU64, // 最后一次生成的序列号
+}
+
+impl HybridClock {
+    pub(crate) fn new() -> Self {
+        Self {
+            last: AtomicU64::new(0),
+        }
+    }
+
+    // 生成一个比之前所有序列号都大的序列号
+    pub(crate) fn tick(&self) -> u64 {
+        let now = current_timestamp() << LOGICAL_BITS;
+        let mut last = self.last.load(Ordering::SeqCst);
+        loop {
+            let next = now.max(last + 1);
+            match self
+                .last
+                .compare_exchange(last, next, Ordering::SeqCst, Ordering::SeqCst)
+            {
+                Ok(_) => return next,
+                Err(current) => last = current,
+            }
+        }
+    }
+
+    // 最后一次生成的序列号
+    pub(crate) fn current(&self) -> u64 {
+        self.last.load(Ordering::SeqCst)
+    }
+
+    // 已经存在写入时间为 timestamp 的记录，之后生成的序列号的物理时间不早于这个时间
+    pub(crate) fn observe(&self, timestamp: u64) {
+        self.last
+            .fetch_max((timestamp << LOGICAL_BITS) | LOGICAL_MASK, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hybrid_clock_tick() {
+        let clock = HybridClock::new();
+        let mut last = clock.current();
+        for _ in 0..100000 {
+            let seq = clock.tick();
+            assert!(seq > last);
+            assert_eq!(clock.current(), seq);
+            last = seq;
+        }
+    }
+
+    #[test]
+    fn test_hybrid_clock_observe() {
+        // 已经存在的记录的写入时间比当前时间更晚时，之后的序列号仍然比这些记录大
+        let clock = HybridClock::new();
+        let future = current_timestamp() + 60 * 1000;
+        clock.observe(future);
+        let seq = clock.tick();
+        assert_eq!(physical_time(seq), future + 1);
+
+        // 观察到更早的写入时间不影响序列号
+        clock.observe(1);
+        assert!(clock.tick() > seq);
+    }
+}
@@ -1,4 +1,10 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
 
 use bytes::Bytes;
 use jammdb::DB;
@@ -10,11 +16,12 @@ use crate::{
 
 use super::{IndexIterator, Indexer};
 
-const BPTREE_INDEXER_FILE_NAME: &str = "bptree-index";
+pub(crate) const BPTREE_INDEXER_FILE_NAME: &str = "bptree-index";
 const BPTREE_BUCKET_NAME: &str = "bitcask-index";
 
 pub struct BPTree {
     tree: Arc<DB>,
+    len: AtomicUsize, // key 的数量，打开时统计一次，之后随着写入和删除更新
 }
 
 impl BPTree {
@@ -24,10 +31,17 @@ impl BPTree {
             DB::open(dir_path.join(BPTREE_INDEXER_FILE_NAME)).expect("failed to open bptree");
         let tree = Arc::new(bptree);
         let tx = tree.tx(true).expect("failed to begin tx");
-        tx.get_or_create_bucket(BPTREE_BUCKET_NAME).unwrap();
+        let len = tx
+            .get_or_create_bucket(BPTREE_BUCKET_NAME)
+            .unwrap()
+            .cursor()
+            .count();
         tx.commit().unwrap();
 
-        Self { tree: tree }
+        Self {
+            tree: tree,
+            len: AtomicUsize::new(len),
+        }
     }
 }
 
@@ -42,11 +56,17 @@ impl Indexer for BPTree {
         let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).unwrap();
 
         if let Some(kv) = bucket.get_kv(key.clone()) {
-            result = Some(decode_log_record_pos(kv.value().to_vec()));
+            result = Some(
+                decode_log_record_pos(kv.value().to_vec())
+                    .expect("failed to decode log record pos"),
+            );
         }
 
         bucket.put(key, pos.encode()).expect("failed to put value");
         tx.commit().unwrap();
+        if result.is_none() {
+            self.len.fetch_add(1, Ordering::SeqCst);
+        }
         result
     }
 
@@ -54,7 +74,10 @@ impl Indexer for BPTree {
         let tx = self.tree.tx(false).expect("failed to begin tx");
         let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).unwrap();
         if let Some(kv) = bucket.get_kv(key) {
-            return Some(decode_log_record_pos(kv.value().to_vec()));
+            return Some(
+                decode_log_record_pos(kv.value().to_vec())
+                    .expect("failed to decode log record pos"),
+            );
         }
         None
     }
@@ -64,10 +87,16 @@ impl Indexer for BPTree {
         let tx = self.tree.tx(true).expect("failed to begin tx");
         let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).unwrap();
         if let Ok(kv) = bucket.delete(key) {
-            result = Some(decode_log_record_pos(kv.value().to_vec()));
+            result = Some(
+                decode_log_record_pos(kv.value().to_vec())
+                    .expect("failed to decode log record pos"),
+            );
         };
 
         tx.commit().unwrap();
+        if result.is_some() {
+            self.len.fetch_sub(1, Ordering::SeqCst);
+        }
         result
     }
 
@@ -88,11 +117,22 @@ impl Indexer for BPTree {
         let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).unwrap();
 
         let mut items = Vec::new();
-        // 将 BTree 中的数据存储到数组中
+        // 将 BTree 中的数据存储到数组中，只保留上下界范围内的数据，超过上界之后不再继续遍历
         for data in bucket.cursor() {
+            if let Some(upper) = &options.upper_bound {
+                if data.key() >= upper.as_slice() {
+                    break;
+                }
+            }
+            if let Some(lower) = &options.lower_bound {
+                if data.key() < lower.as_slice() {
+                    continue;
+                }
+            }
             items.push((
                 data.key().to_vec(),
-                decode_log_record_pos(data.kv().value().to_vec()),
+                decode_log_record_pos(data.kv().value().to_vec())
+                    .expect("failed to decode log record pos"),
             ));
         }
 
@@ -112,6 +152,16 @@ impl Indexer for BPTree {
         tx.delete_bucket(BPTREE_BUCKET_NAME).unwrap();
         tx.get_or_create_bucket(BPTREE_BUCKET_NAME).unwrap();
         tx.commit().unwrap();
+        self.len.store(0, Ordering::SeqCst);
+    }
+
+    fn len(&self) -> usize {
+        self.len.load(Ordering::SeqCst)
+    }
+
+    // 数据存放在磁盘上的索引文件中，通过 mmap 按需加载，不常驻内存
+    fn memory_usage(&self) -> usize {
+        0
     }
 }
 
@@ -310,6 +360,36 @@ mod tests {
         std::fs::remove_dir_all(dir_path).expect("failed to remove path");
     }
 
+    #[test]
+    fn test_bptree_len() {
+        let dir_path = PathBuf::from("/tmp/bitcask-rs-bptree-len");
+        fs::create_dir_all(dir_path.clone()).unwrap();
+        let bpt = BPTree::new(dir_path.clone());
+        let pos = LogRecordPos {
+            file_id: 1,
+            offset: 10,
+            size: 11,
+        };
+        assert_eq!(bpt.len(), 0);
+
+        bpt.put("aa".as_bytes().to_vec(), pos);
+        bpt.put("bb".as_bytes().to_vec(), pos);
+        bpt.put("aa".as_bytes().to_vec(), pos);
+        bpt.delete("bb".as_bytes().to_vec());
+        bpt.delete("not_exist".as_bytes().to_vec());
+        assert_eq!(bpt.len(), 1);
+        assert_eq!(bpt.memory_usage(), 0);
+
+        // 重新打开时从索引文件中统计
+        std::mem::drop(bpt);
+        let bpt = BPTree::new(dir_path.clone());
+        assert_eq!(bpt.len(), 1);
+        bpt.clear();
+        assert_eq!(bpt.len(), 0);
+
+        std::fs::remove_dir_all(dir_path).expect("failed to remove path");
+    }
+
     #[test]
     fn test_bptree_iterator_seek() {
         let dir_path = PathBuf::from("/tmp/bitcask-rs-bptree-iter-seek");
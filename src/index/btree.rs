@@ -1,21 +1,83 @@
-use std::{collections::BTreeMap, sync::Arc};
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    mem::size_of,
+    ops::Bound,
+    sync::{
+        atomic::{AtomicUsize, Ordering as AtomicOrdering},
+        Arc,
+    },
+};
 
 use bytes::Bytes;
 use parking_lot::RwLock;
 
-use crate::{data::log_record::LogRecordPos, errors::Result, options::IteratorOptions};
+use crate::{
+    data::log_record::LogRecordPos,
+    errors::Result,
+    options::{IteratorOptions, KeyComparator},
+};
 
-use super::{IndexIterator, Indexer};
+use super::{IndexIterator, Indexer, RangeCursor};
 
 /// BTree索引，主要封装了标准库中的 BTreeMap
 pub struct BTree {
-    tree: Arc<RwLock<BTreeMap<Vec<u8>, LogRecordPos>>>,
+    tree: Arc<RwLock<BTreeMap<IndexKey, LogRecordPos>>>,
+    comparator: Option<KeyComparator>, // 自定义的 key 排序规则
+    key_bytes: AtomicUsize,            // 所有 key 的总长度，用于估算内存占用
 }
 
+// BTreeMap 中的 key，按照自定义的排序规则比较大小
+struct IndexKey {
+    key: Vec<u8>,
+    comparator: Option<KeyComparator>,
+}
+
+impl Ord for IndexKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match self.comparator {
+            Some(comparator) => comparator(&self.key, &other.key),
+            None => self.key.cmp(&other.key),
+        }
+    }
+}
+
+impl PartialOrd for IndexKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for IndexKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for IndexKey {}
+
 impl BTree {
     pub fn new() -> Self {
         Self {
             tree: Arc::new(RwLock::new(BTreeMap::new())),
+            comparator: None,
+            key_bytes: AtomicUsize::new(0),
+        }
+    }
+
+    /// 使用自定义的排序规则创建 BTree 索引
+    pub fn with_comparator(comparator: KeyComparator) -> Self {
+        Self {
+            tree: Arc::new(RwLock::new(BTreeMap::new())),
+            comparator: Some(comparator),
+            key_bytes: AtomicUsize::new(0),
+        }
+    }
+
+    fn index_key(&self, key: Vec<u8>) -> IndexKey {
+        IndexKey {
+            key,
+            comparator: self.comparator,
         }
     }
 }
@@ -23,35 +85,33 @@ impl BTree {
 impl Indexer for BTree {
     fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> Option<LogRecordPos> {
         let mut write_guard = self.tree.write();
-        write_guard.insert(key, pos)
+        let key_len = key.len();
+        let old = write_guard.insert(self.index_key(key), pos);
+        // key 已经存在时 BTreeMap 保留原来的 key
+        if old.is_none() {
+            self.key_bytes.fetch_add(key_len, AtomicOrdering::SeqCst);
+        }
+        old
     }
 
     fn get(&self, key: Vec<u8>) -> Option<LogRecordPos> {
         let read_guard = self.tree.read();
-        read_guard.get(&key).copied()
+        read_guard.get(&self.index_key(key)).copied()
     }
 
     fn delete(&self, key: Vec<u8>) -> Option<LogRecordPos> {
         let mut write_guard = self.tree.write();
-        write_guard.remove(&key)
+        let (index_key, pos) = write_guard.remove_entry(&self.index_key(key))?;
+        self.key_bytes
+            .fetch_sub(index_key.key.len(), AtomicOrdering::SeqCst);
+        Some(pos)
     }
 
     fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator> {
-        let read_guard = self.tree.read();
-        let mut items = Vec::with_capacity(read_guard.len());
-        // 将 BTree 中的数据存储到数组中
-        for (key, value) in read_guard.iter() {
-            items.push((key.clone(), value.clone()));
-        }
-
-        if options.reverse {
-            items.reverse();
-        }
-
         Box::new(BTreeIterator {
-            items,
-            curr_index: 0,
-            options,
+            tree: self.tree.clone(),
+            comparator: self.comparator,
+            cursor: RangeCursor::new(options, self.comparator),
         })
     }
 
@@ -59,7 +119,7 @@ impl Indexer for BTree {
         let read_guard = self.tree.read();
         let mut keys = Vec::with_capacity(read_guard.len());
         for (k, _) in read_guard.iter() {
-            keys.push(Bytes::copy_from_slice(&k));
+            keys.push(Bytes::copy_from_slice(&k.key));
         }
         Ok(keys)
     }
@@ -67,47 +127,48 @@ impl Indexer for BTree {
     fn clear(&self) {
         let mut write_guard = self.tree.write();
         write_guard.clear();
+        self.key_bytes.store(0, AtomicOrdering::SeqCst);
+    }
+
+    fn len(&self) -> usize {
+        self.tree.read().len()
+    }
+
+    fn memory_usage(&self) -> usize {
+        let entry_size = size_of::<IndexKey>() + size_of::<LogRecordPos>();
+        self.key_bytes.load(AtomicOrdering::SeqCst) + self.len() * entry_size
     }
 }
 
+/// BTree 索引迭代器，直接在 BTreeMap 上按照范围遍历
 pub struct BTreeIterator {
-    items: Vec<(Vec<u8>, LogRecordPos)>, // 存储 key+索引，根据 key 进行排序过的
-    curr_index: usize,                   // 当前遍历的下标
-    options: IteratorOptions,            // 配置项
+    tree: Arc<RwLock<BTreeMap<IndexKey, LogRecordPos>>>,
+    comparator: Option<KeyComparator>,
+    cursor: RangeCursor, // 当前遍历的位置
 }
 
 impl IndexIterator for BTreeIterator {
     fn rewind(&mut self) {
-        self.curr_index = 0;
+        self.cursor.rewind();
     }
 
     fn seek(&mut self, key: Vec<u8>) {
-        // 二分查找
-        self.curr_index = match self.items.binary_search_by(|(x, _)| {
-            if self.options.reverse {
-                x.cmp(&key).reverse()
-            } else {
-                x.cmp(&key)
-            }
-        }) {
-            Ok(equal_value) => equal_value,
-            Err(insert_val) => insert_val,
-        };
+        self.cursor.seek(key);
     }
 
     fn next(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)> {
-        if self.curr_index >= self.items.len() {
-            return None;
-        }
-
-        while let Some(item) = self.items.get(self.curr_index) {
-            self.curr_index += 1;
-            let prefix = &self.options.prefix;
-            if prefix.is_empty() || item.0.starts_with(&prefix) {
-                return Some((&item.0, &item.1));
+        let tree = &self.tree;
+        let comparator = self.comparator;
+        let to_index_key = |bound: Bound<Vec<u8>>| bound.map(|key| IndexKey { key, comparator });
+        self.cursor.next(|(start, end), reverse, limit| {
+            let read_guard = tree.read();
+            let range = (to_index_key(start), to_index_key(end));
+            let items = read_guard.range(range).map(|(k, v)| (k.key.clone(), *v));
+            match reverse {
+                true => items.rev().take(limit).collect(),
+                false => items.take(limit).collect(),
             }
-        }
-        None
+        })
     }
 }
 
@@ -246,6 +307,34 @@ mod tests {
         assert!(pos2.is_none());
     }
 
+    #[test]
+    fn test_btree_len() {
+        let index = BTree::new();
+        let pos = LogRecordPos {
+            file_id: 1,
+            offset: 10,
+            size: 11,
+        };
+        assert_eq!(index.len(), 0);
+        assert_eq!(index.memory_usage(), 0);
+
+        index.put("aa".as_bytes().to_vec(), pos);
+        index.put("bbb".as_bytes().to_vec(), pos);
+        index.put("aa".as_bytes().to_vec(), pos);
+        assert_eq!(index.len(), 2);
+        let entry_size = size_of::<IndexKey>() + size_of::<LogRecordPos>();
+        assert_eq!(index.memory_usage(), 5 + 2 * entry_size);
+
+        index.delete("aa".as_bytes().to_vec());
+        index.delete("not_exist".as_bytes().to_vec());
+        assert_eq!(index.len(), 1);
+        assert_eq!(index.memory_usage(), 3 + entry_size);
+
+        index.clear();
+        assert_eq!(index.len(), 0);
+        assert_eq!(index.memory_usage(), 0);
+    }
+
     #[test]
     fn test_btree_iterator_seek() {
         let bt = BTree::new();
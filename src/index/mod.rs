@@ -2,14 +2,14 @@ pub mod bptree;
 pub mod btree;
 pub mod skiplist;
 
-use std::path::PathBuf;
+use std::{cmp::Ordering, collections::VecDeque, ops::Bound, path::PathBuf};
 
 use bytes::Bytes;
 
 use crate::{
     data::log_record::LogRecordPos,
     errors::Result,
-    options::{IndexType, IteratorOptions},
+    options::{IndexType, IteratorOptions, KeyComparator},
 };
 
 /// 抽象索引接口，后续如果想要接入其他的数据结构，则直接实现这个接口即可
@@ -26,12 +26,23 @@ pub trait Indexer: Sync + Send {
     fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator>;
     /// 清空索引
     fn clear(&self);
+    /// 索引中 key 的数量
+    fn len(&self) -> usize;
+    /// 索引占用的内存大小（字节），是一个估算值，只计算 key、位置信息以及每条数据固定的开销
+    fn memory_usage(&self) -> usize;
 }
 
-/// 根据类型打开内存索引
-pub fn new_indexer(index_type: IndexType, dir_path: PathBuf) -> Box<dyn Indexer> {
+/// 根据类型打开内存索引，key_comparator 只对 BTree 索引生效
+pub fn new_indexer(
+    index_type: IndexType,
+    dir_path: PathBuf,
+    key_comparator: Option<KeyComparator>,
+) -> Box<dyn Indexer> {
     match index_type {
-        IndexType::BTree => Box::new(btree::BTree::new()),
+        IndexType::BTree => match key_comparator {
+            Some(comparator) => Box::new(btree::BTree::with_comparator(comparator)),
+            None => Box::new(btree::BTree::new()),
+        },
         IndexType::SkipList => Box::new(skiplist::SkipList::new()),
         IndexType::BPTree => Box::new(bptree::BPTree::new(dir_path)),
     }
@@ -48,3 +59,392 @@ pub trait IndexIterator: Sync + Send {
     /// 跳转到下一个 key，返回 None 说明遍历完成
     fn next(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)>;
 }
+
+// 流式迭代器每次从索引中取出的数据条数
+const RANGE_BATCH_SIZE: usize = 128;
+
+/// 索引中 key 的遍历范围
+pub(crate) type KeyRange = (Bound<Vec<u8>>, Bound<Vec<u8>>);
+
+/// 索引迭代器的遍历位置，根据 prefix 以及上下界计算出遍历的范围，每次从索引中按照范围取出一批数据，
+/// 不需要把整个索引拷贝出来，遍历期间的写入在还没有取出的范围内可以看到
+/// 使用自定义排序规则时前缀相同的 key 不一定相邻，需要遍历整个索引并过滤掉前缀不匹配的 key
+pub(crate) struct RangeCursor {
+    options: IteratorOptions,
+    comparator: Option<KeyComparator>, // 自定义的 key 排序规则
+    position: Bound<Vec<u8>>,          // 下一批数据的起始位置，反向遍历时为结束位置
+    items: VecDeque<(Vec<u8>, LogRecordPos)>, // 已经取出但还没有遍历的数据
+    curr: Option<(Vec<u8>, LogRecordPos)>, // 当前遍历到的数据
+}
+
+impl RangeCursor {
+    pub(crate) fn new(options: IteratorOptions, comparator: Option<KeyComparator>) -> Self {
+        Self {
+            options,
+            comparator,
+            position: Bound::Unbounded,
+            items: VecDeque::new(),
+            curr: None,
+        }
+    }
+
+    pub(crate) fn rewind(&mut self) {
+        self.position = Bound::Unbounded;
+        self.items.clear();
+    }
+
+    pub(crate) fn seek(&mut self, key: Vec<u8>) {
+        self.position = Bound::Included(key);
+        self.items.clear();
+    }
+
+    /// 跳转到下一条数据，fetch 从索引中取出范围内最多 limit 条数据，reverse 为 true 时从大到小取出
+    pub(crate) fn next<F>(&mut self, mut fetch: F) -> Option<(&Vec<u8>, &LogRecordPos)>
+    where
+        F: FnMut(KeyRange, bool, usize) -> Vec<(Vec<u8>, LogRecordPos)>,
+    {
+        self.curr = None;
+        loop {
+            while let Some(item) = self.items.pop_front() {
+                if item.0.starts_with(&self.options.prefix) {
+                    self.curr = Some(item);
+                    return self.curr.as_ref().map(|(key, pos)| (key, pos));
+                }
+            }
+
+            let range = self.range()?;
+            self.items = fetch(range, self.options.reverse, RANGE_BATCH_SIZE).into();
+            match self.items.back() {
+                Some((key, _)) => self.position = Bound::Excluded(key.clone()),
+                None => return None,
+            }
+        }
+    }
+
+    // 当前需要遍历的范围，为空时返回 None
+    fn range(&self) -> Option<KeyRange> {
+        let prefix = &self.options.prefix;
+        let (mut lower, mut upper) = match self.comparator {
+            Some(_) => (Bound::Unbounded, Bound::Unbounded),
+            None => (
+                match prefix.is_empty() {
+                    true => Bound::Unbounded,
+                    false => Bound::Included(prefix.clone()),
+                },
+                match prefix_upper_bound(prefix) {
+                    Some(end) => Bound::Excluded(end),
+                    None => Bound::Unbounded,
+                },
+            ),
+        };
+
+        // 和 lower_bound/upper_bound 取交集，超出上界之后不会再从索引中取数据
+        if let Some(lower_bound) = &self.options.lower_bound {
+            let bound = Bound::Included(lower_bound.clone());
+            if !self.lower_after(&lower, &bound) {
+                lower = bound;
+            }
+        }
+        if let Some(upper_bound) = &self.options.upper_bound {
+            let bound = Bound::Excluded(upper_bound.clone());
+            if !self.upper_before(&upper, &bound) {
+                upper = bound;
+            }
+        }
+
+        // 已经遍历过的位置
+        if self.options.reverse {
+            if !self.upper_before(&upper, &self.position) {
+                upper = self.position.clone();
+            }
+        } else if !self.lower_after(&lower, &self.position) {
+            lower = self.position.clone();
+        }
+
+        match (&lower, &upper) {
+            (Bound::Included(start), Bound::Included(end))
+            | (Bound::Included(start), Bound::Excluded(end))
+            | (Bound::Excluded(start), Bound::Included(end))
+                if self.compare(start, end) == Ordering::Greater =>
+            {
+                None
+            }
+            (Bound::Excluded(start), Bound::Excluded(end))
+                if self.compare(start, end) != Ordering::Less =>
+            {
+                None
+            }
+            _ => Some((lower, upper)),
+        }
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        match self.comparator {
+            Some(comparator) => comparator(a, b),
+            None => a.cmp(b),
+        }
+    }
+
+    // 下界 a 是否比下界 b 更严格（或者相同）
+    fn lower_after(&self, a: &Bound<Vec<u8>>, b: &Bound<Vec<u8>>) -> bool {
+        match (a, b) {
+            (_, Bound::Unbounded) => true,
+            (Bound::Unbounded, _) => false,
+            (Bound::Included(x), Bound::Excluded(y)) => self.compare(x, y) == Ordering::Greater,
+            (Bound::Included(x), Bound::Included(y))
+            | (Bound::Excluded(x), Bound::Excluded(y))
+            | (Bound::Excluded(x), Bound::Included(y)) => self.compare(x, y) != Ordering::Less,
+        }
+    }
+
+    // 上界 a 是否比上界 b 更严格（或者相同）
+    fn upper_before(&self, a: &Bound<Vec<u8>>, b: &Bound<Vec<u8>>) -> bool {
+        match (a, b) {
+            (_, Bound::Unbounded) => true,
+            (Bound::Unbounded, _) => false,
+            (Bound::Included(x), Bound::Excluded(y)) => self.compare(x, y) == Ordering::Less,
+            (Bound::Included(x), Bound::Included(y))
+            | (Bound::Excluded(x), Bound::Excluded(y))
+            | (Bound::Excluded(x), Bound::Included(y)) => self.compare(x, y) != Ordering::Greater,
+        }
+    }
+}
+
+// 前缀为 prefix 的所有 key 都小于返回的 key，prefix 为空或者全部是 0xff 时没有上界
+fn prefix_upper_bound(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pos(file_id: u64, offset: u64) -> LogRecordPos {
+        LogRecordPos {
+            file_id,
+            offset,
+            size: 11,
+        }
+    }
+
+    // 所有的索引类型共用的测试，put 和 delete 都需要返回之前的位置信息
+    fn check_indexer(indexer: Box<dyn Indexer>) {
+        assert!(indexer.put(b"aa".to_vec(), pos(1, 10)).is_none());
+        assert!(indexer.put(b"bb".to_vec(), pos(1, 20)).is_none());
+        assert_eq!(indexer.put(b"aa".to_vec(), pos(2, 30)), Some(pos(1, 10)));
+        assert_eq!(indexer.get(b"aa".to_vec()), Some(pos(2, 30)));
+        assert!(indexer.get(b"cc".to_vec()).is_none());
+
+        assert_eq!(
+            indexer.list_keys().unwrap(),
+            vec![Bytes::from("aa"), Bytes::from("bb")]
+        );
+        let mut iter = indexer.iterator(IteratorOptions {
+            prefix: Default::default(),
+            reverse: true,
+            ..Default::default()
+        });
+        assert_eq!(iter.next(), Some((&b"bb".to_vec(), &pos(1, 20))));
+        assert_eq!(iter.next(), Some((&b"aa".to_vec(), &pos(2, 30))));
+        assert!(iter.next().is_none());
+
+        assert_eq!(indexer.delete(b"bb".to_vec()), Some(pos(1, 20)));
+        assert!(indexer.delete(b"bb".to_vec()).is_none());
+        assert!(indexer.get(b"bb".to_vec()).is_none());
+
+        indexer.clear();
+        assert!(indexer.get(b"aa".to_vec()).is_none());
+        assert!(indexer.list_keys().unwrap().is_empty());
+        assert!(indexer.put(b"aa".to_vec(), pos(3, 40)).is_none());
+    }
+
+    fn collect_keys(iter: &mut Box<dyn IndexIterator>) -> Vec<Vec<u8>> {
+        let mut keys = Vec::new();
+        while let Some((key, _)) = iter.next() {
+            keys.push(key.clone());
+        }
+        keys
+    }
+
+    // 流式遍历超过一批的数据，校验前缀范围、seek 和遍历期间的写入
+    fn check_streaming_iterator(indexer: Box<dyn Indexer>) {
+        for i in 0..1000 {
+            let key = format!("key-{:04}", i).into_bytes();
+            assert!(indexer.put(key, pos(1, i)).is_none());
+        }
+        assert!(indexer.put(vec![0xff, 0xff], pos(2, 0)).is_none());
+        assert!(indexer.put(vec![0xff, 0xff, 0x01], pos(2, 1)).is_none());
+
+        let mut iter = indexer.iterator(IteratorOptions::default());
+        assert_eq!(collect_keys(&mut iter).len(), 1002);
+
+        let mut iter = indexer.iterator(IteratorOptions {
+            prefix: b"key-05".to_vec(),
+            reverse: false,
+            ..Default::default()
+        });
+        let keys = collect_keys(&mut iter);
+        assert_eq!(keys.len(), 100);
+        assert_eq!(keys[0], b"key-0500".to_vec());
+        assert_eq!(keys[99], b"key-0599".to_vec());
+
+        let mut iter = indexer.iterator(IteratorOptions {
+            prefix: b"key-0".to_vec(),
+            reverse: true,
+            ..Default::default()
+        });
+        let keys = collect_keys(&mut iter);
+        assert_eq!(keys.len(), 1000);
+        assert_eq!(keys[0], b"key-0999".to_vec());
+        assert_eq!(keys[999], b"key-0000".to_vec());
+
+        // 前缀全部是 0xff 时没有上界
+        let mut iter = indexer.iterator(IteratorOptions {
+            prefix: vec![0xff, 0xff],
+            reverse: false,
+            ..Default::default()
+        });
+        assert_eq!(collect_keys(&mut iter).len(), 2);
+
+        // seek 之后从目标位置开始，超出前缀范围时遍历结束
+        let mut iter = indexer.iterator(IteratorOptions {
+            prefix: b"key-05".to_vec(),
+            reverse: true,
+            ..Default::default()
+        });
+        iter.seek(b"key-0550".to_vec());
+        assert_eq!(iter.next().unwrap().0, &b"key-0550".to_vec());
+        iter.seek(b"key-09".to_vec());
+        assert_eq!(iter.next().unwrap().0, &b"key-0599".to_vec());
+        iter.seek(b"key-04".to_vec());
+        assert!(iter.next().is_none());
+        iter.rewind();
+        assert_eq!(iter.next().unwrap().0, &b"key-0599".to_vec());
+
+        // 遍历期间的写入，在还没有取出的范围内可以看到
+        let mut iter = indexer.iterator(IteratorOptions::default());
+        assert_eq!(iter.next().unwrap().0, &b"key-0000".to_vec());
+        assert!(indexer.put(b"key-0999a".to_vec(), pos(3, 0)).is_none());
+        assert!(indexer.delete(b"key-0500".to_vec()).is_some());
+        let keys = collect_keys(&mut iter);
+        assert_eq!(keys.len(), 1001);
+        assert_eq!(keys[0], b"key-0001".to_vec());
+        assert!(keys.contains(&b"key-0999a".to_vec()));
+        assert!(!keys.contains(&b"key-0500".to_vec()));
+    }
+
+    // 上下界范围内的遍历，和前缀以及 seek 组合使用
+    fn check_bounded_iterator(indexer: Box<dyn Indexer>) {
+        for i in 0..1000 {
+            let key = format!("key-{:04}", i).into_bytes();
+            assert!(indexer.put(key, pos(1, i)).is_none());
+        }
+
+        let mut iter = indexer.iterator(IteratorOptions {
+            lower_bound: Some(b"key-0100".to_vec()),
+            upper_bound: Some(b"key-0300".to_vec()),
+            ..Default::default()
+        });
+        let keys = collect_keys(&mut iter);
+        assert_eq!(keys.len(), 200);
+        assert_eq!(keys[0], b"key-0100".to_vec());
+        assert_eq!(keys[199], b"key-0299".to_vec());
+
+        let mut iter = indexer.iterator(IteratorOptions {
+            reverse: true,
+            lower_bound: Some(b"key-0100".to_vec()),
+            upper_bound: Some(b"key-0300".to_vec()),
+            ..Default::default()
+        });
+        let keys = collect_keys(&mut iter);
+        assert_eq!(keys.len(), 200);
+        assert_eq!(keys[0], b"key-0299".to_vec());
+        assert_eq!(keys[199], b"key-0100".to_vec());
+
+        // 和前缀取交集
+        let mut iter = indexer.iterator(IteratorOptions {
+            prefix: b"key-02".to_vec(),
+            reverse: false,
+            lower_bound: Some(b"key-0150".to_vec()),
+            upper_bound: Some(b"key-0210".to_vec()),
+        });
+        let keys = collect_keys(&mut iter);
+        assert_eq!(keys.len(), 10);
+        assert_eq!(keys[0], b"key-0200".to_vec());
+
+        // seek 到下界之前从下界开始，seek 到上界之后遍历结束
+        let mut iter = indexer.iterator(IteratorOptions {
+            lower_bound: Some(b"key-0100".to_vec()),
+            upper_bound: Some(b"key-0300".to_vec()),
+            ..Default::default()
+        });
+        iter.seek(b"key-0000".to_vec());
+        assert_eq!(iter.next().unwrap().0, &b"key-0100".to_vec());
+        iter.seek(b"key-0299".to_vec());
+        assert_eq!(iter.next().unwrap().0, &b"key-0299".to_vec());
+        assert!(iter.next().is_none());
+        iter.seek(b"key-0500".to_vec());
+        assert!(iter.next().is_none());
+
+        // 下界不小于上界时没有数据
+        let mut iter = indexer.iterator(IteratorOptions {
+            lower_bound: Some(b"key-0300".to_vec()),
+            upper_bound: Some(b"key-0300".to_vec()),
+            ..Default::default()
+        });
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn test_bounded_iterator_btree() {
+        check_bounded_iterator(new_indexer(IndexType::BTree, PathBuf::new(), None));
+    }
+
+    #[test]
+    fn test_bounded_iterator_skiplist() {
+        check_bounded_iterator(new_indexer(IndexType::SkipList, PathBuf::new(), None));
+    }
+
+    #[test]
+    fn test_bounded_iterator_bptree() {
+        let dir_path = PathBuf::from("/tmp/bitcask-rs-bounded-iterator-bptree");
+        std::fs::create_dir_all(dir_path.clone()).expect("failed to create path");
+        check_bounded_iterator(new_indexer(IndexType::BPTree, dir_path.clone(), None));
+        std::fs::remove_dir_all(dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_streaming_iterator_btree() {
+        check_streaming_iterator(new_indexer(IndexType::BTree, PathBuf::new(), None));
+    }
+
+    #[test]
+    fn test_streaming_iterator_skiplist() {
+        check_streaming_iterator(new_indexer(IndexType::SkipList, PathBuf::new(), None));
+    }
+
+    #[test]
+    fn test_indexer_btree() {
+        check_indexer(new_indexer(IndexType::BTree, PathBuf::new(), None));
+    }
+
+    #[test]
+    fn test_indexer_skiplist() {
+        check_indexer(new_indexer(IndexType::SkipList, PathBuf::new(), None));
+    }
+
+    #[test]
+    fn test_indexer_bptree() {
+        let dir_path = PathBuf::from("/tmp/bitcask-rs-indexer-bptree");
+        std::fs::create_dir_all(dir_path.clone()).expect("failed to create path");
+        check_indexer(new_indexer(IndexType::BPTree, dir_path.clone(), None));
+        std::fs::remove_dir_all(dir_path).expect("failed to remove path");
+    }
+}
@@ -1,20 +1,28 @@
-use std::sync::Arc;
+use std::{
+    mem::size_of,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
 
 use bytes::Bytes;
 use crossbeam_skiplist::SkipMap;
 
 use crate::{data::log_record::LogRecordPos, options::IteratorOptions};
 
-use super::{IndexIterator, Indexer};
+use super::{IndexIterator, Indexer, RangeCursor};
 
 pub struct SkipList {
     skl: Arc<SkipMap<Vec<u8>, LogRecordPos>>,
+    key_bytes: AtomicUsize, // 所有 key 的总长度，用于估算内存占用
 }
 
 impl SkipList {
     pub fn new() -> Self {
         Self {
             skl: Arc::new(SkipMap::new()),
+            key_bytes: AtomicUsize::new(0),
         }
     }
 }
@@ -24,6 +32,8 @@ impl Indexer for SkipList {
         let mut result = None;
         if let Some(entry) = self.skl.get(&key) {
             result = Some(*entry.value());
+        } else {
+            self.key_bytes.fetch_add(key.len(), Ordering::SeqCst);
         }
         self.skl.insert(key, pos);
         result
@@ -38,6 +48,7 @@ impl Indexer for SkipList {
 
     fn delete(&self, key: Vec<u8>) -> Option<LogRecordPos> {
         if let Some(remove_res) = self.skl.remove(&key) {
+            self.key_bytes.fetch_sub(key.len(), Ordering::SeqCst);
             return Some(*remove_res.value());
         }
         None
@@ -51,67 +62,52 @@ impl Indexer for SkipList {
         Ok(keys)
     }
 
-    fn iterator(&self, options: crate::options::IteratorOptions) -> Box<dyn super::IndexIterator> {
-        let mut items = Vec::with_capacity(self.skl.len());
-        // 将 SkipList 中的数据存储到数组中
-        for e in self.skl.iter() {
-            items.push((e.key().clone(), *e.value()));
-        }
-
-        if options.reverse {
-            items.reverse();
-        }
-
+    fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator> {
         Box::new(SkipListIterator {
-            items,
-            curr_index: 0,
-            options,
+            skl: self.skl.clone(),
+            cursor: RangeCursor::new(options, None),
         })
     }
 
     fn clear(&self) {
         self.skl.clear();
+        self.key_bytes.store(0, Ordering::SeqCst);
+    }
+
+    fn len(&self) -> usize {
+        self.skl.len()
+    }
+
+    fn memory_usage(&self) -> usize {
+        let entry_size = size_of::<Vec<u8>>() + size_of::<LogRecordPos>();
+        self.key_bytes.load(Ordering::SeqCst) + self.len() * entry_size
     }
 }
 
+/// 跳表索引迭代器，直接在跳表上按照范围遍历
 pub struct SkipListIterator {
-    items: Vec<(Vec<u8>, LogRecordPos)>, // 存储 key+索引，根据 key 进行排序过的
-    curr_index: usize,                   // 当前遍历的下标
-    options: IteratorOptions,            // 配置项
+    skl: Arc<SkipMap<Vec<u8>, LogRecordPos>>,
+    cursor: RangeCursor, // 当前遍历的位置
 }
 
 impl IndexIterator for SkipListIterator {
     fn rewind(&mut self) {
-        self.curr_index = 0;
+        self.cursor.rewind();
     }
 
     fn seek(&mut self, key: Vec<u8>) {
-        // 二分查找
-        self.curr_index = match self.items.binary_search_by(|(x, _)| {
-            if self.options.reverse {
-                x.cmp(&key).reverse()
-            } else {
-                x.cmp(&key)
-            }
-        }) {
-            Ok(equal_value) => equal_value,
-            Err(insert_val) => insert_val,
-        };
+        self.cursor.seek(key);
     }
 
     fn next(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)> {
-        if self.curr_index >= self.items.len() {
-            return None;
-        }
-
-        while let Some(item) = self.items.get(self.curr_index) {
-            self.curr_index += 1;
-            let prefix = &self.options.prefix;
-            if prefix.is_empty() || item.0.starts_with(&prefix) {
-                return Some((&item.0, &item.1));
+        let skl = &self.skl;
+        self.cursor.next(|range, reverse, limit| {
+            let items = skl.range(range).map(|e| (e.key().clone(), *e.value()));
+            match reverse {
+                true => items.rev().take(limit).collect(),
+                false => items.take(limit).collect(),
             }
-        }
-        None
+        })
     }
 }
 
@@ -250,6 +246,34 @@ mod tests {
         assert!(pos2.is_none());
     }
 
+    #[test]
+    fn test_skiplist_len() {
+        let index = SkipList::new();
+        let pos = LogRecordPos {
+            file_id: 1,
+            offset: 10,
+            size: 11,
+        };
+        assert_eq!(index.len(), 0);
+        assert_eq!(index.memory_usage(), 0);
+
+        index.put("aa".as_bytes().to_vec(), pos);
+        index.put("bbb".as_bytes().to_vec(), pos);
+        index.put("aa".as_bytes().to_vec(), pos);
+        assert_eq!(index.len(), 2);
+        let entry_size = size_of::<Vec<u8>>() + size_of::<LogRecordPos>();
+        assert_eq!(index.memory_usage(), 5 + 2 * entry_size);
+
+        index.delete("aa".as_bytes().to_vec());
+        index.delete("not_exist".as_bytes().to_vec());
+        assert_eq!(index.len(), 1);
+        assert_eq!(index.memory_usage(), 3 + entry_size);
+
+        index.clear();
+        assert_eq!(index.len(), 0);
+        assert_eq!(index.memory_usage(), 0);
+    }
+
     #[test]
     fn test_skiplist_iterator_seek() {
         let sk = SkipList::new();
@@ -0,0 +1,606 @@
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use bytes::Bytes;
+use log::error;
+
+use crate::{
+    batch::{log_record_key_with_seq, parse_log_record_key, NON_TRANSACTION_SEQ_NO},
+    data::{
+        data_file::{get_data_file_name, DataFile},
+        log_record::{
+            decode_log_record_pos, LogRecord, LogRecordPos, LogRecordType, TransactionRecord,
+        },
+    },
+    db::{preallocate_if_enabled, Engine},
+    errors::{Errors, Result},
+    merge::MergeWriter,
+    options::IOType,
+    util,
+};
+
+const INGEST_DIR_NAME: &str = "ingest";
+
+impl Engine {
+    /// 批量导入数据，key 必须按照索引的顺序严格递增，返回导入的数据条数
+    /// 数据先写入到临时目录中新的数据文件和 hint 文件，不经过活跃文件，也不会逐条 sync，
+    /// 写完之后将这些数据文件作为旧的数据文件安装到当前活跃文件之后，并根据 hint 文件批量更新索引
+    /// 导入的数据会覆盖已经存在的 key，不会通知 watch 的订阅者
+    /// 安装期间会阻塞其他的写入；安装过程中崩溃的话，重启之后可能只有部分数据文件被导入
+    pub fn ingest<I>(&self, iter: I) -> Result<usize>
+    where
+        I: Iterator<Item = (Bytes, Bytes)>,
+    {
+        self.try_ingest(iter.map(Ok))
+    }
+
+    // 和 ingest 相同，数据来源读取出错时停止导入并返回错误，已经写入临时目录的数据不会被安装
+    pub(crate) fn try_ingest<I>(&self, iter: I) -> Result<usize>
+    where
+        I: Iterator<Item = Result<(Bytes, Bytes)>>,
+    {
+        self.check_closed()?;
+        if self.options.read_only {
+            return Err(Errors::DatabaseIsReadOnly);
+        }
+
+        // 和 merge 互斥，避免数据文件在导入的过程中被替换
+        let lock = self.merging_lock.try_lock();
+        if lock.is_none() {
+            return Err(Errors::MergeInProgress);
+        }
+
+        let ingest_path = get_ingest_path(self.options.dir_path.clone());
+        if ingest_path.is_dir() {
+            fs::remove_dir_all(&ingest_path).map_err(ingest_error)?;
+        }
+        if let Err(e) = fs::create_dir_all(&ingest_path) {
+            error!("failed to create ingest path {}", e);
+            return Err(Errors::FailedToCreateDatabaseDir);
+        }
+
+        let res = self
+            .write_ingest_files(&ingest_path, iter)
+            .and_then(|(count, last_file_id)| {
+                if count > 0 {
+                    let sources: Vec<PathBuf> = (0..=last_file_id)
+                        .map(|file_id| get_data_file_name(ingest_path.clone(), file_id))
+                        .collect();
+                    self.install_data_files(&sources, |base_file_id| {
+                        self.load_ingest_hint_file(&ingest_path, base_file_id)
+                    })?;
+                }
+                Ok(count)
+            });
+        if let Err(e) = fs::remove_dir_all(ingest_path) {
+            error!("failed to remove ingest dir: {}", e);
+        }
+        res
+    }
+
+    // 将数据写入到临时目录中，返回数据条数以及最后一个数据文件的 id
+    fn write_ingest_files<I>(&self, ingest_path: &Path, iter: I) -> Result<(usize, u64)>
+    where
+        I: Iterator<Item = Result<(Bytes, Bytes)>>,
+    {
+        let mut writer = MergeWriter::new(
+            ingest_path.to_path_buf(),
+            &self.options,
+            self.cipher.clone(),
+        )?;
+        let hint_file = DataFile::new_hint_file(ingest_path.to_path_buf(), self.cipher.clone())?;
+
+        // 按照索引中 key 的顺序检查是否严格递增
+        let compare = self
+            .options
+            .key_comparator
+            .unwrap_or(|a: &[u8], b: &[u8]| a.cmp(b));
+        let mut count = 0;
+        let mut prev_key: Option<Bytes> = None;
+        for item in iter {
+            let (key, value) = item?;
+            if key.is_empty() {
+                return Err(Errors::KeyIsEmpty);
+            }
+            if let Some(prev_key) = prev_key.as_ref() {
+                if compare(prev_key, &key) != Ordering::Less {
+                    return Err(Errors::IngestKeysNotSorted);
+                }
+            }
+
+            let mut record = LogRecord {
+                key: log_record_key_with_seq(&key, NON_TRANSACTION_SEQ_NO),
+                value: value.to_vec(),
+                rec_type: LogRecordType::NORMAL,
+                timestamp: 0,
+                value_pointer: false,
+            };
+            let pos = writer.append(&mut record)?;
+            hint_file.write_hint_record(key.to_vec(), pos)?;
+            count += 1;
+            prev_key = Some(key);
+        }
+
+        writer.sync()?;
+        hint_file.sync()?;
+        Ok((count, writer.file_id()))
+    }
+
+    /// 导入外部生成的数据文件，按照 paths 的顺序校验文件的格式和每条记录的 CRC，
+    /// 全部校验通过之后重命名到数据目录中，作为当前活跃文件之后的旧数据文件，并更新索引
+    /// 文件中的数据比已经存在的数据更新，删除记录会删除已经存在的 key，未完成的事务中的数据会被忽略
+    /// 文件中不能有指向 blob 文件的 value，开启加密时文件需要使用相同的密钥加密或者不加密
+    pub fn ingest_external_files(&self, paths: &[PathBuf]) -> Result<()> {
+        self.check_closed()?;
+        if self.options.read_only {
+            return Err(Errors::DatabaseIsReadOnly);
+        }
+        if paths.is_empty() {
+            return Ok(());
+        }
+
+        let lock = self.merging_lock.try_lock();
+        if lock.is_none() {
+            return Err(Errors::MergeInProgress);
+        }
+
+        // 位置中的文件 id 先使用文件在 paths 中的下标，安装之后再加上实际的文件 id
+        let mut positions = HashMap::new();
+        let mut reclaim_positions = Vec::new();
+        for (index, path) in paths.iter().enumerate() {
+            self.scan_external_file(path, index as u64, &mut positions, &mut reclaim_positions)?;
+        }
+
+        self.install_data_files(paths, |base_file_id| {
+            for (key, pos) in positions {
+                match pos {
+                    Some(mut pos) => {
+                        pos.file_id += base_file_id;
+                        self.index_put(key, pos);
+                    }
+                    None => self.index_delete(key),
+                }
+            }
+            for mut pos in reclaim_positions {
+                pos.file_id += base_file_id;
+                self.add_reclaim_size(&pos);
+            }
+            Ok(())
+        })
+    }
+
+    // 校验外部数据文件中的每条记录，得到每个 key 最新的位置，被删除的 key 位置为空，
+    // 失效的记录以及删除记录本身的位置放到 reclaim_positions 中
+    fn scan_external_file(
+        &self,
+        path: &Path,
+        file_id: u64,
+        positions: &mut HashMap<Vec<u8>, Option<LogRecordPos>>,
+        reclaim_positions: &mut Vec<LogRecordPos>,
+    ) -> Result<()> {
+        let invalid = |offset| Errors::InvaildExternalFile {
+            path: path.display().to_string(),
+            offset,
+        };
+        if !path.is_file() {
+            return Err(invalid(0));
+        }
+        let data_file = DataFile::open_external_file(path.to_path_buf(), self.cipher.clone())
+            .map_err(|e| match e {
+                Errors::EncryptionKeyRequired => e,
+                _ => invalid(0),
+            })?;
+        // 外部文件必须带有当前格式的头部
+        if data_file.get_header_size() == 0 {
+            return Err(invalid(0));
+        }
+
+        let mut transaction_records: HashMap<u64, Vec<TransactionRecord>> = HashMap::new();
+        let mut offset = data_file.get_header_size();
+        loop {
+            let (mut log_record, size) = match data_file.read_log_record(offset) {
+                Ok(result) => (result.record, result.size),
+                Err(Errors::ReadDataFileEof) => break,
+                Err(_) => return Err(invalid(offset)),
+            };
+            if log_record.value_pointer {
+                return Err(invalid(offset));
+            }
+
+            let pos = LogRecordPos {
+                file_id,
+                offset,
+                size: size as u64,
+            };
+            let (real_key, seq_no) =
+                parse_log_record_key(log_record.key.clone()).map_err(|_| invalid(offset))?;
+            if seq_no == NON_TRANSACTION_SEQ_NO {
+                update_external_position(
+                    positions,
+                    reclaim_positions,
+                    real_key,
+                    log_record.rec_type,
+                    pos,
+                );
+            } else if log_record.rec_type == LogRecordType::TxnFinished {
+                reclaim_positions.push(pos);
+                for txn_record in transaction_records.remove(&seq_no).unwrap_or_default() {
+                    update_external_position(
+                        positions,
+                        reclaim_positions,
+                        txn_record.record.key,
+                        txn_record.record.rec_type,
+                        txn_record.pos,
+                    );
+                }
+            } else {
+                log_record.key = real_key;
+                transaction_records
+                    .entry(seq_no)
+                    .or_default()
+                    .push(TransactionRecord {
+                        record: log_record,
+                        pos,
+                    });
+            }
+            offset += size as u64;
+        }
+
+        // 读到结束标识之后不能还有剩余的数据
+        if offset != data_file.file_size() {
+            return Err(invalid(offset));
+        }
+        // 没有提交的事务中的数据同样可以回收
+        for records in transaction_records.into_values() {
+            reclaim_positions.extend(records.into_iter().map(|txn_record| txn_record.pos));
+        }
+        Ok(())
+    }
+
+    // 将 sources 中的数据文件依次重命名到当前活跃文件之后，并切换到新的活跃文件，
+    // 然后调用 update_index 更新索引，参数为第一个数据文件安装之后的文件 id
+    // 期间持有 relocate_lock 写锁，避免覆盖并发写入的新数据
+    fn install_data_files<F>(&self, sources: &[PathBuf], update_index: F) -> Result<()>
+    where
+        F: FnOnce(u64) -> Result<()>,
+    {
+        let dir_path = self.options.dir_path.clone();
+        let _relocate_lock = self.relocate_lock.write();
+
+        let base_file_id = {
+            let mut active_file = self.active_file.write();
+            active_file.sync()?;
+            let active_file_id = active_file.get_file_id();
+            let base_file_id = active_file_id + 1;
+            let last_file_id = base_file_id + sources.len() as u64 - 1;
+
+            // 移动数据文件之前先记录到 manifest 中
+            let installed_file_ids: Vec<u64> = (base_file_id..=last_file_id).collect();
+            self.record_rotation(
+                &mut self.active_file_meta.lock(),
+                &installed_file_ids,
+                last_file_id + 1,
+            )?;
+
+            for (i, source) in sources.iter().enumerate() {
+                let dest = get_data_file_name(dir_path.clone(), base_file_id + i as u64);
+                move_file(source, &dest).map_err(ingest_error)?;
+            }
+            util::file::sync_dir(&dir_path).map_err(ingest_error)?;
+
+            // 原来的活跃文件和导入的数据文件都作为旧的数据文件
+            let mut installed_files = Vec::with_capacity(sources.len());
+            for file_id in base_file_id..=last_file_id {
+                let data_file = DataFile::new(
+                    dir_path.clone(),
+                    file_id,
+                    IOType::StandardFIO,
+                    self.cipher.clone(),
+                )?;
+                installed_files.push(Arc::new(data_file));
+            }
+            let new_file = DataFile::new(
+                dir_path.clone(),
+                last_file_id + 1,
+                IOType::StandardFIO,
+                self.cipher.clone(),
+            )?;
+            preallocate_if_enabled(&self.options, &new_file)?;
+
+            let mut older_files = self.older_files.write();
+            let old_file = std::mem::replace(&mut *active_file, Arc::new(new_file));
+            older_files.insert(active_file_id, self.seal_data_file(old_file));
+            for data_file in installed_files {
+                older_files.insert(data_file.get_file_id(), self.seal_data_file(data_file));
+            }
+            base_file_id
+        };
+
+        update_index(base_file_id)
+    }
+
+    // 根据临时目录中的 hint 文件批量更新索引，hint 文件中的位置需要加上安装之后的文件 id
+    fn load_ingest_hint_file(&self, ingest_path: &Path, base_file_id: u64) -> Result<()> {
+        let hint_file = DataFile::new_hint_file(ingest_path.to_path_buf(), self.cipher.clone())?;
+        let mut offset = hint_file.get_header_size();
+        loop {
+            let (log_record, size) = match hint_file.read_log_record(offset) {
+                Ok(result) => (result.record, result.size),
+                Err(e) => {
+                    if e == Errors::ReadDataFileEof {
+                        break;
+                    }
+                    return Err(e);
+                }
+            };
+
+            let mut log_record_pos = decode_log_record_pos(log_record.value)?;
+            log_record_pos.file_id += base_file_id;
+            self.index_put(log_record.key, log_record_pos);
+            offset += size as u64;
+        }
+
+        Ok(())
+    }
+}
+
+// 根据记录类型更新外部数据文件中 key 的位置
+fn update_external_position(
+    positions: &mut HashMap<Vec<u8>, Option<LogRecordPos>>,
+    reclaim_positions: &mut Vec<LogRecordPos>,
+    key: Vec<u8>,
+    rec_type: LogRecordType,
+    pos: LogRecordPos,
+) {
+    let new_pos = match rec_type {
+        LogRecordType::NORMAL => Some(pos),
+        LogRecordType::DELETE => {
+            reclaim_positions.push(pos);
+            None
+        }
+        LogRecordType::TxnFinished => return,
+    };
+    if let Some(Some(old_pos)) = positions.insert(key, new_pos) {
+        reclaim_positions.push(old_pos);
+    }
+}
+
+// 移动文件，不在同一个文件系统中无法重命名时复制之后删除原文件
+fn move_file(source: &Path, dest: &Path) -> std::io::Result<()> {
+    if fs::rename(source, dest).is_ok() {
+        return Ok(());
+    }
+    fs::copy(source, dest)?;
+    fs::File::open(dest)?.sync_all()?;
+    fs::remove_file(source)
+}
+
+// 获取临时的用于导入数据的目录
+fn get_ingest_path(dir_path: PathBuf) -> PathBuf {
+    get_sibling_path(dir_path, INGEST_DIR_NAME)
+}
+
+// 数据目录旁边的临时目录，名称为数据目录名称加上后缀
+pub(crate) fn get_sibling_path(dir_path: PathBuf, suffix: &str) -> PathBuf {
+    let file_name = dir_path.file_name().unwrap();
+    let ingest_name = std::format!("{}-{}", file_name.to_str().unwrap(), suffix);
+    let parent = dir_path.parent().unwrap();
+    parent.to_path_buf().join(ingest_name)
+}
+
+fn ingest_error(e: std::io::Error) -> Errors {
+    error!("failed to install ingest files: {}", e);
+    Errors::FailedToInstallIngestFiles
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        options::{IndexType, Options},
+        util::rand_kv::{get_test_key, get_test_value},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_ingest() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-ingest");
+        opts.data_file_size = 64 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        // 已经存在的 key 会被导入的数据覆盖
+        for i in 0..100 {
+            assert!(engine
+                .put(get_test_key(i), Bytes::from("old value"))
+                .is_ok());
+        }
+
+        let count = engine
+            .ingest((0..5000).map(|i| (get_test_key(i), get_test_value(i))))
+            .unwrap();
+        assert_eq!(count, 5000);
+        assert!(!get_ingest_path(opts.dir_path.clone()).exists());
+
+        // 导入之后的写入比导入的数据更新
+        assert!(engine
+            .put(get_test_key(1), Bytes::from("new value"))
+            .is_ok());
+
+        let check = |engine: &Engine| {
+            assert_eq!(engine.list_keys().unwrap().len(), 5000);
+            assert_eq!(engine.stat().unwrap().key_num, 5000);
+            assert_eq!(
+                engine.get(get_test_key(1)).unwrap(),
+                Bytes::from("new value")
+            );
+            for i in (0..5000).filter(|i| *i != 1) {
+                assert_eq!(engine.get(get_test_key(i)).unwrap(), get_test_value(i));
+            }
+        };
+        check(&engine);
+
+        std::mem::drop(engine);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        check(&engine2);
+
+        std::mem::drop(engine2);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_ingest_invalid() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-ingest-invalid");
+        opts.index_type = IndexType::SkipList;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        assert_eq!(engine.ingest(std::iter::empty()).unwrap(), 0);
+
+        // key 没有排序或者重复时不会导入任何数据
+        let unsorted = vec![
+            (Bytes::from("a"), Bytes::from("1")),
+            (Bytes::from("c"), Bytes::from("2")),
+            (Bytes::from("b"), Bytes::from("3")),
+        ];
+        assert_eq!(
+            engine.ingest(unsorted.into_iter()).err().unwrap(),
+            Errors::IngestKeysNotSorted
+        );
+        let duplicated = vec![
+            (Bytes::from("a"), Bytes::from("1")),
+            (Bytes::from("a"), Bytes::from("2")),
+        ];
+        assert_eq!(
+            engine.ingest(duplicated.into_iter()).err().unwrap(),
+            Errors::IngestKeysNotSorted
+        );
+        assert!(engine.list_keys().unwrap().is_empty());
+        assert!(!get_ingest_path(opts.dir_path.clone()).exists());
+
+        std::mem::drop(engine);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    // 使用另一个实例生成外部数据文件，按照文件 id 的顺序返回
+    fn build_external_files(dir_path: PathBuf) -> Vec<PathBuf> {
+        let mut opts = Options::default();
+        opts.dir_path = dir_path.clone();
+        opts.data_file_size = 32 * 1024;
+        let engine = Engine::open(opts).expect("failed to open engine");
+        for i in 0..1000 {
+            assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        for i in 0..100 {
+            assert!(engine.delete(get_test_key(i)).is_ok());
+        }
+        let wb = engine
+            .new_write_batch(crate::options::WriteBatchOptions::default())
+            .unwrap();
+        assert!(wb.put(get_test_key(100), Bytes::from("txn value")).is_ok());
+        assert!(wb.commit().is_ok());
+        engine.close().expect("failed to close engine");
+        std::mem::drop(engine);
+
+        let mut file_ids: Vec<u64> = fs::read_dir(&dir_path)
+            .unwrap()
+            .filter_map(|entry| {
+                let file_name = entry.unwrap().file_name();
+                let file_name = file_name.to_str().unwrap().to_string();
+                file_name
+                    .strip_suffix(crate::data::data_file::DATA_FILE_NAME_SUFFIX)
+                    .map(|id| id.parse().unwrap())
+            })
+            .collect();
+        file_ids.sort();
+        file_ids
+            .into_iter()
+            .map(|file_id| get_data_file_name(dir_path.clone(), file_id))
+            .collect()
+    }
+
+    #[test]
+    fn test_ingest_external_files() {
+        let external_path = PathBuf::from("/tmp/bitcask-rs-ingest-external-src");
+        let paths = build_external_files(external_path.clone());
+        assert!(paths.len() > 1);
+
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-ingest-external");
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        // 外部文件中删除的 key 会删除已经存在的数据
+        assert!(engine
+            .put(get_test_key(0), Bytes::from("old value"))
+            .is_ok());
+        assert!(engine.put(get_test_key(5000), Bytes::from("kept")).is_ok());
+
+        assert!(engine.ingest_external_files(&paths).is_ok());
+        assert!(paths.iter().all(|path| !path.exists()));
+
+        let check = |engine: &Engine| {
+            assert_eq!(engine.list_keys().unwrap().len(), 901);
+            assert_eq!(engine.stat().unwrap().key_num, 901);
+            assert_eq!(
+                engine.get(get_test_key(0)).err().unwrap(),
+                Errors::KeyNotFound
+            );
+            assert_eq!(
+                engine.get(get_test_key(100)).unwrap(),
+                Bytes::from("txn value")
+            );
+            assert_eq!(engine.get(get_test_key(5000)).unwrap(), Bytes::from("kept"));
+            for i in 101..1000 {
+                assert_eq!(engine.get(get_test_key(i)).unwrap(), get_test_value(i));
+            }
+        };
+        check(&engine);
+        assert!(engine.stat().unwrap().reclaim_size > 0);
+
+        std::mem::drop(engine);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        check(&engine2);
+
+        std::mem::drop(engine2);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+        std::fs::remove_dir_all(external_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_ingest_external_files_invalid() {
+        let external_path = PathBuf::from("/tmp/bitcask-rs-ingest-external-invalid-src");
+        let paths = build_external_files(external_path.clone());
+
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-ingest-external-invalid");
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        // 文件不存在
+        let missing = vec![external_path.join("missing.data")];
+        assert!(matches!(
+            engine.ingest_external_files(&missing),
+            Err(Errors::InvaildExternalFile { offset: 0, .. })
+        ));
+
+        // 修改最后一个文件中的数据，CRC 校验失败时不会导入任何文件
+        let last = paths.last().unwrap();
+        let mut content = fs::read(last).unwrap();
+        let len = content.len();
+        content[len - 1] ^= 0xff;
+        fs::write(last, content).unwrap();
+        assert!(matches!(
+            engine.ingest_external_files(&paths),
+            Err(Errors::InvaildExternalFile { .. })
+        ));
+        assert!(paths.iter().all(|path| path.exists()));
+        assert!(engine.list_keys().unwrap().is_empty());
+
+        std::mem::drop(engine);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+        std::fs::remove_dir_all(external_path).expect("failed to remove path");
+    }
+}
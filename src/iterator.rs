@@ -1,27 +1,134 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use bytes::Bytes;
+use log::error;
 use parking_lot::RwLock;
 
-use crate::{db::Engine, errors::Result, index::IndexIterator, options::IteratorOptions};
+use crate::{
+    bucket::is_bucket_key,
+    data::data_file::DataFile,
+    db::Engine,
+    errors::{Errors, Result},
+    index::IndexIterator,
+    mvcc::is_mvcc_key,
+    options::IteratorOptions,
+};
 
 /// 迭代器接口
-pub struct Iterator<'a> {
+pub struct Iterator {
     index_iter: Arc<RwLock<Box<dyn IndexIterator>>>, // 索引迭代器
-    engine: &'a Engine,                              // engine的引用必须比Iterator寿命长
+    engine: Engine,                                  // 存储引擎的 handle，迭代器存在期间不会关闭
+    include_mvcc_keys: bool,                         // 是否遍历 MVCC 事务内部使用的 key
+    include_bucket_keys: bool,                       // 是否遍历 bucket 中的 key
+    pinned_files: Option<HashMap<u64, Arc<DataFile>>>, // 一致模式下固定的数据文件
+}
+
+/// 只遍历 key 的迭代器，不读取 value，按照索引的顺序逐个返回，不会一次性拷贝出所有的 key
+pub struct Keys {
+    index_iter: Box<dyn IndexIterator>, // 索引迭代器
+    include_mvcc_keys: bool,            // 是否遍历 MVCC 事务内部使用的 key
+    include_bucket_keys: bool,          // 是否遍历 bucket 中的 key
+}
+
+/// 分页遍历返回的一页数据
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanPage {
+    pub items: Vec<(Bytes, Bytes)>, // 当前页的 key/value
+    pub next: Option<Bytes>,        // 下一页的游标，为空说明已经遍历完成
 }
 
 impl Engine {
+    /// 返回迭代器，除非 prefix 指定为 MVCC 内部前缀，否则不会遍历到事务内部使用的 key
+    /// bucket 中的 key 同理，只能通过 bucket 的迭代器遍历
+    /// 迭代器创建时拷贝索引，之后写入的 key 不会被遍历到；默认遍历时才读取 value，
+    /// 对应的数据文件被 merge 删除时读取最新的 value，设置 IteratorOptions::consistent 之后只返回创建时的 value
     pub fn iter(&self, options: IteratorOptions) -> Iterator {
+        let include_mvcc_keys = is_mvcc_key(&options.prefix);
+        let include_bucket_keys = is_bucket_key(&options.prefix);
+        // 一致模式下持有数据文件集合的锁期间拷贝索引，索引中的位置都在固定的数据文件中
+        let (index_iter, pinned_files) = match options.consistent {
+            true => {
+                let active_file = self.active_file.read();
+                let older_files = self.older_files.read();
+                let mut files = older_files.clone();
+                files.insert(active_file.get_file_id(), active_file.clone());
+                (self.index.iterator(options), Some(files))
+            }
+            false => (self.index.iterator(options), None),
+        };
         Iterator {
-            index_iter: Arc::new(RwLock::new(self.index.iterator(options))),
-            engine: self,
+            index_iter: Arc::new(RwLock::new(index_iter)),
+            engine: self.clone(),
+            include_mvcc_keys,
+            include_bucket_keys,
+            pinned_files,
         }
     }
 
-    /// 返回数据库中所有的 kyes
+    /// 返回数据库中所有的 kyes，不包含 MVCC 事务内部使用的 key 和 bucket 中的 key
+    /// 会把所有的 key 拷贝到内存中，key 的数量很多时使用 keys 逐个遍历
     pub fn list_keys(&self) -> Result<Vec<Bytes>> {
-        self.index.list_keys()
+        Ok(self.keys(IteratorOptions::default())?.collect())
+    }
+
+    /// 返回遍历 key 的迭代器，跳过的 key 和 iter 相同
+    pub fn keys(&self, options: IteratorOptions) -> Result<Keys> {
+        self.check_closed()?;
+        let include_mvcc_keys = is_mvcc_key(&options.prefix);
+        let include_bucket_keys = is_bucket_key(&options.prefix);
+        Ok(Keys {
+            index_iter: self.index.iterator(options),
+            include_mvcc_keys,
+            include_bucket_keys,
+        })
+    }
+
+    /// 分页遍历前缀为 prefix 的数据，返回 start_after 之后（不包含）的最多 limit 条数据，
+    /// 以及下一页的游标，将游标作为 start_after 传入即可继续遍历
+    pub fn scan(
+        &self,
+        prefix: Bytes,
+        start_after: Option<Bytes>,
+        limit: usize,
+    ) -> Result<ScanPage> {
+        self.check_closed()?;
+        if limit == 0 {
+            return Err(Errors::InvaildScanLimit);
+        }
+
+        let include_mvcc_keys = is_mvcc_key(&prefix);
+        let include_bucket_keys = is_bucket_key(&prefix);
+        let mut index_iter = self.index.iterator(IteratorOptions {
+            prefix: prefix.to_vec(),
+            reverse: false,
+            ..Default::default()
+        });
+        if let Some(start_after) = start_after.as_ref() {
+            index_iter.seek(start_after.to_vec());
+        }
+
+        let mut items = Vec::new();
+        while let Some((key, pos)) = index_iter.next() {
+            if !include_mvcc_keys && is_mvcc_key(key) {
+                continue;
+            }
+            if !include_bucket_keys && is_bucket_key(key) {
+                continue;
+            }
+            // seek 定位到的是第一个大于等于游标的 key，游标本身已经在上一页中返回过
+            if start_after.as_ref().is_some_and(|after| after == key) {
+                continue;
+            }
+            // 多读到一条数据说明还有下一页
+            if items.len() == limit {
+                let next = items.last().map(|(key, _): &(Bytes, Bytes)| key.clone());
+                return Ok(ScanPage { items, next });
+            }
+            let value = self.get_value_by_position(pos)?;
+            items.push((Bytes::from(key.to_vec()), value));
+        }
+
+        Ok(ScanPage { items, next: None })
     }
 
     /// 对数据库中当中的所有数据执行函数操作，函数返回 false 时终止
@@ -30,9 +137,19 @@ impl Engine {
         Self: Sized,
         F: Fn(Bytes, Bytes) -> bool,
     {
+        self.try_fold(|key, value| Ok(f(key, value)))
+    }
+
+    /// 和 fold 相同，函数返回 Ok(false) 时终止，函数返回的错误以及读取数据时的错误都会直接返回
+    pub fn try_fold<F>(&self, mut f: F) -> Result<()>
+    where
+        Self: Sized,
+        F: FnMut(Bytes, Bytes) -> Result<bool>,
+    {
+        self.check_closed()?;
         let mut iter = self.iter(IteratorOptions::default());
-        while let Some((key, value)) = iter.next() {
-            if !f(key, value) {
+        while let Some((key, value)) = iter.try_next()? {
+            if !f(key, value)? {
                 break;
             }
         }
@@ -41,7 +158,7 @@ impl Engine {
     }
 }
 
-impl Iterator<'_> {
+impl Iterator {
     /// 重新回到迭代器的起点，即第一个数据
     pub fn rewind(&mut self) {
         let mut index_iter = self.index_iter.write();
@@ -55,14 +172,63 @@ impl Iterator<'_> {
     }
 
     /// 跳转到下一个 key，返回 None 说明遍历完成
+    /// 读取数据出错时跳过这条数据，需要感知错误时使用 try_next
     pub fn next(&mut self) -> Option<(Bytes, Bytes)> {
+        loop {
+            match self.try_next() {
+                Ok(item) => return item,
+                Err(e) => error!("failed to read value in iterator: {}", e),
+            }
+        }
+    }
+
+    /// 跳转到下一个 key，返回 Ok(None) 说明遍历完成，读取数据出错时返回错误，
+    /// 再次调用会从出错数据的下一个 key 继续遍历
+    pub fn try_next(&mut self) -> Result<Option<(Bytes, Bytes)>> {
         let mut index_iter = self.index_iter.write();
-        if let Some(item) = index_iter.next() {
-            let value = self
-                .engine
-                .get_value_by_position(item.1)
-                .expect("failed to get value from data file");
-            return Some((Bytes::from(item.0.to_vec()), value));
+        while let Some((key, pos)) = index_iter.next() {
+            // 普通的遍历跳过 MVCC 事务内部使用的 key
+            if !self.include_mvcc_keys && is_mvcc_key(key) {
+                continue;
+            }
+            // 普通的遍历跳过 bucket 中的 key
+            if !self.include_bucket_keys && is_bucket_key(key) {
+                continue;
+            }
+            // 一致模式下只从固定的数据文件中读取
+            if let Some(files) = &self.pinned_files {
+                let data_file = files.get(&pos.file_id).ok_or(Errors::DataFileNotFound)?;
+                let value = self.engine.get_value_from_file(data_file, pos)?;
+                return Ok(Some((Bytes::from(key.to_vec()), value)));
+            }
+            let value = match self.engine.get_value_by_position(pos) {
+                Ok(value) => value,
+                // 迭代器拿到的位置所在的数据文件已经被 merge 删除，从索引中重新获取最新的位置，
+                // key 已经不存在的话直接跳过
+                Err(Errors::DataFileNotFound) => match self.engine.index.get(key.clone()) {
+                    Some(pos) => self.engine.get_value_by_position(&pos)?,
+                    None => continue,
+                },
+                Err(e) => return Err(e),
+            };
+            return Ok(Some((Bytes::from(key.to_vec()), value)));
+        }
+        Ok(None)
+    }
+}
+
+impl std::iter::Iterator for Keys {
+    type Item = Bytes;
+
+    fn next(&mut self) -> Option<Bytes> {
+        while let Some((key, _)) = self.index_iter.next() {
+            if !self.include_mvcc_keys && is_mvcc_key(key) {
+                continue;
+            }
+            if !self.include_bucket_keys && is_bucket_key(key) {
+                continue;
+            }
+            return Some(Bytes::from(key.to_vec()));
         }
         None
     }
@@ -274,4 +440,291 @@ mod tests {
 
         std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
     }
+
+    #[test]
+    fn test_iterator_bounds() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-iter-bounds");
+        opts.data_file_size = 64 * 1024 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for key in ["aacc", "bbac", "bbde", "ccde", "eecc"] {
+            let put_res = engine.put(Bytes::from(key), util::rand_kv::get_test_value(10));
+            assert!(put_res.is_ok());
+        }
+
+        let mut iter_opts = IteratorOptions::default();
+        iter_opts.lower_bound = Some("bbde".as_bytes().to_vec());
+        iter_opts.upper_bound = Some("eecc".as_bytes().to_vec());
+        let keys: Vec<Bytes> = engine.keys(iter_opts).unwrap().collect();
+        assert_eq!(keys, vec![Bytes::from("bbde"), Bytes::from("ccde")]);
+
+        let mut iter_opts = IteratorOptions::default();
+        iter_opts.upper_bound = Some("bbde".as_bytes().to_vec());
+        iter_opts.reverse = true;
+        let mut iter = engine.iter(iter_opts);
+        assert_eq!(iter.next().unwrap().0, Bytes::from("bbac"));
+        assert_eq!(iter.next().unwrap().0, Bytes::from("aacc"));
+        assert!(iter.next().is_none());
+
+        // bucket 中的上下界是不带 bucket 前缀的 key
+        let bucket = engine.bucket("b1").unwrap();
+        for key in ["a", "b", "c"] {
+            let put_res = bucket.put(Bytes::from(key), util::rand_kv::get_test_value(10));
+            assert!(put_res.is_ok());
+        }
+        let mut iter_opts = IteratorOptions::default();
+        iter_opts.lower_bound = Some("b".as_bytes().to_vec());
+        let mut bucket_iter = bucket.iter(iter_opts);
+        assert_eq!(bucket_iter.next().unwrap().0, Bytes::from("b"));
+        assert_eq!(bucket_iter.next().unwrap().0, Bytes::from("c"));
+        assert!(bucket_iter.next().is_none());
+
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_scan() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-scan");
+        opts.data_file_size = 64 * 1024 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..25 {
+            let key = Bytes::from(format!("user-{:03}", i));
+            assert!(engine.put(key, util::rand_kv::get_test_value(i)).is_ok());
+        }
+        assert!(engine
+            .put(Bytes::from("other"), util::rand_kv::get_test_value(100))
+            .is_ok());
+
+        // 每页 10 条，依次遍历完所有前缀匹配的数据
+        let mut keys = Vec::new();
+        let mut cursor = None;
+        let mut pages = 0;
+        loop {
+            let page = engine.scan(Bytes::from("user-"), cursor, 10).unwrap();
+            assert!(page.items.len() <= 10);
+            for (key, value) in page.items.iter() {
+                let i: usize = String::from_utf8(key[5..].to_vec())
+                    .unwrap()
+                    .parse()
+                    .unwrap();
+                assert_eq!(*value, util::rand_kv::get_test_value(i));
+                keys.push(key.clone());
+            }
+            pages += 1;
+            if page.next.is_none() {
+                break;
+            }
+            cursor = page.next;
+        }
+        assert_eq!(pages, 3);
+        assert_eq!(keys.len(), 25);
+        assert_eq!(keys[0], Bytes::from("user-000"));
+        assert_eq!(keys[24], Bytes::from("user-024"));
+
+        // 刚好取完最后一页时没有下一页的游标
+        let page = engine.scan(Bytes::new(), None, 26).unwrap();
+        assert_eq!(page.items.len(), 26);
+        assert!(page.next.is_none());
+
+        // 游标不需要是已经存在的 key
+        let page = engine
+            .scan(Bytes::from("user-"), Some(Bytes::from("user-0225")), 10)
+            .unwrap();
+        assert_eq!(page.items[0].0, Bytes::from("user-023"));
+        assert!(page.next.is_none());
+
+        assert_eq!(
+            engine.scan(Bytes::new(), None, 0).err().unwrap(),
+            Errors::InvaildScanLimit
+        );
+
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_iterator_after_merge_files() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-iterator-merge-files");
+        opts.data_file_size = 32 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..500 {
+            let res = engine.put(
+                util::rand_kv::get_test_key(i),
+                util::rand_kv::get_test_value(i),
+            );
+            assert!(res.is_ok());
+        }
+        for i in 0..10 {
+            assert!(engine.delete(util::rand_kv::get_test_key(i)).is_ok());
+        }
+
+        // 迭代器已经拿到的位置所在的数据文件被删除之后，仍然可以读到最新的数据
+        let mut iter = engine.iter(IteratorOptions::default());
+        let first = iter.try_next().unwrap().unwrap();
+        assert_eq!(first.1, util::rand_kv::get_test_value(10));
+        let merged = engine.merge_files(0.0).unwrap();
+        assert!(!merged.is_empty());
+
+        let mut count = 1;
+        while let Some((key, value)) = iter.try_next().unwrap() {
+            assert_eq!(engine.get(key).unwrap(), value);
+            count += 1;
+        }
+        assert_eq!(count, 490);
+
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_iterator_consistent() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-iterator-consistent");
+        opts.data_file_size = 32 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..500 {
+            let res = engine.put(
+                util::rand_kv::get_test_key(i),
+                util::rand_kv::get_test_value(i),
+            );
+            assert!(res.is_ok());
+        }
+
+        let mut iter = engine.iter(IteratorOptions {
+            consistent: true,
+            ..Default::default()
+        });
+        let mut default_iter = engine.iter(IteratorOptions::default());
+
+        // 创建迭代器之后覆盖和删除数据，并且 merge 删除原来的数据文件
+        for i in 0..500 {
+            let res = match i % 2 == 0 {
+                true => engine.put(util::rand_kv::get_test_key(i), Bytes::from("new")),
+                false => engine.delete(util::rand_kv::get_test_key(i)),
+            };
+            assert!(res.is_ok());
+        }
+        let merged = engine.merge_files(0.0).unwrap();
+        assert!(!merged.is_empty());
+
+        // 一致模式下返回的是创建迭代器时的数据
+        let mut count = 0;
+        while let Some((key, value)) = iter.try_next().unwrap() {
+            let i = (0..500)
+                .find(|i| util::rand_kv::get_test_key(*i) == key)
+                .unwrap();
+            assert_eq!(value, util::rand_kv::get_test_value(i));
+            count += 1;
+        }
+        assert_eq!(count, 500);
+
+        // 默认模式下数据文件被删除的 key 读到的是最新的数据，已经删除的 key 被跳过
+        let (mut count, mut new_count) = (0, 0);
+        while let Some((_, value)) = default_iter.try_next().unwrap() {
+            if value == Bytes::from("new") {
+                new_count += 1;
+            }
+            count += 1;
+        }
+        assert!(count < 500);
+        assert!(new_count > 0);
+
+        std::mem::drop(iter);
+        std::mem::drop(default_iter);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_try_fold() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-try-fold");
+        opts.data_file_size = 64 * 1024 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..10 {
+            let res = engine.put(
+                util::rand_kv::get_test_key(i),
+                util::rand_kv::get_test_value(i),
+            );
+            assert!(res.is_ok());
+        }
+
+        // 返回 false 时终止
+        let mut count = 0;
+        let res = engine.try_fold(|_, _| {
+            count += 1;
+            Ok(count < 3)
+        });
+        assert!(res.is_ok());
+        assert_eq!(count, 3);
+
+        // 函数返回的错误直接返回给调用方
+        let mut count = 0;
+        let res = engine.try_fold(|_, _| {
+            count += 1;
+            if count == 5 {
+                return Err(Errors::KeyNotFound);
+            }
+            Ok(true)
+        });
+        assert_eq!(res.err().unwrap(), Errors::KeyNotFound);
+        assert_eq!(count, 5);
+
+        engine.close().expect("failed to close engine");
+        assert_eq!(
+            engine.try_fold(|_, _| Ok(true)).err().unwrap(),
+            Errors::DatabaseClosed
+        );
+
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_keys() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-keys");
+        opts.data_file_size = 64 * 1024 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for key in ["aacc", "eecc", "bbac", "bbde"] {
+            assert!(engine
+                .put(Bytes::from(key), util::rand_kv::get_test_value(10))
+                .is_ok());
+        }
+        // bucket 中的 key 不会被遍历到
+        let bucket = engine.bucket("b1").unwrap();
+        assert!(bucket
+            .put(Bytes::from("aaaa"), util::rand_kv::get_test_value(10))
+            .is_ok());
+
+        let keys: Vec<Bytes> = engine.keys(IteratorOptions::default()).unwrap().collect();
+        assert_eq!(
+            keys,
+            vec![
+                Bytes::from("aacc"),
+                Bytes::from("bbac"),
+                Bytes::from("bbde"),
+                Bytes::from("eecc")
+            ]
+        );
+        assert_eq!(engine.list_keys().unwrap(), keys);
+
+        let mut iter_opts = IteratorOptions::default();
+        iter_opts.prefix = "bb".as_bytes().to_vec();
+        iter_opts.reverse = true;
+        let keys: Vec<Bytes> = engine.keys(iter_opts).unwrap().collect();
+        assert_eq!(keys, vec![Bytes::from("bbde"), Bytes::from("bbac")]);
+
+        engine.close().expect("failed to close engine");
+        assert_eq!(
+            engine.keys(IteratorOptions::default()).err().unwrap(),
+            Errors::DatabaseClosed
+        );
+
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
 }
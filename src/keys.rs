@@ -0,0 +1,313 @@
+use bytes::Bytes;
+
+use crate::errors::{Errors, Result};
+
+// 字符串和字节数组中的 0x00 转义为 0x00 0xff，并以 0x00 0x01 结尾，
+// 编码之后的字节序和原始的字节序一致，并且是其他值前缀的值排在前面
+const ESCAPE: u8 = 0x00;
+const ESCAPED_ZERO: u8 = 0xff;
+const TERMINATOR: u8 = 0x01;
+
+/// 可以保序编码到 key 中的类型，编码之后按照字节序比较的结果和原始值的比较结果相同
+pub trait KeyEncode {
+    /// 将编码之后的数据追加到 buf 的末尾
+    fn encode_key(&self, buf: &mut Vec<u8>);
+}
+
+/// 可以从 KeyEncode 编码的数据中解码出来的类型
+pub trait KeyDecode: Sized {
+    /// 从 buf 的开头解码出一个值，并将 buf 移动到剩余的数据
+    fn decode_key(buf: &mut &[u8]) -> Result<Self>;
+}
+
+/// 编码组合 key，例如 `encode(&("tenant", timestamp, id))`，
+/// 只包含前面几个字段的编码可以作为 IteratorOptions::prefix 遍历这些字段相同的所有 key
+pub fn encode<T: KeyEncode + ?Sized>(key: &T) -> Bytes {
+    let mut buf = Vec::new();
+    key.encode_key(&mut buf);
+    Bytes::from(buf)
+}
+
+/// 解码 encode 编码的 key，类型不匹配或者有多余的数据时返回 InvaildEncodedKey
+pub fn decode<T: KeyDecode>(key: &[u8]) -> Result<T> {
+    let mut buf = key;
+    let value = T::decode_key(&mut buf)?;
+    if !buf.is_empty() {
+        return Err(Errors::InvaildEncodedKey);
+    }
+    Ok(value)
+}
+
+impl<T: KeyEncode + ?Sized> KeyEncode for &T {
+    fn encode_key(&self, buf: &mut Vec<u8>) {
+        (**self).encode_key(buf)
+    }
+}
+
+// 无符号整数按照大端序编码，有符号整数翻转符号位之后按照大端序编码，负数排在前面
+macro_rules! impl_key_int {
+    ($($ty:ty => $unsigned:ty),*) => {
+        $(
+            impl KeyEncode for $ty {
+                fn encode_key(&self, buf: &mut Vec<u8>) {
+                    let flipped = (*self as $unsigned) ^ (<$ty>::MIN as $unsigned);
+                    buf.extend_from_slice(&flipped.to_be_bytes());
+                }
+            }
+
+            impl KeyDecode for $ty {
+                fn decode_key(buf: &mut &[u8]) -> Result<Self> {
+                    const SIZE: usize = std::mem::size_of::<$ty>();
+                    if buf.len() < SIZE {
+                        return Err(Errors::InvaildEncodedKey);
+                    }
+                    let (bytes, rest) = buf.split_at(SIZE);
+                    *buf = rest;
+                    let flipped = <$unsigned>::from_be_bytes(bytes.try_into().unwrap());
+                    Ok((flipped ^ (<$ty>::MIN as $unsigned)) as $ty)
+                }
+            }
+        )*
+    };
+}
+
+impl_key_int!(
+    u8 => u8, u16 => u16, u32 => u32, u64 => u64, u128 => u128,
+    i8 => u8, i16 => u16, i32 => u32, i64 => u64, i128 => u128
+);
+
+impl KeyEncode for [u8] {
+    fn encode_key(&self, buf: &mut Vec<u8>) {
+        for b in self {
+            buf.push(*b);
+            if *b == ESCAPE {
+                buf.push(ESCAPED_ZERO);
+            }
+        }
+        buf.push(ESCAPE);
+        buf.push(TERMINATOR);
+    }
+}
+
+impl KeyEncode for Vec<u8> {
+    fn encode_key(&self, buf: &mut Vec<u8>) {
+        self.as_slice().encode_key(buf)
+    }
+}
+
+impl KeyEncode for Bytes {
+    fn encode_key(&self, buf: &mut Vec<u8>) {
+        self.as_ref().encode_key(buf)
+    }
+}
+
+impl KeyEncode for str {
+    fn encode_key(&self, buf: &mut Vec<u8>) {
+        self.as_bytes().encode_key(buf)
+    }
+}
+
+impl KeyEncode for String {
+    fn encode_key(&self, buf: &mut Vec<u8>) {
+        self.as_bytes().encode_key(buf)
+    }
+}
+
+impl KeyDecode for Vec<u8> {
+    fn decode_key(buf: &mut &[u8]) -> Result<Self> {
+        let mut value = Vec::new();
+        let mut i = 0;
+        loop {
+            match buf.get(i) {
+                Some(&ESCAPE) => {
+                    match buf.get(i + 1) {
+                        Some(&ESCAPED_ZERO) => value.push(ESCAPE),
+                        Some(&TERMINATOR) => break,
+                        _ => return Err(Errors::InvaildEncodedKey),
+                    }
+                    i += 2;
+                }
+                Some(b) => {
+                    value.push(*b);
+                    i += 1;
+                }
+                None => return Err(Errors::InvaildEncodedKey),
+            }
+        }
+        *buf = &buf[i + 2..];
+        Ok(value)
+    }
+}
+
+impl KeyDecode for Bytes {
+    fn decode_key(buf: &mut &[u8]) -> Result<Self> {
+        Vec::<u8>::decode_key(buf).map(Bytes::from)
+    }
+}
+
+impl KeyDecode for String {
+    fn decode_key(buf: &mut &[u8]) -> Result<Self> {
+        String::from_utf8(Vec::<u8>::decode_key(buf)?).map_err(|_| Errors::InvaildEncodedKey)
+    }
+}
+
+// 元组依次编码每个字段，先按照第一个字段排序，相同时再按照之后的字段排序
+macro_rules! impl_key_tuple {
+    ($($name:ident),+) => {
+        impl<$($name: KeyEncode),+> KeyEncode for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn encode_key(&self, buf: &mut Vec<u8>) {
+                let ($($name,)+) = self;
+                $($name.encode_key(buf);)+
+            }
+        }
+
+        impl<$($name: KeyDecode),+> KeyDecode for ($($name,)+) {
+            fn decode_key(buf: &mut &[u8]) -> Result<Self> {
+                Ok(($($name::decode_key(buf)?,)+))
+            }
+        }
+    };
+}
+
+impl_key_tuple!(A);
+impl_key_tuple!(A, B);
+impl_key_tuple!(A, B, C);
+impl_key_tuple!(A, B, C, D);
+impl_key_tuple!(A, B, C, D, E);
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use proptest::prelude::*;
+
+    use crate::{
+        db::Engine,
+        options::{IteratorOptions, Options},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_keys_int_order() {
+        let values: Vec<i64> = vec![i64::MIN, -1000, -1, 0, 1, 255, 256, i64::MAX];
+        let encoded: Vec<Bytes> = values.iter().map(|v| encode(v)).collect();
+        assert!(encoded.windows(2).all(|w| w[0] < w[1]));
+        for (value, enc) in values.iter().zip(encoded.iter()) {
+            assert_eq!(enc.len(), 8);
+            assert_eq!(decode::<i64>(enc).unwrap(), *value);
+        }
+
+        assert_eq!(encode(&1u64).as_ref(), &[0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(encode(&-1i32).as_ref(), &[0x7f, 0xff, 0xff, 0xff]);
+        assert_eq!(decode::<u8>(&encode(&200u8)).unwrap(), 200);
+        assert_eq!(decode::<u32>(&[0, 1]), Err(Errors::InvaildEncodedKey));
+    }
+
+    #[test]
+    fn test_keys_bytes_order() {
+        let values: Vec<&[u8]> = vec![
+            b"",
+            b"\x00",
+            b"\x00\x00",
+            b"\x00\x01",
+            b"a",
+            b"a\x00",
+            b"ab",
+        ];
+        let encoded: Vec<Bytes> = values.iter().map(|v| encode(*v)).collect();
+        assert!(encoded.windows(2).all(|w| w[0] < w[1]));
+        for (value, enc) in values.iter().zip(encoded.iter()) {
+            assert_eq!(decode::<Vec<u8>>(enc).unwrap(), value.to_vec());
+        }
+
+        assert_eq!(encode("a\0b").as_ref(), b"a\x00\xffb\x00\x01");
+        assert_eq!(decode::<String>(b"a\x00\xffb\x00\x01").unwrap(), "a\0b");
+
+        // 缺少结束标识、错误的转义以及多余的数据
+        assert_eq!(decode::<String>(b"abc"), Err(Errors::InvaildEncodedKey));
+        assert_eq!(
+            decode::<String>(b"a\x00\x02"),
+            Err(Errors::InvaildEncodedKey)
+        );
+        assert_eq!(
+            decode::<String>(b"a\x00\x01b"),
+            Err(Errors::InvaildEncodedKey)
+        );
+        assert_eq!(
+            decode::<String>(b"\xff\x00\x01"),
+            Err(Errors::InvaildEncodedKey)
+        );
+    }
+
+    #[test]
+    fn test_keys_tuple() {
+        let key = encode(&("tenant-1", 1700000000u64, 42u64));
+        let (tenant, timestamp, id): (String, u64, u64) = decode(&key).unwrap();
+        assert_eq!(tenant, "tenant-1");
+        assert_eq!(timestamp, 1700000000);
+        assert_eq!(id, 42);
+
+        // 只编码前面的字段得到的是完整 key 的前缀
+        let prefix = encode(&("tenant-1",));
+        assert!(key.starts_with(&prefix));
+        assert!(!encode(&("tenant-10", 0u64, 0u64)).starts_with(&prefix));
+        let prefix = encode(&("tenant-1", 1700000000u64));
+        assert!(key.starts_with(&prefix));
+
+        // 先按照第一个字段排序
+        assert!(encode(&("a", u64::MAX)) < encode(&("ab", 0u64)));
+        assert!(encode(&("a", 1u64)) < encode(&("a", 2u64)));
+        assert_eq!(
+            decode::<(String, u64)>(&encode(&("a",))),
+            Err(Errors::InvaildEncodedKey)
+        );
+    }
+
+    #[test]
+    fn test_keys_range_scan() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-keys-range-scan");
+        opts.data_file_size = 64 * 1024 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for tenant in ["a", "a\0", "ab"] {
+            for timestamp in [300u64, 2, 1000, 70000] {
+                let key = encode(&(tenant, timestamp, 1u64));
+                assert!(engine.put(key, Bytes::from(tenant)).is_ok());
+            }
+        }
+
+        // 前缀遍历只返回同一个租户的数据，并且按照时间戳从小到大排序
+        let mut iter = engine.iter(IteratorOptions {
+            prefix: encode(&("a",)).to_vec(),
+            reverse: false,
+            ..Default::default()
+        });
+        let mut timestamps = Vec::new();
+        while let Some((key, value)) = iter.next() {
+            let (tenant, timestamp, _): (String, u64, u64) = decode(&key).unwrap();
+            assert_eq!(tenant, "a");
+            assert_eq!(value, Bytes::from("a"));
+            timestamps.push(timestamp);
+        }
+        assert_eq!(timestamps, vec![2, 300, 1000, 70000]);
+
+        std::mem::drop(engine);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    proptest! {
+        // 编码之后的字节序和原始值的顺序一致，并且可以解码出原始值
+        #[test]
+        fn prop_keys_order(
+            a in (prop::collection::vec(any::<u8>(), 0..8), any::<u64>(), any::<i64>()),
+            b in (prop::collection::vec(any::<u8>(), 0..8), any::<u64>(), any::<i64>()),
+        ) {
+            let (enc_a, enc_b) = (encode(&a), encode(&b));
+            prop_assert_eq!(a.cmp(&b), enc_a.cmp(&enc_b));
+            prop_assert_eq!(decode::<(Vec<u8>, u64, i64)>(&enc_a).unwrap(), a);
+        }
+    }
+}
@@ -1,14 +1,45 @@
+#[cfg(feature = "object-store")]
+mod archive;
+mod atomic;
 mod batch;
+mod bloom;
+pub mod bucket;
+mod changefeed;
+#[cfg(feature = "serde")]
+pub mod codec;
+pub mod column_family;
 mod data;
 pub mod db;
+mod destroy;
+mod dump;
 pub mod errors;
+mod export;
 mod fio;
+pub mod hlc;
 mod index;
+mod ingest;
 mod iterator;
+pub mod keys;
+pub mod manager;
+mod manifest;
 mod merge;
 mod mvcc;
 pub mod options;
+pub mod raft;
+mod repair;
+pub mod replication;
+mod retention;
+#[cfg(feature = "rocksdb-import")]
+mod rocksdb_import;
+pub mod secondary_index;
+mod stats;
+#[cfg(any(test, feature = "fault-inject"))]
+pub mod testing;
 mod util;
+mod value_log;
+pub mod value_reader;
+pub mod warmup;
+pub mod watch;
 
 #[cfg(test)]
 mod db_tests;
@@ -0,0 +1,345 @@
+use std::{
+    collections::HashMap,
+    fs,
+    sync::{mpsc, Arc},
+    thread,
+    time::Duration,
+};
+
+use log::{error, warn};
+use parking_lot::Mutex;
+
+use crate::{
+    db::{Engine, Stat},
+    errors::{Errors, Result},
+    options::{ManagerOptions, Options},
+};
+
+// 租户目录中存放数据的子目录，merge 等临时目录创建在数据目录旁边，也都位于租户目录中
+const TENANT_DATA_DIR_NAME: &str = "data";
+
+/// 在同一个根目录下管理多个相互隔离的存储引擎实例，每个租户使用根目录中的一个子目录，
+/// 所有租户共用相同的配置，由同一个后台线程依次对每个租户进行 merge
+pub struct EngineManager {
+    inner: Arc<ManagerInner>,
+    merge_worker: Mutex<Option<MergeWorker>>, // 后台定期 merge 的线程
+}
+
+struct ManagerInner {
+    options: ManagerOptions,
+    engines: Mutex<HashMap<String, Arc<Engine>>>, // 已经打开的租户
+}
+
+impl EngineManager {
+    /// 打开多租户管理器，根目录不存在时创建，租户在第一次使用时打开
+    pub fn open(options: ManagerOptions) -> Result<Self> {
+        if let Err(e) = fs::create_dir_all(&options.root_dir) {
+            warn!("create root dir err: {}", e);
+            return Err(Errors::FailedToCreateDatabaseDir);
+        }
+
+        let merge_interval = options.merge_interval;
+        let inner = Arc::new(ManagerInner {
+            options,
+            engines: Mutex::new(HashMap::new()),
+        });
+        let merge_worker =
+            merge_interval.map(|interval| MergeWorker::start(inner.clone(), interval));
+        Ok(Self {
+            inner,
+            merge_worker: Mutex::new(merge_worker),
+        })
+    }
+
+    /// 获取租户的存储引擎实例，没有打开时使用共用的配置打开，数据目录不存在时创建
+    pub fn engine(&self, tenant: &str) -> Result<Arc<Engine>> {
+        check_tenant_name(tenant)?;
+        let mut engines = self.inner.engines.lock();
+        if let Some(engine) = engines.get(tenant) {
+            return Ok(engine.clone());
+        }
+
+        let engine = Arc::new(Engine::open(self.inner.tenant_options(tenant))?);
+        engines.insert(tenant.to_string(), engine.clone());
+        Ok(engine)
+    }
+
+    /// 根目录中所有的租户，包括还没有打开的租户，按照名称排序
+    pub fn tenants(&self) -> Result<Vec<String>> {
+        let entries = fs::read_dir(&self.inner.options.root_dir).map_err(|e| {
+            error!("failed to read root dir: {}", e);
+            Errors::FailedToReadDatabaseDir
+        })?;
+
+        let mut tenants = Vec::new();
+        for entry in entries.flatten() {
+            if !entry.path().is_dir() {
+                continue;
+            }
+            if let Some(name) = entry.file_name().to_str() {
+                tenants.push(name.to_string());
+            }
+        }
+        tenants.sort();
+        Ok(tenants)
+    }
+
+    /// 关闭租户的存储引擎实例，其他地方持有的实例之后会返回 DatabaseClosed
+    pub fn close_tenant(&self, tenant: &str) -> Result<()> {
+        check_tenant_name(tenant)?;
+        match self.inner.engines.lock().remove(tenant) {
+            Some(engine) => engine.close(),
+            None => Ok(()),
+        }
+    }
+
+    /// 关闭租户的存储引擎实例并删除它的所有数据
+    pub fn remove_tenant(&self, tenant: &str) -> Result<()> {
+        self.close_tenant(tenant)?;
+        let dir_path = self.inner.options.root_dir.join(tenant);
+        if dir_path.is_dir() {
+            if let Err(e) = fs::remove_dir_all(dir_path) {
+                error!("failed to remove tenant dir: {}", e);
+                return Err(Errors::FailedToRemoveDataFile);
+            }
+        }
+        Ok(())
+    }
+
+    /// 依次对每个已经打开的租户进行 merge，返回完成了 merge 的租户
+    /// 没有达到 merge 阈值或者正在 merge 的租户直接跳过
+    pub fn merge_all(&self) -> Result<Vec<String>> {
+        self.inner.merge_all()
+    }
+
+    /// 每个已经打开的租户的统计数据，按照名称排序
+    pub fn tenant_stats(&self) -> Result<Vec<(String, Stat)>> {
+        let mut stats = Vec::new();
+        for (tenant, engine) in self.inner.opened_engines() {
+            stats.push((tenant, engine.stat()?));
+        }
+        Ok(stats)
+    }
+
+    /// 所有已经打开的租户的统计数据之和
+    pub fn stat(&self) -> Result<Stat> {
+        let mut total = Stat {
+            key_num: 0,
+            data_file_num: 0,
+            reclaim_size: 0,
+            disk_size: 0,
+            index_key_num: 0,
+            index_memory_usage: 0,
+        };
+        for (_, stat) in self.tenant_stats()? {
+            total.key_num += stat.key_num;
+            total.data_file_num += stat.data_file_num;
+            total.reclaim_size += stat.reclaim_size;
+            total.disk_size += stat.disk_size;
+            total.index_key_num += stat.index_key_num;
+            total.index_memory_usage += stat.index_memory_usage;
+        }
+        Ok(total)
+    }
+
+    /// 停止后台 merge 并关闭所有的租户，重复关闭直接返回
+    pub fn close(&self) -> Result<()> {
+        if let Some(merge_worker) = self.merge_worker.lock().take() {
+            merge_worker.stop();
+        }
+
+        let engines: Vec<Arc<Engine>> = self
+            .inner
+            .engines
+            .lock()
+            .drain()
+            .map(|(_, engine)| engine)
+            .collect();
+        for engine in engines {
+            engine.close()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for EngineManager {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            error!("error whiling close engine manager: {}", e);
+        }
+    }
+}
+
+impl ManagerInner {
+    // 租户使用共用的配置，数据目录位于根目录中以租户命名的子目录中
+    fn tenant_options(&self, tenant: &str) -> Options {
+        let mut options = self.options.engine_options.clone();
+        options.dir_path = self
+            .options
+            .root_dir
+            .join(tenant)
+            .join(TENANT_DATA_DIR_NAME);
+        options
+    }
+
+    // 已经打开的租户，merge 和统计时不持有锁，避免阻塞其他租户的打开
+    fn opened_engines(&self) -> Vec<(String, Arc<Engine>)> {
+        let mut engines: Vec<(String, Arc<Engine>)> = self
+            .engines
+            .lock()
+            .iter()
+            .map(|(tenant, engine)| (tenant.clone(), engine.clone()))
+            .collect();
+        engines.sort_by(|a, b| a.0.cmp(&b.0));
+        engines
+    }
+
+    fn merge_all(&self) -> Result<Vec<String>> {
+        let mut merged = Vec::new();
+        for (tenant, engine) in self.opened_engines() {
+            match engine.merge() {
+                Ok(_) => merged.push(tenant),
+                // 已经被关闭的租户也直接跳过
+                Err(Errors::MergeRatioUnreached)
+                | Err(Errors::MergeInProgress)
+                | Err(Errors::DatabaseClosed) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(merged)
+    }
+}
+
+// 租户名称作为子目录的名称，不能为空，也不能包含路径分隔符
+fn check_tenant_name(tenant: &str) -> Result<()> {
+    if tenant.is_empty() || tenant == "." || tenant == ".." || tenant.contains(['/', '\\']) {
+        return Err(Errors::InvaildTenantName);
+    }
+    Ok(())
+}
+
+// 按照固定的时间间隔对所有租户进行 merge，停止时丢弃 sender 通知线程退出
+struct MergeWorker {
+    sender: mpsc::Sender<()>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl MergeWorker {
+    fn start(inner: Arc<ManagerInner>, interval: Duration) -> Self {
+        let (sender, receiver) = mpsc::channel::<()>();
+        let handle = thread::spawn(move || {
+            while let Err(mpsc::RecvTimeoutError::Timeout) = receiver.recv_timeout(interval) {
+                if let Err(e) = inner.merge_all() {
+                    error!("failed to merge tenants: {}", e);
+                }
+            }
+        });
+        Self { sender, handle }
+    }
+
+    fn stop(self) {
+        drop(self.sender);
+        if self.handle.join().is_err() {
+            error!("merge worker thread panicked");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::util::rand_kv::{get_test_key, get_test_value};
+
+    #[test]
+    fn test_engine_manager() {
+        let mut options = ManagerOptions::default();
+        options.root_dir = PathBuf::from("/tmp/bitcask-rs-manager");
+        options.engine_options.data_file_size = 64 * 1024;
+        options.engine_options.data_file_merge_ratio = 0.0;
+        let manager = EngineManager::open(options.clone()).expect("failed to open manager");
+
+        // 租户之间相互隔离
+        let engine_a = manager.engine("a").unwrap();
+        let engine_b = manager.engine("b").unwrap();
+        assert!(Arc::ptr_eq(&engine_a, &manager.engine("a").unwrap()));
+        for i in 0..1000 {
+            assert!(engine_a.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        for i in 0..100 {
+            assert!(engine_b.put(get_test_key(i), Bytes::from("b")).is_ok());
+        }
+        assert_eq!(engine_b.get(get_test_key(1)).unwrap(), Bytes::from("b"));
+        assert_eq!(
+            engine_b.get(get_test_key(500)).err().unwrap(),
+            Errors::KeyNotFound
+        );
+
+        assert_eq!(manager.tenants().unwrap(), vec!["a", "b"]);
+        let stat = manager.stat().unwrap();
+        assert_eq!(stat.key_num, 1100);
+        let tenant_stats = manager.tenant_stats().unwrap();
+        assert_eq!(tenant_stats[0].0, "a");
+        assert_eq!(tenant_stats[0].1.key_num, 1000);
+        assert_eq!(tenant_stats[1].1.key_num, 100);
+
+        for i in 0..500 {
+            assert!(engine_a.delete(get_test_key(i)).is_ok());
+        }
+        assert!(manager.merge_all().unwrap().contains(&"a".to_string()));
+
+        for name in ["", ".", "..", "a/b"] {
+            assert_eq!(
+                manager.engine(name).err().unwrap(),
+                Errors::InvaildTenantName
+            );
+        }
+
+        // 关闭之后重新打开
+        assert!(manager.close_tenant("a").is_ok());
+        assert_eq!(engine_a.stat().err().unwrap(), Errors::DatabaseClosed);
+        let engine_a = manager.engine("a").unwrap();
+        assert_eq!(engine_a.list_keys().unwrap().len(), 500);
+
+        assert!(manager.remove_tenant("b").is_ok());
+        assert_eq!(manager.tenants().unwrap(), vec!["a"]);
+
+        std::mem::drop(engine_a);
+        std::mem::drop(manager);
+        std::fs::remove_dir_all(options.root_dir).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_engine_manager_background_merge() {
+        let mut options = ManagerOptions::default();
+        options.root_dir = PathBuf::from("/tmp/bitcask-rs-manager-merge");
+        options.engine_options.data_file_merge_ratio = 0.0;
+        options.merge_interval = Some(Duration::from_millis(20));
+        let manager = EngineManager::open(options.clone()).expect("failed to open manager");
+
+        let engine = manager.engine("a").unwrap();
+        for i in 0..100 {
+            assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        for i in 0..50 {
+            assert!(engine.delete(get_test_key(i)).is_ok());
+        }
+
+        let mut merged = false;
+        for _ in 0..250 {
+            if !engine.merge_history().is_empty() {
+                merged = true;
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert!(merged);
+
+        std::mem::drop(engine);
+        std::mem::drop(manager);
+        std::fs::remove_dir_all(options.root_dir).expect("failed to remove path");
+    }
+}
@@ -0,0 +1,530 @@
+//! MANIFEST 文件记录数据目录中有哪些数据文件，以及写满的数据文件的元数据
+//! 新建、写满、删除数据文件时追加一条变更记录，记录和数据文件一样带有 crc 校验，
+//! 启动时重放变更记录得到数据文件列表，不再依赖扫描数据目录，并以此检查数据目录中多出或者缺少的数据文件，
+//! 每次启动之后重新生成只包含当前数据文件的 manifest，避免变更记录无限增长
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use log::{error, warn};
+use parking_lot::{Mutex, RwLock};
+use prost::{
+    decode_length_delimiter,
+    encoding::{decode_varint, encode_varint},
+};
+
+use crate::{
+    data::{
+        cipher::Cipher,
+        data_file::{
+            get_data_file_name, DataFile, DATA_FILE_NAME_SUFFIX, MANIFEST_FILE_NAME,
+            MANIFEST_TMP_FILE_NAME,
+        },
+        log_record::{
+            LogRecord, LogRecordType, LOG_RECORD_TIMESTAMP_FLAG, LOG_RECORD_VALUE_POINTER_FLAG,
+        },
+    },
+    db::{push_loaded_data_file, sync_dir_if_enabled, Engine},
+    errors::{Errors, Result},
+    fio::{data_file_exists, file_cache::FileCache},
+    options::IOType,
+};
+
+// 变更记录的类型，存放在记录的 key 中
+const CREATE_FILE_EDIT: u8 = 1;
+const SEAL_FILE_EDIT: u8 = 2;
+const DELETE_FILE_EDIT: u8 = 3;
+
+/// 数据文件的元数据，写入活跃文件时统计，活跃文件写满之后记录到 manifest 中
+/// 标识事务结束的记录只计入记录数量，加密的数据文件中的 key 是随机数，不统计 key 的范围
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct FileMeta {
+    pub(crate) record_count: u64,
+    pub(crate) min_key: Vec<u8>, // 按照字节序最小的 key，为空表示没有统计
+    pub(crate) max_key: Vec<u8>,
+    pub(crate) min_timestamp: u64, // 记录的写入时间范围，单位毫秒，为 0 表示没有带写入时间的记录
+    pub(crate) max_timestamp: u64,
+    pub(crate) untimed_records: u64, // 没有写入时间的记录数量，标识事务结束的记录除外
+}
+
+impl FileMeta {
+    /// 统计一条记录，key 为去掉事务序列号之后的 key，为空时不统计 key 的范围
+    pub(crate) fn add_record(
+        &mut self,
+        rec_type: LogRecordType,
+        timestamp: u64,
+        key: Option<&[u8]>,
+    ) {
+        self.record_count += 1;
+        if rec_type == LogRecordType::TxnFinished {
+            return;
+        }
+
+        if timestamp == 0 {
+            self.untimed_records += 1;
+        } else {
+            if self.min_timestamp == 0 || timestamp < self.min_timestamp {
+                self.min_timestamp = timestamp;
+            }
+            self.max_timestamp = self.max_timestamp.max(timestamp);
+        }
+
+        if let Some(key) = key {
+            if self.min_key.is_empty() || key < self.min_key.as_slice() {
+                self.min_key = key.to_vec();
+            }
+            if key > self.max_key.as_slice() {
+                self.max_key = key.to_vec();
+            }
+        }
+    }
+
+    /// 统计一条由 LogRecord::encode 编码之后的记录，只解析 header 和 key，不校验 crc
+    pub(crate) fn add_encoded_record(&mut self, mut buf: &[u8], track_keys: bool) {
+        let (type_byte, rest) = match buf.split_first() {
+            Some((type_byte, rest)) => (*type_byte, rest),
+            None => return,
+        };
+        buf = rest;
+        // 类型无法识别的记录已经损坏，不参与统计
+        let rec_type = match LogRecordType::from_u8(
+            type_byte & !(LOG_RECORD_TIMESTAMP_FLAG | LOG_RECORD_VALUE_POINTER_FLAG),
+        ) {
+            Ok(rec_type) => rec_type,
+            Err(_) => return,
+        };
+        let timestamp = match type_byte & LOG_RECORD_TIMESTAMP_FLAG != 0 {
+            true => decode_varint(&mut buf).unwrap_or_default(),
+            false => 0,
+        };
+        let key = match track_keys {
+            true => decode_record_key(buf),
+            false => None,
+        };
+        self.add_record(rec_type, timestamp, key);
+    }
+
+    /// 所有记录都带有写入时间，并且都早于 timestamp
+    pub(crate) fn older_than(&self, timestamp: u64) -> bool {
+        self.untimed_records == 0 && self.max_timestamp > 0 && self.max_timestamp < timestamp
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        encode_varint(self.record_count, buf);
+        encode_varint(self.min_key.len() as u64, buf);
+        buf.extend_from_slice(&self.min_key);
+        encode_varint(self.max_key.len() as u64, buf);
+        buf.extend_from_slice(&self.max_key);
+        encode_varint(self.min_timestamp, buf);
+        encode_varint(self.max_timestamp, buf);
+        encode_varint(self.untimed_records, buf);
+    }
+
+    fn decode(buf: &mut &[u8]) -> Option<Self> {
+        let record_count = decode_varint(buf).ok()?;
+        let min_key = decode_bytes(buf)?;
+        let max_key = decode_bytes(buf)?;
+        Some(Self {
+            record_count,
+            min_key,
+            max_key,
+            min_timestamp: decode_varint(buf).ok()?,
+            max_timestamp: decode_varint(buf).ok()?,
+            untimed_records: decode_varint(buf).ok()?,
+        })
+    }
+}
+
+// 从编码之后的记录中 key size 开始的位置取出 key，并去掉 key 前面的事务序列号
+fn decode_record_key(mut buf: &[u8]) -> Option<&[u8]> {
+    let key_size = decode_length_delimiter(&mut buf).ok()?;
+    decode_length_delimiter(&mut buf).ok()?;
+    let mut key = buf.get(..key_size)?;
+    decode_varint(&mut key).ok()?;
+    Some(key)
+}
+
+fn decode_bytes(buf: &mut &[u8]) -> Option<Vec<u8>> {
+    let len = decode_varint(buf).ok()? as usize;
+    let bytes = buf.get(..len)?.to_vec();
+    *buf = &buf[len..];
+    Some(bytes)
+}
+
+/// manifest 中的一条变更记录
+#[derive(Debug, PartialEq)]
+enum ManifestEdit {
+    /// 新建了数据文件，在创建文件之前写入
+    Create(u64),
+
+    /// 数据文件已经写满，不会再写入，元数据未知（例如启动时没有扫描活跃文件）时为空
+    Seal(u64, Option<FileMeta>),
+
+    /// 删除了数据文件，在删除文件之前写入
+    Delete(u64),
+}
+
+impl ManifestEdit {
+    fn encode(&self) -> Vec<u8> {
+        let (edit_type, file_id) = match self {
+            ManifestEdit::Create(file_id) => (CREATE_FILE_EDIT, file_id),
+            ManifestEdit::Seal(file_id, _) => (SEAL_FILE_EDIT, file_id),
+            ManifestEdit::Delete(file_id) => (DELETE_FILE_EDIT, file_id),
+        };
+        let mut value = Vec::new();
+        encode_varint(*file_id, &mut value);
+        if let ManifestEdit::Seal(_, Some(meta)) = self {
+            meta.encode(&mut value);
+        }
+        LogRecord {
+            key: vec![edit_type],
+            value,
+            rec_type: LogRecordType::NORMAL,
+            timestamp: 0,
+            value_pointer: false,
+        }
+        .encode()
+    }
+
+    fn decode(record: &LogRecord) -> Option<Self> {
+        let mut buf = record.value.as_slice();
+        let file_id = decode_varint(&mut buf).ok()?;
+        let edit = match record.key.as_slice() {
+            [CREATE_FILE_EDIT] => ManifestEdit::Create(file_id),
+            [SEAL_FILE_EDIT] if buf.is_empty() => ManifestEdit::Seal(file_id, None),
+            [SEAL_FILE_EDIT] => ManifestEdit::Seal(file_id, Some(FileMeta::decode(&mut buf)?)),
+            [DELETE_FILE_EDIT] => ManifestEdit::Delete(file_id),
+            _ => return None,
+        };
+        if !buf.is_empty() {
+            return None;
+        }
+        Some(edit)
+    }
+}
+
+/// 数据目录中的数据文件，值为写满的数据文件的元数据，活跃文件以及元数据未知的数据文件为 None
+pub(crate) type ManifestFiles = BTreeMap<u64, Option<FileMeta>>;
+
+/// 运行期间维护的 manifest，变更记录写入之后立即持久化
+pub(crate) struct Manifest {
+    file: Mutex<Option<DataFile>>, // 追加写入变更记录的文件，只读模式下为空
+    files: RwLock<ManifestFiles>,  // 当前的数据文件列表
+}
+
+impl Manifest {
+    /// 重放 manifest 文件中的变更记录，文件不存在时返回 None
+    /// 末尾没有完整写入的记录（写入时崩溃）被忽略，之前的变更都已经生效
+    pub(crate) fn read(dir_path: &Path) -> Result<Option<ManifestFiles>> {
+        let manifest_path = dir_path.join(MANIFEST_FILE_NAME);
+        if !manifest_path.is_file() {
+            return Ok(None);
+        }
+
+        let manifest_file = DataFile::open_manifest_file(manifest_path)?;
+        let mut files = ManifestFiles::new();
+        let mut offset = 0;
+        loop {
+            let (record, size) = match manifest_file.read_log_record(offset) {
+                Ok(result) => (result.record, result.size),
+                Err(Errors::ReadDataFileEof) => break,
+                Err(Errors::InvaildLogRecordCrc) | Err(Errors::InvalidRecordType(_)) => {
+                    warn!("manifest is truncated at offset {}", offset);
+                    break;
+                }
+                Err(e) => return Err(e),
+            };
+            match ManifestEdit::decode(&record) {
+                Some(ManifestEdit::Create(file_id)) => {
+                    files.insert(file_id, None);
+                }
+                Some(ManifestEdit::Seal(file_id, meta)) => {
+                    if let Some(entry) = files.get_mut(&file_id) {
+                        *entry = meta;
+                    }
+                }
+                Some(ManifestEdit::Delete(file_id)) => {
+                    files.remove(&file_id);
+                }
+                None => return Err(Errors::ManifestCorrupted),
+            }
+            offset += size as u64;
+        }
+        Ok(Some(files))
+    }
+
+    /// 使用当前的数据文件列表生成新的 manifest，先写入临时文件再替换原来的文件
+    /// 只读模式下不改动数据目录，只在内存中维护
+    pub(crate) fn create(
+        dir_path: PathBuf,
+        files: ManifestFiles,
+        read_only: bool,
+        fsync_dir: bool,
+    ) -> Result<Self> {
+        let mut file = None;
+        if !read_only {
+            let tmp_path = dir_path.join(MANIFEST_TMP_FILE_NAME);
+            if tmp_path.is_file() {
+                fs::remove_file(&tmp_path).map_err(|e| manifest_error(&tmp_path, e))?;
+            }
+            let tmp_file = DataFile::open_manifest_file(tmp_path.clone())?;
+            let mut buf = Vec::new();
+            for (file_id, meta) in files.iter() {
+                buf.extend(ManifestEdit::Create(*file_id).encode());
+                if meta.is_some() {
+                    buf.extend(ManifestEdit::Seal(*file_id, meta.clone()).encode());
+                }
+            }
+            tmp_file.write(&buf)?;
+            tmp_file.sync()?;
+            drop(tmp_file);
+
+            let manifest_path = dir_path.join(MANIFEST_FILE_NAME);
+            fs::rename(&tmp_path, &manifest_path).map_err(|e| manifest_error(&manifest_path, e))?;
+            sync_dir_if_enabled(fsync_dir, &dir_path)?;
+            let manifest_file = DataFile::open_manifest_file(manifest_path)?;
+            manifest_file.set_write_off(manifest_file.file_size());
+            file = Some(manifest_file);
+        }
+
+        Ok(Self {
+            file: Mutex::new(file),
+            files: RwLock::new(files),
+        })
+    }
+
+    /// 写满的数据文件的元数据，活跃文件以及元数据未知的数据文件返回 None
+    pub(crate) fn file_meta(&self, file_id: u64) -> Option<FileMeta> {
+        self.files.read().get(&file_id).cloned().flatten()
+    }
+
+    pub(crate) fn create_file(&self, file_id: u64) -> Result<()> {
+        self.append(ManifestEdit::Create(file_id))?;
+        self.files.write().insert(file_id, None);
+        Ok(())
+    }
+
+    pub(crate) fn seal_file(&self, file_id: u64, meta: Option<FileMeta>) -> Result<()> {
+        self.append(ManifestEdit::Seal(file_id, meta.clone()))?;
+        if let Some(entry) = self.files.write().get_mut(&file_id) {
+            *entry = meta;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn delete_file(&self, file_id: u64) -> Result<()> {
+        self.append(ManifestEdit::Delete(file_id))?;
+        self.files.write().remove(&file_id);
+        Ok(())
+    }
+
+    fn append(&self, edit: ManifestEdit) -> Result<()> {
+        let file = self.file.lock();
+        match file.as_ref() {
+            Some(file) => {
+                file.write(&edit.encode())?;
+                file.sync()
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+fn manifest_error(path: &Path, e: std::io::Error) -> Errors {
+    error!("failed to write manifest: {}", e);
+    Errors::write_failed(path, e)
+}
+
+/// 活跃文件的元数据，写入时更新，活跃文件写满之后记录到 manifest 中
+pub(crate) struct ActiveFileMeta {
+    pub(crate) file_id: u64,
+    pub(crate) meta: FileMeta,
+    // 是否统计了活跃文件中的所有记录，B+ 树索引启动时不扫描活跃文件，统计的元数据不完整
+    pub(crate) complete: bool,
+}
+
+impl ActiveFileMeta {
+    pub(crate) fn new(file_id: u64, complete: bool) -> Self {
+        Self {
+            file_id,
+            meta: FileMeta::default(),
+            complete,
+        }
+    }
+}
+
+/// 按照 manifest 中的数据文件列表打开数据文件
+/// manifest 中有但是数据目录中没有的数据文件说明数据目录已经损坏，只有最新的数据文件可能在创建之前崩溃，
+/// 数据目录中有但是 manifest 中没有的数据文件不会被加载
+pub(crate) fn load_manifest_data_files(
+    dir_path: PathBuf,
+    files: &ManifestFiles,
+    use_mmap_io: bool,
+    cipher: Option<Arc<Cipher>>,
+    file_cache: Option<&Arc<FileCache>>,
+) -> Result<Vec<DataFile>> {
+    let io_type = match use_mmap_io {
+        true => IOType::MemoryMap,
+        false => IOType::StandardFIO,
+    };
+    let last_file_id = files.keys().last().copied();
+    let mut data_files = Vec::new();
+    for file_id in files.keys() {
+        if !data_file_exists(&get_data_file_name(dir_path.clone(), *file_id)) {
+            if Some(*file_id) == last_file_id {
+                continue;
+            }
+            return Err(Errors::DataFileMissing { file_id: *file_id });
+        }
+        let data_file = DataFile::new(dir_path.clone(), *file_id, io_type, cipher.clone())?;
+        push_loaded_data_file(&mut data_files, data_file, &dir_path, file_cache);
+    }
+
+    // 检查数据目录中多出来的数据文件，例如删除之前崩溃或者从其他地方拷贝进来的文件
+    if let Ok(dir) = fs::read_dir(&dir_path) {
+        for entry in dir.flatten() {
+            let file_name = entry.file_name();
+            let file_id = file_name
+                .to_str()
+                .and_then(|name| name.strip_suffix(DATA_FILE_NAME_SUFFIX))
+                .and_then(|id| id.parse::<u64>().ok());
+            if let Some(file_id) = file_id {
+                if !files.contains_key(&file_id) {
+                    warn!("data file {} is not in the manifest, ignore it", file_id);
+                }
+            }
+        }
+    }
+
+    Ok(data_files)
+}
+
+impl Engine {
+    /// 切换活跃文件之前调用，先在 manifest 中记录旧的活跃文件已经写满以及它的元数据，
+    /// 再登记安装的数据文件和新的活跃文件，之后才能创建这些文件
+    pub(crate) fn record_rotation(
+        &self,
+        active_meta: &mut ActiveFileMeta,
+        installed_file_ids: &[u64],
+        new_file_id: u64,
+    ) -> Result<()> {
+        let meta = match active_meta.complete {
+            true => Some(active_meta.meta.clone()),
+            false => None,
+        };
+        self.manifest.seal_file(active_meta.file_id, meta)?;
+        for file_id in installed_file_ids {
+            self.manifest.create_file(*file_id)?;
+            self.manifest.seal_file(*file_id, None)?;
+        }
+        self.manifest.create_file(new_file_id)?;
+        *active_meta = ActiveFileMeta::new(new_file_id, true);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        options::{FileRotation, Options},
+        util::rand_kv::{get_test_key, get_test_value},
+    };
+
+    #[test]
+    fn test_manifest_edit_encode() {
+        let mut meta = FileMeta::default();
+        meta.add_record(LogRecordType::NORMAL, 200, Some(b"b".as_slice()));
+        meta.add_record(LogRecordType::DELETE, 100, Some(b"c".as_slice()));
+        meta.add_record(LogRecordType::NORMAL, 0, Some(b"a".as_slice()));
+        meta.add_record(LogRecordType::TxnFinished, 0, None);
+        assert_eq!(meta.record_count, 4);
+        assert_eq!(meta.min_key, b"a".to_vec());
+        assert_eq!(meta.max_key, b"c".to_vec());
+        assert_eq!((meta.min_timestamp, meta.max_timestamp), (100, 200));
+        assert_eq!(meta.untimed_records, 1);
+        assert!(!meta.older_than(300));
+
+        let edits = [
+            ManifestEdit::Create(1),
+            ManifestEdit::Seal(1, Some(meta)),
+            ManifestEdit::Seal(2, None),
+            ManifestEdit::Delete(1),
+        ];
+        for edit in edits {
+            let enc = edit.encode();
+            let record = LogRecord {
+                key: enc[3..4].to_vec(),
+                value: enc[4..enc.len() - 4].to_vec(),
+                rec_type: LogRecordType::NORMAL,
+                timestamp: 0,
+                value_pointer: false,
+            };
+            assert_eq!(ManifestEdit::decode(&record), Some(edit));
+        }
+    }
+
+    #[test]
+    fn test_manifest() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-manifest");
+        opts.data_file_size = 64 * 1024 * 1024;
+        opts.file_rotation = FileRotation {
+            max_records: Some(100),
+            max_age: None,
+        };
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..250 {
+            assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+
+        // 写满的数据文件带有写入时统计的元数据，活跃文件没有元数据
+        let files = Manifest::read(&opts.dir_path).unwrap().unwrap();
+        assert_eq!(files.keys().copied().collect::<Vec<_>>(), vec![0, 1, 2]);
+        let meta = files.get(&1).unwrap().clone().unwrap();
+        assert_eq!(meta.record_count, 100);
+        assert_eq!(meta.min_key, get_test_key(100).to_vec());
+        assert_eq!(meta.max_key, get_test_key(199).to_vec());
+        assert!(meta.min_timestamp > 0 && meta.min_timestamp <= meta.max_timestamp);
+        assert_eq!(meta.untimed_records, 0);
+        assert_eq!(files.get(&2).unwrap(), &None);
+
+        // 重启之后活跃文件的元数据从数据文件中统计
+        std::mem::drop(engine);
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 250..300 {
+            assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        assert!(engine.put(get_test_key(300), get_test_value(300)).is_ok());
+        let meta = engine.manifest.file_meta(2).unwrap();
+        assert_eq!(meta.record_count, 100);
+        assert_eq!(meta.min_key, get_test_key(200).to_vec());
+        assert_eq!(meta.max_key, get_test_key(299).to_vec());
+
+        // 数据目录中多出来的数据文件不会被加载
+        std::mem::drop(engine);
+        fs::copy(
+            get_data_file_name(opts.dir_path.clone(), 0),
+            get_data_file_name(opts.dir_path.clone(), 100),
+        )
+        .unwrap();
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine.stat().unwrap().data_file_num, 4);
+        assert_eq!(engine.list_keys().unwrap().len(), 301);
+        assert!(engine.put(get_test_key(301), get_test_value(301)).is_ok());
+
+        // manifest 中的数据文件缺失
+        std::mem::drop(engine);
+        fs::remove_file(get_data_file_name(opts.dir_path.clone(), 1)).unwrap();
+        assert_eq!(
+            Engine::open(opts.clone()).err().unwrap(),
+            Errors::DataFileMissing { file_id: 1 }
+        );
+
+        fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+}
@@ -1,43 +1,160 @@
-use std::{fs, path::PathBuf, sync::atomic::Ordering};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
 
-use log::error;
+use bytes::Bytes;
+use log::{error, warn};
+use parking_lot::Mutex;
 
 use crate::{
     batch::{log_record_key_with_seq, parse_log_record_key, NON_TRANSACTION_SEQ_NO},
     data::{
+        bloom::{bloom_hash, write_bloom_file, BloomFilter},
+        cipher::Cipher,
         data_file::{
-            get_data_file_name, DataFile, HINT_FILE_NAME, MERGE_FIN_FILE_NAME, SEQ_NO_FILE_NAME,
+            get_bloom_file_name, get_data_file_name, write_record_file, DataFile, HINT_FILE_NAME,
+            KEY_CHECK_FILE_NAME, MANIFEST_FILE_NAME, MERGE_FIN_FILE_NAME, MVCC_VERSION_FILE_NAME,
+            SEQ_NO_FILE_NAME, STATS_FILE_NAME,
         },
-        log_record::{decode_log_record_pos, LogRecord, LogRecordType},
+        log_record::{
+            current_timestamp, decode_log_record_pos, LogRecord, LogRecordPos, LogRecordType,
+            TransactionRecord,
+        },
+    },
+    db::{
+        preallocate_if_enabled, sync_dir_if_enabled, Engine, MergeReport, FILE_LOCK_NAME,
+        INITIAL_FILE_ID, MERGE_HISTORY_SIZE,
     },
-    db::{Engine, FILE_LOCK_NAME},
     errors::{Errors, Result},
-    options::{IOType, Options},
+    options::{IOType, IndexType, IteratorOptions, Options},
     util,
+    value_log::{remove_unused_blob_files, write_blob_gc_file},
 };
 
+#[cfg(feature = "object-store")]
+use crate::fio::object_store;
+
 const MERGE_DIR_NAME: &'static str = "merge";
-const MERGE_FIN_KEY: &[u8] = "merge.finished".as_bytes();
+const HINT_DIR_NAME: &str = "hint";
+// 安装 merge 结果时，merge 生成的文件在数据目录中的临时文件名后缀
+pub(crate) const MERGE_TEMP_FILE_SUFFIX: &str = ".merge";
+// 安装 merge 结果时标识 merge 完成的文件：已经提交但还未删除旧数据文件，以及旧数据文件已经删除
+pub(crate) const MERGE_FIN_COMMITTED_NAME: &str = "merge-fin.committed";
+pub(crate) const MERGE_FIN_CLEANED_NAME: &str = "merge-fin.cleaned";
+pub(crate) const MERGE_FIN_KEY: &[u8] = "merge.finished".as_bytes();
+
+/// 用于在其他线程中查看 merge 的进度或者取消 merge
+#[derive(Default)]
+pub struct MergeHandle {
+    pub(crate) processed_bytes: AtomicU64,
+    pub(crate) total_bytes: AtomicU64,
+    pub(crate) records_dropped: AtomicU64,
+    pub(crate) current_file_id: Mutex<Option<u64>>,
+    cancelled: AtomicBool,
+}
+
+impl MergeHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 获取当前的进度
+    pub fn progress(&self) -> MergeProgress {
+        MergeProgress {
+            processed_bytes: self.processed_bytes.load(Ordering::SeqCst),
+            total_bytes: self.total_bytes.load(Ordering::SeqCst),
+            current_file_id: *self.current_file_id.lock(),
+        }
+    }
+
+    /// 取消 merge，merge 在处理下一条记录之前返回 MergeCancelled
+    /// 未完成的临时 merge 目录会在下一次打开数据库时清理
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// merge 的进度
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MergeProgress {
+    /// 已经扫描的数据量
+    pub processed_bytes: u64,
+    /// 参与 merge 的数据文件总大小
+    pub total_bytes: u64,
+    /// 最近开始处理的数据文件 id
+    pub current_file_id: Option<u64>,
+}
 
 impl Engine {
-    // merge 数据目录，处理无效数据，并生成 hint 索引文件
-    pub fn merge(&self) -> Result<()> {
+    // merge 数据目录，处理无效数据，并生成 hint 索引文件，返回这次 merge 的结果
+    pub fn merge(&self) -> Result<MergeReport> {
+        self.merge_with_handle(&MergeHandle::new())
+    }
+
+    /// 和 merge 相同，可以通过 handle 在其他线程中查看进度或者取消
+    pub fn merge_with_handle(&self, merge_handle: &MergeHandle) -> Result<MergeReport> {
+        let report = self.do_merge(merge_handle)?;
+        // 没有数据时不会进行 merge，不需要记录
+        if report.files_merged > 0 {
+            #[cfg(feature = "object-store")]
+            self.upload_after_merge();
+
+            let mut history = self.merge_history.lock();
+            if history.len() >= MERGE_HISTORY_SIZE {
+                history.pop_front();
+            }
+            history.push_back(report.clone());
+        }
+        Ok(report)
+    }
+
+    /// 最近几次 merge 的结果，按照时间从早到晚排列，只保存在内存中，重启之后清空
+    pub fn merge_history(&self) -> Vec<MergeReport> {
+        self.merge_history.lock().iter().cloned().collect()
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "merge", skip_all))]
+    fn do_merge(&self, merge_handle: &MergeHandle) -> Result<MergeReport> {
+        let start = Instant::now();
+        self.check_closed()?;
+        if self.options.read_only {
+            return Err(Errors::DatabaseIsReadOnly);
+        }
+
         // 如果正在 merge，则直接返回
         let lock = self.merging_lock.try_lock();
         if lock.is_none() {
             return Err(Errors::MergeInProgress);
         }
 
+        // 清理 MVCC 事务中不再可见的旧版本数据
+        self.gc_mvcc_versions()?;
+
         // 判断是否达到 merge 阈值
         let reclaim_size = self.reclaim_size.load(Ordering::SeqCst);
         let total_size = util::file::dir_disk_size(self.options.dir_path.clone());
+        // 已经上传到对象存储并且删除了本地文件的数据文件也需要计算在内
+        #[cfg(feature = "object-store")]
+        let total_size = total_size + self.archived_data_size();
 
         // 没有数据不需要进行 merge
         if total_size <= 0 {
-            return Ok(());
+            return Ok(MergeReport::default());
         }
 
-        if (reclaim_size as f32 / total_size as f32) < self.options.data_file_merge_ratio {
+        let merge_ratio = self.runtime_options.data_file_merge_ratio();
+        if (reclaim_size as f32 / total_size as f32) < merge_ratio {
             return Err(Errors::MergeRatioUnreached);
         }
 
@@ -50,7 +167,10 @@ impl Engine {
         let merge_path = get_merge_path(self.options.dir_path.clone());
         // 如果目录已经存在，则先删除
         if merge_path.is_dir() {
-            fs::remove_dir_all(merge_path.clone()).unwrap();
+            if let Err(e) = fs::remove_dir_all(merge_path.clone()) {
+                error!("failed to remove merge path {}", e);
+                return Err(Errors::FailedToRemoveDataFile);
+            }
         }
 
         // 创建 merge 数据目录
@@ -59,95 +179,588 @@ impl Engine {
             return Err(Errors::FailedToCreateDatabaseDir);
         }
 
-        // 获取所有需要进行 merge 的数据文件
-        let merge_files = self.ratate_merge_file()?;
-
-        // 打开临时用于 merge 的 bitcask 实例
-        let mut merge_db_opts = Options::default();
-        merge_db_opts.dir_path = merge_path.clone();
-        merge_db_opts.data_file_size = self.options.data_file_size;
-        let merge_db = Engine::open(merge_db_opts)?;
+        // 获取所有需要进行 merge 的数据文件，同时封存当前的 blob 文件，
+        // 之后写入的大 value 都在新的 blob 文件中
+        let (merge_files, blob_file_ids) = {
+            let _rotate_lock = self.value_log.rotate_lock.write();
+            let blob_file_ids = self.value_log.rotate()?;
+            (self.ratate_merge_file()?, blob_file_ids)
+        };
+        let total_bytes = merge_files.iter().map(|f| f.file_size()).sum();
+        merge_handle
+            .total_bytes
+            .store(total_bytes, Ordering::SeqCst);
+        merge_handle.processed_bytes.store(0, Ordering::SeqCst);
+        merge_handle.records_dropped.store(0, Ordering::SeqCst);
+
+        #[cfg(feature = "tracing")]
+        let rewrite_span = tracing::info_span!(
+            "merge_rewrite",
+            files = merge_files.len(),
+            bytes = total_bytes
+        )
+        .entered();
+
+        // 打开 merge 目录中的数据文件写入器
+        let mut merge_writer =
+            MergeWriter::new(merge_path.clone(), &self.options(), self.cipher.clone())?;
 
         // 打开 hint 文件存储索引
-        let hint_file = DataFile::new_hint_file(merge_path.clone())?;
-        // 依次处理每个数据文件，重写有效的数据
-        for data_file in merge_files.iter() {
-            let mut offset = 0;
+        let hint_file = DataFile::new_hint_file(merge_path.clone(), self.cipher.clone())?;
+        // 有效数据引用的 blob 文件，其余封存的 blob 文件在 merge 之后可以删除
+        let mut blob_refs = HashSet::new();
+        if self.options.merge_threads > 1 && merge_files.len() > 1 {
+            // 多个线程并行扫描数据文件，按照文件顺序统一写入
+            self.rewrite_files_parallel(
+                &merge_files,
+                &mut merge_writer,
+                &hint_file,
+                merge_handle,
+                &mut blob_refs,
+            )?;
+        } else {
+            // 依次处理每个数据文件，重写有效的数据
+            for data_file in merge_files.iter() {
+                self.scan_valid_records(data_file, merge_handle, |real_key, mut log_record| {
+                    add_blob_reference(&mut blob_refs, &log_record)?;
+                    let log_record_pos = merge_writer.append(&mut log_record)?;
+                    // 写 hint 索引，保留的删除记录不需要写入
+                    match log_record.rec_type {
+                        LogRecordType::NORMAL => {
+                            hint_file.write_hint_record(real_key, log_record_pos)
+                        }
+                        _ => Ok(()),
+                    }
+                })?;
+            }
+        }
+
+        #[cfg(feature = "tracing")]
+        drop(rewrite_span);
+
+        // sync 保证持久化
+        #[cfg(feature = "tracing")]
+        let _sync_span = tracing::info_span!("merge_sync").entered();
+        merge_writer.sync()?;
+        hint_file.sync()?;
+
+        // 记录不再被引用的 blob 文件，和 merge 的结果一起安装
+        write_blob_gc_file(merge_path.clone(), &blob_file_ids, &blob_refs)?;
+
+        // 拿到最近未参与 merge 的文件 id
+        let non_merge_file_id = merge_files.last().unwrap().get_file_id() + 1;
+        // merge 完成文件必须在其余文件持久化之后写入
+        sync_dir_if_enabled(self.options.fsync_dir, &merge_path)?;
+        write_merge_fin_file(
+            merge_path.clone(),
+            non_merge_file_id,
+            self.options.fsync_dir,
+        )?;
+
+        let report = MergeReport {
+            files_merged: merge_files.len(),
+            bytes_before: total_bytes,
+            bytes_after: merge_writer.total_size(),
+            records_dropped: merge_handle.records_dropped.load(Ordering::SeqCst),
+            duration: start.elapsed(),
+        };
+        #[cfg(feature = "tracing")]
+        tracing::info!(
+            files_merged = report.files_merged,
+            bytes_before = report.bytes_before,
+            bytes_after = report.bytes_after,
+            records_dropped = report.records_dropped,
+            duration_ms = report.duration.as_millis() as u64,
+            "merge finished"
+        );
+        Ok(report)
+    }
+
+    /// 只整理可以回收的数据占比超过 threshold 的旧数据文件，返回被整理的数据文件 id
+    /// 和 merge 不同，文件中的有效数据直接搬移到活跃文件中并更新索引，之后立即删除这些文件，
+    /// 不需要重写其他的数据文件，也不需要重启
+    pub fn merge_files(&self, threshold: f32) -> Result<Vec<u64>> {
+        self.check_closed()?;
+        if self.options.read_only {
+            return Err(Errors::DatabaseIsReadOnly);
+        }
+        if !(0.0..=1.0).contains(&threshold) {
+            return Err(Errors::InvaildDataFileMergeRatio);
+        }
+
+        // 和 merge 互斥
+        let lock = self.merging_lock.try_lock();
+        if lock.is_none() {
+            return Err(Errors::MergeInProgress);
+        }
+
+        // 选出可以回收的数据占比超过阈值的旧数据文件
+        let active_file_id = self.active_file.read().get_file_id();
+        let file_stats = self.file_stats()?;
+        let merge_file_ids: Vec<u64> = file_stats
+            .iter()
+            .filter(|file| file.file_id != active_file_id && file.dead_size > 0)
+            .filter(|file| {
+                let ratio = file.dead_size as f32 / (file.live_size + file.dead_size) as f32;
+                ratio > threshold
+            })
+            .map(|file| file.file_id)
+            .collect();
+        if merge_file_ids.is_empty() {
+            return Ok(merge_file_ids);
+        }
+
+        // 存在 hint 文件、未安装的 merge 结果或者更早的没有被整理的数据文件时，
+        // 删除标记需要保留，否则重启之后更早的数据会重新出现
+        let has_hint = self.options.dir_path.join(HINT_FILE_NAME).is_file()
+            || get_merge_path(self.options.dir_path.clone()).is_dir();
+        for file_id in merge_file_ids.iter() {
+            let keep_tombstones = has_hint
+                || file_stats
+                    .iter()
+                    .any(|file| file.file_id < *file_id && !merge_file_ids.contains(&file.file_id));
+            let data_file = self.merge_data_file(&self.older_files.read(), *file_id)?;
+            self.relocate_valid_records(&data_file, keep_tombstones)?;
+        }
+
+        // 搬移的数据持久化之后再删除旧的数据文件
+        self.sync()?;
+        let mut older_files = self.older_files.write();
+        for file_id in merge_file_ids.iter() {
+            self.manifest.delete_file(*file_id)?;
+            self.remove_older_file(&mut older_files, *file_id)?;
+            #[cfg(feature = "object-store")]
+            if let Some(archive) = &self.archive {
+                archive.remove(*file_id)?;
+            }
+            self.remove_file_stats(*file_id);
+            self.remove_bloom_filter(*file_id)?;
+        }
+        drop(older_files);
+        sync_dir_if_enabled(self.options.fsync_dir, &self.options.dir_path)?;
+
+        #[cfg(feature = "object-store")]
+        {
+            drop(lock);
+            self.upload_after_merge();
+        }
+
+        Ok(merge_file_ids)
+    }
+
+    /// 将 key 的最新版本重写到活跃文件中，之前所有版本占用的空间都可以被回收，
+    /// 用于频繁更新的 key，不需要进行完整的 merge 就可以让旧数据文件中的数据全部失效
+    /// key 不存在或者已经在活跃文件中时返回 false
+    pub fn compact_key(&self, key: Bytes) -> Result<bool> {
+        self.check_closed()?;
+        if self.options.read_only {
+            return Err(Errors::DatabaseIsReadOnly);
+        }
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+        self.relocate_key(key.to_vec())
+    }
+
+    /// 对所有以 prefix 开头的 key 执行 compact_key，返回重写的 key 的数量
+    pub fn compact_prefix(&self, prefix: Bytes) -> Result<usize> {
+        self.check_closed()?;
+        if self.options.read_only {
+            return Err(Errors::DatabaseIsReadOnly);
+        }
+
+        // 先收集 key，重写时不持有索引的迭代器
+        let mut keys = Vec::new();
+        let mut index_iter = self.index.iterator(IteratorOptions {
+            prefix: prefix.to_vec(),
+            reverse: false,
+            ..Default::default()
+        });
+        while let Some((key, _)) = index_iter.next() {
+            keys.push(key.clone());
+        }
+        drop(index_iter);
+
+        let mut count = 0;
+        for key in keys {
+            if self.relocate_key(key)? {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    // 持有 relocate_lock 写锁，将索引指向的记录原样追加写到活跃文件中并更新索引
+    fn relocate_key(&self, key: Vec<u8>) -> Result<bool> {
+        let _relocate_lock = self.relocate_lock.write();
+        let index_pos = match self.index.get(key.clone()) {
+            Some(pos) => pos,
+            None => return Ok(false),
+        };
+        if index_pos.file_id == self.active_file.read().get_file_id() {
+            return Ok(false);
+        }
+
+        // 存放在 blob 文件中的 value 只重写指针，不复制 value
+        let mut log_record = self.read_raw_log_record(&index_pos)?.record;
+        log_record.key = log_record_key_with_seq(&key, NON_TRANSACTION_SEQ_NO);
+        let pos = self.append_log_record(&mut log_record)?;
+        self.index_put(key, pos);
+        Ok(true)
+    }
+
+    // 将数据文件中的有效数据追加写到活跃文件中，每条数据都在持有 relocate_lock 写锁时检查并更新索引
+    fn relocate_valid_records(&self, data_file: &DataFile, keep_tombstones: bool) -> Result<()> {
+        let mut reader = data_file.sequential_reader();
+        let mut offset = data_file.get_header_size();
+        loop {
+            let (mut log_record, size) = match reader.read_log_record(offset) {
+                Ok(result) => (result.record, result.size),
+                Err(e) => {
+                    if e == Errors::ReadDataFileEof {
+                        break;
+                    }
+                    return Err(e);
+                }
+            };
+
+            let (real_key, _) = parse_log_record_key(log_record.key.clone())?;
+            log_record.key = log_record_key_with_seq(&real_key, NON_TRANSACTION_SEQ_NO);
+            let _relocate_lock = self.relocate_lock.write();
+            let index_pos = self.index.get(real_key.clone());
+            match log_record.rec_type {
+                LogRecordType::NORMAL => {
+                    if let Some(index_pos) = index_pos {
+                        if index_pos.file_id == data_file.get_file_id()
+                            && index_pos.offset == offset
+                        {
+                            let pos = self.append_log_record(&mut log_record)?;
+                            self.index_put(real_key, pos);
+                        }
+                    }
+                }
+                LogRecordType::DELETE => {
+                    if (keep_tombstones || self.tombstone_retained(&log_record))
+                        && index_pos.is_none()
+                    {
+                        let pos = self.append_log_record(&mut log_record)?;
+                        self.add_reclaim_size(&pos);
+                    }
+                }
+                LogRecordType::TxnFinished => {}
+            }
+            offset += size as u64;
+        }
+
+        Ok(())
+    }
+
+    /// 为所有旧的数据文件生成 hint 索引文件，不需要先进行 merge，
+    /// 重启时这些数据文件的索引直接从 hint 文件中加载
+    pub fn generate_hint_files(&self) -> Result<()> {
+        self.check_closed()?;
+        if self.options.read_only {
+            return Err(Errors::DatabaseIsReadOnly);
+        }
+
+        // B+ 树索引本身是持久化的，启动时不需要加载索引
+        if self.options.index_type == IndexType::BPTree {
+            return Ok(());
+        }
+
+        // 和 merge 互斥，避免数据文件在生成的过程中被替换
+        let lock = self.merging_lock.try_lock();
+        if lock.is_none() {
+            return Err(Errors::MergeInProgress);
+        }
+
+        // 持有事务提交锁，保证没有正在写入的事务跨越当前的活跃文件
+        let (active_file_id, mut hint_file_ids) = {
+            let _commit_lock = self.batch_commit_lock.lock();
+            let active_file_id = self.active_file.read().get_file_id();
+            let file_ids: Vec<u64> = self.older_files.read().keys().copied().collect();
+            (active_file_id, file_ids)
+        };
+        if hint_file_ids.is_empty() {
+            return Ok(());
+        }
+        hint_file_ids.sort();
+
+        // 依次遍历旧的数据文件，得到每个 key 最新的位置
+        let mut positions = HashMap::new();
+        let mut transaction_records: HashMap<u64, Vec<TransactionRecord>> = HashMap::new();
+        for file_id in hint_file_ids.iter() {
+            let data_file = DataFile::new(
+                self.options.dir_path.clone(),
+                *file_id,
+                self.options.merge_io_type,
+                self.cipher.clone(),
+            )?;
+            let mut reader = data_file.sequential_reader();
+            let mut offset = data_file.get_header_size();
             loop {
-                let (mut log_record, size) = match data_file.read_log_record(offset) {
+                let (mut log_record, size) = match reader.read_log_record(offset) {
                     Ok(result) => (result.record, result.size),
                     Err(e) => {
                         if e == Errors::ReadDataFileEof {
                             break;
                         }
+                        if matches!(
+                            e,
+                            Errors::InvaildLogRecordCrc | Errors::InvalidRecordType(_)
+                        ) {
+                            return Err(Errors::DataFileCorrupted {
+                                file_id: *file_id,
+                                offset,
+                            });
+                        }
                         return Err(e);
                     }
                 };
 
-                // 解码拿到实际的 key
-                let (real_key, _) = parse_log_record_key(log_record.key.clone());
-                if let Some(index_pos) = self.index.get(real_key.clone()) {
-                    // 如果文件 id 和偏移 offset 均相等，则说明是一条有效的数据
-                    if index_pos.file_id == data_file.get_file_id() && index_pos.offset == offset {
-                        // 去除事务的标识
-                        log_record.key =
-                            log_record_key_with_seq(real_key.clone(), NON_TRANSACTION_SEQ_NO);
-                        let log_record_pos = merge_db.append_log_record(&mut log_record)?;
-                        // 写 hint 索引
-                        hint_file.write_hint_record(real_key.clone(), log_record_pos)?;
+                let log_record_pos = LogRecordPos {
+                    file_id: *file_id,
+                    offset,
+                    size: size as u64,
+                };
+                let (real_key, seq_no) =
+                    parse_log_record_key(log_record.key.clone()).map_err(|_| {
+                        Errors::DataFileCorrupted {
+                            file_id: *file_id,
+                            offset,
+                        }
+                    })?;
+                if seq_no == NON_TRANSACTION_SEQ_NO {
+                    update_hint_position(
+                        &mut positions,
+                        real_key,
+                        log_record.rec_type,
+                        log_record_pos,
+                    );
+                } else if log_record.rec_type == LogRecordType::TxnFinished {
+                    if let Some(records) = transaction_records.remove(&seq_no) {
+                        for txn_record in records {
+                            update_hint_position(
+                                &mut positions,
+                                txn_record.record.key,
+                                txn_record.record.rec_type,
+                                txn_record.pos,
+                            );
+                        }
                     }
+                } else {
+                    log_record.key = real_key;
+                    transaction_records
+                        .entry(seq_no)
+                        .or_default()
+                        .push(TransactionRecord {
+                            record: log_record,
+                            pos: log_record_pos,
+                        });
                 }
+
                 offset += size as u64;
             }
         }
 
-        // sync 保证持久化
-        merge_db.sync()?;
+        // 提交标记写在活跃文件中的事务，通过内存索引判断数据是否已经提交并且仍然有效
+        for records in transaction_records.into_values() {
+            for txn_record in records {
+                if let Some(index_pos) = self.index.get(txn_record.record.key.clone()) {
+                    if index_pos.file_id == txn_record.pos.file_id
+                        && index_pos.offset == txn_record.pos.offset
+                    {
+                        positions.insert(txn_record.record.key, txn_record.pos);
+                    }
+                }
+            }
+        }
+
+        // 先在临时目录中写完 hint 文件，再替换数据目录中的 hint 文件
+        let hint_path = get_hint_path(self.options.dir_path.clone());
+        if hint_path.is_dir() {
+            if let Err(e) = fs::remove_dir_all(hint_path.clone()) {
+                error!("failed to remove hint path {}", e);
+                return Err(Errors::FailedToRemoveDataFile);
+            }
+        }
+        if let Err(e) = fs::create_dir_all(hint_path.clone()) {
+            error!("failed to create hint path {}", e);
+            return Err(Errors::FailedToCreateDatabaseDir);
+        }
+
+        // 按照在数据文件中的位置写入，hint 文件损坏时可以从最后一条有效记录的位置开始重新扫描数据文件
+        let mut positions: Vec<(Vec<u8>, LogRecordPos)> = positions.into_iter().collect();
+        positions.sort_by_key(|(_, pos)| (pos.file_id, pos.offset));
+        let hint_file = DataFile::new_hint_file(hint_path.clone(), self.cipher.clone())?;
+        for (key, log_record_pos) in positions {
+            hint_file.write_hint_record(key, log_record_pos)?;
+        }
         hint_file.sync()?;
 
-        // 拿到最近未参与 merge 的文件 id
-        let non_merge_file_id = merge_files.last().unwrap().get_file_id() + 1;
-        let merge_fin_file = DataFile::new_merge_fin_file(merge_path.clone())?;
-        let merge_fin_record = LogRecord {
-            key: MERGE_FIN_KEY.to_vec(),
-            value: non_merge_file_id.to_string().into_bytes(),
-            rec_type: LogRecordType::NORMAL,
-        };
+        if let Err(e) = fs::rename(
+            hint_path.join(HINT_FILE_NAME),
+            self.options.dir_path.join(HINT_FILE_NAME),
+        ) {
+            error!("failed to replace hint file {}", e);
+            return Err(Errors::write_failed(
+                &self.options.dir_path.join(HINT_FILE_NAME),
+                e,
+            ));
+        }
+        if let Err(e) = fs::remove_dir_all(hint_path) {
+            error!("failed to remove hint path {}", e);
+            return Err(Errors::FailedToRemoveDataFile);
+        }
+        sync_dir_if_enabled(self.options.fsync_dir, &self.options.dir_path)?;
+
+        // 最后原子地替换 merge 完成文件，更新其中的 file id，
+        // 在此之前崩溃的话启动时会多加载一些数据文件，不影响正确性
+        write_merge_fin_file(
+            self.options.dir_path.clone(),
+            active_file_id,
+            self.options.fsync_dir,
+        )
+    }
+
+    // 遍历数据文件，将其中的有效数据交给 handle 处理
+    fn scan_valid_records<F>(
+        &self,
+        data_file: &DataFile,
+        merge_handle: &MergeHandle,
+        mut handle: F,
+    ) -> Result<()>
+    where
+        F: FnMut(Vec<u8>, LogRecord) -> Result<()>,
+    {
+        *merge_handle.current_file_id.lock() = Some(data_file.get_file_id());
+        let mut reader = data_file.sequential_reader();
+        let mut offset = data_file.get_header_size();
+        merge_handle
+            .processed_bytes
+            .fetch_add(offset, Ordering::SeqCst);
+        loop {
+            if merge_handle.is_cancelled() {
+                return Err(Errors::MergeCancelled);
+            }
+
+            let (mut log_record, size) = match reader.read_log_record(offset) {
+                Ok(result) => (result.record, result.size),
+                Err(e) => {
+                    if e == Errors::ReadDataFileEof {
+                        break;
+                    }
+                    return Err(e);
+                }
+            };
 
-        let enc_record = merge_fin_record.encode();
-        merge_fin_file.write(&enc_record)?;
-        merge_fin_file.sync()?;
+            // 解码拿到实际的 key
+            let (real_key, _) = parse_log_record_key(log_record.key.clone())?;
+            let index_pos = self.index.get(real_key.clone());
+            if index_pos.is_none()
+                && log_record.rec_type == LogRecordType::DELETE
+                && self.tombstone_retained(&log_record)
+            {
+                // 保留还在保留时间之内的删除记录
+                log_record.key = log_record_key_with_seq(&real_key, NON_TRANSACTION_SEQ_NO);
+                handle(real_key, log_record)?;
+            } else if let Some(index_pos) = index_pos {
+                // 如果文件 id 和偏移 offset 均相等，则说明是一条有效的数据
+                if index_pos.file_id == data_file.get_file_id() && index_pos.offset == offset {
+                    // 去除事务的标识
+                    log_record.key =
+                        log_record_key_with_seq(&real_key, NON_TRANSACTION_SEQ_NO);
+                    handle(real_key, log_record)?;
+                } else {
+                    merge_handle.records_dropped.fetch_add(1, Ordering::SeqCst);
+                }
+            } else {
+                merge_handle.records_dropped.fetch_add(1, Ordering::SeqCst);
+            }
+            offset += size as u64;
+            merge_handle
+                .processed_bytes
+                .fetch_add(size as u64, Ordering::SeqCst);
+        }
 
         Ok(())
     }
 
-    fn ratate_merge_file(&self) -> Result<Vec<DataFile>> {
+    // 并行 merge：工作线程各自领取数据文件并收集其中的有效数据，
+    // 当前线程作为唯一的写入者，按照文件 id 从小到大的顺序写入 merge 目录
+    fn rewrite_files_parallel(
+        &self,
+        merge_files: &[Arc<DataFile>],
+        merge_writer: &mut MergeWriter,
+        hint_file: &DataFile,
+        merge_handle: &MergeHandle,
+        blob_refs: &mut HashSet<u64>,
+    ) -> Result<()> {
+        // 多个线程并行扫描数据文件，按照文件顺序统一写入
+        util::parallel::for_each_ordered(
+            merge_files,
+            self.options.merge_threads,
+            |data_file| {
+                let mut records = Vec::new();
+                self.scan_valid_records(data_file, merge_handle, |real_key, log_record| {
+                    records.push((real_key, log_record));
+                    Ok(())
+                })?;
+                Ok(records)
+            },
+            |_, records| {
+                for (real_key, mut log_record) in records {
+                    add_blob_reference(blob_refs, &log_record)?;
+                    let log_record_pos = merge_writer.append(&mut log_record)?;
+                    // 写 hint 索引，保留的删除记录不需要写入
+                    if log_record.rec_type == LogRecordType::NORMAL {
+                        hint_file.write_hint_record(real_key, log_record_pos)?;
+                    }
+                }
+                Ok(())
+            },
+        )
+    }
+
+    // 删除记录的写入时间是否还在 tombstone_retention 之内
+    fn tombstone_retained(&self, log_record: &LogRecord) -> bool {
+        match self.options.tombstone_retention {
+            Some(retention) => {
+                let cutoff = current_timestamp().saturating_sub(retention.as_millis() as u64);
+                log_record.timestamp >= cutoff
+            }
+            None => false,
+        }
+    }
+
+    fn ratate_merge_file(&self) -> Result<Vec<Arc<DataFile>>> {
+        // 和写入时的加锁顺序相同，先锁活跃文件再锁旧的数据文件
+        let mut active_file = self.active_file.write();
+        let mut older_files = self.older_files.write();
+
         // 取出旧的数据文件 ID
         let mut merge_file_ids = Vec::new();
-        let mut older_files = self.older_files.write();
         for fid in older_files.keys() {
             merge_file_ids.push(*fid);
         }
 
         // 设置一个新的活跃文件用于写入
-        let mut active_file = self.active_file.write();
         // sync 活跃数据文件，保证数据持久性
         active_file.sync()?;
         let acitve_file_id = active_file.get_file_id();
+        self.record_rotation(&mut self.active_file_meta.lock(), &[], acitve_file_id + 1)?;
         let new_active_file = DataFile::new(
             self.options.dir_path.clone(),
             acitve_file_id + 1,
             IOType::StandardFIO,
+            self.cipher.clone(),
         )?;
-        *active_file = new_active_file;
+        preallocate_if_enabled(&self.options, &new_active_file)?;
+        sync_dir_if_enabled(self.options.fsync_dir, &self.options.dir_path)?;
 
-        // 加载到旧的数据文件中
-        let old_file = DataFile::new(
-            self.options.dir_path.clone(),
-            acitve_file_id,
-            IOType::StandardFIO,
-        )?;
-        older_files.insert(acitve_file_id, old_file);
+        // 原来的活跃文件加入到旧的数据文件中
+        let old_file = std::mem::replace(&mut *active_file, Arc::new(new_active_file));
+        older_files.insert(acitve_file_id, self.seal_data_file(old_file));
 
         // 加到待 merge 的文件列表
         merge_file_ids.push(acitve_file_id);
@@ -155,157 +768,513 @@ impl Engine {
         // 从小到大依次进行 merge
         merge_file_ids.sort();
 
-        // 打开所有需要 merge 的数据文件
+        // 获取所有需要 merge 的数据文件
         let mut merge_files = Vec::new();
         for file_id in merge_file_ids.iter() {
-            let data_file =
-                DataFile::new(self.options.dir_path.clone(), *file_id, IOType::StandardFIO)?;
-            merge_files.push(data_file);
+            merge_files.push(self.merge_data_file(&older_files, *file_id)?);
         }
 
         Ok(merge_files)
     }
 
+    // merge 读取的数据文件，IO 类型和旧的数据文件相同时共享旧的数据文件的句柄，
+    // 否则按照 merge 的 IO 类型单独打开
+    fn merge_data_file(
+        &self,
+        older_files: &HashMap<u64, Arc<DataFile>>,
+        file_id: u64,
+    ) -> Result<Arc<DataFile>> {
+        if let Some(data_file) = older_files.get(&file_id) {
+            if data_file.io_type() == self.options.merge_io_type {
+                return Ok(data_file.clone());
+            }
+        }
+        let data_file = DataFile::new(
+            self.options.dir_path.clone(),
+            file_id,
+            self.options.merge_io_type,
+            self.cipher.clone(),
+        )?;
+        Ok(Arc::new(data_file))
+    }
+
     /// 从 hint 索引文件中加载索引
-    pub(crate) fn load_index_from_hint_file(&self) -> Result<()> {
+    // hint 文件中的记录按照在数据文件中的位置排列，遇到校验失败的记录（例如宕机时没有完整写入）时停止加载，
+    // 返回最后一条有效记录之后的位置（文件 id 和偏移），从这个位置开始重新扫描数据文件
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub(crate) fn load_index_from_hint_file(&self) -> Result<Option<(u64, u64)>> {
         let hit_file_name = self.options.dir_path.join(HINT_FILE_NAME);
         // 如果 hint 不存在则返回
         if !hit_file_name.is_file() {
-            return Ok(());
+            return Ok(None);
         }
 
-        let hint_file = DataFile::new_hint_file(self.options.dir_path.clone())?;
+        let hint_file =
+            DataFile::new_hint_file(self.options.dir_path.clone(), self.cipher.clone())?;
 
-        let mut offset = 0;
+        let mut reader = hint_file.sequential_reader();
+        let mut offset = hint_file.get_header_size();
+        let mut rescan_from = (INITIAL_FILE_ID, 0);
         loop {
-            let (log_record, size) = match hint_file.read_log_record(offset) {
+            let (log_record, size) = match reader.read_log_record(offset) {
                 Ok(result) => (result.record, result.size),
                 Err(e) => {
                     if e == Errors::ReadDataFileEof {
                         break;
                     }
+                    if matches!(
+                        e,
+                        Errors::InvaildLogRecordCrc | Errors::InvalidRecordType(_)
+                    ) {
+                        warn!(
+                            "hint file is corrupted at offset {}, rescan data files from file {} offset {}",
+                            offset, rescan_from.0, rescan_from.1
+                        );
+                        return Ok(Some(rescan_from));
+                    }
                     return Err(e);
                 }
             };
 
-            // 解码 value，拿到位置索引信息
-            let log_record_pos = decode_log_record_pos(log_record.value);
+            // 解码 value，拿到位置索引信息，位置损坏时和记录损坏一样从数据文件中重新扫描
+            let log_record_pos = match decode_log_record_pos(log_record.value) {
+                Ok(log_record_pos) => log_record_pos,
+                Err(_) => {
+                    warn!(
+                        "hint file is corrupted at offset {}, rescan data files from file {} offset {}",
+                        offset, rescan_from.0, rescan_from.1
+                    );
+                    return Ok(Some(rescan_from));
+                }
+            };
+            rescan_from = (
+                log_record_pos.file_id,
+                log_record_pos.offset + log_record_pos.size,
+            );
             // 存储到内存索引中
-            self.index.put(log_record.key, log_record_pos);
+            self.index_put(log_record.key, log_record_pos);
             offset += size as u64;
         }
 
-        Ok(())
+        Ok(None)
     }
 }
 
-// 获取临时的用于 merge 的数据目录
-fn get_merge_path(dir_path: PathBuf) -> PathBuf {
-    let file_name = dir_path.file_name().unwrap();
-    let merge_name = std::format!("{}-{}", file_name.to_str().unwrap(), MERGE_DIR_NAME);
-    let parent = dir_path.parent().unwrap();
-    parent.to_path_buf().join(merge_name)
+// merge 以及 ingest 临时目录中的数据文件写入器，只负责顺序追加数据并在文件写满时切换，
+// 不需要像完整的 Engine 实例一样加载索引、持有文件锁以及在关闭时写入各种元数据文件
+pub(crate) struct MergeWriter {
+    dir_path: PathBuf,
+    data_file_size: u64,
+    bytes_per_sync: usize,
+    io_type: IOType,
+    cipher: Option<Arc<Cipher>>,
+    active_file: DataFile,
+    bytes_write: usize,
+    finished_size: u64,             // 已经写满的数据文件的总大小
+    bloom_hashes: Option<Vec<u32>>, // 开启 bloom filter 时当前数据文件中所有 key 的哈希值
 }
 
-// 加载 merge 数据目录
-pub(crate) fn load_merge_files(dir_path: PathBuf) -> Result<bool> {
-    let merge_path = get_merge_path(dir_path.clone());
-    // 没有发生过 merge 则直接返回
-    if !merge_path.is_dir() {
-        return Ok(false);
+impl MergeWriter {
+    // merge 结果在最后统一 sync 并写入 merge 完成文件之后才会生效，所以不需要 sync_writes，
+    // 但仍然按照 bytes_per_sync 定期持久化，避免积累过多的脏页
+    pub(crate) fn new(
+        dir_path: PathBuf,
+        options: &Options,
+        cipher: Option<Arc<Cipher>>,
+    ) -> Result<Self> {
+        let io_type = options.merge_io_type;
+        let active_file =
+            DataFile::new(dir_path.clone(), INITIAL_FILE_ID, io_type, cipher.clone())?;
+        Ok(Self {
+            dir_path,
+            data_file_size: options.data_file_size,
+            bytes_per_sync: options.bytes_per_sync,
+            io_type,
+            cipher,
+            active_file,
+            bytes_write: 0,
+            finished_size: 0,
+            bloom_hashes: options.bloom_filter.then(Vec::new),
+        })
     }
 
-    let dir = match fs::read_dir(merge_path.clone()) {
-        Ok(dir) => dir,
-        Err(e) => {
-            error!("failed to read merge dir: {}", e);
-            return Err(Errors::FailedToReadDatabaseDir);
+    pub(crate) fn append(&mut self, record: &mut LogRecord) -> Result<LogRecordPos> {
+        let enc_record = match self.cipher.as_ref() {
+            Some(cipher) => record.encrypt(cipher)?.encode(),
+            None => record.encode(),
+        };
+        let record_len = enc_record.len() as u64;
+
+        // 当前文件写满之后持久化，并切换到新的数据文件，空文件放不下的记录直接写入
+        let write_off = self.active_file.get_write_off();
+        if write_off + record_len > self.data_file_size
+            && write_off > self.active_file.get_header_size()
+        {
+            self.active_file.sync()?;
+            self.write_bloom_filter()?;
+            if let Some(hashes) = self.bloom_hashes.as_mut() {
+                hashes.clear();
+            }
+            self.bytes_write = 0;
+            self.finished_size += self.active_file.file_size();
+            let file_id = self.active_file.get_file_id() + 1;
+            self.active_file = DataFile::new(
+                self.dir_path.clone(),
+                file_id,
+                self.io_type,
+                self.cipher.clone(),
+            )?;
         }
-    };
 
-    // 查找是否有标识 merge 完成的文件
-    let mut merge_file_names = Vec::new();
-    let mut merge_finished = false;
-    for file in dir {
-        if let Ok(entry) = file {
-            let file_os_str = entry.file_name();
-            let filename = file_os_str.to_str().unwrap();
-
-            if filename.ends_with(MERGE_FIN_FILE_NAME) {
-                merge_finished = true;
-            }
-            if filename.ends_with(FILE_LOCK_NAME) {
-                continue;
-            }
-            if filename.ends_with(SEQ_NO_FILE_NAME) {
-                continue;
-            }
+        let pos = LogRecordPos {
+            file_id: self.active_file.get_file_id(),
+            offset: self.active_file.get_write_off(),
+            size: record_len,
+        };
+        self.active_file.write(&enc_record)?;
+        if let Some(hashes) = self.bloom_hashes.as_mut() {
+            let (real_key, _) = parse_log_record_key(record.key.clone())?;
+            hashes.push(bloom_hash(&real_key));
+        }
 
-            merge_file_names.push(entry.file_name());
+        self.bytes_write += enc_record.len();
+        if self.bytes_per_sync > 0 && self.bytes_write >= self.bytes_per_sync {
+            self.active_file.sync()?;
+            self.bytes_write = 0;
         }
-    }
 
-    // merge 没有完成，直接返回
-    if !merge_finished {
-        fs::remove_dir_all(merge_path.clone()).unwrap();
-        return Ok(false);
+        Ok(pos)
     }
 
-    // 打开标识 merge 完成的文件，取出未参与 merge 的文件 id
-    let merge_fin_file = DataFile::new_merge_fin_file(merge_path.clone())?;
-    let merge_fin_record = merge_fin_file.read_log_record(0)?;
-    let v = String::from_utf8(merge_fin_record.record.value).unwrap();
-    let non_merge_fid = v.parse::<u64>().unwrap();
+    // 持久化当前的数据文件，开启 bloom filter 时同时写入它的 bloom filter
+    pub(crate) fn sync(&self) -> Result<()> {
+        self.active_file.sync()?;
+        self.write_bloom_filter()
+    }
 
-    // 将旧的数据文件删除
-    for fid in 0..non_merge_fid {
-        let file = get_data_file_name(dir_path.clone(), fid);
-        if file.is_file() {
-            fs::remove_file(file).unwrap();
+    fn write_bloom_filter(&self) -> Result<()> {
+        match self.bloom_hashes.as_ref() {
+            Some(hashes) => {
+                let filter = BloomFilter::new(hashes, self.active_file.file_size());
+                write_bloom_file(
+                    self.dir_path.clone(),
+                    self.active_file.get_file_id(),
+                    &filter,
+                )
+            }
+            None => Ok(()),
         }
     }
 
-    // 将新的数据文件移动到数据目录中
-    for file_name in merge_file_names {
-        let src_path = merge_path.join(file_name.clone());
-        let dest_path = dir_path.join(file_name.clone());
-        fs::rename(src_path, dest_path).unwrap();
+    // 当前正在写入的数据文件 id，即已经写入的最大的文件 id
+    pub(crate) fn file_id(&self) -> u64 {
+        self.active_file.get_file_id()
     }
 
-    // 最后删除临时 merge 目录
-    fs::remove_dir_all(merge_path.clone()).unwrap();
-
-    Ok(true)
+    // 已经写入的所有数据文件的总大小
+    pub(crate) fn total_size(&self) -> u64 {
+        self.finished_size + self.active_file.file_size()
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use std::{sync::Arc, thread, time};
-
-    use bytes::Bytes;
-    use util::rand_kv::{get_test_key, get_test_value};
-
-    use crate::options::IndexType;
+// 从标识 merge 完成的文件中读取最近未参与 merge 的文件 id
+pub(crate) fn read_non_merge_file_id(dir_path: PathBuf) -> Result<u64> {
+    let merge_fin_file = DataFile::new_merge_fin_file(dir_path)?;
+    read_merge_fin_record(&merge_fin_file)
+}
 
-    use super::*;
+fn read_merge_fin_record(merge_fin_file: &DataFile) -> Result<u64> {
+    let merge_fin_record = merge_fin_file.read_log_record(0)?;
+    let non_merge_fid = String::from_utf8(merge_fin_record.record.value)
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok());
+    match non_merge_fid {
+        Some(fid) => Ok(fid),
+        None => Err(Errors::DataDirCorrupted),
+    }
+}
 
-    #[test]
-    fn test_merge_1() {
-        // 没有任何数据的情况下进行 Merge
-        let mut opts = Options::default();
-        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-merge-1");
-        opts.data_file_size = 64 * 1024 * 1024;
-        opts.data_file_merge_ratio = 0 as f32;
-        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+// 写入标识 merge 完成的文件，记录最近未参与 merge 的文件 id，已经存在的文件会被原子地替换
+pub(crate) fn write_merge_fin_file(
+    dir_path: PathBuf,
+    non_merge_file_id: u64,
+    fsync_dir: bool,
+) -> Result<()> {
+    let merge_fin_record = LogRecord {
+        key: MERGE_FIN_KEY.to_vec(),
+        value: non_merge_file_id.to_string().into_bytes(),
+        rec_type: LogRecordType::NORMAL,
+        timestamp: 0,
+        value_pointer: false,
+    };
+    write_record_file(
+        dir_path.join(MERGE_FIN_FILE_NAME),
+        &merge_fin_record,
+        fsync_dir,
+    )
+}
 
-        let res1: std::result::Result<(), Errors> = engine.merge();
-        assert!(res1.is_ok());
+// 记录有效数据的 value 所在的 blob 文件
+fn add_blob_reference(blob_refs: &mut HashSet<u64>, log_record: &LogRecord) -> Result<()> {
+    if log_record.value_pointer {
+        blob_refs.insert(decode_log_record_pos(log_record.value.clone())?.file_id);
+    }
+    Ok(())
+}
 
-        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+// 根据记录类型更新 key 在 hint 文件中的位置，删除的 key 不需要写入
+fn update_hint_position(
+    positions: &mut HashMap<Vec<u8>, LogRecordPos>,
+    key: Vec<u8>,
+    rec_type: LogRecordType,
+    pos: LogRecordPos,
+) {
+    match rec_type {
+        LogRecordType::NORMAL => {
+            positions.insert(key, pos);
+        }
+        LogRecordType::DELETE => {
+            positions.remove(&key);
+        }
+        LogRecordType::TxnFinished => {}
     }
+}
 
-    #[test]
-    fn test_merge_2() {
+// 获取临时的用于生成 hint 文件的目录
+fn get_hint_path(dir_path: PathBuf) -> PathBuf {
+    let file_name = dir_path.file_name().unwrap();
+    let hint_name = std::format!("{}-{}", file_name.to_str().unwrap(), HINT_DIR_NAME);
+    let parent = dir_path.parent().unwrap();
+    parent.to_path_buf().join(hint_name)
+}
+
+// 获取临时的用于 merge 的数据目录
+pub(crate) fn get_merge_path(dir_path: PathBuf) -> PathBuf {
+    let file_name = dir_path.file_name().unwrap();
+    let merge_name = std::format!("{}-{}", file_name.to_str().unwrap(), MERGE_DIR_NAME);
+    let parent = dir_path.parent().unwrap();
+    parent.to_path_buf().join(merge_name)
+}
+
+// 加载 merge 数据目录
+// 安装 merge 结果分为三个阶段，每个阶段都可以在崩溃之后重新执行：
+// 1. 将 merge 目录中的文件以临时文件名移动到数据目录中，最后移动标识 merge 完成的文件作为提交标记
+// 2. 删除参与 merge 的旧数据文件
+// 3. 将临时文件重命名为正式的文件名
+// 最后删除 merge 之后不再被引用的 blob 文件
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(name = "merge_install", skip_all)
+)]
+pub(crate) fn load_merge_files(dir_path: PathBuf) -> Result<bool> {
+    let merge_path = get_merge_path(dir_path.clone());
+    if merge_path.is_dir() {
+        // merge 已经完成，将 merge 目录中的文件移动到数据目录中，
+        // 标识 merge 完成的文件无法读取时当作 merge 没有完成处理
+        if merge_path.join(MERGE_FIN_FILE_NAME).is_file() {
+            match read_non_merge_file_id(merge_path.clone()) {
+                Ok(_) => move_merge_files(&merge_path, &dir_path)?,
+                Err(e) => warn!("merge fin file is corrupted, discard the merge: {}", e),
+            }
+        }
+        // merge 没有完成，或者文件已经全部移动，直接删除 merge 目录
+        if let Err(e) = fs::remove_dir_all(merge_path) {
+            error!("failed to remove merge dir: {}", e);
+            return Err(Errors::FailedToInstallMergeFiles);
+        }
+    }
+
+    // 删除参与 merge 的旧数据文件
+    let committed_path = dir_path.join(MERGE_FIN_COMMITTED_NAME);
+    let cleaned_path = dir_path.join(MERGE_FIN_CLEANED_NAME);
+    if committed_path.is_file() {
+        let merge_fin_file = DataFile::open_merge_fin_file(committed_path.clone())?;
+        let non_merge_fid = read_merge_fin_record(&merge_fin_file)?;
+
+        // 删除旧数据文件之后 manifest 中的数据文件列表不再有效，之后打开时扫描数据目录重新生成
+        let manifest_path = dir_path.join(MANIFEST_FILE_NAME);
+        if manifest_path.is_file() {
+            fs::remove_file(manifest_path).map_err(install_error)?;
+            sync_dir(&dir_path)?;
+        }
+        for fid in 0..non_merge_fid {
+            let file = get_data_file_name(dir_path.clone(), fid);
+            if file.is_file() {
+                fs::remove_file(file).map_err(install_error)?;
+                install_step();
+            }
+            // merge 生成的 bloom filter 此时还是临时文件名，删除的只是旧数据文件的 bloom filter
+            let bloom_file = get_bloom_file_name(dir_path.clone(), fid);
+            if bloom_file.is_file() {
+                fs::remove_file(bloom_file).map_err(install_error)?;
+            }
+            // 已经上传到对象存储的旧数据文件也需要删除，否则重启之后会重新加载
+            #[cfg(feature = "object-store")]
+            if let Some(archive) = object_store::find_archive(&dir_path) {
+                archive.remove(fid)?;
+            }
+        }
+        sync_dir(&dir_path)?;
+        fs::rename(&committed_path, &cleaned_path).map_err(install_error)?;
+        sync_dir(&dir_path)?;
+        install_step();
+    }
+
+    // 将临时文件重命名为正式的文件名，最后替换标识 merge 完成的文件
+    let is_merged = cleaned_path.is_file();
+    if is_merged {
+        let dir = match fs::read_dir(dir_path.clone()) {
+            Ok(dir) => dir,
+            Err(e) => {
+                error!("failed to read database dir: {}", e);
+                return Err(Errors::FailedToReadDatabaseDir);
+            }
+        };
+        for file in dir {
+            let entry = file.map_err(install_error)?;
+            let file_os_str = entry.file_name();
+            let filename = file_os_str.to_str().unwrap();
+            if let Some(dest_name) = filename.strip_suffix(MERGE_TEMP_FILE_SUFFIX) {
+                fs::rename(entry.path(), dir_path.join(dest_name)).map_err(install_error)?;
+                install_step();
+            }
+        }
+        sync_dir(&dir_path)?;
+        fs::rename(&cleaned_path, dir_path.join(MERGE_FIN_FILE_NAME)).map_err(install_error)?;
+        sync_dir(&dir_path)?;
+        install_step();
+    }
+
+    // 数据目录中标识 merge 完成的文件无法读取时，hint 文件覆盖的范围也就无法确定，
+    // 两者一起删除，启动时扫描全部数据文件重建索引
+    let merge_fin_path = dir_path.join(MERGE_FIN_FILE_NAME);
+    if !is_merged && merge_fin_path.is_file() {
+        if let Err(e) = read_non_merge_file_id(dir_path.clone()) {
+            warn!(
+                "merge fin file is corrupted, rebuild index from data files: {}",
+                e
+            );
+            fs::remove_file(merge_fin_path).map_err(install_error)?;
+            let hint_path = dir_path.join(HINT_FILE_NAME);
+            if hint_path.is_file() {
+                fs::remove_file(hint_path).map_err(install_error)?;
+            }
+            sync_dir(&dir_path)?;
+        }
+    }
+
+    // 删除 merge 之后不再被引用的 blob 文件
+    remove_unused_blob_files(&dir_path)?;
+
+    Ok(is_merged)
+}
+
+// 将 merge 目录中的文件以临时文件名移动到数据目录中，标识 merge 完成的文件最后移动
+// 已经移动过的文件不在 merge 目录中，所以崩溃之后可以重新执行
+fn move_merge_files(merge_path: &Path, dir_path: &Path) -> Result<()> {
+    let dir = match fs::read_dir(merge_path) {
+        Ok(dir) => dir,
+        Err(e) => {
+            error!("failed to read merge dir: {}", e);
+            return Err(Errors::FailedToReadDatabaseDir);
+        }
+    };
+
+    for file in dir {
+        let entry = file.map_err(install_error)?;
+        let file_os_str = entry.file_name();
+        let filename = file_os_str.to_str().unwrap();
+
+        if filename.ends_with(MERGE_FIN_FILE_NAME)
+            || filename.ends_with(FILE_LOCK_NAME)
+            || filename.ends_with(SEQ_NO_FILE_NAME)
+            || filename.ends_with(MVCC_VERSION_FILE_NAME)
+            || filename.ends_with(STATS_FILE_NAME)
+            || filename.ends_with(KEY_CHECK_FILE_NAME)
+        {
+            continue;
+        }
+
+        let dest_path = dir_path.join(format!("{}{}", filename, MERGE_TEMP_FILE_SUFFIX));
+        fs::rename(entry.path(), dest_path).map_err(install_error)?;
+        install_step();
+    }
+    sync_dir(dir_path)?;
+
+    // 提交 merge 结果，之后即使崩溃也会继续完成安装
+    fs::rename(
+        merge_path.join(MERGE_FIN_FILE_NAME),
+        dir_path.join(MERGE_FIN_COMMITTED_NAME),
+    )
+    .map_err(install_error)?;
+    sync_dir(dir_path)?;
+    install_step();
+
+    Ok(())
+}
+
+fn sync_dir(dir_path: &Path) -> Result<()> {
+    util::file::sync_dir(dir_path).map_err(install_error)
+}
+
+fn install_error(e: std::io::Error) -> Errors {
+    error!("failed to install merge files: {}", e);
+    Errors::FailedToInstallMergeFiles
+}
+
+// 测试中模拟安装 merge 结果时在第 n 个文件操作之后崩溃
+#[cfg(test)]
+thread_local! {
+    static INSTALL_CRASH_POINT: std::cell::Cell<Option<usize>> = const { std::cell::Cell::new(None) };
+    static INSTALL_STEPS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+fn install_step() {
+    #[cfg(test)]
+    {
+        let steps = INSTALL_STEPS.with(|s| {
+            s.set(s.get() + 1);
+            s.get()
+        });
+        if INSTALL_CRASH_POINT.with(|c| c.get()) == Some(steps) {
+            panic!("simulated crash after {} install steps", steps);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread, time};
+
+    use bytes::Bytes;
+    use util::rand_kv::{get_test_key, get_test_value};
+
+    use crate::{
+        data::data_file::{BLOB_GC_FILE_NAME, DATA_FILE_NAME_SUFFIX},
+        db::ChangeType,
+        options::{IndexType, WriteBatchOptions},
+        replication::ReplicationCursor,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_merge_1() {
+        // 没有任何数据的情况下进行 Merge
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-merge-1");
+        opts.data_file_size = 64 * 1024 * 1024;
+        opts.data_file_merge_ratio = 0 as f32;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        let res1: std::result::Result<MergeReport, Errors> = engine.merge();
+        assert!(res1.is_ok());
+
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_merge_2() {
         // 全部都是有效数据的情况
         let mut opts = Options::default();
         opts.dir_path = PathBuf::from("/tmp/bitcask-rs-merge-2");
@@ -531,4 +1500,740 @@ mod tests {
 
         std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
     }
+
+    #[test]
+    fn test_merge_7() {
+        // 多线程并行 merge 多个数据文件
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-merge-7");
+        opts.data_file_size = 512 * 1024;
+        opts.data_file_merge_ratio = 0 as f32;
+        opts.merge_threads = 4;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..50000 {
+            let res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(res.is_ok());
+        }
+
+        for i in 0..10000 {
+            let res = engine.put(get_test_key(i), Bytes::from("new value in merge"));
+            assert!(res.is_ok());
+        }
+
+        for i in 40000..50000 {
+            let res = engine.delete(get_test_key(i));
+            assert!(res.is_ok());
+        }
+
+        let res1 = engine.merge();
+        assert!(res1.is_ok());
+
+        // 重启校验
+        std::mem::drop(engine);
+
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        let keys = engine2.list_keys().unwrap();
+        assert_eq!(keys.len(), 40000);
+
+        for i in 0..10000 {
+            let res = engine2.get(get_test_key(i));
+            assert_eq!(res.ok().unwrap(), Bytes::from("new value in merge"));
+        }
+        for i in 10000..40000 {
+            let res = engine2.get(get_test_key(i));
+            assert_eq!(res.ok().unwrap(), get_test_value(i));
+        }
+
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_merge_progress() {
+        // merge 完成后进度到达总大小
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-merge-progress");
+        opts.data_file_size = 1024 * 1024;
+        opts.data_file_merge_ratio = 0 as f32;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..10000 {
+            let res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(res.is_ok());
+        }
+
+        let handle = MergeHandle::new();
+        assert_eq!(handle.progress(), MergeProgress::default());
+        let res1 = engine.merge_with_handle(&handle);
+        assert!(res1.is_ok());
+
+        let progress = handle.progress();
+        assert!(progress.total_bytes > 0);
+        assert_eq!(progress.processed_bytes, progress.total_bytes);
+        assert!(progress.current_file_id.is_some());
+
+        std::mem::drop(engine);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_merge_report() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-merge-report");
+        opts.data_file_size = 64 * 1024;
+        opts.data_file_merge_ratio = 0 as f32;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..1000 {
+            assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        for i in 0..200 {
+            assert!(engine
+                .put(get_test_key(i), Bytes::from("new value in merge"))
+                .is_ok());
+        }
+        for i in 900..1000 {
+            assert!(engine.delete(get_test_key(i)).is_ok());
+        }
+
+        let report = engine.merge().unwrap();
+        assert!(report.files_merged > 1);
+        // 被覆盖的 200 条、被删除的 100 条以及 100 条删除记录
+        assert_eq!(report.records_dropped, 400);
+        assert!(report.bytes_after > 0 && report.bytes_after < report.bytes_before);
+        assert_eq!(engine.merge_history(), vec![report.clone()]);
+
+        for _ in 0..MERGE_HISTORY_SIZE {
+            assert!(engine.merge().is_ok());
+        }
+        let history = engine.merge_history();
+        assert_eq!(history.len(), MERGE_HISTORY_SIZE);
+        assert_ne!(history[0], report);
+
+        // 历史记录只保存在内存中
+        std::mem::drop(engine);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert!(engine2.merge_history().is_empty());
+
+        std::mem::drop(engine2);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_merge_cancel() {
+        // 取消 merge，临时 merge 目录在重启时被清理，数据不受影响
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-merge-cancel");
+        opts.data_file_size = 1024 * 1024;
+        opts.data_file_merge_ratio = 0 as f32;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..10000 {
+            let res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(res.is_ok());
+        }
+        for i in 0..5000 {
+            let res = engine.delete(get_test_key(i));
+            assert!(res.is_ok());
+        }
+
+        let handle = MergeHandle::new();
+        handle.cancel();
+        let res1 = engine.merge_with_handle(&handle);
+        assert_eq!(res1.err().unwrap(), Errors::MergeCancelled);
+        let progress = handle.progress();
+        assert!(progress.processed_bytes < progress.total_bytes);
+
+        let merge_path = get_merge_path(opts.dir_path.clone());
+        assert!(merge_path.is_dir());
+
+        // 重启校验
+        std::mem::drop(engine);
+
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert!(!merge_path.exists());
+        let keys = engine2.list_keys().unwrap();
+        assert_eq!(keys.len(), 5000);
+        for i in 5000..10000 {
+            let res = engine2.get(get_test_key(i));
+            assert!(res.is_ok());
+        }
+
+        // 取消之后还可以正常 merge
+        let res2 = engine2.merge();
+        assert!(res2.is_ok());
+
+        std::mem::drop(engine2);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_generate_hint_files() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-generate-hint");
+        opts.data_file_size = 64 * 1024;
+        opts.data_file_merge_ratio = 0 as f32;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..5000 {
+            let res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(res.is_ok());
+        }
+        for i in 0..500 {
+            let res = engine.put(get_test_key(i), Bytes::from("new value in hint"));
+            assert!(res.is_ok());
+        }
+        for i in 4500..5000 {
+            let res = engine.delete(get_test_key(i));
+            assert!(res.is_ok());
+        }
+        let wb = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("failed to create write batch");
+        for i in 5000..5100 {
+            let res = wb.put(get_test_key(i), get_test_value(i));
+            assert!(res.is_ok());
+        }
+        assert!(wb.commit().is_ok());
+
+        let res1 = engine.generate_hint_files();
+        assert!(res1.is_ok());
+        assert!(opts.dir_path.join(HINT_FILE_NAME).is_file());
+        let active_file_id = engine.active_file.read().get_file_id();
+        assert!(active_file_id > 0);
+        assert_eq!(
+            read_non_merge_file_id(opts.dir_path.clone()).unwrap(),
+            active_file_id
+        );
+
+        // 生成 hint 文件之后继续写入
+        for i in 5100..5200 {
+            let res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(res.is_ok());
+        }
+        for i in 0..10 {
+            let res = engine.delete(get_test_key(i));
+            assert!(res.is_ok());
+        }
+
+        // 重启校验
+        std::mem::drop(wb);
+        std::mem::drop(engine);
+
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        let keys = engine2.list_keys().unwrap();
+        assert_eq!(keys.len(), 4690);
+        for i in 10..500 {
+            let res = engine2.get(get_test_key(i));
+            assert_eq!(res.ok().unwrap(), Bytes::from("new value in hint"));
+        }
+        for i in 500..4500 {
+            let res = engine2.get(get_test_key(i));
+            assert_eq!(res.ok().unwrap(), get_test_value(i));
+        }
+        for i in 5000..5200 {
+            let res = engine2.get(get_test_key(i));
+            assert!(res.is_ok());
+        }
+
+        // 生成 hint 文件之后还可以正常 merge
+        let res2 = engine2.merge();
+        assert!(res2.is_ok());
+        std::mem::drop(engine2);
+
+        let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+        let keys = engine3.list_keys().unwrap();
+        assert_eq!(keys.len(), 4690);
+
+        std::mem::drop(engine3);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_hint_file_corrupted() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-hint-corrupted");
+        opts.data_file_size = 64 * 1024;
+        opts.data_file_merge_ratio = 0 as f32;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..5000 {
+            assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        for i in 0..500 {
+            assert!(engine
+                .put(get_test_key(i), Bytes::from("new value in hint"))
+                .is_ok());
+        }
+        for i in 4500..5000 {
+            assert!(engine.delete(get_test_key(i)).is_ok());
+        }
+        let wb = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("failed to create write batch");
+        for i in 5000..5100 {
+            assert!(wb.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        assert!(wb.commit().is_ok());
+        assert!(engine.generate_hint_files().is_ok());
+        std::mem::drop(wb);
+        std::mem::drop(engine);
+
+        let check = |engine: &Engine| {
+            assert_eq!(engine.list_keys().unwrap().len(), 4600);
+            for i in 0..500 {
+                let res = engine.get(get_test_key(i));
+                assert_eq!(res.unwrap(), Bytes::from("new value in hint"));
+            }
+            for i in 500..4500 {
+                assert_eq!(engine.get(get_test_key(i)).unwrap(), get_test_value(i));
+            }
+            for i in 4500..5000 {
+                let res = engine.get(get_test_key(i));
+                assert_eq!(res.err().unwrap(), Errors::KeyNotFound);
+            }
+            for i in 5000..5100 {
+                assert_eq!(engine.get(get_test_key(i)).unwrap(), get_test_value(i));
+            }
+        };
+
+        // hint 文件中间的记录损坏，之后的数据从数据文件中重新加载
+        let hint_path = opts.dir_path.join(HINT_FILE_NAME);
+        let mut content = fs::read(&hint_path).unwrap();
+        let corrupt_at = content.len() / 3;
+        content[corrupt_at] ^= 0xff;
+        fs::write(&hint_path, &content).unwrap();
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        check(&engine2);
+
+        // merge 生成的 hint 文件末尾没有完整写入
+        assert!(engine2.merge().is_ok());
+        std::mem::drop(engine2);
+        let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+        check(&engine3);
+        std::mem::drop(engine3);
+
+        let hint_len = fs::metadata(&hint_path).unwrap().len();
+        let hint_file = fs::OpenOptions::new().write(true).open(&hint_path).unwrap();
+        hint_file.set_len(hint_len / 2 + 3).unwrap();
+        let engine4 = Engine::open(opts.clone()).expect("failed to open engine");
+        check(&engine4);
+
+        std::mem::drop(engine4);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_merge_fin_corrupted() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-merge-fin-corrupted");
+        opts.data_file_size = 64 * 1024;
+        opts.data_file_merge_ratio = 0 as f32;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..5000 {
+            assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        for i in 0..1000 {
+            assert!(engine.delete(get_test_key(i)).is_ok());
+        }
+
+        let check = |engine: &Engine| {
+            assert_eq!(engine.list_keys().unwrap().len(), 4000);
+            for i in 0..1000 {
+                let res = engine.get(get_test_key(i));
+                assert_eq!(res.err().unwrap(), Errors::KeyNotFound);
+            }
+            for i in 1000..5000 {
+                assert_eq!(engine.get(get_test_key(i)).unwrap(), get_test_value(i));
+            }
+        };
+        let truncate = |path: PathBuf| {
+            let content = fs::read(&path).unwrap();
+            fs::write(&path, &content[..content.len() / 2]).unwrap();
+        };
+
+        // merge 目录中标识 merge 完成的文件损坏，丢弃这次 merge 的结果
+        assert!(engine.merge().is_ok());
+        std::mem::drop(engine);
+        let merge_path = get_merge_path(opts.dir_path.clone());
+        truncate(merge_path.join(MERGE_FIN_FILE_NAME));
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        check(&engine2);
+        assert!(!merge_path.is_dir());
+        assert!(!opts.dir_path.join(MERGE_FIN_FILE_NAME).is_file());
+
+        // 数据目录中标识 merge 完成的文件损坏，删除 hint 文件，从数据文件中重建索引
+        assert!(engine2.merge().is_ok());
+        std::mem::drop(engine2);
+        let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+        check(&engine3);
+        std::mem::drop(engine3);
+        truncate(opts.dir_path.join(MERGE_FIN_FILE_NAME));
+        let engine4 = Engine::open(opts.clone()).expect("failed to open engine");
+        check(&engine4);
+        assert!(!opts.dir_path.join(MERGE_FIN_FILE_NAME).is_file());
+        assert!(!opts.dir_path.join(HINT_FILE_NAME).is_file());
+
+        std::mem::drop(engine4);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_merge_install_crash() {
+        // 模拟在安装 merge 结果的每一个文件操作之后崩溃，重启之后数据都不会丢失
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-merge-install-crash");
+        opts.data_file_size = 32 * 1024;
+        opts.data_file_merge_ratio = 0 as f32;
+
+        let check = |engine: &Engine| {
+            assert_eq!(engine.list_keys().unwrap().len(), 2000);
+            for i in 1000..1500 {
+                let res = engine.get(get_test_key(i));
+                assert_eq!(res.unwrap(), Bytes::from("new value in merge"));
+            }
+            for i in 1500..3000 {
+                let res = engine.get(get_test_key(i));
+                assert_eq!(res.unwrap(), get_test_value(i));
+            }
+        };
+
+        let mut crash_point = 1;
+        loop {
+            let engine = Engine::open(opts.clone()).expect("failed to open engine");
+            for i in 0..3000 {
+                let res = engine.put(get_test_key(i), get_test_value(i));
+                assert!(res.is_ok());
+            }
+            for i in 0..1000 {
+                let res = engine.delete(get_test_key(i));
+                assert!(res.is_ok());
+            }
+            for i in 1000..1500 {
+                let res = engine.put(get_test_key(i), Bytes::from("new value in merge"));
+                assert!(res.is_ok());
+            }
+            assert!(engine.merge().is_ok());
+            std::mem::drop(engine);
+
+            INSTALL_STEPS.with(|s| s.set(0));
+            INSTALL_CRASH_POINT.with(|c| c.set(Some(crash_point)));
+            let open = std::panic::AssertUnwindSafe(|| Engine::open(opts.clone()).map(|_| ()));
+            let res = std::panic::catch_unwind(open);
+            INSTALL_CRASH_POINT.with(|c| c.set(None));
+            let crashed = res.is_err();
+
+            // 崩溃之后重启，继续完成安装
+            let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+            check(&engine2);
+            std::mem::drop(engine2);
+
+            let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+            check(&engine3);
+            std::mem::drop(engine3);
+
+            let dir = fs::read_dir(opts.dir_path.clone()).unwrap();
+            for entry in dir {
+                let file_name = entry.unwrap().file_name();
+                let file_name = file_name.to_str().unwrap();
+                assert!(!file_name.ends_with(MERGE_TEMP_FILE_SUFFIX));
+                assert!(!file_name.starts_with("merge-fin."));
+            }
+            assert!(!get_merge_path(opts.dir_path.clone()).exists());
+            std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+
+            if !crashed {
+                break;
+            }
+            crash_point += 1;
+        }
+        // 至少覆盖了移动、删除和重命名三个阶段
+        assert!(crash_point > 3);
+    }
+
+    #[test]
+    fn test_merge_files() {
+        // 只整理无效数据占比超过阈值的数据文件
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-merge-files");
+        opts.data_file_size = 32 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..200 {
+            assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        for i in 1000..2000 {
+            assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+            if i == 1500 {
+                // 删除标记落在即将被整理的文件中
+                assert!(engine.delete(get_test_key(5)).is_ok());
+            }
+        }
+        for i in 1000..2000 {
+            assert!(engine
+                .put(get_test_key(i), Bytes::from("new value"))
+                .is_ok());
+        }
+
+        assert_eq!(
+            engine.merge_files(1.5).err().unwrap(),
+            Errors::InvaildDataFileMergeRatio
+        );
+
+        let file_stats = engine.file_stats().unwrap();
+        let merged = engine.merge_files(0.5).unwrap();
+        assert!(!merged.is_empty());
+        // 第一个数据文件几乎都是有效数据，不会被整理
+        assert!(!merged.contains(&file_stats[0].file_id));
+        for file_id in merged.iter() {
+            assert!(!get_data_file_name(opts.dir_path.clone(), *file_id).exists());
+        }
+        let remaining = engine.file_stats().unwrap();
+        assert!(remaining.iter().all(|file| !merged.contains(&file.file_id)));
+
+        let check = |engine: &Engine| {
+            assert_eq!(engine.list_keys().unwrap().len(), 1199);
+            for i in 0..200 {
+                let res = engine.get(get_test_key(i));
+                if i == 5 {
+                    assert_eq!(res.err().unwrap(), Errors::KeyNotFound);
+                } else {
+                    assert_eq!(res.unwrap(), get_test_value(i));
+                }
+            }
+            for i in 1000..2000 {
+                assert_eq!(
+                    engine.get(get_test_key(i)).unwrap(),
+                    Bytes::from("new value")
+                );
+            }
+        };
+        check(&engine);
+
+        // 重启之后被删除的数据不会重新出现
+        engine.close().expect("failed to close engine");
+        std::mem::drop(engine);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        check(&engine2);
+
+        std::mem::drop(engine2);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_compact_key() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-compact-key");
+        opts.data_file_size = 64 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..2000 {
+            assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        for i in 1..2000 {
+            assert!(engine
+                .put(get_test_key(i), Bytes::from("new value in compact"))
+                .is_ok());
+        }
+
+        // 第一个数据文件中只剩下 key 0 是有效的
+        let first_file = engine.file_stats().unwrap()[0];
+        assert!(first_file.live_size > 0);
+        assert!(engine.compact_key(get_test_key(0)).unwrap());
+        let stats = engine.file_stats().unwrap();
+        let first = stats
+            .iter()
+            .find(|f| f.file_id == first_file.file_id)
+            .unwrap();
+        assert_eq!(first.live_size, 0);
+
+        // 已经在活跃文件中或者不存在的 key 不需要重写
+        assert!(!engine.compact_key(get_test_key(0)).unwrap());
+        assert!(!engine.compact_key(Bytes::from("not exist")).unwrap());
+        assert_eq!(
+            engine.compact_key(Bytes::new()).err().unwrap(),
+            Errors::KeyIsEmpty
+        );
+
+        // 重写所有的 key 之后旧的数据文件可以全部删除
+        let mut old_file_ids: Vec<u64> = engine
+            .file_stats()
+            .unwrap()
+            .iter()
+            .map(|f| f.file_id)
+            .collect();
+        old_file_ids.pop();
+        assert!(
+            engine
+                .compact_prefix(Bytes::from("bitcask-rs-key-"))
+                .unwrap()
+                > 0
+        );
+        let merged = engine.merge_files(0.0).unwrap();
+        assert!(old_file_ids.iter().all(|fid| merged.contains(fid)));
+
+        std::mem::drop(engine);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine2.list_keys().unwrap().len(), 2000);
+        assert_eq!(engine2.get(get_test_key(0)).unwrap(), get_test_value(0));
+        for i in 1..2000 {
+            assert_eq!(
+                engine2.get(get_test_key(i)).unwrap(),
+                Bytes::from("new value in compact")
+            );
+        }
+
+        std::mem::drop(engine2);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_merge_bptree() {
+        // B+ 树索引的数据库进行 merge，merge 目录中只会生成数据文件和 merge 需要的文件
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-merge-bptree");
+        opts.data_file_size = 32 * 1024;
+        opts.data_file_merge_ratio = 0 as f32;
+        opts.index_type = IndexType::BPTree;
+        opts.bytes_per_sync = 4 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..2000 {
+            assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        for i in 0..500 {
+            assert!(engine.delete(get_test_key(i)).is_ok());
+        }
+        assert!(engine.merge().is_ok());
+
+        let merge_path = get_merge_path(opts.dir_path.clone());
+        let mut data_files = 0;
+        for entry in fs::read_dir(merge_path).unwrap() {
+            let file_name = entry.unwrap().file_name();
+            let file_name = file_name.to_str().unwrap().to_string();
+            if file_name.ends_with(DATA_FILE_NAME_SUFFIX) {
+                data_files += 1;
+                continue;
+            }
+            assert!(
+                file_name == HINT_FILE_NAME
+                    || file_name == MERGE_FIN_FILE_NAME
+                    || file_name == BLOB_GC_FILE_NAME,
+                "unexpected file {} in merge dir",
+                file_name
+            );
+        }
+        assert!(data_files > 1);
+
+        // 重启之后安装 merge 结果
+        std::mem::drop(engine);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine2.list_keys().unwrap().len(), 1500);
+        for i in 0..2000 {
+            let res = engine2.get(get_test_key(i));
+            if i < 500 {
+                assert_eq!(res.err().unwrap(), Errors::KeyNotFound);
+            } else {
+                assert_eq!(res.unwrap(), get_test_value(i));
+            }
+        }
+
+        std::mem::drop(engine2);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_merge_direct_io() {
+        // merge 使用 O_DIRECT 读写数据文件
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-merge-direct-io");
+        opts.data_file_size = 32 * 1024;
+        opts.data_file_merge_ratio = 0 as f32;
+        opts.merge_io_type = IOType::MemoryMap;
+        assert_eq!(
+            Engine::open(opts.clone()).err().unwrap(),
+            Errors::InvaildMergeIOType
+        );
+
+        opts.merge_io_type = IOType::DirectIO;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..2000 {
+            assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        for i in 0..500 {
+            assert!(engine.delete(get_test_key(i)).is_ok());
+        }
+        for i in 500..1000 {
+            assert!(engine
+                .put(get_test_key(i), Bytes::from("new value in merge"))
+                .is_ok());
+        }
+        assert!(!engine.merge_files(0.3).unwrap().is_empty());
+        assert!(engine.merge().is_ok());
+
+        std::mem::drop(engine);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine2.list_keys().unwrap().len(), 1500);
+        for i in 0..2000 {
+            let res = engine2.get(get_test_key(i));
+            if i < 500 {
+                assert_eq!(res.err().unwrap(), Errors::KeyNotFound);
+            } else if i < 1000 {
+                assert_eq!(res.unwrap(), Bytes::from("new value in merge"));
+            } else {
+                assert_eq!(res.unwrap(), get_test_value(i));
+            }
+        }
+
+        std::mem::drop(engine2);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_merge_tombstone_retention() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-merge-tombstone");
+        opts.data_file_size = 64 * 1024;
+        opts.data_file_merge_ratio = 0 as f32;
+        opts.tombstone_retention = Some(time::Duration::from_secs(3600));
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..1000 {
+            assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        for i in 0..100 {
+            assert!(engine.delete(get_test_key(i)).is_ok());
+        }
+        assert!(engine.merge().is_ok());
+        std::mem::drop(engine);
+
+        // 保留时间之内的删除记录在 merge 之后仍然可以读取到，删除的 key 不会重新出现
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine.list_keys().unwrap().len(), 900);
+        let deletes = engine
+            .read_log_since(ReplicationCursor::default())
+            .unwrap()
+            .map(|change| change.unwrap())
+            .filter(|change| change.change_type == ChangeType::Delete)
+            .count();
+        assert_eq!(deletes, 100);
+        std::mem::drop(engine);
+
+        // 超过保留时间的删除记录在 merge 时清理
+        opts.tombstone_retention = Some(time::Duration::ZERO);
+        thread::sleep(time::Duration::from_millis(10));
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        assert!(engine.merge().is_ok());
+        std::mem::drop(engine);
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine.list_keys().unwrap().len(), 900);
+        let deletes = engine
+            .read_log_since(ReplicationCursor::default())
+            .unwrap()
+            .filter(|change| change.as_ref().unwrap().change_type == ChangeType::Delete)
+            .count();
+        assert_eq!(deletes, 0);
+
+        std::mem::drop(engine);
+        std::fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
 }
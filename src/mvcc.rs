@@ -1,145 +1,317 @@
 use std::{
-    collections::{HashMap, HashSet},
-    sync::{
-        atomic::{AtomicU64, Ordering},
-        Arc,
-    },
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs,
+    sync::{atomic::Ordering, mpsc, Arc},
+    thread,
+    time::{Duration, Instant},
 };
 
 use crate::errors::Result;
-use crate::{db::Engine, errors::Errors, options::IteratorOptions};
+use crate::{
+    data::{
+        data_file::{DataFile, MVCC_VERSION_FILE_NAME},
+        log_record::{LogRecord, LogRecordType},
+    },
+    db::{Engine, EngineInner, TransactionInfo},
+    errors::Errors,
+    options::{
+        IsolationLevel, IteratorOptions, KeyComparator, TransactionOptions, WriteBatchOptions,
+    },
+};
 
-use bytes::Bytes;
-use lazy_static::lazy_static;
-use log::error;
-use parking_lot::RwLock;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use log::{error, warn};
+use parking_lot::{Mutex, RwLock};
 
-use serde::{Deserialize, Serialize};
+/// MVCC 数据在存储引擎中的内部 key 前缀，以此开头的 key 对非事务接口不可见
+pub(crate) const MVCC_KEY_PREFIX: &[u8] = "\0bitcask-mvcc\0".as_bytes();
 
 /// MVCC 事务
-pub struct Transaction<'a> {
-    /// 底层 KV 存储引擎
-    engine: &'a Engine, // engine的引用必须比Iterator寿命长
+pub struct Transaction {
+    /// 底层 KV 存储引擎，事务存在期间不会关闭
+    engine: Engine,
     /// 事务版本号
     version: u64,
     /// 事务开启时的活跃事务列表，不包含自己
     active_xid: HashSet<u64>,
+    /// 事务的隔离级别
+    isolation: IsolationLevel,
+    /// 可串行化隔离级别下事务读取过的 key
+    read_keys: Mutex<HashSet<Vec<u8>>>,
+    /// 可串行化隔离级别下事务遍历过的前缀
+    read_prefixes: Mutex<Vec<Vec<u8>>>,
+    /// 事务中暂存的写入，原始 key -> 编码后的 value，提交时通过 WriteBatch 原子地写入
+    writes: Mutex<BTreeMap<Vec<u8>, Bytes>>,
+}
+
+const MVCC_VERSION_KEY: &str = "mvcc.version";
+
+// MVCC 数据 value 的第一个字节，标识这个版本是写入的数据还是删除标记
+const MVCC_VALUE_PUT: u8 = 1;
+const MVCC_VALUE_TOMBSTONE: u8 = 2;
+
+/// 活跃事务信息
+pub(crate) struct ActiveTxn {
+    /// 事务开启时的活跃事务列表，用于判断旧版本数据是否还被该事务需要
+    active_xid: HashSet<u64>,
+    /// 事务已经写入的 key
+    keys: HashSet<Vec<u8>>,
+    /// 事务开启的时间
+    start_time: Instant,
+}
+
+/// 按照固定的时间间隔回滚超过最大存活时间的事务，停止时丢弃 sender 通知线程退出
+pub(crate) struct TxnReaper {
+    sender: mpsc::Sender<()>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl TxnReaper {
+    pub(crate) fn start(
+        active_txn: Arc<RwLock<HashMap<u64, ActiveTxn>>>,
+        max_age: Duration,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel::<()>();
+        // 超时的事务最多在 max_age 的一半之后被回滚
+        let interval = (max_age / 2).max(Duration::from_millis(1));
+        let handle = thread::spawn(move || {
+            while let Err(mpsc::RecvTimeoutError::Timeout) = receiver.recv_timeout(interval) {
+                // 写入的数据在提交之前只暂存在事务中，从活跃事务列表中删除即可
+                active_txn.write().retain(|version, txn| {
+                    let expired = txn.start_time.elapsed() > max_age;
+                    if expired {
+                        warn!("transaction {} exceeds the max age, roll it back", version);
+                    }
+                    !expired
+                });
+            }
+        });
+        Self { sender, handle }
+    }
+
+    pub(crate) fn stop(self) {
+        drop(self.sender);
+        if self.handle.join().is_err() {
+            error!("transaction reaper thread panicked");
+        }
+    }
 }
 
 impl Engine {
     pub fn begin(&self) -> Transaction {
         Transaction::begin(self)
     }
+
+    /// 当前所有活跃的事务，按照版本号从小到大排列，用于排查长时间没有结束的事务
+    pub fn active_transactions(&self) -> Vec<TransactionInfo> {
+        let mut txns: Vec<TransactionInfo> = self
+            .active_txn
+            .read()
+            .iter()
+            .map(|(version, txn)| TransactionInfo {
+                version: *version,
+                age: txn.start_time.elapsed(),
+                write_key_num: txn.keys.len(),
+            })
+            .collect();
+        txns.sort_by_key(|txn| txn.version);
+        txns
+    }
+
+    /// 使用指定的配置开启事务
+    pub fn begin_with_options(&self, options: TransactionOptions) -> Transaction {
+        Transaction::begin_with_options(self, options)
+    }
+
+    /// 清理所有不再被任何事务可见的旧版本数据，merge 之前调用
+    pub(crate) fn gc_mvcc_versions(&self) -> Result<()> {
+        // 持有读锁，保证清理期间活跃事务列表不变
+        let active_txn = self.active_txn.read();
+
+        let mut iter = self.iter(IteratorOptions {
+            prefix: MVCC_KEY_PREFIX.to_vec(),
+            ..Default::default()
+        });
+        let mut raw_keys = BTreeSet::new();
+        while let Some((enc_key, _)) = iter.next() {
+            if let Some(key_version) = decode_key(&enc_key.to_vec()) {
+                raw_keys.insert(key_version.raw_key);
+            }
+        }
+
+        for raw_key in raw_keys {
+            gc_key_versions(self, &raw_key, &active_txn)?;
+        }
+
+        Ok(())
+    }
+
+    /// 加载 MVCC 事务版本号
+    /// 正常关闭时版本号会记录在文件中，异常退出时则根据已经写入的 MVCC 数据中最大的版本号恢复
+    /// 只持久化版本号，活跃事务列表不持久化：事务的写入在提交前只暂存在内存中，重启后未提交的事务直接丢弃
+    pub(crate) fn load_mvcc_version(&self) -> Result<()> {
+        let mut next_version = 1;
+
+        let mut iter = self.iter(IteratorOptions {
+            prefix: MVCC_KEY_PREFIX.to_vec(),
+            ..Default::default()
+        });
+        while let Some((enc_key, _)) = iter.next() {
+            if let Some(key_version) = decode_key(&enc_key.to_vec()) {
+                next_version = next_version.max(key_version.version + 1);
+            }
+        }
+
+        let version_file_path = self.options.dir_path.join(MVCC_VERSION_FILE_NAME);
+        if version_file_path.is_file() {
+            let version_file = DataFile::new_mvcc_version_file(self.options.dir_path.clone())?;
+            match version_file.read_log_record(0) {
+                Ok(res) => match String::from_utf8(res.record.value)
+                    .ok()
+                    .and_then(|v| v.parse::<u64>().ok())
+                {
+                    Some(version) => next_version = next_version.max(version),
+                    None => warn!("invalid mvcc version file, ignore it"),
+                },
+                Err(e) => warn!("failed to read mvcc version: {}", e),
+            }
+
+            // 加载后删掉，避免追加写入，只读模式下不改动数据目录中的文件
+            if !self.options.read_only {
+                if let Err(e) = fs::remove_file(version_file_path) {
+                    error!("failed to remove mvcc version file: {}", e);
+                }
+            }
+        }
+
+        self.mvcc_version.store(next_version, Ordering::SeqCst);
+        Ok(())
+    }
 }
 
-impl Transaction<'_> {
-    pub fn begin<'a>(engine: &'a Engine) -> Transaction {
+impl EngineInner {
+    /// 关闭时记录下一个 MVCC 事务版本号
+    pub(crate) fn save_mvcc_version(&self) -> Result<()> {
+        // 先删除旧的文件，保证文件中只有一条记录
+        let version_file_path = self.options.dir_path.join(MVCC_VERSION_FILE_NAME);
+        if version_file_path.is_file() {
+            if let Err(e) = fs::remove_file(version_file_path) {
+                error!("failed to remove mvcc version file: {}", e);
+            }
+        }
+
+        let version_file = DataFile::new_mvcc_version_file(self.options.dir_path.clone())?;
+        let version = self.mvcc_version.load(Ordering::SeqCst);
+        let record = LogRecord {
+            key: MVCC_VERSION_KEY.as_bytes().to_vec(),
+            value: version.to_string().into_bytes(),
+            rec_type: LogRecordType::NORMAL,
+            timestamp: 0,
+            value_pointer: false,
+        };
+        version_file.write(&record.encode())?;
+        version_file.sync()
+    }
+}
+
+impl Transaction {
+    pub fn begin(engine: &Engine) -> Transaction {
+        Transaction::begin_with_options(engine, TransactionOptions::default())
+    }
+
+    pub fn begin_with_options(engine: &Engine, options: TransactionOptions) -> Transaction {
         // 获取全局事务号
-        let version = acquire_next_version();
+        let version = engine.mvcc_version.fetch_add(1, Ordering::SeqCst);
 
-        let mut active_txn = ACTIVE_TXN.write();
+        let mut active_txn = engine.active_txn.write();
         // 这个 map 中的 key 就是当前所有的活跃事务
-        let active_xid = active_txn.keys().cloned().collect();
+        let active_xid: HashSet<u64> = active_txn.keys().cloned().collect();
 
         // 添加到当前活跃事务 id 列表中
-        active_txn.insert(version, vec![]);
+        active_txn.insert(
+            version,
+            ActiveTxn {
+                active_xid: active_xid.clone(),
+                keys: HashSet::new(),
+                start_time: Instant::now(),
+            },
+        );
 
         // 返回结果
         Transaction {
-            engine: engine,
+            engine: engine.clone(),
             version: version,
             active_xid: active_xid,
+            isolation: options.isolation,
+            read_keys: Mutex::new(HashSet::new()),
+            read_prefixes: Mutex::new(Vec::new()),
+            writes: Mutex::new(BTreeMap::new()),
         }
     }
 
-    /// 写入数据
+    /// 写入数据，value 可以为空
     pub fn put(&self, key: Bytes, value: Bytes) -> Result<()> {
         if key.is_empty() {
             return Err(Errors::KeyIsEmpty);
         }
-        if value.is_empty() {
-            return Err(Errors::ValueIsEmpty);
-        }
-
-        let txn_key = match self.txn_write(key) {
-            Ok(key) => key,
-            Err(e) => {
-                return Err(e);
-            }
-        };
 
-        self.engine.put(Bytes::from(txn_key.encode()), value)
+        self.txn_write(key, encode_value(Some(&value)))
     }
 
     /// 删除数据
-    /// put 一条 value 为空的数据，此限制了用户不能 put value 为空的数据
-    /// TODO: 真正的数据没被删除，需要在commit时候加入一条删除
+    /// 写入一个删除标记版本，旧版本在不再被任何事务可见之后由 gc 清理
     pub fn delete(&self, key: Bytes) -> Result<()> {
-        let txn_key = match self.txn_write(key) {
-            Ok(key) => key,
-            Err(e) => {
-                return Err(e);
-            }
-        };
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
 
-        self.engine
-            .put(Bytes::from(txn_key.encode()), Bytes::default())
+        self.txn_write(key, encode_value(None))
     }
 
-    fn txn_write(&self, key: Bytes) -> Result<Key> {
-        // 判断当前写入的 key 是否和其他的事务冲突
-        // key 是按照 key-version 排序的，所以只需要判断最近的一个 key 即可
-        let engine = self.engine;
-        let mut iter_opts = IteratorOptions::default();
-        iter_opts.reverse = true;
-        let mut iter = engine.iter(iter_opts);
-        while let Some((enc_key, _)) = iter.next() {
-            let key_version = decode_key(&enc_key.to_vec());
-            if key_version.raw_key.eq(&key.to_vec()) {
-                if !self.is_visible(key_version.version) {
-                    // 有一种情况是可以写入的
-                    // T1开启事务，写入了key1，还未提交。之后T2开启事务，此时T2是不能写入key1的，但是如果此时T1提交，T2是可以写入key1的，
-                    // 所以需要在这里判断下T1是否提交，已提交的事务版本号会从 ACTIVE_TXN 中删除，直接判断在不在其中即可
-                    let active_txn = ACTIVE_TXN.read();
-                    if !active_txn.contains_key(&key_version.version) {
-                        break;
-                    }
-                    return Err(Errors::MvccTxnWriteKeyConflictsWithOtherTransactions);
-                }
-                break;
-            }
+    fn txn_write(&self, key: Bytes, value: Bytes) -> Result<()> {
+        // 判断当前写入的 key 是否和其他的事务冲突，其他活跃事务已经写入过这个 key 时不能写入
+        // T1开启事务，写入了key1，还未提交。之后T2开启事务，此时T2是不能写入key1的，但是如果此时T1提交，T2是可以写入key1的，
+        // 已提交的事务会从活跃事务列表中删除，直接判断在不在其中即可
+        let mut active_txn = self.engine.active_txn.write();
+        let conflicted = active_txn
+            .iter()
+            .any(|(version, txn)| *version != self.version && txn.keys.contains(key.as_ref()));
+        if conflicted {
+            return Err(Errors::MvccTxnWriteKeyConflictsWithOtherTransactions);
         }
 
-        // 写入 TxnWrite
-        let mut active_txn = ACTIVE_TXN.write();
-        active_txn
-            .entry(self.version)
-            .and_modify(|keys| keys.push(key.to_vec()))
-            .or_insert_with(|| vec![key.to_vec()]);
-
-        // 写入数据
-        let enc_key = Key {
-            raw_key: key.to_vec(),
-            version: self.version,
+        // 写入 TxnWrite，已经回滚或者超时被回滚的事务不能继续写入
+        match active_txn.get_mut(&self.version) {
+            Some(txn) => txn.keys.insert(key.to_vec()),
+            None => return Err(Errors::MvccCommitActiveTxnIsNotExist),
         };
 
-        Ok(enc_key)
+        // 暂存数据，提交时再写入存储引擎
+        self.writes.lock().insert(key.to_vec(), value);
+        Ok(())
     }
 
-    /// 读取数据，从最后一条数据进行遍历，找到第一条可见的数据
+    /// 读取数据，从最新的版本开始遍历，找到第一条可见的数据
     pub fn get(&self, key: Bytes) -> Result<Bytes> {
-        let engine = self.engine;
-        let mut iter_opts = IteratorOptions::default();
-        iter_opts.reverse = true;
-        let mut iter = engine.iter(iter_opts);
+        if self.isolation == IsolationLevel::Serializable {
+            self.read_keys.lock().insert(key.to_vec());
+        }
+
+        // 优先读取当前事务自己暂存的写入
+        if let Some(value) = self.writes.lock().get(key.as_ref()) {
+            return decode_value(value.clone()).ok_or(Errors::KeyNotFound);
+        }
+
+        let engine = &self.engine;
+        let mut iter = engine.iter(key_versions_iter_options(&key));
         while let Some((enc_key, v)) = iter.next() {
-            let key_version = decode_key(&enc_key.to_vec());
+            let key_version = match decode_key(&enc_key.to_vec()) {
+                Some(key_version) => key_version,
+                None => continue,
+            };
             if key_version.raw_key.eq(&key.to_vec()) {
                 if self.is_visible(key_version.version) {
-                    if v.is_empty() {
-                        return Err(Errors::KeyNotFound);
-                    }
-                    return Ok(v);
+                    return decode_value(v).ok_or(Errors::KeyNotFound);
                 }
             }
         }
@@ -149,46 +321,187 @@ impl Transaction<'_> {
 
     /// 提交事务
     pub fn commit(&self) -> Result<()> {
+        self.engine.check_closed()?;
+
         // 清除活跃列表中的数据
-        let mut active_txn = ACTIVE_TXN.write();
-        match active_txn.remove(&self.version) {
-            Some(_) => {
-                return Ok(());
-            }
-            None => {
-                return Err(Errors::MvccCommitActiveTxnIsNotExist);
+        let mut active_txn = self.engine.active_txn.write();
+        if !active_txn.contains_key(&self.version) {
+            return Err(Errors::MvccCommitActiveTxnIsNotExist);
+        }
+
+        // 持有写锁校验读取过的数据以及写入，保证期间没有其他事务提交
+        if self.isolation == IsolationLevel::Serializable && self.is_read_invalidated() {
+            active_txn.remove(&self.version);
+            self.writes.lock().clear();
+            return Err(Errors::MvccTxnReadConflictsWithOtherTransactions);
+        }
+
+        // 所有的写入通过 WriteBatch 一起写入，宕机时没有完整写入的事务在重启时会被丢弃
+        // 写入失败时事务同样结束，已经写入的部分不会生效
+        let txn = active_txn.remove(&self.version).unwrap();
+        if let Err(e) = self.write_pending() {
+            self.writes.lock().clear();
+            return Err(e);
+        }
+        drop(active_txn);
+
+        // 清理写入的 key 不再被任何事务可见的旧版本，清理失败不影响事务提交
+        let active_txn = self.engine.active_txn.read();
+        for key in txn.keys.iter() {
+            if let Err(e) = gc_key_versions(&self.engine, key, &active_txn) {
+                error!("gc mvcc key versions failed, {}", e);
+                break;
             }
         }
+
+        Ok(())
+    }
+
+    // 将暂存的写入作为一个批次写入存储引擎
+    fn write_pending(&self) -> Result<()> {
+        let mut writes = self.writes.lock();
+        if writes.is_empty() {
+            return Ok(());
+        }
+
+        let batch = self.engine.new_write_batch(WriteBatchOptions {
+            max_batch_num: writes.len() as u64,
+            sync_writes: self.engine.runtime_options.sync_writes(),
+            merge_redundant_ops: false,
+        })?;
+        for (key, value) in std::mem::take(&mut *writes) {
+            let enc_key = Key {
+                raw_key: key,
+                version: self.version,
+            };
+            batch.put(Bytes::from(enc_key.encode()), value)?;
+        }
+        batch.commit()?;
+        Ok(())
     }
 
     /// 回滚事务
+    /// 写入的数据在提交之前只暂存在内存中，直接丢弃即可
     pub fn rollback(&self) -> Result<()> {
-        // 清除写入的数据
-        let mut active_txn = ACTIVE_TXN.write();
-        if let Some(keys) = active_txn.get(&self.version) {
-            let engine = self.engine;
-            for k in keys {
-                let enc_key = Key {
-                    raw_key: k.to_vec(),
-                    version: self.version,
-                };
-                match engine.delete(Bytes::from(enc_key.encode())) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        error!("engine delete data failed, {}", e);
-                        // 清除活跃事务列表中的数据
-                        active_txn.remove(&self.version);
-                        return Err(Errors::MvccRollbackDeleteDataFailed);
-                    }
-                }
-            }
-        }
+        self.writes.lock().clear();
 
         // 清除活跃事务列表中的数据
+        let mut active_txn = self.engine.active_txn.write();
         active_txn.remove(&self.version);
         Ok(())
     }
 
+    /// 返回事务内的迭代器，每个 key 只会遍历到当前事务可见的最新版本，已删除的 key 会被跳过
+    /// 当前事务自己写入但还未提交的数据同样可见
+    pub fn iter(&self, options: IteratorOptions) -> TxnIterator {
+        if self.isolation == IsolationLevel::Serializable {
+            self.read_prefixes.lock().push(options.prefix.clone());
+        }
+
+        let comparator = self.engine.options.key_comparator;
+        let mut prefix = MVCC_KEY_PREFIX.to_vec();
+        prefix.extend_from_slice(&options.prefix);
+        let mut iter = self.engine.iter(IteratorOptions {
+            prefix,
+            reverse: false,
+            ..Default::default()
+        });
+
+        // 每个 key 只保留当前事务可见的最新版本
+        let mut latest: BTreeMap<Vec<u8>, (u64, Bytes)> = BTreeMap::new();
+        while let Some((enc_key, value)) = iter.next() {
+            let key_version = match decode_key(&enc_key.to_vec()) {
+                Some(key_version) => key_version,
+                None => continue,
+            };
+            if !self.is_visible(key_version.version) {
+                continue;
+            }
+            // 编码之后的 key 带有版本号，上下界只能按照原始的 key 过滤
+            if !options.within_bounds(&key_version.raw_key, comparator) {
+                continue;
+            }
+            if let Some((version, _)) = latest.get(&key_version.raw_key) {
+                if *version > key_version.version {
+                    continue;
+                }
+            }
+            latest.insert(key_version.raw_key, (key_version.version, value));
+        }
+
+        // 当前事务暂存的写入是最新的版本
+        for (key, value) in self.writes.lock().iter() {
+            if key.starts_with(&options.prefix) && options.within_bounds(key, comparator) {
+                latest.insert(key.clone(), (self.version, value.clone()));
+            }
+        }
+
+        let mut items: Vec<(Bytes, Bytes)> = latest
+            .into_iter()
+            .filter_map(|(key, (_, value))| Some((Bytes::from(key), decode_value(value)?)))
+            .collect();
+        if let Some(comparator) = comparator {
+            items.sort_by(|a, b| comparator(&a.0, &b.0));
+        }
+        if options.reverse {
+            items.reverse();
+        }
+
+        TxnIterator {
+            items,
+            curr_index: 0,
+            reverse: options.reverse,
+            comparator,
+        }
+    }
+
+    // 读取过的 key 或者遍历过的前缀中，是否有事务开启之后其他事务提交的版本
+    // 有则说明当前事务读取到的数据已经过期，提交会破坏可串行化
+    fn is_read_invalidated(&self) -> bool {
+        let read_keys = self.read_keys.lock();
+        let invalidated_key = read_keys.iter().any(|key| {
+            self.has_invisible_committed_version(key_versions_iter_options(key), Some(key))
+        });
+        if invalidated_key {
+            return true;
+        }
+
+        let read_prefixes = self.read_prefixes.lock();
+        read_prefixes.iter().any(|prefix| {
+            let mut mvcc_prefix = MVCC_KEY_PREFIX.to_vec();
+            mvcc_prefix.extend_from_slice(prefix);
+            let options = IteratorOptions {
+                prefix: mvcc_prefix,
+                reverse: false,
+                ..Default::default()
+            };
+            self.has_invisible_committed_version(options, None)
+        })
+    }
+
+    // 遍历 MVCC 数据，判断是否有对当前事务不可见但已经提交的版本，raw_key 不为空时只判断这个 key
+    fn has_invisible_committed_version(
+        &self,
+        options: IteratorOptions,
+        raw_key: Option<&[u8]>,
+    ) -> bool {
+        let mut iter = self.engine.iter(options);
+        while let Some((enc_key, _)) = iter.next() {
+            let key_version = match decode_key(&enc_key.to_vec()) {
+                Some(key_version) => key_version,
+                None => continue,
+            };
+            if raw_key.is_some_and(|raw_key| key_version.raw_key != raw_key) {
+                continue;
+            }
+            // 事务提交之前不会写入存储引擎，存储引擎中的版本都已经提交
+            if !self.is_visible(key_version.version) {
+                return true;
+            }
+        }
+        false
+    }
+
     // 判断一个版本的数据对当前事务是否可见
     // 1. 如果是另一个活跃事务，则不可见
     // 2. 如果版本号比当前大，则不可见
@@ -200,34 +513,191 @@ impl Transaction<'_> {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Drop for Transaction {
+    // 没有提交或者回滚就被释放的事务自动回滚，避免一直留在活跃事务列表中阻塞其他事务的写入
+    fn drop(&mut self) {
+        if self
+            .engine
+            .active_txn
+            .write()
+            .remove(&self.version)
+            .is_some()
+        {
+            warn!(
+                "transaction {} is dropped without commit or rollback, roll it back",
+                self.version
+            );
+        }
+    }
+}
+
+/// 事务迭代器，数据在创建时按照事务的可见性规则确定
+pub struct TxnIterator {
+    items: Vec<(Bytes, Bytes)>, // 当前事务可见的 key/value，根据 key 进行排序过的
+    curr_index: usize,          // 当前遍历的下标
+    reverse: bool,              // 是否反向遍历
+    comparator: Option<KeyComparator>, // 自定义的 key 排序规则
+}
+
+impl TxnIterator {
+    /// 重新回到迭代器的起点，即第一个数据
+    pub fn rewind(&mut self) {
+        self.curr_index = 0;
+    }
+
+    /// 根据传入的 key 查找到第一个大于（或小于）等于的目标 key，从这个 key 开始遍历
+    pub fn seek(&mut self, key: Vec<u8>) {
+        self.curr_index = match self.items.binary_search_by(|(x, _)| {
+            let ord = match self.comparator {
+                Some(comparator) => comparator(x, &key),
+                None => x.as_ref().cmp(key.as_slice()),
+            };
+            if self.reverse {
+                ord.reverse()
+            } else {
+                ord
+            }
+        }) {
+            Ok(equal_value) => equal_value,
+            Err(insert_val) => insert_val,
+        };
+    }
+
+    /// 跳转到下一个 key，返回 None 说明遍历完成
+    pub fn next(&mut self) -> Option<(Bytes, Bytes)> {
+        let item = self.items.get(self.curr_index)?;
+        self.curr_index += 1;
+        Some(item.clone())
+    }
+}
+
+/// 清理某个 key 不再被任何事务可见的旧版本
+/// 需要保留的版本：最新的已提交版本，以及每个活跃事务当前能看到的版本，其余已提交的版本均可删除
+fn gc_key_versions(
+    engine: &Engine,
+    raw_key: &[u8],
+    active_txn: &HashMap<u64, ActiveTxn>,
+) -> Result<()> {
+    // 取出该 key 所有已提交的版本，从新到旧排列
+    let mut committed = Vec::new();
+    let mut iter = engine.iter(key_versions_iter_options(raw_key));
+    while let Some((enc_key, v)) = iter.next() {
+        if let Some(key_version) = decode_key(&enc_key.to_vec()) {
+            if key_version.raw_key == raw_key && !active_txn.contains_key(&key_version.version) {
+                committed.push((key_version.version, decode_value(v).is_none()));
+            }
+        }
+    }
+
+    if committed.is_empty() {
+        return Ok(());
+    }
+
+    let mut keep = HashSet::new();
+    keep.insert(committed[0].0);
+    for (version, txn) in active_txn.iter() {
+        if let Some((v, _)) = committed
+            .iter()
+            .find(|(v, _)| *v <= *version && !txn.active_xid.contains(v))
+        {
+            keep.insert(*v);
+        }
+    }
+
+    // 最新的版本是删除标记，且没有其他版本需要保留时，删除标记本身也可以清理掉
+    let (newest, is_deleted) = committed[0];
+    if is_deleted && keep.len() == 1 {
+        keep.remove(&newest);
+    }
+
+    for (version, _) in committed.iter() {
+        if keep.contains(version) {
+            continue;
+        }
+        let enc_key = Key {
+            raw_key: raw_key.to_vec(),
+            version: *version,
+        };
+        engine.delete(Bytes::from(enc_key.encode()))?;
+    }
+
+    Ok(())
+}
+
+/// 判断是否是 MVCC 内部使用的 key
+pub(crate) fn is_mvcc_key(key: &[u8]) -> bool {
+    key.starts_with(MVCC_KEY_PREFIX)
+}
+
+// 遍历某个 key 所有版本的迭代器配置，从新版本往旧版本遍历
+fn key_versions_iter_options(key: &[u8]) -> IteratorOptions {
+    let mut prefix = MVCC_KEY_PREFIX.to_vec();
+    prefix.extend_from_slice(key);
+
+    IteratorOptions {
+        prefix,
+        reverse: true,
+        ..Default::default()
+    }
+}
+
+#[derive(Debug)]
 struct Key {
     raw_key: Vec<u8>,
     version: u64,
 }
 
 impl Key {
+    /// 编码格式：内部前缀 + 原始 key + 大端序的版本号
+    /// 同一个 key 的不同版本在索引中按照版本号从小到大排序
     fn encode(&self) -> Vec<u8> {
-        bincode::serialize(self).unwrap()
+        let mut buf = BytesMut::new();
+        buf.put_slice(MVCC_KEY_PREFIX);
+        buf.put_slice(&self.raw_key);
+        buf.put_u64(self.version);
+        buf.to_vec()
     }
 }
 
-fn decode_key(b: &Vec<u8>) -> Key {
-    bincode::deserialize(&b).unwrap()
+/// 编码 MVCC 数据的 value：类型 + 用户写入的 value，删除标记没有 value
+fn encode_value(value: Option<&[u8]>) -> Bytes {
+    let mut buf = BytesMut::with_capacity(1 + value.map_or(0, |v| v.len()));
+    match value {
+        Some(value) => {
+            buf.put_u8(MVCC_VALUE_PUT);
+            buf.put_slice(value);
+        }
+        None => buf.put_u8(MVCC_VALUE_TOMBSTONE),
+    }
+    buf.freeze()
 }
 
-/// 全局递增的版本号
-static VERSION: AtomicU64 = AtomicU64::new(1);
-
-/// 获取下一个版本号
-fn acquire_next_version() -> u64 {
-    let version = VERSION.fetch_add(1, Ordering::SeqCst);
-    version
+/// 解码 MVCC 数据的 value，删除标记返回 None
+/// 旧版本写入的数据没有类型，空的 value 是删除标记，其余的原样返回
+fn decode_value(mut v: Bytes) -> Option<Bytes> {
+    match v.first() {
+        None => None,
+        Some(&MVCC_VALUE_TOMBSTONE) if v.len() == 1 => None,
+        Some(&MVCC_VALUE_PUT) => {
+            v.advance(1);
+            Some(v)
+        }
+        Some(_) => Some(v),
+    }
 }
 
-lazy_static! {
-  /// 当前活跃事务，包含当前活跃事务ID以及已经写入的key信息
-  static ref ACTIVE_TXN: Arc<RwLock<HashMap<u64, Vec<Vec<u8>>>>> = Arc::new(RwLock::new(HashMap::new()));
+fn decode_key(b: &Vec<u8>) -> Option<Key> {
+    if !is_mvcc_key(b) || b.len() < MVCC_KEY_PREFIX.len() + 8 {
+        return None;
+    }
+
+    let version_start = b.len() - 8;
+    let mut version = [0u8; 8];
+    version.copy_from_slice(&b[version_start..]);
+    Some(Key {
+        raw_key: b[MVCC_KEY_PREFIX.len()..version_start].to_vec(),
+        version: u64::from_be_bytes(version),
+    })
 }
 
 #[cfg(test)]
@@ -235,7 +705,10 @@ mod tests {
     use std::path::PathBuf;
 
     use super::*;
-    use crate::options::Options;
+    use crate::{
+        options::Options,
+        util::rand_kv::{get_test_key, get_test_value},
+    };
 
     #[test]
     fn test_mvcc_put() {
@@ -356,6 +829,219 @@ mod tests {
         std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
     }
 
+    #[test]
+    fn test_mvcc_empty_value() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-mvcc-empty-value");
+        opts.data_file_size = 64 * 1024 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        // 事务中可以写入空的 value，和删除的 key 可以区分开
+        let txn1 = engine.begin();
+        assert!(txn1.put(Bytes::from("key1"), Bytes::new()).is_ok());
+        assert!(txn1.put(Bytes::from("key2"), Bytes::from("value2")).is_ok());
+        assert_eq!(txn1.get(Bytes::from("key1")).unwrap(), Bytes::new());
+        assert!(txn1.commit().is_ok());
+
+        let txn2 = engine.begin();
+        assert_eq!(txn2.get(Bytes::from("key1")).unwrap(), Bytes::new());
+        assert!(txn2.delete(Bytes::from("key2")).is_ok());
+        assert_eq!(
+            txn2.get(Bytes::from("key2")).err().unwrap(),
+            Errors::KeyNotFound
+        );
+        let mut iter = txn2.iter(IteratorOptions::default());
+        assert_eq!(iter.next().unwrap(), (Bytes::from("key1"), Bytes::new()));
+        assert!(iter.next().is_none());
+        assert!(txn2.commit().is_ok());
+        assert_eq!(txn2.delete(Bytes::new()).err().unwrap(), Errors::KeyIsEmpty);
+
+        // 重启之后空的 value 依然可以读取到
+        std::mem::drop((txn1, txn2));
+        std::mem::drop(engine);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        let txn3 = engine2.begin();
+        assert_eq!(txn3.get(Bytes::from("key1")).unwrap(), Bytes::new());
+        assert_eq!(
+            txn3.get(Bytes::from("key2")).err().unwrap(),
+            Errors::KeyNotFound
+        );
+        assert!(txn3.commit().is_ok());
+
+        assert_eq!(encode_value(Some(b"")).len(), 1);
+        assert_eq!(decode_value(encode_value(None)), None);
+        assert_eq!(decode_value(Bytes::new()), None);
+        assert_eq!(
+            decode_value(Bytes::from("legacy")),
+            Some(Bytes::from("legacy"))
+        );
+
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_mvcc_serializable() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-mvcc-serializable");
+        opts.data_file_size = 64 * 1024 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        let serializable = || TransactionOptions {
+            isolation: IsolationLevel::Serializable,
+        };
+
+        let txn0 = engine.begin();
+        assert!(txn0.put(Bytes::from("x"), Bytes::from("0")).is_ok());
+        assert!(txn0.put(Bytes::from("y"), Bytes::from("0")).is_ok());
+        assert!(txn0.commit().is_ok());
+
+        // 写偏斜：两个事务分别读取对方写入的 key
+        let txn1 = engine.begin_with_options(serializable());
+        let txn2 = engine.begin_with_options(serializable());
+        assert_eq!(txn1.get(Bytes::from("x")).unwrap(), Bytes::from("0"));
+        assert_eq!(txn2.get(Bytes::from("y")).unwrap(), Bytes::from("0"));
+        assert!(txn1.put(Bytes::from("y"), Bytes::from("1")).is_ok());
+        assert!(txn2.put(Bytes::from("x"), Bytes::from("2")).is_ok());
+        assert!(txn1.commit().is_ok());
+        // txn2 读取的 y 已经被 txn1 修改，提交失败并回滚
+        assert_eq!(
+            txn2.commit().err().unwrap(),
+            Errors::MvccTxnReadConflictsWithOtherTransactions
+        );
+        let txn3 = engine.begin();
+        assert_eq!(txn3.get(Bytes::from("x")).unwrap(), Bytes::from("0"));
+        assert_eq!(txn3.get(Bytes::from("y")).unwrap(), Bytes::from("1"));
+        assert!(txn3.commit().is_ok());
+
+        // 快照隔离下允许写偏斜
+        let txn4 = engine.begin();
+        let txn5 = engine.begin();
+        assert!(txn4.get(Bytes::from("x")).is_ok());
+        assert!(txn5.get(Bytes::from("y")).is_ok());
+        assert!(txn4.put(Bytes::from("y"), Bytes::from("4")).is_ok());
+        assert!(txn5.put(Bytes::from("x"), Bytes::from("5")).is_ok());
+        assert!(txn4.commit().is_ok());
+        assert!(txn5.commit().is_ok());
+
+        // 遍历过的前缀中出现新写入的 key 也会冲突
+        let txn6 = engine.begin_with_options(serializable());
+        let txn7 = engine.begin();
+        let mut iter_opts = IteratorOptions::default();
+        iter_opts.prefix = "z".as_bytes().to_vec();
+        assert!(txn6.iter(iter_opts).next().is_none());
+        assert!(txn7.put(Bytes::from("z1"), Bytes::from("7")).is_ok());
+        assert!(txn7.commit().is_ok());
+        assert!(txn6.put(Bytes::from("w"), Bytes::from("6")).is_ok());
+        assert_eq!(
+            txn6.commit().err().unwrap(),
+            Errors::MvccTxnReadConflictsWithOtherTransactions
+        );
+
+        // 读取的数据没有被修改时正常提交
+        let txn8 = engine.begin_with_options(serializable());
+        assert_eq!(txn8.get(Bytes::from("x")).unwrap(), Bytes::from("5"));
+        assert!(txn8.get(Bytes::from("not-exist")).is_err());
+        assert!(txn8.put(Bytes::from("w"), Bytes::from("8")).is_ok());
+        assert!(txn8.commit().is_ok());
+
+        let txn9 = engine.begin();
+        assert_eq!(txn9.get(Bytes::from("w")).unwrap(), Bytes::from("8"));
+        assert!(txn9.commit().is_ok());
+
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_mvcc_commit_atomic() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-mvcc-commit-atomic");
+        opts.data_file_size = 64 * 1024 * 1024;
+
+        for fail_after in [0, 1, 2, 3] {
+            let engine =
+                crate::testing::run_with_write_fault(opts.clone(), fail_after, 8, |engine| {
+                    let txn = engine.begin();
+                    for i in 0..3 {
+                        assert!(txn.put(get_test_key(i), get_test_value(i)).is_ok());
+                    }
+                    // 提交之前数据不会写入存储引擎
+                    let mut iter_opts = IteratorOptions::default();
+                    iter_opts.prefix = MVCC_KEY_PREFIX.to_vec();
+                    assert!(engine.iter(iter_opts).next().is_none());
+                    assert!(txn.commit().is_err());
+                })
+                .expect("failed to recover engine");
+
+            // 提交失败的事务写入的数据全部不可见
+            let txn = engine.begin();
+            for i in 0..3 {
+                assert_eq!(txn.get(get_test_key(i)).err().unwrap(), Errors::KeyNotFound);
+            }
+            assert!(txn.commit().is_ok());
+
+            std::mem::drop(txn);
+            std::mem::drop(engine);
+            std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+        }
+    }
+
+    #[test]
+    fn test_mvcc_txn_leak() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-mvcc-txn-leak");
+        opts.data_file_size = 64 * 1024 * 1024;
+        opts.max_transaction_age = Some(Duration::ZERO);
+        assert_eq!(
+            Engine::open(opts.clone()).err().unwrap(),
+            Errors::InvaildMaxTransactionAge
+        );
+        opts.max_transaction_age = Some(Duration::from_millis(100));
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        // 释放没有提交的事务时自动回滚
+        {
+            let txn1 = engine.begin();
+            assert!(txn1.put(Bytes::from("key1"), Bytes::from("1")).is_ok());
+            let txns = engine.active_transactions();
+            assert_eq!(txns.len(), 1);
+            assert_eq!(txns[0].version, txn1.version);
+            assert_eq!(txns[0].write_key_num, 1);
+        }
+        assert!(engine.active_transactions().is_empty());
+        let txn2 = engine.begin();
+        assert!(txn2.put(Bytes::from("key1"), Bytes::from("2")).is_ok());
+        assert_eq!(txn2.get(Bytes::from("key1")).unwrap(), Bytes::from("2"));
+
+        // 超时的事务被后台回滚，不再阻塞其他事务的写入
+        let txn3 = engine.begin();
+        assert_eq!(
+            txn3.put(Bytes::from("key1"), Bytes::from("3"))
+                .err()
+                .unwrap(),
+            Errors::MvccTxnWriteKeyConflictsWithOtherTransactions
+        );
+        std::thread::sleep(Duration::from_millis(300));
+        assert!(engine.active_transactions().is_empty());
+        assert_eq!(
+            txn2.commit().err().unwrap(),
+            Errors::MvccCommitActiveTxnIsNotExist
+        );
+        assert_eq!(
+            txn3.put(Bytes::from("key1"), Bytes::from("3"))
+                .err()
+                .unwrap(),
+            Errors::MvccCommitActiveTxnIsNotExist
+        );
+        let txn4 = engine.begin();
+        assert!(txn4.put(Bytes::from("key1"), Bytes::from("4")).is_ok());
+        assert!(txn4.commit().is_ok());
+        assert_eq!(
+            engine.begin().get(Bytes::from("key1")).unwrap(),
+            Bytes::from("4")
+        );
+
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
     #[test]
     fn test_mvcc_rollback() {
         let mut opts = Options::default();
@@ -433,4 +1119,159 @@ mod tests {
 
         std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
     }
+
+    #[test]
+    fn test_mvcc_gc() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-mvcc-gc");
+        opts.data_file_size = 64 * 1024 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        let count_versions = |engine: &Engine| {
+            let mut iter_opts = IteratorOptions::default();
+            iter_opts.prefix = MVCC_KEY_PREFIX.to_vec();
+            let mut iter = engine.iter(iter_opts);
+            let mut count = 0;
+            while let Some(_) = iter.next() {
+                count += 1;
+            }
+            count
+        };
+
+        let txn1 = engine.begin();
+        assert!(txn1.put(Bytes::from("key1"), Bytes::from("1")).is_ok());
+        assert!(txn1.commit().is_ok());
+
+        // 旧事务还需要读取 key1 的第一个版本
+        let reader = engine.begin();
+
+        let txn2 = engine.begin();
+        assert!(txn2.put(Bytes::from("key1"), Bytes::from("2")).is_ok());
+        assert!(txn2.commit().is_ok());
+        let txn3 = engine.begin();
+        assert!(txn3.put(Bytes::from("key1"), Bytes::from("3")).is_ok());
+        assert!(txn3.commit().is_ok());
+        assert_eq!(count_versions(&engine), 2);
+
+        // MVCC 内部的 key 对普通接口不可见
+        assert!(engine.list_keys().unwrap().is_empty());
+        assert!(engine.iter(IteratorOptions::default()).next().is_none());
+
+        let get_reader_res1 = reader.get(Bytes::from("key1"));
+        assert_eq!(get_reader_res1.unwrap(), Bytes::from("1"));
+        assert!(reader.commit().is_ok());
+
+        // 旧事务结束之后，merge 时只保留最新的版本
+        let mut merge_opts = opts.clone();
+        merge_opts.data_file_merge_ratio = 0 as f32;
+        std::mem::drop((txn1, txn2, txn3, reader));
+        std::mem::drop(engine);
+        let engine2 = Engine::open(merge_opts.clone()).expect("failed to open engine");
+        assert!(engine2.merge().is_ok());
+
+        // 重启校验
+        std::mem::drop(engine2);
+        let engine2 = Engine::open(merge_opts).expect("failed to open engine");
+        assert_eq!(count_versions(&engine2), 1);
+
+        // 删除之后没有事务需要旧版本，删除标记也会被清理
+        let txn4 = engine2.begin();
+        assert!(txn4.delete(Bytes::from("key1")).is_ok());
+        assert!(txn4.commit().is_ok());
+        assert_eq!(count_versions(&engine2), 0);
+
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_mvcc_iter() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-mvcc-iter");
+        opts.data_file_size = 64 * 1024 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        let txn1 = engine.begin();
+        assert!(txn1.put(Bytes::from("iter-a1"), Bytes::from("a1")).is_ok());
+        assert!(txn1.put(Bytes::from("iter-a2"), Bytes::from("a2")).is_ok());
+        assert!(txn1.put(Bytes::from("iter-b1"), Bytes::from("b1")).is_ok());
+        assert!(txn1.commit().is_ok());
+
+        let txn2 = engine.begin();
+        let txn3 = engine.begin();
+        assert!(txn2.put(Bytes::from("iter-a3"), Bytes::from("a3")).is_ok());
+        assert!(txn2.put(Bytes::from("iter-a2"), Bytes::from("a22")).is_ok());
+        assert!(txn2.delete(Bytes::from("iter-a1")).is_ok());
+
+        // 事务内可以遍历到自己未提交的写入
+        let mut iter_opts1 = IteratorOptions::default();
+        iter_opts1.prefix = "iter-a".as_bytes().to_vec();
+        let mut iter1 = txn2.iter(iter_opts1);
+        let res1 = iter1.next().unwrap();
+        assert_eq!(res1, (Bytes::from("iter-a2"), Bytes::from("a22")));
+        let res2 = iter1.next().unwrap();
+        assert_eq!(res2, (Bytes::from("iter-a3"), Bytes::from("a3")));
+        assert!(iter1.next().is_none());
+
+        assert!(txn2.commit().is_ok());
+
+        // 其他事务遍历不到开启之后才提交的数据
+        let mut iter_opts2 = IteratorOptions::default();
+        iter_opts2.prefix = "iter-".as_bytes().to_vec();
+        iter_opts2.reverse = true;
+        let mut iter2 = txn3.iter(iter_opts2);
+        assert_eq!(iter2.next().unwrap().0, Bytes::from("iter-b1"));
+        let res3 = iter2.next().unwrap();
+        assert_eq!(res3, (Bytes::from("iter-a2"), Bytes::from("a2")));
+        assert_eq!(iter2.next().unwrap().0, Bytes::from("iter-a1"));
+        assert!(iter2.next().is_none());
+
+        iter2.seek("iter-a2".as_bytes().to_vec());
+        assert_eq!(iter2.next().unwrap().0, Bytes::from("iter-a2"));
+        assert!(txn3.commit().is_ok());
+
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_mvcc_version_persist() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-mvcc-version-persist");
+        opts.data_file_size = 64 * 1024 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        let txn1 = engine.begin();
+        assert!(txn1.put(Bytes::from("key1"), Bytes::from("1")).is_ok());
+        assert!(txn1.commit().is_ok());
+        let txn2 = engine.begin();
+        assert!(txn2.put(Bytes::from("key1"), Bytes::from("2")).is_ok());
+        assert!(txn2.commit().is_ok());
+        let last_version = txn2.version;
+
+        // 正常关闭后重启，版本号继续递增，之前提交的数据依然可见
+        engine.close().expect("failed to close");
+        std::mem::drop((txn1, txn2));
+        std::mem::drop(engine);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        let txn3 = engine2.begin();
+        assert!(txn3.version > last_version);
+        assert_eq!(txn3.get(Bytes::from("key1")).unwrap(), Bytes::from("2"));
+        assert!(txn3.put(Bytes::from("key1"), Bytes::from("3")).is_ok());
+        assert!(txn3.commit().is_ok());
+        let last_version = txn3.version;
+
+        // 模拟异常退出，版本号文件不存在时根据已写入的数据恢复
+        std::mem::drop(txn3);
+        std::mem::drop(engine2);
+        let _ = std::fs::remove_file(opts.dir_path.join(MVCC_VERSION_FILE_NAME));
+        let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+        let txn4 = engine3.begin();
+        assert!(txn4.version > last_version);
+        assert_eq!(txn4.get(Bytes::from("key1")).unwrap(), Bytes::from("3"));
+        assert!(txn4.commit().is_ok());
+
+        // 活跃事务列表属于各自的引擎实例
+        assert!(engine3.active_txn.read().is_empty());
+
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
 }
@@ -1,4 +1,14 @@
-use std::path::PathBuf;
+use std::{cmp::Ordering, collections::HashMap, path::PathBuf, time::Duration};
+
+#[cfg(feature = "object-store")]
+use std::sync::Arc;
+
+#[cfg(feature = "serde")]
+use crate::codec::SerdeCodec;
+use crate::data::data_file::DEFAULT_READ_CHUNK_SIZE;
+
+#[cfg(feature = "object-store")]
+pub use crate::fio::object_store::{LocalObjectStore, ObjectStore};
 
 #[derive(Clone)]
 pub struct Options {
@@ -8,22 +18,124 @@ pub struct Options {
     // 数据文件大小
     pub data_file_size: u64,
 
+    // 活跃文件的轮转策略，除了数据文件大小之外，记录数量或者写入时间达到上限时也切换到新的活跃文件，
+    // 可以限制启动时扫描单个数据文件的耗时，也方便按照时间清理旧的数据文件
+    pub file_rotation: FileRotation,
+
     // 是否每次写都持久化
     pub sync_writes: bool,
 
     // 累计写到多少字节后进行持久化
     pub bytes_per_sync: usize,
 
+    // 后台定期持久化活跃文件的时间间隔，宕机时最多丢失这段时间内写入的数据
+    pub sync_interval: Option<Duration>,
+
+    // 新建活跃文件时是否按照 data_file_size 预先分配磁盘空间，减少追加写入时的文件碎片和元数据更新，
+    // 只分配空间不改变文件大小，不支持预分配的平台和文件系统上忽略
+    pub preallocate: bool,
+
+    // 新建数据文件以及写入元数据文件之后是否持久化数据目录，
+    // 部分文件系统上不持久化目录的话，宕机之后新建的文件可能丢失
+    pub fsync_dir: bool,
+
     // 索引类型
     pub index_type: IndexType,
 
     // 是否用 mmap 打开数据库
     pub mmap_at_startup: bool,
 
+    // 读取一条记录时第一次读取的字节数，header 和 key/value 不超过这个大小时只需要一次 IO，
+    // 更大的记录再读取一次剩余的部分
+    pub read_chunk_size: usize,
+
     // 执行数据文件 merge 的阈值
     pub data_file_merge_ratio: f32,
+
+    // value 大于等于这个大小时单独存放到 blob 文件中，数据文件中只保存指向 blob 文件的位置，
+    // merge 时只需要重写位置信息，为空表示不分离存放
+    pub value_log_threshold: Option<usize>,
+
+    // 是否为旧数据文件维护 bloom filter，按照 key 扫描数据文件时跳过一定不包含这个 key 的文件
+    // bloom filter 存放在数据文件旁边，第一次使用时生成，merge 时为新的数据文件重新生成
+    pub bloom_filter: bool,
+
+    // merge 时并行处理数据文件的线程数
+    pub merge_threads: usize,
+
+    // merge 读写数据文件使用的 IO 类型，DirectIO 可以避免 merge 挤占页缓存，影响正常读取的延迟
+    // 只支持 StandardFIO 和 DirectIO
+    pub merge_io_type: IOType,
+
+    // 可以回收的数据量超过这个大小并且正在 merge 时对 put 进行限流，
+    // 避免持续的覆盖写在 merge 完成之前写满磁盘，为空表示不限流
+    pub write_stall_reclaim_size: Option<usize>,
+
+    // 触发限流时 put 的处理方式
+    pub write_stall_mode: WriteStallMode,
+
+    // 启动时并行扫描数据文件加载索引的线程数
+    pub load_index_threads: usize,
+
+    // 启动加载索引时遇到类型无法识别的记录的处理方式，默认打开失败
+    pub invalid_record_type: InvalidRecordTypeMode,
+
+    // 启动加载索引时校验数据文件中记录 CRC 的范围，不校验的文件只读取记录的 header 和 key，
+    // 可以加快打开的速度，损坏的记录在读取 value 时才会发现
+    pub startup_verify: StartupVerify,
+
+    // 是否以只读模式打开，只读模式不获取文件锁，也不允许写入
+    pub read_only: bool,
+
+    // 数据加密密钥，不为空时使用 AES-256-GCM 加密数据文件和 hint 文件中记录的 key 和 value
+    // B+ 树索引文件中的 key 无法加密，因此不能和 B+ 树索引一起使用
+    pub encryption_key: Option<[u8; 32]>,
+
+    // put_serde 和 get_serde 使用的序列化格式
+    #[cfg(feature = "serde")]
+    pub serde_codec: SerdeCodec,
+
+    // 事务开启之后超过这个时间没有提交或者回滚时，后台自动回滚，为空表示不限制
+    pub max_transaction_age: Option<Duration>,
+
+    // 自定义 key 的排序规则，索引、迭代器和 seek 都按照这个规则排序，为空时按照字节序排序
+    // 只支持 BTree 索引，比较结果为 Equal 的 key 会被当作同一个 key，因此必须是全序关系
+    // 前缀相同的 key 不一定相邻，按照前缀遍历时需要扫描整个索引
+    pub key_comparator: Option<KeyComparator>,
+
+    // 同时打开的旧数据文件数量上限，为空表示不限制
+    // 设置之后旧数据文件只在读取时打开，超过上限时关闭最近最少使用的文件，适合数据文件非常多的数据库
+    pub max_open_files: Option<usize>,
+
+    // merge 时保留写入时间在这段时间之内的删除记录，为空表示不保留
+    // 通过 read_log_since 等读取数据文件同步数据的下游落后时，可以避免删除记录被 merge 清理导致已经删除的 key 重新出现
+    pub tombstone_retention: Option<Duration>,
+
+    // 旧数据文件上传到的对象存储，为空表示不使用对象存储
+    #[cfg(feature = "object-store")]
+    pub object_store: Option<ObjectStoreOptions>,
 }
 
+/// 运行时可以通过 Engine::set_runtime_option 调整的配置项，修改立即生效，不需要重新打开数据库
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RuntimeOption {
+    /// 是否每次写都持久化
+    SyncWrites(bool),
+
+    /// 累计写到多少字节后进行持久化，0 表示不按照写入量持久化
+    BytesPerSync(usize),
+
+    /// 执行数据文件 merge 的阈值，取值范围 [0, 1]
+    DataFileMergeRatio(f32),
+
+    /// 对象存储读取缓存的容量，缩小时立即淘汰超出容量的缓存
+    #[cfg(feature = "object-store")]
+    ObjectStoreCacheSize(usize),
+}
+
+/// 比较两个 key 的大小，决定索引和迭代器中 key 的顺序
+pub type KeyComparator = fn(&[u8], &[u8]) -> Ordering;
+
 #[derive(Clone, PartialEq)]
 pub enum IndexType {
     /// BTree 索引
@@ -41,19 +153,104 @@ impl Default for Options {
         Self {
             dir_path: std::env::temp_dir().join("bitcask-rs"),
             data_file_size: 256 * 1024 * 1024, // 256MB
+            file_rotation: FileRotation::default(),
             sync_writes: false,
             bytes_per_sync: 0,
+            sync_interval: None,
+            preallocate: false,
+            fsync_dir: true,
             index_type: IndexType::BTree,
             mmap_at_startup: true,
+            read_chunk_size: DEFAULT_READ_CHUNK_SIZE,
             data_file_merge_ratio: 0.5,
+            value_log_threshold: None,
+            bloom_filter: false,
+            merge_threads: 1,
+            merge_io_type: IOType::StandardFIO,
+            write_stall_reclaim_size: None,
+            write_stall_mode: WriteStallMode::Delay(Duration::from_millis(10)),
+            load_index_threads: 1,
+            invalid_record_type: InvalidRecordTypeMode::Strict,
+            startup_verify: StartupVerify::Full,
+            read_only: false,
+            encryption_key: None,
+            #[cfg(feature = "serde")]
+            serde_codec: SerdeCodec::default(),
+            max_transaction_age: None,
+            key_comparator: None,
+            max_open_files: None,
+            tombstone_retention: None,
+            #[cfg(feature = "object-store")]
+            object_store: None,
         }
     }
 }
 
+/// 活跃文件的轮转策略，满足任意一个条件时切换到新的活跃文件
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FileRotation {
+    // 一个数据文件中最多写入的记录数量，为空表示不限制
+    pub max_records: Option<u64>,
+
+    // 数据文件从创建开始可以写入的最长时间，写入时检查，为空表示不限制
+    // 没有头部的旧数据文件无法得知创建时间，不按照时间轮转
+    pub max_age: Option<Duration>,
+}
+
+/// 写入限流时 put 的处理方式
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WriteStallMode {
+    /// 等待一段时间之后再写入，给 merge 留出时间
+    Delay(Duration),
+
+    /// 直接返回 WriteStalled 错误
+    Reject,
+}
+
+/// 加载索引时遇到类型无法识别的记录的处理方式
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InvalidRecordTypeMode {
+    /// 返回 DataFileCorrupted 错误，可以通过 Engine::repair 修复
+    Strict,
+
+    /// 按照 header 中的长度跳过这条记录，继续加载之后的记录
+    Skip,
+
+    /// 忽略这条记录以及同一个数据文件中之后的所有记录
+    Stop,
+}
+
+/// 启动加载索引时的校验级别，只影响需要扫描的数据文件，
+/// 已经通过 hint 文件加载过索引的数据文件不会被读取
+/// 加密的数据文件需要读取完整的记录才能解密 key，总是会校验
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StartupVerify {
+    /// 不校验 CRC，活跃文件末尾没有完整写入的记录只能通过长度发现
+    Off,
+
+    /// 只校验活跃文件，旧的数据文件已经写完并且持久化，不再校验
+    ChecksumActiveFile,
+
+    /// 校验所有扫描的数据文件，打开时发现所有损坏的记录
+    Full,
+}
+
 /// 索引迭代器配置项
 pub struct IteratorOptions {
     pub prefix: Vec<u8>,
     pub reverse: bool,
+
+    // 遍历范围的下界（包含），索引直接从这个位置开始遍历，不需要跳过前面的 key
+    pub lower_bound: Option<Vec<u8>>,
+
+    // 遍历范围的上界（不包含），遍历超过上界之后直接结束，不会继续遍历剩余的 key
+    pub upper_bound: Option<Vec<u8>>,
+
+    // 是否使用一致的快照遍历
+    // 默认创建迭代器时拷贝索引，遍历时再读取 value，数据文件被 merge 删除时会从索引中读取最新的 value，
+    // 因此可能读到创建迭代器之后写入的 value，或者跳过之后被删除的 key
+    // 设置为 true 时迭代器固定创建时的所有数据文件，所有的 value 都从这些文件中读取，返回的是创建时的数据
+    pub consistent: bool,
 }
 
 impl Default for IteratorOptions {
@@ -61,23 +258,146 @@ impl Default for IteratorOptions {
         Self {
             prefix: Default::default(),
             reverse: false,
+            lower_bound: None,
+            upper_bound: None,
+            consistent: false,
+        }
+    }
+}
+
+impl IteratorOptions {
+    /// key 是否在 lower_bound 和 upper_bound 的范围内，设置了自定义排序规则时按照排序规则比较
+    pub(crate) fn within_bounds(&self, key: &[u8], comparator: Option<KeyComparator>) -> bool {
+        let compare = |a: &[u8], b: &[u8]| match comparator {
+            Some(comparator) => comparator(a, b),
+            None => a.cmp(b),
+        };
+        if let Some(lower) = &self.lower_bound {
+            if compare(key, lower) == Ordering::Less {
+                return false;
+            }
+        }
+        if let Some(upper) = &self.upper_bound {
+            if compare(key, upper) != Ordering::Less {
+                return false;
+            }
         }
+        true
     }
 }
 
 /// 批量写配置项
 pub struct WriteBatchOptions {
     // 一个批次当中最大的数据量
-    pub max_batch_num: usize,
+    pub max_batch_num: u64,
+    // 一个批次当中暂存的 key 和 value 的最大字节数，put 和 delete 时检查，为空表示不限制
+    // 按照暂存的所有操作计算，包括提交时会被合并的冗余操作
+    pub max_batch_bytes: Option<u64>,
     // 提交时候是否进行 sync 持久化
     pub sync_writes: bool,
+    // 提交时候是否合并同一个 key 的冗余操作，只保留最后一次操作
+    pub merge_redundant_ops: bool,
 }
 
 impl Default for WriteBatchOptions {
     fn default() -> Self {
         Self {
             max_batch_num: 10000,
+            max_batch_bytes: None,
             sync_writes: true,
+            merge_redundant_ops: false,
+        }
+    }
+}
+
+/// MVCC 事务配置项
+pub struct TransactionOptions {
+    // 事务的隔离级别
+    pub isolation: IsolationLevel,
+}
+
+impl Default for TransactionOptions {
+    fn default() -> Self {
+        Self {
+            isolation: IsolationLevel::Snapshot,
+        }
+    }
+}
+
+/// MVCC 事务的隔离级别
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IsolationLevel {
+    /// 快照隔离，只检查写入的 key 是否和其他事务冲突
+    Snapshot,
+
+    /// 可串行化快照隔离，提交时额外检查读取过的数据是否已经被其他事务修改
+    Serializable,
+}
+
+/// 多租户管理器配置项
+#[derive(Clone)]
+pub struct ManagerOptions {
+    // 根目录，每个租户使用其中以租户命名的子目录
+    pub root_dir: PathBuf,
+    // 所有租户共用的存储引擎配置，dir_path 会被替换为租户的子目录
+    pub engine_options: Options,
+    // 后台依次对每个已经打开的租户尝试 merge 的时间间隔，为空表示不在后台 merge
+    pub merge_interval: Option<Duration>,
+}
+
+impl Default for ManagerOptions {
+    fn default() -> Self {
+        Self {
+            root_dir: std::env::temp_dir().join("bitcask-rs-tenants"),
+            engine_options: Options::default(),
+            merge_interval: None,
+        }
+    }
+}
+
+/// 列族配置项
+#[derive(Clone)]
+pub struct ColumnFamilyOptions {
+    // 根目录，每个列族使用其中以列族命名的子目录
+    pub root_dir: PathBuf,
+    // 没有单独配置的列族使用的存储引擎配置，dir_path 会被替换为列族的子目录
+    pub default_options: Options,
+    // 单独配置的列族，例如为存放大 value 的列族使用更大的数据文件以及不同的 merge 阈值
+    pub family_options: HashMap<String, Options>,
+}
+
+impl Default for ColumnFamilyOptions {
+    fn default() -> Self {
+        Self {
+            root_dir: std::env::temp_dir().join("bitcask-rs-column-families"),
+            default_options: Options::default(),
+            family_options: HashMap::new(),
+        }
+    }
+}
+
+/// 修复数据目录配置项
+pub struct RepairOptions {
+    // 数据目录使用的索引类型，B+ 树索引会重建持久化的索引文件
+    pub index_type: IndexType,
+    // 是否丢弃损坏的数据，不丢弃时遇到损坏的数据直接返回错误
+    pub drop_corrupted_records: bool,
+    // 是否重新生成 hint 索引文件
+    pub rebuild_hint_file: bool,
+    // 是否根据数据文件重置事务序列号
+    pub reset_seq_no: bool,
+    // 数据加密密钥，数据目录开启了加密时必须提供
+    pub encryption_key: Option<[u8; 32]>,
+}
+
+impl Default for RepairOptions {
+    fn default() -> Self {
+        Self {
+            index_type: IndexType::BTree,
+            drop_corrupted_records: true,
+            rebuild_hint_file: true,
+            reset_seq_no: true,
+            encryption_key: None,
         }
     }
 }
@@ -89,4 +409,44 @@ pub enum IOType {
 
     // 内存文件映射
     MemoryMap,
+
+    // 使用 O_DIRECT 读写文件，绕过操作系统的页缓存
+    DirectIO,
+
+    // 从对象存储中读取已经上传的数据文件，只读
+    #[cfg(feature = "object-store")]
+    ObjectStore,
+}
+
+/// 对象存储配置项，旧的数据文件不会再修改，上传到对象存储之后可以删除本地的文件，
+/// 读取时从对象存储中按块读取并缓存
+#[cfg(feature = "object-store")]
+#[derive(Clone)]
+pub struct ObjectStoreOptions {
+    // 对象存储
+    pub store: Arc<dyn ObjectStore>,
+    // 数据文件在对象存储中的 key 的前缀，多个数据目录共用一个对象存储时需要使用不同的前缀
+    pub prefix: String,
+    // 从对象存储中读取数据时按照这个大小分块读取并缓存
+    pub cache_chunk_size: usize,
+    // 缓存的数据量上限
+    pub cache_size: usize,
+    // merge 完成之后是否自动上传旧的数据文件
+    pub upload_after_merge: bool,
+    // 上传之后是否保留本地的数据文件，不保留时直接从对象存储中读取
+    pub keep_local_files: bool,
+}
+
+#[cfg(feature = "object-store")]
+impl ObjectStoreOptions {
+    pub fn new(store: Arc<dyn ObjectStore>) -> Self {
+        Self {
+            store,
+            prefix: String::new(),
+            cache_chunk_size: 64 * 1024,  // 64KB
+            cache_size: 64 * 1024 * 1024, // 64MB
+            upload_after_merge: true,
+            keep_local_files: false,
+        }
+    }
 }
@@ -0,0 +1,518 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use log::error;
+use parking_lot::{Mutex, RwLock};
+use prost::encoding::{decode_varint, encode_varint};
+
+use crate::{
+    db::{Engine, FILE_LOCK_NAME},
+    errors::{Errors, Result},
+    options::{Options, WriteBatchOptions},
+    util,
+};
+
+// 记录最近应用的 raft 日志位置的文件
+pub const RAFT_APPLIED_FILE_NAME: &str = "raft-applied";
+// 安装快照时的临时目录后缀：新数据先复制到 staging 目录，旧的数据目录先重命名为 old 目录
+const SNAPSHOT_STAGING_DIR_SUFFIX: &str = "snapshot-staging";
+const SNAPSHOT_OLD_DIR_SUFFIX: &str = "snapshot-old";
+
+const OP_PUT: u8 = 1;
+const OP_DELETE: u8 = 2;
+
+/// raft 日志的位置
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LogId {
+    pub term: u64,
+    pub index: u64,
+}
+
+/// 状态机命令中的单个操作，一条 raft 日志中的所有操作原子地写入
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    Put { key: Bytes, value: Bytes },
+    Delete { key: Bytes },
+}
+
+/// 将一条 raft 日志中的操作编码为字节数组，作为 raft 日志的内容
+/// +--------+----------------+----------+------------------+------------+
+/// | 操作数  |  类型（1字节）  | key 长度  |   value 长度      | key, value |
+/// +--------+----------------+----------+------------------+------------+
+///   变长                        变长       变长（仅 put）
+pub fn encode_command(ops: &[Operation]) -> Vec<u8> {
+    let mut buf = BytesMut::new();
+    encode_varint(ops.len() as u64, &mut buf);
+    for op in ops {
+        match op {
+            Operation::Put { key, value } => {
+                buf.put_u8(OP_PUT);
+                encode_varint(key.len() as u64, &mut buf);
+                encode_varint(value.len() as u64, &mut buf);
+                buf.put_slice(key);
+                buf.put_slice(value);
+            }
+            Operation::Delete { key } => {
+                buf.put_u8(OP_DELETE);
+                encode_varint(key.len() as u64, &mut buf);
+                buf.put_slice(key);
+            }
+        }
+    }
+    buf.to_vec()
+}
+
+/// 解码 raft 日志中的操作
+pub fn decode_command(mut buf: &[u8]) -> Result<Vec<Operation>> {
+    let count = decode_varint(&mut buf).map_err(|_| Errors::InvaildRaftCommand)?;
+    let mut ops = Vec::new();
+    for _ in 0..count {
+        if !buf.has_remaining() {
+            return Err(Errors::InvaildRaftCommand);
+        }
+        let op_type = buf.get_u8();
+        let key_len = decode_varint(&mut buf).map_err(|_| Errors::InvaildRaftCommand)? as usize;
+        let value_len = match op_type {
+            OP_PUT => decode_varint(&mut buf).map_err(|_| Errors::InvaildRaftCommand)? as usize,
+            OP_DELETE => 0,
+            _ => return Err(Errors::InvaildRaftCommand),
+        };
+        if buf.remaining() < key_len.saturating_add(value_len) {
+            return Err(Errors::InvaildRaftCommand);
+        }
+        let key = Bytes::copy_from_slice(&buf[..key_len]);
+        let value = Bytes::copy_from_slice(&buf[key_len..key_len + value_len]);
+        buf.advance(key_len + value_len);
+        let op = match op_type {
+            OP_PUT => Operation::Put { key, value },
+            _ => Operation::Delete { key },
+        };
+        ops.push(op);
+    }
+    if buf.has_remaining() {
+        return Err(Errors::InvaildRaftCommand);
+    }
+    Ok(ops)
+}
+
+/// 将存储引擎作为 raft 的状态机，提供应用日志、生成快照和安装快照的接口，
+/// 不依赖具体的 raft 实现，可以在 openraft 或者 raft-rs 的状态机接口中调用
+///
+/// 应用日志时不会立即持久化最近应用的日志位置，只在生成快照和关闭时写入，
+/// 重启之后 raft 会从记录的位置重新应用后面的日志，由于每条日志中都是覆盖写入的 put 和 delete，
+/// 按顺序重复应用已经应用过的日志得到的结果不变
+pub struct StateMachine {
+    options: Options,
+    engine: RwLock<Arc<Engine>>,
+    // 最近应用的日志位置，同时用于串行化应用日志、生成快照和安装快照
+    applied: Mutex<LogId>,
+}
+
+impl StateMachine {
+    /// 打开状态机，会先完成之前中断的快照安装
+    pub fn open(options: Options) -> Result<Self> {
+        recover_snapshot_install(&options.dir_path)?;
+        let engine = Engine::open(options.clone())?;
+        let applied = read_applied(&options.dir_path)?;
+        Ok(Self {
+            options,
+            engine: RwLock::new(Arc::new(engine)),
+            applied: Mutex::new(applied),
+        })
+    }
+
+    /// 当前的存储引擎实例，安装快照之后旧的实例会被关闭，需要重新获取
+    pub fn engine(&self) -> Arc<Engine> {
+        self.engine.read().clone()
+    }
+
+    /// 最近应用的日志位置
+    pub fn applied(&self) -> LogId {
+        *self.applied.lock()
+    }
+
+    /// 应用一条日志，已经应用过的日志直接跳过，操作为空的日志（例如成员变更）只更新应用位置
+    pub fn apply(&self, log_id: LogId, ops: &[Operation]) -> Result<()> {
+        let mut applied = self.applied.lock();
+        if log_id.index <= applied.index {
+            return Ok(());
+        }
+
+        if !ops.is_empty() {
+            let engine = self.engine.read();
+            let wb = engine.new_write_batch(WriteBatchOptions {
+                max_batch_num: ops.len() as u64,
+                max_batch_bytes: None,
+                // raft 日志本身已经持久化，状态机的写入不需要每次都 sync
+                sync_writes: false,
+                merge_redundant_ops: false,
+            })?;
+            for op in ops {
+                match op {
+                    Operation::Put { key, value } => wb.put(key.clone(), value.clone())?,
+                    Operation::Delete { key } => wb.delete(key.clone())?,
+                }
+            }
+            wb.commit()?;
+        }
+
+        *applied = log_id;
+        Ok(())
+    }
+
+    /// 解码并应用一条日志
+    pub fn apply_command(&self, log_id: LogId, command: &[u8]) -> Result<()> {
+        let ops = decode_command(command)?;
+        self.apply(log_id, &ops)
+    }
+
+    /// 将当前的状态机数据复制到 dest_dir 中作为快照，返回快照包含的最近应用的日志位置
+    pub fn snapshot(&self, dest_dir: PathBuf) -> Result<LogId> {
+        let applied = self.applied.lock();
+        let engine = self.engine.read();
+        engine.sync()?;
+        write_applied(&self.options.dir_path, *applied)?;
+        engine.backup(dest_dir)?;
+        Ok(*applied)
+    }
+
+    /// 安装 snapshot 生成的快照，替换整个数据目录，返回快照中最近应用的日志位置
+    /// 先将快照复制到临时目录，再通过重命名替换数据目录，中途崩溃的话下次打开时会继续完成或者回滚
+    pub fn install_snapshot(&self, snapshot_dir: PathBuf) -> Result<LogId> {
+        let mut applied = self.applied.lock();
+        let snapshot_applied = read_applied(&snapshot_dir)?;
+
+        let dir_path = self.options.dir_path.clone();
+        let staging_path = sibling_dir(&dir_path, SNAPSHOT_STAGING_DIR_SUFFIX);
+        let old_path = sibling_dir(&dir_path, SNAPSHOT_OLD_DIR_SUFFIX);
+        if staging_path.is_dir() {
+            fs::remove_dir_all(&staging_path).map_err(install_error)?;
+        }
+        util::file::copy_dir(snapshot_dir, staging_path.clone(), &[FILE_LOCK_NAME])
+            .map_err(install_error)?;
+        sync_files(&staging_path)?;
+
+        // 关闭当前的实例之后替换数据目录
+        let mut engine = self.engine.write();
+        engine.close()?;
+        fs::rename(&dir_path, &old_path).map_err(install_error)?;
+        fs::rename(&staging_path, &dir_path).map_err(install_error)?;
+        if let Some(parent) = dir_path.parent() {
+            util::file::sync_dir(parent).map_err(install_error)?;
+        }
+        fs::remove_dir_all(&old_path).map_err(install_error)?;
+
+        *engine = Arc::new(Engine::open(self.options.clone())?);
+        *applied = snapshot_applied;
+        Ok(snapshot_applied)
+    }
+
+    /// 持久化最近应用的日志位置并关闭存储引擎
+    pub fn close(&self) -> Result<()> {
+        let applied = self.applied.lock();
+        let engine = self.engine.read();
+        if engine.is_closed() {
+            return Ok(());
+        }
+        write_applied(&self.options.dir_path, *applied)?;
+        engine.close()
+    }
+}
+
+impl Drop for StateMachine {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            error!("error whiling close state machine: {}", e);
+        }
+    }
+}
+
+// 完成或者回滚之前中断的快照安装
+// 数据目录不存在说明已经重命名为 old 目录，此时 staging 目录已经完整，继续完成替换
+// 数据目录存在说明还没有开始替换，staging 目录可能不完整，直接删除
+fn recover_snapshot_install(dir_path: &Path) -> Result<()> {
+    let staging_path = sibling_dir(dir_path, SNAPSHOT_STAGING_DIR_SUFFIX);
+    let old_path = sibling_dir(dir_path, SNAPSHOT_OLD_DIR_SUFFIX);
+    if staging_path.is_dir() {
+        if dir_path.is_dir() {
+            fs::remove_dir_all(&staging_path).map_err(install_error)?;
+        } else {
+            fs::rename(&staging_path, dir_path).map_err(install_error)?;
+        }
+    }
+    if old_path.is_dir() && dir_path.is_dir() {
+        fs::remove_dir_all(&old_path).map_err(install_error)?;
+    }
+    Ok(())
+}
+
+// 读取最近应用的日志位置，文件不存在时从头开始应用
+fn read_applied(dir_path: &Path) -> Result<LogId> {
+    let path = dir_path.join(RAFT_APPLIED_FILE_NAME);
+    if !path.is_file() {
+        return Ok(LogId::default());
+    }
+    let content = fs::read(&path).map_err(|e| {
+        error!("failed to read raft applied file: {}", e);
+        Errors::read_failed(&path, 0, e)
+    })?;
+    if content.len() != 20 {
+        return Err(Errors::DataDirCorrupted);
+    }
+    let mut buf = &content[..];
+    let term = buf.get_u64();
+    let index = buf.get_u64();
+    if buf.get_u32() != crc32fast::hash(&content[..16]) {
+        return Err(Errors::DataDirCorrupted);
+    }
+    Ok(LogId { term, index })
+}
+
+// 通过临时文件和重命名原子地写入最近应用的日志位置
+// +---------+---------+---------+
+// |  term   |  index  |   crc   |
+// +---------+---------+---------+
+//   8字节      8字节     4字节
+fn write_applied(dir_path: &Path, applied: LogId) -> Result<()> {
+    let mut buf = BytesMut::with_capacity(20);
+    buf.put_u64(applied.term);
+    buf.put_u64(applied.index);
+    let crc = crc32fast::hash(&buf);
+    buf.put_u32(crc);
+
+    let path = dir_path.join(RAFT_APPLIED_FILE_NAME);
+    let temp_path = dir_path.join(format!("{}.tmp", RAFT_APPLIED_FILE_NAME));
+    let res = fs::write(&temp_path, &buf)
+        .and_then(|_| fs::File::open(&temp_path)?.sync_all())
+        .and_then(|_| fs::rename(&temp_path, &path))
+        .and_then(|_| util::file::sync_dir(dir_path));
+    res.map_err(|e| {
+        error!("failed to write raft applied file: {}", e);
+        Errors::write_failed(&path, e)
+    })
+}
+
+// 持久化目录中的所有文件以及目录本身
+fn sync_files(dir_path: &Path) -> Result<()> {
+    for entry in fs::read_dir(dir_path).map_err(install_error)? {
+        let path = entry.map_err(install_error)?.path();
+        if path.is_file() {
+            fs::File::open(path)
+                .and_then(|f| f.sync_all())
+                .map_err(install_error)?;
+        }
+    }
+    util::file::sync_dir(dir_path).map_err(install_error)
+}
+
+// 和数据目录同级的临时目录
+fn sibling_dir(dir_path: &Path, suffix: &str) -> PathBuf {
+    let file_name = dir_path.file_name().unwrap();
+    let name = format!("{}-{}", file_name.to_str().unwrap(), suffix);
+    dir_path.parent().unwrap().join(name)
+}
+
+fn install_error(e: std::io::Error) -> Errors {
+    error!("failed to install raft snapshot: {}", e);
+    Errors::FailedToInstallSnapshot
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::rand_kv::{get_test_key, get_test_value};
+
+    use super::*;
+
+    #[test]
+    fn test_command_codec() {
+        let ops = vec![
+            Operation::Put {
+                key: Bytes::from("key-1"),
+                value: Bytes::from("value-1"),
+            },
+            Operation::Delete {
+                key: Bytes::from("key-2"),
+            },
+            Operation::Put {
+                key: Bytes::from("key-3"),
+                value: Bytes::new(),
+            },
+        ];
+        let buf = encode_command(&ops);
+        assert_eq!(decode_command(&buf).unwrap(), ops);
+        assert_eq!(decode_command(&encode_command(&[])).unwrap(), vec![]);
+
+        // 截断或者多余的数据都是无效的命令
+        assert_eq!(
+            decode_command(&buf[..buf.len() - 1]).err().unwrap(),
+            Errors::InvaildRaftCommand
+        );
+        let mut extra = buf.clone();
+        extra.push(0);
+        assert_eq!(
+            decode_command(&extra).err().unwrap(),
+            Errors::InvaildRaftCommand
+        );
+    }
+
+    #[test]
+    fn test_state_machine() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-raft");
+        opts.data_file_size = 64 * 1024;
+        let sm = StateMachine::open(opts.clone()).expect("failed to open state machine");
+        assert_eq!(sm.applied(), LogId::default());
+
+        for i in 1..=100 {
+            let command = encode_command(&[
+                Operation::Put {
+                    key: get_test_key(i),
+                    value: get_test_value(i),
+                },
+                Operation::Delete {
+                    key: get_test_key(i - 1),
+                },
+            ]);
+            let res = sm.apply_command(
+                LogId {
+                    term: 1,
+                    index: i as u64,
+                },
+                &command,
+            );
+            assert!(res.is_ok());
+        }
+        // 已经应用过的日志会被跳过
+        let res = sm.apply(
+            LogId { term: 1, index: 50 },
+            &[Operation::Delete {
+                key: get_test_key(100),
+            }],
+        );
+        assert!(res.is_ok());
+        assert_eq!(
+            sm.applied(),
+            LogId {
+                term: 1,
+                index: 100
+            }
+        );
+        assert_eq!(sm.engine().list_keys().unwrap(), vec![get_test_key(100)]);
+
+        // 重启之后恢复最近应用的日志位置
+        sm.close().expect("failed to close state machine");
+        std::mem::drop(sm);
+        let sm = StateMachine::open(opts.clone()).expect("failed to open state machine");
+        assert_eq!(
+            sm.applied(),
+            LogId {
+                term: 1,
+                index: 100
+            }
+        );
+        assert_eq!(
+            sm.engine().get(get_test_key(100)).unwrap(),
+            get_test_value(100)
+        );
+
+        std::mem::drop(sm);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_state_machine_snapshot() {
+        let mut leader_opts = Options::default();
+        leader_opts.dir_path = PathBuf::from("/tmp/bitcask-rs-raft-leader");
+        let mut follower_opts = Options::default();
+        follower_opts.dir_path = PathBuf::from("/tmp/bitcask-rs-raft-follower");
+        let snapshot_dir = PathBuf::from("/tmp/bitcask-rs-raft-snapshot");
+
+        let leader = StateMachine::open(leader_opts.clone()).expect("failed to open leader");
+        for i in 1..=100 {
+            let ops = [Operation::Put {
+                key: get_test_key(i),
+                value: get_test_value(i),
+            }];
+            assert!(leader
+                .apply(
+                    LogId {
+                        term: 2,
+                        index: i as u64
+                    },
+                    &ops
+                )
+                .is_ok());
+        }
+        let snapshot_id = leader.snapshot(snapshot_dir.clone()).unwrap();
+        assert_eq!(
+            snapshot_id,
+            LogId {
+                term: 2,
+                index: 100
+            }
+        );
+
+        let follower = StateMachine::open(follower_opts.clone()).expect("failed to open follower");
+        let ops = [Operation::Put {
+            key: Bytes::from("stale"),
+            value: Bytes::from("value"),
+        }];
+        assert!(follower.apply(LogId { term: 1, index: 1 }, &ops).is_ok());
+        let old_engine = follower.engine();
+
+        // 安装快照之后数据被整体替换，旧的实例已经关闭
+        let installed = follower.install_snapshot(snapshot_dir.clone()).unwrap();
+        assert_eq!(installed, snapshot_id);
+        assert_eq!(follower.applied(), snapshot_id);
+        assert_eq!(
+            old_engine.get(Bytes::from("stale")).err().unwrap(),
+            Errors::DatabaseClosed
+        );
+        let engine = follower.engine();
+        assert_eq!(
+            engine.get(Bytes::from("stale")).err().unwrap(),
+            Errors::KeyNotFound
+        );
+        assert_eq!(engine.list_keys().unwrap().len(), 100);
+        for i in 1..=100 {
+            assert_eq!(engine.get(get_test_key(i)).unwrap(), get_test_value(i));
+        }
+        assert!(!sibling_dir(&follower_opts.dir_path, SNAPSHOT_STAGING_DIR_SUFFIX).exists());
+        assert!(!sibling_dir(&follower_opts.dir_path, SNAPSHOT_OLD_DIR_SUFFIX).exists());
+
+        std::mem::drop(engine);
+        std::mem::drop(follower);
+        std::mem::drop(leader);
+        std::fs::remove_dir_all(leader_opts.dir_path).expect("failed to remove path");
+        std::fs::remove_dir_all(follower_opts.dir_path).expect("failed to remove path");
+        std::fs::remove_dir_all(snapshot_dir).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_recover_snapshot_install() {
+        let dir_path = PathBuf::from("/tmp/bitcask-rs-raft-recover");
+        let staging_path = sibling_dir(&dir_path, SNAPSHOT_STAGING_DIR_SUFFIX);
+        let old_path = sibling_dir(&dir_path, SNAPSHOT_OLD_DIR_SUFFIX);
+
+        // 数据目录已经被重命名，继续完成替换
+        fs::create_dir_all(&staging_path).unwrap();
+        fs::create_dir_all(&old_path).unwrap();
+        fs::write(staging_path.join("new"), b"new").unwrap();
+        recover_snapshot_install(&dir_path).unwrap();
+        assert!(dir_path.join("new").is_file());
+        assert!(!staging_path.exists());
+        assert!(!old_path.exists());
+
+        // 还没有开始替换，丢弃不完整的 staging 目录
+        fs::create_dir_all(&staging_path).unwrap();
+        recover_snapshot_install(&dir_path).unwrap();
+        assert!(dir_path.join("new").is_file());
+        assert!(!staging_path.exists());
+
+        std::fs::remove_dir_all(dir_path).expect("failed to remove path");
+    }
+}
@@ -0,0 +1,527 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, OpenOptions},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use fs2::FileExt;
+use log::{error, warn};
+
+use crate::{
+    batch::{parse_log_record_key, NON_TRANSACTION_SEQ_NO},
+    data::{
+        cipher::load_cipher,
+        data_file::{
+            get_data_file_name, DataFile, DATA_FILE_HEADER_SIZE, DATA_FILE_MAGIC,
+            DATA_FILE_NAME_SUFFIX, HINT_FILE_NAME, MANIFEST_FILE_NAME, MERGE_FIN_FILE_NAME,
+            STATS_FILE_NAME,
+        },
+        log_record::{LogRecordPos, LogRecordType, TransactionRecord},
+    },
+    db::{load_data_files, write_seq_no_file, DirRegistration, Engine, RepairStat, FILE_LOCK_NAME},
+    errors::{Errors, Result},
+    index,
+    merge::{load_merge_files, write_merge_fin_file},
+    options::{IndexType, RepairOptions},
+};
+
+impl Engine {
+    /// 修复损坏的数据目录
+    /// 重新扫描所有的数据文件，丢弃无法读取的数据，并重建索引、hint 文件以及事务序列号
+    /// 数据文件中的记录没有分隔标识，所以同一个文件中损坏位置之后的数据也会一起被丢弃
+    pub fn repair(dir_path: PathBuf, options: RepairOptions) -> Result<RepairStat> {
+        if !dir_path.is_dir() {
+            return Err(Errors::FailedToReadDatabaseDir);
+        }
+
+        // 修复期间持有文件锁，避免数据目录被其他实例打开
+        let _dir_registration = DirRegistration::register(&dir_path)?;
+        let lock_path = dir_path.join(FILE_LOCK_NAME);
+        let lock_file = match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&lock_path)
+        {
+            Ok(file) => file,
+            Err(e) => {
+                error!("open lock file err: {}", e);
+                return Err(Errors::open_failed(&lock_path, e));
+            }
+        };
+        if let Err(e) = lock_file.try_lock_exclusive() {
+            error!("get lock file err: {}", e);
+            return Err(Errors::DatabaseIsUsing);
+        }
+
+        let res = repair_dir(dir_path, options);
+
+        if let Err(e) = lock_file.unlock() {
+            error!("release lock file err: {}", e);
+        }
+        res
+    }
+}
+
+fn repair_dir(dir_path: PathBuf, options: RepairOptions) -> Result<RepairStat> {
+    // 先处理上一次 merge 留下的数据
+    load_merge_files(dir_path.clone())?;
+
+    // 修复之后以数据目录中的数据文件为准，下次打开时重新生成 manifest
+    remove_file_if_exists(dir_path.join(MANIFEST_FILE_NAME))?;
+
+    // 删除创建时头部没有完整写入的数据文件
+    let (torn_file_num, torn_bytes) = match options.drop_corrupted_records {
+        true => remove_torn_data_files(dir_path.clone())?,
+        false => (0, 0),
+    };
+
+    // 校验加密密钥，加密的数据文件需要解密之后才能重放
+    let cipher =
+        load_cipher(dir_path.clone(), options.encryption_key.as_ref(), false)?.map(Arc::new);
+
+    let data_files = load_data_files(dir_path.clone(), false, cipher.clone(), None)?;
+    let mut stat = RepairStat {
+        data_file_num: data_files.len(),
+        corrupted_file_num: torn_file_num,
+        dropped_bytes: torn_bytes,
+        ..Default::default()
+    };
+
+    // 按照启动时加载索引的规则重放所有有效的数据
+    let mut keys: BTreeMap<Vec<u8>, LogRecordPos> = BTreeMap::new();
+    let mut transaction_records: HashMap<u64, Vec<TransactionRecord>> = HashMap::new();
+    let mut current_seq_no = NON_TRANSACTION_SEQ_NO;
+    for data_file in data_files.iter() {
+        let file_id = data_file.get_file_id();
+        let mut offset = data_file.get_header_size();
+        loop {
+            let (mut log_record, size) = match data_file.read_log_record(offset) {
+                Ok(result) => (result.record, result.size),
+                Err(Errors::ReadDataFileEof) => break,
+                Err(Errors::InvaildLogRecordCrc) | Err(Errors::InvalidRecordType(_)) => {
+                    if !options.drop_corrupted_records {
+                        return Err(Errors::DataFileCorrupted { file_id, offset });
+                    }
+
+                    // 截断损坏位置之后的数据
+                    let dropped = truncate_data_file(dir_path.clone(), file_id, offset)?;
+                    warn!(
+                        "data file {} is corrupted at offset {}, drop {} bytes",
+                        file_id, offset, dropped
+                    );
+                    stat.corrupted_file_num += 1;
+                    stat.dropped_bytes += dropped;
+                    break;
+                }
+                Err(e) => return Err(e),
+            };
+
+            let log_record_pos = LogRecordPos {
+                file_id,
+                offset,
+                size: size as u64,
+            };
+
+            let (real_key, seq_no) = parse_log_record_key(log_record.key.clone())
+                .map_err(|_| Errors::DataFileCorrupted { file_id, offset })?;
+            if seq_no == NON_TRANSACTION_SEQ_NO {
+                update_keys(&mut keys, real_key, log_record.rec_type, log_record_pos);
+            } else if log_record.rec_type == LogRecordType::TxnFinished {
+                if let Some(records) = transaction_records.remove(&seq_no) {
+                    for txn_record in records {
+                        update_keys(
+                            &mut keys,
+                            txn_record.record.key,
+                            txn_record.record.rec_type,
+                            txn_record.pos,
+                        );
+                    }
+                }
+            } else {
+                log_record.key = real_key;
+                transaction_records
+                    .entry(seq_no)
+                    .or_default()
+                    .push(TransactionRecord {
+                        record: log_record,
+                        pos: log_record_pos,
+                    });
+            }
+
+            if seq_no > current_seq_no {
+                current_seq_no = seq_no;
+            }
+
+            offset += size as u64;
+        }
+    }
+    stat.key_num = keys.len();
+    stat.seq_no = current_seq_no + 1;
+
+    // 旧的 hint 文件和 merge 完成标识可能指向被丢弃的数据，统计信息也已经过期，全部删除
+    remove_file_if_exists(dir_path.join(HINT_FILE_NAME))?;
+    remove_file_if_exists(dir_path.join(MERGE_FIN_FILE_NAME))?;
+    remove_file_if_exists(dir_path.join(STATS_FILE_NAME))?;
+
+    // 除最新的数据文件之外，其余文件中数据的索引写入 hint 文件，启动时只需要重放最新的数据文件
+    if options.rebuild_hint_file && data_files.len() > 1 {
+        let active_file_id = data_files.last().unwrap().get_file_id();
+
+        let hint_file = DataFile::new_hint_file(dir_path.clone(), cipher.clone())?;
+        for (key, pos) in keys.iter() {
+            if pos.file_id < active_file_id {
+                hint_file.write_hint_record(key.clone(), *pos)?;
+            }
+        }
+        hint_file.sync()?;
+
+        write_merge_fin_file(dir_path.clone(), active_file_id, true)?;
+    }
+
+    // B+ 树索引是持久化的，需要根据修复后的数据重建
+    if options.index_type == IndexType::BPTree {
+        let indexer = index::new_indexer(IndexType::BPTree, dir_path.clone(), None);
+        indexer.clear();
+        for (key, pos) in keys {
+            indexer.put(key, pos);
+        }
+    }
+
+    // 重置事务序列号
+    if options.reset_seq_no {
+        write_seq_no_file(dir_path.clone(), stat.seq_no, true)?;
+    }
+
+    Ok(stat)
+}
+
+// 重放数据时更新 key 对应的位置信息
+fn update_keys(
+    keys: &mut BTreeMap<Vec<u8>, LogRecordPos>,
+    key: Vec<u8>,
+    rec_type: LogRecordType,
+    pos: LogRecordPos,
+) {
+    if rec_type == LogRecordType::NORMAL {
+        keys.insert(key, pos);
+    } else if rec_type == LogRecordType::DELETE {
+        keys.remove(&key);
+    }
+}
+
+// 将数据文件截断到指定的位置，返回被丢弃的数据大小
+fn truncate_data_file(dir_path: PathBuf, file_id: u64, offset: u64) -> Result<u64> {
+    let path = get_data_file_name(dir_path, file_id);
+    let file = match OpenOptions::new().write(true).open(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            error!("open data file err: {}", e);
+            return Err(Errors::open_failed(&path, e));
+        }
+    };
+
+    let size = match file.metadata() {
+        Ok(metadata) => metadata.len(),
+        Err(e) => {
+            error!("read data file metadata err: {}", e);
+            return Err(Errors::read_failed(&path, 0, e));
+        }
+    };
+
+    if let Err(e) = file.set_len(offset) {
+        error!("truncate data file err: {}", e);
+        return Err(Errors::write_failed(&path, e));
+    }
+    if let Err(e) = file.sync_all() {
+        error!("sync data file err: {}", e);
+        return Err(Errors::sync_failed(&path, e));
+    }
+
+    Ok(size.saturating_sub(offset))
+}
+
+// 创建数据文件时宕机，文件中只有不完整的头部，还没有任何记录，直接删除
+// 返回删除的文件数量以及数据大小
+fn remove_torn_data_files(dir_path: PathBuf) -> Result<(usize, u64)> {
+    let dir = match fs::read_dir(dir_path.clone()) {
+        Ok(dir) => dir,
+        Err(e) => {
+            error!("read database dir err: {}", e);
+            return Err(Errors::FailedToReadDatabaseDir);
+        }
+    };
+
+    let mut removed = (0, 0);
+    for entry in dir.flatten() {
+        let file_name = entry.file_name();
+        if !file_name.to_string_lossy().ends_with(DATA_FILE_NAME_SUFFIX) {
+            continue;
+        }
+        let content = match fs::read(entry.path()) {
+            Ok(content) => content,
+            Err(e) => {
+                error!("read data file err: {}", e);
+                return Err(Errors::read_failed(&entry.path(), 0, e));
+            }
+        };
+        let magic_len = content.len().min(DATA_FILE_MAGIC.len());
+        if content.is_empty()
+            || content.len() as u64 >= DATA_FILE_HEADER_SIZE
+            || content[..magic_len] != DATA_FILE_MAGIC[..magic_len]
+        {
+            continue;
+        }
+
+        warn!(
+            "data file {:?} has a torn header, drop {} bytes",
+            file_name,
+            content.len()
+        );
+        remove_file_if_exists(entry.path())?;
+        removed.0 += 1;
+        removed.1 += content.len() as u64;
+    }
+    Ok(removed)
+}
+
+fn remove_file_if_exists(path: PathBuf) -> Result<()> {
+    if !path.is_file() {
+        return Ok(());
+    }
+    if let Err(e) = fs::remove_file(&path) {
+        error!("remove file err: {}", e);
+        return Err(Errors::write_failed(&path, e));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Seek, SeekFrom, Write};
+
+    use crate::{
+        data::log_record::LogRecord,
+        manifest::Manifest,
+        options::Options,
+        util::rand_kv::{get_test_key, get_test_value},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_repair_partial_write() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-repair-partial-write");
+        opts.data_file_size = 64 * 1024 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..100 {
+            let put_res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(put_res.is_ok());
+        }
+        engine.close().expect("failed to close");
+        std::mem::drop(engine);
+
+        // 模拟最后一条数据只写入了一半
+        let data_file_name = get_data_file_name(opts.dir_path.clone(), 0);
+        let valid_size = fs::metadata(data_file_name.clone()).unwrap().len();
+        let record = LogRecord {
+            key: get_test_key(100).to_vec(),
+            value: get_test_value(100).to_vec(),
+            rec_type: LogRecordType::NORMAL,
+            timestamp: 0,
+            value_pointer: false,
+        };
+        let enc_record = record.encode();
+        let partial = &enc_record[..enc_record.len() / 2];
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(data_file_name.clone())
+            .unwrap();
+        file.write_all(partial).unwrap();
+        std::mem::drop(file);
+
+        // 打开时返回具体损坏的位置
+        let open_res = Engine::open(opts.clone());
+        assert_eq!(
+            open_res.err().unwrap(),
+            Errors::DataFileCorrupted {
+                file_id: 0,
+                offset: valid_size
+            }
+        );
+
+        // 不丢弃数据时只返回错误
+        let repair_res1 = Engine::repair(
+            opts.dir_path.clone(),
+            RepairOptions {
+                drop_corrupted_records: false,
+                ..Default::default()
+            },
+        );
+        assert!(repair_res1.is_err());
+
+        let stat = Engine::repair(opts.dir_path.clone(), RepairOptions::default())
+            .expect("failed to repair");
+        assert_eq!(stat.data_file_num, 1);
+        assert_eq!(stat.key_num, 100);
+        assert_eq!(stat.corrupted_file_num, 1);
+        assert_eq!(stat.dropped_bytes, partial.len() as u64);
+        assert_eq!(fs::metadata(data_file_name).unwrap().len(), valid_size);
+
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine2.list_keys().unwrap().len(), 100);
+        for i in 0..100 {
+            assert_eq!(engine2.get(get_test_key(i)).unwrap(), get_test_value(i));
+        }
+        let put_res = engine2.put(get_test_key(100), get_test_value(100));
+        assert!(put_res.is_ok());
+
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_repair_torn_header() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-repair-torn-header");
+        opts.data_file_size = 64 * 1024 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..10 {
+            assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        engine.close().expect("failed to close");
+        std::mem::drop(engine);
+
+        // 模拟切换数据文件时宕机，新文件已经记录到 manifest 中，但头部只写入了一部分
+        let mut files = Manifest::read(&opts.dir_path).unwrap().unwrap();
+        files.insert(1, None);
+        Manifest::create(opts.dir_path.clone(), files, false, true).unwrap();
+        let torn_file_name = get_data_file_name(opts.dir_path.clone(), 1);
+        fs::write(torn_file_name.clone(), &DATA_FILE_MAGIC[..3]).unwrap();
+        assert_eq!(
+            Engine::open(opts.clone()).err().unwrap(),
+            Errors::DataFileCorrupted {
+                file_id: 1,
+                offset: 0
+            }
+        );
+
+        let stat = Engine::repair(opts.dir_path.clone(), RepairOptions::default())
+            .expect("failed to repair");
+        assert_eq!(stat.data_file_num, 1);
+        assert_eq!(stat.key_num, 10);
+        assert_eq!(stat.corrupted_file_num, 1);
+        assert_eq!(stat.dropped_bytes, 3);
+        assert!(!torn_file_name.is_file());
+
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine2.list_keys().unwrap().len(), 10);
+
+        std::mem::drop(engine2);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_repair_corrupted_older_file() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-repair-corrupted-older-file");
+        opts.data_file_size = 32 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..2000 {
+            let put_res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(put_res.is_ok());
+        }
+        for i in 0..100 {
+            let del_res = engine.delete(get_test_key(i));
+            assert!(del_res.is_ok());
+        }
+        engine.close().expect("failed to close");
+        std::mem::drop(engine);
+
+        // 破坏第二个数据文件中间的一个字节
+        let data_file_name = get_data_file_name(opts.dir_path.clone(), 1);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(data_file_name)
+            .unwrap();
+        file.seek(SeekFrom::Start(16 * 1024)).unwrap();
+        file.write_all(&[0xff, 0xff, 0xff, 0xff]).unwrap();
+        std::mem::drop(file);
+
+        let open_res = Engine::open(opts.clone());
+        assert!(matches!(
+            open_res.err().unwrap(),
+            Errors::DataFileCorrupted { file_id: 1, .. }
+        ));
+
+        let stat = Engine::repair(opts.dir_path.clone(), RepairOptions::default())
+            .expect("failed to repair");
+        assert_eq!(stat.corrupted_file_num, 1);
+        assert!(stat.dropped_bytes > 0);
+        assert!(stat.key_num < 1900);
+        assert!(opts.dir_path.join(HINT_FILE_NAME).is_file());
+
+        // 重启校验，损坏位置之前以及其他文件中的数据都可以正常读取
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        let keys = engine2.list_keys().unwrap();
+        assert_eq!(keys.len(), stat.key_num);
+        for i in 0..100 {
+            assert!(engine2.get(get_test_key(i)).is_err());
+        }
+        assert_eq!(
+            engine2.get(get_test_key(1999)).unwrap(),
+            get_test_value(1999)
+        );
+        for key in keys {
+            assert!(engine2.get(key).is_ok());
+        }
+
+        let put_res = engine2.put(get_test_key(2000), get_test_value(2000));
+        assert!(put_res.is_ok());
+        std::mem::drop(engine2);
+
+        let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine3.list_keys().unwrap().len(), stat.key_num + 1);
+
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_repair_encrypted() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-repair-encrypted");
+        opts.data_file_size = 32 * 1024;
+        opts.encryption_key = Some([3; 32]);
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..1000 {
+            let put_res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(put_res.is_ok());
+        }
+        engine.close().expect("failed to close");
+        std::mem::drop(engine);
+
+        // 加密的数据目录需要提供密钥
+        let repair_res = Engine::repair(opts.dir_path.clone(), RepairOptions::default());
+        assert_eq!(repair_res.err().unwrap(), Errors::EncryptionKeyRequired);
+
+        let repair_opts = RepairOptions {
+            encryption_key: opts.encryption_key,
+            ..Default::default()
+        };
+        let stat = Engine::repair(opts.dir_path.clone(), repair_opts).unwrap();
+        assert_eq!(stat.key_num, 1000);
+        assert_eq!(stat.corrupted_file_num, 0);
+
+        // 重新生成的 hint 文件同样是加密的
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine2.list_keys().unwrap().len(), 1000);
+        assert_eq!(engine2.get(get_test_key(10)).unwrap(), get_test_value(10));
+        let hint_file = DataFile::new_hint_file(opts.dir_path.clone(), engine2.cipher.clone());
+        assert!(hint_file.unwrap().is_encrypted());
+
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+}
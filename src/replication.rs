@@ -0,0 +1,554 @@
+use std::{
+    collections::HashMap,
+    io::{BufWriter, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use log::{error, warn};
+use parking_lot::Mutex;
+
+use crate::{
+    batch::{parse_log_record_key, NON_TRANSACTION_SEQ_NO},
+    data::log_record::{LogRecordType, ReadLogRecord},
+    db::Engine,
+    errors::{Errors, Result},
+    options::WriteBatchOptions,
+};
+
+// 建立连接时 follower 发送的标识
+const HANDSHAKE_MAGIC: &[u8; 4] = b"BKRP";
+// 读到活跃文件末尾之后，等待新数据写入的间隔
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+// follower 连接断开之后重连的间隔
+const RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+/// 复制的位置，即 primary 数据文件中的文件 id 和偏移
+/// 从 (0, 0) 开始复制会先发送所有已经存在的数据文件
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ReplicationCursor {
+    pub file_id: u64,
+    pub offset: u64,
+}
+
+/// primary 端的复制服务，为每个连接的 follower 启动一个线程，按顺序发送数据文件中的记录
+/// 先从 follower 给出的位置发送已经存在的数据，读到活跃文件末尾之后继续发送新追加的数据
+/// 复制是异步的，发送的是解密之后的记录，网络传输本身不加密，所以开启了加密的数据库不能作为 primary
+/// primary 执行 merge 并重启之后数据文件会被重写，follower 需要从 (0, 0) 重新复制
+pub struct ReplicationServer {
+    local_addr: SocketAddr,
+    stopped: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ReplicationServer {
+    /// 在 addr 上监听 follower 的连接
+    pub fn start(engine: Arc<Engine>, addr: &str) -> Result<Self> {
+        // 记录会以明文的方式在网络中传输
+        if engine.cipher.is_some() {
+            return Err(Errors::ReplicationUnsupportedEncryption);
+        }
+
+        let listener = match TcpListener::bind(addr) {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("failed to bind replication addr {}: {}", addr, e);
+                return Err(Errors::ReplicationConnectionFailed);
+            }
+        };
+        let local_addr = listener.local_addr().unwrap();
+        // 非阻塞地接收连接，以便及时响应停止
+        listener.set_nonblocking(true).unwrap();
+
+        let stopped = Arc::new(AtomicBool::new(false));
+        let server_stopped = stopped.clone();
+        let handle = thread::spawn(move || {
+            let mut senders = Vec::new();
+            while !server_stopped.load(Ordering::SeqCst) {
+                match listener.accept() {
+                    Ok((stream, peer)) => {
+                        let engine = engine.clone();
+                        let stopped = server_stopped.clone();
+                        senders.push(thread::spawn(move || {
+                            if let Err(e) = serve_follower(&engine, stream, &stopped) {
+                                warn!("replication to follower {} stopped: {}", peer, e);
+                            }
+                        }));
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+                    Err(e) => {
+                        error!("failed to accept follower: {}", e);
+                        thread::sleep(POLL_INTERVAL);
+                    }
+                }
+            }
+            for sender in senders {
+                let _ = sender.join();
+            }
+        });
+
+        Ok(Self {
+            local_addr,
+            stopped,
+            handle: Some(handle),
+        })
+    }
+
+    /// 实际监听的地址
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// 停止复制服务，断开所有的 follower
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for ReplicationServer {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+// 向一个 follower 发送数据
+fn serve_follower(engine: &Engine, mut stream: TcpStream, stopped: &AtomicBool) -> Result<()> {
+    stream.set_nonblocking(false).map_err(io_err)?;
+    stream
+        .set_read_timeout(Some(RETRY_INTERVAL))
+        .map_err(io_err)?;
+
+    // 读取 follower 的起始位置
+    let mut handshake = [0u8; 20];
+    stream.read_exact(&mut handshake).map_err(io_err)?;
+    if &handshake[..4] != HANDSHAKE_MAGIC {
+        return Err(Errors::InvaildReplicationMessage);
+    }
+    let mut buf = &handshake[4..];
+    let mut cursor = ReplicationCursor {
+        file_id: buf.get_u64(),
+        offset: buf.get_u64(),
+    };
+
+    let mut writer = BufWriter::new(stream);
+    while !stopped.load(Ordering::SeqCst) {
+        match read_next_record(engine, &mut cursor)? {
+            Some((file_id, offset, record)) => {
+                writer
+                    .write_all(&encode_frame(file_id, offset, record)?)
+                    .map_err(io_err)?;
+            }
+            None => {
+                // 没有新的数据，先把缓冲的数据发送出去再等待
+                writer.flush().map_err(io_err)?;
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+    }
+    Ok(())
+}
+
+// 从 cursor 的位置读取下一条记录，读取成功之后 cursor 指向下一条记录
+// 返回 None 说明已经读到了活跃文件的末尾
+pub(crate) fn read_next_record(
+    engine: &Engine,
+    cursor: &mut ReplicationCursor,
+) -> Result<Option<(u64, u64, ReadLogRecord)>> {
+    loop {
+        // 读取之前先拿到活跃文件 id，不是活跃文件的数据文件不会再被写入
+        let active_file_id = engine.active_file.read().get_file_id();
+
+        let res = match engine.get_data_file(cursor.file_id) {
+            Ok(data_file) => {
+                cursor.offset = cursor.offset.max(data_file.get_header_size());
+                Some(data_file.read_log_record(cursor.offset))
+            }
+            Err(Errors::DataFileNotFound) => None,
+            Err(e) => return Err(e),
+        };
+
+        let err = match res {
+            Some(Ok(mut record)) => {
+                // 发送实际的 value，follower 按照自己的配置决定是否分离存放
+                engine.resolve_value_pointer(&mut record.record, cursor.file_id, cursor.offset)?;
+                let offset = cursor.offset;
+                cursor.offset += record.size as u64;
+                return Ok(Some((cursor.file_id, offset, record)));
+            }
+            Some(Err(e)) => e,
+            // 文件不存在，跳到下一个数据文件
+            None => Errors::ReadDataFileEof,
+        };
+
+        let is_active = cursor.file_id == active_file_id;
+        match err {
+            // 活跃文件中的数据可能还没有完整写入，等待之后重新读取
+            Errors::ReadDataFileEof | Errors::InvaildLogRecordCrc if is_active => return Ok(None),
+            Errors::ReadDataFileEof => match next_file_id(engine, cursor.file_id) {
+                Some(file_id) => {
+                    cursor.file_id = file_id;
+                    cursor.offset = 0;
+                }
+                None => return Ok(None),
+            },
+            Errors::InvaildLogRecordCrc | Errors::InvalidRecordType(_) => {
+                return Err(Errors::DataFileCorrupted {
+                    file_id: cursor.file_id,
+                    offset: cursor.offset,
+                })
+            }
+            e => return Err(e),
+        }
+    }
+}
+
+// 比 file_id 大的最小的数据文件 id
+fn next_file_id(engine: &Engine, file_id: u64) -> Option<u64> {
+    let active_file_id = engine.active_file.read().get_file_id();
+    let older_files = engine.older_files.read();
+    older_files
+        .keys()
+        .chain(std::iter::once(&active_file_id))
+        .filter(|fid| **fid > file_id)
+        .min()
+        .copied()
+}
+
+/// 复制的消息格式
+///
+/// +----------+----------+----------+---------+----------+------------+-----------+--------------+----------+
+/// | file id  |  offset  |   size   |  type   |  seq no  |  key size  |    key    |  value size  |  value   |
+/// +----------+----------+----------+---------+----------+------------+-----------+--------------+----------+
+///    8字节       8字节      4字节      1字节      8字节       4字节        变长          4字节          变长
+///
+/// size 为记录在数据文件中的大小，key 为去掉事务序列号之后的实际 key
+fn encode_frame(file_id: u64, offset: u64, read_record: ReadLogRecord) -> Result<Vec<u8>> {
+    let record = read_record.record;
+    let (key, seq_no) = parse_log_record_key(record.key)?;
+
+    let mut buf = BytesMut::with_capacity(37 + key.len() + record.value.len());
+    buf.put_u64(file_id);
+    buf.put_u64(offset);
+    buf.put_u32(read_record.size as u32);
+    buf.put_u8(record.rec_type as u8);
+    buf.put_u64(seq_no);
+    buf.put_u32(key.len() as u32);
+    buf.put_slice(&key);
+    buf.put_u32(record.value.len() as u32);
+    buf.put_slice(&record.value);
+    Ok(buf.to_vec())
+}
+
+// follower 收到的一条记录
+struct ReplicatedRecord {
+    file_id: u64,
+    offset: u64,
+    size: u64,
+    rec_type: LogRecordType,
+    seq_no: u64,
+    key: Bytes,
+    value: Bytes,
+}
+
+/// follower 端，连接 primary 并将收到的记录写入到本地的存储引擎中
+/// 连接断开之后会从最近一次完整应用的位置自动重连，事务中的数据在提交标识到达之后才会写入
+pub struct Follower {
+    cursor: Arc<Mutex<ReplicationCursor>>,
+    stopped: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Follower {
+    /// 从 cursor 的位置开始复制 primary_addr 上的数据
+    pub fn start(engine: Arc<Engine>, primary_addr: &str, cursor: ReplicationCursor) -> Self {
+        let cursor = Arc::new(Mutex::new(cursor));
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let addr = primary_addr.to_string();
+        let follower_cursor = cursor.clone();
+        let follower_stopped = stopped.clone();
+        let handle = thread::spawn(move || {
+            while !follower_stopped.load(Ordering::SeqCst) {
+                if let Err(e) = follow(&engine, &addr, &follower_cursor, &follower_stopped) {
+                    warn!("replication from primary {} interrupted: {}", addr, e);
+                }
+                if !follower_stopped.load(Ordering::SeqCst) {
+                    thread::sleep(RETRY_INTERVAL);
+                }
+            }
+        });
+
+        Self {
+            cursor,
+            stopped,
+            handle: Some(handle),
+        }
+    }
+
+    /// 已经完整应用的位置，保存下来之后可以在重启时从这个位置继续复制
+    pub fn cursor(&self) -> ReplicationCursor {
+        *self.cursor.lock()
+    }
+
+    /// 停止复制，返回已经完整应用的位置
+    pub fn stop(mut self) -> ReplicationCursor {
+        self.shutdown();
+        self.cursor()
+    }
+
+    fn shutdown(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for Follower {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+// 建立一次连接并持续应用收到的记录
+fn follow(
+    engine: &Engine,
+    addr: &str,
+    cursor: &Mutex<ReplicationCursor>,
+    stopped: &AtomicBool,
+) -> Result<()> {
+    let mut stream = TcpStream::connect(addr).map_err(io_err)?;
+    stream
+        .set_read_timeout(Some(RETRY_INTERVAL))
+        .map_err(io_err)?;
+
+    let start = *cursor.lock();
+    let mut handshake = BytesMut::with_capacity(20);
+    handshake.put_slice(HANDSHAKE_MAGIC);
+    handshake.put_u64(start.file_id);
+    handshake.put_u64(start.offset);
+    stream.write_all(&handshake).map_err(io_err)?;
+
+    // 暂存还没有提交的事务数据
+    let mut transaction_records: HashMap<u64, Vec<ReplicatedRecord>> = HashMap::new();
+    while let Some(record) = read_frame(&mut stream, stopped)? {
+        let next = ReplicationCursor {
+            file_id: record.file_id,
+            offset: record.offset + record.size,
+        };
+
+        if record.seq_no == NON_TRANSACTION_SEQ_NO {
+            apply_record(engine, &record)?;
+        } else if record.rec_type == LogRecordType::TxnFinished {
+            if let Some(records) = transaction_records.remove(&record.seq_no) {
+                let wb = engine.new_write_batch(WriteBatchOptions {
+                    max_batch_num: u64::MAX,
+                    ..Default::default()
+                })?;
+                for txn_record in records.iter() {
+                    match txn_record.rec_type {
+                        LogRecordType::DELETE => wb.delete(txn_record.key.clone())?,
+                        _ => wb.put(txn_record.key.clone(), txn_record.value.clone())?,
+                    }
+                }
+                wb.commit()?;
+            }
+        } else {
+            transaction_records
+                .entry(record.seq_no)
+                .or_default()
+                .push(record);
+        }
+
+        // 没有未提交的事务时，之前的数据都已经应用完成，重连时从这里继续
+        if transaction_records.is_empty() {
+            *cursor.lock() = next;
+        }
+    }
+    Ok(())
+}
+
+fn apply_record(engine: &Engine, record: &ReplicatedRecord) -> Result<()> {
+    match record.rec_type {
+        LogRecordType::NORMAL => engine
+            .put(record.key.clone(), record.value.clone())
+            .map(|_| ()),
+        LogRecordType::DELETE => engine.delete(record.key.clone()),
+        LogRecordType::TxnFinished => Ok(()),
+    }
+}
+
+// 读取一条消息，停止时返回 None
+fn read_frame(stream: &mut TcpStream, stopped: &AtomicBool) -> Result<Option<ReplicatedRecord>> {
+    let mut header = [0u8; 33];
+    if !read_full(stream, &mut header, stopped)? {
+        return Ok(None);
+    }
+    let mut buf = &header[..];
+    let file_id = buf.get_u64();
+    let offset = buf.get_u64();
+    let size = buf.get_u32() as u64;
+    let rec_type = buf.get_u8();
+    let seq_no = buf.get_u64();
+    let key_size = buf.get_u32() as usize;
+    let rec_type =
+        LogRecordType::from_u8(rec_type).map_err(|_| Errors::InvaildReplicationMessage)?;
+
+    let mut key = vec![0u8; key_size];
+    let mut value_size = [0u8; 4];
+    if !read_full(stream, &mut key, stopped)? || !read_full(stream, &mut value_size, stopped)? {
+        return Ok(None);
+    }
+    let mut value = vec![0u8; u32::from_be_bytes(value_size) as usize];
+    if !read_full(stream, &mut value, stopped)? {
+        return Ok(None);
+    }
+
+    Ok(Some(ReplicatedRecord {
+        file_id,
+        offset,
+        size,
+        rec_type,
+        seq_no,
+        key: Bytes::from(key),
+        value: Bytes::from(value),
+    }))
+}
+
+// 读满 buf，读超时的时候检查是否已经停止，停止时返回 false
+fn read_full(stream: &mut TcpStream, buf: &mut [u8], stopped: &AtomicBool) -> Result<bool> {
+    let mut n = 0;
+    while n < buf.len() {
+        if stopped.load(Ordering::SeqCst) {
+            return Ok(false);
+        }
+        match stream.read(&mut buf[n..]) {
+            Ok(0) => return Err(Errors::ReplicationConnectionFailed),
+            Ok(size) => n += size,
+            Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {}
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(io_err(e)),
+        }
+    }
+    Ok(true)
+}
+
+fn io_err(e: std::io::Error) -> Errors {
+    warn!("replication io err: {}", e);
+    Errors::ReplicationConnectionFailed
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::Instant};
+
+    use crate::{
+        options::Options,
+        util::rand_kv::{get_test_key, get_test_value},
+    };
+
+    use super::*;
+
+    fn open_engine(path: &str) -> Arc<Engine> {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from(path);
+        opts.data_file_size = 32 * 1024;
+        Arc::new(Engine::open(opts).expect("failed to open engine"))
+    }
+
+    // 等待 follower 追上 primary
+    fn wait_until<F: Fn() -> bool>(f: F) {
+        let start = Instant::now();
+        while !f() {
+            assert!(start.elapsed() < Duration::from_secs(10), "wait timeout");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_replication() {
+        let primary = open_engine("/tmp/bitcask-rs-replication-primary");
+        let follower_engine = open_engine("/tmp/bitcask-rs-replication-follower");
+
+        // 已经存在的数据通过追赶发送
+        for i in 0..1000 {
+            primary.put(get_test_key(i), get_test_value(i)).unwrap();
+        }
+
+        let server = ReplicationServer::start(primary.clone(), "127.0.0.1:0").unwrap();
+        let addr = server.local_addr().to_string();
+        let follower =
+            Follower::start(follower_engine.clone(), &addr, ReplicationCursor::default());
+        wait_until(|| follower_engine.list_keys().unwrap().len() == 1000);
+
+        // 新写入的数据，包括删除和批量写
+        for i in 0..100 {
+            primary.delete(get_test_key(i)).unwrap();
+        }
+        let wb = primary
+            .new_write_batch(WriteBatchOptions::default())
+            .unwrap();
+        wb.put(get_test_key(1000), get_test_value(1000)).unwrap();
+        wb.delete(get_test_key(999)).unwrap();
+        wb.commit().unwrap();
+        wait_until(|| follower_engine.get(get_test_key(1000)).is_ok());
+        wait_until(|| follower_engine.list_keys().unwrap().len() == 900);
+        assert_eq!(
+            follower_engine.get(get_test_key(500)).unwrap(),
+            get_test_value(500)
+        );
+        assert!(follower_engine.get(get_test_key(10)).is_err());
+        assert!(follower_engine.get(get_test_key(999)).is_err());
+
+        // 停止之后从保存的位置继续复制
+        let cursor = follower.stop();
+        assert_eq!(cursor.file_id, primary.active_file.read().get_file_id());
+        for i in 2000..2100 {
+            primary.put(get_test_key(i), get_test_value(i)).unwrap();
+        }
+        let follower2 = Follower::start(follower_engine.clone(), &addr, cursor);
+        wait_until(|| follower_engine.list_keys().unwrap().len() == 1000);
+        assert_eq!(
+            follower_engine.get(get_test_key(2050)).unwrap(),
+            get_test_value(2050)
+        );
+
+        follower2.stop();
+        server.stop();
+        std::fs::remove_dir_all("/tmp/bitcask-rs-replication-primary")
+            .expect("failed to remove path");
+        std::fs::remove_dir_all("/tmp/bitcask-rs-replication-follower")
+            .expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_replication_encrypted() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-replication-encrypted");
+        opts.encryption_key = Some([6; 32]);
+        let engine = Arc::new(Engine::open(opts.clone()).expect("failed to open engine"));
+
+        assert_eq!(
+            ReplicationServer::start(engine.clone(), "127.0.0.1:0")
+                .err()
+                .unwrap(),
+            Errors::ReplicationUnsupportedEncryption
+        );
+
+        std::mem::drop(engine);
+        std::fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+}
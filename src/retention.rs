@@ -0,0 +1,254 @@
+//! 按照写入时间清理旧数据，适用于日志、监控指标等只需要保留一段时间的数据
+//! 只删除整个数据文件，文件中所有记录的写入时间都早于截止时间时才会被删除
+
+use std::{collections::HashSet, fs};
+
+use bytes::Bytes;
+use log::error;
+
+use crate::{
+    batch::parse_log_record_key,
+    data::data_file::{DataFile, HINT_FILE_NAME, MERGE_FIN_FILE_NAME},
+    db::{sync_dir_if_enabled, Engine},
+    errors::{Errors, Result},
+    manifest::FileMeta,
+    merge::{get_merge_path, read_non_merge_file_id},
+    options::IteratorOptions,
+};
+
+impl Engine {
+    /// 删除所有记录的写入时间都早于 timestamp（单位毫秒）的旧数据文件，返回删除的文件数量
+    /// 删除之前为索引指向这些文件的 key 写入删除记录，活跃文件不会被删除
+    /// 为了避免更早的数据在删除记录被清理之后重新出现，只从最旧的数据文件开始连续删除，
+    /// 遇到不满足条件的文件（包括存在没有写入时间的记录的文件）就停止
+    pub fn purge_older_than(&self, timestamp: u64) -> Result<usize> {
+        self.check_closed()?;
+        if self.options.read_only {
+            return Err(Errors::DatabaseIsReadOnly);
+        }
+
+        // 和 merge 互斥，避免删除的数据文件正在被 merge
+        let lock = self.merging_lock.try_lock();
+        if lock.is_none() {
+            return Err(Errors::MergeInProgress);
+        }
+
+        // 还没有安装的 merge 结果会在重启时替换掉较早的数据文件，此时不能删除
+        let dir_path = self.options.dir_path.clone();
+        if get_merge_path(dir_path.clone())
+            .join(MERGE_FIN_FILE_NAME)
+            .is_file()
+        {
+            return Ok(0);
+        }
+
+        let mut file_ids: Vec<u64> = self.older_files.read().keys().copied().collect();
+        file_ids.sort();
+        let mut purge_file_ids = HashSet::new();
+        for file_id in file_ids {
+            match self.sealed_file_meta(file_id)?.older_than(timestamp) {
+                true => purge_file_ids.insert(file_id),
+                false => break,
+            };
+        }
+        if purge_file_ids.is_empty() {
+            return Ok(0);
+        }
+
+        // 先收集 key，写入删除记录时不持有索引的迭代器
+        let mut keys = Vec::new();
+        let mut index_iter = self.index.iterator(IteratorOptions::default());
+        while let Some((key, pos)) = index_iter.next() {
+            if purge_file_ids.contains(&pos.file_id) {
+                keys.push(key.clone());
+            }
+        }
+        drop(index_iter);
+
+        for key in keys {
+            let key = Bytes::from(key);
+            let _key_lock = self.key_locks.lock(&key);
+            // 收集之后 key 可能已经被重新写入
+            match self.index.get(key.to_vec()) {
+                Some(pos) if purge_file_ids.contains(&pos.file_id) => self.do_delete(key)?,
+                _ => {}
+            }
+        }
+
+        // 删除记录持久化之后再删除旧的数据文件
+        self.sync()?;
+
+        // hint 文件中可能有指向被删除的文件的 key，这些 key 的删除记录也可能在被删除的文件中，
+        // 删除 hint 文件，下次启动时扫描剩余的数据文件重建索引，先删除 merge 完成文件，
+        // 否则中途崩溃时没有 hint 文件覆盖的较早数据文件不会被加载
+        let merge_fin_path = dir_path.join(MERGE_FIN_FILE_NAME);
+        if merge_fin_path.is_file() {
+            let non_merge_fid = read_non_merge_file_id(dir_path.clone())?;
+            if purge_file_ids
+                .iter()
+                .any(|file_id| *file_id < non_merge_fid)
+            {
+                for path in [merge_fin_path, dir_path.join(HINT_FILE_NAME)] {
+                    if path.is_file() {
+                        if let Err(e) = fs::remove_file(path) {
+                            error!("failed to remove hint file: {}", e);
+                            return Err(Errors::FailedToRemoveDataFile);
+                        }
+                    }
+                }
+                sync_dir_if_enabled(self.options.fsync_dir, &dir_path)?;
+            }
+        }
+
+        let mut older_files = self.older_files.write();
+        for file_id in purge_file_ids.iter() {
+            self.manifest.delete_file(*file_id)?;
+            self.remove_older_file(&mut older_files, *file_id)?;
+            #[cfg(feature = "object-store")]
+            if let Some(archive) = &self.archive {
+                archive.remove(*file_id)?;
+            }
+            self.remove_file_stats(*file_id);
+            self.remove_bloom_filter(*file_id)?;
+        }
+        drop(older_files);
+        sync_dir_if_enabled(self.options.fsync_dir, &dir_path)?;
+
+        Ok(purge_file_ids.len())
+    }
+
+    // 旧数据文件的元数据，manifest 中没有时（例如 merge 生成的数据文件）扫描数据文件统计，并补充到 manifest 中
+    fn sealed_file_meta(&self, file_id: u64) -> Result<FileMeta> {
+        if let Some(meta) = self.manifest.file_meta(file_id) {
+            return Ok(meta);
+        }
+
+        let data_file = match self.older_files.read().get(&file_id) {
+            Some(data_file) => data_file.clone(),
+            None => return Err(Errors::DataFileNotFound),
+        };
+        let meta = scan_file_meta(&data_file)?;
+
+        self.manifest.seal_file(file_id, Some(meta.clone()))?;
+        Ok(meta)
+    }
+}
+
+// 遍历数据文件中的所有记录，统计数据文件的元数据
+fn scan_file_meta(data_file: &DataFile) -> Result<FileMeta> {
+    let mut meta = FileMeta::default();
+    let mut offset = data_file.get_header_size();
+    loop {
+        let (log_record, size) = match data_file.read_log_record(offset) {
+            Ok(result) => (result.record, result.size),
+            Err(Errors::ReadDataFileEof) => break,
+            Err(e) => return Err(e),
+        };
+        offset += size as u64;
+
+        let (key, _) = parse_log_record_key(log_record.key)?;
+        let key = match data_file.is_encrypted() {
+            true => None,
+            false => Some(key.as_slice()),
+        };
+        meta.add_record(log_record.rec_type, log_record.timestamp, key);
+    }
+    Ok(meta)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::Duration};
+
+    use super::*;
+    use crate::{
+        data::{data_file::get_data_file_name, log_record::current_timestamp},
+        options::{FileRotation, Options},
+        util::rand_kv::{get_test_key, get_test_value},
+    };
+
+    #[test]
+    fn test_purge_older_than() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-purge-older-than");
+        opts.data_file_size = 64 * 1024 * 1024;
+        opts.file_rotation = FileRotation {
+            max_records: Some(100),
+            max_age: None,
+        };
+        opts.data_file_merge_ratio = 0.0;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        // 活跃文件不会被删除
+        for i in 0..50 {
+            assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        assert_eq!(engine.purge_older_than(u64::MAX).unwrap(), 0);
+
+        for i in 50..300 {
+            assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        std::thread::sleep(Duration::from_millis(10));
+        let cutoff = current_timestamp();
+        std::thread::sleep(Duration::from_millis(10));
+        // 覆盖写入较早的 key，删除之后保留新的数据
+        for i in 0..10 {
+            assert!(engine.put(get_test_key(i), get_test_value(1000)).is_ok());
+        }
+        for i in 300..400 {
+            assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+
+        assert_eq!(engine.purge_older_than(cutoff).unwrap(), 3);
+        for file_id in 0..3 {
+            assert!(!get_data_file_name(opts.dir_path.clone(), file_id).is_file());
+        }
+        assert!(get_data_file_name(opts.dir_path.clone(), 3).is_file());
+        assert_eq!(engine.purge_older_than(cutoff).unwrap(), 0);
+        let check = |engine: &Engine| {
+            for i in 0..10 {
+                assert_eq!(
+                    engine.get(get_test_key(i)).unwrap(),
+                    get_test_value(1000).slice(..)
+                );
+            }
+            for i in 10..300 {
+                assert_eq!(
+                    engine.get(get_test_key(i)).err().unwrap(),
+                    Errors::KeyNotFound
+                );
+            }
+            for i in 300..400 {
+                assert_eq!(
+                    engine.get(get_test_key(i)).unwrap(),
+                    get_test_value(i).slice(..)
+                );
+            }
+            assert_eq!(engine.list_keys().unwrap().len(), 110);
+        };
+        check(&engine);
+
+        // 重启之后被删除的数据不会重新出现
+        std::mem::drop(engine);
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        check(&engine);
+
+        // merge 之后的数据文件由 hint 文件加载索引，删除之后重新扫描数据文件
+        assert!(engine.merge().is_ok());
+        std::mem::drop(engine);
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        check(&engine);
+        assert!(engine.delete(get_test_key(300)).is_ok());
+        assert!(engine.purge_older_than(current_timestamp() + 1).unwrap() > 0);
+        assert!(!opts.dir_path.join(HINT_FILE_NAME).is_file());
+        std::mem::drop(engine);
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(
+            engine.get(get_test_key(300)).err().unwrap(),
+            Errors::KeyNotFound
+        );
+
+        std::mem::drop(engine);
+        fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+}
@@ -0,0 +1,165 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use bytes::Bytes;
+use log::error;
+use rocksdb::{IngestExternalFileOptions, IteratorMode, Options as RocksDbOptions, DB};
+
+use crate::{
+    db::Engine,
+    errors::{Errors, Result},
+    ingest::get_sibling_path,
+};
+
+const SST_IMPORT_DIR_NAME: &str = "sst-import";
+
+impl Engine {
+    /// 从已有的 RocksDB 数据目录中批量导入数据，返回导入的数据条数
+    /// 以只读的方式打开 RocksDB，只导入默认列族中的数据，LevelDB 的数据目录同样可以通过 RocksDB 读取
+    /// 数据按照字节序读取之后通过 ingest 导入，因此要求索引使用默认的 key 比较方式，
+    /// 否则会返回 IngestKeysNotSorted；读取出错时已经读取的数据不会被导入
+    pub fn import_rocksdb<P: AsRef<Path>>(&self, path: P) -> Result<usize> {
+        self.check_closed()?;
+        let db = DB::open_for_read_only(&RocksDbOptions::default(), path, false)
+            .map_err(rocksdb_error)?;
+        self.ingest_rocksdb(&db)
+    }
+
+    /// 从 RocksDB/LevelDB 的 SST 文件中批量导入数据，返回导入的数据条数
+    /// SST 文件先导入到数据目录旁边的临时 RocksDB 中，后面的文件中的数据覆盖前面的文件中相同的 key，
+    /// 合并之后再按照字节序通过 ingest 导入，对 key 比较方式的要求和 import_rocksdb 相同
+    pub fn import_sst_files(&self, paths: &[PathBuf]) -> Result<usize> {
+        self.check_closed()?;
+        if self.options.read_only {
+            return Err(Errors::DatabaseIsReadOnly);
+        }
+
+        let tmp_path = get_sibling_path(self.options.dir_path.clone(), SST_IMPORT_DIR_NAME);
+        if tmp_path.is_dir() {
+            if let Err(e) = fs::remove_dir_all(&tmp_path) {
+                error!("failed to remove sst import dir: {}", e);
+                return Err(Errors::FailedToCreateDatabaseDir);
+            }
+        }
+
+        let res = (|| {
+            let mut opts = RocksDbOptions::default();
+            opts.create_if_missing(true);
+            let db = DB::open(&opts, &tmp_path).map_err(rocksdb_error)?;
+            // 逐个导入，保证后面的文件覆盖前面的文件
+            for path in paths {
+                let ingest_opts = IngestExternalFileOptions::default();
+                db.ingest_external_file_opts(&ingest_opts, vec![path])
+                    .map_err(rocksdb_error)?;
+            }
+            self.ingest_rocksdb(&db)
+        })();
+
+        if let Err(e) = DB::destroy(&RocksDbOptions::default(), &tmp_path) {
+            error!("failed to destroy sst import db: {}", e);
+        }
+        if tmp_path.is_dir() {
+            if let Err(e) = fs::remove_dir_all(&tmp_path) {
+                error!("failed to remove sst import dir: {}", e);
+            }
+        }
+        res
+    }
+
+    // 按照字节序遍历 RocksDB 中的所有数据，通过 ingest 导入
+    fn ingest_rocksdb(&self, db: &DB) -> Result<usize> {
+        let iter = db.iterator(IteratorMode::Start).map(|item| match item {
+            Ok((key, value)) => Ok((Bytes::from(key.into_vec()), Bytes::from(value.into_vec()))),
+            Err(e) => Err(rocksdb_error(e)),
+        });
+        self.try_ingest(iter)
+    }
+}
+
+fn rocksdb_error(e: rocksdb::Error) -> Errors {
+    error!("rocksdb err: {}", e);
+    Errors::FailedToReadRocksDb(e.into_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use rocksdb::SstFileWriter;
+
+    use super::*;
+    use crate::{
+        options::Options,
+        util::rand_kv::{get_test_key, get_test_value},
+    };
+
+    #[test]
+    fn test_import_rocksdb() {
+        let source_path = PathBuf::from("/tmp/bitcask-rs-rocksdb-source");
+        {
+            let mut rocks_opts = RocksDbOptions::default();
+            rocks_opts.create_if_missing(true);
+            let db = DB::open(&rocks_opts, &source_path).unwrap();
+            for i in 0..1000 {
+                db.put(get_test_key(i), get_test_value(i)).unwrap();
+            }
+            db.delete(get_test_key(0)).unwrap();
+        }
+
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-rocksdb-import");
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine.import_rocksdb(&source_path).unwrap(), 999);
+        assert_eq!(
+            engine.get(get_test_key(0)).err().unwrap(),
+            Errors::KeyNotFound
+        );
+        assert_eq!(engine.get(get_test_key(1)).unwrap(), get_test_value(1));
+        assert_eq!(engine.list_keys().unwrap().len(), 999);
+
+        // 不存在的数据目录
+        assert!(matches!(
+            engine.import_rocksdb("/tmp/bitcask-rs-rocksdb-not-exist"),
+            Err(Errors::FailedToReadRocksDb(_))
+        ));
+
+        std::mem::drop(engine);
+        DB::destroy(&RocksDbOptions::default(), &source_path).unwrap();
+        let _ = fs::remove_dir_all(&source_path);
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_import_sst_files() {
+        let sst_dir = PathBuf::from("/tmp/bitcask-rs-sst-files");
+        fs::create_dir_all(&sst_dir).unwrap();
+        let rocks_opts = RocksDbOptions::default();
+        let mut paths = Vec::new();
+        for n in 0..2 {
+            let path = sst_dir.join(format!("{}.sst", n));
+            let mut writer = SstFileWriter::create(&rocks_opts);
+            writer.open(&path).unwrap();
+            for i in 0..100 {
+                let value = format!("value-{}-{}", n, i);
+                writer.put(get_test_key(i), value).unwrap();
+            }
+            writer.finish().unwrap();
+            paths.push(path);
+        }
+
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-sst-import");
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine.import_sst_files(&paths).unwrap(), 100);
+        // 后面的文件覆盖前面的文件
+        assert_eq!(
+            engine.get(get_test_key(10)).unwrap(),
+            Bytes::from("value-1-10")
+        );
+        assert!(!get_sibling_path(opts.dir_path.clone(), SST_IMPORT_DIR_NAME).exists());
+
+        std::mem::drop(engine);
+        fs::remove_dir_all(sst_dir).expect("failed to remove path");
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+}
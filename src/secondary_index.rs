@@ -0,0 +1,294 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use bytes::Bytes;
+use parking_lot::RwLock;
+
+use crate::{
+    data::log_record::LogRecordPos,
+    db::Engine,
+    errors::{Errors, Result},
+    options::IteratorOptions,
+};
+
+/// 从 key/value 中提取二级索引 key 的函数，一条数据可以对应多个二级索引 key
+pub type IndexExtractor = fn(&[u8], &[u8]) -> Vec<Vec<u8>>;
+
+/// 一个二级索引，保存二级索引 key 到主键的映射
+struct SecondaryIndex {
+    extractor: IndexExtractor,
+    entries: BTreeMap<Vec<u8>, BTreeSet<Vec<u8>>>,
+    // 主键当前对应的二级索引 key，更新和删除主键时使用
+    secondary_keys: HashMap<Vec<u8>, Vec<Vec<u8>>>,
+}
+
+impl SecondaryIndex {
+    fn new(extractor: IndexExtractor) -> Self {
+        Self {
+            extractor,
+            entries: BTreeMap::new(),
+            secondary_keys: HashMap::new(),
+        }
+    }
+
+    fn put(&mut self, key: &[u8], value: &[u8]) {
+        self.remove(key);
+
+        let mut secondary_keys = (self.extractor)(key, value);
+        secondary_keys.sort();
+        secondary_keys.dedup();
+        if secondary_keys.is_empty() {
+            return;
+        }
+        for secondary_key in secondary_keys.iter() {
+            self.entries
+                .entry(secondary_key.clone())
+                .or_default()
+                .insert(key.to_vec());
+        }
+        self.secondary_keys.insert(key.to_vec(), secondary_keys);
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        let secondary_keys = match self.secondary_keys.remove(key) {
+            Some(secondary_keys) => secondary_keys,
+            None => return,
+        };
+        for secondary_key in secondary_keys {
+            if let Some(primary_keys) = self.entries.get_mut(&secondary_key) {
+                primary_keys.remove(key);
+                if primary_keys.is_empty() {
+                    self.entries.remove(&secondary_key);
+                }
+            }
+        }
+    }
+}
+
+/// 所有注册的二级索引，只保存在内存中，重启之后需要重新注册
+pub(crate) struct SecondaryIndexes {
+    indexes: RwLock<HashMap<String, SecondaryIndex>>,
+}
+
+impl SecondaryIndexes {
+    pub(crate) fn new() -> Self {
+        Self {
+            indexes: RwLock::new(HashMap::new()),
+        }
+    }
+
+    // 清空所有二级索引中的数据，保留已经注册的二级索引
+    pub(crate) fn clear(&self) {
+        for index in self.indexes.write().values_mut() {
+            index.entries.clear();
+            index.secondary_keys.clear();
+        }
+    }
+}
+
+impl Engine {
+    /// 注册一个二级索引，注册时根据已有的数据构建索引，之后在每次写入和删除时更新
+    /// 二级索引不会持久化，重新打开数据库之后需要再次注册，注册时会重新构建
+    pub fn create_index(&self, name: &str, extractor: IndexExtractor) -> Result<()> {
+        self.check_closed()?;
+        // 构建期间持有写锁，并发写入会在构建完成之后再更新二级索引
+        let mut indexes = self.secondary_indexes.indexes.write();
+        if indexes.contains_key(name) {
+            return Err(Errors::SecondaryIndexExists);
+        }
+
+        let mut index = SecondaryIndex::new(extractor);
+        let mut iter = self.index.iterator(IteratorOptions::default());
+        while let Some((key, pos)) = iter.next() {
+            let value = match self.get_value_by_position(pos) {
+                Ok(value) => value,
+                // 已经被并发删除的数据
+                Err(Errors::KeyNotFound) => continue,
+                Err(e) => return Err(e),
+            };
+            index.put(key, &value);
+        }
+
+        indexes.insert(name.to_string(), index);
+        Ok(())
+    }
+
+    /// 根据二级索引 key 获取对应的所有主键，按照主键从小到大排列
+    pub fn get_by_index(&self, name: &str, secondary_key: Bytes) -> Result<Vec<Bytes>> {
+        let indexes = self.secondary_indexes.indexes.read();
+        let index = match indexes.get(name) {
+            Some(index) => index,
+            None => return Err(Errors::SecondaryIndexNotFound),
+        };
+
+        Ok(match index.entries.get(secondary_key.as_ref()) {
+            Some(primary_keys) => primary_keys
+                .iter()
+                .map(|key| Bytes::from(key.clone()))
+                .collect(),
+            None => Vec::new(),
+        })
+    }
+
+    /// 写入成功之后更新二级索引，value 为空表示删除
+    /// 只有内存索引中仍然是这次写入的结果时才更新，避免并发写入同一个 key 时乱序更新
+    pub(crate) fn update_secondary_indexes(
+        &self,
+        key: &[u8],
+        value: Option<(&[u8], LogRecordPos)>,
+    ) {
+        if self.secondary_indexes.indexes.read().is_empty() {
+            return;
+        }
+
+        let mut indexes = self.secondary_indexes.indexes.write();
+        let current = self.index.get(key.to_vec());
+        match (value, current) {
+            (Some((value, pos)), Some(current))
+                if current.file_id == pos.file_id && current.offset == pos.offset =>
+            {
+                for index in indexes.values_mut() {
+                    index.put(key, value);
+                }
+            }
+            (None, None) => {
+                for index in indexes.values_mut() {
+                    index.remove(key);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::Arc, thread};
+
+    use crate::options::{Options, WriteBatchOptions};
+
+    use super::*;
+
+    // value 的格式为 "city:name"，以 city 作为二级索引
+    fn city_extractor(_key: &[u8], value: &[u8]) -> Vec<Vec<u8>> {
+        match value.iter().position(|b| *b == b':') {
+            Some(i) => vec![value[..i].to_vec()],
+            None => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_secondary_index() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-secondary-index");
+        opts.data_file_size = 64 * 1024 * 1024;
+        let engine = Arc::new(Engine::open(opts.clone()).expect("failed to open engine"));
+
+        // 注册之前写入的数据
+        let res1 = engine.put(Bytes::from("user-1"), Bytes::from("beijing:tom"));
+        assert!(res1.is_ok());
+        let res2 = engine.put(Bytes::from("user-2"), Bytes::from("shanghai:jack"));
+        assert!(res2.is_ok());
+
+        let res3 = engine.create_index("city", city_extractor);
+        assert!(res3.is_ok());
+        let res4 = engine.create_index("city", city_extractor);
+        assert_eq!(Errors::SecondaryIndexExists, res4.err().unwrap());
+        let res5 = engine.get_by_index("name", Bytes::from("tom"));
+        assert_eq!(Errors::SecondaryIndexNotFound, res5.err().unwrap());
+
+        assert_eq!(
+            engine.get_by_index("city", Bytes::from("beijing")).unwrap(),
+            vec![Bytes::from("user-1")]
+        );
+
+        // 注册之后的写入、更新和删除
+        let res6 = engine.put(Bytes::from("user-3"), Bytes::from("beijing:lucy"));
+        assert!(res6.is_ok());
+        let res7 = engine.put(Bytes::from("user-2"), Bytes::from("beijing:jack"));
+        assert!(res7.is_ok());
+        let res8 = engine.delete(Bytes::from("user-1"));
+        assert!(res8.is_ok());
+        let res9 = engine.put(Bytes::from("user-4"), Bytes::from("no city"));
+        assert!(res9.is_ok());
+
+        assert_eq!(
+            engine.get_by_index("city", Bytes::from("beijing")).unwrap(),
+            vec![Bytes::from("user-2"), Bytes::from("user-3")]
+        );
+        assert!(engine
+            .get_by_index("city", Bytes::from("shanghai"))
+            .unwrap()
+            .is_empty());
+
+        // 批量写入
+        let wb = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("failed to create write batch");
+        assert!(wb
+            .put(Bytes::from("user-5"), Bytes::from("shanghai:lily"))
+            .is_ok());
+        assert!(wb.delete(Bytes::from("user-3")).is_ok());
+        assert!(wb.commit().is_ok());
+
+        assert_eq!(
+            engine.get_by_index("city", Bytes::from("beijing")).unwrap(),
+            vec![Bytes::from("user-2")]
+        );
+        assert_eq!(
+            engine
+                .get_by_index("city", Bytes::from("shanghai"))
+                .unwrap(),
+            vec![Bytes::from("user-5")]
+        );
+
+        // 并发更新同一个 key，二级索引和最终的数据保持一致
+        let mut handles = Vec::new();
+        for t in 0..4 {
+            let engine = engine.clone();
+            handles.push(thread::spawn(move || {
+                for i in 0..200 {
+                    let value = format!("city-{}:name", (t * 200 + i) % 7);
+                    let res = engine.put(Bytes::from("user-6"), Bytes::from(value));
+                    assert!(res.is_ok());
+                }
+            }));
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        let value = engine.get(Bytes::from("user-6")).unwrap();
+        let city = city_extractor(b"user-6", &value).pop().unwrap();
+        for i in 0..7 {
+            let other = format!("city-{}", i).into_bytes();
+            let primary_keys = engine.get_by_index("city", Bytes::from(other.clone()));
+            match other == city {
+                true => assert_eq!(primary_keys.unwrap(), vec![Bytes::from("user-6")]),
+                false => assert!(primary_keys.unwrap().is_empty()),
+            }
+        }
+
+        // 重启之后重新注册，根据已有的数据重新构建
+        std::mem::drop(wb);
+        std::mem::drop(engine);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        let res10 = engine2.get_by_index("city", Bytes::from("beijing"));
+        assert_eq!(Errors::SecondaryIndexNotFound, res10.err().unwrap());
+        let res11 = engine2.create_index("city", city_extractor);
+        assert!(res11.is_ok());
+        assert_eq!(
+            engine2
+                .get_by_index("city", Bytes::from("beijing"))
+                .unwrap(),
+            vec![Bytes::from("user-2")]
+        );
+        assert_eq!(
+            engine2
+                .get_by_index("city", Bytes::from("shanghai"))
+                .unwrap(),
+            vec![Bytes::from("user-5")]
+        );
+
+        std::mem::drop(engine2);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+}
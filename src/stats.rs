@@ -0,0 +1,295 @@
+use std::{
+    collections::HashMap,
+    fs,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use bytes::{Buf, Bytes};
+use log::error;
+use parking_lot::RwLock;
+use prost::encoding::{decode_varint, encode_varint};
+
+use crate::{
+    bucket::is_bucket_key,
+    data::{
+        data_file::{DataFile, STATS_FILE_NAME},
+        log_record::{LogRecord, LogRecordPos, LogRecordType},
+    },
+    db::{Engine, EngineInner, FileStat, PrefixStat, SizeHistogram},
+    errors::Result,
+    mvcc::is_mvcc_key,
+    options::IteratorOptions,
+};
+
+const STATS_KEY: &str = "stats";
+
+/// 引擎运行期间维护的统计信息，关闭时持久化到 stats 文件中
+pub(crate) struct EngineStats {
+    key_num: AtomicUsize, // key 的数量，不包含 MVCC 事务内部使用的 key 和 bucket 中的 key
+    dead_sizes: RwLock<HashMap<u64, u64>>, // 每个数据文件中可以回收的数据量
+}
+
+impl EngineStats {
+    pub(crate) fn new() -> Self {
+        Self {
+            key_num: AtomicUsize::new(0),
+            dead_sizes: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn key_num(&self) -> usize {
+        self.key_num.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn reset(&self) {
+        self.key_num.store(0, Ordering::SeqCst);
+        self.dead_sizes.write().clear();
+    }
+}
+
+// 持久化的统计信息
+struct StatsSnapshot {
+    reclaim_size: usize,
+    key_num: usize,
+    files: Vec<FileStat>,
+}
+
+impl StatsSnapshot {
+    // +--------------+----------+------------+-----------------------------------+
+    // | reclaim_size | key_num  | 文件数量    | file_id + live_size + dead_size ... |
+    // +--------------+----------+------------+-----------------------------------+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        encode_varint(self.reclaim_size as u64, &mut buf);
+        encode_varint(self.key_num as u64, &mut buf);
+        encode_varint(self.files.len() as u64, &mut buf);
+        for file in self.files.iter() {
+            encode_varint(file.file_id, &mut buf);
+            encode_varint(file.live_size, &mut buf);
+            encode_varint(file.dead_size, &mut buf);
+        }
+        buf
+    }
+
+    fn decode(mut buf: &[u8]) -> Option<Self> {
+        let reclaim_size = decode_varint(&mut buf).ok()? as usize;
+        let key_num = decode_varint(&mut buf).ok()? as usize;
+        let file_num = decode_varint(&mut buf).ok()?;
+        let mut files = Vec::new();
+        for _ in 0..file_num {
+            files.push(FileStat {
+                file_id: decode_varint(&mut buf).ok()?,
+                live_size: decode_varint(&mut buf).ok()?,
+                dead_size: decode_varint(&mut buf).ok()?,
+            });
+        }
+        if buf.has_remaining() {
+            return None;
+        }
+        Some(Self {
+            reclaim_size,
+            key_num,
+            files,
+        })
+    }
+}
+
+impl EngineInner {
+    /// 关闭时持久化统计信息，先删除旧的文件，保证文件中只有一条记录
+    pub(crate) fn save_stats(&self) -> Result<()> {
+        let stats_file_path = self.options.dir_path.join(STATS_FILE_NAME);
+        if stats_file_path.is_file() {
+            if let Err(e) = fs::remove_file(stats_file_path) {
+                error!("failed to remove stats file: {}", e);
+            }
+        }
+
+        let snapshot = StatsSnapshot {
+            reclaim_size: self.reclaim_size.load(Ordering::SeqCst),
+            key_num: self.stats.key_num(),
+            files: self.collect_file_stats(),
+        };
+        let stats_file = DataFile::new_stats_file(self.options.dir_path.clone())?;
+        let record = LogRecord {
+            key: STATS_KEY.as_bytes().to_vec(),
+            value: snapshot.encode(),
+            rec_type: LogRecordType::NORMAL,
+            timestamp: 0,
+            value_pointer: false,
+        };
+        stats_file.write(&record.encode())?;
+        stats_file.sync()
+    }
+
+    fn collect_file_stats(&self) -> Vec<FileStat> {
+        let dead_sizes = self.stats.dead_sizes.read();
+        let file_stat = |data_file: &DataFile, size: u64| {
+            let file_id = data_file.get_file_id();
+            let dead_size = dead_sizes.get(&file_id).copied().unwrap_or_default();
+            let size = size.saturating_sub(data_file.get_header_size());
+            FileStat {
+                file_id,
+                live_size: size.saturating_sub(dead_size),
+                dead_size,
+            }
+        };
+
+        // 旧的数据文件不会再写入，直接使用文件大小
+        let older_files = self.older_files.read();
+        let mut files: Vec<FileStat> = older_files
+            .values()
+            .map(|data_file| file_stat(data_file, data_file.file_size()))
+            .collect();
+        let active_file = self.active_file.read();
+        files.push(file_stat(&active_file, active_file.get_write_off()));
+        files.sort_by_key(|file| file.file_id);
+        files
+    }
+}
+
+impl Engine {
+    /// 返回每个数据文件中有效的数据量和可以回收的数据量，按照文件 id 排列
+    pub fn file_stats(&self) -> Result<Vec<FileStat>> {
+        self.check_closed()?;
+        Ok(self.collect_file_stats())
+    }
+
+    /// 统计前缀为 prefix 的 key 的数量和占据的空间，只遍历内存索引，不读取 value
+    /// 大小使用索引中记录的长度，不包含已经被覆盖或者删除的旧数据
+    pub fn prefix_stat(&self, prefix: Bytes) -> Result<PrefixStat> {
+        self.check_closed()?;
+        let include_mvcc_keys = is_mvcc_key(&prefix);
+        let include_bucket_keys = is_bucket_key(&prefix);
+        let mut index_iter = self.index.iterator(IteratorOptions {
+            prefix: prefix.to_vec(),
+            reverse: false,
+            ..Default::default()
+        });
+
+        let mut stat = PrefixStat::default();
+        while let Some((key, pos)) = index_iter.next() {
+            if (!include_mvcc_keys && is_mvcc_key(key))
+                || (!include_bucket_keys && is_bucket_key(key))
+            {
+                continue;
+            }
+            stat.key_count += 1;
+            stat.approx_bytes += pos.size;
+        }
+        Ok(stat)
+    }
+
+    /// 统计所有 key 的大小分布，只遍历内存索引，不包含 MVCC 事务内部使用的 key 和 bucket 中的 key
+    pub fn key_size_histogram(&self) -> Result<SizeHistogram> {
+        self.size_histogram(|key, _| key.len() as u64)
+    }
+
+    /// 统计所有 value 的大小分布，只遍历内存索引，不读取 value
+    /// 大小使用索引中记录的长度减去 key 的长度估算，包含记录头部的十几个字节，
+    /// 存放在 blob 文件中的 value 统计的是指针的大小，加密的记录包含加密的开销
+    pub fn value_size_histogram(&self) -> Result<SizeHistogram> {
+        self.size_histogram(|key, pos| pos.size.saturating_sub(key.len() as u64))
+    }
+
+    fn size_histogram<F>(&self, size: F) -> Result<SizeHistogram>
+    where
+        F: Fn(&[u8], &LogRecordPos) -> u64,
+    {
+        self.check_closed()?;
+        let mut index_iter = self.index.iterator(IteratorOptions::default());
+        let mut histogram = SizeHistogram::default();
+        while let Some((key, pos)) = index_iter.next() {
+            if is_mvcc_key(key) || is_bucket_key(key) {
+                continue;
+            }
+            histogram.add(size(key, pos));
+        }
+        Ok(histogram)
+    }
+
+    /// 更新内存索引，同时更新统计信息
+    pub(crate) fn index_put(&self, key: Vec<u8>, pos: LogRecordPos) {
+        let is_user_key = !is_mvcc_key(&key) && !is_bucket_key(&key);
+        match self.index.put(key, pos) {
+            Some(old_pos) => self.add_reclaim_size(&old_pos),
+            None if is_user_key => {
+                self.stats.key_num.fetch_add(1, Ordering::SeqCst);
+            }
+            None => {}
+        }
+    }
+
+    /// 删除内存索引中的 key，同时更新统计信息
+    pub(crate) fn index_delete(&self, key: Vec<u8>) {
+        let is_user_key = !is_mvcc_key(&key) && !is_bucket_key(&key);
+        if let Some(old_pos) = self.index.delete(key) {
+            self.add_reclaim_size(&old_pos);
+            if is_user_key {
+                self.stats.key_num.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// 记录 pos 指向的数据已经失效，可以在 merge 时回收
+    pub(crate) fn add_reclaim_size(&self, pos: &LogRecordPos) {
+        self.reclaim_size
+            .fetch_add(pos.size as usize, Ordering::SeqCst);
+        *self
+            .stats
+            .dead_sizes
+            .write()
+            .entry(pos.file_id)
+            .or_default() += pos.size;
+    }
+
+    /// 数据文件被删除之后，文件中可以回收的数据已经全部回收
+    pub(crate) fn remove_file_stats(&self, file_id: u64) {
+        if let Some(dead_size) = self.stats.dead_sizes.write().remove(&file_id) {
+            self.reclaim_size
+                .fetch_sub(dead_size as usize, Ordering::SeqCst);
+        }
+    }
+
+    /// 打开数据库时读取持久化的统计信息，读取之后删除，避免异常退出之后读到过期的数据
+    /// 只有不需要重新扫描数据文件时 apply 为 true，否则统计信息在加载索引时已经重新计算
+    pub(crate) fn load_stats(&self, apply: bool) -> Result<bool> {
+        let stats_file_path = self.options.dir_path.join(STATS_FILE_NAME);
+        if !stats_file_path.is_file() {
+            return Ok(false);
+        }
+
+        let stats_file = DataFile::new_stats_file(self.options.dir_path.clone())?;
+        let snapshot = match stats_file.read_log_record(0) {
+            Ok(res) => StatsSnapshot::decode(&res.record.value),
+            Err(e) => {
+                error!("failed to read stats file: {}", e);
+                None
+            }
+        };
+        if !self.options.read_only {
+            if let Err(e) = fs::remove_file(stats_file_path) {
+                error!("failed to remove stats file: {}", e);
+            }
+        }
+
+        let snapshot = match snapshot {
+            Some(snapshot) if apply => snapshot,
+            _ => return Ok(false),
+        };
+        self.reclaim_size
+            .store(snapshot.reclaim_size, Ordering::SeqCst);
+        self.stats.key_num.store(snapshot.key_num, Ordering::SeqCst);
+        let mut dead_sizes = self.stats.dead_sizes.write();
+        for file in snapshot.files {
+            dead_sizes.insert(file.file_id, file.dead_size);
+        }
+        Ok(true)
+    }
+
+    /// 没有可用的统计信息时重新统计 key 的数量，逐个遍历 key，不需要拷贝出所有的 key
+    pub(crate) fn count_keys(&self) -> Result<()> {
+        let key_num = self.keys(IteratorOptions::default())?.count();
+        self.stats.key_num.store(key_num, Ordering::SeqCst);
+        Ok(())
+    }
+}
@@ -0,0 +1,283 @@
+//! 崩溃恢复测试工具，需要开启 fault-inject feature
+//! 在数据目录上注册故障注入之后，目录中的文件写入或者持久化会在指定的位置失败，
+//! 模拟写入过程中宕机，再通过 recover 修复并重新打开数据目录，检查数据是否正确恢复
+
+use std::{
+    fs::OpenOptions,
+    path::{Path, PathBuf},
+};
+
+use log::{error, warn};
+
+pub use crate::fio::fault_inject::{FaultInjection, FaultInjector};
+use crate::{
+    data::data_file::get_data_file_name,
+    db::Engine,
+    errors::{Errors, Result},
+    fio::fault_inject,
+    options::{Options, RepairOptions},
+};
+
+/// 在数据目录上注册故障注入，返回值被释放时取消注册
+pub fn inject_faults(dir_path: &Path) -> FaultInjection {
+    fault_inject::register(dir_path)
+}
+
+/// 数据文件的路径
+pub fn data_file_path(dir_path: &Path, file_id: u64) -> PathBuf {
+    get_data_file_name(dir_path.to_path_buf(), file_id)
+}
+
+/// 将数据文件截断到 size 大小，模拟宕机时丢失了没有持久化的数据
+pub fn truncate_data_file(dir_path: &Path, file_id: u64, size: u64) -> Result<()> {
+    let path = data_file_path(dir_path, file_id);
+    let res = OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .and_then(|file| file.set_len(size));
+    if let Err(e) = res {
+        error!("failed to truncate data file: {}", e);
+        return Err(Errors::write_failed(&path, e));
+    }
+    Ok(())
+}
+
+/// 模拟宕机之后重启：数据文件损坏时先修复数据目录，丢弃没有完整写入的数据，再重新打开
+pub fn recover(opts: Options) -> Result<Engine> {
+    match Engine::open(opts.clone()) {
+        Err(Errors::DataFileCorrupted { file_id, offset }) => {
+            warn!(
+                "data file {} is corrupted at offset {}, repair it",
+                file_id, offset
+            );
+            let repair_options = RepairOptions {
+                index_type: opts.index_type.clone(),
+                encryption_key: opts.encryption_key,
+                ..Default::default()
+            };
+            Engine::repair(opts.dir_path.clone(), repair_options)?;
+            Engine::open(opts)
+        }
+        res => res,
+    }
+}
+
+/// 崩溃恢复测试：打开存储引擎，成功写入 fail_after 次之后所有的写入都失败，
+/// 第一次失败的写入只写入 torn_bytes 个字节，workload 执行完成之后关闭存储引擎，
+/// 取消故障注入并通过 recover 重新打开，返回恢复之后的存储引擎
+pub fn run_with_write_fault<F>(
+    opts: Options,
+    fail_after: u64,
+    torn_bytes: usize,
+    workload: F,
+) -> Result<Engine>
+where
+    F: FnOnce(&Engine),
+{
+    {
+        let injection = inject_faults(&opts.dir_path);
+        let engine = Engine::open(opts.clone())?;
+        injection.torn_writes(torn_bytes);
+        injection.fail_writes_after(fail_after);
+        workload(&engine);
+        // 故障仍然生效，关闭时写入的元数据文件也会失败
+        if let Err(e) = engine.close() {
+            warn!("failed to close engine after fault injected: {}", e);
+        }
+    }
+    recover(opts)
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::{
+        fio::{file_io::FileIO, IOManager},
+        options::WriteBatchOptions,
+        util::rand_kv::{get_test_key, get_test_value},
+    };
+
+    #[test]
+    fn test_fault_inject_io() {
+        let dir_path = PathBuf::from("/tmp/bitcask-rs-fault-inject-io");
+        std::fs::create_dir_all(&dir_path).unwrap();
+        let path = dir_path.join("a.data");
+
+        let injection = inject_faults(&dir_path);
+        assert!(fault_inject::find_injector(&path).is_some());
+        assert!(fault_inject::find_injector(&PathBuf::from("/tmp/bitcask-rs-x/a.data")).is_none());
+        // merge 使用的临时目录也会注入故障
+        let merge_path = PathBuf::from("/tmp/bitcask-rs-fault-inject-io-merge/a.data");
+        assert!(fault_inject::find_injector(&merge_path).is_some());
+
+        let io = fault_inject::FaultInjectIO::new(
+            path.clone(),
+            Box::new(FileIO::new(path.clone()).unwrap()),
+            fault_inject::find_injector(&path).unwrap(),
+        );
+        assert!(io.write(b"aaaa").is_ok());
+        injection.torn_writes(2);
+        injection.fail_writes_after(1);
+        assert!(io.write(b"bbbb").is_ok());
+        let err = io.write(b"cccc").err().unwrap();
+        assert!(matches!(
+            &err,
+            Errors::FailedToWriteDataToDataFile { path: p, .. } if *p == path.display().to_string()
+        ));
+        assert!(err.to_string().contains("injected fault"));
+        assert!(io.write(b"dddd").is_err());
+        assert!(injection.is_triggered());
+        // 只有第一次失败的写入写入了部分数据
+        assert_eq!(std::fs::read(&path).unwrap(), b"aaaabbbbcc");
+        assert_eq!(injection.write_count(), 2);
+
+        injection.fail_syncs_after(0);
+        assert!(matches!(
+            io.sync().err().unwrap(),
+            Errors::FailedSyncDataFile { .. }
+        ));
+        injection.clear();
+        assert!(!injection.is_triggered());
+        assert!(io.write(b"eeee").is_ok());
+        assert!(io.sync().is_ok());
+
+        std::mem::drop(injection);
+        assert!(fault_inject::find_injector(&path).is_none());
+        std::fs::remove_dir_all(dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_crash_recovery_put() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-crash-put");
+        opts.data_file_size = 4 * 1024;
+
+        for fail_after in [0, 1, 7, 20, 45] {
+            let torn_bytes = (fail_after as usize * 7) % 30;
+            let mut acked = 0;
+            let engine = run_with_write_fault(opts.clone(), fail_after, torn_bytes, |engine| {
+                for i in 0..50 {
+                    if engine.put(get_test_key(i), get_test_value(i)).is_err() {
+                        break;
+                    }
+                    acked += 1;
+                }
+            })
+            .expect("failed to recover engine");
+
+            // 写入成功的数据都可以读取到，失败之后的数据都不存在
+            for i in 0..50 {
+                match i < acked {
+                    true => assert!(engine.get(get_test_key(i)).is_ok()),
+                    false => assert_eq!(
+                        engine.get(get_test_key(i)).err().unwrap(),
+                        Errors::KeyNotFound
+                    ),
+                }
+            }
+            assert_eq!(engine.list_keys().unwrap().len(), acked);
+
+            std::mem::drop(engine);
+            std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+        }
+    }
+
+    #[test]
+    fn test_crash_recovery_batch_commit() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-crash-batch");
+        opts.data_file_size = 64 * 1024 * 1024;
+
+        for fail_after in [0, 1, 2, 5] {
+            let engine = run_with_write_fault(opts.clone(), fail_after, 10, |engine| {
+                for b in 0..5 {
+                    let batch = engine
+                        .new_write_batch(WriteBatchOptions::default())
+                        .unwrap();
+                    for i in 0..10 {
+                        batch
+                            .put(get_test_key(b * 10 + i), get_test_value(i))
+                            .unwrap();
+                    }
+                    if batch.commit().is_err() {
+                        break;
+                    }
+                }
+            })
+            .expect("failed to recover engine");
+
+            // 每个批次的数据要么全部存在，要么全部不存在
+            for b in 0..5 {
+                let exists: Vec<bool> = (0..10)
+                    .map(|i| engine.get(get_test_key(b * 10 + i)).is_ok())
+                    .collect();
+                assert!(exists.iter().all(|e| *e) || exists.iter().all(|e| !*e));
+            }
+
+            std::mem::drop(engine);
+            std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+        }
+    }
+
+    #[test]
+    fn test_crash_recovery_merge() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-crash-merge");
+        opts.data_file_size = 32 * 1024;
+        opts.data_file_merge_ratio = 0.0;
+
+        for fail_after in [0, 3, 20] {
+            let engine = Engine::open(opts.clone()).expect("failed to open engine");
+            for i in 0..500 {
+                assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+            }
+            for i in 0..250 {
+                assert!(engine.delete(get_test_key(i)).is_ok());
+            }
+            std::mem::drop(engine);
+
+            // merge 失败之后数据不受影响
+            let engine = run_with_write_fault(opts.clone(), fail_after, 5, |engine| {
+                assert!(engine.merge().is_err());
+            })
+            .expect("failed to recover engine");
+            assert_eq!(engine.list_keys().unwrap().len(), 250);
+            for i in 250..500 {
+                assert_eq!(
+                    engine.get(get_test_key(i)).unwrap(),
+                    get_test_value(i).slice(..)
+                );
+            }
+
+            std::mem::drop(engine);
+            std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+        }
+    }
+
+    #[test]
+    fn test_truncate_data_file() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-crash-truncate");
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        assert!(engine.put(Bytes::from("a"), Bytes::from("1")).is_ok());
+        assert!(engine.put(Bytes::from("b"), Bytes::from("2")).is_ok());
+        std::mem::drop(engine);
+
+        // 最后一条记录只剩下一部分
+        let size = std::fs::metadata(data_file_path(&opts.dir_path, 0))
+            .unwrap()
+            .len();
+        assert!(truncate_data_file(&opts.dir_path, 0, size - 3).is_ok());
+        let engine = recover(opts.clone()).expect("failed to recover engine");
+        assert_eq!(engine.get(Bytes::from("a")).unwrap(), Bytes::from("1"));
+        assert_eq!(
+            engine.get(Bytes::from("b")).err().unwrap(),
+            Errors::KeyNotFound
+        );
+
+        std::mem::drop(engine);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+}
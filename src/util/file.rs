@@ -1,4 +1,8 @@
-use std::{fs, io, path::PathBuf};
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
 
 /// 获取磁盘剩余空间
 pub fn available_disk_size(dir_path: PathBuf) -> u64 {
@@ -43,6 +47,32 @@ pub fn copy_dir(src: PathBuf, dest: PathBuf, exculde: &[&str]) -> io::Result<()>
     Ok(())
 }
 
+/// 持久化目录项，保证目录中文件的创建、重命名和删除已经落盘
+pub fn sync_dir(dir_path: &Path) -> io::Result<()> {
+    fs::File::open(dir_path)?.sync_all()
+}
+
+/// 先写入临时文件并持久化，再重命名为目标文件，崩溃时目标文件要么是旧的内容，要么是完整的新内容
+/// sync_parent 为 true 时重命名之后持久化所在的目录
+pub fn write_file_atomically(path: &Path, buf: &[u8], sync_parent: bool) -> io::Result<()> {
+    let mut tmp_name = path.as_os_str().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_name);
+
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(buf)?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&tmp_path, path)?;
+    if sync_parent {
+        if let Some(parent) = path.parent() {
+            sync_dir(parent)?;
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -53,4 +83,20 @@ mod tests {
         let free_space = available_disk_size(path);
         println!("{:?}", free_space / 1024 / 1024 / 1024);
     }
+
+    #[test]
+    fn test_write_file_atomically() {
+        let dir_path = PathBuf::from("/tmp/bitcask-rs-write-file-atomically");
+        fs::create_dir_all(&dir_path).unwrap();
+        let path = dir_path.join("seq-no");
+
+        write_file_atomically(&path, b"first", true).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"first");
+        // 覆盖旧的内容，不会留下临时文件
+        write_file_atomically(&path, b"second", false).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        assert!(!dir_path.join("seq-no.tmp").exists());
+
+        fs::remove_dir_all(dir_path).unwrap();
+    }
 }
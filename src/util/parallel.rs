@@ -0,0 +1,163 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    thread,
+};
+
+use parking_lot::Mutex;
+
+use crate::errors::Result;
+
+/// 使用 threads 个线程并行地对 items 执行 work，当前线程按照 items 的顺序依次把结果交给 handle
+/// 最多只有 threads + 1 个结果在处理中或者等待 handle，先处理完的结果不会无限制地堆积在内存中
+/// work 或者 handle 返回错误时不再处理之后的 item，并返回第一个错误
+pub(crate) fn for_each_ordered<T, R, W, H>(
+    items: &[T],
+    threads: usize,
+    work: W,
+    mut handle: H,
+) -> Result<()>
+where
+    T: Sync,
+    R: Send,
+    W: Fn(&T) -> Result<R> + Sync,
+    H: FnMut(&T, R) -> Result<()>,
+{
+    let threads = threads.max(1).min(items.len());
+    let stopped = AtomicBool::new(false);
+    // 每个任务带有单独的结果通道，当前线程按照任务的顺序读取结果
+    let (task_sender, task_receiver) = mpsc::channel::<(usize, mpsc::SyncSender<Result<R>>)>();
+    let task_receiver = Mutex::new(task_receiver);
+
+    thread::scope(|s| {
+        for _ in 0..threads {
+            let (task_receiver, stopped, work) = (&task_receiver, &stopped, &work);
+            s.spawn(move || loop {
+                let task = task_receiver.lock().recv();
+                let (i, result_sender) = match task {
+                    Ok(task) => task,
+                    Err(_) => break,
+                };
+                // 已经出错，剩余的任务不需要再处理
+                if stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                let _ = result_sender.send(work(&items[i]));
+            });
+        }
+
+        let res = dispatch(items, threads, &task_sender, &mut handle);
+        if res.is_err() {
+            stopped.store(true, Ordering::SeqCst);
+        }
+        // 通知工作线程退出，scope 结束时等待所有工作线程
+        drop(task_sender);
+        res
+    })
+}
+
+// 按顺序分发任务并处理结果，进行中的任务超过 threads 个时先等待最早的任务完成
+fn dispatch<T, R, H>(
+    items: &[T],
+    threads: usize,
+    task_sender: &mpsc::Sender<(usize, mpsc::SyncSender<Result<R>>)>,
+    handle: &mut H,
+) -> Result<()>
+where
+    H: FnMut(&T, R) -> Result<()>,
+{
+    let mut pending = VecDeque::with_capacity(threads + 1);
+    for i in 0..items.len() {
+        let (result_sender, result_receiver) = mpsc::sync_channel(1);
+        // 任务通道的接收端一直存在，发送不会失败
+        let _ = task_sender.send((i, result_sender));
+        pending.push_back((i, result_receiver));
+        if pending.len() > threads {
+            let (i, result_receiver) = pending.pop_front().unwrap();
+            handle_result(items, i, result_receiver, handle)?;
+        }
+    }
+    while let Some((i, result_receiver)) = pending.pop_front() {
+        handle_result(items, i, result_receiver, handle)?;
+    }
+    Ok(())
+}
+
+fn handle_result<T, R, H>(
+    items: &[T],
+    i: usize,
+    result_receiver: mpsc::Receiver<Result<R>>,
+    handle: &mut H,
+) -> Result<()>
+where
+    H: FnMut(&T, R) -> Result<()>,
+{
+    // 工作线程 panic 时结果通道被关闭，scope 结束时会继续抛出 panic
+    match result_receiver.recv() {
+        Ok(res) => handle(&items[i], res?),
+        Err(_) => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::errors::Errors;
+
+    #[test]
+    fn test_for_each_ordered() {
+        let items: Vec<usize> = (0..100).collect();
+        let mut handled = Vec::new();
+        let res = for_each_ordered(
+            &items,
+            4,
+            |i| {
+                // 前面的任务更慢，结果仍然按照顺序处理
+                thread::sleep(Duration::from_micros(((100 - i) * 10) as u64));
+                Ok(i * 2)
+            },
+            |i, r| {
+                assert_eq!(*i * 2, r);
+                handled.push(*i);
+                Ok(())
+            },
+        );
+        assert!(res.is_ok());
+        assert_eq!(handled, items);
+
+        // 出错之后不再处理之后的任务
+        let worked = AtomicUsize::new(0);
+        let mut handled = 0;
+        let res = for_each_ordered(
+            &items,
+            4,
+            |i| {
+                worked.fetch_add(1, Ordering::SeqCst);
+                match *i == 10 {
+                    true => Err(Errors::MergeCancelled),
+                    false => Ok(*i),
+                }
+            },
+            |_, _| {
+                handled += 1;
+                Ok(())
+            },
+        );
+        assert_eq!(res.err().unwrap(), Errors::MergeCancelled);
+        assert_eq!(handled, 10);
+        assert!(worked.load(Ordering::SeqCst) < items.len());
+
+        // 没有任务
+        let res = for_each_ordered(&[] as &[usize], 4, |i| Ok(*i), |_, _| Ok(()));
+        assert!(res.is_ok());
+    }
+}
@@ -0,0 +1,486 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use log::error;
+use parking_lot::RwLock;
+
+use crate::{
+    data::{
+        cipher::Cipher,
+        data_file::{get_blob_file_name, DataFile, BLOB_FILE_NAME_SUFFIX, BLOB_GC_FILE_NAME},
+        log_record::{decode_log_record_pos, LogRecord, LogRecordPos, LogRecordType},
+    },
+    db::{sync_dir_if_enabled, Engine},
+    errors::{Errors, Result},
+};
+
+const BLOB_GC_KEY: &[u8] = "blob.gc".as_bytes();
+
+/// 存放大 value 的 blob 文件，数据文件中只保存 value 在 blob 文件中的位置
+/// blob 文件不会被 merge 重写，merge 之后不再被引用的 blob 文件在下一次打开数据库时删除
+pub(crate) struct ValueLog {
+    dir_path: PathBuf,
+    file_size: u64,
+    fsync_dir: bool, // 新建 blob 文件之后是否持久化数据目录
+    cipher: Option<Arc<Cipher>>,
+    active_file: RwLock<Option<Arc<DataFile>>>, // 当前写入的 blob 文件，第一次写入大 value 时创建
+    older_files: RwLock<HashMap<u64, Arc<DataFile>>>, // 旧的 blob 文件集合
+    // 写入 value 和对应的指针期间持有读锁，merge 切换 blob 文件时持有写锁，
+    // 保证 merge 开始之后不会再有指向旧 blob 文件的指针写入到不参与 merge 的数据文件中
+    pub(crate) rotate_lock: RwLock<()>,
+}
+
+impl ValueLog {
+    /// 打开数据目录中所有的 blob 文件，最后一个 blob 文件作为活跃文件继续写入
+    pub(crate) fn open(
+        dir_path: PathBuf,
+        file_size: u64,
+        fsync_dir: bool,
+        cipher: Option<Arc<Cipher>>,
+    ) -> Result<Self> {
+        let dir = match fs::read_dir(dir_path.clone()) {
+            Ok(dir) => dir,
+            Err(e) => {
+                error!("failed to read database dir: {}", e);
+                return Err(Errors::FailedToReadDatabaseDir);
+            }
+        };
+
+        let mut file_ids = Vec::new();
+        for entry in dir.flatten() {
+            let file_os_name = entry.file_name();
+            let file_name = file_os_name.to_str().unwrap();
+            if let Some(file_id) = file_name.strip_suffix(BLOB_FILE_NAME_SUFFIX) {
+                match file_id.parse::<u64>() {
+                    Ok(fid) => file_ids.push(fid),
+                    Err(_) => return Err(Errors::DataDirCorrupted),
+                }
+            }
+        }
+        file_ids.sort();
+
+        let mut older_files = HashMap::new();
+        for file_id in file_ids.iter() {
+            let blob_file = DataFile::new_blob_file(dir_path.clone(), *file_id, cipher.clone())?;
+            older_files.insert(*file_id, Arc::new(blob_file));
+        }
+        let active_file = file_ids.last().map(|fid| {
+            let blob_file = older_files.remove(fid).unwrap();
+            blob_file.set_write_off(blob_file.file_size());
+            blob_file
+        });
+
+        Ok(Self {
+            dir_path,
+            file_size,
+            fsync_dir,
+            cipher,
+            active_file: RwLock::new(active_file),
+            older_files: RwLock::new(older_files),
+            rotate_lock: RwLock::new(()),
+        })
+    }
+
+    /// 写入 value，返回 value 在 blob 文件中的位置
+    pub(crate) fn write(&self, key: &[u8], value: &[u8], sync: bool) -> Result<LogRecordPos> {
+        let mut record = LogRecord {
+            key: key.to_vec(),
+            value: value.to_vec(),
+            rec_type: LogRecordType::NORMAL,
+            timestamp: 0,
+            value_pointer: false,
+        };
+
+        let mut active_file = self.active_file.write();
+        // 当前 blob 文件写满之后切换到新的 blob 文件，空文件中的 value 即使超过大小也直接写入
+        if let Some(blob_file) = active_file.as_ref() {
+            let write_off = blob_file.get_write_off();
+            if write_off > blob_file.get_header_size()
+                && write_off + (key.len() + value.len()) as u64 > self.file_size
+            {
+                self.seal_active_file(&mut active_file)?;
+            }
+        }
+        if active_file.is_none() {
+            let file_id = self.next_file_id();
+            *active_file = Some(Arc::new(DataFile::new_blob_file(
+                self.dir_path.clone(),
+                file_id,
+                self.cipher.clone(),
+            )?));
+            sync_dir_if_enabled(self.fsync_dir, &self.dir_path)?;
+        }
+
+        let blob_file = active_file.as_ref().unwrap();
+        if blob_file.is_encrypted() {
+            if let Some(cipher) = self.cipher.as_ref() {
+                record = record.encrypt(cipher)?;
+            }
+        }
+        let enc_record = record.encode();
+        let write_off = blob_file.get_write_off();
+        blob_file.write(&enc_record)?;
+        if sync {
+            blob_file.sync()?;
+        }
+
+        Ok(LogRecordPos {
+            file_id: blob_file.get_file_id(),
+            offset: write_off,
+            size: enc_record.len() as u64,
+        })
+    }
+
+    /// 根据 id 获取 blob 文件，blob 文件只在重新打开数据库时删除，句柄在数据库打开期间一直可以读取
+    pub(crate) fn get_blob_file(&self, file_id: u64) -> Result<Arc<DataFile>> {
+        if let Some(blob_file) = self.active_file.read().as_ref() {
+            if blob_file.get_file_id() == file_id {
+                return Ok(blob_file.clone());
+            }
+        }
+        match self.older_files.read().get(&file_id) {
+            Some(blob_file) => Ok(blob_file.clone()),
+            None => Err(Errors::BlobFileNotFound),
+        }
+    }
+
+    /// 根据位置读取 blob 文件中的 value
+    pub(crate) fn read(&self, pos: &LogRecordPos) -> Result<Vec<u8>> {
+        let blob_file = self.get_blob_file(pos.file_id)?;
+        Ok(blob_file.read_log_record(pos.offset)?.record.value)
+    }
+
+    /// 根据位置读取 blob 文件中 value 的大小，只读取记录的 header，blob 文件不能是加密的
+    pub(crate) fn value_size(&self, pos: &LogRecordPos) -> Result<u64> {
+        let blob_file = self.get_blob_file(pos.file_id)?;
+        Ok(blob_file.read_record_layout(pos.offset)?.value_size)
+    }
+
+    /// merge 开始时封存当前的 blob 文件，之后的写入使用新的 blob 文件，
+    /// 返回所有已经封存的 blob 文件 id，调用方需要持有 rotate_lock 写锁
+    pub(crate) fn rotate(&self) -> Result<Vec<u64>> {
+        let mut active_file = self.active_file.write();
+        if active_file.is_some() {
+            self.seal_active_file(&mut active_file)?;
+        }
+        Ok(self.older_files.read().keys().copied().collect())
+    }
+
+    pub(crate) fn sync(&self) -> Result<()> {
+        match self.active_file.read().as_ref() {
+            Some(blob_file) => blob_file.sync(),
+            None => Ok(()),
+        }
+    }
+
+    // 持久化并封存活跃文件，下一次写入时再创建新的活跃文件
+    fn seal_active_file(&self, active_file: &mut Option<Arc<DataFile>>) -> Result<()> {
+        if let Some(blob_file) = active_file.take() {
+            blob_file.sync()?;
+            self.older_files
+                .write()
+                .insert(blob_file.get_file_id(), blob_file);
+        }
+        Ok(())
+    }
+
+    fn next_file_id(&self) -> u64 {
+        match self.older_files.read().keys().max() {
+            Some(fid) => fid + 1,
+            None => 0,
+        }
+    }
+}
+
+impl Engine {
+    // 判断 value 是否需要单独存放到 blob 文件中
+    pub(crate) fn is_large_value(&self, value: &[u8]) -> bool {
+        match self.options.value_log_threshold {
+            Some(threshold) => value.len() >= threshold,
+            None => false,
+        }
+    }
+
+    // 将记录的 value 写入 blob 文件，返回数据文件中需要写入的指针记录
+    // 调用方需要在写入指针记录之前一直持有 value_log.rotate_lock 读锁
+    pub(crate) fn write_large_value(&self, key: &[u8], record: &LogRecord) -> Result<LogRecord> {
+        let blob_pos = self
+            .value_log
+            .write(key, &record.value, self.runtime_options.sync_writes())?;
+        Ok(LogRecord {
+            key: record.key.clone(),
+            value: blob_pos.encode(),
+            rec_type: record.rec_type,
+            timestamp: record.timestamp,
+            value_pointer: true,
+        })
+    }
+
+    // 如果记录的 value 是指向 blob 文件的指针，读取出实际的 value
+    // file_id 和 offset 为记录在数据文件中的位置，指针损坏时返回 DataFileCorrupted
+    pub(crate) fn resolve_value_pointer(
+        &self,
+        record: &mut LogRecord,
+        file_id: u64,
+        offset: u64,
+    ) -> Result<()> {
+        if !record.value_pointer {
+            return Ok(());
+        }
+        let blob_pos = decode_value_pointer(record.value.clone(), file_id, offset)?;
+        record.value = self.value_log.read(&blob_pos)?;
+        record.value_pointer = false;
+        Ok(())
+    }
+}
+
+// 解码数据文件中 file_id、offset 处的记录中指向 blob 文件的指针，指针损坏时返回 DataFileCorrupted
+pub(crate) fn decode_value_pointer(
+    value: Vec<u8>,
+    file_id: u64,
+    offset: u64,
+) -> Result<LogRecordPos> {
+    decode_log_record_pos(value).map_err(|_| Errors::DataFileCorrupted { file_id, offset })
+}
+
+// 记录 merge 之后可以删除的 blob 文件，和 merge 的结果一起安装
+pub(crate) fn write_blob_gc_file(
+    dir_path: PathBuf,
+    blob_file_ids: &[u64],
+    referenced: &HashSet<u64>,
+) -> Result<()> {
+    let unused: Vec<String> = blob_file_ids
+        .iter()
+        .filter(|fid| !referenced.contains(fid))
+        .map(|fid| fid.to_string())
+        .collect();
+    if unused.is_empty() {
+        return Ok(());
+    }
+
+    let blob_gc_file = DataFile::new_blob_gc_file(dir_path)?;
+    let record = LogRecord {
+        key: BLOB_GC_KEY.to_vec(),
+        value: unused.join(",").into_bytes(),
+        rec_type: LogRecordType::NORMAL,
+        timestamp: 0,
+        value_pointer: false,
+    };
+    blob_gc_file.write(&record.encode())?;
+    blob_gc_file.sync()
+}
+
+// merge 的结果安装完成之后，删除不再被引用的 blob 文件
+pub(crate) fn remove_unused_blob_files(dir_path: &Path) -> Result<()> {
+    let blob_gc_path = dir_path.join(BLOB_GC_FILE_NAME);
+    if !blob_gc_path.is_file() {
+        return Ok(());
+    }
+
+    let blob_gc_file = DataFile::new_blob_gc_file(dir_path.to_path_buf())?;
+    let record = blob_gc_file.read_log_record(0)?.record;
+    let file_ids = String::from_utf8(record.value).map_err(|_| Errors::DataDirCorrupted)?;
+    for file_id in file_ids.split(',') {
+        let file_id = file_id
+            .parse::<u64>()
+            .map_err(|_| Errors::DataDirCorrupted)?;
+        let blob_file = get_blob_file_name(dir_path.to_path_buf(), file_id);
+        if blob_file.is_file() {
+            if let Err(e) = fs::remove_file(blob_file) {
+                error!("failed to remove blob file: {}", e);
+                return Err(Errors::FailedToInstallMergeFiles);
+            }
+        }
+    }
+
+    if let Err(e) = fs::remove_file(blob_gc_path) {
+        error!("failed to remove blob gc file: {}", e);
+        return Err(Errors::FailedToInstallMergeFiles);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::{
+        batch::{log_record_key_with_seq, NON_TRANSACTION_SEQ_NO},
+        options::{Options, WriteBatchOptions},
+    };
+
+    use super::*;
+
+    fn large_value(i: usize) -> Bytes {
+        Bytes::from(format!("{:08}", i).repeat(512))
+    }
+
+    fn blob_file_ids(dir_path: &Path) -> Vec<u64> {
+        let mut file_ids: Vec<u64> = fs::read_dir(dir_path)
+            .unwrap()
+            .filter_map(|entry| {
+                let file_name = entry.unwrap().file_name();
+                let file_name = file_name.to_str().unwrap().to_string();
+                file_name
+                    .strip_suffix(BLOB_FILE_NAME_SUFFIX)
+                    .map(|fid| fid.parse::<u64>().unwrap())
+            })
+            .collect();
+        file_ids.sort();
+        file_ids
+    }
+
+    #[test]
+    fn test_value_log() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-value-log");
+        opts.data_file_size = 64 * 1024;
+        opts.data_file_merge_ratio = 0 as f32;
+        opts.value_log_threshold = Some(1024);
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        // 小 value 直接写入数据文件，大 value 写入 blob 文件
+        assert!(engine.put(Bytes::from("small"), Bytes::from("v")).is_ok());
+        for i in 0..100 {
+            let res = engine.put(Bytes::from(format!("key-{}", i)), large_value(i));
+            assert!(res.is_ok());
+        }
+        assert_eq!(engine.get(Bytes::from("small")).unwrap(), Bytes::from("v"));
+        assert_eq!(engine.get(Bytes::from("key-10")).unwrap(), large_value(10));
+        let location = engine.get_position(Bytes::from("key-10")).unwrap();
+        assert!(location.size < 100);
+        assert_eq!(engine.read_at(location).unwrap(), large_value(10));
+        assert!(blob_file_ids(&opts.dir_path).len() > 1);
+
+        // 批量写入
+        let wb = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("failed to create write batch");
+        assert!(wb.put(Bytes::from("batch-1"), large_value(1000)).is_ok());
+        assert!(wb.put(Bytes::from("batch-2"), Bytes::from("v")).is_ok());
+        assert!(wb.commit().is_ok());
+        assert_eq!(
+            engine.get(Bytes::from("batch-1")).unwrap(),
+            large_value(1000)
+        );
+
+        // 重启之后关闭分离存放，之前写入 blob 文件中的数据仍然可以读取
+        std::mem::drop(wb);
+        std::mem::drop(engine);
+        let mut opts2 = opts.clone();
+        opts2.value_log_threshold = None;
+        let engine2 = Engine::open(opts2.clone()).expect("failed to open engine");
+        for i in 0..100 {
+            let res = engine2.get(Bytes::from(format!("key-{}", i)));
+            assert_eq!(res.unwrap(), large_value(i));
+        }
+        assert_eq!(
+            engine2.get(Bytes::from("batch-1")).unwrap(),
+            large_value(1000)
+        );
+        std::mem::drop(engine2);
+
+        // 覆盖写入大部分数据之后 merge，不再被引用的 blob 文件在重启时删除
+        let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+        let old_blob_file_ids = blob_file_ids(&opts.dir_path);
+        for i in 0..100 {
+            let res = engine3.put(Bytes::from(format!("key-{}", i)), large_value(i + 1));
+            assert!(res.is_ok());
+        }
+        assert!(engine3.delete(Bytes::from("batch-1")).is_ok());
+        assert!(engine3.merge().is_ok());
+        std::mem::drop(engine3);
+
+        let engine4 = Engine::open(opts.clone()).expect("failed to open engine");
+        let new_blob_file_ids = blob_file_ids(&opts.dir_path);
+        assert!(!new_blob_file_ids.contains(&old_blob_file_ids[0]));
+        assert!(!opts.dir_path.join(BLOB_GC_FILE_NAME).exists());
+        for i in 0..100 {
+            let res = engine4.get(Bytes::from(format!("key-{}", i)));
+            assert_eq!(res.unwrap(), large_value(i + 1));
+        }
+        assert_eq!(engine4.get(Bytes::from("small")).unwrap(), Bytes::from("v"));
+        assert_eq!(
+            Errors::KeyNotFound,
+            engine4.get(Bytes::from("batch-1")).err().unwrap()
+        );
+
+        std::mem::drop(engine4);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_value_log_encrypted() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-value-log-encrypted");
+        opts.data_file_size = 64 * 1024 * 1024;
+        opts.value_log_threshold = Some(16);
+        opts.encryption_key = Some([7; 32]);
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        assert!(engine.put(Bytes::from("key"), large_value(1)).is_ok());
+        std::mem::drop(engine);
+
+        // blob 文件中的数据同样被加密
+        let blob_file = get_blob_file_name(opts.dir_path.clone(), 0);
+        let content = fs::read(blob_file).unwrap();
+        let plain = large_value(1);
+        assert!(!content.windows(16).any(|w| w == &plain[..16]));
+
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine2.get(Bytes::from("key")).unwrap(), large_value(1));
+
+        std::mem::drop(engine2);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_value_log_corrupted_pointer() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-value-log-corrupted-pointer");
+        opts.value_log_threshold = Some(1024);
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        // 指向 blob 文件的指针损坏，读取时返回错误而不是 panic
+        let mut record = LogRecord {
+            key: log_record_key_with_seq(b"key", NON_TRANSACTION_SEQ_NO),
+            value: vec![0xff; 12],
+            rec_type: LogRecordType::NORMAL,
+            timestamp: 0,
+            value_pointer: true,
+        };
+        let pos = engine.append_log_record(&mut record).unwrap();
+        std::mem::drop(engine);
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        let corrupted = Errors::DataFileCorrupted {
+            file_id: pos.file_id,
+            offset: pos.offset,
+        };
+        assert_eq!(engine.get(Bytes::from("key")).err().unwrap(), corrupted);
+        assert_eq!(
+            engine.value_len(Bytes::from("key")).err().unwrap(),
+            corrupted
+        );
+        assert_eq!(
+            engine.value_reader(Bytes::from("key")).err().unwrap(),
+            corrupted
+        );
+
+        std::mem::drop(engine);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_value_log_invalid_threshold() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-value-log-threshold");
+        opts.value_log_threshold = Some(0);
+        let res = Engine::open(opts);
+        assert_eq!(Errors::InvaildValueLogThreshold, res.err().unwrap());
+    }
+}
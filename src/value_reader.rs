@@ -0,0 +1,242 @@
+use std::{
+    io::{self, Read},
+    sync::Arc,
+};
+
+use bytes::Bytes;
+
+use crate::{
+    data::{
+        data_file::{DataFile, RecordLayout},
+        log_record::LogRecordType,
+    },
+    db::Engine,
+    errors::{Errors, Result},
+    value_log::decode_value_pointer,
+};
+
+/// 按块读取一个 value，不需要一次把整个 value 读到内存中
+/// 读取期间持有数据文件或者 blob 文件的句柄，之后的写入以及 merge 删除数据文件都不影响读取到的内容
+pub struct ValueReader {
+    source: ValueSource,
+    len: u64,  // value 的大小
+    read: u64, // 已经读取的字节数
+}
+
+// value 的来源
+enum ValueSource {
+    // 直接从数据文件或者 blob 文件中读取，读取到末尾时用记录中的 CRC 校验读取到的内容
+    File {
+        data_file: Arc<DataFile>,
+        value_offset: u64, // value 在文件中的偏移，value 之后是 CRC 校验值
+        hasher: crc32fast::Hasher,
+    },
+    // 已经读到内存中的 value
+    Memory(Bytes),
+}
+
+impl Engine {
+    /// 返回按块读取 key 对应的 value 的 reader，适合很大的 value，reader 的大小就是 value 的大小
+    /// 读取到末尾时校验整条记录的 CRC，校验失败时返回 InvalidData 错误；
+    /// value 存放在 blob 文件中时从 blob 文件中按块读取，数据库加密时 value 在创建 reader 时整个读到内存中
+    pub fn value_reader(&self, key: Bytes) -> Result<ValueReader> {
+        self.check_closed()?;
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+
+        let (_, reader) = self.read_by_key(&key, |pos| {
+            let data_file = self.get_data_file(pos.file_id)?;
+            if !data_file.is_encrypted() {
+                let layout = data_file.read_record_layout(pos.offset)?;
+                if layout.rec_type == LogRecordType::DELETE {
+                    return Err(Errors::KeyNotFound);
+                }
+                if !layout.value_pointer {
+                    return file_value_reader(data_file, pos.offset, &layout);
+                }
+
+                // 数据文件中只有指向 blob 文件的指针，从 blob 文件中的记录读取 value
+                let record = data_file.read_log_record(pos.offset)?.record;
+                let blob_pos = decode_value_pointer(record.value, pos.file_id, pos.offset)?;
+                let blob_file = self.value_log.get_blob_file(blob_pos.file_id)?;
+                if !blob_file.is_encrypted() {
+                    let layout = blob_file.read_record_layout(blob_pos.offset)?;
+                    return file_value_reader(blob_file, blob_pos.offset, &layout);
+                }
+            }
+
+            let record = self.read_log_record_by_position(pos)?.record;
+            if record.rec_type == LogRecordType::DELETE {
+                return Err(Errors::KeyNotFound);
+            }
+            Ok(ValueReader {
+                len: record.value.len() as u64,
+                source: ValueSource::Memory(Bytes::from(record.value)),
+                read: 0,
+            })
+        })?;
+        Ok(reader)
+    }
+}
+
+// 从文件中 offset 处的记录按块读取 value，先计算 header 和 key 的 CRC，value 在读取时继续计算
+fn file_value_reader(
+    data_file: Arc<DataFile>,
+    offset: u64,
+    layout: &RecordLayout,
+) -> Result<ValueReader> {
+    let value_offset = offset + layout.header_size + layout.key_size;
+    let mut buf = vec![0; (layout.header_size + layout.key_size) as usize];
+    data_file.read_raw(&mut buf, offset)?;
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&buf);
+    Ok(ValueReader {
+        source: ValueSource::File {
+            data_file,
+            value_offset,
+            hasher,
+        },
+        len: layout.value_size,
+        read: 0,
+    })
+}
+
+impl ValueReader {
+    /// value 的大小
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Read for ValueReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = buf.len().min((self.len - self.read) as usize);
+        if n == 0 {
+            return Ok(0);
+        }
+
+        match &mut self.source {
+            ValueSource::Memory(value) => {
+                let start = self.read as usize;
+                buf[..n].copy_from_slice(&value[start..start + n]);
+            }
+            ValueSource::File {
+                data_file,
+                value_offset,
+                hasher,
+            } => {
+                data_file
+                    .read_raw(&mut buf[..n], *value_offset + self.read)
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                hasher.update(&buf[..n]);
+
+                // 读取到末尾时校验 CRC
+                if self.read + n as u64 == self.len {
+                    let mut crc = [0u8; 4];
+                    data_file
+                        .read_raw(&mut crc, *value_offset + self.len)
+                        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                    if u32::from_be_bytes(crc) != hasher.clone().finalize() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            Errors::InvaildLogRecordCrc,
+                        ));
+                    }
+                }
+            }
+        }
+        self.read += n as u64;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::{options::Options, util::rand_kv::get_test_key};
+
+    #[test]
+    fn test_engine_value_reader() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-value-reader");
+        opts.data_file_size = 64 * 1024;
+        opts.data_file_merge_ratio = 0.0;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        let value: Vec<u8> = (0..10000).map(|i| (i % 251) as u8).collect();
+        assert!(engine
+            .put(get_test_key(1), Bytes::from(value.clone()))
+            .is_ok());
+        assert!(engine.put(get_test_key(2), Bytes::new()).is_ok());
+
+        // 按块读取
+        let mut reader = engine.value_reader(get_test_key(1)).unwrap();
+        assert_eq!(reader.len(), 10000);
+        let mut buf = [0u8; 1000];
+        let mut read = Vec::new();
+        loop {
+            let n = reader.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            read.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(read, value);
+
+        let mut reader = engine.value_reader(get_test_key(2)).unwrap();
+        assert!(reader.is_empty());
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+        assert_eq!(
+            engine.value_reader(get_test_key(3)).err().unwrap(),
+            Errors::KeyNotFound
+        );
+
+        // 读取期间覆盖写并 merge，仍然读取到原来的 value
+        let mut reader = engine.value_reader(get_test_key(1)).unwrap();
+        for i in 0..100 {
+            assert!(engine
+                .put(get_test_key(1), Bytes::from(format!("new-{}", i)))
+                .is_ok());
+        }
+        assert!(engine.merge().is_ok());
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, value);
+        assert_eq!(engine.value_len(get_test_key(1)).unwrap(), 6);
+
+        std::mem::drop(reader);
+        std::mem::drop(engine);
+        std::fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_engine_value_reader_blob() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-value-reader-blob");
+        opts.value_log_threshold = Some(1024);
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        // 大 value 存放在 blob 文件中，同样按块读取
+        let value: Vec<u8> = (0..100000).map(|i| (i % 251) as u8).collect();
+        assert!(engine
+            .put(get_test_key(1), Bytes::from(value.clone()))
+            .is_ok());
+        let mut reader = engine.value_reader(get_test_key(1)).unwrap();
+        assert!(matches!(reader.source, ValueSource::File { .. }));
+        assert_eq!(reader.len(), 100000);
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, value);
+
+        std::mem::drop(reader);
+        std::mem::drop(engine);
+        std::fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+}
@@ -0,0 +1,170 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use bytes::Bytes;
+use log::error;
+
+use crate::{
+    db::Engine,
+    errors::{Errors, Result},
+    options::IteratorOptions,
+};
+
+/// 需要预热的 key
+pub enum WarmupKeys {
+    /// 指定的 key，不存在的 key 直接跳过
+    Keys(Vec<Bytes>),
+    /// 以这个前缀开头的所有 key
+    Prefix(Bytes),
+}
+
+/// 后台预热的句柄，可以查看进度、取消或者等待预热完成
+/// 释放句柄不会停止预热，预热线程持有存储引擎的 handle，预热完成之前存储引擎不会因为释放而关闭
+pub struct WarmupHandle {
+    state: Arc<WarmupState>,
+    handle: thread::JoinHandle<Result<usize>>,
+}
+
+// 预热线程和句柄共享的状态
+#[derive(Default)]
+struct WarmupState {
+    loaded: AtomicUsize, // 已经读取的 key 的数量
+    cancelled: AtomicBool,
+}
+
+impl Engine {
+    /// 在后台线程中依次读取 key 对应的 value，让数据文件的内容进入页缓存，
+    /// 对象存储中的数据文件会被读取到本地的缓存中，设置了 max_open_files 时也会打开对应的数据文件，
+    /// 用于打开数据库之后提前加载热点数据，避免刚启动时的读取延迟过高
+    /// 预热期间不阻塞读写，也不计入读取的统计数据
+    pub fn warmup(&self, keys: WarmupKeys) -> Result<WarmupHandle> {
+        self.check_closed()?;
+        let state = Arc::new(WarmupState::default());
+        let engine = self.clone();
+        let thread_state = state.clone();
+        let handle = thread::spawn(move || {
+            let res = engine.warmup_keys(keys, &thread_state);
+            if let Err(e) = &res {
+                error!("failed to warm up: {}", e);
+            }
+            res
+        });
+        Ok(WarmupHandle { state, handle })
+    }
+
+    fn warmup_keys(&self, keys: WarmupKeys, state: &WarmupState) -> Result<usize> {
+        let keys = match keys {
+            WarmupKeys::Keys(keys) => keys,
+            WarmupKeys::Prefix(prefix) => {
+                // 先收集 key，读取时不持有索引的迭代器
+                let mut keys = Vec::new();
+                let mut index_iter = self.index.iterator(IteratorOptions {
+                    prefix: prefix.to_vec(),
+                    ..Default::default()
+                });
+                while let Some((key, _)) = index_iter.next() {
+                    keys.push(Bytes::copy_from_slice(key));
+                }
+                keys
+            }
+        };
+
+        for key in keys {
+            if state.cancelled.load(Ordering::SeqCst) {
+                break;
+            }
+            self.check_closed()?;
+            if key.is_empty() {
+                continue;
+            }
+            match self.read_log_record_by_key(&key) {
+                Ok(_) => {
+                    state.loaded.fetch_add(1, Ordering::SeqCst);
+                }
+                Err(Errors::KeyNotFound) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(state.loaded.load(Ordering::SeqCst))
+    }
+}
+
+impl WarmupHandle {
+    /// 已经读取的 key 的数量
+    pub fn loaded(&self) -> usize {
+        self.state.loaded.load(Ordering::SeqCst)
+    }
+
+    /// 停止预热，已经读取的数据仍然保留在缓存中
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    /// 等待预热完成，返回读取的 key 的数量
+    pub fn wait(self) -> Result<usize> {
+        match self.handle.join() {
+            Ok(res) => res,
+            Err(_) => {
+                error!("warmup thread panicked");
+                Ok(self.state.loaded.load(Ordering::SeqCst))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::{
+        options::Options,
+        util::rand_kv::{get_test_key, get_test_value},
+    };
+
+    #[test]
+    fn test_engine_warmup() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-warmup");
+        opts.data_file_size = 64 * 1024;
+        opts.max_open_files = Some(2);
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..1000 {
+            assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        assert!(engine.put(Bytes::from("hot-1"), Bytes::from("a")).is_ok());
+        assert!(engine.put(Bytes::from("hot-2"), Bytes::from("b")).is_ok());
+        std::mem::drop(engine);
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        let keys = vec![get_test_key(1), get_test_key(500), Bytes::from("missing")];
+        let handle = engine.warmup(WarmupKeys::Keys(keys)).unwrap();
+        assert_eq!(handle.wait().unwrap(), 2);
+        // 预热之后设置了 max_open_files 的数据文件已经打开
+        assert!(engine.file_cache.as_ref().unwrap().open_files() > 0);
+
+        let handle = engine
+            .warmup(WarmupKeys::Prefix(Bytes::from("hot-")))
+            .unwrap();
+        assert_eq!(handle.wait().unwrap(), 2);
+
+        // 取消之后不再继续读取
+        let handle = engine
+            .warmup(WarmupKeys::Prefix(Bytes::from("bitcask")))
+            .unwrap();
+        handle.cancel();
+        assert!(handle.wait().unwrap() <= 1000);
+
+        std::mem::drop(engine);
+        std::fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+}
@@ -0,0 +1,236 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    mpsc::{sync_channel, Receiver, SyncSender, TrySendError},
+};
+
+use bytes::Bytes;
+use log::warn;
+use parking_lot::Mutex;
+
+use crate::{
+    bucket::is_bucket_key,
+    data::log_record::{LogRecord, LogRecordType},
+    db::Engine,
+    mvcc::is_mvcc_key,
+};
+
+/// 每个订阅者最多缓存的未读取事件数量，超过之后订阅者会被移除
+pub const WATCH_CHANNEL_CAPACITY: usize = 4096;
+
+/// key 的变更事件
+/// seq 为写入的序列号，在进程内单调递增，同一个批次中的写入共享一个序列号，重启之后重新计数
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Put { key: Bytes, value: Bytes, seq: u64 },
+    Delete { key: Bytes, seq: u64 },
+}
+
+impl Event {
+    pub fn key(&self) -> &Bytes {
+        match self {
+            Event::Put { key, .. } => key,
+            Event::Delete { key, .. } => key,
+        }
+    }
+
+    pub fn seq(&self) -> u64 {
+        match self {
+            Event::Put { seq, .. } => *seq,
+            Event::Delete { seq, .. } => *seq,
+        }
+    }
+}
+
+/// 订阅者
+struct Watcher {
+    prefix: Vec<u8>,
+    sender: SyncSender<Event>,
+    include_mvcc_keys: bool, // 是否订阅 MVCC 的内部 key，只有 prefix 是内部前缀时才订阅
+    include_bucket_keys: bool, // 是否订阅 bucket 中的 key，只有 prefix 是 bucket 前缀时才订阅
+}
+
+/// 管理所有的订阅者，写入成功之后向 key 匹配前缀的订阅者发送事件
+pub(crate) struct Watchers {
+    seq: AtomicU64,
+    watchers: Mutex<Vec<Watcher>>,
+}
+
+impl Watchers {
+    pub(crate) fn new() -> Self {
+        Self {
+            seq: AtomicU64::new(0),
+            watchers: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn subscribe(&self, prefix: Vec<u8>) -> Receiver<Event> {
+        let (sender, receiver) = sync_channel(WATCH_CHANNEL_CAPACITY);
+        self.watchers.lock().push(Watcher {
+            include_mvcc_keys: is_mvcc_key(&prefix),
+            include_bucket_keys: is_bucket_key(&prefix),
+            prefix,
+            sender,
+        });
+        receiver
+    }
+
+    /// 发送一次写入产生的事件，records 中的 key 为实际的 key
+    pub(crate) fn notify(&self, records: &[LogRecord]) {
+        let seq = self.seq.fetch_add(1, Ordering::SeqCst) + 1;
+
+        let mut watchers = self.watchers.lock();
+        if watchers.is_empty() {
+            return;
+        }
+
+        // 接收端已经关闭的订阅者直接移除；来不及读取事件的订阅者也移除，
+        // 不再发送之后的事件，避免缓存的事件无限增长，也不会在事件中间留下空洞
+        watchers.retain(|watcher| {
+            for record in records.iter() {
+                if !record.key.starts_with(&watcher.prefix) {
+                    continue;
+                }
+                if !watcher.include_mvcc_keys && is_mvcc_key(&record.key) {
+                    continue;
+                }
+                if !watcher.include_bucket_keys && is_bucket_key(&record.key) {
+                    continue;
+                }
+                let key = Bytes::from(record.key.clone());
+                let event = match record.rec_type {
+                    LogRecordType::DELETE => Event::Delete { key, seq },
+                    _ => Event::Put {
+                        key,
+                        value: Bytes::from(record.value.clone()),
+                        seq,
+                    },
+                };
+                match watcher.sender.try_send(event) {
+                    Ok(()) => {}
+                    Err(TrySendError::Full(_)) => {
+                        warn!(
+                            "watcher of prefix {:?} lags behind, remove it",
+                            watcher.prefix
+                        );
+                        return false;
+                    }
+                    Err(TrySendError::Disconnected(_)) => return false,
+                }
+            }
+            true
+        });
+    }
+}
+
+impl Engine {
+    /// 订阅 key 以 prefix 开头的数据变更，prefix 为空时订阅所有的 key
+    /// 每次 put、delete 或者批量写提交成功之后发送事件，丢弃返回的 Receiver 即可取消订阅
+    /// 并发写入同一个 key 时，事件的顺序和写入数据文件的顺序不一定一致，需要最新值时应重新读取
+    /// 事务和 bucket 的内部 key 只有 prefix 是对应的内部前缀时才会订阅到
+    /// 未读取的事件超过 WATCH_CHANNEL_CAPACITY 时订阅被取消，Receiver 读完已缓存的事件之后返回断开，需要重新订阅
+    pub fn watch(&self, prefix: Bytes) -> Receiver<Event> {
+        self.watchers.subscribe(prefix.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::{
+        options::{Options, WriteBatchOptions},
+        util::rand_kv::{get_test_key, get_test_value},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_watch() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-watch");
+        opts.data_file_size = 64 * 1024 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        let all = engine.watch(Bytes::new());
+        let prefixed = engine.watch(Bytes::from("user:"));
+
+        engine.put(get_test_key(1), get_test_value(1)).unwrap();
+        engine
+            .put(Bytes::from("user:1"), Bytes::from("alice"))
+            .unwrap();
+        engine.delete(Bytes::from("user:1")).unwrap();
+        // 不存在的 key 不会写入数据，也没有事件
+        engine.delete(Bytes::from("user:2")).unwrap();
+
+        let events: Vec<Event> = all.try_iter().collect();
+        assert_eq!(events.len(), 3);
+        assert_eq!(
+            events[0],
+            Event::Put {
+                key: get_test_key(1),
+                value: get_test_value(1),
+                seq: 1
+            }
+        );
+        assert!(events[0].seq() < events[1].seq());
+        assert!(events[1].seq() < events[2].seq());
+
+        let events: Vec<Event> = prefixed.try_iter().collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            events[0],
+            Event::Put {
+                key: Bytes::from("user:1"),
+                value: Bytes::from("alice"),
+                seq: 2
+            }
+        );
+        assert_eq!(
+            events[1],
+            Event::Delete {
+                key: Bytes::from("user:1"),
+                seq: 3
+            }
+        );
+
+        // 批量写提交之后发送事件，共享同一个序列号
+        let wb = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .unwrap();
+        wb.put(Bytes::from("user:3"), Bytes::from("bob")).unwrap();
+        wb.put(get_test_key(2), get_test_value(2)).unwrap();
+        assert!(prefixed.try_recv().is_err());
+        wb.commit().unwrap();
+
+        let events: Vec<Event> = all.try_iter().collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].key(), &Bytes::from("user:3"));
+        assert_eq!(events[1].key(), &get_test_key(2));
+        assert_eq!(events[0].seq(), events[1].seq());
+        assert_eq!(prefixed.try_iter().count(), 1);
+
+        // 事务和 bucket 的内部 key 不会发送给订阅所有 key 的订阅者
+        let txn = engine.begin();
+        txn.put(get_test_key(4), get_test_value(4)).unwrap();
+        txn.commit().unwrap();
+        let bucket = engine.bucket("users").unwrap();
+        bucket.put(get_test_key(5), get_test_value(5)).unwrap();
+        assert_eq!(all.try_iter().count(), 0);
+
+        // 取消订阅
+        std::mem::drop(all);
+        engine.put(get_test_key(3), get_test_value(3)).unwrap();
+        assert_eq!(engine.watchers.watchers.lock().len(), 1);
+
+        // 来不及读取事件的订阅者被移除，已经缓存的事件仍然可以读取
+        let lagged = engine.watch(Bytes::new());
+        for i in 0..WATCH_CHANNEL_CAPACITY + 1 {
+            engine.put(get_test_key(i), get_test_value(i)).unwrap();
+        }
+        assert_eq!(engine.watchers.watchers.lock().len(), 1);
+        assert_eq!(lagged.try_iter().count(), WATCH_CHANNEL_CAPACITY);
+        assert!(lagged.recv().is_err());
+
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+}